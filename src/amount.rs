@@ -0,0 +1,249 @@
+//! Arithmetic on the native amounts computed by the runtime itself, without
+//! going through the `Interface`.
+//!
+//! An amount is a `u64` number of nano-coins, as sent with the calls. The
+//! AssemblyScript ABIs do not trap on an invalid operation but return an
+//! `ArrayBuffer` starting with an `AmountError` code, followed by the 8 bytes
+//! little-endian result when the code is `0`.
+//!
+//! The wasmv1 ABIs convert amounts with the decimals of
+//! `RuntimeConfig::native_amount_decimals` instead, and reject a malformed string
+//! with an `AmountParseError`.
+
+use displaydoc::Display;
+
+/// Digits of the fractional part of an amount
+const DECIMALS: u32 = 9;
+
+/// Reason why an operation on amounts has no result, encoded as the first
+/// byte of the serialized result
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub(crate) enum AmountError {
+    Overflow = 1,
+    Underflow = 2,
+    DivisionByZero = 3,
+    Parse = 4,
+}
+
+pub(crate) fn checked_add(a: u64, b: u64) -> Result<u64, AmountError> {
+    a.checked_add(b).ok_or(AmountError::Overflow)
+}
+
+pub(crate) fn checked_sub(a: u64, b: u64) -> Result<u64, AmountError> {
+    a.checked_sub(b).ok_or(AmountError::Underflow)
+}
+
+pub(crate) fn checked_mul(amount: u64, factor: u64) -> Result<u64, AmountError> {
+    amount.checked_mul(factor).ok_or(AmountError::Overflow)
+}
+
+/// Quotient of `amount` by `divisor`, rounded down
+pub(crate) fn checked_div(amount: u64, divisor: u64) -> Result<u64, AmountError> {
+    amount
+        .checked_div(divisor)
+        .ok_or(AmountError::DivisionByZero)
+}
+
+/// Reason why a string is not a decimal amount, its code prefixes the error
+/// message of the wasmv1 ABIs
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Display)]
+#[repr(u8)]
+pub(crate) enum AmountParseError {
+    /// empty amount
+    Empty = 1,
+    /// sign before the amount
+    Sign = 2,
+    /// character other than an ASCII digit or a decimal point between digits
+    Malformed = 3,
+    /// more fractional digits than the decimals of the amounts
+    TooManyDecimals = 4,
+    /// amount above the maximum
+    Overflow = 5,
+}
+
+/// Parse a decimal number of coins, such as `"12"` or `"0.000000001"`, with
+/// at most 9 fractional digits, into nano-coins
+pub(crate) fn from_string(amount: &str) -> Result<u64, AmountError> {
+    from_decimal_string(amount, DECIMALS).map_err(|err| match err {
+        AmountParseError::Overflow => AmountError::Overflow,
+        _ => AmountError::Parse,
+    })
+}
+
+/// Parse a decimal number of coins with at most `decimals` fractional digits
+/// into units of `10^-decimals` coins
+pub(crate) fn from_decimal_string(amount: &str, decimals: u32) -> Result<u64, AmountParseError> {
+    if amount.is_empty() {
+        return Err(AmountParseError::Empty);
+    }
+    if amount.starts_with(['+', '-']) {
+        return Err(AmountParseError::Sign);
+    }
+    let (integer, fraction) = match amount.split_once('.') {
+        Some((integer, fraction)) if !fraction.is_empty() => (integer, fraction),
+        Some(_) => return Err(AmountParseError::Malformed),
+        None => (amount, ""),
+    };
+    let all_digits = |part: &str| part.bytes().all(|b| b.is_ascii_digit());
+    if integer.is_empty() || !all_digits(integer) || !all_digits(fraction) {
+        return Err(AmountParseError::Malformed);
+    }
+    if fraction.len() > decimals as usize {
+        return Err(AmountParseError::TooManyDecimals);
+    }
+    // the digits were checked, the parts can only be too large
+    let unit = 10u64
+        .checked_pow(decimals)
+        .ok_or(AmountParseError::Overflow)?;
+    let integer: u64 = integer.parse().map_err(|_| AmountParseError::Overflow)?;
+    let fraction: u64 = match fraction {
+        "" => 0,
+        fraction => format!("{:0<width$}", fraction, width = decimals as usize)
+            .parse()
+            .map_err(|_| AmountParseError::Overflow)?,
+    };
+    integer
+        .checked_mul(unit)
+        .and_then(|integer| integer.checked_add(fraction))
+        .ok_or(AmountParseError::Overflow)
+}
+
+/// Decimal number of coins of `amount` nano-coins, without trailing zeros
+pub(crate) fn to_string(amount: u64) -> String {
+    to_decimal_string(amount, DECIMALS)
+}
+
+/// Decimal number of coins of `amount` units of `10^-decimals` coins,
+/// without trailing zeros
+pub(crate) fn to_decimal_string(amount: u64, decimals: u32) -> String {
+    let digits = format!("{:0>width$}", amount, width = decimals as usize + 1);
+    let (integer, fraction) = digits.split_at(digits.len() - decimals as usize);
+    match fraction.trim_end_matches('0') {
+        "" => integer.to_string(),
+        fraction => format!("{}.{}", integer, fraction),
+    }
+}
+
+/// Serialized result of an operation, see the module documentation
+pub(crate) fn serialize(result: Result<u64, AmountError>) -> Vec<u8> {
+    match result {
+        Ok(amount) => {
+            let mut serialized = vec![0];
+            serialized.extend(amount.to_le_bytes());
+            serialized
+        }
+        Err(err) => vec![err as u8],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Nano-coins in a coin
+    const COIN: u64 = 1_000_000_000;
+
+    #[test]
+    fn test_arithmetic_bounds() {
+        assert_eq!(checked_add(u64::MAX - 1, 1), Ok(u64::MAX));
+        assert_eq!(checked_add(u64::MAX, 1), Err(AmountError::Overflow));
+        assert_eq!(checked_sub(0, 0), Ok(0));
+        assert_eq!(checked_sub(0, 1), Err(AmountError::Underflow));
+        assert_eq!(checked_mul(u64::MAX, 1), Ok(u64::MAX));
+        assert_eq!(checked_mul(u64::MAX / 2 + 1, 2), Err(AmountError::Overflow));
+        assert_eq!(checked_mul(u64::MAX, 0), Ok(0));
+        assert_eq!(checked_div(u64::MAX, u64::MAX), Ok(1));
+        assert_eq!(checked_div(7, 2), Ok(3));
+        assert_eq!(checked_div(7, 0), Err(AmountError::DivisionByZero));
+        assert_eq!(checked_div(0, 0), Err(AmountError::DivisionByZero));
+    }
+
+    #[test]
+    fn test_string_conversions() {
+        for (amount, string) in [
+            (0, "0"),
+            (1, "0.000000001"),
+            (1_500_000_000, "1.5"),
+            (42 * COIN, "42"),
+            (u64::MAX, "18446744073.709551615"),
+        ] {
+            assert_eq!(to_string(amount), string);
+            assert_eq!(from_string(string), Ok(amount));
+        }
+        assert_eq!(from_string("1.500000000"), Ok(1_500_000_000));
+        assert_eq!(from_string("007"), Ok(7 * COIN));
+        assert_eq!(
+            from_string("18446744073.709551616"),
+            Err(AmountError::Overflow)
+        );
+        assert_eq!(
+            from_string("99999999999999999999999"),
+            Err(AmountError::Overflow)
+        );
+        for malformed in [
+            "",
+            ".",
+            "1.",
+            ".5",
+            "-1",
+            "+1",
+            "1e9",
+            "1,5",
+            " 1",
+            "0.0000000001",
+            "1.2.3",
+        ] {
+            assert_eq!(
+                from_string(malformed),
+                Err(AmountError::Parse),
+                "{}",
+                malformed
+            );
+        }
+    }
+
+    #[test]
+    fn test_decimal_strings() {
+        for decimals in [0, 2, 9, 19] {
+            assert_eq!(to_decimal_string(0, decimals), "0");
+            assert_eq!(from_decimal_string("0", decimals), Ok(0));
+            let max = to_decimal_string(u64::MAX, decimals);
+            assert_eq!(from_decimal_string(&max, decimals), Ok(u64::MAX));
+        }
+        assert_eq!(to_decimal_string(u64::MAX, 0), "18446744073709551615");
+        assert_eq!(to_decimal_string(u64::MAX, 19), "1.8446744073709551615");
+        assert_eq!(to_decimal_string(1_500, 3), "1.5");
+        assert_eq!(from_decimal_string("1.500", 3), Ok(1_500));
+        assert_eq!(
+            from_decimal_string("1.5", 0),
+            Err(AmountParseError::TooManyDecimals)
+        );
+        assert_eq!(
+            from_decimal_string("1.8446744073709551616", 19),
+            Err(AmountParseError::Overflow)
+        );
+        assert_eq!(
+            from_decimal_string("1", 20),
+            Err(AmountParseError::Overflow)
+        );
+        for (malformed, err) in [
+            ("", AmountParseError::Empty),
+            ("+1", AmountParseError::Sign),
+            ("-1", AmountParseError::Sign),
+            ("1,5", AmountParseError::Malformed),
+            ("1 000", AmountParseError::Malformed),
+            ("1'000.5", AmountParseError::Malformed),
+            ("\u{661}", AmountParseError::Malformed),
+            ("0.0000000001", AmountParseError::TooManyDecimals),
+        ] {
+            assert_eq!(from_decimal_string(malformed, 9), Err(err), "{}", malformed);
+        }
+    }
+
+    #[test]
+    fn test_serialize() {
+        assert_eq!(serialize(Ok(1)), vec![0, 1, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(serialize(Err(AmountError::DivisionByZero)), vec![3]);
+    }
+}
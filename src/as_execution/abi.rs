@@ -5,51 +5,95 @@
 //! module. You can look at the other side of the mirror in `massa.ts` and the
 //! rust side in `execution_impl.rs`.
 
-use as_ffi_bindings::{BufferPtr, Read as ASRead, StringPtr, Write as ASWrite};
+use as_ffi_bindings::{BufferPtr, StringPtr, Write as ASWrite};
 use function_name::named;
+use massa_proto_rs::massa::{abi::v1::SendAsyncMessageRequest, model::v1::Slot};
+use prost::Message;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
 use std::ops::Add;
-use wasmer::{AsStoreMut, AsStoreRef, FunctionEnvMut, Memory};
+use wasmer::{AsStoreMut, FunctionEnvMut, StoreMut};
 
-use super::env::{get_remaining_points, sub_remaining_gas_abi, ASEnv};
-use crate::settings;
+use super::env::{
+    get_remaining_points, refund_storage_gas, sub_remaining_gas_abi, sub_remaining_gas_abi_of,
+    sub_remaining_gas_abi_per_byte, sub_remaining_gas_abi_per_comparison,
+    sub_remaining_gas_abi_per_entry, ASEnv, Metered,
+};
+use crate::amount;
+use crate::digest::NativeDigest;
+use crate::merkle::{self, MerkleHash};
+use crate::read_only_cache::CachedEvent;
+use crate::types::sort_keys;
+use crate::{
+    bls, settings, AbortInfo, Response, RuntimeConfig, StorageLimitError, StorageLimits,
+    TransferError,
+};
 #[cfg(feature = "execution-trace")]
 use crate::{
     into_trace_value,
     types::{AbiTrace, AbiTraceType},
 };
 
-use super::common::{call_module, create_sc, function_exists, local_call};
-use super::error::{abi_bail, ABIResult};
-
-macro_rules! get_memory {
-    ($env:ident) => {
-        match $env.get_ffi_env().memory.as_ref() {
-            Some(mem) => mem,
-            _ => abi_bail!("AssemblyScript memory is missing from the environment"),
-        }
-    };
-}
+use super::common::{
+    call_module, check_created_bytecode, create_sc, create_sc_with_datastore, create_sc_with_seed,
+    function_exists, local_call, local_call_self, set_bytecode, try_call_module,
+};
+use super::error::{abi_bail, ABIError, ABIResult};
 
 /// Retrieves the AssemblyScript environment.
 ///
 /// Fails during instantiation to avoid gas manipulation in the WASM start
-/// function.
+/// function, and once the deadline of the execution has passed or it was
+/// interrupted.
 pub(crate) fn get_env(ctx: &FunctionEnvMut<ASEnv>) -> ABIResult<ASEnv> {
-    let env = ctx.data().clone();
+    let env = ctx.data();
+    check_env(env)?;
+    Ok(env.clone())
+}
+
+/// Like `get_env`, borrowing the environment instead of copying it, along
+/// with the store the ABI is charged on. For the ABIs that do not need the
+/// whole context once charged.
+pub(crate) fn get_env_and_store<'a>(
+    ctx: &'a mut FunctionEnvMut<ASEnv>,
+) -> ABIResult<(&'a ASEnv, StoreMut<'a>)> {
+    let (env, store) = ctx.data_and_store_mut();
+    check_env(env)?;
+    Ok((&*env, store))
+}
+
+fn check_env(env: &ASEnv) -> ABIResult<()> {
     if !(env.abi_enabled.load(std::sync::atomic::Ordering::Relaxed)) {
         abi_bail!("ABI calls are not available during instantiation");
-    } else {
-        Ok(env)
     }
+    env.get_call_context().check_deadline()?;
+    Ok(())
 }
 
 /// Get the coins that have been made available for a specific purpose for the
 /// current call.
 #[named]
 pub(crate) fn assembly_script_get_call_coins(mut ctx: FunctionEnvMut<ASEnv>) -> ABIResult<i64> {
-    let env = get_env(&ctx)?;
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
-    let res = env.get_interface().get_call_coins()? as i64;
+    let (env, mut store) = get_env_and_store(&mut ctx)?;
+    let _call = sub_remaining_gas_abi(env, &mut store, function_name!())?;
+    let res = env.interface().get_call_coins()? as i64;
+    #[cfg(feature = "execution-trace")]
+    ctx.data_mut().trace.push(AbiTrace {
+        name: function_name!().to_string(),
+        params: vec![],
+        return_value: res.into(),
+        sub_calls: None,
+    });
+    Ok(res)
+}
+
+/// Get the coins sent so far by the nested calls of the current call. The
+/// coins of the nested calls that failed are not counted.
+#[named]
+pub(crate) fn assembly_script_get_spent_coins(mut ctx: FunctionEnvMut<ASEnv>) -> ABIResult<i64> {
+    let (env, mut store) = get_env_and_store(&mut ctx)?;
+    let _call = sub_remaining_gas_abi(env, &mut store, function_name!())?;
+    let res = env.get_spent_coins() as i64;
     #[cfg(feature = "execution-trace")]
     ctx.data_mut().trace.push(AbiTrace {
         name: function_name!().to_string(),
@@ -69,22 +113,22 @@ pub(crate) fn assembly_script_transfer_coins(
     raw_amount: i64,
 ) -> ABIResult<()> {
     let env = get_env(&ctx)?;
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    env.get_call_context().check_write(function_name!())?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
     if raw_amount.is_negative() {
         abi_bail!("Negative raw amount.");
     }
-    let memory = get_memory!(env);
-    let to_address = read_string(memory, &ctx, to_address)?;
+    let to_address = env.read_string(&mut ctx, function_name!(), to_address)?;
     // Do not remove this. It could be used for gas_calibration in future.
     // if cfg!(feature = "gas_calibration") {
     //     let fname = format!("massa.{}:0", function_name!());
     //     param_size_update(&env, &mut ctx, &fname, to_address.len(), true);
     // }
-    env.get_interface()
+    env.interface()
         .transfer_coins(&to_address, raw_amount as u64)?;
     #[cfg(feature = "execution-trace")]
     {
-        let call_stack = env.get_interface().get_call_stack();
+        let call_stack = env.interface().get_call_stack();
         // TODO: check if this is always correct (with nested of nested call?)
         let from_address = call_stack
             .unwrap_or_default()
@@ -115,13 +159,13 @@ pub(crate) fn assembly_script_transfer_coins_for(
     raw_amount: i64,
 ) -> ABIResult<()> {
     let env = get_env(&ctx)?;
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    env.get_call_context().check_write(function_name!())?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
     if raw_amount.is_negative() {
         abi_bail!("Negative raw amount.");
     }
-    let memory = get_memory!(env);
-    let from_address = read_string(memory, &ctx, from_address)?;
-    let to_address = read_string(memory, &ctx, to_address)?;
+    let from_address = env.read_string(&mut ctx, function_name!(), from_address)?;
+    let to_address = env.read_string(&mut ctx, function_name!(), to_address)?;
     // Do not remove this. It could be used for gas_calibration in future.
     // if cfg!(feature = "gas_calibration") {
     //     let fname = format!("massa.{}:0", function_name!());
@@ -129,7 +173,7 @@ pub(crate) fn assembly_script_transfer_coins_for(
     //     let fname = format!("massa.{}:1", function_name!());
     //     param_size_update(&env, &mut ctx, &fname, to_address.len(), true);
     // }
-    env.get_interface()
+    env.interface()
         .transfer_coins_for(&from_address, &to_address, raw_amount as u64)?;
     #[cfg(feature = "execution-trace")]
     ctx.data_mut().trace.push(AbiTrace {
@@ -145,11 +189,52 @@ pub(crate) fn assembly_script_transfer_coins_for(
     Ok(())
 }
 
+/// Same as `assembly_script_transfer_coins` but the failures reported by the
+/// interface as a `TransferError` do not trap.
+///
+/// Returns the output of `ser_transfer_result`: 1 on success, or 0 followed by
+/// the `TransferError` code.
+#[named]
+pub(crate) fn assembly_script_transfer_coins_checked(
+    mut ctx: FunctionEnvMut<ASEnv>,
+    to_address: i32,
+    raw_amount: i64,
+) -> ABIResult<i32> {
+    let env = get_env(&ctx)?;
+    env.get_call_context().check_write(function_name!())?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    if raw_amount.is_negative() {
+        abi_bail!("Negative raw amount.");
+    }
+    let to_address = env.read_string(&mut ctx, function_name!(), to_address)?;
+    let result = env
+        .interface()
+        .transfer_coins_checked(&to_address, raw_amount as u64)?;
+    let fmt_result = ser_transfer_result(&result);
+    #[cfg(feature = "execution-trace")]
+    ctx.data_mut().trace.push(AbiTrace {
+        name: function_name!().to_string(),
+        params: vec![
+            into_trace_value!(to_address),
+            (stringify!(raw_amount), raw_amount as u64).into(),
+        ],
+        return_value: fmt_result.clone().into(),
+        sub_calls: None,
+    });
+    match BufferPtr::alloc(&fmt_result, env.get_ffi_env(), &mut ctx) {
+        Ok(ret) => Ok(ret.offset() as i32),
+        _ => abi_bail!(format!(
+            "Cannot allocate the result of the transfer to {}",
+            to_address
+        )),
+    }
+}
+
 #[named]
 pub(crate) fn assembly_script_get_balance(mut ctx: FunctionEnvMut<ASEnv>) -> ABIResult<i64> {
     let env = get_env(&ctx)?;
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
-    let res = env.get_interface().get_balance()? as i64;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let res = env.interface().get_balance()? as i64;
     #[cfg(feature = "execution-trace")]
     ctx.data_mut().trace.push(AbiTrace {
         name: function_name!().to_string(),
@@ -166,15 +251,17 @@ pub(crate) fn assembly_script_get_balance_for(
     address: i32,
 ) -> ABIResult<i64> {
     let env = get_env(&ctx)?;
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
-    let memory = get_memory!(env);
-    let address = read_string(memory, &ctx, address)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let address = env.read_string(&mut ctx, function_name!(), address)?;
     // Do not remove this. It could be used for gas_calibration in future.
     // if cfg!(feature = "gas_calibration") {
     //     let fname = format!("massa.{}:0", function_name!());
     //     param_size_update(&env, &mut ctx, &fname, address.len(), true);
     // }
-    let res = env.get_interface().get_balance_for(&address)? as i64;
+    if let Err(reason) = env.get_config().address_validator.validate(&address) {
+        return Err(ABIError::InvalidAddress { address, reason });
+    }
+    let res = env.interface().get_balance_for(&address)? as i64;
     #[cfg(feature = "execution-trace")]
     ctx.data_mut().trace.push(AbiTrace {
         name: function_name!().to_string(),
@@ -185,6 +272,64 @@ pub(crate) fn assembly_script_get_balance_for(
     Ok(res)
 }
 
+/// Balances of the addresses serialized by `ser_bytearray_vec`, charged for
+/// each of them. Each balance is serialized as `BALANCE_OK` followed by the
+/// amount (64 bits LE), or as a marker alone when the address is refused by
+/// the `AddressValidator` or its balance cannot be read, after the number of
+/// addresses (32 bits LE).
+#[named]
+pub(crate) fn assembly_script_get_balances(
+    mut ctx: FunctionEnvMut<ASEnv>,
+    addresses: i32,
+) -> ABIResult<i32> {
+    let env = get_env(&ctx)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let addresses = deser_bytearray_vec(&env.read_buffer(&ctx, function_name!(), addresses)?)?;
+    let max = env.get_config().max_balance_batch_len;
+    if addresses.len() > max {
+        abi_bail!(format!(
+            "Too many addresses: {}, the maximum is {}",
+            addresses.len(),
+            max
+        ));
+    }
+    sub_remaining_gas_abi_per_entry(&env, &mut ctx, function_name!(), addresses.len())?;
+    let mut balances = (addresses.len() as u32).to_le_bytes().to_vec();
+    for address in &addresses {
+        let balance = match std::str::from_utf8(address) {
+            Ok(address) => match env.get_config().address_validator.validate(address) {
+                Ok(()) => env
+                    .interface()
+                    .get_balance_for(address)
+                    .map_err(|_| BALANCE_UNAVAILABLE),
+                Err(_) => Err(BALANCE_INVALID_ADDRESS),
+            },
+            Err(_) => Err(BALANCE_INVALID_ADDRESS),
+        };
+        match balance {
+            Ok(balance) => {
+                balances.push(BALANCE_OK);
+                balances.extend(balance.to_le_bytes());
+            }
+            Err(marker) => balances.push(marker),
+        }
+    }
+    let ptr = pointer_from_bytearray(&env, &mut ctx, &balances)?.offset();
+    #[cfg(feature = "execution-trace")]
+    ctx.data_mut().trace.push(AbiTrace {
+        name: function_name!().to_string(),
+        params: vec![into_trace_value!(addresses)],
+        return_value: balances.into(),
+        sub_calls: None,
+    });
+    Ok(ptr as i32)
+}
+
+/// Markers of the balances of `assembly_script_get_balances`
+const BALANCE_OK: u8 = 0;
+const BALANCE_INVALID_ADDRESS: u8 = 1;
+const BALANCE_UNAVAILABLE: u8 = 2;
+
 /// Raw call that have the right type signature to be able to be call a module
 /// directly form AssemblyScript:
 #[named]
@@ -196,11 +341,10 @@ pub(crate) fn assembly_script_call(
     call_coins: i64,
 ) -> ABIResult<i32> {
     let env = get_env(&ctx)?;
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
-    let memory = get_memory!(env);
-    let address = read_string(memory, &ctx, address)?;
-    let function = read_string(memory, &ctx, function)?;
-    let param = read_buffer(memory, &ctx, param)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let address = env.read_string(&mut ctx, function_name!(), address)?;
+    let function = env.read_string(&mut ctx, function_name!(), function)?;
+    let param = env.read_buffer(&ctx, function_name!(), param)?;
 
     // Do not remove this. It could be used for gas_calibration in future.
     // if cfg!(feature = "gas_calibration") {
@@ -212,7 +356,15 @@ pub(crate) fn assembly_script_call(
     //     param_size_update(&env, &mut ctx, &fname, param.len(), true);
     // }
 
-    let response = call_module(&mut ctx, &address, &function, &param, call_coins)?;
+    let response = call_module(
+        &mut ctx,
+        function_name!(),
+        &address,
+        &function,
+        &param,
+        call_coins,
+        None,
+    )?;
     #[cfg(feature = "execution-trace")]
     ctx.data_mut().trace.push(AbiTrace {
         name: function_name!().to_string(),
@@ -234,11 +386,113 @@ pub(crate) fn assembly_script_call(
     }
 }
 
+/// Same as `assembly_script_call` but the execution of the callee is given at
+/// most `max_gas`, see `call_module`. A `max_gas` above the remaining gas
+/// gives it the remaining gas.
 #[named]
-pub(crate) fn assembly_script_get_remaining_gas(mut ctx: FunctionEnvMut<ASEnv>) -> ABIResult<i64> {
+pub(crate) fn assembly_script_call_with_gas_limit(
+    mut ctx: FunctionEnvMut<ASEnv>,
+    address: i32,
+    function: i32,
+    param: i32,
+    call_coins: i64,
+    max_gas: i64,
+) -> ABIResult<i32> {
+    let env = get_env(&ctx)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let Ok(max_gas) = u64::try_from(max_gas) else {
+        abi_bail!(format!("Negative gas limit of call: {}", max_gas));
+    };
+    let address = env.read_string(&mut ctx, function_name!(), address)?;
+    let function = env.read_string(&mut ctx, function_name!(), function)?;
+    let param = env.read_buffer(&ctx, function_name!(), param)?;
+
+    let response = call_module(
+        &mut ctx,
+        function_name!(),
+        &address,
+        &function,
+        &param,
+        call_coins,
+        Some(max_gas),
+    )?;
+    #[cfg(feature = "execution-trace")]
+    ctx.data_mut().trace.push(AbiTrace {
+        name: function_name!().to_string(),
+        params: vec![
+            ("address", address.clone()).into(),
+            ("function", function.clone()).into(),
+            into_trace_value!(param),
+            into_trace_value!(call_coins),
+            into_trace_value!(max_gas),
+        ],
+        return_value: response.ret.clone().into(),
+        sub_calls: Some(response.trace),
+    });
+    match BufferPtr::alloc(&response.ret, env.get_ffi_env(), &mut ctx) {
+        Ok(ret) => Ok(ret.offset() as i32),
+        _ => abi_bail!(format!(
+            "Cannot allocate response in call {}::{}",
+            address, function
+        )),
+    }
+}
+
+/// Same as `assembly_script_call` but a failure of the callee does not trap.
+///
+/// Returns the output of `ser_try_call_result`: 1 followed by the return value
+/// of the callee, or 0 followed by the UTF-8 error message.
+#[named]
+pub(crate) fn assembly_script_try_call(
+    mut ctx: FunctionEnvMut<ASEnv>,
+    address: i32,
+    function: i32,
+    param: i32,
+    call_coins: i64,
+) -> ABIResult<i32> {
     let env = get_env(&ctx)?;
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
-    let res = get_remaining_points(&env, &mut ctx)? as i64;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let address = env.read_string(&mut ctx, function_name!(), address)?;
+    let function = env.read_string(&mut ctx, function_name!(), function)?;
+    let param = env.read_buffer(&ctx, function_name!(), param)?;
+
+    let result = try_call_module(
+        &mut ctx,
+        function_name!(),
+        &address,
+        &function,
+        &param,
+        call_coins,
+    )?;
+    let fmt_result = ser_try_call_result(&result);
+    #[cfg(feature = "execution-trace")]
+    ctx.data_mut().trace.push(AbiTrace {
+        name: function_name!().to_string(),
+        params: vec![
+            ("address", address.clone()).into(),
+            ("function", function.clone()).into(),
+            into_trace_value!(param),
+            into_trace_value!(call_coins),
+        ],
+        return_value: fmt_result.clone().into(),
+        sub_calls: result.ok().map(|response| response.trace),
+    });
+    match BufferPtr::alloc(&fmt_result, env.get_ffi_env(), &mut ctx) {
+        Ok(ret) => Ok(ret.offset() as i32),
+        _ => abi_bail!(format!(
+            "Cannot allocate response in try call {}::{}",
+            address, function
+        )),
+    }
+}
+
+/// Get the remaining gas, read after charging the cost of this ABI.
+/// Returns `u64::MAX` when the `gas_calibration` feature is enabled.
+#[named]
+pub(crate) fn assembly_script_get_remaining_gas(mut ctx: FunctionEnvMut<ASEnv>) -> ABIResult<i64> {
+    let (env, mut store) = get_env_and_store(&mut ctx)?;
+    let _call = sub_remaining_gas_abi(env, &mut store, function_name!())?;
+    let res = get_remaining_points(env, &mut store)? as i64;
     #[cfg(feature = "execution-trace")]
     ctx.data_mut().trace.push(AbiTrace {
         name: function_name!().to_string(),
@@ -249,16 +503,21 @@ pub(crate) fn assembly_script_get_remaining_gas(mut ctx: FunctionEnvMut<ASEnv>)
     Ok(res)
 }
 
+/// Check of the deadline injected at the loop headers by `DeadlineCheck`.
+/// Not charged, and allowed during instantiation.
+pub(crate) fn assembly_script_check_deadline(ctx: FunctionEnvMut<ASEnv>) -> ABIResult<()> {
+    ctx.data().get_call_context().check_deadline()
+}
+
 /// Create an instance of VM from a module with a
 /// given interface, an operation number limit and a webassembly module
 ///
 /// An utility print function to write on stdout directly from AssemblyScript:
 #[named]
 pub(crate) fn assembly_script_print(mut ctx: FunctionEnvMut<ASEnv>, arg: i32) -> ABIResult<()> {
-    let env = get_env(&ctx)?;
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
-    let memory = get_memory!(env);
-    let message = read_string(memory, &ctx, arg)?;
+    let (env, mut store) = get_env_and_store(&mut ctx)?;
+    let _call = sub_remaining_gas_abi(env, &mut store, function_name!())?;
+    let message = env.read_string(&mut store, function_name!(), arg)?;
 
     // Do not remove this. It could be used for gas_calibration in future.
     // if cfg!(feature = "gas_calibration") {
@@ -266,7 +525,7 @@ pub(crate) fn assembly_script_print(mut ctx: FunctionEnvMut<ASEnv>, arg: i32) ->
     //     param_size_update(&env, &mut ctx, &fname, message.len(), true);
     // }
 
-    env.get_interface().print(&message)?;
+    env.interface().print(&message)?;
     #[cfg(feature = "execution-trace")]
     ctx.data_mut().trace.push(AbiTrace {
         name: function_name!().to_string(),
@@ -277,14 +536,17 @@ pub(crate) fn assembly_script_print(mut ctx: FunctionEnvMut<ASEnv>, arg: i32) ->
     Ok(())
 }
 
-/// Get the operation datastore keys (aka entries)
+/// Get the operation datastore keys (aka entries), sorted bytewise
 #[named]
 pub(crate) fn assembly_script_get_op_keys(mut ctx: FunctionEnvMut<ASEnv>) -> ABIResult<i32> {
     let env = get_env(&ctx)?;
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
-    match env.get_interface().get_op_keys(None) {
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    match env.interface().get_op_keys(None) {
         Err(err) => abi_bail!(err),
-        Ok(keys) => {
+        Ok(mut keys) => {
+            env.get_storage_limits().check_entry_count(keys.len())?;
+            let comparisons = sort_keys(&mut keys);
+            sub_remaining_gas_abi_per_comparison(&env, &mut ctx, function_name!(), comparisons)?;
             let fmt_keys =
                 ser_bytearray_vec(&keys, keys.len(), settings::max_op_datastore_entry_count())?;
             let ptr = pointer_from_bytearray(&env, &mut ctx, &fmt_keys)?.offset();
@@ -301,24 +563,31 @@ pub(crate) fn assembly_script_get_op_keys(mut ctx: FunctionEnvMut<ASEnv>) -> ABI
     }
 }
 
-/// Get the operation datastore keys (aka entries)
+/// Get the operation datastore keys (aka entries) starting with a binary
+/// prefix, every key if the prefix is empty, sorted bytewise.
+///
+/// The gas is charged for each key returned and for their sort, at most
+/// `max_keys_per_query` of the storage limits are returned.
 #[named]
 pub(crate) fn assembly_script_get_op_keys_prefix(
     mut ctx: FunctionEnvMut<ASEnv>,
     prefix: i32,
 ) -> ABIResult<i32> {
     let env = get_env(&ctx)?;
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
-    let memory = get_memory!(env);
-    let prefix = read_buffer(memory, &ctx, prefix)?;
-    let prefix_opt = if !prefix.is_empty() {
-        Some(prefix.as_ref())
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let prefix = env.read_buffer(&ctx, function_name!(), prefix)?;
+    let keys = if prefix.is_empty() {
+        env.interface().get_op_keys(None)
     } else {
-        None
+        env.interface().get_op_keys_prefix(&prefix)
     };
-    match env.get_interface().get_op_keys(prefix_opt) {
+    match keys {
         Err(err) => abi_bail!(err),
-        Ok(keys) => {
+        Ok(mut keys) => {
+            env.get_storage_limits().check_entry_count(keys.len())?;
+            sub_remaining_gas_abi_per_entry(&env, &mut ctx, function_name!(), keys.len())?;
+            let comparisons = sort_keys(&mut keys);
+            sub_remaining_gas_abi_per_comparison(&env, &mut ctx, function_name!(), comparisons)?;
             let fmt_keys =
                 ser_bytearray_vec(&keys, keys.len(), settings::max_op_datastore_entry_count())?;
             let ptr = pointer_from_bytearray(&env, &mut ctx, &fmt_keys)?.offset();
@@ -342,17 +611,16 @@ pub(crate) fn assembly_script_has_op_key(
     key: i32,
 ) -> ABIResult<i32> {
     let env = get_env(&ctx)?;
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
-    let env = get_env(&ctx)?;
-    let memory = get_memory!(env);
-    let key_bytes = read_buffer(memory, &ctx, key)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let key_bytes = env.read_buffer(&ctx, function_name!(), key)?;
     // Do not remove this. It could be used for gas_calibration in future.
     // if cfg!(feature = "gas_calibration") {
     //     let fname = format!("massa.{}:0", function_name!());
     //     param_size_update(&env, &mut ctx, &fname, key_bytes.len(), true);
     // }
 
-    match env.get_interface().op_entry_exists(&key_bytes) {
+    env.get_storage_limits().check_key(&key_bytes)?;
+    match env.interface().op_entry_exists(&key_bytes) {
         Err(err) => abi_bail!(err),
         Ok(b) => {
             // https://doc.rust-lang.org/reference/types/boolean.html
@@ -380,15 +648,16 @@ pub(crate) fn assembly_script_get_op_data(
     key: i32,
 ) -> ABIResult<i32> {
     let env = get_env(&ctx)?;
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
-    let memory = get_memory!(env);
-    let key_bytes = read_buffer(memory, &ctx, key)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let key_bytes = env.read_buffer(&ctx, function_name!(), key)?;
     // Do not remove this. It could be used for gas_calibration in future.
     // if cfg!(feature = "gas_calibration") {
     //     let fname = format!("massa.{}:0", function_name!());
     //     param_size_update(&env, &mut ctx, &fname, key_bytes.len(), true);
     // }
-    let data = env.get_interface().get_op_data(&key_bytes)?;
+    env.get_storage_limits().check_key(&key_bytes)?;
+    let data = env.interface().get_op_data(&key_bytes)?;
+    env.get_storage_limits().check_value(&data)?;
     let ptr = pointer_from_bytearray(&env, &mut ctx, &data)?.offset() as i32;
 
     #[cfg(feature = "execution-trace")]
@@ -409,16 +678,17 @@ pub(crate) fn assembly_script_create_sc(
     bytecode: i32,
 ) -> ABIResult<i32> {
     let env = get_env(&ctx)?;
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
-    let memory = get_memory!(env);
-    let bytecode: Vec<u8> = read_buffer(memory, &ctx, bytecode)?;
+    env.get_call_context().check_write(function_name!())?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let bytecode: Vec<u8> = env.read_buffer(&ctx, function_name!(), bytecode)?;
     // Do not remove this. It could be used for gas_calibration in future.
     // if cfg!(feature = "gas_calibration") {
     //     let fname = format!("massa.{}:0", function_name!());
     //     param_size_update(&env, &mut ctx, &fname, bytecode.len(), true);
     // }
+    check_created_bytecode(&mut ctx, function_name!(), &bytecode)?;
     let address = create_sc(&mut ctx, &bytecode)?;
-    let ptr = StringPtr::alloc(&address, env.get_ffi_env(), &mut ctx)?.offset() as i32;
+    let ptr = pointer_from_string(&env, &mut ctx, &address)?.offset() as i32;
 
     #[cfg(feature = "execution-trace")]
     ctx.data_mut().trace.push(AbiTrace {
@@ -430,19 +700,99 @@ pub(crate) fn assembly_script_create_sc(
     Ok(ptr)
 }
 
+/// Create a smart contract with its initial datastore, serialized in the
+/// `assembly_script_get_keys_values` format. The contract is not created if
+/// one of the entries is invalid.
+#[named]
+pub(crate) fn assembly_script_create_sc_with_data(
+    mut ctx: FunctionEnvMut<ASEnv>,
+    bytecode: i32,
+    datastore: i32,
+) -> ABIResult<i32> {
+    let env = get_env(&ctx)?;
+    env.get_call_context().check_write(function_name!())?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let bytecode = env.read_buffer(&ctx, function_name!(), bytecode)?;
+    let datastore = env.read_buffer(&ctx, function_name!(), datastore)?;
+    // the bytecode is charged by `check_created_bytecode`
+    sub_remaining_gas_abi_per_byte(&env, &mut ctx, function_name!(), datastore.len())?;
+    let entries = deser_key_value_vec(&datastore, &env.get_storage_limits())?;
+    check_created_bytecode(&mut ctx, function_name!(), &bytecode)?;
+    let address = create_sc_with_datastore(&mut ctx, &bytecode, &entries)?;
+    let ptr = pointer_from_string(&env, &mut ctx, &address)?.offset() as i32;
+
+    #[cfg(feature = "execution-trace")]
+    ctx.data_mut().trace.push(AbiTrace {
+        name: function_name!().to_string(),
+        params: vec![into_trace_value!(bytecode), into_trace_value!(datastore)],
+        return_value: address.clone().into(),
+        sub_calls: None,
+    });
+    Ok(ptr)
+}
+
+/// Create a smart contract at the address derived from `seed`, the one
+/// `assembly_script_predict_sc_address` returns for it
+#[named]
+pub(crate) fn assembly_script_create_sc_with_seed(
+    mut ctx: FunctionEnvMut<ASEnv>,
+    bytecode: i32,
+    seed: i32,
+) -> ABIResult<i32> {
+    let env = get_env(&ctx)?;
+    env.get_call_context().check_write(function_name!())?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let bytecode = env.read_buffer(&ctx, function_name!(), bytecode)?;
+    let seed = env.read_buffer(&ctx, function_name!(), seed)?;
+    check_created_bytecode(&mut ctx, function_name!(), &bytecode)?;
+    let address = create_sc_with_seed(&mut ctx, &bytecode, &seed)?;
+    let ptr = pointer_from_string(&env, &mut ctx, &address)?.offset() as i32;
+
+    #[cfg(feature = "execution-trace")]
+    ctx.data_mut().trace.push(AbiTrace {
+        name: function_name!().to_string(),
+        params: vec![into_trace_value!(bytecode), into_trace_value!(seed)],
+        return_value: address.clone().into(),
+        sub_calls: None,
+    });
+    Ok(ptr)
+}
+
+/// Address a smart contract created by the current address with `seed` would
+/// have, for the factories registering their contracts before creating them
+#[named]
+pub(crate) fn assembly_script_predict_sc_address(
+    mut ctx: FunctionEnvMut<ASEnv>,
+    seed: i32,
+) -> ABIResult<i32> {
+    let env = get_env(&ctx)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let seed = env.read_buffer(&ctx, function_name!(), seed)?;
+    let address = env.get_interface().predict_created_address(&seed)?;
+    let ptr = pointer_from_string(&env, &mut ctx, &address)?.offset() as i32;
+
+    #[cfg(feature = "execution-trace")]
+    ctx.data_mut().trace.push(AbiTrace {
+        name: function_name!().to_string(),
+        params: vec![into_trace_value!(seed)],
+        return_value: address.clone().into(),
+        sub_calls: None,
+    });
+    Ok(ptr)
+}
+
 /// performs a hash on a bytearray and returns the hash
 #[named]
 pub(crate) fn assembly_script_hash(mut ctx: FunctionEnvMut<ASEnv>, value: i32) -> ABIResult<i32> {
     let env = get_env(&ctx)?;
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
-    let memory = get_memory!(env);
-    let bytes = read_buffer(memory, &ctx, value)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let bytes = env.read_buffer(&ctx, function_name!(), value)?;
     // Do not remove this. It could be used for gas_calibration in future.
     // if cfg!(feature = "gas_calibration") {
     //     let fname = format!("massa.{}:0", function_name!());
     //     param_size_update(&env, &mut ctx, &fname, value.len(), true);
     // }
-    let hash = env.get_interface().hash(&bytes)?.to_vec();
+    let hash = env.interface().hash(&bytes)?.to_vec();
     let ptr = pointer_from_bytearray(&env, &mut ctx, &hash)?.offset();
     #[cfg(feature = "execution-trace")]
     ctx.data_mut().trace.push(AbiTrace {
@@ -461,10 +811,10 @@ pub(crate) fn assembly_script_keccak256_hash(
     value: i32,
 ) -> ABIResult<i32> {
     let env = get_env(&ctx)?;
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
-    let memory = get_memory!(env);
-    let bytes = read_buffer(memory, &ctx, value)?;
-    let hash = env.get_interface().hash_keccak256(&bytes)?.to_vec();
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let bytes = env.read_buffer(&ctx, function_name!(), value)?;
+    sub_remaining_gas_abi_per_byte(&env, &mut ctx, function_name!(), bytes.len())?;
+    let hash = env.interface().hash_keccak256(&bytes)?.to_vec();
     let ptr = pointer_from_bytearray(&env, &mut ctx, &hash)?.offset();
 
     #[cfg(feature = "execution-trace")]
@@ -478,6 +828,75 @@ pub(crate) fn assembly_script_keccak256_hash(
     Ok(ptr as i32)
 }
 
+/// Compute `digest` of `data`, an `ArrayBuffer`. Shared by the digests of
+/// `crate::digest::DIGESTS`, charged under their own name.
+pub(crate) fn assembly_script_digest(
+    mut ctx: FunctionEnvMut<ASEnv>,
+    digest: &NativeDigest,
+    data: i32,
+) -> ABIResult<i32> {
+    let env = get_env(&ctx)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, digest.as_abi)?;
+    let data = env.read_buffer(&ctx, digest.as_abi, data)?;
+    sub_remaining_gas_abi_per_byte(&env, &mut ctx, digest.as_abi, data.len())?;
+    let hash = match digest.compute(&data, env.get_config().max_digest_input_len) {
+        Ok(hash) => hash,
+        Err(err) => abi_bail!(err),
+    };
+    let ptr = pointer_from_bytearray(&env, &mut ctx, &hash)?.offset();
+
+    #[cfg(feature = "execution-trace")]
+    ctx.data_mut().trace.push(AbiTrace {
+        name: digest.as_abi.to_string(),
+        params: vec![into_trace_value!(data)],
+        return_value: hash.into(),
+        sub_calls: None,
+    });
+
+    Ok(ptr as i32)
+}
+
+/// Verify a Merkle proof, see `crate::merkle` for its encoding. `hash_algo`
+/// is `0` for the Massa hash and `1` for SHA-256. A malformed proof fails the
+/// call instead of returning false.
+#[named]
+pub(crate) fn assembly_script_verify_merkle_proof(
+    mut ctx: FunctionEnvMut<ASEnv>,
+    leaf: i32,
+    proof: i32,
+    root: i32,
+    hash_algo: i32,
+) -> ABIResult<i32> {
+    let env = get_env(&ctx)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let hash = MerkleHash::from_discriminant(hash_algo)?;
+    let leaf = env.read_buffer(&ctx, function_name!(), leaf)?;
+    let proof = env.read_buffer(&ctx, function_name!(), proof)?;
+    let root = env.read_buffer(&ctx, function_name!(), root)?;
+    let depth = merkle::check_proof(&proof, env.get_config().max_merkle_proof_depth)?;
+    sub_remaining_gas_abi_per_entry(&env, &mut ctx, function_name!(), depth)?;
+    let interface = env.interface();
+    let valid = merkle::verify(&leaf, &proof, &root, |data| -> ABIResult<[u8; 32]> {
+        match hash {
+            MerkleHash::Massa => Ok(interface.hash(data)?),
+            MerkleHash::Sha256 => Ok(Sha256::digest(data).into()),
+        }
+    })?;
+    #[cfg(feature = "execution-trace")]
+    ctx.data_mut().trace.push(AbiTrace {
+        name: function_name!().to_string(),
+        params: vec![
+            into_trace_value!(leaf),
+            into_trace_value!(proof),
+            into_trace_value!(root),
+            into_trace_value!(hash_algo),
+        ],
+        return_value: valid.into(),
+        sub_calls: None,
+    });
+    Ok(valid as i32)
+}
+
 /// Get keys (aka entries) in the datastore
 #[named]
 pub(crate) fn assembly_script_get_keys(
@@ -485,16 +904,16 @@ pub(crate) fn assembly_script_get_keys(
     prefix: i32,
 ) -> ABIResult<i32> {
     let env = get_env(&ctx)?;
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
-    let memory = get_memory!(env);
-    let prefix = read_buffer(memory, &ctx, prefix)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let prefix = env.read_buffer(&ctx, function_name!(), prefix)?;
     let prefix_opt = if !prefix.is_empty() {
         Some(prefix.as_ref())
     } else {
         None
     };
-    let keys = env.get_interface().get_keys(prefix_opt)?;
-    let fmt_keys = ser_bytearray_vec(&keys, keys.len(), settings::max_datastore_entry_count())?;
+    let keys = env.interface().get_keys(prefix_opt)?;
+    let max_keys = env.get_storage_limits().max_keys_per_query;
+    let fmt_keys = ser_bytearray_vec(&keys, keys.len(), max_keys)?;
     let ptr = pointer_from_bytearray(&env, &mut ctx, &fmt_keys)?.offset();
 
     #[cfg(feature = "execution-trace")]
@@ -507,6 +926,73 @@ pub(crate) fn assembly_script_get_keys(
     Ok(ptr as i32)
 }
 
+/// Get a page of up to `limit` keys in the datastore, starting after
+/// `cursor`, or at the first key with an empty one.
+///
+/// The result has the format: C_L (32 bits LE) C (8 bits * C_L) followed by
+/// the keys as serialized by `ser_bytearray_vec`, C being the cursor of the
+/// next page, empty once every key was returned.
+#[named]
+pub(crate) fn assembly_script_get_keys_paged(
+    mut ctx: FunctionEnvMut<ASEnv>,
+    prefix: i32,
+    cursor: i32,
+    limit: i32,
+) -> ABIResult<i32> {
+    let env = get_env(&ctx)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let prefix = env.read_buffer(&ctx, function_name!(), prefix)?;
+    let cursor = env.read_buffer(&ctx, function_name!(), cursor)?;
+    let max_keys = env.get_storage_limits().max_keys_per_query;
+    let limit = match usize::try_from(limit) {
+        Ok(limit) if (1..=max_keys).contains(&limit) => limit,
+        _ => abi_bail!(format!(
+            "Invalid page limit {}, it must be between 1 and {}",
+            limit, max_keys
+        )),
+    };
+    let prefix_opt = if !prefix.is_empty() {
+        Some(prefix.as_ref())
+    } else {
+        None
+    };
+    let (keys, next) = env
+        .interface()
+        .get_keys_paged(prefix_opt, open_cursor(&cursor)?, limit)
+        .map_err(|err| {
+            ABIError::Error(anyhow::anyhow!("Could not get the page of keys: {}", err))
+        })?;
+    if keys.len() > limit {
+        abi_bail!(format!(
+            "The interface returned {} keys for a page of {}",
+            keys.len(),
+            limit
+        ));
+    }
+    sub_remaining_gas_abi_per_entry(&env, &mut ctx, function_name!(), keys.len())?;
+    let next = next.map(|next| seal_cursor(&next)).unwrap_or_default();
+    let fmt_page = [
+        &(next.len() as u32).to_le_bytes()[..],
+        &next,
+        &ser_bytearray_vec(&keys, keys.len(), max_keys)?,
+    ]
+    .concat();
+    let ptr = pointer_from_bytearray(&env, &mut ctx, &fmt_page)?.offset();
+
+    #[cfg(feature = "execution-trace")]
+    ctx.data_mut().trace.push(AbiTrace {
+        name: function_name!().to_string(),
+        params: vec![
+            into_trace_value!(prefix),
+            into_trace_value!(cursor),
+            into_trace_value!(limit as i64),
+        ],
+        return_value: fmt_page.into(),
+        sub_calls: None,
+    });
+    Ok(ptr as i32)
+}
+
 /// Get keys (aka entries) in the datastore
 #[named]
 pub(crate) fn assembly_script_get_keys_for(
@@ -515,17 +1001,17 @@ pub(crate) fn assembly_script_get_keys_for(
     prefix: i32,
 ) -> ABIResult<i32> {
     let env = get_env(&ctx)?;
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
-    let memory = get_memory!(env);
-    let address = read_string(memory, &ctx, address)?;
-    let prefix = read_buffer(memory, &ctx, prefix)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let address = env.read_string(&mut ctx, function_name!(), address)?;
+    let prefix = env.read_buffer(&ctx, function_name!(), prefix)?;
     let prefix_opt = if !prefix.is_empty() {
         Some(prefix.as_ref())
     } else {
         None
     };
-    let keys = env.get_interface().get_keys_for(&address, prefix_opt)?;
-    let fmt_keys = ser_bytearray_vec(&keys, keys.len(), settings::max_datastore_entry_count())?;
+    let keys = env.interface().get_keys_for(&address, prefix_opt)?;
+    let max_keys = env.get_storage_limits().max_keys_per_query;
+    let fmt_keys = ser_bytearray_vec(&keys, keys.len(), max_keys)?;
     let ptr = pointer_from_bytearray(&env, &mut ctx, &fmt_keys)?.offset();
 
     #[cfg(feature = "execution-trace")]
@@ -538,21 +1024,55 @@ pub(crate) fn assembly_script_get_keys_for(
     Ok(ptr as i32)
 }
 
-/// sets a key-indexed data entry in the datastore, overwriting existing values
-/// if any
+/// Get the keys and values of the datastore entries matching a prefix in a
+/// single call
 #[named]
-pub(crate) fn assembly_script_set_data(
+pub(crate) fn assembly_script_get_keys_values(
     mut ctx: FunctionEnvMut<ASEnv>,
-    key: i32,
-    value: i32,
-) -> ABIResult<()> {
+    prefix: i32,
+) -> ABIResult<i32> {
     let env = get_env(&ctx)?;
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
-    let memory = get_memory!(env);
-    let key = read_buffer(memory, &ctx, key)?;
-    let value = read_buffer(memory, &ctx, value)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let prefix = env.read_buffer(&ctx, function_name!(), prefix)?;
+    let prefix_opt = if !prefix.is_empty() {
+        Some(prefix.as_ref())
+    } else {
+        None
+    };
+    // the keys are counted and charged before their values are read
+    let keys: Vec<Vec<u8>> = env.interface().get_keys(prefix_opt)?.into_iter().collect();
+    env.get_storage_limits().check_entry_count(keys.len())?;
+    sub_remaining_gas_abi_per_entry(&env, &mut ctx, function_name!(), keys.len())?;
+    let entries = env.interface().raw_get_data_batch(&keys)?;
+    let fmt_entries = ser_key_value_vec(&entries, env.get_storage_limits().max_keys_per_query)?;
+    sub_remaining_gas_abi_per_byte(&env, &mut ctx, function_name!(), fmt_entries.len())?;
+    let ptr = pointer_from_bytearray(&env, &mut ctx, &fmt_entries)?.offset();
 
-    // Do not remove this. It could be used for gas_calibration in future.
+    #[cfg(feature = "execution-trace")]
+    ctx.data_mut().trace.push(AbiTrace {
+        name: function_name!().to_string(),
+        params: vec![into_trace_value!(prefix)],
+        return_value: fmt_entries.into(),
+        sub_calls: None,
+    });
+    Ok(ptr as i32)
+}
+
+/// sets a key-indexed data entry in the datastore, overwriting existing values
+/// if any
+#[named]
+pub(crate) fn assembly_script_set_data(
+    mut ctx: FunctionEnvMut<ASEnv>,
+    key: i32,
+    value: i32,
+) -> ABIResult<()> {
+    let env = get_env(&ctx)?;
+    env.get_call_context().check_write(function_name!())?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let key = env.read_buffer(&ctx, function_name!(), key)?;
+    let value = env.read_buffer(&ctx, function_name!(), value)?;
+
+    // Do not remove this. It could be used for gas_calibration in future.
     // if cfg!(feature = "gas_calibration") {
     //     param_size_update(
     //         &env,
@@ -570,7 +1090,8 @@ pub(crate) fn assembly_script_set_data(
     //     );
     // }
 
-    env.get_interface().raw_set_data(&key, &value)?;
+    env.get_storage_limits().check_entry(&key, &value)?;
+    env.interface().raw_set_data(&key, &value)?;
     #[cfg(feature = "execution-trace")]
     ctx.data_mut().trace.push(AbiTrace {
         name: function_name!().to_string(),
@@ -581,6 +1102,31 @@ pub(crate) fn assembly_script_set_data(
     Ok(())
 }
 
+/// sets several key-indexed data entries in the datastore at once, serialized
+/// in the `assembly_script_get_keys_values` format. No entry is written if one
+/// of them is invalid.
+#[named]
+pub(crate) fn assembly_script_set_data_batch(
+    mut ctx: FunctionEnvMut<ASEnv>,
+    entries: i32,
+) -> ABIResult<()> {
+    let env = get_env(&ctx)?;
+    env.get_call_context().check_write(function_name!())?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let entries = env.read_buffer(&ctx, function_name!(), entries)?;
+    sub_remaining_gas_abi_per_byte(&env, &mut ctx, function_name!(), entries.len())?;
+    let batch = deser_key_value_vec(&entries, &env.get_storage_limits())?;
+    env.interface().raw_set_data_batch(&batch)?;
+    #[cfg(feature = "execution-trace")]
+    ctx.data_mut().trace.push(AbiTrace {
+        name: function_name!().to_string(),
+        params: vec![into_trace_value!(entries)],
+        return_value: AbiTraceType::None,
+        sub_calls: None,
+    });
+    Ok(())
+}
+
 /// appends data to a key-indexed data entry in the datastore, fails if the
 /// entry does not exist
 #[named]
@@ -590,10 +1136,10 @@ pub(crate) fn assembly_script_append_data(
     value: i32,
 ) -> ABIResult<()> {
     let env = get_env(&ctx)?;
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
-    let memory = get_memory!(env);
-    let key = read_buffer(memory, &ctx, key)?;
-    let value = read_buffer(memory, &ctx, value)?;
+    env.get_call_context().check_write(function_name!())?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let key = env.read_buffer(&ctx, function_name!(), key)?;
+    let value = env.read_buffer(&ctx, function_name!(), value)?;
     // Do not remove this. It could be used for gas_calibration in future.
     // if cfg!(feature = "gas_calibration") {
     //     let fname = format!("massa.{}:0", function_name!());
@@ -601,7 +1147,8 @@ pub(crate) fn assembly_script_append_data(
     //     let fname = format!("massa.{}:1", function_name!());
     //     param_size_update(&env, &mut ctx, &fname, value.len(), true);
     // }
-    env.get_interface().raw_append_data(&key, &value)?;
+    env.get_storage_limits().check_entry(&key, &value)?;
+    env.interface().raw_append_data(&key, &value)?;
     #[cfg(feature = "execution-trace")]
     ctx.data_mut().trace.push(AbiTrace {
         name: function_name!().to_string(),
@@ -616,15 +1163,15 @@ pub(crate) fn assembly_script_append_data(
 #[named]
 pub(crate) fn assembly_script_get_data(mut ctx: FunctionEnvMut<ASEnv>, key: i32) -> ABIResult<i32> {
     let env = get_env(&ctx)?;
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
-    let memory = get_memory!(env);
-    let key = read_buffer(memory, &ctx, key)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let key = env.read_buffer(&ctx, function_name!(), key)?;
     // Do not remove this. It could be used for gas_calibration in future.
     // if cfg!(feature = "gas_calibration") {
     //     let fname = format!("massa.{}:0", function_name!());
     //     param_size_update(&env, &mut ctx, &fname, key.len(), true);
     // }
-    let data = env.get_interface().raw_get_data(&key)?;
+    env.get_storage_limits().check_key(&key)?;
+    let data = env.interface().raw_get_data(&key)?;
     let ptr = pointer_from_bytearray(&env, &mut ctx, &data)?.offset() as i32;
     #[cfg(feature = "execution-trace")]
     ctx.data_mut().trace.push(AbiTrace {
@@ -636,19 +1183,120 @@ pub(crate) fn assembly_script_get_data(mut ctx: FunctionEnvMut<ASEnv>, key: i32)
     Ok(ptr)
 }
 
+/// Read at most `len` bytes of the datastore value of `key` from `offset`,
+/// an empty buffer when `offset` is past its end. A chunk is a single copy
+/// to the guest memory, bounded by `RuntimeConfig::max_abi_read_len`, and the gas
+/// per byte is charged for the bytes actually returned.
+#[named]
+pub(crate) fn assembly_script_get_data_chunk(
+    mut ctx: FunctionEnvMut<ASEnv>,
+    key: i32,
+    offset: i64,
+    len: i32,
+) -> ABIResult<i32> {
+    let env = get_env(&ctx)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let key = env.read_buffer(&ctx, function_name!(), key)?;
+    if offset.is_negative() {
+        abi_bail!("Negative offset.");
+    }
+    if len.is_negative() {
+        abi_bail!("Negative length.");
+    }
+    let max = env.get_config().max_abi_read_len;
+    if len as usize > max {
+        abi_bail!(format!(
+            "Chunk of {} bytes requested, the maximum is {}",
+            len, max
+        ));
+    }
+    env.get_storage_limits().check_key(&key)?;
+    let mut data = env
+        .interface()
+        .raw_get_data_chunk(&key, offset as u64, len as u64)?;
+    data.truncate(len as usize);
+    sub_remaining_gas_abi_per_byte(&env, &mut ctx, function_name!(), data.len())?;
+    let ptr = pointer_from_bytearray(&env, &mut ctx, &data)?.offset() as i32;
+    #[cfg(feature = "execution-trace")]
+    ctx.data_mut().trace.push(AbiTrace {
+        name: function_name!().to_string(),
+        params: vec![
+            into_trace_value!(key),
+            into_trace_value!(offset),
+            into_trace_value!(len),
+        ],
+        return_value: data.into(),
+        sub_calls: None,
+    });
+    Ok(ptr)
+}
+
+/// Length in bytes of the datastore value of `key`, to read it by chunks
+#[named]
+pub(crate) fn assembly_script_get_data_len(
+    mut ctx: FunctionEnvMut<ASEnv>,
+    key: i32,
+) -> ABIResult<i64> {
+    let env = get_env(&ctx)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let key = env.read_buffer(&ctx, function_name!(), key)?;
+    env.get_storage_limits().check_key(&key)?;
+    let len = env.interface().raw_get_data(&key)?.len() as i64;
+    #[cfg(feature = "execution-trace")]
+    ctx.data_mut().trace.push(AbiTrace {
+        name: function_name!().to_string(),
+        params: vec![into_trace_value!(key)],
+        return_value: len.into(),
+        sub_calls: None,
+    });
+    Ok(len)
+}
+
+/// Hint the interface that the datastore entries of the keys serialized by
+/// `ser_bytearray_vec` are about to be read, charged for each key. Whatever
+/// the interface does with it, the hint has no effect on the execution.
+#[named]
+pub(crate) fn assembly_script_hint_keys(
+    mut ctx: FunctionEnvMut<ASEnv>,
+    keys: i32,
+) -> ABIResult<()> {
+    let env = get_env(&ctx)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let keys = deser_bytearray_vec(&env.read_buffer(&ctx, function_name!(), keys)?)?;
+    let max = env.get_config().max_hint_keys;
+    if keys.len() > max {
+        abi_bail!(format!(
+            "Too many keys hinted: {}, the maximum is {}",
+            keys.len(),
+            max
+        ));
+    }
+    sub_remaining_gas_abi_per_entry(&env, &mut ctx, function_name!(), keys.len())?;
+    // the guest cannot tell whether the interface warmed anything
+    let _ = env.interface().prefetch(&keys);
+    #[cfg(feature = "execution-trace")]
+    ctx.data_mut().trace.push(AbiTrace {
+        name: function_name!().to_string(),
+        params: vec![into_trace_value!(keys)],
+        return_value: AbiTraceType::None,
+        sub_calls: None,
+    });
+    Ok(())
+}
+
 /// checks if a key-indexed data entry exists in the datastore
 #[named]
 pub(crate) fn assembly_script_has_data(mut ctx: FunctionEnvMut<ASEnv>, key: i32) -> ABIResult<i32> {
-    let env = get_env(&ctx)?;
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
-    let memory = get_memory!(env);
-    let key = read_buffer(memory, &ctx, key)?;
+    let (env, mut store) = get_env_and_store(&mut ctx)?;
+    let _call = sub_remaining_gas_abi(env, &mut store, function_name!())?;
+    let key = env.read_buffer(&store, function_name!(), key)?;
     // Do not remove this. It could be used for gas_calibration in future.
     // if cfg!(feature = "gas_calibration") {
     //     let fname = format!("massa.{}:0", function_name!());
     //     param_size_update(&env, &mut ctx, &fname, key.len(), true);
     // }
-    let res = env.get_interface().has_data(&key)?;
+    env.get_storage_limits().check_key(&key)?;
+    let res = env.interface().has_data(&key)?;
     #[cfg(feature = "execution-trace")]
     ctx.data_mut().trace.push(AbiTrace {
         name: function_name!().to_string(),
@@ -659,6 +1307,60 @@ pub(crate) fn assembly_script_has_data(mut ctx: FunctionEnvMut<ASEnv>, key: i32)
     Ok(res as i32)
 }
 
+/// Number of bytes given to the storage cost ABIs, at most the size of a
+/// datastore value
+fn storage_bytes(env: &ASEnv, bytes: i64) -> ABIResult<u64> {
+    let Ok(bytes) = u64::try_from(bytes) else {
+        abi_bail!(format!("Negative number of storage bytes: {}", bytes));
+    };
+    env.get_storage_limits()
+        .check_value_len(usize::try_from(bytes).unwrap_or(usize::MAX))?;
+    Ok(bytes)
+}
+
+/// Coins the current address would be charged to store `bytes` more bytes,
+/// for the contracts to check they can afford a write before building it
+#[named]
+pub(crate) fn assembly_script_get_storage_cost(
+    mut ctx: FunctionEnvMut<ASEnv>,
+    bytes: i64,
+) -> ABIResult<i64> {
+    let env = get_env(&ctx)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let bytes = storage_bytes(&env, bytes)?;
+    let cost = env.interface().get_storage_cost(bytes)?;
+    #[cfg(feature = "execution-trace")]
+    ctx.data_mut().trace.push(AbiTrace {
+        name: function_name!().to_string(),
+        params: vec![into_trace_value!(bytes)],
+        return_value: cost.into(),
+        sub_calls: None,
+    });
+    Ok(cost as i64)
+}
+
+/// Reserve the coins needed to store `bytes` more bytes at the current
+/// address, returns false if its balance does not cover them
+#[named]
+pub(crate) fn assembly_script_reserve_storage(
+    mut ctx: FunctionEnvMut<ASEnv>,
+    bytes: i64,
+) -> ABIResult<i32> {
+    let env = get_env(&ctx)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    env.get_call_context().check_write(function_name!())?;
+    let bytes = storage_bytes(&env, bytes)?;
+    let reserved = env.interface().reserve_storage(bytes)?;
+    #[cfg(feature = "execution-trace")]
+    ctx.data_mut().trace.push(AbiTrace {
+        name: function_name!().to_string(),
+        params: vec![into_trace_value!(bytes)],
+        return_value: reserved.into(),
+        sub_calls: None,
+    });
+    Ok(reserved as i32)
+}
+
 /// deletes a key-indexed data entry in the datastore of the current address,
 /// fails if the entry is absent
 #[named]
@@ -667,15 +1369,25 @@ pub(crate) fn assembly_script_delete_data(
     key: i32,
 ) -> ABIResult<()> {
     let env = get_env(&ctx)?;
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
-    let memory = get_memory!(env);
-    let key = read_buffer(memory, &ctx, key)?;
+    env.get_call_context().check_write(function_name!())?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let key = env.read_buffer(&ctx, function_name!(), key)?;
     // Do not remove this. It could be used for gas_calibration in future.
     // if cfg!(feature = "gas_calibration") {
     //     let fname = format!("massa.{}:0", function_name!());
     //     param_size_update(&env, &mut ctx, &fname, key.len(), true);
     // }
-    env.get_interface().raw_delete_data(&key)?;
+    env.get_storage_limits().check_key(&key)?;
+    // freed entries are refunded, their size is read before the deletion
+    let refund_per_byte = env.get_gas_costs().storage_refund_per_byte;
+    let freed_bytes = if refund_per_byte > 0 {
+        sub_remaining_gas_abi_of(&env, &mut ctx, function_name!(), "assembly_script_get_data")?;
+        key.len() + env.interface().raw_get_data(&key)?.len()
+    } else {
+        0
+    };
+    env.interface().raw_delete_data(&key)?;
+    refund_storage_gas(&mut ctx, refund_per_byte.saturating_mul(freed_bytes as u64))?;
     #[cfg(feature = "execution-trace")]
     ctx.data_mut().trace.push(AbiTrace {
         name: function_name!().to_string(),
@@ -696,11 +1408,11 @@ pub(crate) fn assembly_script_set_data_for(
     value: i32,
 ) -> ABIResult<()> {
     let env = get_env(&ctx)?;
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
-    let memory = get_memory!(env);
-    let key = read_buffer(memory, &ctx, key)?;
-    let value = read_buffer(memory, &ctx, value)?;
-    let address = read_string(memory, &ctx, address)?;
+    env.get_call_context().check_write(function_name!())?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let key = env.read_buffer(&ctx, function_name!(), key)?;
+    let value = env.read_buffer(&ctx, function_name!(), value)?;
+    let address = env.read_string(&mut ctx, function_name!(), address)?;
     // Do not remove this. It could be used for gas_calibration in future.
     // if cfg!(feature = "gas_calibration") {
     //     let fname = format!("massa.{}:0", function_name!());
@@ -710,8 +1422,8 @@ pub(crate) fn assembly_script_set_data_for(
     //     let fname = format!("massa.{}:2", function_name!());
     //     param_size_update(&env, &mut ctx, &fname, value.len(), true);
     // }
-    env.get_interface()
-        .raw_set_data_for(&address, &key, &value)?;
+    env.get_storage_limits().check_entry(&key, &value)?;
+    env.interface().raw_set_data_for(&address, &key, &value)?;
     #[cfg(feature = "execution-trace")]
     ctx.data_mut().trace.push(AbiTrace {
         name: function_name!().to_string(),
@@ -736,11 +1448,11 @@ pub(crate) fn assembly_script_append_data_for(
     value: i32,
 ) -> ABIResult<()> {
     let env = get_env(&ctx)?;
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
-    let memory = get_memory!(env);
-    let key = read_buffer(memory, &ctx, key)?;
-    let value = read_buffer(memory, &ctx, value)?;
-    let address = read_string(memory, &ctx, address)?;
+    env.get_call_context().check_write(function_name!())?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let key = env.read_buffer(&ctx, function_name!(), key)?;
+    let value = env.read_buffer(&ctx, function_name!(), value)?;
+    let address = env.read_string(&mut ctx, function_name!(), address)?;
     // Do not remove this. It could be used for gas_calibration in future.
     // if cfg!(feature = "gas_calibration") {
     //     let fname = format!("massa.{}:0", function_name!());
@@ -750,7 +1462,8 @@ pub(crate) fn assembly_script_append_data_for(
     //     let fname = format!("massa.{}:2", function_name!());
     //     param_size_update(&env, &mut ctx, &fname, value.len(), true);
     // }
-    env.get_interface()
+    env.get_storage_limits().check_entry(&key, &value)?;
+    env.interface()
         .raw_append_data_for(&address, &key, &value)?;
     #[cfg(feature = "execution-trace")]
     ctx.data_mut().trace.push(AbiTrace {
@@ -775,10 +1488,9 @@ pub(crate) fn assembly_script_get_data_for(
     key: i32,
 ) -> ABIResult<i32> {
     let env = get_env(&ctx)?;
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
-    let memory = get_memory!(env);
-    let address = read_string(memory, &ctx, address)?;
-    let key = read_buffer(memory, &ctx, key)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let address = env.read_string(&mut ctx, function_name!(), address)?;
+    let key = env.read_buffer(&ctx, function_name!(), key)?;
     // Do not remove this. It could be used for gas_calibration in future.
     // if cfg!(feature = "gas_calibration") {
     //     let fname = format!("massa.{}:0", function_name!());
@@ -787,7 +1499,8 @@ pub(crate) fn assembly_script_get_data_for(
     //     param_size_update(&env, &mut ctx, &fname, key.len(), true);
     // }
 
-    let data = env.get_interface().raw_get_data_for(&address, &key)?;
+    env.get_storage_limits().check_key(&key)?;
+    let data = env.interface().raw_get_data_for(&address, &key)?;
     let ptr = pointer_from_bytearray(&env, &mut ctx, &data)?.offset() as i32;
     #[cfg(feature = "execution-trace")]
     ctx.data_mut().trace.push(AbiTrace {
@@ -808,10 +1521,10 @@ pub(crate) fn assembly_script_delete_data_for(
     key: i32,
 ) -> ABIResult<()> {
     let env = get_env(&ctx)?;
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
-    let memory = get_memory!(env);
-    let address = read_string(memory, &ctx, address)?;
-    let key = read_buffer(memory, &ctx, key)?;
+    env.get_call_context().check_write(function_name!())?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let address = env.read_string(&mut ctx, function_name!(), address)?;
+    let key = env.read_buffer(&ctx, function_name!(), key)?;
     // Do not remove this. It could be used for gas_calibration in future.
     // if cfg!(feature = "gas_calibration") {
     //     let fname = format!("massa.{}:0", function_name!());
@@ -819,7 +1532,22 @@ pub(crate) fn assembly_script_delete_data_for(
     //     let fname = format!("massa.{}:1", function_name!());
     //     param_size_update(&env, &mut ctx, &fname, key.len(), true);
     // }
-    env.get_interface().raw_delete_data_for(&address, &key)?;
+    env.get_storage_limits().check_key(&key)?;
+    // freed entries are refunded, their size is read before the deletion
+    let refund_per_byte = env.get_gas_costs().storage_refund_per_byte;
+    let freed_bytes = if refund_per_byte > 0 {
+        sub_remaining_gas_abi_of(
+            &env,
+            &mut ctx,
+            function_name!(),
+            "assembly_script_get_data_for",
+        )?;
+        key.len() + env.interface().raw_get_data_for(&address, &key)?.len()
+    } else {
+        0
+    };
+    env.interface().raw_delete_data_for(&address, &key)?;
+    refund_storage_gas(&mut ctx, refund_per_byte.saturating_mul(freed_bytes as u64))?;
     #[cfg(feature = "execution-trace")]
     ctx.data_mut().trace.push(AbiTrace {
         name: function_name!().to_string(),
@@ -837,10 +1565,9 @@ pub(crate) fn assembly_script_has_data_for(
     key: i32,
 ) -> ABIResult<i32> {
     let env = get_env(&ctx)?;
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
-    let memory = get_memory!(env);
-    let address = read_string(memory, &ctx, address)?;
-    let key = read_buffer(memory, &ctx, key)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let address = env.read_string(&mut ctx, function_name!(), address)?;
+    let key = env.read_buffer(&ctx, function_name!(), key)?;
     // Do not remove this. It could be used for gas_calibration in future.
     // if cfg!(feature = "gas_calibration") {
     //     let fname = format!("massa.{}:0", function_name!());
@@ -848,7 +1575,8 @@ pub(crate) fn assembly_script_has_data_for(
     //     let fname = format!("massa.{}:1", function_name!());
     //     param_size_update(&env, &mut ctx, &fname, key.len(), true);
     // }
-    let res = env.get_interface().has_data_for(&address, &key)?;
+    env.get_storage_limits().check_key(&key)?;
+    let res = env.interface().has_data_for(&address, &key)?;
     #[cfg(feature = "execution-trace")]
     ctx.data_mut().trace.push(AbiTrace {
         name: function_name!().to_string(),
@@ -864,8 +1592,8 @@ pub(crate) fn assembly_script_get_owned_addresses(
     mut ctx: FunctionEnvMut<ASEnv>,
 ) -> ABIResult<i32> {
     let env = get_env(&ctx)?;
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
-    let data = env.get_interface().get_owned_addresses()?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let data = env.interface().get_owned_addresses()?;
     // prevent data.clone() when enabling execution-trace
     #[allow(clippy::let_and_return)]
     let ptr = alloc_string_array(&mut ctx, &data);
@@ -879,11 +1607,23 @@ pub(crate) fn assembly_script_get_owned_addresses(
     ptr
 }
 
+/// get the addresses of the call stack, from the origin down to the current
+/// smart contract. The interface only provides the stack of the top-level
+/// execution, the calls made from there are tracked by the runtime.
 #[named]
 pub(crate) fn assembly_script_get_call_stack(mut ctx: FunctionEnvMut<ASEnv>) -> ABIResult<i32> {
     let env = get_env(&ctx)?;
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
-    let data = env.get_interface().get_call_stack()?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let data = env.get_call_context().call_stack(&*env.interface())?;
+    let size: usize = data.iter().map(String::len).sum();
+    if size > settings::max_call_stack_size() {
+        abi_bail!(format!(
+            "Call stack of {} bytes exceeds the maximum of {} bytes",
+            size,
+            settings::max_call_stack_size()
+        ));
+    }
+    sub_remaining_gas_abi_per_byte(&env, &mut ctx, function_name!(), size)?;
     // prevent data.clone() when enabling execution-trace
     #[allow(clippy::let_and_return)]
     let ptr = alloc_string_array(&mut ctx, &data);
@@ -903,15 +1643,22 @@ pub(crate) fn assembly_script_generate_event(
     event: i32,
 ) -> ABIResult<()> {
     let env = get_env(&ctx)?;
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
-    let memory = get_memory!(env);
-    let event = read_string(memory, &ctx, event)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let event = env.read_string(&mut ctx, function_name!(), event)?;
     // Do not remove this. It could be used for gas_calibration in future.
     // if cfg!(feature = "gas_calibration") {
     //     let fname = format!("massa.{}:0", function_name!());
     //     param_size_update(&env, &mut ctx, &fname, event.len(), true);
     // }
-    env.get_interface().generate_event(event.clone())?;
+    let index = env.record_event(event.len(), event.as_bytes())?;
+    env.interface()
+        .generate_event_indexed(index, event.clone())?;
+    env.get_call_context().record_read_only(|recorder| {
+        recorder.event_emitted(CachedEvent::Text {
+            index: Some(index),
+            event: event.clone(),
+        })
+    });
     #[cfg(feature = "execution-trace")]
     ctx.data_mut().trace.push(AbiTrace {
         name: function_name!().to_string(),
@@ -922,6 +1669,80 @@ pub(crate) fn assembly_script_generate_event(
     Ok(())
 }
 
+/// generates an event made of indexed topics, given as concatenated 32 bytes
+/// values, and of opaque data
+#[named]
+pub(crate) fn assembly_script_generate_event_binary(
+    mut ctx: FunctionEnvMut<ASEnv>,
+    topics: i32,
+    data: i32,
+) -> ABIResult<()> {
+    let env = get_env(&ctx)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let topics = env.read_buffer(&ctx, function_name!(), topics)?;
+    let data = env.read_buffer(&ctx, function_name!(), data)?;
+    sub_remaining_gas_abi_per_byte(&env, &mut ctx, function_name!(), data.len())?;
+    let topic_list = deser_event_topics(&topics, env.get_event_limits().max_topics)?;
+    env.record_event(topics.len() + data.len(), &data)?;
+    env.interface().generate_event_binary(&topic_list, &data)?;
+    env.get_call_context().record_read_only(|recorder| {
+        recorder.event_emitted(CachedEvent::Binary {
+            topics: topic_list.clone(),
+            data: data.clone(),
+        })
+    });
+    #[cfg(feature = "execution-trace")]
+    ctx.data_mut().trace.push(AbiTrace {
+        name: function_name!().to_string(),
+        params: vec![into_trace_value!(topics), into_trace_value!(data)],
+        return_value: AbiTraceType::None,
+        sub_calls: None,
+    });
+    Ok(())
+}
+
+/// Number of events emitted so far by the whole call tree, the ones of the
+/// nested calls included
+#[named]
+pub(crate) fn assembly_script_get_event_count(mut ctx: FunctionEnvMut<ASEnv>) -> ABIResult<i64> {
+    let env = get_env(&ctx)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let count = env.get_call_context().events.lock().count();
+    #[cfg(feature = "execution-trace")]
+    ctx.data_mut().trace.push(AbiTrace {
+        name: function_name!().to_string(),
+        params: vec![],
+        return_value: count.into(),
+        sub_calls: None,
+    });
+    Ok(count as i64)
+}
+
+/// Last event emitted by the whole call tree, the text of an event or the data
+/// of a binary one. Empty when no event was emitted.
+#[named]
+pub(crate) fn assembly_script_get_last_event(mut ctx: FunctionEnvMut<ASEnv>) -> ABIResult<i32> {
+    let env = get_env(&ctx)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let event = env
+        .get_call_context()
+        .events
+        .lock()
+        .last()
+        .map(<[u8]>::to_vec)
+        .unwrap_or_default();
+    sub_remaining_gas_abi_per_byte(&env, &mut ctx, function_name!(), event.len())?;
+    let ptr = pointer_from_bytearray(&env, &mut ctx, &event)?.offset() as i32;
+    #[cfg(feature = "execution-trace")]
+    ctx.data_mut().trace.push(AbiTrace {
+        name: function_name!().to_string(),
+        params: vec![],
+        return_value: event.into(),
+        sub_calls: None,
+    });
+    Ok(ptr)
+}
+
 /// verify a signature of data given a public key. Returns Ok(1) if correctly
 /// verified, otherwise Ok(0)
 #[named]
@@ -932,11 +1753,10 @@ pub(crate) fn assembly_script_signature_verify(
     public_key: i32,
 ) -> ABIResult<i32> {
     let env = get_env(&ctx)?;
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
-    let memory = get_memory!(env);
-    let data = read_string(memory, &ctx, data)?;
-    let signature = read_string(memory, &ctx, signature)?;
-    let public_key = read_string(memory, &ctx, public_key)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let data = env.read_string(&mut ctx, function_name!(), data)?;
+    let signature = env.read_string(&mut ctx, function_name!(), signature)?;
+    let public_key = env.read_string(&mut ctx, function_name!(), public_key)?;
     // Do not remove this. It could be used for gas_calibration in future.
     // if cfg!(feature = "gas_calibration") {
     //     let fname = format!("massa.{}:0", function_name!());
@@ -947,7 +1767,7 @@ pub(crate) fn assembly_script_signature_verify(
     //     param_size_update(&env, &mut ctx, &fname, public_key.len(), true);
     // }
     let res = env
-        .get_interface()
+        .interface()
         .signature_verify(data.as_bytes(), &signature, &public_key)?;
     #[cfg(feature = "execution-trace")]
     ctx.data_mut().trace.push(AbiTrace {
@@ -963,6 +1783,156 @@ pub(crate) fn assembly_script_signature_verify(
     Ok(res as i32)
 }
 
+/// Verify a BLS12-381 signature of `message` by a 48 bytes compressed public
+/// key, without going through the interface.
+/// Returns Ok(1) if correctly verified, Ok(0) otherwise, malformed keys and
+/// signatures are errors.
+#[named]
+pub(crate) fn assembly_script_bls_verify(
+    mut ctx: FunctionEnvMut<ASEnv>,
+    message: i32,
+    signature: i32,
+    public_key: i32,
+) -> ABIResult<i32> {
+    let env = get_env(&ctx)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let message = env.read_buffer(&ctx, function_name!(), message)?;
+    let signature = env.read_buffer(&ctx, function_name!(), signature)?;
+    let public_key = env.read_buffer(&ctx, function_name!(), public_key)?;
+    sub_remaining_gas_abi_per_byte(&env, &mut ctx, function_name!(), message.len())?;
+    let res = match bls::verify(&message, &signature, &public_key) {
+        Ok(res) => res,
+        Err(err) => abi_bail!(err),
+    };
+    #[cfg(feature = "execution-trace")]
+    ctx.data_mut().trace.push(AbiTrace {
+        name: function_name!().to_string(),
+        params: vec![
+            into_trace_value!(message),
+            into_trace_value!(signature),
+            into_trace_value!(public_key),
+        ],
+        return_value: res.into(),
+        sub_calls: None,
+    });
+    Ok(res as i32)
+}
+
+/// Verify a BLS12-381 aggregated signature of `messages`, serialized as by
+/// `ser_bytearray_vec`, each signed by the public key at the same index in the
+/// concatenated 48 bytes `public_keys`.
+/// Returns Ok(1) if correctly verified, Ok(0) otherwise.
+#[named]
+pub(crate) fn assembly_script_bls_aggregate_verify(
+    mut ctx: FunctionEnvMut<ASEnv>,
+    messages: i32,
+    signature: i32,
+    public_keys: i32,
+) -> ABIResult<i32> {
+    let env = get_env(&ctx)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let messages = env.read_buffer(&ctx, function_name!(), messages)?;
+    let signature = env.read_buffer(&ctx, function_name!(), signature)?;
+    let public_keys = env.read_buffer(&ctx, function_name!(), public_keys)?;
+    sub_remaining_gas_abi_per_entry(
+        &env,
+        &mut ctx,
+        function_name!(),
+        bls::public_key_count(&public_keys),
+    )?;
+    sub_remaining_gas_abi_per_byte(&env, &mut ctx, function_name!(), messages.len())?;
+    let message_list = deser_bytearray_vec(&messages)?;
+    let res = match bls::aggregate_verify(&message_list, &signature, &public_keys) {
+        Ok(res) => res,
+        Err(err) => abi_bail!(err),
+    };
+    #[cfg(feature = "execution-trace")]
+    ctx.data_mut().trace.push(AbiTrace {
+        name: function_name!().to_string(),
+        params: vec![
+            into_trace_value!(messages),
+            into_trace_value!(signature),
+            into_trace_value!(public_keys),
+        ],
+        return_value: res.into(),
+        sub_calls: None,
+    });
+    Ok(res as i32)
+}
+
+/// Aggregate the concatenated 48 bytes BLS12-381 `public_keys` into a single
+/// compressed public key
+#[named]
+pub(crate) fn assembly_script_bls_aggregate_public_keys(
+    mut ctx: FunctionEnvMut<ASEnv>,
+    public_keys: i32,
+) -> ABIResult<i32> {
+    let env = get_env(&ctx)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let public_keys = env.read_buffer(&ctx, function_name!(), public_keys)?;
+    sub_remaining_gas_abi_per_entry(
+        &env,
+        &mut ctx,
+        function_name!(),
+        bls::public_key_count(&public_keys),
+    )?;
+    let aggregate = match bls::aggregate_public_keys(&public_keys) {
+        Ok(aggregate) => aggregate,
+        Err(err) => abi_bail!(err),
+    };
+    let ptr = pointer_from_bytearray(&env, &mut ctx, &aggregate)?.offset();
+    #[cfg(feature = "execution-trace")]
+    ctx.data_mut().trace.push(AbiTrace {
+        name: function_name!().to_string(),
+        params: vec![into_trace_value!(public_keys)],
+        return_value: aggregate.into(),
+        sub_calls: None,
+    });
+    Ok(ptr as i32)
+}
+
+/// Length of a secp256k1 signature with its recovery id
+const EVM_SIGNATURE_LEN: usize = 65;
+/// Length of an uncompressed secp256k1 public key without its 0x04 prefix
+const EVM_PUBLIC_KEY_LEN: usize = 64;
+/// Length of a compressed secp256k1 public key, its 0x02 or 0x03 prefix
+/// included
+const EVM_COMPRESSED_PUBLIC_KEY_LEN: usize = 33;
+
+/// Check the length of the inputs of an EVM signature verification before
+/// handing them to the crypto library, for the AssemblyScript and the wasmv1
+/// ABIs alike. The public key is uncompressed, with or without its prefix, or
+/// compressed.
+pub(crate) fn check_evm_signature_inputs(
+    signature: &[u8],
+    public_key: &[u8],
+) -> Result<(), String> {
+    if signature.len() != EVM_SIGNATURE_LEN {
+        return Err(format!(
+            "invalid EVM signature length: expected {} bytes, got {}",
+            EVM_SIGNATURE_LEN,
+            signature.len()
+        ));
+    }
+    let valid = match public_key.len() {
+        EVM_PUBLIC_KEY_LEN => true,
+        len if len == EVM_PUBLIC_KEY_LEN + 1 => public_key[0] == 0x04,
+        EVM_COMPRESSED_PUBLIC_KEY_LEN => matches!(public_key[0], 0x02 | 0x03),
+        _ => false,
+    };
+    if !valid {
+        return Err(format!(
+            "invalid EVM public key: expected {} bytes, {} prefixed by 0x04 or {} prefixed \
+             by 0x02 or 0x03, got {}",
+            EVM_PUBLIC_KEY_LEN,
+            EVM_PUBLIC_KEY_LEN + 1,
+            EVM_COMPRESSED_PUBLIC_KEY_LEN,
+            public_key.len()
+        ));
+    }
+    Ok(())
+}
+
 /// Verify an EVM signature.
 /// Returns Ok(1) if correctly verified, Ok(0) otherwise.
 #[named]
@@ -973,13 +1943,15 @@ pub(crate) fn assembly_script_evm_signature_verify(
     public_key: i32,
 ) -> ABIResult<i32> {
     let env = get_env(&ctx)?;
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
-    let memory = get_memory!(env);
-    let data = read_buffer(memory, &ctx, data)?;
-    let signature = read_buffer(memory, &ctx, signature)?;
-    let public_key = read_buffer(memory, &ctx, public_key)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let data = env.read_buffer(&ctx, function_name!(), data)?;
+    let signature = env.read_buffer(&ctx, function_name!(), signature)?;
+    let public_key = env.read_buffer(&ctx, function_name!(), public_key)?;
+    if let Err(err) = check_evm_signature_inputs(&signature, &public_key) {
+        abi_bail!(err);
+    }
     let res = env
-        .get_interface()
+        .interface()
         .evm_signature_verify(&data, &signature, &public_key)?;
     #[cfg(feature = "execution-trace")]
     ctx.data_mut().trace.push(AbiTrace {
@@ -1002,12 +1974,9 @@ pub(crate) fn assembly_script_evm_get_address_from_pubkey(
     public_key: i32,
 ) -> ABIResult<i32> {
     let env = get_env(&ctx)?;
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
-    let memory = get_memory!(env);
-    let public_key = read_buffer(memory, &ctx, public_key)?;
-    let address = env
-        .get_interface()
-        .evm_get_address_from_pubkey(&public_key)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let public_key = env.read_buffer(&ctx, function_name!(), public_key)?;
+    let address = env.interface().evm_get_address_from_pubkey(&public_key)?;
     let ptr = pointer_from_bytearray(&env, &mut ctx, &address)?.offset();
     #[cfg(feature = "execution-trace")]
     ctx.data_mut().trace.push(AbiTrace {
@@ -1027,12 +1996,11 @@ pub(crate) fn assembly_script_evm_get_pubkey_from_signature(
     signature: i32,
 ) -> ABIResult<i32> {
     let env = get_env(&ctx)?;
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
-    let memory = get_memory!(env);
-    let data = read_buffer(memory, &ctx, data)?;
-    let signature = read_buffer(memory, &ctx, signature)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let data = env.read_buffer(&ctx, function_name!(), data)?;
+    let signature = env.read_buffer(&ctx, function_name!(), signature)?;
     let public_key = env
-        .get_interface()
+        .interface()
         .evm_get_pubkey_from_signature(&data, &signature)?;
     let ptr = pointer_from_bytearray(&env, &mut ctx, &public_key)?.offset();
     #[cfg(feature = "execution-trace")]
@@ -1052,10 +2020,9 @@ pub(crate) fn assembly_script_is_address_eoa(
     address: i32,
 ) -> ABIResult<i32> {
     let env = get_env(&ctx)?;
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
-    let memory = get_memory!(env);
-    let address = read_string(memory, &ctx, address)?;
-    let res = env.get_interface().is_address_eoa(&address)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let address = env.read_string(&mut ctx, function_name!(), address)?;
+    let res = ctx.data_mut().is_address_eoa(&address)?;
     #[cfg(feature = "execution-trace")]
     ctx.data_mut().trace.push(AbiTrace {
         name: function_name!().to_string(),
@@ -1073,15 +2040,35 @@ pub(crate) fn assembly_script_address_from_public_key(
     public_key: i32,
 ) -> ABIResult<i32> {
     let env = get_env(&ctx)?;
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
-    let memory = get_memory!(env);
-    let public_key = read_string(memory, &ctx, public_key)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let public_key = env.read_string(&mut ctx, function_name!(), public_key)?;
     // Do not remove this. It could be used for gas_calibration in future.
     // if cfg!(feature = "gas_calibration") {
     //     let fname = format!("massa.{}:0", function_name!());
     //     param_size_update(&env, &mut ctx, &fname, public_key.len(), true);
     // }
-    let addr = env.get_interface().address_from_public_key(&public_key)?;
+    let addr = ctx.data_mut().address_from_public_key(&public_key)?;
+    let ptr = pointer_from_string(&env, &mut ctx, &addr)?.offset() as i32;
+    #[cfg(feature = "execution-trace")]
+    ctx.data_mut().trace.push(AbiTrace {
+        name: function_name!().to_string(),
+        params: vec![into_trace_value!(public_key)],
+        return_value: addr.into(),
+        sub_calls: None,
+    });
+    Ok(ptr)
+}
+
+/// converts a public key given as its serialized bytes to an address
+#[named]
+pub(crate) fn assembly_script_address_from_public_key_bytes(
+    mut ctx: FunctionEnvMut<ASEnv>,
+    public_key: i32,
+) -> ABIResult<i32> {
+    let env = get_env(&ctx)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let public_key = env.read_buffer(&ctx, function_name!(), public_key)?;
+    let addr = ctx.data_mut().address_from_public_key_bytes(&public_key)?;
     let ptr = pointer_from_string(&env, &mut ctx, &addr)?.offset() as i32;
     #[cfg(feature = "execution-trace")]
     ctx.data_mut().trace.push(AbiTrace {
@@ -1093,6 +2080,27 @@ pub(crate) fn assembly_script_address_from_public_key(
     Ok(ptr)
 }
 
+/// converts a public key given as a string to its serialized bytes
+#[named]
+pub(crate) fn assembly_script_public_key_from_string(
+    mut ctx: FunctionEnvMut<ASEnv>,
+    public_key: i32,
+) -> ABIResult<i32> {
+    let env = get_env(&ctx)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let public_key = env.read_string(&mut ctx, function_name!(), public_key)?;
+    let bytes = ctx.data_mut().public_key_from_string(&public_key)?;
+    let ptr = pointer_from_bytearray(&env, &mut ctx, &bytes)?.offset() as i32;
+    #[cfg(feature = "execution-trace")]
+    ctx.data_mut().trace.push(AbiTrace {
+        name: function_name!().to_string(),
+        params: vec![into_trace_value!(public_key)],
+        return_value: bytes.into(),
+        sub_calls: None,
+    });
+    Ok(ptr)
+}
+
 /// Validates an address is correct
 #[named]
 pub(crate) fn assembly_script_validate_address(
@@ -1100,10 +2108,9 @@ pub(crate) fn assembly_script_validate_address(
     address: i32,
 ) -> ABIResult<i32> {
     let env = ctx.data().clone();
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
-    let memory = get_memory!(env);
-    let address = read_string(memory, &ctx, address)?;
-    let res = env.get_interface().validate_address(&address)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let address = env.read_string(&mut ctx, function_name!(), address)?;
+    let res = ctx.data_mut().validate_address(&address)?;
     #[cfg(feature = "execution-trace")]
     ctx.data_mut().trace.push(AbiTrace {
         name: function_name!().to_string(),
@@ -1118,8 +2125,8 @@ pub(crate) fn assembly_script_validate_address(
 #[named]
 pub(crate) fn assembly_script_unsafe_random(mut ctx: FunctionEnvMut<ASEnv>) -> ABIResult<i64> {
     let env = get_env(&ctx)?;
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
-    let res = env.get_interface().unsafe_random()?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let res = env.interface().unsafe_random()?;
     #[cfg(feature = "execution-trace")]
     ctx.data_mut().trace.push(AbiTrace {
         name: function_name!().to_string(),
@@ -1130,26 +2137,82 @@ pub(crate) fn assembly_script_unsafe_random(mut ctx: FunctionEnvMut<ASEnv>) -> A
     Ok(res)
 }
 
-/// gets the current unix timestamp in milliseconds
+/// Seed of the deterministic randomness of the call tree, the same 32 bytes
+/// for all its frames, see `crate::random`
 #[named]
-pub(crate) fn assembly_script_get_time(mut ctx: FunctionEnvMut<ASEnv>) -> ABIResult<i64> {
+pub(crate) fn assembly_script_get_random_seed(mut ctx: FunctionEnvMut<ASEnv>) -> ABIResult<i32> {
     let env = get_env(&ctx)?;
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
-    let res = env.get_interface().get_time()?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let seed = env
+        .get_call_context()
+        .random
+        .lock()
+        .seed(&*env.interface())?
+        .to_vec();
+    let ptr = pointer_from_bytearray(&env, &mut ctx, &seed)?.offset();
     #[cfg(feature = "execution-trace")]
     ctx.data_mut().trace.push(AbiTrace {
         name: function_name!().to_string(),
         params: vec![],
-        return_value: res.into(),
+        return_value: seed.into(),
         sub_calls: None,
     });
-    Ok(res as i64)
+    Ok(ptr as i32)
 }
 
-/// sends an async message
-#[allow(clippy::too_many_arguments)]
+/// Next `len` bytes expanded from the random seed of the call tree, two calls
+/// never return the same expansion, see `crate::random`
 #[named]
-pub(crate) fn assembly_script_send_message(
+pub(crate) fn assembly_script_random_bytes(
+    mut ctx: FunctionEnvMut<ASEnv>,
+    len: i32,
+) -> ABIResult<i32> {
+    let env = get_env(&ctx)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let Ok(len) = usize::try_from(len) else {
+        abi_bail!(format!("Negative number of random bytes: {}", len));
+    };
+    let max = env.get_config().max_random_bytes_len;
+    if len > max {
+        return Err(ABIError::RandomBytesTooLong { len, max });
+    }
+    sub_remaining_gas_abi_per_byte(&env, &mut ctx, function_name!(), len)?;
+    let bytes = env
+        .get_call_context()
+        .random
+        .lock()
+        .next_bytes(&*env.interface(), len)?;
+    let ptr = pointer_from_bytearray(&env, &mut ctx, &bytes)?.offset();
+    #[cfg(feature = "execution-trace")]
+    ctx.data_mut().trace.push(AbiTrace {
+        name: function_name!().to_string(),
+        params: vec![into_trace_value!(len as u64)],
+        return_value: bytes.into(),
+        sub_calls: None,
+    });
+    Ok(ptr as i32)
+}
+
+/// gets the current unix timestamp in milliseconds
+#[named]
+pub(crate) fn assembly_script_get_time(mut ctx: FunctionEnvMut<ASEnv>) -> ABIResult<i64> {
+    let (env, mut store) = get_env_and_store(&mut ctx)?;
+    let _call = sub_remaining_gas_abi(env, &mut store, function_name!())?;
+    let res = env.interface().get_time()?;
+    #[cfg(feature = "execution-trace")]
+    ctx.data_mut().trace.push(AbiTrace {
+        name: function_name!().to_string(),
+        params: vec![],
+        return_value: res.into(),
+        sub_calls: None,
+    });
+    Ok(res as i64)
+}
+
+/// sends an async message
+#[allow(clippy::too_many_arguments)]
+#[named]
+pub(crate) fn assembly_script_send_message(
     mut ctx: FunctionEnvMut<ASEnv>,
     target_address: i32,
     target_handler: i32,
@@ -1165,7 +2228,8 @@ pub(crate) fn assembly_script_send_message(
     filter_datastore_key: i32,
 ) -> ABIResult<()> {
     let env = get_env(&ctx)?;
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    env.get_call_context().check_write(function_name!())?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
     let validity_start: (u64, u8) = match (
         validity_start_period.try_into(),
         validity_start_thread.try_into(),
@@ -1182,19 +2246,21 @@ pub(crate) fn assembly_script_send_message(
         (Err(_), _) => abi_bail!("negative validity end period"),
         (_, Err(_)) => abi_bail!("invalid validity end thread"),
     };
-    if max_gas.is_negative() {
-        abi_bail!("negative max gas");
-    }
-    if raw_fee.is_negative() {
-        abi_bail!("negative raw_fee");
-    }
-    if raw_coins.is_negative() {
-        abi_bail!("negative coins")
-    }
-    let memory = get_memory!(env);
-    let target_address = read_string(memory, &ctx, target_address)?;
-    let target_handler = read_string(memory, &ctx, target_handler)?;
-    let data = read_buffer(memory, &ctx, data)?;
+    let max_gas: u64 = match max_gas.try_into() {
+        Ok(v) => v,
+        Err(_) => abi_bail!("negative max gas"),
+    };
+    let raw_fee: u64 = match raw_fee.try_into() {
+        Ok(v) => v,
+        Err(_) => abi_bail!("negative raw_fee"),
+    };
+    let raw_coins: u64 = match raw_coins.try_into() {
+        Ok(v) => v,
+        Err(_) => abi_bail!("negative coins"),
+    };
+    let target_address = env.read_string(&mut ctx, function_name!(), target_address)?;
+    let target_handler = env.read_string(&mut ctx, function_name!(), target_handler)?;
+    let data = env.read_buffer(&ctx, function_name!(), data)?;
     // Do not remove this. It could be used for gas_calibration in future.
     // if cfg!(feature = "gas_calibration") {
     //     let fname = format!("massa.{}:0", function_name!());
@@ -1204,22 +2270,29 @@ pub(crate) fn assembly_script_send_message(
     // true);     let fname = format!("massa.{}:2", function_name!());
     //     param_size_update(&env, &mut ctx, &fname, data.len(), true);
     // }
-    let filter_address_string = read_string(memory, &ctx, filter_address)?;
-    let key = read_buffer(memory, &ctx, filter_datastore_key)?;
+    let filter_address_string = env.read_string(&mut ctx, function_name!(), filter_address)?;
+    let key = env.read_buffer(&ctx, function_name!(), filter_datastore_key)?;
     let filter = match (filter_address_string.as_str(), key.as_slice()) {
         ("", _) => None,
         (addr, &[]) => Some((addr, None)),
         (addr, key) => Some((addr, Some(key))),
     };
+    check_async_message(
+        env.get_config(),
+        validity_start,
+        validity_end,
+        max_gas,
+        filter,
+    )?;
 
-    env.get_interface().send_message(
+    env.interface().send_message(
         &target_address,
         &target_handler,
         validity_start,
         validity_end,
-        max_gas as u64,
-        raw_fee as u64,
-        raw_coins as u64,
+        max_gas,
+        raw_fee,
+        raw_coins,
         &data,
         filter,
     )?;
@@ -1234,9 +2307,9 @@ pub(crate) fn assembly_script_send_message(
             into_trace_value!(validity_start_thread),
             into_trace_value!(validity_end_period),
             into_trace_value!(validity_end_thread),
-            into_trace_value!(max_gas as u64),
-            into_trace_value!(raw_fee as u64),
-            into_trace_value!(raw_coins as u64),
+            into_trace_value!(max_gas),
+            into_trace_value!(raw_fee),
+            into_trace_value!(raw_coins),
             into_trace_value!(data),
             into_trace_value!(filter_address_string),
             into_trace_value!(key),
@@ -1248,17 +2321,277 @@ pub(crate) fn assembly_script_send_message(
     Ok(())
 }
 
-/// converts a public key to an address
+/// sends an async message described by a serialized `SendAsyncMessageRequest`,
+/// the request of the wasmv1 ABI
+#[named]
+pub(crate) fn assembly_script_send_async_message(
+    mut ctx: FunctionEnvMut<ASEnv>,
+    request: i32,
+) -> ABIResult<()> {
+    let env = get_env(&ctx)?;
+    env.get_call_context().check_write(function_name!())?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let request = env.read_buffer(&ctx, function_name!(), request)?;
+    let req = match SendAsyncMessageRequest::decode(request.as_slice()) {
+        Ok(req) => req,
+        Err(e) => abi_bail!(format!("invalid async message request: {}", e)),
+    };
+    let slot = |slot: Option<Slot>, name: &str| -> ABIResult<(u64, u8)> {
+        match slot {
+            Some(Slot { period, thread }) => match thread.try_into() {
+                Ok(thread) => Ok((period, thread)),
+                Err(_) => abi_bail!(format!("invalid {} thread", name)),
+            },
+            None => abi_bail!(format!("missing {} slot", name)),
+        }
+    };
+    let validity_start = slot(req.validity_start, "validity start")?;
+    let validity_end = slot(req.validity_end, "validity end")?;
+    let filter = req
+        .filter
+        .as_ref()
+        .map(|f| (f.target_address.as_str(), f.target_key.as_deref()));
+    check_async_message(
+        env.get_config(),
+        validity_start,
+        validity_end,
+        req.execution_gas,
+        filter,
+    )?;
+
+    env.interface().send_message(
+        &req.target_address,
+        &req.target_handler,
+        validity_start,
+        validity_end,
+        req.execution_gas,
+        req.raw_fee,
+        req.raw_coins,
+        &req.data,
+        filter,
+    )?;
+
+    #[cfg(feature = "execution-trace")]
+    ctx.data_mut().trace.push(AbiTrace {
+        name: function_name!().to_string(),
+        params: vec![into_trace_value!(request)],
+        return_value: AbiTraceType::None,
+        sub_calls: None,
+    });
+
+    Ok(())
+}
+
+/// Check the slots, the gas and the filter of an asynchronous message before
+/// they are handed to the interface, for the AssemblyScript and the wasmv1
+/// ABIs alike
+pub(crate) fn check_async_message(
+    config: &RuntimeConfig,
+    validity_start: (u64, u8),
+    validity_end: (u64, u8),
+    max_gas: u64,
+    filter: Option<(&str, Option<&[u8]>)>,
+) -> ABIResult<()> {
+    if validity_start.1 >= config.thread_count {
+        abi_bail!("invalid validity start thread");
+    }
+    if validity_end.1 >= config.thread_count {
+        abi_bail!("invalid validity end thread");
+    }
+    if validity_start > validity_end {
+        abi_bail!(format!(
+            "validity start (period {}, thread {}) is after validity end (period {}, thread {})",
+            validity_start.0, validity_start.1, validity_end.0, validity_end.1
+        ));
+    }
+    if max_gas > config.max_async_message_gas {
+        abi_bail!(format!(
+            "async message max gas {} exceeds the maximum of {}",
+            max_gas, config.max_async_message_gas
+        ));
+    }
+    if let Some((_, Some(key))) = filter {
+        if key.len() > config.storage_limits.max_key_len {
+            abi_bail!(format!(
+                "filter datastore key of {} bytes exceeds the maximum of {} bytes",
+                key.len(),
+                config.storage_limits.max_key_len
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Check the slot and the gas of a deferred call before they are handed to
+/// the interface
+fn check_deferred_call(
+    env: &ASEnv,
+    target_period: i64,
+    target_thread: i32,
+    max_gas: i64,
+) -> ABIResult<((u64, u8), u64)> {
+    let target_slot: (u64, u8) = match (target_period.try_into(), target_thread.try_into()) {
+        (Ok(p), Ok(t)) if t < env.get_config().thread_count => (p, t),
+        (Err(_), _) => abi_bail!("negative deferred call target period"),
+        _ => abi_bail!("invalid deferred call target thread"),
+    };
+    let max_gas: u64 = match max_gas.try_into() {
+        Ok(0) | Err(_) => abi_bail!("deferred call max gas must be positive"),
+        Ok(v) => v,
+    };
+    let max_allowed = env.get_config().max_deferred_call_gas;
+    if max_gas > max_allowed {
+        abi_bail!(format!(
+            "deferred call max gas {} exceeds the maximum of {}",
+            max_gas, max_allowed
+        ));
+    }
+    Ok((target_slot, max_gas))
+}
+
+/// Schedule a call of `target_function` at `target_address` during the target
+/// slot, returns the id of the deferred call
+#[named]
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn assembly_script_deferred_call_register(
+    mut ctx: FunctionEnvMut<ASEnv>,
+    target_address: i32,
+    target_function: i32,
+    target_period: i64,
+    target_thread: i32,
+    max_gas: i64,
+    params: i32,
+    raw_coins: i64,
+) -> ABIResult<i32> {
+    let env = get_env(&ctx)?;
+    env.get_call_context().check_write(function_name!())?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let (target_slot, max_gas) = check_deferred_call(&env, target_period, target_thread, max_gas)?;
+    let raw_coins: u64 = match raw_coins.try_into() {
+        Ok(v) => v,
+        Err(_) => abi_bail!("negative amount of coins in deferred call"),
+    };
+    let target_address = env.read_string(&mut ctx, function_name!(), target_address)?;
+    let target_function = env.read_string(&mut ctx, function_name!(), target_function)?;
+    let params = env.read_buffer(&ctx, function_name!(), params)?;
+    let id = env.interface().deferred_call_register(
+        &target_address,
+        &target_function,
+        target_slot,
+        max_gas,
+        &params,
+        raw_coins,
+    )?;
+    let ptr = pointer_from_string(&env, &mut ctx, &id)?.offset();
+    #[cfg(feature = "execution-trace")]
+    ctx.data_mut().trace.push(AbiTrace {
+        name: function_name!().to_string(),
+        params: vec![
+            into_trace_value!(target_address),
+            into_trace_value!(target_function),
+            into_trace_value!(target_period),
+            into_trace_value!(target_thread),
+            into_trace_value!(max_gas),
+            into_trace_value!(params),
+            into_trace_value!(raw_coins),
+        ],
+        return_value: id.into(),
+        sub_calls: None,
+    });
+    Ok(ptr as i32)
+}
+
+/// Check whether a deferred call is still scheduled
+#[named]
+pub(crate) fn assembly_script_deferred_call_exists(
+    mut ctx: FunctionEnvMut<ASEnv>,
+    id: i32,
+) -> ABIResult<i32> {
+    let env = get_env(&ctx)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let id = env.read_string(&mut ctx, function_name!(), id)?;
+    let res = env.interface().deferred_call_exists(&id)?;
+    #[cfg(feature = "execution-trace")]
+    ctx.data_mut().trace.push(AbiTrace {
+        name: function_name!().to_string(),
+        params: vec![into_trace_value!(id)],
+        return_value: res.into(),
+        sub_calls: None,
+    });
+    Ok(res as i32)
+}
+
+/// Cancel a deferred call, the error of the interface (unknown call, not the
+/// owner...) is returned as is
+#[named]
+pub(crate) fn assembly_script_deferred_call_cancel(
+    mut ctx: FunctionEnvMut<ASEnv>,
+    id: i32,
+) -> ABIResult<()> {
+    let env = get_env(&ctx)?;
+    env.get_call_context().check_write(function_name!())?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let id = env.read_string(&mut ctx, function_name!(), id)?;
+    env.interface().deferred_call_cancel(&id)?;
+    #[cfg(feature = "execution-trace")]
+    ctx.data_mut().trace.push(AbiTrace {
+        name: function_name!().to_string(),
+        params: vec![into_trace_value!(id)],
+        return_value: AbiTraceType::None,
+        sub_calls: None,
+    });
+    Ok(())
+}
+
+/// Fee to pay to register a deferred call of `max_gas` during the target slot
+#[named]
+pub(crate) fn assembly_script_deferred_call_quote(
+    mut ctx: FunctionEnvMut<ASEnv>,
+    target_period: i64,
+    target_thread: i32,
+    max_gas: i64,
+) -> ABIResult<i64> {
+    let env = get_env(&ctx)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let (target_slot, max_gas) = check_deferred_call(&env, target_period, target_thread, max_gas)?;
+    let fee = env.interface().deferred_call_quote(target_slot, max_gas)?;
+    let fee: i64 = match fee.try_into() {
+        Ok(v) => v,
+        Err(_) => abi_bail!("deferred call fee overflows an i64"),
+    };
+    #[cfg(feature = "execution-trace")]
+    ctx.data_mut().trace.push(AbiTrace {
+        name: function_name!().to_string(),
+        params: vec![
+            into_trace_value!(target_period),
+            into_trace_value!(target_thread),
+            into_trace_value!(max_gas),
+        ],
+        return_value: fee.into(),
+        sub_calls: None,
+    });
+    Ok(fee)
+}
+
+/// Gets the id of the operation that triggered the execution, empty when it
+/// was not triggered by an operation
 #[named]
 pub(crate) fn assembly_script_get_origin_operation_id(
     mut ctx: FunctionEnvMut<ASEnv>,
 ) -> ABIResult<i32> {
     let env = get_env(&ctx)?;
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
     let operation_id = env
-        .get_interface()
+        .interface()
         .get_origin_operation_id()?
         .unwrap_or_default();
+    if operation_id.len() > settings::max_operation_id_length() {
+        abi_bail!(format!(
+            "Operation id of {} bytes exceeds the maximum of {} bytes",
+            operation_id.len(),
+            settings::max_operation_id_length()
+        ));
+    }
     let ptr = pointer_from_string(&env, &mut ctx, &operation_id)?.offset() as i32;
     #[cfg(feature = "execution-trace")]
     ctx.data_mut().trace.push(AbiTrace {
@@ -1271,11 +2604,14 @@ pub(crate) fn assembly_script_get_origin_operation_id(
 }
 
 /// gets the period of the current execution slot
+///
+/// Deprecated in favor of `assembly_script_get_current_slot`, which reads the
+/// period and the thread at once.
 #[named]
 pub(crate) fn assembly_script_get_current_period(mut ctx: FunctionEnvMut<ASEnv>) -> ABIResult<i64> {
     let env = get_env(&ctx)?;
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
-    let current_period = env.get_interface().get_current_period()?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let (current_period, _) = current_slot(&env)?;
     #[cfg(feature = "execution-trace")]
     ctx.data_mut().trace.push(AbiTrace {
         name: function_name!().to_string(),
@@ -1287,11 +2623,13 @@ pub(crate) fn assembly_script_get_current_period(mut ctx: FunctionEnvMut<ASEnv>)
 }
 
 /// gets the thread of the current execution slot
+///
+/// Deprecated like `assembly_script_get_current_period`.
 #[named]
 pub(crate) fn assembly_script_get_current_thread(mut ctx: FunctionEnvMut<ASEnv>) -> ABIResult<i32> {
     let env = get_env(&ctx)?;
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
-    let current_thread = env.get_interface().get_current_thread()?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let (_, current_thread) = current_slot(&env)?;
     #[cfg(feature = "execution-trace")]
     ctx.data_mut().trace.push(AbiTrace {
         name: function_name!().to_string(),
@@ -1302,7 +2640,108 @@ pub(crate) fn assembly_script_get_current_thread(mut ctx: FunctionEnvMut<ASEnv>)
     Ok(current_thread as i32)
 }
 
-/// sets the executable bytecode of an arbitrary address
+/// Gets the current execution slot, as its period (8 bytes little-endian)
+/// followed by its thread (1 byte)
+#[named]
+pub(crate) fn assembly_script_get_current_slot(mut ctx: FunctionEnvMut<ASEnv>) -> ABIResult<i32> {
+    let env = get_env(&ctx)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let slot = current_slot(&env)?;
+    let ptr = pointer_from_bytearray(&env, &mut ctx, &ser_slot(slot))?.offset();
+    #[cfg(feature = "execution-trace")]
+    ctx.data_mut().trace.push(AbiTrace {
+        name: function_name!().to_string(),
+        params: vec![],
+        return_value: slot.into(),
+        sub_calls: None,
+    });
+    Ok(ptr as i32)
+}
+
+/// Slot (period, thread) of the call tree, the same for all the reads of its
+/// frames
+fn current_slot(env: &ASEnv) -> ABIResult<(u64, u8)> {
+    let slot = env.get_call_context().current_slot(env.interface())?;
+    match u8::try_from(slot.thread) {
+        Ok(thread) if thread < env.get_config().thread_count => Ok((slot.period, thread)),
+        _ => abi_bail!(format!(
+            "Invalid thread {} of the current slot",
+            slot.thread
+        )),
+    }
+}
+
+/// Timestamp (milliseconds) of the start of the slot (`period`, `thread`)
+#[named]
+pub(crate) fn assembly_script_slot_to_timestamp(
+    mut ctx: FunctionEnvMut<ASEnv>,
+    period: i64,
+    thread: i32,
+) -> ABIResult<i64> {
+    let env = get_env(&ctx)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let thread_count = env.get_config().thread_count;
+    let slot: (u64, u8) = match (period.try_into(), thread.try_into()) {
+        (Ok(p), Ok(t)) if t < thread_count => (p, t),
+        (Err(_), _) => abi_bail!("Negative slot period"),
+        _ => abi_bail!(format!(
+            "Invalid slot thread {}: the thread count is {}",
+            thread, thread_count
+        )),
+    };
+    let timestamp = env.interface().slot_to_timestamp(slot)?;
+    let Ok(timestamp) = i64::try_from(timestamp) else {
+        abi_bail!(format!(
+            "Timestamp {} of the slot does not fit in an i64",
+            timestamp
+        ));
+    };
+    #[cfg(feature = "execution-trace")]
+    ctx.data_mut().trace.push(AbiTrace {
+        name: function_name!().to_string(),
+        params: vec![into_trace_value!(period), into_trace_value!(thread)],
+        return_value: timestamp.into(),
+        sub_calls: None,
+    });
+    Ok(timestamp)
+}
+
+/// Slot in progress at `timestamp` (milliseconds), serialized like the
+/// current slot
+#[named]
+pub(crate) fn assembly_script_timestamp_to_slot(
+    mut ctx: FunctionEnvMut<ASEnv>,
+    timestamp: i64,
+) -> ABIResult<i32> {
+    let env = get_env(&ctx)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let Ok(unsigned_timestamp) = u64::try_from(timestamp) else {
+        abi_bail!("Negative timestamp");
+    };
+    let slot = env.interface().timestamp_to_slot(unsigned_timestamp)?;
+    if slot.1 >= env.get_config().thread_count {
+        abi_bail!(format!("Invalid thread {} of the slot", slot.1));
+    }
+    let ptr = pointer_from_bytearray(&env, &mut ctx, &ser_slot(slot))?.offset();
+    #[cfg(feature = "execution-trace")]
+    ctx.data_mut().trace.push(AbiTrace {
+        name: function_name!().to_string(),
+        params: vec![into_trace_value!(timestamp)],
+        return_value: slot.into(),
+        sub_calls: None,
+    });
+    Ok(ptr as i32)
+}
+
+/// Period (8 bytes little-endian) followed by the thread (1 byte)
+fn ser_slot((period, thread): (u64, u8)) -> Vec<u8> {
+    let mut serialized = period.to_le_bytes().to_vec();
+    serialized.push(thread);
+    serialized
+}
+
+/// sets the executable bytecode of an arbitrary address, the bytecode has to
+/// pass the validation of a deployment
 #[named]
 pub(crate) fn assembly_script_set_bytecode_for(
     mut ctx: FunctionEnvMut<ASEnv>,
@@ -1310,10 +2749,10 @@ pub(crate) fn assembly_script_set_bytecode_for(
     bytecode: i32,
 ) -> ABIResult<()> {
     let env = get_env(&ctx)?;
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
-    let memory = get_memory!(env);
-    let address = read_string(memory, &ctx, address)?;
-    let bytecode_raw = read_buffer(memory, &ctx, bytecode)?;
+    env.get_call_context().check_write(function_name!())?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let address = env.read_string(&mut ctx, function_name!(), address)?;
+    let bytecode_raw = env.read_buffer(&ctx, function_name!(), bytecode)?;
     // Do not remove this. It could be used for gas_calibration in future.
     // if cfg!(feature = "gas_calibration") {
     //     let fname = format!("massa.{}:0", function_name!());
@@ -1321,8 +2760,8 @@ pub(crate) fn assembly_script_set_bytecode_for(
     //     let fname = format!("massa.{}:1", function_name!());
     //     param_size_update(&env, &mut ctx, &fname, bytecode_raw.len(), true);
     // }
-    env.get_interface()
-        .raw_set_bytecode_for(&address, &bytecode_raw)?;
+    sub_remaining_gas_abi_per_byte(&env, &mut ctx, function_name!(), bytecode_raw.len())?;
+    set_bytecode(&env, Some(&address), &bytecode_raw)?;
     #[cfg(feature = "execution-trace")]
     ctx.data_mut().trace.push(AbiTrace {
         name: function_name!().to_string(),
@@ -1333,22 +2772,24 @@ pub(crate) fn assembly_script_set_bytecode_for(
     Ok(())
 }
 
-/// sets the executable bytecode of the current address
+/// sets the executable bytecode of the current address, the bytecode has to
+/// pass the validation of a deployment
 #[named]
 pub(crate) fn assembly_script_set_bytecode(
     mut ctx: FunctionEnvMut<ASEnv>,
     bytecode: i32,
 ) -> ABIResult<()> {
     let env = get_env(&ctx)?;
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
-    let memory = get_memory!(env);
-    let bytecode_raw = read_buffer(memory, &ctx, bytecode)?;
+    env.get_call_context().check_write(function_name!())?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let bytecode_raw = env.read_buffer(&ctx, function_name!(), bytecode)?;
     // Do not remove this. It could be used for gas_calibration in future.
     // if cfg!(feature = "gas_calibration") {
     //     let fname = format!("massa.{}:0", function_name!());
     //     param_size_update(&env, &mut ctx, &fname, bytecode_raw.len(), true);
     // }
-    env.get_interface().raw_set_bytecode(&bytecode_raw)?;
+    sub_remaining_gas_abi_per_byte(&env, &mut ctx, function_name!(), bytecode_raw.len())?;
+    set_bytecode(&env, None, &bytecode_raw)?;
     #[cfg(feature = "execution-trace")]
     ctx.data_mut().trace.push(AbiTrace {
         name: function_name!().to_string(),
@@ -1363,8 +2804,9 @@ pub(crate) fn assembly_script_set_bytecode(
 #[named]
 pub(crate) fn assembly_script_get_bytecode(mut ctx: FunctionEnvMut<ASEnv>) -> ABIResult<i32> {
     let env = get_env(&ctx)?;
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
-    let data = env.get_interface().raw_get_bytecode()?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let data = env.interface().raw_get_bytecode()?;
+    sub_remaining_gas_abi_per_byte(&env, &mut ctx, function_name!(), data.len())?;
     let ptr = pointer_from_bytearray(&env, &mut ctx, &data)?.offset() as i32;
     #[cfg(feature = "execution-trace")]
     ctx.data_mut().trace.push(AbiTrace {
@@ -1376,17 +2818,18 @@ pub(crate) fn assembly_script_get_bytecode(mut ctx: FunctionEnvMut<ASEnv>) -> AB
     Ok(ptr)
 }
 
-/// get bytecode of the target address
+/// get bytecode of the target address, fails if there is no smart contract
+/// at this address
 #[named]
 pub(crate) fn assembly_script_get_bytecode_for(
     mut ctx: FunctionEnvMut<ASEnv>,
     address: i32,
 ) -> ABIResult<i32> {
     let env = get_env(&ctx)?;
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
-    let memory = get_memory!(env);
-    let address = read_string(memory, &ctx, address)?;
-    let data = env.get_interface().raw_get_bytecode_for(&address)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let address = env.read_string(&mut ctx, function_name!(), address)?;
+    let data = env.interface().raw_get_bytecode_for(&address)?;
+    sub_remaining_gas_abi_per_byte(&env, &mut ctx, function_name!(), data.len())?;
     let ptr = pointer_from_bytearray(&env, &mut ctx, &data)?.offset() as i32;
     #[cfg(feature = "execution-trace")]
     ctx.data_mut().trace.push(AbiTrace {
@@ -1407,13 +2850,20 @@ pub(crate) fn assembly_script_local_execution(
     param: i32,
 ) -> ABIResult<i32> {
     let env = get_env(&ctx)?;
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
-    let memory = get_memory!(env);
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
 
-    let bytecode = read_buffer(memory, &ctx, bytecode)?;
-    let function = read_string(memory, &ctx, function)?;
-    let param = read_buffer(memory, &ctx, param)?;
-    let response = local_call(&mut ctx, &bytecode, &function, &param, true)?;
+    let bytecode = env.read_buffer(&ctx, function_name!(), bytecode)?;
+    let function = env.read_string(&mut ctx, function_name!(), function)?;
+    let param = env.read_buffer(&ctx, function_name!(), param)?;
+    let response = local_call(
+        &mut ctx,
+        function_name!(),
+        &bytecode,
+        &function,
+        &param,
+        true,
+        0,
+    )?;
     let res = match BufferPtr::alloc(&response.ret, env.get_ffi_env(), &mut ctx) {
         Ok(ret) => Ok(ret.offset() as i32),
         _ => abi_bail!(format!(
@@ -1435,6 +2885,53 @@ pub(crate) fn assembly_script_local_execution(
     res
 }
 
+/// execute `function` of the given bytecode in the current context, making
+/// `coins` available to the execution
+#[named]
+pub(crate) fn assembly_script_local_execution_with_coins(
+    mut ctx: FunctionEnvMut<ASEnv>,
+    bytecode: i32,
+    function: i32,
+    param: i32,
+    coins: i64,
+) -> ABIResult<i32> {
+    let env = get_env(&ctx)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+
+    let bytecode = env.read_buffer(&ctx, function_name!(), bytecode)?;
+    let function = env.read_string(&mut ctx, function_name!(), function)?;
+    let param = env.read_buffer(&ctx, function_name!(), param)?;
+    let response = local_call(
+        &mut ctx,
+        function_name!(),
+        &bytecode,
+        &function,
+        &param,
+        true,
+        coins,
+    )?;
+    let res = match BufferPtr::alloc(&response.ret, env.get_ffi_env(), &mut ctx) {
+        Ok(ret) => Ok(ret.offset() as i32),
+        _ => abi_bail!(format!(
+            "Cannot allocate response in local call of {}",
+            function
+        )),
+    };
+    #[cfg(feature = "execution-trace")]
+    ctx.data_mut().trace.push(AbiTrace {
+        name: function_name!().to_string(),
+        params: vec![
+            into_trace_value!(bytecode),
+            into_trace_value!(function),
+            into_trace_value!(param),
+            into_trace_value!(coins),
+        ],
+        return_value: response.ret.clone().into(),
+        sub_calls: Some(response.trace),
+    });
+    res
+}
+
 /// execute `function` of the bytecode located at `address` in the current
 /// context
 #[named]
@@ -1445,15 +2942,22 @@ pub(crate) fn assembly_script_local_call(
     param: i32,
 ) -> ABIResult<i32> {
     let env = get_env(&ctx)?;
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
-    let memory = get_memory!(env);
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
 
-    let address = &read_string(memory, &ctx, address)?;
-    let bytecode = env.get_interface().raw_get_bytecode_for(address)?;
-    let function = read_string(memory, &ctx, function)?;
-    let param = read_buffer(memory, &ctx, param)?;
+    let address = &env.read_string(&mut ctx, function_name!(), address)?;
+    let bytecode = env.interface().raw_get_bytecode_for(address)?;
+    let function = env.read_string(&mut ctx, function_name!(), function)?;
+    let param = env.read_buffer(&ctx, function_name!(), param)?;
 
-    let response = local_call(&mut ctx, &bytecode, &function, &param, false)?;
+    let response = local_call(
+        &mut ctx,
+        function_name!(),
+        &bytecode,
+        &function,
+        &param,
+        false,
+        0,
+    )?;
     let res = match BufferPtr::alloc(&response.ret, env.get_ffi_env(), &mut ctx) {
         Ok(ret) => Ok(ret.offset() as i32),
         _ => abi_bail!(format!(
@@ -1476,12 +2980,45 @@ pub(crate) fn assembly_script_local_call(
     res
 }
 
+/// execute `function` of the module currently executed in the current
+/// context, in a fresh instance and without compiling the module again
+#[named]
+pub(crate) fn assembly_script_local_call_self(
+    mut ctx: FunctionEnvMut<ASEnv>,
+    function: i32,
+    param: i32,
+) -> ABIResult<i32> {
+    let env = get_env(&ctx)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+
+    let function = env.read_string(&mut ctx, function_name!(), function)?;
+    let param = env.read_buffer(&ctx, function_name!(), param)?;
+
+    let response = local_call_self(&mut ctx, &function, &param)?;
+    let res = match BufferPtr::alloc(&response.ret, env.get_ffi_env(), &mut ctx) {
+        Ok(ret) => Ok(ret.offset() as i32),
+        _ => abi_bail!(format!(
+            "Cannot allocate response in local call of {}",
+            function
+        )),
+    };
+    #[cfg(feature = "execution-trace")]
+    ctx.data_mut().trace.push(AbiTrace {
+        name: function_name!().to_string(),
+        params: vec![into_trace_value!(function), into_trace_value!(param)],
+        return_value: response.ret.clone().into(),
+        sub_calls: Some(response.trace),
+    });
+
+    res
+}
+
 /// Check whether or not the caller has write access in the current context
 #[named]
 pub fn assembly_script_caller_has_write_access(mut ctx: FunctionEnvMut<ASEnv>) -> ABIResult<i32> {
     let env = get_env(&ctx)?;
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
-    let has_write_access = env.get_interface().caller_has_write_access()?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let has_write_access = ctx.data_mut().caller_has_write_access()?;
     #[cfg(feature = "execution-trace")]
     ctx.data_mut().trace.push(AbiTrace {
         name: function_name!().to_string(),
@@ -1500,10 +3037,9 @@ pub fn assembly_script_function_exists(
     function: i32,
 ) -> ABIResult<i32> {
     let env = get_env(&ctx)?;
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
-    let memory = get_memory!(env);
-    let address = read_string(memory, &ctx, address)?;
-    let function = read_string(memory, &ctx, function)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let address = env.read_string(&mut ctx, function_name!(), address)?;
+    let function = env.read_string(&mut ctx, function_name!(), function)?;
     let function_exists = function_exists(&mut ctx, &address, &function)?;
     #[cfg(feature = "execution-trace")]
     ctx.data_mut().trace.push(AbiTrace {
@@ -1519,8 +3055,8 @@ pub fn assembly_script_function_exists(
 #[named]
 pub(crate) fn assembly_script_chain_id(mut ctx: FunctionEnvMut<ASEnv>) -> ABIResult<u64> {
     let env = get_env(&ctx)?;
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
-    let chain_id = env.get_interface().chain_id()?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let chain_id = env.interface().chain_id()?;
     #[cfg(feature = "execution-trace")]
     ctx.data_mut().trace.push(AbiTrace {
         name: function_name!().to_string(),
@@ -1531,11 +3067,28 @@ pub(crate) fn assembly_script_chain_id(mut ctx: FunctionEnvMut<ASEnv>) -> ABIRes
     Ok(chain_id as u64)
 }
 
+/// Highest ABI version implemented by the runtime, for contracts checking
+/// whether an optional ABI is available before importing its module
+#[named]
+pub(crate) fn assembly_script_runtime_version(mut ctx: FunctionEnvMut<ASEnv>) -> ABIResult<i32> {
+    let env = get_env(&ctx)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    #[cfg(feature = "execution-trace")]
+    ctx.data_mut().trace.push(AbiTrace {
+        name: function_name!().to_string(),
+        params: vec![],
+        return_value: settings::ABI_VERSION.into(),
+        sub_calls: None,
+    });
+    Ok(settings::ABI_VERSION as i32)
+}
+
 /// Assembly script builtin `abort` function.
 ///
-/// It prints the origin filename, an error messag, the line and column.
-#[allow(unused_macros)]
-#[allow(unused_mut)]
+/// It fails the execution with the error message, the origin filename, the
+/// line and column, also kept in the environment for the resulting
+/// `VMError`. The strings are decoded lossily like those of the other ABIs,
+/// a contract aborting on a corrupted message still reports where.
 #[named]
 pub fn assembly_script_abort(
     mut ctx: FunctionEnvMut<ASEnv>,
@@ -1544,52 +3097,48 @@ pub fn assembly_script_abort(
     line: i32,
     col: i32,
 ) -> ABIResult<()> {
-    let memory = ctx
-        .data()
-        .get_ffi_env()
-        .memory
-        .as_ref()
-        .expect("Failed to get memory on env")
-        .clone();
-    let message_ = message
-        .read(&memory, &ctx)
-        .map_err(|e| wasmer::RuntimeError::new(e.to_string()));
-    let filename_ = filename
-        .read(&memory, &ctx)
-        .map_err(|e| wasmer::RuntimeError::new(e.to_string()));
-
-    if message_.is_err() || filename_.is_err() {
+    let env = ctx.data().clone();
+    let message_ = env
+        .read_string(&mut ctx, function_name!(), message.offset() as i32)
+        .ok();
+    let filename_ = env
+        .read_string(&mut ctx, function_name!(), filename.offset() as i32)
+        .ok();
+
+    let (Some(message_), Some(filename_)) = (message_, filename_) else {
         abi_bail!("aborting failed to load message or filename")
-    }
+    };
     #[cfg(feature = "execution-trace")]
     ctx.data_mut().trace.push(AbiTrace {
         name: function_name!().to_string(),
         params: vec![
-            into_trace_value!(message_.clone().unwrap_or_default()),
-            into_trace_value!(filename_.clone().unwrap_or_default()),
+            into_trace_value!(message_.clone()),
+            into_trace_value!(filename_.clone()),
             into_trace_value!(line),
             into_trace_value!(col),
         ],
         return_value: AbiTraceType::None,
         sub_calls: None,
     });
-    abi_bail!(format!(
-        "error: {} at {}:{} col: {}",
-        message_.unwrap(),
-        filename_.unwrap(),
-        line,
-        col
-    ));
+    let info = AbortInfo {
+        message: message_,
+        file: filename_,
+        line: line as u32,
+        col: col as u32,
+    };
+    let message = info.to_string();
+    ctx.data_mut().set_abort_info(info);
+    Err(ABIError::Aborted(message))
 }
 
 /// Assembly script builtin `seed` function
 #[named]
 pub fn assembly_script_seed(mut ctx: FunctionEnvMut<ASEnv>) -> ABIResult<f64> {
     let env = get_env(&ctx)?;
-    if cfg!(not(feature = "gas_calibration")) {
-        sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    if !env.gas_calibration() {
+        let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
     }
-    let seed = match env.interface.unsafe_random_f64() {
+    let seed = match env.interface().unsafe_random_f64() {
         Ok(ret) => ret,
         _ => abi_bail!("failed to get random from interface"),
     };
@@ -1607,10 +3156,10 @@ pub fn assembly_script_seed(mut ctx: FunctionEnvMut<ASEnv>) -> ABIResult<f64> {
 #[named]
 pub fn assembly_script_date_now(mut ctx: FunctionEnvMut<ASEnv>) -> ABIResult<f64> {
     let env = get_env(&ctx)?;
-    if cfg!(not(feature = "gas_calibration")) {
-        sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    if !env.gas_calibration() {
+        let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
     }
-    let utime = match env.interface.get_time() {
+    let utime = match env.interface().get_time() {
         Ok(time) => time,
         _ => abi_bail!("failed to get time from interface"),
     };
@@ -1632,8 +3181,8 @@ pub fn assembly_script_console_log(
     message: StringPtr,
 ) -> ABIResult<()> {
     let env = get_env(&ctx)?;
-    if cfg!(not(feature = "gas_calibration")) {
-        sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    if !env.gas_calibration() {
+        let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
     }
 
     assembly_script_console(ctx, message, "LOG")
@@ -1646,8 +3195,8 @@ pub fn assembly_script_console_info(
     message: StringPtr,
 ) -> ABIResult<()> {
     let env = get_env(&ctx)?;
-    if cfg!(not(feature = "gas_calibration")) {
-        sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    if !env.gas_calibration() {
+        let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
     }
     assembly_script_console(ctx, message, "INFO")
 }
@@ -1659,8 +3208,8 @@ pub fn assembly_script_console_warn(
     message: StringPtr,
 ) -> ABIResult<()> {
     let env = get_env(&ctx)?;
-    if cfg!(not(feature = "gas_calibration")) {
-        sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    if !env.gas_calibration() {
+        let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
     }
     assembly_script_console(ctx, message, "WARN")
 }
@@ -1672,8 +3221,8 @@ pub fn assembly_script_console_debug(
     message: StringPtr,
 ) -> ABIResult<()> {
     let env = get_env(&ctx)?;
-    if cfg!(not(feature = "gas_calibration")) {
-        sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    if !env.gas_calibration() {
+        let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
     }
 
     assembly_script_console(ctx, message, "DEBUG")
@@ -1686,8 +3235,8 @@ pub fn assembly_script_console_error(
     message: StringPtr,
 ) -> ABIResult<()> {
     let env = get_env(&ctx)?;
-    if cfg!(not(feature = "gas_calibration")) {
-        sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    if !env.gas_calibration() {
+        let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
     }
     assembly_script_console(ctx, message, "ERROR")
 }
@@ -1703,19 +3252,19 @@ fn assembly_script_console(
 ) -> ABIResult<()> {
     let env = get_env(&ctx)?;
 
-    let memory = ctx
-        .data()
-        .get_ffi_env()
-        .memory
-        .as_ref()
-        .expect("Failed to get memory on env")
-        .clone();
-    let message = prefix
-        .to_string()
-        .add(" | ")
-        .add(&message.read(&memory, &ctx)?);
+    let message = prefix.to_string().add(" | ").add(&env.read_string(
+        &mut ctx,
+        function_name!(),
+        message.offset() as i32,
+    )?);
 
-    env.get_interface().generate_event(message.clone())?;
+    env.interface().generate_event(message.clone())?;
+    env.get_call_context().record_read_only(|recorder| {
+        recorder.event_emitted(CachedEvent::Text {
+            index: None,
+            event: message.clone(),
+        })
+    });
     #[cfg(feature = "execution-trace")]
     ctx.data_mut().trace.push(AbiTrace {
         name: function_name!().to_string(),
@@ -1740,19 +3289,11 @@ pub fn assembly_script_trace(
     a4: f64,
 ) -> ABIResult<()> {
     let env = get_env(&ctx)?;
-    if cfg!(not(feature = "gas_calibration")) {
-        sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    if !env.gas_calibration() {
+        let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
     }
 
-    let memory = ctx
-        .data()
-        .get_ffi_env()
-        .memory
-        .as_ref()
-        .expect("Failed to get memory on env")
-        .clone();
-
-    let message = message.read(&memory, &ctx)?;
+    let message = env.read_string(&mut ctx, function_name!(), message.offset() as i32)?;
 
     let message_for_event = match n {
         1 => format!("msg: {}, a0: {}", message, a0),
@@ -1769,8 +3310,13 @@ pub fn assembly_script_trace(
         _ => message, // Should we warn here or return an error?
     };
 
-    env.get_interface()
-        .generate_event(message_for_event.clone())?;
+    env.interface().generate_event(message_for_event.clone())?;
+    env.get_call_context().record_read_only(|recorder| {
+        recorder.event_emitted(CachedEvent::Text {
+            index: None,
+            event: message_for_event.clone(),
+        })
+    });
     #[cfg(feature = "execution-trace")]
     ctx.data_mut().trace.push(AbiTrace {
         name: function_name!().to_string(),
@@ -1783,7 +3329,7 @@ pub fn assembly_script_trace(
 
 /// Assembly script builtin `process.exit()`.
 pub fn assembly_script_process_exit(_ctx: FunctionEnvMut<ASEnv>, exit_code: i32) -> ABIResult<()> {
-    abi_bail!(format!("exit with code: {}", exit_code));
+    Err(ABIError::Aborted(format!("exit with code: {}", exit_code)))
 }
 
 /// Tooling, return a StringPtr allocated from a String
@@ -1792,6 +3338,8 @@ fn pointer_from_string(
     ctx: &mut impl AsStoreMut,
     value: &str,
 ) -> ABIResult<StringPtr> {
+    // stored as UTF-16 in the guest memory
+    env.record_host_data(value.encode_utf16().count() * 2)?;
     Ok(*StringPtr::alloc(&value.into(), env.get_ffi_env(), ctx)?)
 }
 
@@ -1801,24 +3349,43 @@ fn pointer_from_bytearray(
     ctx: &mut impl AsStoreMut,
     value: &Vec<u8>,
 ) -> ABIResult<BufferPtr> {
+    env.record_host_data(value.len())?;
     Ok(*BufferPtr::alloc(value, env.get_ffi_env(), ctx)?)
 }
 
-/// Tooling that reads a buffer (Vec<u8>) in memory
-fn read_buffer(memory: &Memory, store: &impl AsStoreRef, offset: i32) -> ABIResult<Vec<u8>> {
-    Ok(BufferPtr::new(offset as u32).read(memory, store)?)
-}
-
-/// Tooling, return a string from a given offset
-fn read_string(memory: &Memory, store: &impl AsStoreRef, ptr: i32) -> ABIResult<String> {
-    Ok(StringPtr::new(ptr as u32).read(memory, store)?)
-}
-
 /// Tooling, return a pointer offset of a serialized list in json
 fn alloc_string_array(ctx: &mut FunctionEnvMut<ASEnv>, vec: &[String]) -> ABIResult<i32> {
     let env = get_env(ctx)?;
-    let addresses = serde_json::to_string(vec)?;
-    Ok(StringPtr::alloc(&addresses, env.get_ffi_env(), ctx)?.offset() as i32)
+    let addresses = json_string_array(vec);
+    Ok(pointer_from_string(&env, ctx, &addresses)?.offset() as i32)
+}
+
+/// JSON array of `strings`, escaped like `serde_json` does so that the guests
+/// get the same bytes whether the `json` feature is enabled or not
+fn json_string_array(strings: &[String]) -> String {
+    let mut json = String::from("[");
+    for (i, string) in strings.iter().enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+        json.push('"');
+        for c in string.chars() {
+            match c {
+                '"' => json.push_str("\\\""),
+                '\\' => json.push_str("\\\\"),
+                '\n' => json.push_str("\\n"),
+                '\r' => json.push_str("\\r"),
+                '\t' => json.push_str("\\t"),
+                '\u{8}' => json.push_str("\\b"),
+                '\u{c}' => json.push_str("\\f"),
+                c if c < ' ' => json.push_str(&format!("\\u{:04x}", c as u32)),
+                c => json.push(c),
+            }
+        }
+        json.push('"');
+    }
+    json.push(']');
+    json
 }
 
 /// Flatten a Vec<Vec<u8>> (or anything that can be turned into an iterator) to
@@ -1839,7 +3406,9 @@ where
     // pre alloc with max capacity
     let mut buffer = Vec::with_capacity(4 + (data_len * (1 + 255)));
 
-    let entry_count = u32::try_from(data_len).unwrap();
+    let Ok(entry_count) = u32::try_from(data_len) else {
+        abi_bail!("Too many entries in the datastore");
+    };
     buffer.extend_from_slice(&entry_count.to_le_bytes());
 
     for key in data.into_iter() {
@@ -1854,6 +3423,184 @@ where
     Ok(buffer)
 }
 
+/// Version prefixed to the cursors of the interface handed to the guest
+const CURSOR_VERSION: u8 = 1;
+
+/// Cursor handed to the guest for the cursor `cursor` of the interface
+fn seal_cursor(cursor: &[u8]) -> Vec<u8> {
+    [&[CURSOR_VERSION][..], cursor].concat()
+}
+
+/// Cursor of the interface in a cursor given by the guest, `None` for an
+/// empty one
+fn open_cursor(cursor: &[u8]) -> ABIResult<Option<&[u8]>> {
+    match cursor.split_first() {
+        None => Ok(None),
+        Some((&CURSOR_VERSION, cursor)) => Ok(Some(cursor)),
+        Some((version, _)) => Err(ABIError::InvalidCursor(format!(
+            "unknown version {}",
+            version
+        ))),
+    }
+}
+
+/// Read a Vec<Vec<u8>> serialized by `ser_bytearray_vec`
+fn deser_bytearray_vec(buffer: &[u8]) -> ABIResult<Vec<Vec<u8>>> {
+    fn take<'a>(buffer: &mut &'a [u8], len: usize) -> ABIResult<&'a [u8]> {
+        if buffer.len() < len {
+            abi_bail!("Malformed byte array list");
+        }
+        let (head, tail) = buffer.split_at(len);
+        *buffer = tail;
+        Ok(head)
+    }
+
+    if buffer.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut buffer = buffer;
+    let count = u32::from_le_bytes(take(&mut buffer, 4)?.try_into().unwrap());
+    let mut data = Vec::new();
+    for _ in 0..count {
+        let len = take(&mut buffer, 1)?[0] as usize;
+        data.push(take(&mut buffer, len)?.to_vec());
+    }
+    if !buffer.is_empty() {
+        abi_bail!("Malformed byte array list");
+    }
+
+    Ok(data)
+}
+
+/// Flatten the result of a try call to a Vec<u8> with the format: 1 RET on
+/// success, 0 MSG (UTF-8) on failure
+fn ser_try_call_result(result: &Result<Response, String>) -> Vec<u8> {
+    match result {
+        Ok(response) => [&[1u8][..], &response.ret].concat(),
+        Err(error) => [&[0u8][..], error.as_bytes()].concat(),
+    }
+}
+
+/// Flatten the result of a checked transfer to a Vec<u8> with the format: 1 on
+/// success, 0 CODE (8 bits) on failure
+fn ser_transfer_result(result: &Result<(), TransferError>) -> Vec<u8> {
+    match result {
+        Ok(()) => vec![1],
+        Err(error) => vec![0, *error as u8],
+    }
+}
+
+/// Flatten datastore entries to a Vec<u8> with the format: L (32 bits LE)
+/// K1_L (8 bits) K1 (8 bits * K1_L) V1_L (32 bits LE) V1 (8 bits * V1_L), K2_L
+/// ... VN (8 bits * VN_L)
+fn ser_key_value_vec(
+    entries: &BTreeMap<Vec<u8>, Vec<u8>>,
+    max_length: usize,
+) -> ABIResult<Vec<u8>> {
+    if entries.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    if entries.len() > max_length {
+        return Err(StorageLimitError::TooManyEntries {
+            count: entries.len(),
+            max: max_length,
+        }
+        .into());
+    }
+
+    let Ok(entry_count) = u32::try_from(entries.len()) else {
+        return Err(StorageLimitError::TooManyEntries {
+            count: entries.len(),
+            max: u32::MAX as usize,
+        }
+        .into());
+    };
+    let mut buffer = entry_count.to_le_bytes().to_vec();
+
+    for (key, value) in entries {
+        let k_len = match u8::try_from(key.len()) {
+            Ok(l) => l,
+            Err(_) => abi_bail!("Some Datastore keys are too long"),
+        };
+        let v_len = match u32::try_from(value.len()) {
+            Ok(l) => l,
+            Err(_) => abi_bail!("Some Datastore values are too long"),
+        };
+        buffer.push(k_len);
+        buffer.extend_from_slice(key);
+        buffer.extend_from_slice(&v_len.to_le_bytes());
+        buffer.extend_from_slice(value);
+    }
+
+    Ok(buffer)
+}
+
+/// Read datastore entries serialized by `ser_key_value_vec`, the whole buffer
+/// is rejected if it is malformed or if an entry violates the `limits`
+fn deser_key_value_vec(
+    buffer: &[u8],
+    limits: &StorageLimits,
+) -> ABIResult<Vec<(Vec<u8>, Vec<u8>)>> {
+    fn take<'a>(buffer: &mut &'a [u8], len: usize) -> ABIResult<&'a [u8]> {
+        if buffer.len() < len {
+            abi_bail!("Malformed datastore entries");
+        }
+        let (head, tail) = buffer.split_at(len);
+        *buffer = tail;
+        Ok(head)
+    }
+
+    if buffer.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut buffer = buffer;
+    let entry_count = u32::from_le_bytes(take(&mut buffer, 4)?.try_into().unwrap());
+    let mut entries = Vec::new();
+    for _ in 0..entry_count {
+        let k_len = take(&mut buffer, 1)?[0] as usize;
+        let key = take(&mut buffer, k_len)?.to_vec();
+        limits.check_key(&key)?;
+        let v_len = u32::from_le_bytes(take(&mut buffer, 4)?.try_into().unwrap()) as usize;
+        limits.check_value_len(v_len)?;
+        let value = take(&mut buffer, v_len)?.to_vec();
+        entries.push((key, value));
+    }
+    if !buffer.is_empty() {
+        abi_bail!("Malformed datastore entries");
+    }
+
+    Ok(entries)
+}
+
+/// Split the concatenated topics of a binary event, at most `max_topics` of
+/// them are accepted
+fn deser_event_topics(
+    buffer: &[u8],
+    max_topics: usize,
+) -> ABIResult<Vec<[u8; settings::EVENT_TOPIC_LEN]>> {
+    if buffer.len() % settings::EVENT_TOPIC_LEN != 0 {
+        abi_bail!(format!(
+            "Malformed event topics: {} bytes is not a multiple of {}",
+            buffer.len(),
+            settings::EVENT_TOPIC_LEN
+        ));
+    }
+    let count = buffer.len() / settings::EVENT_TOPIC_LEN;
+    if count > max_topics {
+        return Err(ABIError::TooManyEventTopics {
+            count,
+            max: max_topics,
+        });
+    }
+    Ok(buffer
+        .chunks_exact(settings::EVENT_TOPIC_LEN)
+        .map(|topic| topic.try_into().expect("chunks have the topic length"))
+        .collect())
+}
+
 /// performs a sha256 hash on byte array and returns the hash as byte array
 #[named]
 pub(crate) fn assembly_script_hash_sha256(
@@ -1861,17 +3608,285 @@ pub(crate) fn assembly_script_hash_sha256(
     bytes: i32,
 ) -> ABIResult<i32> {
     let env = get_env(&ctx)?;
-    sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
-    let memory = get_memory!(env);
-    let bytes = read_buffer(memory, &ctx, bytes)?;
-    let hash = env.get_interface().hash_sha256(&bytes)?.to_vec();
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let bytes = env.read_buffer(&ctx, function_name!(), bytes)?;
+    let hash = env.interface().hash_sha256(&bytes)?.to_vec();
     let ptr = pointer_from_bytearray(&env, &mut ctx, &hash)?.offset();
     Ok(ptr as i32)
 }
 
+/// Encode `data` in base58check, as the node does for the addresses
+#[named]
+pub(crate) fn assembly_script_base58_check_encode(
+    mut ctx: FunctionEnvMut<ASEnv>,
+    data: i32,
+) -> ABIResult<i32> {
+    let env = get_env(&ctx)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let data = env.read_buffer(&ctx, function_name!(), data)?;
+    sub_remaining_gas_abi_per_byte(&env, &mut ctx, function_name!(), data.len())?;
+    let encoded = base58_check_encode(&data, env.get_config().max_base58_check_input_len)?;
+    let ptr = pointer_from_string(&env, &mut ctx, &encoded)?.offset();
+    #[cfg(feature = "execution-trace")]
+    ctx.data_mut().trace.push(AbiTrace {
+        name: function_name!().to_string(),
+        params: vec![into_trace_value!(data)],
+        return_value: encoded.into(),
+        sub_calls: None,
+    });
+    Ok(ptr as i32)
+}
+
+/// Decode a base58check string, failing if its checksum does not match
+#[named]
+pub(crate) fn assembly_script_base58_check_decode(
+    mut ctx: FunctionEnvMut<ASEnv>,
+    encoded: i32,
+) -> ABIResult<i32> {
+    let env = get_env(&ctx)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let encoded = env.read_string(&mut ctx, function_name!(), encoded)?;
+    sub_remaining_gas_abi_per_byte(&env, &mut ctx, function_name!(), encoded.len())?;
+    let data = base58_check_decode(&encoded, env.get_config().max_base58_check_input_len)?;
+    let ptr = pointer_from_bytearray(&env, &mut ctx, &data)?.offset();
+    #[cfg(feature = "execution-trace")]
+    ctx.data_mut().trace.push(AbiTrace {
+        name: function_name!().to_string(),
+        params: vec![into_trace_value!(encoded)],
+        return_value: data.into(),
+        sub_calls: None,
+    });
+    Ok(ptr as i32)
+}
+
+/// Compare two `ArrayBuffer`s in a time depending only on their lengths, for
+/// the secrets such as HMACs. Inputs of different lengths are not equal, the
+/// result is not an error so that the comparison takes the same path.
+#[named]
+pub(crate) fn assembly_script_constant_eq(
+    mut ctx: FunctionEnvMut<ASEnv>,
+    a: i32,
+    b: i32,
+) -> ABIResult<i32> {
+    let env = get_env(&ctx)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let a = env.read_buffer(&ctx, function_name!(), a)?;
+    let b = env.read_buffer(&ctx, function_name!(), b)?;
+    let len = a.len().max(b.len());
+    sub_remaining_gas_abi_per_byte(&env, &mut ctx, function_name!(), len)?;
+    let max = env.get_config().max_constant_eq_input_len;
+    if len > max {
+        return Err(ABIError::ConstantEqInputTooLong { len, max });
+    }
+    let equal = constant_time_eq(&a, &b);
+    #[cfg(feature = "execution-trace")]
+    ctx.data_mut().trace.push(AbiTrace {
+        name: function_name!().to_string(),
+        params: vec![into_trace_value!(a), into_trace_value!(b)],
+        return_value: equal.into(),
+        sub_calls: None,
+    });
+    Ok(equal as i32)
+}
+
+/// Whether `a` and `b` are equal, going through every byte of the longer one
+/// whatever their differences
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    let len = a.len().max(b.len());
+    let mut diff = a.len() ^ b.len();
+    for i in 0..len {
+        let x = a.get(i).copied().unwrap_or(0);
+        let y = b.get(i).copied().unwrap_or(0);
+        diff |= (x ^ y) as usize;
+    }
+    // keep the compiler from stopping at the first difference
+    std::hint::black_box(diff) == 0
+}
+
+/// Sum of two amounts, see `crate::amount` for the result
+#[named]
+pub(crate) fn assembly_script_checked_add_native_amount(
+    ctx: FunctionEnvMut<ASEnv>,
+    a: i64,
+    b: i64,
+) -> ABIResult<i32> {
+    amount_operation(ctx, function_name!(), a, b, amount::checked_add)
+}
+
+/// Difference of two amounts, see `crate::amount` for the result
+#[named]
+pub(crate) fn assembly_script_checked_sub_native_amount(
+    ctx: FunctionEnvMut<ASEnv>,
+    a: i64,
+    b: i64,
+) -> ABIResult<i32> {
+    amount_operation(ctx, function_name!(), a, b, amount::checked_sub)
+}
+
+/// Product of an amount by a `u64`, see `crate::amount` for the result
+#[named]
+pub(crate) fn assembly_script_checked_mul_native_amount(
+    ctx: FunctionEnvMut<ASEnv>,
+    amount: i64,
+    factor: i64,
+) -> ABIResult<i32> {
+    amount_operation(ctx, function_name!(), amount, factor, amount::checked_mul)
+}
+
+/// Quotient of an amount by a `u64`, see `crate::amount` for the result
+#[named]
+pub(crate) fn assembly_script_checked_div_native_amount(
+    ctx: FunctionEnvMut<ASEnv>,
+    amount: i64,
+    divisor: i64,
+) -> ABIResult<i32> {
+    amount_operation(ctx, function_name!(), amount, divisor, amount::checked_div)
+}
+
+/// Apply `operation` to the `u64` operands, received as `i64`, of the amount
+/// ABI `abi`
+fn amount_operation(
+    mut ctx: FunctionEnvMut<ASEnv>,
+    abi: &str,
+    a: i64,
+    b: i64,
+    operation: fn(u64, u64) -> Result<u64, amount::AmountError>,
+) -> ABIResult<i32> {
+    let env = get_env(&ctx)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, abi)?;
+    let result = amount::serialize(operation(a as u64, b as u64));
+    let ptr = pointer_from_bytearray(&env, &mut ctx, &result)?.offset();
+    #[cfg(feature = "execution-trace")]
+    ctx.data_mut().trace.push(AbiTrace {
+        name: abi.to_string(),
+        params: vec![into_trace_value!(a), into_trace_value!(b)],
+        return_value: result.into(),
+        sub_calls: None,
+    });
+    Ok(ptr as i32)
+}
+
+/// Parse a decimal number of coins into nano-coins, see `crate::amount` for
+/// the result
+#[named]
+pub(crate) fn assembly_script_native_amount_from_string(
+    mut ctx: FunctionEnvMut<ASEnv>,
+    amount: i32,
+) -> ABIResult<i32> {
+    let env = get_env(&ctx)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let amount = env.read_string(&mut ctx, function_name!(), amount)?;
+    let result = amount::serialize(amount::from_string(&amount));
+    let ptr = pointer_from_bytearray(&env, &mut ctx, &result)?.offset();
+    #[cfg(feature = "execution-trace")]
+    ctx.data_mut().trace.push(AbiTrace {
+        name: function_name!().to_string(),
+        params: vec![into_trace_value!(amount)],
+        return_value: result.into(),
+        sub_calls: None,
+    });
+    Ok(ptr as i32)
+}
+
+/// Decimal number of coins of an amount of nano-coins. Every `u64` is an
+/// amount, the string is returned as is.
+#[named]
+pub(crate) fn assembly_script_native_amount_to_string(
+    mut ctx: FunctionEnvMut<ASEnv>,
+    amount: i64,
+) -> ABIResult<i32> {
+    let env = get_env(&ctx)?;
+    let _call = sub_remaining_gas_abi(&env, &mut ctx, function_name!())?;
+    let string = amount::to_string(amount as u64);
+    let ptr = pointer_from_string(&env, &mut ctx, &string)?.offset();
+    #[cfg(feature = "execution-trace")]
+    ctx.data_mut().trace.push(AbiTrace {
+        name: function_name!().to_string(),
+        params: vec![into_trace_value!(amount)],
+        return_value: string.into(),
+        sub_calls: None,
+    });
+    Ok(ptr as i32)
+}
+
+fn base58_check_encode(data: &[u8], max_len: usize) -> ABIResult<String> {
+    if data.len() > max_len {
+        return Err(ABIError::Base58CheckInputTooLong {
+            len: data.len(),
+            max: max_len,
+        });
+    }
+    Ok(bs58::encode(data).with_check().into_string())
+}
+
+fn base58_check_decode(encoded: &str, max_len: usize) -> ABIResult<Vec<u8>> {
+    if encoded.len() > max_len {
+        return Err(ABIError::Base58CheckInputTooLong {
+            len: encoded.len(),
+            max: max_len,
+        });
+    }
+    bs58::decode(encoded)
+        .with_check(None)
+        .into_vec()
+        .map_err(|err| match err {
+            bs58::decode::Error::InvalidChecksum { .. } => ABIError::InvalidBase58CheckChecksum,
+            err => ABIError::InvalidBase58Check(err.to_string()),
+        })
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::as_execution::abi::ser_bytearray_vec;
+    use crate::as_execution::abi::{
+        base58_check_decode, base58_check_encode, check_evm_signature_inputs, deser_bytearray_vec,
+        deser_event_topics, deser_key_value_vec, json_string_array, ser_bytearray_vec,
+        ser_key_value_vec, ser_try_call_result,
+    };
+    use crate::as_execution::ABIError;
+    use crate::{Response, StorageLimitError, StorageLimits};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_check_evm_signature_inputs() {
+        let signature = [0u8; 65];
+        let public_key = [1u8; 64];
+        let prefixed_public_key = [&[0x04][..], &public_key].concat();
+        let compressed_public_key = [&[0x03][..], &public_key[..32]].concat();
+
+        assert!(check_evm_signature_inputs(&signature, &public_key).is_ok());
+        assert!(check_evm_signature_inputs(&signature, &prefixed_public_key).is_ok());
+        assert!(check_evm_signature_inputs(&signature, &compressed_public_key).is_ok());
+        // truncated inputs
+        assert!(check_evm_signature_inputs(&signature[..64], &public_key).is_err());
+        assert!(check_evm_signature_inputs(&signature, &public_key[..32]).is_err());
+        assert!(check_evm_signature_inputs(&[], &[]).is_err());
+        // keys with the prefix of the other format
+        assert!(check_evm_signature_inputs(&signature, &[1u8; 65]).is_err());
+        assert!(check_evm_signature_inputs(&signature, &[0x04; 33]).is_err());
+    }
+
+    #[test]
+    fn test_json_string_array() {
+        let strings: Vec<String> = [
+            "AU12dhs6CsQk8AXFTYyUpc1P9e8GDf65ozU6RcigW68qfJV7vdbNf",
+            "",
+            "quote \" backslash \\ controls \n\r\t\u{8}\u{c}\u{1}\u{1f} del \u{7f}",
+            "🦀 é",
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect();
+        assert_eq!(json_string_array(&[]), "[]");
+        assert_eq!(
+            json_string_array(&strings[..2]),
+            r#"["AU12dhs6CsQk8AXFTYyUpc1P9e8GDf65ozU6RcigW68qfJV7vdbNf",""]"#
+        );
+        #[cfg(feature = "json")]
+        assert_eq!(
+            json_string_array(&strings),
+            serde_json::to_string(&strings).unwrap()
+        );
+    }
 
     #[test]
     fn test_ser() {
@@ -1915,4 +3930,175 @@ mod tests {
         assert_eq!(vb_ser[vb_ser.len() - 2], 1);
         assert_eq!(vb_ser[vb_ser.len() - 1], 254);
     }
+
+    #[test]
+    fn test_deser_bytearray_vec() {
+        let vb: Vec<Vec<u8>> = vec![vec![1, 2, 3], vec![], vec![255]];
+        let vb_ser = ser_bytearray_vec(&vb, vb.len(), 10).unwrap();
+        assert_eq!(deser_bytearray_vec(&vb_ser).unwrap(), vb);
+        assert!(deser_bytearray_vec(&[]).unwrap().is_empty());
+
+        // truncated or trailing bytes
+        assert!(deser_bytearray_vec(&vb_ser[..vb_ser.len() - 1]).is_err());
+        assert!(deser_bytearray_vec(&[vb_ser, vec![0]].concat()).is_err());
+        assert!(deser_bytearray_vec(&[1, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn test_ser_key_value() {
+        let mut entries = BTreeMap::new();
+        entries.insert(vec![1, 2], vec![3]);
+        entries.insert(vec![4], vec![]);
+
+        let ser = ser_key_value_vec(&entries, 10).unwrap();
+        assert_eq!(ser, [2, 0, 0, 0, 2, 1, 2, 1, 0, 0, 0, 3, 1, 4, 0, 0, 0, 0]);
+
+        // no matching entries
+        let ser = ser_key_value_vec(&BTreeMap::new(), 10).unwrap();
+        assert!(ser.is_empty());
+
+        // exactly at the cap
+        assert!(ser_key_value_vec(&entries, 2).is_ok());
+        // over the cap
+        assert!(matches!(
+            ser_key_value_vec(&entries, 1),
+            Err(ABIError::StorageLimit(StorageLimitError::TooManyEntries {
+                count: 2,
+                max: 1
+            }))
+        ));
+    }
+
+    #[test]
+    fn test_deser_key_value() {
+        let mut entries = BTreeMap::new();
+        entries.insert(vec![1, 2], vec![3]);
+        entries.insert(vec![4], vec![5, 6, 7]);
+        let ser = ser_key_value_vec(&entries, 10).unwrap();
+
+        let limits = |max_key_len, max_value_len| StorageLimits {
+            max_key_len,
+            max_value_len,
+            ..StorageLimits::default()
+        };
+
+        let batch = deser_key_value_vec(&ser, &limits(2, 3)).unwrap();
+        assert_eq!(batch, entries.into_iter().collect::<Vec<_>>());
+        assert!(deser_key_value_vec(&[], &limits(2, 3)).unwrap().is_empty());
+
+        // a single entry over the limits rejects the whole batch
+        assert!(matches!(
+            deser_key_value_vec(&ser, &limits(2, 2)),
+            Err(ABIError::StorageLimit(StorageLimitError::ValueTooLong {
+                len: 3,
+                max: 2
+            }))
+        ));
+        assert!(matches!(
+            deser_key_value_vec(&ser, &limits(1, 3)),
+            Err(ABIError::StorageLimit(StorageLimitError::KeyTooLong {
+                len: 2,
+                max: 1
+            }))
+        ));
+        // truncated or trailing bytes
+        assert!(deser_key_value_vec(&ser[..ser.len() - 1], &limits(2, 3)).is_err());
+        let mut trailing = ser.clone();
+        trailing.push(0);
+        assert!(deser_key_value_vec(&trailing, &limits(2, 3)).is_err());
+    }
+
+    #[test]
+    fn test_ser_try_call_result() {
+        let response = Response {
+            ret: vec![4, 2],
+            remaining_gas: 0,
+            init_gas_cost: 0,
+            #[cfg(feature = "execution-trace")]
+            trace: Default::default(),
+            gas_breakdown: None,
+            #[cfg(feature = "profiling")]
+            profile: Default::default(),
+        };
+        assert_eq!(ser_try_call_result(&Ok(response)), [1, 4, 2]);
+        assert_eq!(
+            ser_try_call_result(&Err("abort".to_string())),
+            [0, b'a', b'b', b'o', b'r', b't']
+        );
+    }
+
+    #[test]
+    fn test_deser_event_topics() {
+        let topics: Vec<u8> = (0..4).flat_map(|i| [i; 32]).collect();
+        assert_eq!(
+            deser_event_topics(&topics, 4).unwrap(),
+            vec![[0; 32], [1; 32], [2; 32], [3; 32]]
+        );
+        assert!(deser_event_topics(&[], 4).unwrap().is_empty());
+        // one over the maximum
+        let topics = [topics, vec![4; 32]].concat();
+        assert!(matches!(
+            deser_event_topics(&topics, 4),
+            Err(ABIError::TooManyEventTopics { count: 5, max: 4 })
+        ));
+        // topics must be exactly 32 bytes
+        assert!(matches!(
+            deser_event_topics(&[0; 33], 4),
+            Err(ABIError::Error(_))
+        ));
+    }
+
+    #[test]
+    fn test_base58_check() {
+        // version byte followed by the hash of the address, as encoded by the
+        // node after its `AU` / `AS` prefix
+        let mut genesis = vec![0];
+        genesis.extend_from_slice(&[
+            0x62, 0xe9, 0x07, 0xb1, 0x5c, 0xbf, 0x27, 0xd5, 0x42, 0x53, 0x99, 0xeb, 0xf6, 0xf0,
+            0xfb, 0x50, 0xeb, 0xb8, 0x8f, 0x18,
+        ]);
+        assert_eq!(
+            base58_check_encode(&genesis, 1024).unwrap(),
+            "1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa"
+        );
+        assert_eq!(
+            base58_check_decode("1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa", 1024).unwrap(),
+            genesis
+        );
+        assert_eq!(
+            base58_check_encode(&[0; 21], 1024).unwrap(),
+            "1111111111111111111114oLvT2"
+        );
+
+        // round trips of a 32 bytes hash with a version
+        for version in 0..3u8 {
+            let data: Vec<u8> = std::iter::once(version).chain(0..32).collect();
+            let encoded = base58_check_encode(&data, 1024).unwrap();
+            assert_eq!(base58_check_decode(&encoded, 1024).unwrap(), data);
+        }
+        assert_eq!(
+            base58_check_decode(&base58_check_encode(&[], 1024).unwrap(), 1024).unwrap(),
+            Vec::<u8>::new()
+        );
+
+        // checksum mismatch
+        assert!(matches!(
+            base58_check_decode("1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNb", 1024),
+            Err(ABIError::InvalidBase58CheckChecksum)
+        ));
+        // not base58
+        assert!(matches!(
+            base58_check_decode("0OIl", 1024),
+            Err(ABIError::InvalidBase58Check(_))
+        ));
+        // too long inputs
+        assert!(matches!(
+            base58_check_encode(&[0; 33], 32),
+            Err(ABIError::Base58CheckInputTooLong { len: 33, max: 32 })
+        ));
+        assert!(matches!(
+            base58_check_decode("1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa", 33),
+            Err(ABIError::Base58CheckInputTooLong { len: 34, max: 33 })
+        ));
+    }
 }
@@ -3,33 +3,78 @@
 //! IMPORTANT: these were designed for, and should not be called outside of
 //! ABIs.
 
+use std::time::Duration;
 use wasmer::FunctionEnvMut;
 
 use super::abi::get_env;
-use super::env::{get_remaining_points, set_remaining_points, ASEnv, Metered};
-use super::error::{abi_bail, ABIResult};
-use crate::Response;
+use super::env::{charge, get_remaining_points, set_remaining_points, ASEnv, Metered};
+use super::error::{abi_bail, ABIError, ABIResult};
+use crate::compilation_watchdog::{compilation_timeout, compile_nested_module};
+use crate::observer::ExecutionEvent;
+use crate::validation::{
+    check_contract_abi_namespaces, check_module_abi_namespaces, validate_contract_bytecode,
+    validate_created_bytecode,
+};
+use crate::{CallFrame, Compiler, Interface, Response, RuntimeModule, VMError};
 
-/// Calls an exported function in a WASM module at a given address
+/// Calls an exported function in a WASM module at a given address.
+///
+/// With `max_gas`, the execution of the callee is given at most that much gas
+/// and what it leaves goes back to the caller. Exhausting it fails the call
+/// with `ABIError::GasStipendExhausted` rather than an exhaustion of the gas
+/// of the caller. The compilation of the callee is charged to the caller.
+///
+/// A call re-entering the call stack in a way `RuntimeConfig::reentrancy` forbids
+/// fails with `ABIError::ReentrancyDenied` before `init_call`. A call with
+/// coins fails with `ABIError::WriteInReadOnlyMode`, naming `abi_name`, in a
+/// read-only execution.
 pub(crate) fn call_module(
     ctx: &mut FunctionEnvMut<ASEnv>,
+    abi_name: &str,
     address: &str,
     function: &str,
     param: &[u8],
     raw_coins: i64,
+    max_gas: Option<u64>,
 ) -> ABIResult<Response> {
     let raw_coins: u64 = match raw_coins.try_into() {
         Ok(v) => v,
         Err(_) => abi_bail!("negative amount of coins in Call"),
     };
     let env = get_env(ctx)?;
-    let bytecode = env.get_interface().init_call(address, raw_coins)?;
+    check_coins_write(&env, abi_name, raw_coins)?;
+    if max_gas == Some(0) {
+        abi_bail!(format!(
+            "Call to {}:{} with a gas limit of 0",
+            address, function
+        ));
+    }
+    let call_depth = next_call_depth(&env)?;
     let interface = env.get_interface();
-    let remaining_gas = get_remaining_gas(&env, ctx)?;
+    let mut gas = GasScope::new(&env, ctx)?;
+    let remaining_gas = gas.remaining_gas();
+    let call_context = env.get_call_context().nested_call(
+        call_depth,
+        CallFrame::new(address, function, remaining_gas),
+        &*interface,
+    )?;
+    call_context.check_reentrancy(env.get_config().reentrancy)?;
+    let callee = CalleeCode::init_call(&*interface, address, raw_coins, &call_context.compiler)?;
+    gas.charge_compilation(&callee, address)?;
+    let caller_gas = gas.remaining_gas();
+    let remaining_gas = max_gas.map_or(caller_gas, |max_gas| max_gas.min(caller_gas));
 
-    let module = interface
-        .get_module(&bytecode, remaining_gas)
+    let module = callee
+        .into_module(
+            &*interface,
+            remaining_gas,
+            call_context.compiler.clone(),
+            env.get_config().max_compilation_time,
+        )
         .map_err(|e| {
+            if let Some(max_time) = compilation_timeout(&e) {
+                return super::ABIError::CompilationTimeout { max_time };
+            }
             super::ABIError::Error(anyhow::anyhow!(format!(
                 "call to {}:{} error: {}",
                 address,
@@ -38,52 +83,483 @@ pub(crate) fn call_module(
             )))
         })?;
 
-    let resp = crate::execution::run_function(
+    observe_call_started(&env, address, function, remaining_gas);
+    let resp = match crate::execution::exec(
         &*interface,
         module,
         function,
         param,
         remaining_gas,
-        env.get_gas_costs(),
-    )?;
-    if cfg!(not(feature = "gas_calibration")) {
-        set_remaining_points(&env, ctx, resp.remaining_gas)?;
+        env.get_gas_costs().clone(),
+        call_context,
+    ) {
+        Ok((resp, _)) => resp,
+        Err(err) => {
+            gas.failed_sub_execution(remaining_gas, &err);
+            env.get_call_context()
+                .record_callee_gas(address, err.gas_used(remaining_gas));
+            observe_call_finished(&env, address, function, gas.remaining_gas(), false);
+            return Err(match (max_gas, &err) {
+                // the caller has gas left
+                (Some(max_gas), VMError::OutOfGas { .. }) if remaining_gas < caller_gas => {
+                    ABIError::GasStipendExhausted {
+                        address: address.to_string(),
+                        function: function.to_string(),
+                        max_gas,
+                    }
+                }
+                _ => ABIError::from_nested(err),
+            });
+        }
+    };
+    // the gas used by the callee is charged before `finish_call` so that it
+    // is never charged again when the node handles the failure
+    gas.sub_execution(remaining_gas, &resp);
+    env.get_call_context()
+        .record_callee_gas(address, remaining_gas.saturating_sub(resp.remaining_gas));
+    observe_call_finished(&env, address, function, gas.remaining_gas(), true);
+    gas.finish()?;
+    if let Err(error) = env.get_interface().finish_call() {
+        return Err(ABIError::FinishCallFailed {
+            error,
+            response: resp,
+        });
     }
-    env.get_interface().finish_call()?;
+    ctx.data_mut().add_spent_coins(raw_coins);
     Ok(resp)
 }
 
+/// Same as `call_module` but a failure of the callee is returned to the caller
+/// instead of trapping.
+///
+/// The gas used by the failed callee is still consumed and `finish_try_call`
+/// is called in every case so that the interface can roll back the changes of
+/// the failed sub-call. Errors occurring before the callee is started (invalid
+/// coins, coins in a read-only execution, call depth, re-entrancy,
+/// `init_call`) still trap.
+pub(crate) fn try_call_module(
+    ctx: &mut FunctionEnvMut<ASEnv>,
+    abi_name: &str,
+    address: &str,
+    function: &str,
+    param: &[u8],
+    raw_coins: i64,
+) -> ABIResult<Result<Response, String>> {
+    let raw_coins: u64 = match raw_coins.try_into() {
+        Ok(v) => v,
+        Err(_) => abi_bail!("negative amount of coins in Call"),
+    };
+    let env = get_env(ctx)?;
+    check_coins_write(&env, abi_name, raw_coins)?;
+    let call_depth = next_call_depth(&env)?;
+    let interface = env.get_interface();
+    let mut gas = GasScope::new(&env, ctx)?;
+    let remaining_gas = gas.remaining_gas();
+    let call_context = env.get_call_context().nested_call(
+        call_depth,
+        CallFrame::new(address, function, remaining_gas),
+        &*interface,
+    )?;
+    call_context.check_reentrancy(env.get_config().reentrancy)?;
+    let callee = CalleeCode::init_call(&*interface, address, raw_coins, &call_context.compiler)?;
+    gas.charge_compilation(&callee, address)?;
+    let remaining_gas = gas.remaining_gas();
+
+    let result = match callee.into_module(
+        &*interface,
+        remaining_gas,
+        call_context.compiler.clone(),
+        env.get_config().max_compilation_time,
+    ) {
+        Ok(module) => {
+            observe_call_started(&env, address, function, remaining_gas);
+            let result = match crate::execution::exec(
+                &*interface,
+                module,
+                function,
+                param,
+                remaining_gas,
+                env.get_gas_costs().clone(),
+                call_context,
+            ) {
+                Ok((resp, _)) => {
+                    gas.sub_execution(remaining_gas, &resp);
+                    env.get_call_context().record_callee_gas(
+                        address,
+                        remaining_gas.saturating_sub(resp.remaining_gas),
+                    );
+                    Ok(resp)
+                }
+                Err(err) => {
+                    gas.failed_sub_execution(remaining_gas, &err);
+                    env.get_call_context()
+                        .record_callee_gas(address, err.gas_used(remaining_gas));
+                    Err(err.to_string())
+                }
+            };
+            let remaining_gas = match &result {
+                Ok(resp) => resp.remaining_gas,
+                Err(_) => gas.remaining_gas(),
+            };
+            observe_call_finished(&env, address, function, remaining_gas, result.is_ok());
+            result
+        }
+        Err(e) => Err(format!("call to {}:{} error: {}", address, function, e)),
+    };
+    gas.finish()?;
+    env.get_interface().finish_try_call(result.is_ok())?;
+    // the coins of a failed callee are given back by the interface
+    if result.is_ok() {
+        ctx.data_mut().add_spent_coins(raw_coins);
+    }
+    Ok(result)
+}
+
+/// Code of a called smart contract, as found by `init_call`
+enum CalleeCode {
+    /// Module compiled from the bytecode of a previous call, whose length was
+    /// announced by `init_call_hash`
+    Cached { module: RuntimeModule, len: usize },
+    /// Bytecode to compile
+    Bytecode(Vec<u8>),
+}
+
+impl CalleeCode {
+    /// Prepare the call to `address` through the interface. The bytecode is
+    /// only fetched when the interface has no module compiled from it with
+    /// `compiler`.
+    fn init_call(
+        interface: &dyn Interface,
+        address: &str,
+        raw_coins: u64,
+        compiler: &Compiler,
+    ) -> anyhow::Result<Self> {
+        let Some((hash, len)) = interface.init_call_hash(address, raw_coins)? else {
+            return Ok(Self::Bytecode(interface.init_call(address, raw_coins)?));
+        };
+        if let Some(module) = interface.get_cached_module(&hash, compiler.clone())? {
+            return Ok(Self::Cached { module, len });
+        }
+        let bytecode = interface.raw_get_bytecode_for(address)?;
+        if bytecode.len() != len {
+            anyhow::bail!(
+                "bytecode of {} is {} bytes long, {} bytes were announced by init_call_hash",
+                address,
+                bytecode.len(),
+                len
+            );
+        }
+        Ok(Self::Bytecode(bytecode))
+    }
+
+    /// Module to run, compiled by the interface in at most
+    /// `max_compilation_time` if it was not cached
+    fn into_module(
+        self,
+        interface: &dyn Interface,
+        gas_limit: u64,
+        compiler: Compiler,
+        max_compilation_time: Option<Duration>,
+    ) -> anyhow::Result<RuntimeModule> {
+        match self {
+            Self::Cached { module, .. } => Ok(module),
+            Self::Bytecode(bytecode) => compile_nested_module(
+                interface,
+                bytecode,
+                gas_limit,
+                compiler,
+                max_compilation_time,
+            ),
+        }
+    }
+}
+
+/// Report the start of a nested call given `gas_limit`
+fn observe_call_started(env: &ASEnv, address: &str, function: &str, gas_limit: u64) {
+    env.get_call_context()
+        .observe(|| ExecutionEvent::NestedCallStarted {
+            address: address.to_string(),
+            function: function.to_string(),
+            gas_limit,
+        });
+}
+
+/// Report the end of a nested call, `remaining_gas` being the gas left to the
+/// callee
+fn observe_call_finished(
+    env: &ASEnv,
+    address: &str,
+    function: &str,
+    remaining_gas: u64,
+    success: bool,
+) {
+    if !success {
+        env.get_call_context()
+            .record_read_only(|recorder| recorder.nested_call_failed(address));
+    }
+    env.get_call_context()
+        .observe(|| ExecutionEvent::NestedCallFinished {
+            address: address.to_string(),
+            function: function.to_string(),
+            remaining_gas,
+            success,
+        });
+}
+
+/// Fail if `raw_coins` are sent by `abi_name` in a read-only execution
+fn check_coins_write(env: &ASEnv, abi_name: &str, raw_coins: u64) -> ABIResult<()> {
+    if raw_coins != 0 {
+        env.get_call_context().check_write(abi_name)?;
+    }
+    Ok(())
+}
+
+/// Gas left to the caller after a failed call, everything that was given to
+/// the callee is consumed when the failure does not report it
+fn failed_call_remaining_gas(err: &VMError) -> u64 {
+    match err {
+        VMError::ExecutionError { remaining_gas, .. }
+        | VMError::FinishCallError { remaining_gas, .. }
+        | VMError::Aborted { remaining_gas, .. }
+        | VMError::ForbiddenAbi { remaining_gas, .. }
+        | VMError::Timeout { remaining_gas, .. }
+        | VMError::Interrupted { remaining_gas, .. }
+        | VMError::FunctionNotFound { remaining_gas, .. }
+        | VMError::ReturnTooLarge { remaining_gas, .. } => *remaining_gas,
+        VMError::OutOfGas { .. }
+        | VMError::InstanceError(_)
+        | VMError::ParamTooLarge { .. }
+        | VMError::IncompatibleInterface { .. } => 0,
+    }
+}
+
 /// Alternative to `call_module` to execute bytecode in a local context
+///
+/// If `raw_coins` is not zero, the coins are debited from the caller through
+/// `init_local_call` and credited back if the execution fails. Coins are
+/// refused in a read-only execution like in `call_module`.
 pub(crate) fn local_call(
     ctx: &mut FunctionEnvMut<ASEnv>,
+    abi_name: &str,
     bytecode: &[u8],
     function: &str,
     param: &[u8],
     tmp: bool,
+    raw_coins: i64,
 ) -> ABIResult<Response> {
+    let raw_coins: u64 = match raw_coins.try_into() {
+        Ok(v) => v,
+        Err(_) => abi_bail!("negative amount of coins in local call"),
+    };
     let env = get_env(ctx)?;
-    let gas_costs = env.get_gas_costs();
-    let interface = env.get_interface();
-    let remaining_gas = get_remaining_gas(&env, ctx)?;
+    check_coins_write(&env, abi_name, raw_coins)?;
+    if raw_coins == 0 {
+        return exec_local_call(ctx, &env, bytecode, function, param, tmp);
+    }
+
+    env.get_interface().init_local_call(raw_coins)?;
+    match exec_local_call(ctx, &env, bytecode, function, param, tmp) {
+        Ok(resp) => {
+            env.get_interface().finish_local_call(true)?;
+            Ok(resp)
+        }
+        Err(err) => {
+            env.get_interface().finish_local_call(false)?;
+            Err(err)
+        }
+    }
+}
 
+fn exec_local_call(
+    ctx: &mut FunctionEnvMut<ASEnv>,
+    env: &ASEnv,
+    bytecode: &[u8],
+    function: &str,
+    param: &[u8],
+    tmp: bool,
+) -> ABIResult<Response> {
+    let interface = env.get_interface();
+    let gas = GasScope::new(env, ctx)?;
+    let remaining_gas = gas.remaining_gas();
     let module = if tmp {
         interface.get_tmp_module(bytecode, remaining_gas)?
     } else {
-        interface.get_module(bytecode, remaining_gas)?
+        interface.get_module_with_compiler(
+            bytecode,
+            remaining_gas,
+            env.get_call_context().compiler.clone(),
+        )?
+    };
+    exec_local_module(gas, module, function, param)
+}
+
+/// Execute `function` of the module currently executed, in the current
+/// context.
+///
+/// The compiled module is reused so no compilation is charged, but the call
+/// runs in a fresh instance with its own metering like any other local call.
+pub(crate) fn local_call_self(
+    ctx: &mut FunctionEnvMut<ASEnv>,
+    function: &str,
+    param: &[u8],
+) -> ABIResult<Response> {
+    let env = get_env(ctx)?;
+    let module = match env.get_current_module() {
+        Some(module) => module.clone(),
+        None => abi_bail!("No module is being executed"),
     };
+    if !module.function_exists(function) {
+        abi_bail!(format!(
+            "Function {} is not exported by the current module",
+            function
+        ));
+    }
+    let gas = GasScope::new(&env, ctx)?;
+    exec_local_module(gas, RuntimeModule::ASModule(module), function, param)
+}
+
+/// Execute `function` of `module` in the current context with the gas of
+/// `gas`
+fn exec_local_module(
+    mut gas: GasScope,
+    module: RuntimeModule,
+    function: &str,
+    param: &[u8],
+) -> ABIResult<Response> {
+    let env = gas.env;
+    let call_depth = next_call_depth(env)?;
+    let interface = env.get_interface();
+    let remaining_gas = gas.remaining_gas();
 
-    let resp = crate::execution::run_function(
+    match crate::execution::exec(
         &*interface,
         module,
         function,
         param,
         remaining_gas,
-        gas_costs,
-    )?;
-    if cfg!(not(feature = "gas_calibration")) {
-        set_remaining_points(&env, ctx, resp.remaining_gas)?;
+        env.get_gas_costs().clone(),
+        env.get_call_context().nested(call_depth, None),
+    ) {
+        Ok((resp, _)) => {
+            gas.sub_execution(remaining_gas, &resp);
+            gas.finish()?;
+            Ok(resp)
+        }
+        Err(err) => {
+            gas.failed_sub_execution(remaining_gas, &err);
+            Err(ABIError::from_nested(err))
+        }
     }
-    Ok(resp)
+}
+
+/// Gas of the caller of nested executions.
+///
+/// The remaining points of the caller are read when the scope is created and
+/// lent to the nested executions. What they leave is written back when the
+/// scope is finished, or dropped on an error path.
+pub(crate) struct GasScope<'a, 'ctx> {
+    env: &'a ASEnv,
+    ctx: &'a mut FunctionEnvMut<'ctx, ASEnv>,
+    remaining_gas: u64,
+    finished: bool,
+}
+
+impl<'a, 'ctx> GasScope<'a, 'ctx> {
+    pub(crate) fn new(env: &'a ASEnv, ctx: &'a mut FunctionEnvMut<'ctx, ASEnv>) -> ABIResult<Self> {
+        let remaining_gas = get_remaining_gas(env, ctx)?;
+        Ok(Self {
+            env,
+            ctx,
+            remaining_gas,
+            finished: false,
+        })
+    }
+
+    /// Gas left to the caller, the limit of a nested execution
+    pub(crate) fn remaining_gas(&self) -> u64 {
+        self.remaining_gas
+    }
+
+    /// Charge `gas` to the caller, which cannot consume more than it has left
+    pub(crate) fn charge(&mut self, gas: u64) {
+        self.remaining_gas = self.remaining_gas.saturating_sub(gas);
+    }
+
+    /// Charge the compilation of the bytecode of `callee`, the called smart
+    /// contract at `address`, before it happens, a bytecode mixing the ABI
+    /// versions being refused before it is. A module cached by the interface
+    /// is charged the same, per byte of its bytecode: the gas of a call does
+    /// not depend on the modules a node has at hand.
+    fn charge_compilation(&mut self, callee: &CalleeCode, address: &str) -> ABIResult<()> {
+        let config = self.env.get_config();
+        let len = match callee {
+            CalleeCode::Cached { module, len } => {
+                check_module_abi_namespaces(module, config)?;
+                *len
+            }
+            CalleeCode::Bytecode(bytecode) => {
+                check_contract_abi_namespaces(bytecode, config)?;
+                bytecode.len()
+            }
+        };
+        if config.gas_calibration {
+            return Ok(());
+        }
+        let cost_per_byte = self.env.get_gas_costs().compilation_cost_per_byte;
+        let cost = (len as u64).saturating_mul(cost_per_byte);
+        if cost > self.remaining_gas {
+            return Err(ABIError::OutOfGas {
+                at_function: format!("compilation of {}", address),
+            });
+        }
+        self.charge(cost);
+        Ok(())
+    }
+
+    /// Charge the gas consumed by a nested execution given `limit`
+    pub(crate) fn sub_execution(&mut self, limit: u64, resp: &Response) {
+        self.charge(limit.saturating_sub(resp.remaining_gas));
+    }
+
+    /// Charge the gas consumed by a failed nested execution given `limit`
+    pub(crate) fn failed_sub_execution(&mut self, limit: u64, err: &VMError) {
+        self.charge(limit.saturating_sub(failed_call_remaining_gas(err)));
+    }
+
+    /// Write the remaining gas back to the caller
+    pub(crate) fn finish(mut self) -> ABIResult<()> {
+        self.finished = true;
+        set_remaining_points(self.env, self.ctx, self.remaining_gas)
+    }
+}
+
+impl Drop for GasScope<'_, '_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            // the error being returned takes precedence
+            let _ = set_remaining_points(self.env, self.ctx, self.remaining_gas);
+        }
+    }
+}
+
+/// Charge `GasCosts::sc_creation_byte_cost` for each byte of the `bytecode`
+/// of a smart contract created by the ABI `abi_name`, then validate it. The
+/// interface is not called when it fails.
+pub(crate) fn check_created_bytecode(
+    ctx: &mut FunctionEnvMut<ASEnv>,
+    abi_name: &str,
+    bytecode: &[u8],
+) -> ABIResult<()> {
+    let env = get_env(ctx)?;
+    charge(
+        &env,
+        ctx,
+        abi_name,
+        0,
+        env.get_gas_costs().sc_creation_byte_cost,
+        bytecode.len(),
+    )?;
+    validate_created_bytecode(bytecode, env.get_config())
 }
 
 /// Create a smart contract with the given `bytecode`
@@ -92,6 +568,50 @@ pub(crate) fn create_sc(ctx: &mut FunctionEnvMut<ASEnv>, bytecode: &[u8]) -> ABI
     Ok(env.get_interface().create_module(bytecode)?)
 }
 
+/// Replace the bytecode of `address`, or of the current address if `None`, by
+/// `bytecode`, which has to pass the validation of a deployment
+pub(crate) fn set_bytecode(env: &ASEnv, address: Option<&str>, bytecode: &[u8]) -> ABIResult<()> {
+    validate_contract_bytecode(bytecode, env.get_config())?;
+    let interface = env.get_interface();
+    interface.invalidate_cached_module(address)?;
+    match address {
+        Some(address) => interface.raw_set_bytecode_for(address, bytecode)?,
+        None => interface.raw_set_bytecode(bytecode)?,
+    }
+    Ok(())
+}
+
+/// Create a smart contract with the given `bytecode` and initial `datastore`
+pub(crate) fn create_sc_with_datastore(
+    ctx: &mut FunctionEnvMut<ASEnv>,
+    bytecode: &[u8],
+    datastore: &[(Vec<u8>, Vec<u8>)],
+) -> ABIResult<String> {
+    let env = ctx.data();
+    Ok(env
+        .get_interface()
+        .create_module_with_datastore(bytecode, datastore)?)
+}
+
+/// Create a smart contract with the given `bytecode` at the address predicted
+/// for `seed`, an interface creating it elsewhere failing the call
+pub(crate) fn create_sc_with_seed(
+    ctx: &mut FunctionEnvMut<ASEnv>,
+    bytecode: &[u8],
+    seed: &[u8],
+) -> ABIResult<String> {
+    let interface = ctx.data().get_interface();
+    let predicted = interface.predict_created_address(seed)?;
+    let address = interface.create_module_with_seed(bytecode, seed)?;
+    if address != predicted {
+        abi_bail!(format!(
+            "the smart contract was created at {} instead of the predicted address {}",
+            address, predicted
+        ));
+    }
+    Ok(address)
+}
+
 /// Check the exports of a compiled module to see if it contains the given
 /// function
 pub(crate) fn function_exists(
@@ -105,20 +625,167 @@ pub(crate) fn function_exists(
     let remaining_gas = get_remaining_gas(&env, ctx)?;
 
     let function_exists = interface
-        .get_module(&bytecode, remaining_gas)?
+        .get_module_with_compiler(
+            &bytecode,
+            remaining_gas,
+            env.get_call_context().compiler.clone(),
+        )?
         .function_exists(function);
 
     Ok(function_exists)
 }
 
+/// Depth of a call made from the current execution, fails if it goes over
+/// the configured `max_recursion_depth`
+fn next_call_depth(env: &ASEnv) -> ABIResult<u32> {
+    check_call_depth(
+        env.get_call_context().depth,
+        env.get_config().max_recursion_depth,
+    )
+}
+
+/// Depth of a call made at `current_depth`, shared with the wasmv1 ABIs
+pub(crate) fn check_call_depth(current_depth: u32, max_depth: u32) -> ABIResult<u32> {
+    match current_depth.checked_add(1) {
+        Some(depth) if depth <= max_depth => Ok(depth),
+        _ => Err(ABIError::DepthLimitReached(max_depth)),
+    }
+}
+
 pub(crate) fn get_remaining_gas(
     env: &ASEnv,
     ctx: &mut FunctionEnvMut<'_, ASEnv>,
 ) -> Result<u64, super::ABIError> {
-    let remaining_gas = if cfg!(feature = "gas_calibration") {
+    let remaining_gas = if env.gas_calibration() {
         u64::MAX
     } else {
         get_remaining_points(env, ctx)?
     };
     Ok(remaining_gas)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::execution::CallContext;
+
+    #[test]
+    fn test_failed_call_remaining_gas() {
+        let err = VMError::ExecutionError {
+            error: "abort".to_string(),
+            init_gas_cost: 10,
+            remaining_gas: 500,
+            call_stack: vec![],
+        };
+        assert_eq!(failed_call_remaining_gas(&err), 500);
+        let err = VMError::OutOfGas {
+            at_function: "main".to_string(),
+            init_gas_cost: 10,
+        };
+        assert_eq!(failed_call_remaining_gas(&err), 0);
+        let err = VMError::InstanceError("invalid module".to_string());
+        assert_eq!(failed_call_remaining_gas(&err), 0);
+    }
+
+    #[test]
+    fn test_check_call_depth() {
+        // every nested level up to the limit is allowed
+        for depth in 0..4 {
+            assert_eq!(check_call_depth(depth, 4).unwrap(), depth + 1);
+        }
+        // the call that would go over the limit fails
+        assert!(matches!(
+            check_call_depth(4, 4),
+            Err(ABIError::DepthLimitReached(4))
+        ));
+        assert!(matches!(
+            check_call_depth(0, 0),
+            Err(ABIError::DepthLimitReached(0))
+        ));
+        // sibling calls made from the same level see the same depth
+        assert_eq!(check_call_depth(2, 4).unwrap(), 3);
+        assert_eq!(check_call_depth(2, 4).unwrap(), 3);
+        assert!(check_call_depth(u32::MAX, u32::MAX).is_err());
+    }
+
+    #[test]
+    fn test_nested_call_context() {
+        let top = CallContext::default();
+        let first = top.nested(1, Some(CallFrame::new("AS1", "main", 1_000)));
+        let local = first.nested(2, None);
+        let second = local.nested(3, Some(CallFrame::new("AS2", "receive", 400)));
+        assert_eq!(second.depth, 3);
+        assert_eq!(
+            second.stack,
+            vec![
+                CallFrame::new("AS1", "main", 1_000),
+                CallFrame::new("AS2", "receive", 400)
+            ]
+        );
+        // the parent contexts are left untouched
+        assert!(top.stack.is_empty());
+        assert_eq!(local.stack.len(), 1);
+
+        // the innermost failure carries its full stack up to the top-level
+        // execution
+        let third = second.nested(4, Some(CallFrame::new("AS3", "abort", 100)));
+        let err = anyhow::Error::new(ABIError::from_nested(VMError::ExecutionError {
+            error: "abort".to_string(),
+            init_gas_cost: 0,
+            remaining_gas: 0,
+            call_stack: third.stack.clone(),
+        }));
+        let call_stack = ABIError::nested_call_stack(&err).unwrap();
+        let addresses: Vec<_> = call_stack.iter().map(|f| f.address.as_str()).collect();
+        assert_eq!(addresses, vec!["AS1", "AS2", "AS3"]);
+        assert_eq!(call_stack[2].function, "abort");
+
+        // only the innermost frames are kept
+        let mut ctx = CallContext::default();
+        let max = crate::settings::max_call_stack_frames();
+        for depth in 0..(max as u64 + 5) {
+            ctx = ctx.nested(depth as u32 + 1, Some(CallFrame::new("AS", "f", depth)));
+        }
+        assert_eq!(ctx.stack.len(), max);
+        assert_eq!(ctx.stack.last().unwrap().gas_at_entry, max as u64 + 4);
+        assert_eq!(ctx.stack[0].gas_at_entry, 5);
+    }
+
+    #[test]
+    fn test_call_frame_truncation() {
+        let max = crate::settings::max_call_frame_string_length();
+        let frame = CallFrame::new(&"a".repeat(max + 10), &"é".repeat(max), 0);
+        assert_eq!(frame.address.len(), max);
+        assert!(frame.function.len() <= max);
+        assert!(frame.function.chars().all(|c| c == 'é'));
+    }
+
+    #[test]
+    fn test_call_context_call_stack() {
+        let interface = crate::tests::TestInterface;
+        let root = CallContext::default();
+        assert!(root.call_stack(&interface).unwrap().is_empty());
+
+        let a = root
+            .nested_call(1, CallFrame::new("AS1", "main", 300), &interface)
+            .unwrap();
+        let b = a
+            .nested_call(2, CallFrame::new("AS2", "main", 200), &interface)
+            .unwrap();
+        // a local call does not change the stack
+        let local = b.nested(3, None);
+        let c = local
+            .nested_call(4, CallFrame::new("AS3", "main", 100), &interface)
+            .unwrap();
+        assert_eq!(
+            c.call_stack(&interface).unwrap(),
+            vec!["AS1".to_string(), "AS2".to_string(), "AS3".to_string()]
+        );
+        // back in the callers once the nested calls returned
+        assert_eq!(
+            b.call_stack(&interface).unwrap(),
+            vec!["AS1".to_string(), "AS2".to_string()]
+        );
+        assert_eq!(a.call_stack(&interface).unwrap(), vec!["AS1".to_string()]);
+    }
+}
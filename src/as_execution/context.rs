@@ -1,15 +1,138 @@
 use super::abi::*;
-use super::env::{get_remaining_points, set_remaining_points, ASEnv, Metered};
+use super::env::{
+    get_remaining_points, set_remaining_points, sub_remaining_gas_abi, ASEnv, Metered,
+};
+use super::{ABIError, ABIResult};
+use crate::digest::{NativeDigest, DIGESTS};
+use crate::execution::CallContext;
+use crate::middlewares::deadline::{DEADLINE_CHECK_NAME, DEADLINE_CHECK_NAMESPACE};
 use crate::types::Response;
-use crate::{GasCosts, Interface};
+use crate::{ForbiddenAbiMode, GasCosts, Interface, RuntimeConfig};
 use anyhow::{bail, Result};
-use as_ffi_bindings::{BufferPtr, Read as ASRead, Write as ASWrite};
 use wasmer::{
-    imports, Function, FunctionEnv, Imports, Instance, InstantiationError, Module, Store, Value,
+    imports, Extern, Function, FunctionEnv, FunctionEnvMut, FunctionType, Imports, Instance,
+    InstantiationError, Module, RuntimeError, Store, Type, Value,
 };
 use wasmer_middlewares::metering::{self, MeteringPoints};
 use wasmer_types::TrapCode;
 
+/// ABIs charged under their own name in the gas costs, the ones imported
+/// from "env" by AssemblyScript included. `assembly_script_process_exit`
+/// aborts the execution without being charged.
+pub(crate) const ABI_NAMES: &[&str] = &[
+    "assembly_script_abort",
+    "assembly_script_seed",
+    "assembly_script_date_now",
+    "assembly_script_console_log",
+    "assembly_script_console_info",
+    "assembly_script_console_warn",
+    "assembly_script_console_error",
+    "assembly_script_console_debug",
+    "assembly_script_trace",
+    "assembly_script_print",
+    "assembly_script_call",
+    "assembly_script_try_call",
+    "assembly_script_call_with_gas_limit",
+    "assembly_script_get_remaining_gas",
+    "assembly_script_create_sc",
+    "assembly_script_create_sc_with_data",
+    "assembly_script_create_sc_with_seed",
+    "assembly_script_predict_sc_address",
+    "assembly_script_set_data",
+    "assembly_script_set_data_batch",
+    "assembly_script_set_data_for",
+    "assembly_script_get_data",
+    "assembly_script_get_data_chunk",
+    "assembly_script_get_data_len",
+    "assembly_script_hint_keys",
+    "assembly_script_get_data_for",
+    "assembly_script_delete_data",
+    "assembly_script_delete_data_for",
+    "assembly_script_append_data",
+    "assembly_script_append_data_for",
+    "assembly_script_has_data",
+    "assembly_script_has_data_for",
+    "assembly_script_get_storage_cost",
+    "assembly_script_reserve_storage",
+    "assembly_script_get_owned_addresses",
+    "assembly_script_get_call_stack",
+    "assembly_script_generate_event",
+    "assembly_script_generate_event_binary",
+    "assembly_script_get_event_count",
+    "assembly_script_get_last_event",
+    "assembly_script_transfer_coins",
+    "assembly_script_transfer_coins_for",
+    "assembly_script_transfer_coins_checked",
+    "assembly_script_get_balance",
+    "assembly_script_get_balance_for",
+    "assembly_script_get_balances",
+    "assembly_script_hash",
+    "assembly_script_hash_sha256",
+    "assembly_script_base58_check_encode",
+    "assembly_script_base58_check_decode",
+    "assembly_script_constant_eq",
+    "assembly_script_checked_add_native_amount",
+    "assembly_script_checked_sub_native_amount",
+    "assembly_script_checked_mul_native_amount",
+    "assembly_script_checked_div_native_amount",
+    "assembly_script_native_amount_from_string",
+    "assembly_script_native_amount_to_string",
+    "assembly_script_keccak256_hash",
+    "assembly_script_sha256",
+    "assembly_script_verify_merkle_proof",
+    "assembly_script_sha3_256",
+    "assembly_script_signature_verify",
+    "assembly_script_bls_verify",
+    "assembly_script_bls_aggregate_verify",
+    "assembly_script_bls_aggregate_public_keys",
+    "assembly_script_evm_signature_verify",
+    "assembly_script_evm_get_address_from_pubkey",
+    "assembly_script_evm_get_pubkey_from_signature",
+    "assembly_script_is_address_eoa",
+    "assembly_script_address_from_public_key",
+    "assembly_script_address_from_public_key_bytes",
+    "assembly_script_public_key_from_string",
+    "assembly_script_validate_address",
+    "assembly_script_unsafe_random",
+    "assembly_script_get_random_seed",
+    "assembly_script_random_bytes",
+    "assembly_script_get_call_coins",
+    "assembly_script_get_spent_coins",
+    "assembly_script_get_time",
+    "assembly_script_send_message",
+    "assembly_script_send_async_message",
+    "assembly_script_deferred_call_register",
+    "assembly_script_deferred_call_exists",
+    "assembly_script_deferred_call_cancel",
+    "assembly_script_deferred_call_quote",
+    "assembly_script_get_origin_operation_id",
+    "assembly_script_get_current_period",
+    "assembly_script_get_current_thread",
+    "assembly_script_get_current_slot",
+    "assembly_script_slot_to_timestamp",
+    "assembly_script_timestamp_to_slot",
+    "assembly_script_set_bytecode",
+    "assembly_script_set_bytecode_for",
+    "assembly_script_get_op_keys",
+    "assembly_script_get_op_keys_prefix",
+    "assembly_script_get_keys",
+    "assembly_script_get_keys_for",
+    "assembly_script_get_keys_paged",
+    "assembly_script_get_keys_values",
+    "assembly_script_has_op_key",
+    "assembly_script_get_op_data",
+    "assembly_script_get_bytecode",
+    "assembly_script_get_bytecode_for",
+    "assembly_script_local_call",
+    "assembly_script_local_call_self",
+    "assembly_script_local_execution",
+    "assembly_script_local_execution_with_coins",
+    "assembly_script_caller_has_write_access",
+    "assembly_script_function_exists",
+    "assembly_script_chain_id",
+    "assembly_script_runtime_version",
+];
+
 pub(crate) struct ASContext {
     pub env: ASEnv,
     pub module: Module,
@@ -26,9 +149,10 @@ impl ASContext {
         interface: &dyn Interface,
         binary_module: Module,
         gas_costs: GasCosts,
+        call_context: CallContext,
     ) -> Self {
         Self {
-            env: ASEnv::new(interface, gas_costs),
+            env: ASEnv::new(interface, gas_costs, call_context),
             module: binary_module,
         }
     }
@@ -38,11 +162,25 @@ impl ASContext {
         &mut self,
         store: &mut Store,
     ) -> Result<(Instance, FunctionEnv<ASEnv>, u64)> {
-        let (imports, mut fenv) = self.resolver(store);
-        match Instance::new(store, &self.module, &imports) {
+        let (imports, fenv) = self.resolver(store);
+        self.create_vm_instance_with_imports(store, &imports, fenv)
+    }
+
+    /// Same as `create_vm_instance_and_init_env` with `imports` resolved in
+    /// `store`, possibly by a previous execution: the environment of their
+    /// functions is replaced by the one of this execution
+    pub(crate) fn create_vm_instance_with_imports(
+        &mut self,
+        store: &mut Store,
+        imports: &Imports,
+        mut fenv: FunctionEnv<ASEnv>,
+    ) -> Result<(Instance, FunctionEnv<ASEnv>, u64)> {
+        *fenv.as_mut(store) = self.env.clone();
+        self.check_forbidden_imports()?;
+        match Instance::new(store, &self.module, imports) {
             Ok(instance) => {
                 self.init_with_instance(store, &instance, &mut fenv)?;
-                let post_init_points = if cfg!(not(feature = "gas_calibration")) {
+                let post_init_points = if !self.env.gas_calibration() {
                     if let MeteringPoints::Remaining(points) =
                         metering::get_remaining_points(store, &instance)
                     {
@@ -80,7 +218,7 @@ impl ASContext {
         function: &str,
         param: &[u8],
     ) -> Result<Response> {
-        if cfg!(not(feature = "gas_calibration")) {
+        if !self.env.gas_calibration() {
             // Sub initial metering cost
             let metering_initial_cost = self.env.get_gas_costs().launch_cost;
             let remaining_gas = get_remaining_points(&self.env, store)?;
@@ -95,16 +233,18 @@ impl ASContext {
         let res = if argc == 0 {
             wasm_func.call(store, &[])
         } else if argc == 1 {
-            let param_ptr = *BufferPtr::alloc(&param.to_vec(), self.env.get_ffi_env(), store)?;
-            wasm_func.call(store, &[Value::I32(param_ptr.offset() as i32)])
+            let param_offset = self.env.write_buffer(store, function, param)?;
+            wasm_func.call(store, &[Value::I32(param_offset)])
         } else {
             bail!("Unexpected number of parameters in the function called")
         };
 
         match res {
             Ok(value) => {
-                if function.eq(crate::settings::MAIN) {
-                    let remaining_gas = if cfg!(feature = "gas_calibration") {
+                // legacy mains return a status, only the ones given a
+                // parameter return bytes
+                if function.eq(crate::settings::MAIN) && param.is_empty() {
+                    let remaining_gas = if self.env.gas_calibration() {
                         Ok(0_u64)
                     } else {
                         get_remaining_points(&self.env, store)
@@ -116,20 +256,21 @@ impl ASContext {
                         init_gas_cost: 0,
                         #[cfg(feature = "execution-trace")]
                         trace: Default::default(),
+                        gas_breakdown: None,
+                        #[cfg(feature = "profiling")]
+                        profile: Default::default(),
                     });
                 }
                 let ret = if let Some(offset) = value.first() {
                     if let Some(offset) = offset.i32() {
-                        let buffer_ptr = BufferPtr::new(offset as u32);
-                        let memory = instance.exports.get_memory("memory")?;
-                        buffer_ptr.read(memory, store)?
+                        self.env.read_return_value(store, function, offset)?
                     } else {
                         bail!("Execution wasn't in capacity to read the return value")
                     }
                 } else {
                     Vec::new()
                 };
-                let remaining_gas = if cfg!(feature = "gas_calibration") {
+                let remaining_gas = if self.env.gas_calibration() {
                     Ok(0_u64)
                 } else {
                     get_remaining_points(&self.env, store)
@@ -140,6 +281,9 @@ impl ASContext {
                     init_gas_cost: 0,
                     #[cfg(feature = "execution-trace")]
                     trace: Default::default(),
+                    gas_breakdown: None,
+                    #[cfg(feature = "profiling")]
+                    profile: Default::default(),
                 })
             }
             Err(error) => bail!(error),
@@ -191,7 +335,7 @@ impl ASContext {
         );
 
         // Metering counters
-        if cfg!(not(feature = "gas_calibration")) {
+        if !self.env.gas_calibration() {
             let g_1 = instance
                 .exports
                 .get_global("wasmer_metering_remaining_points")?
@@ -213,7 +357,7 @@ impl ASContext {
     pub(crate) fn resolver(&self, store: &mut Store) -> (Imports, FunctionEnv<ASEnv>) {
         let fenv = FunctionEnv::new(store, self.env.clone());
 
-        let imports = imports! {
+        let mut imports = imports! {
             "env" => {
                 // Needed by WASM generated by AssemblyScript
                 "abort" =>  Function::new_typed_with_env(store, &fenv, assembly_script_abort),
@@ -230,11 +374,20 @@ impl ASContext {
             "massa" => {
                 "assembly_script_print" => Function::new_typed_with_env(store, &fenv, assembly_script_print),
                 "assembly_script_call" => Function::new_typed_with_env(store, &fenv, assembly_script_call),
+                "assembly_script_try_call" => Function::new_typed_with_env(store, &fenv, assembly_script_try_call),
+                "assembly_script_call_with_gas_limit" => Function::new_typed_with_env(store, &fenv, assembly_script_call_with_gas_limit),
                 "assembly_script_get_remaining_gas" => Function::new_typed_with_env(store, &fenv, assembly_script_get_remaining_gas),
                 "assembly_script_create_sc" => Function::new_typed_with_env(store, &fenv, assembly_script_create_sc),
+                "assembly_script_create_sc_with_data" => Function::new_typed_with_env(store, &fenv, assembly_script_create_sc_with_data),
+                "assembly_script_create_sc_with_seed" => Function::new_typed_with_env(store, &fenv, assembly_script_create_sc_with_seed),
+                "assembly_script_predict_sc_address" => Function::new_typed_with_env(store, &fenv, assembly_script_predict_sc_address),
                 "assembly_script_set_data" => Function::new_typed_with_env(store, &fenv, assembly_script_set_data),
+                "assembly_script_set_data_batch" => Function::new_typed_with_env(store, &fenv, assembly_script_set_data_batch),
                 "assembly_script_set_data_for" => Function::new_typed_with_env(store, &fenv, assembly_script_set_data_for),
                 "assembly_script_get_data" => Function::new_typed_with_env(store, &fenv, assembly_script_get_data),
+                "assembly_script_get_data_chunk" => Function::new_typed_with_env(store, &fenv, assembly_script_get_data_chunk),
+                "assembly_script_get_data_len" => Function::new_typed_with_env(store, &fenv, assembly_script_get_data_len),
+                "assembly_script_hint_keys" => Function::new_typed_with_env(store, &fenv, assembly_script_hint_keys),
                 "assembly_script_get_data_for" => Function::new_typed_with_env(store, &fenv, assembly_script_get_data_for),
                 "assembly_script_delete_data" => Function::new_typed_with_env(store, &fenv, assembly_script_delete_data),
                 "assembly_script_delete_data_for" => Function::new_typed_with_env(store, &fenv, assembly_script_delete_data_for),
@@ -242,48 +395,214 @@ impl ASContext {
                 "assembly_script_append_data_for" => Function::new_typed_with_env(store, &fenv, assembly_script_append_data_for),
                 "assembly_script_has_data" => Function::new_typed_with_env(store, &fenv, assembly_script_has_data),
                 "assembly_script_has_data_for" => Function::new_typed_with_env(store, &fenv, assembly_script_has_data_for),
+                "assembly_script_get_storage_cost" => Function::new_typed_with_env(store, &fenv, assembly_script_get_storage_cost),
+                "assembly_script_reserve_storage" => Function::new_typed_with_env(store, &fenv, assembly_script_reserve_storage),
                 "assembly_script_get_owned_addresses" => Function::new_typed_with_env(store, &fenv, assembly_script_get_owned_addresses),
                 "assembly_script_get_call_stack" => Function::new_typed_with_env(store, &fenv, assembly_script_get_call_stack),
                 "assembly_script_generate_event" => Function::new_typed_with_env(store, &fenv, assembly_script_generate_event),
+                "assembly_script_generate_event_binary" => Function::new_typed_with_env(store, &fenv, assembly_script_generate_event_binary),
+                "assembly_script_get_event_count" => Function::new_typed_with_env(store, &fenv, assembly_script_get_event_count),
+                "assembly_script_get_last_event" => Function::new_typed_with_env(store, &fenv, assembly_script_get_last_event),
                 "assembly_script_transfer_coins" => Function::new_typed_with_env(store, &fenv, assembly_script_transfer_coins),
                 "assembly_script_transfer_coins_for" => Function::new_typed_with_env(store, &fenv, assembly_script_transfer_coins_for),
+                "assembly_script_transfer_coins_checked" => Function::new_typed_with_env(store, &fenv, assembly_script_transfer_coins_checked),
                 "assembly_script_get_balance" => Function::new_typed_with_env(store, &fenv, assembly_script_get_balance),
                 "assembly_script_get_balance_for" => Function::new_typed_with_env(store, &fenv, assembly_script_get_balance_for),
+                "assembly_script_get_balances" => Function::new_typed_with_env(store, &fenv, assembly_script_get_balances),
                 "assembly_script_hash" => Function::new_typed_with_env(store, &fenv, assembly_script_hash),
                 "assembly_script_hash_sha256" =>  Function::new_typed_with_env(store, &fenv, assembly_script_hash_sha256),
+                "assembly_script_base58_check_encode" => Function::new_typed_with_env(store, &fenv, assembly_script_base58_check_encode),
+                "assembly_script_base58_check_decode" => Function::new_typed_with_env(store, &fenv, assembly_script_base58_check_decode),
+                "assembly_script_constant_eq" => Function::new_typed_with_env(store, &fenv, assembly_script_constant_eq),
+                "assembly_script_checked_add_native_amount" => Function::new_typed_with_env(store, &fenv, assembly_script_checked_add_native_amount),
+                "assembly_script_checked_sub_native_amount" => Function::new_typed_with_env(store, &fenv, assembly_script_checked_sub_native_amount),
+                "assembly_script_checked_mul_native_amount" => Function::new_typed_with_env(store, &fenv, assembly_script_checked_mul_native_amount),
+                "assembly_script_checked_div_native_amount" => Function::new_typed_with_env(store, &fenv, assembly_script_checked_div_native_amount),
+                "assembly_script_native_amount_from_string" => Function::new_typed_with_env(store, &fenv, assembly_script_native_amount_from_string),
+                "assembly_script_native_amount_to_string" => Function::new_typed_with_env(store, &fenv, assembly_script_native_amount_to_string),
                 "assembly_script_keccak256_hash" =>  Function::new_typed_with_env(store, &fenv, assembly_script_keccak256_hash),
+                "assembly_script_verify_merkle_proof" => Function::new_typed_with_env(store, &fenv, assembly_script_verify_merkle_proof),
                 "assembly_script_signature_verify" => Function::new_typed_with_env(store, &fenv, assembly_script_signature_verify),
+                "assembly_script_bls_verify" => Function::new_typed_with_env(store, &fenv, assembly_script_bls_verify),
+                "assembly_script_bls_aggregate_verify" => Function::new_typed_with_env(store, &fenv, assembly_script_bls_aggregate_verify),
+                "assembly_script_bls_aggregate_public_keys" => Function::new_typed_with_env(store, &fenv, assembly_script_bls_aggregate_public_keys),
                 "assembly_script_evm_signature_verify" => Function::new_typed_with_env(store, &fenv, assembly_script_evm_signature_verify),
                 "assembly_script_evm_get_address_from_pubkey" => Function::new_typed_with_env(store, &fenv, assembly_script_evm_get_address_from_pubkey),
                 "assembly_script_evm_get_pubkey_from_signature" => Function::new_typed_with_env(store, &fenv, assembly_script_evm_get_pubkey_from_signature),
                 "assembly_script_is_address_eoa" => Function::new_typed_with_env(store, &fenv, assembly_script_is_address_eoa),
                 "assembly_script_address_from_public_key" => Function::new_typed_with_env(store, &fenv, assembly_script_address_from_public_key),
+                "assembly_script_address_from_public_key_bytes" => Function::new_typed_with_env(store, &fenv, assembly_script_address_from_public_key_bytes),
+                "assembly_script_public_key_from_string" => Function::new_typed_with_env(store, &fenv, assembly_script_public_key_from_string),
                 "assembly_script_validate_address" => Function::new_typed_with_env(store, &fenv, assembly_script_validate_address),
                 "assembly_script_unsafe_random" => Function::new_typed_with_env(store, &fenv, assembly_script_unsafe_random),
+                "assembly_script_get_random_seed" => Function::new_typed_with_env(store, &fenv, assembly_script_get_random_seed),
+                "assembly_script_random_bytes" => Function::new_typed_with_env(store, &fenv, assembly_script_random_bytes),
                 "assembly_script_get_call_coins" => Function::new_typed_with_env(store, &fenv, assembly_script_get_call_coins),
+                "assembly_script_get_spent_coins" => Function::new_typed_with_env(store, &fenv, assembly_script_get_spent_coins),
                 "assembly_script_get_time" => Function::new_typed_with_env(store, &fenv, assembly_script_get_time),
                 "assembly_script_send_message" => Function::new_typed_with_env(store, &fenv, assembly_script_send_message),
+                "assembly_script_send_async_message" => Function::new_typed_with_env(store, &fenv, assembly_script_send_async_message),
+                "assembly_script_deferred_call_register" => Function::new_typed_with_env(store, &fenv, assembly_script_deferred_call_register),
+                "assembly_script_deferred_call_exists" => Function::new_typed_with_env(store, &fenv, assembly_script_deferred_call_exists),
+                "assembly_script_deferred_call_cancel" => Function::new_typed_with_env(store, &fenv, assembly_script_deferred_call_cancel),
+                "assembly_script_deferred_call_quote" => Function::new_typed_with_env(store, &fenv, assembly_script_deferred_call_quote),
                 "assembly_script_get_origin_operation_id" => Function::new_typed_with_env(store, &fenv, assembly_script_get_origin_operation_id),
                 "assembly_script_get_current_period" => Function::new_typed_with_env(store, &fenv, assembly_script_get_current_period),
                 "assembly_script_get_current_thread" => Function::new_typed_with_env(store, &fenv, assembly_script_get_current_thread),
+                "assembly_script_get_current_slot" => Function::new_typed_with_env(store, &fenv, assembly_script_get_current_slot),
+                "assembly_script_slot_to_timestamp" => Function::new_typed_with_env(store, &fenv, assembly_script_slot_to_timestamp),
+                "assembly_script_timestamp_to_slot" => Function::new_typed_with_env(store, &fenv, assembly_script_timestamp_to_slot),
                 "assembly_script_set_bytecode" => Function::new_typed_with_env(store, &fenv, assembly_script_set_bytecode),
                 "assembly_script_set_bytecode_for" => Function::new_typed_with_env(store, &fenv, assembly_script_set_bytecode_for),
                 "assembly_script_get_op_keys" => Function::new_typed_with_env(store, &fenv, assembly_script_get_op_keys),
                 "assembly_script_get_op_keys_prefix" => Function::new_typed_with_env(store, &fenv, assembly_script_get_op_keys_prefix),
                 "assembly_script_get_keys" => Function::new_typed_with_env(store, &fenv, assembly_script_get_keys),
                 "assembly_script_get_keys_for" => Function::new_typed_with_env(store, &fenv, assembly_script_get_keys_for),
+                "assembly_script_get_keys_paged" => Function::new_typed_with_env(store, &fenv, assembly_script_get_keys_paged),
+                "assembly_script_get_keys_values" => Function::new_typed_with_env(store, &fenv, assembly_script_get_keys_values),
                 "assembly_script_has_op_key" => Function::new_typed_with_env(store, &fenv, assembly_script_has_op_key),
                 "assembly_script_get_op_data" => Function::new_typed_with_env(store, &fenv, assembly_script_get_op_data),
                 "assembly_script_get_bytecode" => Function::new_typed_with_env(store, &fenv, assembly_script_get_bytecode),
                 "assembly_script_get_bytecode_for" => Function::new_typed_with_env(store, &fenv, assembly_script_get_bytecode_for),
                 "assembly_script_local_call" => Function::new_typed_with_env(store, &fenv, assembly_script_local_call),
+                "assembly_script_local_call_self" => Function::new_typed_with_env(store, &fenv, assembly_script_local_call_self),
                 "assembly_script_local_execution" => Function::new_typed_with_env(store, &fenv, assembly_script_local_execution),
+                "assembly_script_local_execution_with_coins" => Function::new_typed_with_env(store, &fenv, assembly_script_local_execution_with_coins),
                 "assembly_script_caller_has_write_access" => Function::new_typed_with_env(store, &fenv, assembly_script_caller_has_write_access),
                 "assembly_script_function_exists" => Function::new_typed_with_env(store, &fenv, assembly_script_function_exists),
                 "assembly_script_chain_id" => Function::new_typed_with_env(store, &fenv, assembly_script_chain_id),
+                "assembly_script_runtime_version" => Function::new_typed_with_env(store, &fenv, assembly_script_runtime_version),
             },
         };
 
+        for digest in DIGESTS {
+            imports.define("massa", digest.as_abi, digest_abi(store, &fenv, digest));
+        }
+
+        let mut imports = self.filter_imports(store, &fenv, imports);
+        imports.define(
+            DEADLINE_CHECK_NAMESPACE,
+            DEADLINE_CHECK_NAME,
+            Function::new_typed_with_env(store, &fenv, assembly_script_check_deadline),
+        );
+        self.env
+            .get_config()
+            .host_extensions
+            .define(store, &mut imports);
         (imports, fenv)
     }
+
+    /// Replace the imports refused by the ABI filter of the execution by
+    /// stubs failing when called. With `ForbiddenAbiMode::LinkError`, they
+    /// are refused by `check_forbidden_imports` instead.
+    fn filter_imports(
+        &self,
+        store: &mut Store,
+        fenv: &FunctionEnv<ASEnv>,
+        imports: Imports,
+    ) -> Imports {
+        let filter = &self.env.get_config().abi_filter;
+        if filter.is_empty() || filter.mode == ForbiddenAbiMode::LinkError {
+            return imports;
+        }
+        let mut filtered = Imports::new();
+        for (namespace, name, import) in imports.iter() {
+            let abi_name = abi_name(namespace, name);
+            match import {
+                Extern::Function(function) if !filter.is_allowed(&abi_name) => {
+                    let ty = function.ty(store);
+                    filtered.define(namespace, name, forbidden_abi(store, fenv, ty, abi_name));
+                }
+                import => filtered.define(namespace, name, import.clone()),
+            }
+        }
+        filtered
+    }
+
+    /// Refuse the modules importing an ABI refused by the ABI filter of the
+    /// execution, with `ForbiddenAbiMode::LinkError`
+    fn check_forbidden_imports(&self) -> Result<()> {
+        let RuntimeConfig {
+            abi_filter: filter,
+            host_extensions,
+            ..
+        } = self.env.get_config();
+        if filter.mode != ForbiddenAbiMode::LinkError {
+            return Ok(());
+        }
+        for import in self.module.imports() {
+            if host_extensions.contains(import.module(), import.name())
+                || import.module() == DEADLINE_CHECK_NAMESPACE
+            {
+                continue;
+            }
+            let abi_name = abi_name(import.module(), import.name());
+            if !filter.is_allowed(&abi_name) {
+                bail!(
+                    "Failed to link \"{}\".\"{}\": {}",
+                    import.module(),
+                    import.name(),
+                    ABIError::ForbiddenAbi(abi_name)
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// ABI computing `digest`, see `assembly_script_digest`
+fn digest_abi(
+    store: &mut Store,
+    fenv: &FunctionEnv<ASEnv>,
+    digest: &'static NativeDigest,
+) -> Function {
+    let ty = FunctionType::new(vec![Type::I32], vec![Type::I32]);
+    Function::new_with_env(
+        store,
+        fenv,
+        ty,
+        move |ctx: FunctionEnvMut<ASEnv>, args: &[Value]| {
+            let ptr = assembly_script_digest(ctx, digest, args[0].unwrap_i32())
+                .map_err(|err| RuntimeError::user(Box::new(err)))?;
+            Ok(vec![Value::I32(ptr)])
+        },
+    )
+}
+
+/// Name under which an import is charged and filtered, `Date.now` from "env"
+/// being `assembly_script_date_now`
+pub(crate) fn abi_name(namespace: &str, name: &str) -> String {
+    match namespace {
+        "env" => format!("assembly_script_{}", name.to_lowercase().replace('.', "_")),
+        _ => name.to_string(),
+    }
+}
+
+/// Stand-in of a refused ABI, charged like the ABI before failing with
+/// `ABIError::ForbiddenAbi`
+fn forbidden_abi(
+    store: &mut Store,
+    fenv: &FunctionEnv<ASEnv>,
+    ty: FunctionType,
+    abi_name: String,
+) -> Function {
+    Function::new_with_env(
+        store,
+        fenv,
+        ty,
+        move |mut ctx: FunctionEnvMut<ASEnv>, _: &[Value]| {
+            let charge = |ctx: &mut FunctionEnvMut<ASEnv>| -> ABIResult<()> {
+                let env = get_env(ctx)?;
+                if env.get_gas_costs().abi_costs.contains_key(&abi_name) {
+                    sub_remaining_gas_abi(&env, ctx, &abi_name)?;
+                }
+                Ok(())
+            };
+            let err = match charge(&mut ctx) {
+                Ok(()) => ABIError::ForbiddenAbi(abi_name.clone()),
+                Err(err) => err,
+            };
+            Err(RuntimeError::user(Box::new(err)))
+        },
+    )
 }
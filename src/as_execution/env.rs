@@ -1,15 +1,36 @@
-use super::{abi_bail, ABIResult};
+use super::{abi_bail, ABIError, ABIResult, ASModule};
+use crate::execution::{AccessCache, CallContext, ConversionCache};
+use crate::observer::ExecutionEvent;
+use crate::panic_guard::AbiScope;
+use crate::profiling::AbiCall;
+#[cfg(feature = "profiling")]
+use crate::profiling::Profiler;
 use crate::types::Interface;
 
 #[cfg(feature = "execution-trace")]
 use crate::types::AbiTrace;
 
-use crate::GasCosts;
+use crate::{AbortInfo, EventLimits, GasCosts, RuntimeConfig, StorageLimits};
 use std::{
     collections::HashMap,
     sync::{atomic::AtomicBool, Arc},
 };
-use wasmer::{AsStoreMut, Global};
+use wasmer::{AsStoreMut, AsStoreRef, FunctionEnvMut, Global, Memory};
+
+/// Class id of `ArrayBuffer` in the AssemblyScript runtime
+const ARRAY_BUFFER_ID: i32 = 1;
+
+/// Parts of `ASEnv` that do not change during an execution, behind a single
+/// `Arc` so that the copies of the environment made by the ABIs are cheap.
+struct SharedEnv {
+    /// Exposed interface functions used by the ABIs and implemented
+    /// externally. In `massa/massa-execution-worker` for example.
+    interface: Box<dyn Interface>,
+    /// Gas costs of different execution operations.
+    gas_costs: GasCosts,
+    /// Position of the current execution in the call tree.
+    call_context: CallContext,
+}
 
 /// AssemblyScript execution environment.
 ///
@@ -23,37 +44,341 @@ pub struct ASEnv {
     /// ABIs should be disabled in the AssemblyScript `start` function.
     /// It prevents non-deterministic behaviour in the intances creation.
     pub abi_enabled: Arc<AtomicBool>,
-    /// Exposed interface functions used by the ABIs and implemented
-    /// externally. In `massa/massa-execution-worker` for example.
-    pub interface: Box<dyn Interface>,
+    /// Interface, gas costs and call context of the execution.
+    shared: Arc<SharedEnv>,
     /// Remaining metering points in the current execution context.
     pub remaining_points: Option<Global>,
     /// Cumulated exhausted points in the current execution context.
     pub exhausted_points: Option<Global>,
-    /// Gas costs of different execution operations.
-    gas_costs: GasCosts,
     /// Initially added for gas calibration but unused at the moment.
     param_size_map: HashMap<String, Option<Global>>,
+    /// Gas available to the current execution after the instance creation.
+    gas_limit: u64,
+    /// Gas credited back by the storage refunds of the call tree when the
+    /// current execution started.
+    refunded_at_start: u64,
+    /// Coins sent by the nested calls of the current execution that
+    /// succeeded.
+    spent_coins: u64,
+    /// Compiled module of the current execution, reused by the calls the
+    /// module makes to itself.
+    current_module: Option<ASModule>,
+    /// Access rights of the current execution already asked to the interface.
+    access_cache: AccessCache,
+    /// Conversions of public keys and addresses of the current execution
+    /// already asked to the interface.
+    conversions: ConversionCache,
+    /// Arguments of the `abort` of the current execution, if it aborted.
+    abort_info: Option<AbortInfo>,
     #[cfg(feature = "execution-trace")]
     pub trace: Vec<AbiTrace>,
 }
 
 impl ASEnv {
-    pub fn new(interface: &dyn Interface, gas_costs: GasCosts) -> Self {
+    pub fn new(interface: &dyn Interface, gas_costs: GasCosts, call_context: CallContext) -> Self {
         Self {
             ffi_env: Default::default(),
             abi_enabled: Arc::new(AtomicBool::new(false)),
-            gas_costs,
-            interface: interface.clone_box(),
+            shared: Arc::new(SharedEnv {
+                interface: interface.clone_box(),
+                gas_costs,
+                call_context,
+            }),
             remaining_points: None,
             exhausted_points: None,
             param_size_map: Default::default(),
+            gas_limit: 0,
+            refunded_at_start: 0,
+            spent_coins: 0,
+            current_module: None,
+            access_cache: AccessCache::default(),
+            conversions: ConversionCache::default(),
+            abort_info: None,
             #[cfg(feature = "execution-trace")]
             trace: Default::default(),
         }
     }
     pub fn get_interface(&self) -> Box<dyn Interface> {
-        self.interface.clone()
+        self.shared.interface.clone()
+    }
+    /// Interface of the execution, borrowed rather than cloned like with
+    /// `get_interface`
+    pub(crate) fn interface(&self) -> &dyn Interface {
+        &*self.shared.interface
+    }
+    pub(crate) fn get_call_context(&self) -> &CallContext {
+        &self.shared.call_context
+    }
+    /// Settings of the call tree
+    pub(crate) fn get_config(&self) -> &RuntimeConfig {
+        &self.shared.call_context.config
+    }
+    pub fn get_storage_limits(&self) -> StorageLimits {
+        self.get_config().storage_limits
+    }
+    pub fn get_event_limits(&self) -> EventLimits {
+        self.get_config().event_limits
+    }
+    pub(crate) fn set_gas_limit(&mut self, gas_limit: u64) {
+        self.gas_limit = gas_limit;
+        self.refunded_at_start = self.shared.call_context.refunded_gas();
+    }
+    /// Account for `len` bytes about to be copied to the guest memory, see
+    /// `RuntimeConfig::max_host_data_len`
+    pub(crate) fn record_host_data(&self, len: usize) -> ABIResult<()> {
+        self.shared
+            .call_context
+            .record_host_data(len, self.get_config().max_host_data_len)
+    }
+    pub(crate) fn get_spent_coins(&self) -> u64 {
+        self.spent_coins
+    }
+    /// Account for the coins sent by a nested call, once it succeeded
+    pub(crate) fn add_spent_coins(&mut self, coins: u64) {
+        self.spent_coins = self.spent_coins.saturating_add(coins);
+    }
+    pub(crate) fn get_current_module(&self) -> Option<&ASModule> {
+        self.current_module.as_ref()
+    }
+    pub(crate) fn set_current_module(&mut self, module: ASModule) {
+        self.current_module = Some(module);
+    }
+    pub(crate) fn clear_current_module(&mut self) {
+        self.current_module = None;
+    }
+    pub(crate) fn set_abort_info(&mut self, info: AbortInfo) {
+        self.abort_info = Some(info);
+    }
+    /// Arguments of the `abort` that ended the execution, if any
+    pub(crate) fn take_abort_info(&mut self) -> Option<AbortInfo> {
+        self.abort_info.take()
+    }
+    /// Whether the caller has write access, asked once per execution
+    pub(crate) fn caller_has_write_access(&mut self) -> ABIResult<bool> {
+        Ok(self
+            .access_cache
+            .caller_has_write_access(&*self.shared.interface)?)
+    }
+    /// Whether `address` is a user address, asked once per execution
+    pub(crate) fn is_address_eoa(&mut self, address: &str) -> ABIResult<bool> {
+        Ok(self
+            .access_cache
+            .is_address_eoa(&*self.shared.interface, address)?)
+    }
+    /// Address of the public key `public_key`, asked once per execution
+    pub(crate) fn address_from_public_key(&mut self, public_key: &str) -> ABIResult<String> {
+        Ok(self
+            .conversions
+            .address_from_public_key(&*self.shared.interface, public_key)?)
+    }
+    /// Address of the serialized public key `public_key`, asked once per
+    /// execution
+    pub(crate) fn address_from_public_key_bytes(&mut self, public_key: &[u8]) -> ABIResult<String> {
+        Ok(self
+            .conversions
+            .address_from_public_key_bytes(&*self.shared.interface, public_key)?)
+    }
+    /// Serialized bytes of the public key `public_key`, asked once per
+    /// execution
+    pub(crate) fn public_key_from_string(&mut self, public_key: &str) -> ABIResult<Vec<u8>> {
+        Ok(self
+            .conversions
+            .public_key_from_string(&*self.shared.interface, public_key)?)
+    }
+    /// Whether `address` is valid, asked once per execution
+    pub(crate) fn validate_address(&mut self, address: &str) -> ABIResult<bool> {
+        Ok(self
+            .conversions
+            .validate_address(&*self.shared.interface, address)?)
+    }
+    /// Account for an event of `size` bytes in the budget shared by the
+    /// whole call tree, `data` becoming its last event. Returns the index of
+    /// the event in the call tree.
+    pub(crate) fn record_event(&self, size: usize, data: &[u8]) -> ABIResult<u64> {
+        self.shared
+            .call_context
+            .events
+            .lock()
+            .record(&self.get_config().event_limits, size, data)
+    }
+    /// Read the buffer at `offset` in the guest memory for the ABI `abi`, in a
+    /// single copy out of the memory
+    pub(crate) fn read_buffer(
+        &self,
+        store: &impl AsStoreRef,
+        abi: &str,
+        offset: i32,
+    ) -> ABIResult<Vec<u8>> {
+        let (memory, len) =
+            self.check_read(store, abi, offset, self.get_config().max_abi_read_len)?;
+        let mut buffer = vec![0u8; len as usize];
+        memory
+            .view(store)
+            .read(offset as u32 as u64, &mut buffer)
+            .map_err(|err| ABIError::Error(err.into()))?;
+        self.observe_argument(abi, buffer.len());
+        Ok(buffer)
+    }
+    /// Read the value returned by `function` at `offset`, at most
+    /// `RuntimeConfig::max_return_size` bytes charged
+    /// `GasCosts::return_byte_cost`
+    /// each before they are copied
+    pub(crate) fn read_return_value(
+        &self,
+        store: &mut impl AsStoreMut,
+        function: &str,
+        offset: i32,
+    ) -> ABIResult<Vec<u8>> {
+        let gas_costs = &self.shared.gas_costs;
+        let max_return_size = self.get_config().max_return_size;
+        let (memory, len) = match self.check_read(store, function, offset, max_return_size) {
+            Err(ABIError::ReadTooLarge { len, max, .. }) => {
+                return Err(ABIError::ReturnTooLarge { len, max })
+            }
+            checked => checked?,
+        };
+        charge(
+            self,
+            store,
+            function,
+            0,
+            gas_costs.return_byte_cost,
+            len as usize,
+        )?;
+        let mut buffer = vec![0u8; len as usize];
+        memory
+            .view(store)
+            .read(offset as u32 as u64, &mut buffer)
+            .map_err(|err| ABIError::Error(err.into()))?;
+        Ok(buffer)
+    }
+    /// Allocate an `ArrayBuffer` of the length of `data` with `__new` and
+    /// write `data` in it in a single copy, for the ABI `abi`
+    pub(crate) fn write_buffer(
+        &self,
+        store: &mut impl AsStoreMut,
+        abi: &str,
+        data: &[u8],
+    ) -> ABIResult<i32> {
+        let (Some(fn_new), Some(memory)) =
+            (self.ffi_env.fn_new.as_ref(), self.ffi_env.memory.as_ref())
+        else {
+            abi_bail!("AssemblyScript __new or memory is missing from the environment");
+        };
+        let Ok(len) = i32::try_from(data.len()) else {
+            abi_bail!(format!(
+                "Buffer of {} bytes cannot be allocated",
+                data.len()
+            ));
+        };
+        let offset = fn_new.call(store, len, ARRAY_BUFFER_ID)?;
+        // the memory may have grown during the allocation
+        let view = memory.view(store);
+        let mem_size = view.data_size();
+        let start = offset as u32 as u64;
+        if start + data.len() as u64 > mem_size {
+            return Err(ABIError::InvalidPointer {
+                abi: abi.to_string(),
+                offset: start,
+                len: data.len() as u64,
+                mem_size,
+            });
+        }
+        view.write(start, data)
+            .map_err(|err| ABIError::Error(err.into()))?;
+        Ok(offset)
+    }
+    /// Read the string at `offset` in the guest memory for the ABI `abi`.
+    ///
+    /// Its length is checked against `RuntimeConfig::max_string_len` and its
+    /// UTF-16 code units charged `GasCosts::string_code_unit_cost` each before
+    /// it is converted. The unpaired surrogates are replaced by `U+FFFD`, the
+    /// AssemblyScript strings not being checked by the guest.
+    pub(crate) fn read_string(
+        &self,
+        store: &mut impl AsStoreMut,
+        abi: &str,
+        offset: i32,
+    ) -> ABIResult<String> {
+        let (memory, len) =
+            self.check_read(store, abi, offset, self.get_config().max_abi_read_len)?;
+        let units = len / 2;
+        let gas_costs = &self.shared.gas_costs;
+        let max_string_len = self.get_config().max_string_len;
+        if units > max_string_len as u64 {
+            return Err(ABIError::StringTooLong {
+                abi: abi.to_string(),
+                len: units,
+                max: max_string_len,
+            });
+        }
+        charge(
+            self,
+            store,
+            abi,
+            0,
+            gas_costs.string_code_unit_cost,
+            units as usize,
+        )?;
+        let mut bytes = vec![0u8; len as usize];
+        memory
+            .view(store)
+            .read(offset as u32 as u64, &mut bytes)
+            .map_err(|err| ABIError::Error(err.into()))?;
+        self.observe_argument(abi, bytes.len());
+        Ok(decode_utf16_lossy(&bytes))
+    }
+    /// Report an argument of `len` bytes read by `abi` to the observer of the
+    /// call tree
+    fn observe_argument(&self, abi: &str, len: usize) {
+        #[cfg(feature = "tracing")]
+        tracing::trace!(abi, len, "abi argument");
+        self.shared
+            .call_context
+            .observe(|| ExecutionEvent::AbiArgument {
+                abi: abi.to_string(),
+                len,
+            });
+    }
+    /// Check that the AssemblyScript object at `offset`, whose length in bytes
+    /// is stored in the 4 bytes before it, is within the guest memory and at
+    /// most `max` bytes long, returns the memory and that length
+    fn check_read(
+        &self,
+        store: &impl AsStoreRef,
+        abi: &str,
+        offset: i32,
+        max: usize,
+    ) -> ABIResult<(&Memory, u64)> {
+        let Some(memory) = self.ffi_env.memory.as_ref() else {
+            abi_bail!("AssemblyScript memory is missing from the environment");
+        };
+        let view = memory.view(store);
+        let mem_size = view.data_size();
+        let offset = offset as u32 as u64;
+        let invalid = |len| ABIError::InvalidPointer {
+            abi: abi.to_string(),
+            offset,
+            len,
+            mem_size,
+        };
+        if offset < 4 || offset > mem_size {
+            return Err(invalid(4));
+        }
+        let mut len = [0u8; 4];
+        view.read(offset - 4, &mut len).map_err(|_| invalid(4))?;
+        let len = u32::from_le_bytes(len) as u64;
+        if len > max as u64 {
+            return Err(ABIError::ReadTooLarge {
+                abi: abi.to_string(),
+                len,
+                max,
+            });
+        }
+        if offset + len > mem_size {
+            return Err(invalid(len));
+        }
+        Ok((memory, len))
     }
     pub fn get_ffi_env(&self) -> &as_ffi_bindings::Env {
         &self.ffi_env
@@ -73,8 +398,18 @@ impl Metered for ASEnv {
     fn get_gc_param(&self, name: &str) -> Option<&Global> {
         self.param_size_map.get(name)?.as_ref()
     }
-    fn get_gas_costs(&self) -> GasCosts {
-        self.gas_costs.clone()
+    fn get_gas_costs(&self) -> &GasCosts {
+        &self.shared.gas_costs
+    }
+    fn gas_calibration(&self) -> bool {
+        self.get_config().gas_calibration
+    }
+    fn get_call_context(&self) -> &CallContext {
+        &self.shared.call_context
+    }
+    #[cfg(feature = "profiling")]
+    fn get_profiler(&self) -> &Profiler {
+        &self.shared.call_context.profiler
     }
 }
 
@@ -85,7 +420,13 @@ pub(crate) trait Metered {
     fn get_exhausted_points(&self) -> Option<&Global>;
     fn get_remaining_points(&self) -> Option<&Global>;
     fn get_gc_param(&self, name: &str) -> Option<&Global>;
-    fn get_gas_costs(&self) -> GasCosts;
+    fn get_gas_costs(&self) -> &GasCosts;
+    /// Whether the execution calibrates the gas costs, it is then not
+    /// metered
+    fn gas_calibration(&self) -> bool;
+    fn get_call_context(&self) -> &CallContext;
+    #[cfg(feature = "profiling")]
+    fn get_profiler(&self) -> &Profiler;
 }
 
 /// Get remaining metering points.
@@ -95,7 +436,7 @@ pub(crate) fn get_remaining_points(
     env: &impl Metered,
     store: &mut impl AsStoreMut,
 ) -> ABIResult<u64> {
-    if cfg!(feature = "gas_calibration") {
+    if env.gas_calibration() {
         Ok(u64::MAX)
     } else {
         match env.get_exhausted_points().as_ref() {
@@ -128,7 +469,7 @@ pub(crate) fn set_remaining_points(
     store: &mut impl AsStoreMut,
     points: u64,
 ) -> ABIResult<()> {
-    if cfg!(not(feature = "gas_calibration")) {
+    if !env.gas_calibration() {
         match env.get_remaining_points().as_ref() {
             Some(remaining_points) => {
                 if remaining_points.set(store, points.into()).is_err() {
@@ -149,33 +490,240 @@ pub(crate) fn set_remaining_points(
     Ok(())
 }
 
-pub(crate) fn sub_remaining_gas(
+fn sub_remaining_gas(
     env: &impl Metered,
     store: &mut impl AsStoreMut,
     gas: u64,
+    at_function: &str,
 ) -> ABIResult<()> {
-    if cfg!(feature = "gas_calibration") {
+    if env.gas_calibration() {
         return Ok(());
     }
     let remaining_gas = get_remaining_points(env, store)?;
     if let Some(remaining_gas) = remaining_gas.checked_sub(gas) {
         set_remaining_points(env, store, remaining_gas)?;
+        #[cfg(feature = "profiling")]
+        env.get_profiler().record_gas(at_function, gas);
     } else {
-        abi_bail!("Out of gas")
+        return Err(ABIError::OutOfGas {
+            at_function: at_function.to_string(),
+        });
     }
     Ok(())
 }
 
-pub(crate) fn sub_remaining_gas_abi(
+/// Add metering points, the inverse of `sub_remaining_gas`
+pub(crate) fn add_remaining_points(
+    env: &impl Metered,
+    store: &mut impl AsStoreMut,
+    points: u64,
+) -> ABIResult<()> {
+    if env.gas_calibration() {
+        return Ok(());
+    }
+    let remaining_gas = get_remaining_points(env, store)?;
+    set_remaining_points(env, store, remaining_gas.saturating_add(points))
+}
+
+/// Credit back `amount` of gas for freed storage.
+///
+/// The refunds of the whole call tree are capped to
+/// `max_storage_refund_percent` of the gas spent by the current execution,
+/// see `CallContext::storage_refund`.
+pub(crate) fn refund_storage_gas(ctx: &mut FunctionEnvMut<ASEnv>, amount: u64) -> ABIResult<()> {
+    if ctx.data().gas_calibration() || amount == 0 {
+        return Ok(());
+    }
+    let (env, mut store) = ctx.data_and_store_mut();
+    let call_context = &env.shared.call_context;
+    let remaining_gas = get_remaining_points(&*env, &mut store)?;
+    // the refunds since the execution started were added to its gas
+    let gas_spent = env.gas_limit.saturating_sub(remaining_gas).saturating_add(
+        call_context
+            .refunded_gas()
+            .saturating_sub(env.refunded_at_start),
+    );
+    let refund = call_context.storage_refund(
+        amount,
+        gas_spent,
+        env.shared.gas_costs.max_storage_refund_percent,
+    );
+    add_remaining_points(&*env, &mut store, refund)
+}
+
+/// Charge to `abi_name` the flat cost of the ABI `cost_name`, whose
+/// host-side work it does too
+pub(crate) fn sub_remaining_gas_abi_of(
     env: &impl Metered,
     store: &mut impl AsStoreMut,
     abi_name: &str,
+    cost_name: &str,
 ) -> ABIResult<()> {
-    sub_remaining_gas(
-        env,
-        store,
-        *env.get_gas_costs().abi_costs.get(abi_name).ok_or_else(|| {
-            wasmer::RuntimeError::new(format!("Failed to get gas for {} ABI", abi_name))
-        })?,
-    )
+    let cost = *env
+        .get_gas_costs()
+        .abi_costs
+        .get(cost_name)
+        .ok_or_else(|| {
+            wasmer::RuntimeError::new(format!("Failed to get gas for {} ABI", cost_name))
+        })?;
+    charge(env, store, abi_name, cost, 0, 0)
+}
+
+/// Charge the flat cost of an ABI.
+///
+/// The returned `AbiCall` and `AbiScope` must be kept until the ABI returns:
+/// the call is profiled when they are dropped, and a panic of the ABI is
+/// reported with its name.
+pub(crate) fn sub_remaining_gas_abi<'a>(
+    env: &impl Metered,
+    store: &mut impl AsStoreMut,
+    abi_name: &'a str,
+) -> ABIResult<(AbiCall, AbiScope<'a>)> {
+    let scope = AbiScope::new(abi_name);
+    #[cfg(feature = "profiling")]
+    let call = env.get_profiler().start_call(abi_name);
+    #[cfg(not(feature = "profiling"))]
+    let call = AbiCall;
+    let cost = *env.get_gas_costs().abi_costs.get(abi_name).ok_or_else(|| {
+        wasmer::RuntimeError::new(format!("Failed to get gas for {} ABI", abi_name))
+    })?;
+    charge(env, store, abi_name, cost, 0, 0)?;
+    #[cfg(feature = "tracing")]
+    tracing::trace!(abi = abi_name, "abi call");
+    env.get_call_context()
+        .record_read_only(|recorder| recorder.abi_called(abi_name));
+    env.get_call_context()
+        .observe(|| ExecutionEvent::AbiCalled {
+            abi: abi_name.to_string(),
+            remaining_gas: get_remaining_points(env, store).unwrap_or_default(),
+        });
+    Ok((call, scope))
+}
+
+/// Charge the length dependent cost of an ABI, found under
+/// `<abi_name>_per_byte` in the gas costs, for an input of `len` bytes.
+pub(crate) fn sub_remaining_gas_abi_per_byte(
+    env: &impl Metered,
+    store: &mut impl AsStoreMut,
+    abi_name: &str,
+    len: usize,
+) -> ABIResult<()> {
+    sub_remaining_gas_abi_scaled(env, store, abi_name, "per_byte", len)
+}
+
+/// Charge the cost of an ABI found under `<abi_name>_per_entry` in the gas
+/// costs for each of the `count` entries it handles.
+pub(crate) fn sub_remaining_gas_abi_per_entry(
+    env: &impl Metered,
+    store: &mut impl AsStoreMut,
+    abi_name: &str,
+    count: usize,
+) -> ABIResult<()> {
+    sub_remaining_gas_abi_scaled(env, store, abi_name, "per_entry", count)
+}
+
+/// Charge the cost of an ABI found under `<abi_name>_per_comparison` in the
+/// gas costs for each of the `count` comparisons of a sort.
+pub(crate) fn sub_remaining_gas_abi_per_comparison(
+    env: &impl Metered,
+    store: &mut impl AsStoreMut,
+    abi_name: &str,
+    count: usize,
+) -> ABIResult<()> {
+    sub_remaining_gas_abi_scaled(env, store, abi_name, "per_comparison", count)
+}
+
+fn sub_remaining_gas_abi_scaled(
+    env: &impl Metered,
+    store: &mut impl AsStoreMut,
+    abi_name: &str,
+    unit: &str,
+    count: usize,
+) -> ABIResult<()> {
+    let cost_name = format!("{}_{}", abi_name, unit);
+    match env.get_gas_costs().unit_cost(&cost_name) {
+        Some(cost) => charge(env, store, abi_name, 0, cost, count),
+        None => abi_bail!(format!("gas cost {} is missing", cost_name)),
+    }
+}
+
+/// Charge `base + per_unit * units` to the ABI `abi_name`.
+///
+/// This is the only way an ABI pays for its host-side work, its `GasCosts`
+/// prices going through `sub_remaining_gas_abi` and its variants. The guest
+/// instructions marshalling the arguments and the results, `__new` included,
+/// are charged by the metering like any other instruction, and the gas of the
+/// nested calls is the one they used themselves.
+///
+/// Fails with `ABIError::GasOverflow` when the cost does not fit in 64 bits,
+/// the lengths being chosen by the guest.
+pub(crate) fn charge(
+    env: &impl Metered,
+    store: &mut impl AsStoreMut,
+    abi_name: &str,
+    base: u64,
+    per_unit: u64,
+    units: usize,
+) -> ABIResult<()> {
+    let Some(gas) = checked_cost(base, per_unit, units) else {
+        return Err(ABIError::GasOverflow(abi_name.to_string()));
+    };
+    sub_remaining_gas(env, store, gas, abi_name)
+}
+
+/// String of the little-endian UTF-16 `bytes`, with the unpaired surrogates
+/// and a trailing odd byte replaced by `U+FFFD`
+pub(crate) fn decode_utf16_lossy(bytes: &[u8]) -> String {
+    let units = bytes
+        .chunks_exact(2)
+        .map(|unit| u16::from_le_bytes([unit[0], unit[1]]));
+    let mut string: String = char::decode_utf16(units)
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect();
+    if bytes.len() % 2 != 0 {
+        string.push(char::REPLACEMENT_CHARACTER);
+    }
+    string
+}
+
+/// `base + per_unit * units`, if it fits in 64 bits
+pub(crate) fn checked_cost(base: u64, per_unit: u64, units: usize) -> Option<u64> {
+    per_unit
+        .checked_mul(u64::try_from(units).ok()?)?
+        .checked_add(base)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checked_cost() {
+        assert_eq!(checked_cost(10, 3, 4), Some(22));
+        assert_eq!(checked_cost(u64::MAX, 0, usize::MAX), Some(u64::MAX));
+        // lengths around the largest one that can be charged
+        let per_byte = 1_000;
+        let max_len = (u64::MAX / per_byte) as usize;
+        assert_eq!(
+            checked_cost(0, per_byte, max_len),
+            Some(max_len as u64 * per_byte)
+        );
+        assert_eq!(checked_cost(0, per_byte, max_len + 1), None);
+        assert_eq!(checked_cost(u64::MAX / 2, per_byte, max_len), None);
+        assert_eq!(checked_cost(1, u64::MAX, 1), None);
+    }
+
+    #[test]
+    fn test_decode_utf16_lossy() {
+        let utf16 = |s: &str| -> Vec<u8> { s.encode_utf16().flat_map(u16::to_le_bytes).collect() };
+        assert_eq!(decode_utf16_lossy(&utf16("sc.ts")), "sc.ts");
+        assert_eq!(decode_utf16_lossy(&utf16("🦀 é")), "🦀 é");
+        assert_eq!(decode_utf16_lossy(&[]), "");
+        // lone high and low surrogates, then an odd trailing byte
+        assert_eq!(
+            decode_utf16_lossy(&[0x00, 0xd8, b'a', 0, 0x00, 0xdc]),
+            "\u{fffd}a\u{fffd}"
+        );
+        assert_eq!(decode_utf16_lossy(&[b'a', 0, b'b']), "a\u{fffd}");
+    }
 }
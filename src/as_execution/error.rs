@@ -1,4 +1,4 @@
-use crate::VMError;
+use crate::{AbortInfo, CallFrame, MerkleProofError, Response, StorageLimitError, VMError};
 use displaydoc::Display;
 use thiserror::Error;
 
@@ -11,9 +11,252 @@ pub enum ABIError {
     /// Wasmer runtime error: {0}
     RuntimeError(#[from] wasmer::RuntimeError),
     /// Serde error: {0}
+    #[cfg(feature = "json")]
     SerdeError(#[from] serde_json::Error),
     /// VM error: {0}
     VMError(#[from] VMError),
+    /// Maximum call depth reached: {0}
+    DepthLimitReached(u32),
+    /// Incompatible artifact: {0}
+    IncompatibleArtifact(String),
+    /// Not enough gas, limit reached at: {at_function}
+    OutOfGas { at_function: String },
+    /// Re-entrant call to {address}:{function} denied
+    ReentrancyDenied { address: String, function: String },
+    /// Call to {address}:{function} exhausted its gas limit of {max_gas}
+    GasStipendExhausted {
+        address: String,
+        function: String,
+        max_gas: u64,
+    },
+    /// Storage limit error: {0}
+    StorageLimit(#[from] StorageLimitError),
+    /// Invalid Merkle proof: {0}
+    MerkleProof(#[from] MerkleProofError),
+    /// Invalid bytecode: {0}
+    InvalidBytecode(String),
+    /// Forbidden WebAssembly feature: {0}
+    ForbiddenWasmFeature(&'static str),
+    /// Unsupported ABI version: the module requires version {required}, the runtime supports up to {supported}
+    UnsupportedAbiVersion { required: u32, supported: u32 },
+    /// Malformed ABI version declaration: {0}
+    MalformedAbiVersion(&'static str),
+    /// Mixed ABI namespaces: the module imports both the AssemblyScript ABI {legacy} and the wasmv1 ABI {wasmv1}
+    MixedAbiNamespaces { legacy: String, wasmv1: String },
+    /// Module limit {limit} exceeded: {value}, the configured maximum is {max}
+    ModuleLimitExceeded {
+        limit: &'static str,
+        value: usize,
+        max: usize,
+    },
+    /// Execution timeout: the maximum execution time was exceeded
+    ExecutionTimeout,
+    /// Compilation timeout: the compilation exceeded the maximum of {max_time:?}
+    CompilationTimeout { max_time: std::time::Duration },
+    /// Execution interrupted through its interrupt handle
+    Interrupted,
+    /// Event with {count} topics exceeds the maximum of {max} topics
+    TooManyEventTopics { count: usize, max: usize },
+    /// Maximum number of events per execution reached: {0}
+    TooManyEvents(usize),
+    /// Events of {size} bytes exceed the maximum of {max} bytes per execution
+    EventDataTooLarge { size: usize, max: usize },
+    /// Base58check input of {len} bytes exceeds the maximum of {max} bytes
+    Base58CheckInputTooLong { len: usize, max: usize },
+    /// Invalid base58check checksum
+    InvalidBase58CheckChecksum,
+    /// Invalid base58check string: {0}
+    InvalidBase58Check(String),
+    /// Constant-time comparison input of {len} bytes exceeds the maximum of {max} bytes
+    ConstantEqInputTooLong { len: usize, max: usize },
+    /// Random bytes request of {len} bytes exceeds the maximum of {max} bytes
+    RandomBytesTooLong { len: usize, max: usize },
+    /// Host data limit exceeded: copying {len} bytes would bring the data copied to the guest to {total} bytes, the maximum is {max}
+    HostDataLimitExceeded {
+        len: usize,
+        total: usize,
+        max: usize,
+    },
+    /// Invalid address {address}: {reason}
+    InvalidAddress { address: String, reason: String },
+    /// Invalid datastore cursor: {0}
+    InvalidCursor(String),
+    /// Invalid pointer in {abi}: {len} bytes at offset {offset} are out of the {mem_size} bytes of memory
+    InvalidPointer {
+        abi: String,
+        offset: u64,
+        len: u64,
+        mem_size: u64,
+    },
+    /// Read of {len} bytes in {abi} exceeds the maximum of {max} bytes
+    ReadTooLarge { abi: String, len: u64, max: usize },
+    /// Return value of {len} bytes exceeds the maximum of {max} bytes
+    ReturnTooLarge { len: u64, max: usize },
+    /// String of {len} UTF-16 code units in {abi} exceeds the maximum of {max} code units
+    StringTooLong { abi: String, len: u64, max: usize },
+    /// Memory limit exceeded: memory.grow above the maximum of {max_pages} pages
+    MemoryLimitExceeded { max_pages: u32 },
+    /// Stack overflow: the guest calls exceed the maximum depth of {max_depth}
+    StackOverflow { max_depth: u32 },
+    /// Write in read-only mode: {0} is not allowed
+    WriteInReadOnlyMode(String),
+    /// Forbidden ABI: {0} is not allowed in this execution
+    ForbiddenAbi(String),
+    /// Gas overflow in {0}: its cost does not fit in 64 bits
+    GasOverflow(String),
+    /// Runtime error: {0}
+    Aborted(String),
+    /// Internal error in {abi}: {message}
+    InternalError {
+        /// ABI that panicked
+        abi: String,
+        /// Message of the panic
+        message: String,
+    },
+    /// Failed to finish a successful call: {error}
+    FinishCallFailed {
+        error: anyhow::Error,
+        /// Response of the callee
+        response: Response,
+    },
+}
+
+impl ABIError {
+    /// Keep a nested out-of-gas typed when it reaches the caller
+    pub(crate) fn from_nested(err: VMError) -> Self {
+        match err {
+            VMError::OutOfGas { at_function, .. } => ABIError::OutOfGas { at_function },
+            err => ABIError::VMError(err),
+        }
+    }
+
+    /// Find the `ABIError` behind an execution error, if any
+    fn downcast(err: &anyhow::Error) -> Option<&ABIError> {
+        match err.downcast_ref::<ABIError>() {
+            Some(abi_error) => Some(abi_error),
+            None => err
+                .downcast_ref::<wasmer::RuntimeError>()?
+                .downcast_ref::<ABIError>(),
+        }
+    }
+
+    /// Name of the function where the gas was exhausted, if the error was
+    /// caused by an exhaustion
+    pub(crate) fn out_of_gas_at(err: &anyhow::Error) -> Option<&str> {
+        match Self::downcast(err)? {
+            ABIError::OutOfGas { at_function } => Some(at_function),
+            _ => None,
+        }
+    }
+
+    /// Error of a `finish_call` failing after a successful nested call, with
+    /// the response of the callee
+    pub(crate) fn finish_call_failure(err: &anyhow::Error) -> Option<(&anyhow::Error, &Response)> {
+        match Self::downcast(err)? {
+            ABIError::FinishCallFailed { error, response } => Some((error, response)),
+            _ => None,
+        }
+    }
+
+    /// Call stack of the failed nested execution, if the error was caused by
+    /// one
+    pub(crate) fn nested_call_stack(err: &anyhow::Error) -> Option<Vec<CallFrame>> {
+        match Self::downcast(err)? {
+            ABIError::VMError(err) => err.call_stack().map(<[CallFrame]>::to_vec),
+            _ => None,
+        }
+    }
+
+    /// Error of an execution failing with `err`, typed after the `ABIError`
+    /// behind it. The kind of a failed nested execution is kept, and
+    /// `abort_info` is the one the execution stored in its `ASEnv`.
+    pub(crate) fn to_vm_error(
+        err: &anyhow::Error,
+        abort_info: Option<AbortInfo>,
+        init_gas_cost: u64,
+        remaining_gas: u64,
+        call_stack: Vec<CallFrame>,
+    ) -> VMError {
+        let error = err.to_string();
+        match Self::downcast(err) {
+            Some(ABIError::Aborted(message)) => VMError::Aborted {
+                message: message.clone(),
+                abort_info,
+                error,
+                init_gas_cost,
+                remaining_gas,
+                call_stack,
+            },
+            Some(ABIError::VMError(VMError::Aborted {
+                message,
+                abort_info,
+                ..
+            })) => VMError::Aborted {
+                message: message.clone(),
+                abort_info: abort_info.clone(),
+                error,
+                init_gas_cost,
+                remaining_gas,
+                call_stack,
+            },
+            Some(ABIError::WriteInReadOnlyMode(abi))
+            | Some(ABIError::ForbiddenAbi(abi))
+            | Some(ABIError::VMError(VMError::ForbiddenAbi { abi, .. })) => VMError::ForbiddenAbi {
+                abi: abi.clone(),
+                error,
+                init_gas_cost,
+                remaining_gas,
+                call_stack,
+            },
+            Some(ABIError::ExecutionTimeout) | Some(ABIError::VMError(VMError::Timeout { .. })) => {
+                VMError::Timeout {
+                    error,
+                    init_gas_cost,
+                    remaining_gas,
+                    call_stack,
+                }
+            }
+            Some(ABIError::Interrupted) | Some(ABIError::VMError(VMError::Interrupted { .. })) => {
+                VMError::Interrupted {
+                    error,
+                    init_gas_cost,
+                    remaining_gas,
+                    call_stack,
+                }
+            }
+            // the oversized value of a callee fails its caller alike
+            Some(ABIError::ReturnTooLarge { len, max })
+            | Some(ABIError::VMError(VMError::ReturnTooLarge { len, max, .. })) => {
+                VMError::ReturnTooLarge {
+                    len: *len,
+                    max: *max,
+                    error,
+                    init_gas_cost,
+                    remaining_gas,
+                    call_stack,
+                }
+            }
+            // the callee misses the function, it did not fail
+            Some(ABIError::VMError(VMError::FunctionNotFound {
+                function,
+                available,
+                ..
+            })) => VMError::FunctionNotFound {
+                function: function.clone(),
+                available: available.clone(),
+                error,
+                init_gas_cost,
+                remaining_gas,
+                call_stack,
+            },
+            _ => VMError::ExecutionError {
+                error,
+                init_gas_cost,
+                remaining_gas,
+                call_stack,
+            },
+        }
+    }
 }
 
 macro_rules! abi_bail {
@@ -3,23 +3,38 @@ mod common;
 mod context;
 pub(crate) mod env;
 mod error;
+pub(crate) mod pool;
 
+use crate::compilation_watchdog::compile_module;
+use crate::engine_provider::{EngineProvider, EngineRuntime};
 use crate::error::{exec_bail, VMResult};
-use crate::execution::Compiler;
-use crate::middlewares::gas_calibration::{get_gas_calibration_result, GasCalibrationResult};
-use crate::middlewares::{dumper::Dumper, gas_calibration::GasCalibration};
-use crate::settings::max_number_of_pages;
+use crate::execution::{check_exported_function, CallContext, Compiler, GasCalibrationResult};
+use crate::middlewares::bulk_memory::BulkMemoryMetering;
+use crate::middlewares::deadline::DeadlineCheck;
+#[cfg(feature = "dumper")]
+use crate::middlewares::dumper::Dumper;
+#[cfg(feature = "calibration")]
+use crate::middlewares::gas_calibration::{get_gas_calibration_result, GasCalibration};
+use crate::middlewares::memory_grow::{memory_limit_exceeded, MemoryGrowMetering};
+use crate::middlewares::stack_limit::{stack_limit_exceeded, StackLimit};
+use crate::panic_guard::catch_abi_panic;
 use crate::tunable_memory::LimitingTunables;
-use crate::{GasCosts, Interface, Response};
-use anyhow::Result;
+use crate::{GasCosts, Interface, Response, RuntimeConfig, VMError};
+use anyhow::{anyhow, Result};
+use env::{ASEnv, Metered};
+use pool::{InstancePool, PooledStore};
 use std::sync::Arc;
+#[cfg(feature = "cranelift")]
+use wasmer::Cranelift;
 use wasmer::NativeEngineExt;
 use wasmer::{wasmparser::Operator, BaseTunables, Engine, EngineBuilder, Pages, Target};
-use wasmer::{CompilerConfig, Cranelift, Features, Module, Store};
+use wasmer::{CompilerConfig, Features, FunctionEnv, FunctionType, Instance, Module, Store, Type};
 use wasmer_compiler_singlepass::Singlepass;
 use wasmer_middlewares::metering::MeteringPoints;
 use wasmer_middlewares::{metering, Metering};
 
+pub(crate) use abi::{check_async_message, check_evm_signature_inputs};
+pub(crate) use common::check_call_depth;
 pub(crate) use context::*;
 pub(crate) use error::*;
 
@@ -29,6 +44,13 @@ pub struct ASModule {
     pub(crate) binary_module: Module,
     pub(crate) initial_limit: u64,
     pub compiler: Compiler,
+    /// Whether the module was compiled to calibrate the gas costs, it can
+    /// only be executed in that mode
+    pub(crate) gas_calibration: bool,
+    /// Stores of the past executions, shared by the clones of the module.
+    /// Disabled unless the module is created with
+    /// `RuntimeModule::new_with_instance_pool`.
+    pub(crate) instance_pool: Option<Arc<InstancePool>>,
     // Compilation engine can not be dropped
     pub(crate) _engine: Engine,
 }
@@ -38,32 +60,43 @@ impl ASModule {
         bytecode: &[u8],
         limit: u64,
         gas_costs: GasCosts,
+        config: &RuntimeConfig,
         compiler: Compiler,
     ) -> Result<Self> {
         let engine = match compiler {
-            Compiler::CL => init_cl_engine(limit, gas_costs),
-            Compiler::SP => init_sp_engine(limit, gas_costs),
+            #[cfg(feature = "cranelift")]
+            Compiler::CL => init_cl_engine(limit, gas_costs, config),
+            Compiler::SP => init_sp_engine(limit, gas_costs, config),
         };
         Ok(Self {
-            binary_module: Module::new(&engine, bytecode)?,
+            binary_module: compile_module(&engine, bytecode, config.max_compilation_time)?,
             initial_limit: limit,
             compiler,
+            gas_calibration: config.gas_calibration,
+            instance_pool: None,
             _engine: engine,
         })
     }
 
     pub fn serialize(&self) -> Result<Vec<u8>> {
         match self.compiler {
+            #[cfg(feature = "cranelift")]
             Compiler::CL => Ok(self.binary_module.serialize()?.to_vec()),
-            Compiler::SP => {
-                panic!("cannot serialize a module compiled with Singlepass")
-            }
+            Compiler::SP => Err(anyhow!(
+                "cannot serialize a module compiled with Singlepass"
+            )),
         }
     }
 
-    pub fn deserialize(ser_module: &[u8], limit: u64, gas_costs: GasCosts) -> Result<Self> {
+    #[cfg(feature = "cranelift")]
+    pub fn deserialize(
+        ser_module: &[u8],
+        limit: u64,
+        gas_costs: GasCosts,
+        config: &RuntimeConfig,
+    ) -> Result<Self> {
         // Deserialization is only meant for Cranelift modules
-        let engine = init_cl_engine(limit, gas_costs);
+        let engine = init_cl_engine(limit, gas_costs, config);
         let store = Store::new(engine.clone());
         // Unsafe because code injection is possible
         // That's not an issue because we only deserialize modules we have
@@ -73,6 +106,8 @@ impl ASModule {
             binary_module: module,
             initial_limit: limit,
             compiler: Compiler::CL,
+            gas_calibration: config.gas_calibration,
+            instance_pool: None,
             _engine: engine,
         })
     }
@@ -95,46 +130,61 @@ impl ASModule {
 // * https://github.com/WebAssembly/design/blob/390bab47efdb76b600371bcef1ec0ea374aa8c43/Nondeterminism.md
 // * https://github.com/WebAssembly/proposals
 //
-// TLDR: Turn off every feature except for `bulk_memory`.
+// TLDR: Turn off every feature except for `bulk_memory`, whose operators are
+// charged per byte by `BulkMemoryMetering`. The sign-extension operators need
+// no feature, wasmer always accepts them, and are allowed by the validation.
 const FEATURES: Features = Features {
-    threads: false,         // non-deterministic
-    reference_types: false, // could be enabled but we have no need for it atm
-    simd: false,            // non-deterministic
-    bulk_memory: true,      // enables the use of buffers in AS
-    multi_value: false,     // could be enabled but we have no need for it atm
-    tail_call: false,       // experimental
-    module_linking: false,  // experimental
-    multi_memory: false,    // experimental
-    memory64: false,        // experimental
-    exceptions: false,      // experimental
-    relaxed_simd: false,    // experimental
-    extended_const: false,  // experimental
+    threads: false,               // non-deterministic
+    reference_types: false,       // could be enabled but we have no need for it atm
+    simd: cfg!(feature = "simd"), // non-deterministic
+    bulk_memory: true,            // enables the use of buffers in AS
+    multi_value: false,           // could be enabled but we have no need for it atm
+    tail_call: false,             // experimental
+    module_linking: false,        // experimental
+    multi_memory: false,          // experimental
+    memory64: false,              // experimental
+    exceptions: false,            // experimental
+    relaxed_simd: false,          // experimental
+    extended_const: false,        // experimental
 };
 
-pub(crate) fn init_sp_engine(limit: u64, gas_costs: GasCosts) -> Engine {
+pub(crate) fn init_sp_engine(limit: u64, gas_costs: GasCosts, config: &RuntimeConfig) -> Engine {
     // Singlepass is used to compile arbitrary bytecode.
     //
     // Reference:
     // * https://docs.rs/wasmer-compiler-singlepass/latest/wasmer_compiler_singlepass/
     let mut compiler_config = Singlepass::new();
 
+    let max_memory_pages = config.condom_limits.max_memory_pages;
+
     // Canonicalize NaN
     compiler_config.canonicalize_nans(true);
 
-    if cfg!(feature = "gas_calibration") {
-        // Add gas calibration middleware
-        let gas_calibration = Arc::new(GasCalibration::new());
-        compiler_config.push_middleware(gas_calibration);
+    if config.gas_calibration {
+        // Add gas calibration middleware, the modules are refused before
+        // being compiled in calibration mode without it
+        #[cfg(feature = "calibration")]
+        compiler_config.push_middleware(Arc::new(GasCalibration::new()));
     } else {
-        // Add metering middleware
-        let metering = Arc::new(Metering::new(limit, move |_: &Operator| -> u64 {
-            gas_costs.operator_cost
+        // Add metering middleware, then the instrumentations of memory.grow,
+        // of the bulk memory operators, of the calls and of the loops which
+        // it does not charge
+        let memory_grow = Arc::new(MemoryGrowMetering::new(&gas_costs, &config.condom_limits));
+        let bulk_memory = Arc::new(BulkMemoryMetering::new(&gas_costs));
+        let stack_limit = Arc::new(StackLimit::new(&config.condom_limits));
+        let deadline = Arc::new(DeadlineCheck::new());
+        let metering = Arc::new(Metering::new(limit, move |operator: &Operator| -> u64 {
+            gas_costs.cost_of_operator(operator)
         }));
         compiler_config.push_middleware(metering);
+        compiler_config.push_middleware(memory_grow);
+        compiler_config.push_middleware(bulk_memory);
+        compiler_config.push_middleware(stack_limit);
+        compiler_config.push_middleware(deadline);
     }
 
     let base = BaseTunables::for_target(&Target::default());
-    let tunables = LimitingTunables::new(base, Pages(max_number_of_pages()));
+    let tunables = LimitingTunables::new(base, Pages(max_memory_pages));
 
     let mut engine = Engine::from(
         EngineBuilder::new(compiler_config)
@@ -145,34 +195,48 @@ pub(crate) fn init_sp_engine(limit: u64, gas_costs: GasCosts) -> Engine {
     engine
 }
 
-pub(crate) fn init_cl_engine(limit: u64, gas_costs: GasCosts) -> Engine {
+#[cfg(feature = "cranelift")]
+pub(crate) fn init_cl_engine(limit: u64, gas_costs: GasCosts, config: &RuntimeConfig) -> Engine {
     // Cranelift is used to compile bytecode that will be cached.
     //
     // Reference:
     // * https://docs.rs/wasmer-compiler-cranelift/latest/wasmer_compiler_cranelift/
     let mut compiler_config = Cranelift::new();
 
+    let max_memory_pages = config.condom_limits.max_memory_pages;
+
     // Canonicalize NaN
     compiler_config.canonicalize_nans(true);
 
-    if cfg!(feature = "gas_calibration") {
-        // Add gas calibration middleware
-        let gas_calibration = Arc::new(GasCalibration::new());
-        compiler_config.push_middleware(gas_calibration);
+    if config.gas_calibration {
+        // Add gas calibration middleware, the modules are refused before
+        // being compiled in calibration mode without it
+        #[cfg(feature = "calibration")]
+        compiler_config.push_middleware(Arc::new(GasCalibration::new()));
     } else if cfg!(feature = "dumper") {
         // Add dumper middleware
-        let dumper = Arc::new(Dumper::new());
-        compiler_config.push_middleware(dumper);
+        #[cfg(feature = "dumper")]
+        compiler_config.push_middleware(Arc::new(Dumper::new()));
     } else {
-        // Add metering middleware
-        let metering = Arc::new(Metering::new(limit, move |_: &Operator| -> u64 {
-            gas_costs.operator_cost
+        // Add metering middleware, then the instrumentations of memory.grow,
+        // of the bulk memory operators, of the calls and of the loops which
+        // it does not charge
+        let memory_grow = Arc::new(MemoryGrowMetering::new(&gas_costs, &config.condom_limits));
+        let bulk_memory = Arc::new(BulkMemoryMetering::new(&gas_costs));
+        let stack_limit = Arc::new(StackLimit::new(&config.condom_limits));
+        let deadline = Arc::new(DeadlineCheck::new());
+        let metering = Arc::new(Metering::new(limit, move |operator: &Operator| -> u64 {
+            gas_costs.cost_of_operator(operator)
         }));
         compiler_config.push_middleware(metering);
+        compiler_config.push_middleware(memory_grow);
+        compiler_config.push_middleware(bulk_memory);
+        compiler_config.push_middleware(stack_limit);
+        compiler_config.push_middleware(deadline);
     }
 
     let base = BaseTunables::for_target(&Target::default());
-    let tunables = LimitingTunables::new(base, Pages(max_number_of_pages()));
+    let tunables = LimitingTunables::new(base, Pages(max_memory_pages));
 
     let mut engine = Engine::from(
         EngineBuilder::new(compiler_config)
@@ -182,6 +246,28 @@ pub(crate) fn init_cl_engine(limit: u64, gas_costs: GasCosts) -> Engine {
     engine.set_tunables(tunables);
     engine
 }
+/// Engine of a store running a module compiled with `compiler`, reused from
+/// the previous executions by `engines`
+pub(crate) fn execution_engine(
+    compiler: &Compiler,
+    gas_costs: &GasCosts,
+    config: &RuntimeConfig,
+    engines: &EngineProvider,
+) -> Engine {
+    let build = match compiler {
+        #[cfg(feature = "cranelift")]
+        Compiler::CL => init_cl_engine,
+        Compiler::SP => init_sp_engine,
+    };
+    Engine::clone(&engines.engine(
+        EngineRuntime::AssemblyScript,
+        compiler.clone(),
+        gas_costs,
+        config,
+        build,
+    ))
+}
+
 /// Internal execution function, used on smart contract called from node or
 /// from another smart contract
 /// Parameters:
@@ -191,6 +277,7 @@ pub(crate) fn init_cl_engine(limit: u64, gas_costs: GasCosts) -> Engine {
 /// * `param`: Parameter passed to the function
 /// * `cache`: Cache of pre compiled modules
 /// * `gas_costs`: Cost in gas of every VM operation
+/// * `call_context`: Position of this execution in the call tree
 ///
 /// Return:
 /// * Output of the executed function, remaininng gas after execution and the
@@ -203,54 +290,206 @@ pub(crate) fn exec_as_module(
     param: &[u8],
     limit: u64,
     gas_costs: GasCosts,
+    call_context: CallContext,
 ) -> VMResult<(Response, Option<GasCalibrationResult>)> {
-    let engine = match as_module.compiler {
-        Compiler::CL => init_cl_engine(limit, gas_costs.clone()),
-        Compiler::SP => init_sp_engine(limit, gas_costs.clone()),
+    let config = call_context.config.clone();
+    config.host_extensions.check()?;
+    let gas_calibration = config.gas_calibration;
+    let max_memory_pages = config.condom_limits.max_memory_pages;
+    let instance_pool = as_module.instance_pool.clone();
+    let mut pooled = match instance_pool.as_ref().and_then(|pool| {
+        pool.take(
+            max_memory_pages,
+            &config.abi_filter,
+            &config.host_extensions,
+        )
+    }) {
+        Some(pooled) => pooled,
+        None => {
+            let engine = execution_engine(
+                &as_module.compiler,
+                &gas_costs,
+                &config,
+                &call_context.engines,
+            );
+            PooledStore::new(
+                Store::new(engine),
+                max_memory_pages,
+                config.abi_filter.clone(),
+                config.host_extensions.clone(),
+            )
+        }
     };
-    let mut store = Store::new(engine);
-    let mut context = ASContext::new(interface, as_module.binary_module, gas_costs);
+    let store = &mut pooled.store;
+    let mut context = ASContext::new(
+        interface,
+        as_module.binary_module.clone(),
+        gas_costs,
+        call_context,
+    );
 
     // save the gas remaining before sub-execution: used by readonly execution
     interface.save_gas_remaining_before_subexecution(limit);
 
-    let (instance, _fenv, init_rem_points) = context.create_vm_instance_and_init_env(&mut store)?;
+    // the imports of a pooled store are reused, not its instances
+    let (imports, fenv) = match pooled.imports.take() {
+        Some(resolved) => resolved,
+        None => context.resolver(store),
+    };
+    let (instance, mut fenv, init_rem_points) =
+        context.create_vm_instance_with_imports(store, &imports, fenv)?;
     let init_cost = as_module.initial_limit.saturating_sub(init_rem_points);
+    #[cfg(feature = "tracing")]
+    tracing::debug!(init_gas_cost = init_cost, "instantiation");
+
+    let remaining_gas = if gas_calibration {
+        0
+    } else {
+        limit.saturating_sub(init_cost)
+    };
+    check_callable(&context, &instance, function, init_cost, remaining_gas)?;
+    if !gas_calibration {
+        metering::set_remaining_points(store, &instance, limit.saturating_sub(init_cost));
+    }
+    let env = fenv.as_mut(store);
+    env.set_gas_limit(limit.saturating_sub(init_cost));
+    env.set_current_module(as_module);
 
-    if cfg!(not(feature = "gas_calibration")) {
-        metering::set_remaining_points(&mut store, &instance, limit.saturating_sub(init_cost));
+    let response = run_instance(
+        &context, store, &instance, &fenv, function, param, init_cost,
+    )?;
+    #[cfg(feature = "calibration")]
+    let gc_result = gas_calibration.then(|| get_gas_calibration_result(&instance, store));
+    #[cfg(not(feature = "calibration"))]
+    let gc_result = None;
+    if let Some(pool) = instance_pool {
+        // the module holds the pool
+        fenv.as_mut(store).clear_current_module();
+        pooled.imports = Some((imports, fenv));
+        pool.put(pooled);
     }
+    Ok((response, gc_result))
+}
+
+/// The functions run by `ASContext::execution` take the parameter or nothing,
+/// and return the result or nothing
+fn is_callable(ty: &FunctionType) -> bool {
+    matches!(ty.params(), [] | [Type::I32]) && matches!(ty.results(), [] | [Type::I32])
+}
+
+/// Fail with a `VMError::FunctionNotFound` before running `function` if the
+/// module of `instance` does not export it with a callable signature
+pub(crate) fn check_callable(
+    context: &ASContext,
+    instance: &Instance,
+    function: &str,
+    init_cost: u64,
+    remaining_gas: u64,
+) -> VMResult<()> {
+    check_exported_function(instance.module(), function, is_callable).map_err(|available| {
+        VMError::function_not_found(
+            function,
+            available,
+            init_cost,
+            remaining_gas,
+            context.env.get_call_context().stack.clone(),
+        )
+    })
+}
+
+/// Run `function` of an `instance` of the module of `context`, whose
+/// instantiation cost `init_cost`, turning its failure into a `VMError`
+pub(crate) fn run_instance(
+    context: &ASContext,
+    store: &mut Store,
+    instance: &Instance,
+    fenv: &FunctionEnv<ASEnv>,
+    function: &str,
+    param: &[u8],
+    init_cost: u64,
+) -> VMResult<Response> {
+    let config = context.env.get_config();
+    let gas_calibration = config.gas_calibration;
+    let max_memory_pages = config.condom_limits.max_memory_pages;
+    let max_stack_depth = config.condom_limits.max_stack_depth;
+    let call_stack = context.env.get_call_context().stack.clone();
 
-    match context.execution(&mut store, &instance, function, param) {
+    // a panic of an ABI fails the execution like an error of the ABI
+    let result = catch_abi_panic(|| context.execution(store, instance, function, param))
+        .unwrap_or_else(|err| Err(err.into()));
+    match result {
         Ok(mut response) => {
-            let gc_result = if cfg!(feature = "gas_calibration") {
-                Some(get_gas_calibration_result(&instance, &mut store))
-            } else {
-                None
-            };
             response.init_gas_cost = init_cost;
 
             #[cfg(feature = "execution-trace")]
             {
-                response.trace = _fenv.as_ref(&store).trace.clone();
+                response.trace = std::mem::take(&mut fenv.as_mut(store).trace);
             }
-
-            Ok((response, gc_result))
+            Ok(response)
         }
         Err(err) => {
-            if cfg!(feature = "gas_calibration") {
-                exec_bail!(err, init_cost)
+            // the callee succeeded, the node decides what to do with its
+            // response
+            if let Some((error, response)) = ABIError::finish_call_failure(&err) {
+                return Err(VMError::FinishCallError {
+                    error: error.to_string(),
+                    init_gas_cost: init_cost,
+                    ret: response.ret.clone(),
+                    remaining_gas: response.remaining_gas,
+                    call_stack,
+                });
+            }
+            // keep the stack of the innermost failed call
+            let call_stack = ABIError::nested_call_stack(&err).unwrap_or(call_stack);
+            let abort_info = fenv.as_mut(store).take_abort_info();
+            if gas_calibration {
+                Err(ABIError::to_vm_error(
+                    &err, abort_info, init_cost, 0, call_stack,
+                ))
             } else {
+                // Gas exhausted by an ABI or a nested call
+                if let Some(at_function) = ABIError::out_of_gas_at(&err) {
+                    return Err(VMError::OutOfGas {
+                        at_function: at_function.to_string(),
+                        init_gas_cost: init_cost,
+                    });
+                }
                 // Because the last needed more than the remaining points, we
                 // should have an error.
-                match metering::get_remaining_points(&mut store, &instance) {
-                    MeteringPoints::Remaining(..) => exec_bail!(err, init_cost),
-                    MeteringPoints::Exhausted => {
+                match metering::get_remaining_points(store, instance) {
+                    MeteringPoints::Remaining(remaining)
+                        if memory_limit_exceeded(instance, store) =>
+                    {
+                        exec_bail!(
+                            ABIError::MemoryLimitExceeded {
+                                max_pages: max_memory_pages
+                            },
+                            init_cost,
+                            remaining,
+                            call_stack
+                        )
+                    }
+                    MeteringPoints::Remaining(remaining)
+                        if stack_limit_exceeded(instance, store) =>
+                    {
                         exec_bail!(
-                            format!("Not enough gas, limit reached at: {function}"),
-                            init_cost
+                            ABIError::StackOverflow {
+                                max_depth: max_stack_depth
+                            },
+                            init_cost,
+                            remaining,
+                            call_stack
                         )
                     }
+                    // the ABIs charge the metering points as they go, what a
+                    // failed ABI charged before failing is not left
+                    MeteringPoints::Remaining(remaining) => Err(ABIError::to_vm_error(
+                        &err, abort_info, init_cost, remaining, call_stack,
+                    )),
+                    MeteringPoints::Exhausted => Err(VMError::OutOfGas {
+                        at_function: function.to_string(),
+                        init_gas_cost: init_cost,
+                    }),
                 }
             }
         }
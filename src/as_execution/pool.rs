@@ -0,0 +1,100 @@
+//! Pool of the stores in which an AssemblyScript module is instantiated.
+//!
+//! For small contracts, building the engine, the store and the import object
+//! costs about as much as the execution itself. A pooled store keeps them for
+//! the next executions of the same module, but every execution still creates
+//! its own instance: the linear memory, the globals and the data segments of
+//! an execution are never seen by another one.
+//!
+//! The instances of the past executions are only released with their store,
+//! which is therefore dropped after `settings::max_pooled_store_executions`
+//! executions.
+
+use super::env::ASEnv;
+use crate::{settings, AbiFilter, HostExtensions};
+use parking_lot::Mutex;
+use wasmer::{FunctionEnv, Imports, Store};
+
+pub(crate) struct PooledStore {
+    pub store: Store,
+    /// Imports resolved in `store` by a previous execution, with the
+    /// environment of their functions
+    pub imports: Option<(Imports, FunctionEnv<ASEnv>)>,
+    /// Memory limit of the tunables of the engine of `store`
+    max_memory_pages: u32,
+    /// Filter applied to `imports`
+    abi_filter: AbiFilter,
+    /// Extensions defined in `imports`
+    host_extensions: HostExtensions,
+    executions: u32,
+}
+
+impl PooledStore {
+    pub(crate) fn new(
+        store: Store,
+        max_memory_pages: u32,
+        abi_filter: AbiFilter,
+        host_extensions: HostExtensions,
+    ) -> Self {
+        Self {
+            store,
+            imports: None,
+            max_memory_pages,
+            abi_filter,
+            host_extensions,
+            executions: 0,
+        }
+    }
+}
+
+/// Stores of the past executions of a module, at most `capacity` of them
+pub(crate) struct InstancePool {
+    capacity: usize,
+    stores: Mutex<Vec<PooledStore>>,
+}
+
+impl InstancePool {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            stores: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Take a store whose engine limits the memory to `max_memory_pages`, and
+    /// whose imports are filtered by `abi_filter` and extended by
+    /// `host_extensions`
+    pub(crate) fn take(
+        &self,
+        max_memory_pages: u32,
+        abi_filter: &AbiFilter,
+        host_extensions: &HostExtensions,
+    ) -> Option<PooledStore> {
+        let mut stores = self.stores.lock();
+        let index = stores.iter().position(|pooled| {
+            pooled.max_memory_pages == max_memory_pages
+                && pooled.abi_filter == *abi_filter
+                && pooled.host_extensions == *host_extensions
+        })?;
+        Some(stores.swap_remove(index))
+    }
+
+    /// Give back the store of a successful execution, it is dropped if the
+    /// pool is full or if it was used too many times
+    pub(crate) fn put(&self, mut pooled: PooledStore) {
+        pooled.executions += 1;
+        if pooled.executions >= settings::max_pooled_store_executions() {
+            return;
+        }
+        let mut stores = self.stores.lock();
+        if stores.len() < self.capacity {
+            stores.push(pooled);
+        }
+    }
+
+    /// Number of stores available for the next executions
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.stores.lock().len()
+    }
+}
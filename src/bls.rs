@@ -0,0 +1,229 @@
+//! BLS12-381 signatures verified by the runtime itself, without going through
+//! the `Interface`.
+//!
+//! Public keys are 48 bytes compressed G1 points and signatures 96 bytes
+//! compressed G2 points, signed with the proof of possession scheme used by
+//! Ethereum.
+
+use blst::min_pk::{AggregatePublicKey, PublicKey, Signature};
+use blst::BLST_ERROR;
+
+pub(crate) const BLS_PUBLIC_KEY_LEN: usize = 48;
+pub(crate) const BLS_SIGNATURE_LEN: usize = 96;
+
+/// Domain separation tag of the proof of possession scheme
+const BLS_DST: &[u8] = b"BLS_SIG_BLS12381G2_XMD:SHA-256_SSWU_RO_POP_";
+
+/// Parse a compressed public key, rejecting the points that are not on the
+/// curve, not in the subgroup or the identity
+fn parse_public_key(public_key: &[u8]) -> Result<PublicKey, String> {
+    if public_key.len() != BLS_PUBLIC_KEY_LEN {
+        return Err(format!(
+            "invalid BLS public key length: expected {} bytes, got {}",
+            BLS_PUBLIC_KEY_LEN,
+            public_key.len()
+        ));
+    }
+    PublicKey::key_validate(public_key).map_err(|err| format!("invalid BLS public key: {:?}", err))
+}
+
+/// Parse concatenated compressed public keys, at least one is required
+fn parse_public_keys(public_keys: &[u8]) -> Result<Vec<PublicKey>, String> {
+    if public_keys.is_empty() || public_keys.len() % BLS_PUBLIC_KEY_LEN != 0 {
+        return Err(format!(
+            "invalid BLS public keys length: expected a non-zero multiple of {} bytes, got {}",
+            BLS_PUBLIC_KEY_LEN,
+            public_keys.len()
+        ));
+    }
+    public_keys
+        .chunks_exact(BLS_PUBLIC_KEY_LEN)
+        .map(parse_public_key)
+        .collect()
+}
+
+/// Parse a compressed signature, rejecting the points that are not on the
+/// curve, not in the subgroup or the identity
+fn parse_signature(signature: &[u8]) -> Result<Signature, String> {
+    if signature.len() != BLS_SIGNATURE_LEN {
+        return Err(format!(
+            "invalid BLS signature length: expected {} bytes, got {}",
+            BLS_SIGNATURE_LEN,
+            signature.len()
+        ));
+    }
+    Signature::sig_validate(signature, true)
+        .map_err(|err| format!("invalid BLS signature: {:?}", err))
+}
+
+/// Number of public keys in `public_keys`, used to charge the gas before they
+/// are parsed
+pub(crate) fn public_key_count(public_keys: &[u8]) -> usize {
+    public_keys.len() / BLS_PUBLIC_KEY_LEN
+}
+
+/// Verify the `signature` of `message` by `public_key`.
+///
+/// Malformed inputs are errors, a well-formed but wrong signature is
+/// `Ok(false)`.
+pub(crate) fn verify(message: &[u8], signature: &[u8], public_key: &[u8]) -> Result<bool, String> {
+    let public_key = parse_public_key(public_key)?;
+    let signature = parse_signature(signature)?;
+    Ok(
+        signature.verify(false, message, BLS_DST, &[], &public_key, false)
+            == BLST_ERROR::BLST_SUCCESS,
+    )
+}
+
+/// Verify an aggregated `signature` of each of the `messages` by the public
+/// key at the same index in the concatenated `public_keys`
+pub(crate) fn aggregate_verify(
+    messages: &[Vec<u8>],
+    signature: &[u8],
+    public_keys: &[u8],
+) -> Result<bool, String> {
+    let public_keys = parse_public_keys(public_keys)?;
+    if messages.len() != public_keys.len() {
+        return Err(format!(
+            "{} messages for {} BLS public keys",
+            messages.len(),
+            public_keys.len()
+        ));
+    }
+    let signature = parse_signature(signature)?;
+    let messages: Vec<&[u8]> = messages.iter().map(|message| message.as_slice()).collect();
+    let public_keys: Vec<&PublicKey> = public_keys.iter().collect();
+    Ok(
+        signature.aggregate_verify(false, &messages, BLS_DST, &public_keys, false)
+            == BLST_ERROR::BLST_SUCCESS,
+    )
+}
+
+/// Aggregate the concatenated `public_keys` into a single compressed public
+/// key, verifying the signatures of a same message aggregated the same way
+pub(crate) fn aggregate_public_keys(public_keys: &[u8]) -> Result<Vec<u8>, String> {
+    let public_keys = parse_public_keys(public_keys)?;
+    let public_keys: Vec<&PublicKey> = public_keys.iter().collect();
+    let aggregate = AggregatePublicKey::aggregate(&public_keys, false)
+        .map_err(|err| format!("could not aggregate the BLS public keys: {:?}", err))?;
+    Ok(aggregate.to_public_key().compress().to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blst::min_pk::{AggregateSignature, SecretKey};
+
+    /// Compressed generator of G1, the public key of the secret key 1
+    const G1_GENERATOR: &str = "97f1d3a73197d7942695638c4fa9ac0fc3688c4f9774b905a14e3a3f171bac586c55e83ff97a1aeffb3af00adb22c6bb";
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    fn secret_key(seed: u8) -> SecretKey {
+        SecretKey::key_gen(&[seed; 32], &[]).unwrap()
+    }
+
+    fn sign(secret_key: &SecretKey, message: &[u8]) -> Vec<u8> {
+        secret_key.sign(message, BLS_DST, &[]).compress().to_vec()
+    }
+
+    #[test]
+    fn test_bls_public_key_vector() {
+        let mut one = [0u8; 32];
+        one[31] = 1;
+        let public_key = SecretKey::from_bytes(&one).unwrap().sk_to_pk().compress();
+        assert_eq!(hex(&public_key), G1_GENERATOR);
+        assert!(parse_public_key(&public_key).is_ok());
+    }
+
+    #[test]
+    fn test_bls_verify() {
+        let secret_key = secret_key(1);
+        let public_key = secret_key.sk_to_pk().compress();
+        let signature = sign(&secret_key, b"message");
+
+        assert_eq!(verify(b"message", &signature, &public_key), Ok(true));
+        assert_eq!(verify(b"other message", &signature, &public_key), Ok(false));
+        let other_key = self::secret_key(2).sk_to_pk().compress();
+        assert_eq!(verify(b"message", &signature, &other_key), Ok(false));
+
+        // malformed inputs
+        assert!(verify(b"message", &signature[..95], &public_key).is_err());
+        assert!(verify(b"message", &signature, &public_key[..47]).is_err());
+        // identity
+        let mut infinity = [0u8; BLS_PUBLIC_KEY_LEN];
+        infinity[0] = 0xc0;
+        assert!(verify(b"message", &signature, &infinity).is_err());
+        // identity flag with a non-zero coordinate
+        assert!(verify(b"message", &signature, &[0xff; BLS_PUBLIC_KEY_LEN]).is_err());
+        // x coordinate over the field modulus
+        let mut over_modulus = [0xff; BLS_PUBLIC_KEY_LEN];
+        over_modulus[0] = 0x9f;
+        assert!(verify(b"message", &signature, &over_modulus).is_err());
+        assert!(verify(b"message", &[0xff; BLS_SIGNATURE_LEN], &public_key).is_err());
+    }
+
+    #[test]
+    fn test_bls_aggregate_verify() {
+        let secret_keys: Vec<SecretKey> = (1..=3).map(secret_key).collect();
+        let messages: Vec<Vec<u8>> = (1..=3).map(|i| vec![i; 32]).collect();
+        let signatures: Vec<Signature> = secret_keys
+            .iter()
+            .zip(&messages)
+            .map(|(secret_key, message)| secret_key.sign(message, BLS_DST, &[]))
+            .collect();
+        let signature = AggregateSignature::aggregate(&signatures.iter().collect::<Vec<_>>(), true)
+            .unwrap()
+            .to_signature()
+            .compress();
+        let public_keys: Vec<u8> = secret_keys
+            .iter()
+            .flat_map(|secret_key| secret_key.sk_to_pk().compress())
+            .collect();
+
+        assert_eq!(
+            aggregate_verify(&messages, &signature, &public_keys),
+            Ok(true)
+        );
+        let mut swapped = messages.clone();
+        swapped.swap(0, 1);
+        assert_eq!(
+            aggregate_verify(&swapped, &signature, &public_keys),
+            Ok(false)
+        );
+        // one key per message
+        assert!(aggregate_verify(&messages[..2], &signature, &public_keys).is_err());
+        assert!(aggregate_verify(&[], &signature, &[]).is_err());
+        assert!(aggregate_verify(&messages, &signature, &public_keys[..140]).is_err());
+    }
+
+    #[test]
+    fn test_bls_aggregate_public_keys() {
+        let secret_keys: Vec<SecretKey> = (1..=3).map(secret_key).collect();
+        let public_keys: Vec<u8> = secret_keys
+            .iter()
+            .flat_map(|secret_key| secret_key.sk_to_pk().compress())
+            .collect();
+        let signatures: Vec<Signature> = secret_keys
+            .iter()
+            .map(|secret_key| secret_key.sign(b"message", BLS_DST, &[]))
+            .collect();
+        let signature = AggregateSignature::aggregate(&signatures.iter().collect::<Vec<_>>(), true)
+            .unwrap()
+            .to_signature()
+            .compress();
+
+        let aggregate = aggregate_public_keys(&public_keys).unwrap();
+        assert_eq!(aggregate.len(), BLS_PUBLIC_KEY_LEN);
+        assert_eq!(verify(b"message", &signature, &aggregate), Ok(true));
+        // a single key is its own aggregate
+        assert_eq!(
+            aggregate_public_keys(&public_keys[..BLS_PUBLIC_KEY_LEN]).unwrap(),
+            public_keys[..BLS_PUBLIC_KEY_LEN]
+        );
+        assert!(aggregate_public_keys(&[]).is_err());
+        assert!(aggregate_public_keys(&[0xff; BLS_PUBLIC_KEY_LEN]).is_err());
+    }
+}
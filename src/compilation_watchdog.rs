@@ -0,0 +1,109 @@
+//! Wall-clock bound on the compilation of the modules.
+//!
+//! The structural limits of the validation keep the compilation time of most
+//! bytecode in check, but nothing bounds it under Cranelift. Wasmer cannot
+//! interrupt a compilation, so the watchdog runs it on a thread of its own and
+//! stops waiting for it after `RuntimeConfig::max_compilation_time`: the
+//! compilation keeps its thread until it finishes, and its module is dropped.
+//! Without a maximum the compilation runs on the calling thread.
+
+use crate::as_execution::ABIError;
+use crate::execution::{Compiler, RuntimeModule};
+use crate::types::Interface;
+use anyhow::{anyhow, Result};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+use wasmer::{Engine, Module};
+
+/// Run `compile`, failing with `ABIError::CompilationTimeout` when it takes
+/// more than `max_time`
+pub(crate) fn with_compilation_timeout<T: Send + 'static>(
+    max_time: Option<Duration>,
+    compile: impl FnOnce() -> Result<T> + Send + 'static,
+) -> Result<T> {
+    let Some(max_time) = max_time else {
+        return compile();
+    };
+    let (sender, receiver) = mpsc::sync_channel(1);
+    thread::Builder::new()
+        .name("massa-sc-compilation".to_string())
+        .spawn(move || {
+            // nobody waits for the result after a timeout
+            let _ = sender.send(compile());
+        })?;
+    match receiver.recv_timeout(max_time) {
+        Ok(result) => result,
+        Err(RecvTimeoutError::Timeout) => Err(ABIError::CompilationTimeout { max_time }.into()),
+        Err(RecvTimeoutError::Disconnected) => Err(anyhow!("the compilation panicked")),
+    }
+}
+
+/// Compile `bytecode` with `engine` in at most `max_time`
+pub(crate) fn compile_module(
+    engine: &Engine,
+    bytecode: &[u8],
+    max_time: Option<Duration>,
+) -> Result<Module> {
+    if max_time.is_none() {
+        return Ok(Module::new(engine, bytecode)?);
+    }
+    let (engine, bytecode) = (engine.clone(), bytecode.to_vec());
+    with_compilation_timeout(max_time, move || Ok(Module::new(&engine, bytecode)?))
+}
+
+/// Module of a nested call compiled by `interface` from `bytecode` in at most
+/// `max_time`, see `Interface::get_module_with_compiler`
+pub(crate) fn compile_nested_module(
+    interface: &dyn Interface,
+    bytecode: Vec<u8>,
+    gas_limit: u64,
+    compiler: Compiler,
+    max_time: Option<Duration>,
+) -> Result<RuntimeModule> {
+    if max_time.is_none() {
+        return interface.get_module_with_compiler(&bytecode, gas_limit, compiler);
+    }
+    let interface = interface.clone_box();
+    with_compilation_timeout(max_time, move || {
+        interface.get_module_with_compiler(&bytecode, gas_limit, compiler)
+    })
+}
+
+/// Whether `err` is a compilation exceeding its maximum time, with that time
+pub(crate) fn compilation_timeout(err: &anyhow::Error) -> Option<Duration> {
+    match err.downcast_ref::<ABIError>()? {
+        ABIError::CompilationTimeout { max_time } => Some(*max_time),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_without_maximum_runs_inline() {
+        let caller = thread::current().id();
+        let compiled = with_compilation_timeout(None, move || Ok(thread::current().id()));
+        assert_eq!(compiled.unwrap(), caller);
+    }
+
+    #[test]
+    fn test_timeout() {
+        let err = with_compilation_timeout(Some(Duration::from_millis(1)), || {
+            thread::sleep(Duration::from_secs(1));
+            Ok(())
+        })
+        .unwrap_err();
+        assert_eq!(
+            compilation_timeout(&err),
+            Some(Duration::from_millis(1)),
+            "{}",
+            err
+        );
+
+        let compiled = with_compilation_timeout(Some(Duration::from_secs(60)), || Ok(7));
+        assert_eq!(compiled.unwrap(), 7);
+    }
+}
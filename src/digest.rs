@@ -0,0 +1,100 @@
+//! Digests computed by the runtime itself, without going through the
+//! `Interface`.
+//!
+//! Each entry of `DIGESTS` is exposed to both ABI families under its own
+//! names, and charged like any ABI plus `<abi>_per_byte` for each byte of its
+//! input. A new digest only needs an entry and its gas costs.
+
+use sha2::{Digest, Sha256};
+use sha3::Sha3_256;
+
+/// A digest and the ABIs computing it
+pub(crate) struct NativeDigest {
+    /// Name of the AssemblyScript ABI, taking and returning an `ArrayBuffer`
+    pub as_abi: &'static str,
+    /// Name of the wasmv1 ABI, taking and returning raw bytes
+    pub wasmv1_abi: &'static str,
+    hash: fn(&[u8]) -> Vec<u8>,
+}
+
+pub(crate) const DIGESTS: &[NativeDigest] = &[
+    NativeDigest {
+        as_abi: "assembly_script_sha256",
+        wasmv1_abi: "abi_sha256",
+        hash: sha256,
+    },
+    NativeDigest {
+        as_abi: "assembly_script_sha3_256",
+        wasmv1_abi: "abi_sha3_256",
+        hash: sha3_256,
+    },
+];
+
+impl NativeDigest {
+    /// Digest of `data`, refused above `max_len` bytes
+    pub(crate) fn compute(&self, data: &[u8], max_len: usize) -> Result<Vec<u8>, String> {
+        if data.len() > max_len {
+            return Err(format!(
+                "digest input of {} bytes exceeds the maximum of {} bytes",
+                data.len(),
+                max_len
+            ));
+        }
+        Ok((self.hash)(data))
+    }
+}
+
+fn sha256(data: &[u8]) -> Vec<u8> {
+    Sha256::digest(data).to_vec()
+}
+
+fn sha3_256(data: &[u8]) -> Vec<u8> {
+    Sha3_256::digest(data).to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn digest(abi: &str, data: &[u8]) -> String {
+        let digest = DIGESTS.iter().find(|digest| digest.as_abi == abi).unwrap();
+        let hash = digest.compute(data, usize::MAX).unwrap();
+        hash.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn test_nist_vectors() {
+        // FIPS 180-2 and FIPS 202 examples
+        for (data, sha256, sha3_256) in [
+            (
+                &b""[..],
+                "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+                "a7ffc6f8bf1ed76651c14756a061d662f580ff4de43b49fa82d80a4b80f8434a",
+            ),
+            (
+                &b"abc"[..],
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+                "3a985da74fe225b2045c172d6bd390bd855f086e3e9d525b46bfe24511431532",
+            ),
+            (
+                &b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"[..],
+                "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1",
+                "41c0dba2a9d6240849100376a8235e2c82e1b9998a999e21db32dd97496d3376",
+            ),
+        ] {
+            assert_eq!(digest("assembly_script_sha256", data), sha256);
+            assert_eq!(digest("assembly_script_sha3_256", data), sha3_256);
+        }
+    }
+
+    #[test]
+    fn test_max_input_len() {
+        for digest in DIGESTS {
+            assert_eq!(digest.compute(&[7; 8], 8).unwrap().len(), 32);
+            assert_eq!(
+                digest.compute(&[7; 9], 8).unwrap_err(),
+                "digest input of 9 bytes exceeds the maximum of 8 bytes"
+            );
+        }
+    }
+}
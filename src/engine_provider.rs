@@ -0,0 +1,224 @@
+//! Engines of the executions, reused from one execution to the next.
+//!
+//! An execution does not compile its module, it runs it in a store whose
+//! engine provides the tunables of the instance. Building that engine sets
+//! up the compiler and the target each time, so `EngineProvider` keeps the
+//! engines around, keyed by everything they are built from: the runtime, and
+//! thus the wasm features, the compiler, and the costs and limits read by the
+//! middlewares. Gas costs charging the operators differently never get an
+//! engine built with the previous costs.
+//!
+//! The embedder keeps a provider and gives it to `run_main` or `run_function`
+//! in `ExecutionOptions::engines`, the nested calls using the provider of
+//! their call tree. The other executions build the engines of their call tree.
+//!
+//! The middlewares of an engine instrument a single module, so the engines
+//! handed out must not compile: the modules are compiled by engines of their
+//! own, see `RuntimeModule::new`.
+
+use crate::execution::Compiler;
+use crate::{settings, CondomLimits, GasCosts, RuntimeConfig};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::fmt::{self, Debug};
+use std::sync::Arc;
+use wasmer::Engine;
+
+/// Runtime whose modules an engine runs, each one having its wasm features
+/// and middlewares
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum EngineRuntime {
+    AssemblyScript,
+    WasmV1,
+}
+
+/// Configuration an engine is built from
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct EngineKey {
+    runtime: EngineRuntime,
+    compiler: Compiler,
+    gas_calibration: bool,
+    operator_cost: u64,
+    /// Sorted by operator
+    operator_costs: Vec<(String, u64)>,
+    memory_page_cost: u64,
+    bulk_memory_byte_cost: u64,
+    condom_limits: CondomLimits,
+}
+
+impl EngineKey {
+    fn new(
+        runtime: EngineRuntime,
+        compiler: Compiler,
+        gas_costs: &GasCosts,
+        config: &RuntimeConfig,
+    ) -> Self {
+        let mut operator_costs: Vec<_> = gas_costs
+            .operator_costs
+            .iter()
+            .map(|(operator, cost)| (operator.clone(), *cost))
+            .collect();
+        operator_costs.sort_unstable();
+        Self {
+            runtime,
+            compiler,
+            gas_calibration: config.gas_calibration,
+            operator_cost: gas_costs.operator_cost,
+            operator_costs,
+            memory_page_cost: gas_costs.memory_page_cost,
+            bulk_memory_byte_cost: gas_costs.bulk_memory_byte_cost,
+            condom_limits: config.condom_limits,
+        }
+    }
+}
+
+/// Engines of the executions, shared by the clones of a provider.
+///
+/// At most `settings::max_cached_engines` engines are kept, the provider is
+/// emptied when it is full.
+#[derive(Clone, Default)]
+pub struct EngineProvider {
+    engines: Arc<Mutex<HashMap<EngineKey, Arc<Engine>>>>,
+}
+
+impl EngineProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Engine running the modules of `runtime` compiled with `compiler`,
+    /// `gas_costs` and `config`, built by `build` unless an engine of the
+    /// same configuration was built before
+    pub(crate) fn engine(
+        &self,
+        runtime: EngineRuntime,
+        compiler: Compiler,
+        gas_costs: &GasCosts,
+        config: &RuntimeConfig,
+        build: impl FnOnce(u64, GasCosts, &RuntimeConfig) -> Engine,
+    ) -> Arc<Engine> {
+        let key = EngineKey::new(runtime, compiler, gas_costs, config);
+        let mut engines = self.engines.lock();
+        if let Some(engine) = engines.get(&key) {
+            return engine.clone();
+        }
+        if engines.len() >= settings::max_cached_engines() {
+            engines.clear();
+        }
+        // the engine never compiles, the limit of its metering does not
+        // matter
+        let engine = Arc::new(build(0, gas_costs.clone(), config));
+        engines.insert(key, engine.clone());
+        engine
+    }
+
+    /// Number of cached engines
+    pub fn len(&self) -> usize {
+        self.engines.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.engines.lock().is_empty()
+    }
+
+    /// Drop the cached engines
+    pub fn clear(&self) {
+        self.engines.lock().clear();
+    }
+}
+
+impl Debug for EngineProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EngineProvider")
+            .field("engines", &self.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::as_execution::init_sp_engine;
+
+    fn engine(provider: &EngineProvider, gas_costs: &GasCosts) -> Arc<Engine> {
+        engine_with_config(provider, gas_costs, &RuntimeConfig::default())
+    }
+
+    fn engine_with_config(
+        provider: &EngineProvider,
+        gas_costs: &GasCosts,
+        config: &RuntimeConfig,
+    ) -> Arc<Engine> {
+        provider.engine(
+            EngineRuntime::AssemblyScript,
+            Compiler::SP,
+            gas_costs,
+            config,
+            init_sp_engine,
+        )
+    }
+
+    #[test]
+    fn test_same_configuration_shares_the_engine() {
+        let provider = EngineProvider::new();
+        let gas_costs = GasCosts::default();
+        let first = engine(&provider, &gas_costs);
+        // the other costs are not part of the key
+        let mut other = gas_costs.clone();
+        other.launch_cost += 1;
+        assert!(Arc::ptr_eq(&first, &engine(&provider, &other)));
+        assert_eq!(provider.len(), 1);
+
+        // nor are the cached engines of a clone
+        assert!(Arc::ptr_eq(&first, &engine(&provider.clone(), &gas_costs)));
+    }
+
+    #[test]
+    fn test_operator_costs_are_part_of_the_key() {
+        let provider = EngineProvider::new();
+        let gas_costs = GasCosts::default();
+        let first = engine(&provider, &gas_costs);
+
+        let mut other = gas_costs.clone();
+        other.operator_cost += 1;
+        assert!(!Arc::ptr_eq(&first, &engine(&provider, &other)));
+        let mut other = gas_costs.clone();
+        other.operator_costs.insert("I32Add".to_string(), 7);
+        assert!(!Arc::ptr_eq(&first, &engine(&provider, &other)));
+        let mut config = RuntimeConfig::default();
+        config.condom_limits.max_memory_pages -= 1;
+        assert!(!Arc::ptr_eq(
+            &first,
+            &engine_with_config(&provider, &gas_costs, &config)
+        ));
+        assert_eq!(provider.len(), 4);
+
+        // the other settings are not part of the key
+        let mut config = RuntimeConfig::default();
+        config.max_return_size += 1;
+        assert!(Arc::ptr_eq(
+            &first,
+            &engine_with_config(&provider, &gas_costs, &config)
+        ));
+
+        let wasmv1 = provider.engine(
+            EngineRuntime::WasmV1,
+            Compiler::SP,
+            &gas_costs,
+            &RuntimeConfig::default(),
+            crate::wasmv1_execution::init_sp_engine,
+        );
+        assert!(!Arc::ptr_eq(&first, &wasmv1));
+    }
+
+    #[test]
+    fn test_provider_is_bounded() {
+        let provider = EngineProvider::new();
+        let mut gas_costs = GasCosts::default();
+        for cost in 0..=settings::max_cached_engines() as u64 {
+            gas_costs.operator_cost = cost;
+            engine(&provider, &gas_costs);
+        }
+        assert_eq!(provider.len(), 1);
+    }
+}
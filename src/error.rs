@@ -1,14 +1,422 @@
+use crate::{settings, CallFrame, Response};
 use displaydoc::Display;
+use std::ops::RangeInclusive;
 use thiserror::Error;
 
 pub type VMResult<T> = Result<T, VMError>;
 
+/// Failure of an execution.
+///
+/// The variants of the failures raised during an execution are
+/// `#[non_exhaustive]`, so that they can gain fields without breaking the
+/// patterns matching them: match them with `..`, and read their gas and call
+/// stack with `gas_used` and `call_stack`.
 #[derive(Clone, Error, Display, Debug)]
 pub enum VMError {
     /// VM instance error: {0}
     InstanceError(String),
     /// VM execution error: {error}
-    ExecutionError { error: String, init_gas_cost: u64 },
+    #[non_exhaustive]
+    ExecutionError {
+        error: String,
+        init_gas_cost: u64,
+        /// Gas left when the execution failed
+        remaining_gas: u64,
+        /// Calls leading to the failed execution, the innermost last
+        call_stack: Vec<CallFrame>,
+    },
+    /// VM execution error: finish_call failed after a successful call: {error}
+    #[non_exhaustive]
+    FinishCallError {
+        error: String,
+        init_gas_cost: u64,
+        /// Value returned by the callee
+        ret: Vec<u8>,
+        /// Gas left when `finish_call` failed, the gas used by the callee
+        /// already deducted
+        remaining_gas: u64,
+        /// Calls leading to the execution that made the call
+        call_stack: Vec<CallFrame>,
+    },
+    /// VM execution error: Not enough gas, limit reached at: {at_function}
+    OutOfGas {
+        at_function: String,
+        init_gas_cost: u64,
+    },
+    /// VM execution error: {error}
+    #[non_exhaustive]
+    Aborted {
+        /// Reason given by the smart contract
+        message: String,
+        /// Arguments of the AssemblyScript `abort` behind the failure, if the
+        /// execution did not abort otherwise
+        abort_info: Option<AbortInfo>,
+        error: String,
+        init_gas_cost: u64,
+        remaining_gas: u64,
+        call_stack: Vec<CallFrame>,
+    },
+    /// VM execution error: {error}
+    #[non_exhaustive]
+    ForbiddenAbi {
+        /// ABI the execution is not allowed to call
+        abi: String,
+        error: String,
+        init_gas_cost: u64,
+        remaining_gas: u64,
+        call_stack: Vec<CallFrame>,
+    },
+    /// VM execution error: {error}
+    #[non_exhaustive]
+    Timeout {
+        error: String,
+        init_gas_cost: u64,
+        remaining_gas: u64,
+        call_stack: Vec<CallFrame>,
+    },
+    /// VM execution error: {error}
+    #[non_exhaustive]
+    Interrupted {
+        error: String,
+        init_gas_cost: u64,
+        remaining_gas: u64,
+        call_stack: Vec<CallFrame>,
+    },
+    /// VM instance error: parameter of {len} bytes exceeds the maximum of {max} bytes
+    ParamTooLarge { len: usize, max: usize },
+    /// VM execution error: {error}
+    #[non_exhaustive]
+    ReturnTooLarge {
+        /// Size in bytes of the value returned by the function
+        len: u64,
+        max: usize,
+        error: String,
+        init_gas_cost: u64,
+        remaining_gas: u64,
+        call_stack: Vec<CallFrame>,
+    },
+    /// VM instance error: interface version {got} is not supported, the runtime supports {supported:?}
+    IncompatibleInterface {
+        /// Version reported by `Interface::interface_version`
+        got: u32,
+        supported: RangeInclusive<u32>,
+    },
+    /// VM execution error: {error}
+    #[non_exhaustive]
+    FunctionNotFound {
+        /// Function missing from the executed module, or exported with a
+        /// signature it cannot be called with
+        function: String,
+        /// Functions the module exports with a callable signature, at most
+        /// `settings::max_listed_exports` of them
+        available: Vec<String>,
+        error: String,
+        init_gas_cost: u64,
+        remaining_gas: u64,
+        call_stack: Vec<CallFrame>,
+    },
+}
+
+/// Arguments of an AssemblyScript `abort(message, file, line, col)`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AbortInfo {
+    pub message: String,
+    /// Source file of the smart contract that aborted
+    pub file: String,
+    pub line: u32,
+    pub col: u32,
+}
+
+impl std::fmt::Display for AbortInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "error: {} at {}:{} col: {}",
+            self.message, self.file, self.line, self.col
+        )
+    }
+}
+
+/// Kind of a `VMError`, meant to classify the failures without relying on
+/// their messages. The values are stable.
+#[repr(u16)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum VMErrorCode {
+    Instance = 1,
+    Execution = 2,
+    FinishCall = 3,
+    OutOfGas = 4,
+    Aborted = 5,
+    ForbiddenAbi = 6,
+    Timeout = 7,
+    Interrupted = 8,
+    ParamTooLarge = 9,
+    IncompatibleInterface = 10,
+    FunctionNotFound = 11,
+    ReturnTooLarge = 12,
+}
+
+impl VMError {
+    pub fn code(&self) -> VMErrorCode {
+        match self {
+            VMError::InstanceError(_) => VMErrorCode::Instance,
+            VMError::ExecutionError { .. } => VMErrorCode::Execution,
+            VMError::FinishCallError { .. } => VMErrorCode::FinishCall,
+            VMError::OutOfGas { .. } => VMErrorCode::OutOfGas,
+            VMError::Aborted { .. } => VMErrorCode::Aborted,
+            VMError::ForbiddenAbi { .. } => VMErrorCode::ForbiddenAbi,
+            VMError::Timeout { .. } => VMErrorCode::Timeout,
+            VMError::Interrupted { .. } => VMErrorCode::Interrupted,
+            VMError::ParamTooLarge { .. } => VMErrorCode::ParamTooLarge,
+            VMError::IncompatibleInterface { .. } => VMErrorCode::IncompatibleInterface,
+            VMError::FunctionNotFound { .. } => VMErrorCode::FunctionNotFound,
+            VMError::ReturnTooLarge { .. } => VMErrorCode::ReturnTooLarge,
+        }
+    }
+
+    /// `FunctionNotFound` error of an execution of `function`, listing the
+    /// first `settings::max_listed_exports` of the `available` functions
+    pub(crate) fn function_not_found(
+        function: &str,
+        mut available: Vec<String>,
+        init_gas_cost: u64,
+        remaining_gas: u64,
+        call_stack: Vec<CallFrame>,
+    ) -> Self {
+        available.truncate(settings::max_listed_exports());
+        VMError::FunctionNotFound {
+            error: format!(
+                "function {} not found, the module exports {:?}",
+                function, available
+            ),
+            function: function.to_string(),
+            available,
+            init_gas_cost,
+            remaining_gas,
+            call_stack,
+        }
+    }
+
+    /// Gas consumed up to the failure of an execution given `gas_limit`, the
+    /// instance creation included.
+    ///
+    /// The whole limit is consumed when the gas is exhausted, and for an
+    /// `InstanceError` whose remaining gas is unknown.
+    pub fn gas_used(&self, gas_limit: u64) -> u64 {
+        match self {
+            VMError::InstanceError(_)
+            | VMError::OutOfGas { .. }
+            | VMError::ParamTooLarge { .. }
+            | VMError::IncompatibleInterface { .. } => gas_limit,
+            VMError::ExecutionError { remaining_gas, .. }
+            | VMError::FinishCallError { remaining_gas, .. }
+            | VMError::Aborted { remaining_gas, .. }
+            | VMError::ForbiddenAbi { remaining_gas, .. }
+            | VMError::Timeout { remaining_gas, .. }
+            | VMError::Interrupted { remaining_gas, .. }
+            | VMError::FunctionNotFound { remaining_gas, .. }
+            | VMError::ReturnTooLarge { remaining_gas, .. } => {
+                gas_limit.saturating_sub(*remaining_gas)
+            }
+        }
+    }
+
+    /// Calls leading to the failed execution, the innermost last
+    pub fn call_stack(&self) -> Option<&[CallFrame]> {
+        match self {
+            VMError::InstanceError(_)
+            | VMError::OutOfGas { .. }
+            | VMError::ParamTooLarge { .. }
+            | VMError::IncompatibleInterface { .. } => None,
+            VMError::ExecutionError { call_stack, .. }
+            | VMError::FinishCallError { call_stack, .. }
+            | VMError::Aborted { call_stack, .. }
+            | VMError::ForbiddenAbi { call_stack, .. }
+            | VMError::Timeout { call_stack, .. }
+            | VMError::Interrupted { call_stack, .. }
+            | VMError::FunctionNotFound { call_stack, .. }
+            | VMError::ReturnTooLarge { call_stack, .. } => Some(call_stack),
+        }
+    }
+
+    /// Prefix the message of the errors raised during an execution, the
+    /// other ones are turned into an `InstanceError`
+    pub(crate) fn with_prefix(self, prefix: &str) -> Self {
+        match self {
+            VMError::OutOfGas { .. }
+            | VMError::ParamTooLarge { .. }
+            | VMError::IncompatibleInterface { .. } => self,
+            VMError::ExecutionError {
+                error,
+                init_gas_cost,
+                remaining_gas,
+                call_stack,
+            } => VMError::ExecutionError {
+                error: format!("{}{}", prefix, error),
+                init_gas_cost,
+                remaining_gas,
+                call_stack,
+            },
+            VMError::Aborted {
+                message,
+                abort_info,
+                error,
+                init_gas_cost,
+                remaining_gas,
+                call_stack,
+            } => VMError::Aborted {
+                message,
+                abort_info,
+                error: format!("{}{}", prefix, error),
+                init_gas_cost,
+                remaining_gas,
+                call_stack,
+            },
+            VMError::ForbiddenAbi {
+                abi,
+                error,
+                init_gas_cost,
+                remaining_gas,
+                call_stack,
+            } => VMError::ForbiddenAbi {
+                abi,
+                error: format!("{}{}", prefix, error),
+                init_gas_cost,
+                remaining_gas,
+                call_stack,
+            },
+            VMError::Timeout {
+                error,
+                init_gas_cost,
+                remaining_gas,
+                call_stack,
+            } => VMError::Timeout {
+                error: format!("{}{}", prefix, error),
+                init_gas_cost,
+                remaining_gas,
+                call_stack,
+            },
+            VMError::Interrupted {
+                error,
+                init_gas_cost,
+                remaining_gas,
+                call_stack,
+            } => VMError::Interrupted {
+                error: format!("{}{}", prefix, error),
+                init_gas_cost,
+                remaining_gas,
+                call_stack,
+            },
+            VMError::FunctionNotFound {
+                function,
+                available,
+                error,
+                init_gas_cost,
+                remaining_gas,
+                call_stack,
+            } => VMError::FunctionNotFound {
+                function,
+                available,
+                error: format!("{}{}", prefix, error),
+                init_gas_cost,
+                remaining_gas,
+                call_stack,
+            },
+            VMError::ReturnTooLarge {
+                len,
+                max,
+                error,
+                init_gas_cost,
+                remaining_gas,
+                call_stack,
+            } => VMError::ReturnTooLarge {
+                len,
+                max,
+                error: format!("{}{}", prefix, error),
+                init_gas_cost,
+                remaining_gas,
+                call_stack,
+            },
+            err => VMError::InstanceError(format!("{}{}", prefix, err)),
+        }
+    }
+}
+
+/// Violation of the configured `StorageLimits`
+#[derive(Clone, Error, Display, Debug, PartialEq, Eq)]
+pub enum StorageLimitError {
+    /// Datastore key of {len} bytes exceeds the maximum of {max} bytes
+    KeyTooLong { len: usize, max: usize },
+    /// Empty datastore keys are not allowed
+    EmptyKey,
+    /// Datastore value of {len} bytes exceeds the maximum of {max} bytes
+    ValueTooLong { len: usize, max: usize },
+    /// Too many datastore entries: {count}, the maximum is {max}
+    TooManyEntries { count: usize, max: usize },
+}
+
+/// Merkle proof refused by the verification ABI, rather than found invalid
+#[derive(Clone, Error, Display, Debug, PartialEq, Eq)]
+pub enum MerkleProofError {
+    /// The {name} hash is {len} bytes long instead of 32
+    InvalidHashLength { name: &'static str, len: usize },
+    /// Malformed proof of {len} bytes, not a whole number of 33 bytes elements
+    MalformedProof { len: usize },
+    /// Invalid direction {direction} of the proof element {index}, expected 0 or 1
+    InvalidDirection { index: usize, direction: u8 },
+    /// Proof of depth {depth} exceeds the maximum of {max}
+    TooDeep { depth: usize, max: usize },
+    /// Unknown hash algorithm {0}
+    UnknownHash(i32),
+}
+
+/// Gas cost files that cannot be used by the runtime
+#[derive(Clone, Error, Display, Debug, PartialEq, Eq)]
+pub enum GasCostsError {
+    /// Could not read the gas cost file {path}: {error}
+    File { path: String, error: String },
+    /// Invalid gas costs, missing: {missing:?}, zero: {zero:?}, above {max}: {too_large:?}
+    Invalid {
+        /// Names of the required costs absent from the gas costs
+        missing: Vec<String>,
+        /// Names of the costs set to zero, making the corresponding step free
+        zero: Vec<String>,
+        /// Names of the costs above the sanity bound
+        too_large: Vec<String>,
+        /// Sanity bound of the ABI costs
+        max: u64,
+    },
+}
+
+/// Host extension refused by `HostExtensions::register`
+#[derive(Clone, Error, Display, Debug, PartialEq, Eq)]
+pub enum HostExtensionError {
+    /// Host extensions are disallowed by the strict flag
+    Strict,
+    /// Host extension {namespace}.{name} collides with a registered function
+    Collision { namespace: String, name: String },
+}
+
+/// Failure of `run_function_typed`
+#[derive(Error, Display, Debug)]
+pub enum TypedCallError {
+    /// Typed calls are only supported by wasmv1 modules, not by AssemblyScript ones
+    UnsupportedModuleFormat,
+    /// {0}
+    Execution(VMError),
+    /// Malformed response returned by {function}: {error}
+    MalformedResponse {
+        function: String,
+        error: prost::DecodeError,
+        /// Response holding the undecoded bytes
+        response: Response,
+    },
+}
+
+impl From<VMError> for TypedCallError {
+    fn from(value: VMError) -> Self {
+        Self::Execution(value)
+    }
 }
 
 impl From<anyhow::Error> for VMError {
@@ -18,10 +426,12 @@ impl From<anyhow::Error> for VMError {
 }
 
 macro_rules! exec_bail {
-    ($err:expr, $init_gas_cost:expr) => {
+    ($err:expr, $init_gas_cost:expr, $remaining_gas:expr, $call_stack:expr) => {
         return Err(crate::VMError::ExecutionError {
             error: $err.to_string(),
             init_gas_cost: $init_gas_cost,
+            remaining_gas: $remaining_gas,
+            call_stack: $call_stack,
         })
     };
 }
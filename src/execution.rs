@@ -1,20 +1,72 @@
-use crate::as_execution::{exec_as_module, ASModule};
-use crate::error::VMResult;
-use crate::middlewares::gas_calibration::GasCalibrationResult;
+use crate::as_execution::pool::InstancePool;
+use crate::as_execution::{exec_as_module, ABIError, ASModule};
+use crate::engine_provider::EngineProvider;
+use crate::error::{TypedCallError, VMError, VMResult};
+use crate::interrupt::InterruptHandle;
+#[cfg(feature = "calibration")]
+pub(crate) use crate::middlewares::gas_calibration::{GasCalibrationReport, GasCalibrationResult};
+use crate::observer::{ExecutionEvent, ExecutionObserver, SharedObserver};
+#[cfg(feature = "profiling")]
+use crate::profiling::Profiler;
+use crate::random::RandomState;
+use crate::read_only_cache::{CachedExecution, ReadOnlyCache, ReadOnlyCacheKey, ReadOnlyRecorder};
 use crate::settings;
-use crate::types::{Interface, Response};
-use crate::wasmv1_execution::{exec_wasmv1_module, WasmV1Module};
-use crate::GasCosts;
+use crate::types::{CallFrame, EventLimits, Interface, Reentrancy, Response, TypedResponse};
+use crate::validation::validate_contract_bytecode;
+use crate::wasmv1_execution::{exec_wasmv1_module, WasmV1Error, WasmV1Module};
+use crate::{GasCosts, RuntimeConfig};
 use anyhow::{anyhow, Result};
+use massa_proto_rs::massa::abi::v1::{CallRequest, CallResponse};
+use massa_proto_rs::massa::model::v1::Slot;
 use num_enum::{IntoPrimitive, TryFromPrimitive};
+use parking_lot::Mutex;
+use prost::Message;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt::{self, Debug};
+use std::sync::Arc;
+use std::time::Instant;
+use wasmer::FunctionType;
+
+/// Stand-in for the result of a calibrating execution, none can run without
+/// the `calibration` feature
+#[cfg(not(feature = "calibration"))]
+#[derive(Debug)]
+pub(crate) enum GasCalibrationResult {}
+
+/// Refuse `RuntimeConfig::gas_calibration` in the builds without the
+/// calibration middleware, the modules would not be instrumented
+fn check_calibration_support(config: &RuntimeConfig) -> Result<()> {
+    if config.gas_calibration && !cfg!(feature = "calibration") {
+        return Err(anyhow!(
+            "gas calibration is not supported without the calibration feature"
+        ));
+    }
+    Ok(())
+}
 
 /// Enum listing the available compilers
-#[derive(Clone)]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
 pub enum Compiler {
+    /// Cranelift, for the executions where throughput matters, only available
+    /// with the `cranelift` feature
+    #[cfg(feature = "cranelift")]
     CL,
+    /// Singlepass, whose compilation time is bounded by the bytecode size
+    #[default]
     SP,
 }
 
+/// What the ABIs are allowed to do during an execution and its nested calls
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExecutionMode {
+    #[default]
+    ReadWrite,
+    /// The ABIs mutating the state fail with `ABIError::WriteInReadOnlyMode`,
+    /// whatever the interface would do
+    ReadOnly,
+}
+
 #[derive(Clone)]
 pub enum RuntimeModule {
     ASModule(ASModule),
@@ -35,7 +87,12 @@ impl RuntimeModule {
     /// * (0): legacy AssemblyScript module
     /// * (1): new agnostic module
     /// * (_): unsupported module
-    pub fn new(bytecode: &[u8], gas_costs: GasCosts, compiler: Compiler) -> Result<Self> {
+    pub fn new(
+        bytecode: &[u8],
+        gas_costs: GasCosts,
+        config: &RuntimeConfig,
+        compiler: Compiler,
+    ) -> Result<Self> {
         if bytecode.len() <= 2 {
             return Err(anyhow!("Too small bytecode"));
         }
@@ -48,11 +105,17 @@ impl RuntimeModule {
             .unwrap(); // Safe to unwrap as we checked the bytecode length and for conversion
                        // errors
 
+        check_calibration_support(config)?;
+        validate_contract_bytecode(bytecode, config)?;
+        #[cfg(feature = "tracing")]
+        tracing::debug!(bytecode_len = bytecode.len(), ?compiler, "compilation");
+
         match module_id {
             RuntimeModuleId::ASModuleId => Ok(Self::ASModule(ASModule::new(
                 bytecode,
                 gas_costs.max_instance_cost,
                 gas_costs,
+                config,
                 compiler,
             )?)),
             RuntimeModuleId::WasmV1ModuleId => {
@@ -62,14 +125,44 @@ impl RuntimeModule {
                     &bytecode[1..],
                     gas_costs.max_instance_cost,
                     gas_costs,
+                    config,
                     compiler,
                 )
-                .map_err(|err| anyhow!("Failed to compile WasmV1 module: {}", err))?;
+                .map_err(|err| match err {
+                    // typed as for the AssemblyScript modules
+                    WasmV1Error::CompilationTimeout(max_time) => {
+                        ABIError::CompilationTimeout { max_time }.into()
+                    }
+                    err => anyhow!("Failed to compile WasmV1 module: {}", err),
+                })?;
                 Ok(Self::WasmV1Module(res))
             }
         }
     }
 
+    /// Same as `new`, the executions of an AssemblyScript module reusing the
+    /// stores and the import objects of up to `pool_size` of its previous
+    /// executions. Each execution still gets a new instance, and thus a new
+    /// linear memory.
+    ///
+    /// A `pool_size` of 0 disables the pool as in `new`, WasmV1 modules are
+    /// never pooled.
+    pub fn new_with_instance_pool(
+        bytecode: &[u8],
+        gas_costs: GasCosts,
+        config: &RuntimeConfig,
+        compiler: Compiler,
+        pool_size: usize,
+    ) -> Result<Self> {
+        let mut module = Self::new(bytecode, gas_costs, config, compiler)?;
+        if let RuntimeModule::ASModule(module) = &mut module {
+            if pool_size > 0 {
+                module.instance_pool = Some(Arc::new(InstancePool::new(pool_size)));
+            }
+        }
+        Ok(module)
+    }
+
     /// Used compiler for the current module
     pub fn compiler(&self) -> Compiler {
         match self {
@@ -78,12 +171,21 @@ impl RuntimeModule {
         }
     }
 
+    /// Whether the module was compiled to calibrate the gas costs, see
+    /// `RuntimeConfig::gas_calibration`
+    pub fn gas_calibration(&self) -> bool {
+        match self {
+            RuntimeModule::ASModule(module) => module.gas_calibration,
+            RuntimeModule::WasmV1Module(module) => module.gas_calibration,
+        }
+    }
+
     /// Serialize a RuntimeModule, prepending its byte id
     pub fn serialize(&self) -> Result<Vec<u8>> {
         let (mut ser, id) = match self {
             RuntimeModule::ASModule(module) => (module.serialize()?, RuntimeModuleId::ASModuleId),
             RuntimeModule::WasmV1Module(module) => {
-                (module.serialize(), RuntimeModuleId::WasmV1ModuleId)
+                (module.serialize()?, RuntimeModuleId::WasmV1ModuleId)
             }
         };
 
@@ -95,21 +197,41 @@ impl RuntimeModule {
     }
 
     /// Deserialize a RuntimeModule
-    pub fn deserialize(ser_module: &[u8], limit: u64, gas_costs: GasCosts) -> Result<Self> {
+    ///
+    /// Artifacts that were not produced by `serialize` with the same wasmer
+    /// version and compiler are rejected with `ABIError::IncompatibleArtifact`.
+    /// Only Cranelift modules can be serialized, hence the `cranelift` feature.
+    ///
+    /// The artifacts are native code run as is, they must only come from the
+    /// embedder's own cache. For that reason the bytecode a nested call gets
+    /// from `Interface::init_call`, and thus from the ledger, is always
+    /// compiled from wasm: it is never taken for an artifact.
+    #[cfg(feature = "cranelift")]
+    pub fn deserialize(
+        ser_module: &[u8],
+        limit: u64,
+        gas_costs: GasCosts,
+        config: &RuntimeConfig,
+    ) -> Result<Self> {
+        check_calibration_support(config)?;
         let module_id = ser_module
             .first()
             .map(|&id| RuntimeModuleId::try_from(id))
-            .transpose()?;
+            .transpose()
+            .map_err(|err| ABIError::IncompatibleArtifact(err.to_string()))?;
 
-        match module_id {
-            Some(RuntimeModuleId::ASModuleId) => Ok(RuntimeModule::ASModule(
-                ASModule::deserialize(&ser_module[1..], limit, gas_costs)?,
-            )),
-            Some(RuntimeModuleId::WasmV1ModuleId) => Ok(RuntimeModule::WasmV1Module(
-                WasmV1Module::deserialize(&ser_module[1..], limit, gas_costs)?,
-            )),
-            None => Err(anyhow!("Empty bytecode")),
-        }
+        let module = match module_id {
+            Some(RuntimeModuleId::ASModuleId) => {
+                ASModule::deserialize(&ser_module[1..], limit, gas_costs, config)
+                    .map(Self::ASModule)
+            }
+            Some(RuntimeModuleId::WasmV1ModuleId) => {
+                WasmV1Module::deserialize(&ser_module[1..], limit, gas_costs, config)
+                    .map(Self::WasmV1Module)
+            }
+            None => return Err(anyhow!("Empty bytecode")),
+        };
+        module.map_err(|err| ABIError::IncompatibleArtifact(err.to_string()).into())
     }
 
     /// Check the exports of a compiled module to see if it contains the given
@@ -122,6 +244,484 @@ impl RuntimeModule {
     }
 }
 
+/// Position of an execution in the call tree
+#[derive(Clone, Debug, Default)]
+pub(crate) struct CallContext {
+    /// Number of nested calls leading to the execution, 0 being the top-level
+    /// one
+    pub depth: u32,
+    /// Calls leading to the execution, the innermost last. Only the last
+    /// `settings::max_call_stack_frames` are kept.
+    pub stack: Vec<CallFrame>,
+    /// Call stack of the top-level execution as reported by the interface,
+    /// the calls made from there are in `stack`. Only set once a call was made.
+    pub origin: Option<Arc<Vec<String>>>,
+    /// Address and function of each call made from the top-level execution
+    /// down to the current one. Unlike `stack` it is never truncated.
+    pub calls: Vec<(String, String)>,
+    /// Function run by the top-level execution, set when it starts
+    pub root_function: Arc<Mutex<Option<String>>>,
+    /// Instant after which the whole call tree is aborted
+    pub deadline: Option<Instant>,
+    /// Events emitted by the whole call tree
+    pub events: Arc<Mutex<EventCounter>>,
+    /// Random seed of the whole call tree and its expansions
+    pub random: Arc<Mutex<RandomState>>,
+    /// Slot of the whole call tree, asked once to the interface so that all
+    /// its reads agree
+    pub slot: Arc<Mutex<Option<Slot>>>,
+    /// Bytes copied from the host to the guest memories by the whole call
+    /// tree
+    pub host_data: Arc<Mutex<usize>>,
+    /// Gas credited back by the storage refunds of the whole call tree
+    pub storage_refunds: Arc<Mutex<u64>>,
+    /// Gas used by each address called in the whole call tree, in the order
+    /// of their first call. Only set with `RuntimeConfig::collect_gas_breakdown`.
+    pub gas_breakdown: Option<Arc<Mutex<Vec<(String, u64)>>>>,
+    /// Calibration results of the successful executions of the whole call
+    /// tree, with their depth. Only set by `run_main_gas_calibration`.
+    #[cfg(feature = "calibration")]
+    pub calibrations: Option<Arc<Mutex<Vec<(u32, GasCalibrationResult)>>>>,
+    /// Recorder shared by every execution of the call tree
+    #[cfg(feature = "profiling")]
+    pub profiler: Profiler,
+    /// Compiler of the top-level module, the modules of the nested calls are
+    /// compiled with it
+    pub compiler: Compiler,
+    /// Mode of the whole call tree
+    pub mode: ExecutionMode,
+    /// Observer of the whole call tree
+    pub observer: Option<SharedObserver>,
+    /// Handle interrupting the whole call tree
+    pub interrupt: Option<InterruptHandle>,
+    /// Recorder of the call tree when it is run by `run_read_only_cached`
+    pub read_only: Option<ReadOnlyRecorder>,
+    /// Engines of the stores of the whole call tree, given by the embedder to
+    /// reuse them across executions
+    pub engines: EngineProvider,
+    /// Settings of the whole call tree
+    pub config: Arc<RuntimeConfig>,
+}
+
+impl CallContext {
+    /// Context of a top-level execution of a module compiled with `compiler`
+    pub(crate) fn root(config: &RuntimeConfig, compiler: Compiler, mode: ExecutionMode) -> Self {
+        Self {
+            deadline: config
+                .max_execution_time
+                .and_then(|time| Instant::now().checked_add(time)),
+            gas_breakdown: config.collect_gas_breakdown.then(Default::default),
+            compiler,
+            mode,
+            config: Arc::new(config.clone()),
+            ..Default::default()
+        }
+    }
+
+    /// Context of an execution nested in this one at the given `depth`
+    pub(crate) fn nested(&self, depth: u32, frame: Option<CallFrame>) -> Self {
+        let mut stack = self.stack.clone();
+        if let Some(frame) = frame {
+            stack.push(frame);
+        }
+        let overflow = stack
+            .len()
+            .saturating_sub(settings::max_call_stack_frames());
+        stack.drain(..overflow);
+        Self {
+            depth,
+            stack,
+            origin: self.origin.clone(),
+            calls: self.calls.clone(),
+            root_function: self.root_function.clone(),
+            deadline: self.deadline,
+            events: self.events.clone(),
+            random: self.random.clone(),
+            slot: self.slot.clone(),
+            host_data: self.host_data.clone(),
+            storage_refunds: self.storage_refunds.clone(),
+            gas_breakdown: self.gas_breakdown.clone(),
+            #[cfg(feature = "calibration")]
+            calibrations: self.calibrations.clone(),
+            #[cfg(feature = "profiling")]
+            profiler: self.profiler.clone(),
+            compiler: self.compiler.clone(),
+            mode: self.mode,
+            observer: self.observer.clone(),
+            interrupt: self.interrupt.clone(),
+            read_only: self.read_only.clone(),
+            engines: self.engines.clone(),
+            config: self.config.clone(),
+        }
+    }
+
+    /// Context of a call to the smart contract of `frame`.
+    ///
+    /// Must be built before the interface is told about the call, the origin
+    /// of the call tree is read from it on its first call.
+    pub(crate) fn nested_call(
+        &self,
+        depth: u32,
+        frame: CallFrame,
+        interface: &dyn Interface,
+    ) -> Result<Self> {
+        let origin = match &self.origin {
+            Some(origin) => origin.clone(),
+            None => Arc::new(interface.get_call_stack()?),
+        };
+        let mut calls = self.calls.clone();
+        calls.push((frame.address.clone(), frame.function.clone()));
+        Ok(Self {
+            origin: Some(origin),
+            calls,
+            ..self.nested(depth, Some(frame))
+        })
+    }
+
+    /// Add `gas_used` by a call to `address` to the breakdown of the call
+    /// tree, if it is collected
+    pub(crate) fn record_callee_gas(&self, address: &str, gas_used: u64) {
+        let Some(breakdown) = &self.gas_breakdown else {
+            return;
+        };
+        let mut breakdown = breakdown.lock();
+        match breakdown.iter_mut().find(|(callee, _)| callee == address) {
+            Some((_, gas)) => *gas = gas.saturating_add(gas_used),
+            None => breakdown.push((address.to_string(), gas_used)),
+        }
+    }
+
+    /// Addresses of the call stack, from the origin down to the current smart
+    /// contract
+    pub(crate) fn call_stack(&self, interface: &dyn Interface) -> Result<Vec<String>> {
+        let mut addresses = match &self.origin {
+            Some(origin) => origin.to_vec(),
+            None => interface.get_call_stack()?,
+        };
+        addresses.extend(self.stack.iter().map(|frame| frame.address.clone()));
+        Ok(addresses)
+    }
+
+    /// Fails if the call of this context, built by `nested_call`, re-enters
+    /// a smart contract of the call stack in a way `reentrancy` forbids. The
+    /// top-level smart contract is the last address of the origin.
+    pub(crate) fn check_reentrancy(&self, reentrancy: Reentrancy) -> Result<(), ABIError> {
+        let Some(((address, function), callers)) = self.calls.split_last() else {
+            return Ok(());
+        };
+        let origin = self
+            .origin
+            .as_deref()
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let denied = match reentrancy {
+            Reentrancy::Allow => false,
+            Reentrancy::Deny => {
+                origin.contains(address) || callers.iter().any(|(caller, _)| caller == address)
+            }
+            Reentrancy::DenySameFunction => {
+                callers.iter().any(|(caller, caller_function)| {
+                    caller == address && caller_function == function
+                }) || (origin.last() == Some(address)
+                    && self.root_function.lock().as_ref() == Some(function))
+            }
+        };
+        if denied {
+            return Err(ABIError::ReentrancyDenied {
+                address: address.clone(),
+                function: function.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Fails if the ABI `abi_name`, which mutates the state, is called in
+    /// read-only mode
+    pub(crate) fn check_write(&self, abi_name: &str) -> Result<(), ABIError> {
+        match self.mode {
+            ExecutionMode::ReadWrite => Ok(()),
+            ExecutionMode::ReadOnly => Err(ABIError::WriteInReadOnlyMode(abi_name.to_string())),
+        }
+    }
+
+    /// Slot of the call tree, asked to `interface` on the first read
+    pub(crate) fn current_slot(&self, interface: &dyn Interface) -> Result<Slot> {
+        let mut slot = self.slot.lock();
+        if let Some(slot) = &*slot {
+            return Ok(slot.clone());
+        }
+        let current = interface.get_current_slot()?;
+        *slot = Some(current.clone());
+        Ok(current)
+    }
+
+    /// Account for `len` bytes about to be copied from the host to a guest
+    /// memory, fails if the call tree would copy more than `max` bytes
+    pub(crate) fn record_host_data(&self, len: usize, max: usize) -> Result<(), ABIError> {
+        let mut host_data = self.host_data.lock();
+        let total = host_data.saturating_add(len);
+        if total > max {
+            return Err(ABIError::HostDataLimitExceeded { len, total, max });
+        }
+        *host_data = total;
+        Ok(())
+    }
+
+    /// Gas credited back by the storage refunds of the whole call tree so far
+    pub(crate) fn refunded_gas(&self) -> u64 {
+        *self.storage_refunds.lock()
+    }
+
+    /// Part of the refund `amount` credited back to an execution which spent
+    /// `gas_spent`, the refunds of its nested calls included. The refunds of
+    /// the whole call tree stay within `max_percent` of it, so that a
+    /// contract cannot get more gas back than it paid for by nesting calls.
+    pub(crate) fn storage_refund(&self, amount: u64, gas_spent: u64, max_percent: u64) -> u64 {
+        let mut refunded = self.storage_refunds.lock();
+        let refund = capped_refund(amount, gas_spent, *refunded, max_percent);
+        *refunded += refund;
+        refund
+    }
+
+    /// Report the event built by `event` to the observer of the call tree, if
+    /// there is one
+    pub(crate) fn observe(&self, event: impl FnOnce() -> ExecutionEvent) {
+        if let Some(observer) = &self.observer {
+            observer.on_event(self.depth, event());
+        }
+    }
+
+    /// Give `record` the recorder of the call tree, if it is run by
+    /// `run_read_only_cached`
+    pub(crate) fn record_read_only(&self, record: impl FnOnce(&ReadOnlyRecorder)) {
+        if let Some(recorder) = &self.read_only {
+            record(recorder);
+        }
+    }
+
+    /// Fails if the deadline of the call tree has passed or if it was
+    /// interrupted
+    pub(crate) fn check_deadline(&self) -> Result<(), ABIError> {
+        if let Some(interrupt) = &self.interrupt {
+            if interrupt.is_interrupted() {
+                return Err(ABIError::Interrupted);
+            }
+        }
+        match self.deadline {
+            Some(deadline) if Instant::now() >= deadline => Err(ABIError::ExecutionTimeout),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn capped_refund(amount: u64, gas_spent: u64, already_refunded: u64, max_percent: u64) -> u64 {
+    let max_refund = (gas_spent as u128 * max_percent as u128 / 100) as u64;
+    amount.min(max_refund.saturating_sub(already_refunded))
+}
+
+/// Events emitted by a call tree, checked against its `EventLimits`
+#[derive(Clone, Debug, Default)]
+pub(crate) struct EventCounter {
+    count: usize,
+    data_size: usize,
+    /// Bytes of the last event, within the `max_event_data_size` of the
+    /// limits
+    last: Vec<u8>,
+}
+
+impl EventCounter {
+    /// Account for an event of `size` bytes, fails if it goes over the
+    /// `limits`. `data` is kept as the last event of the call tree. Returns
+    /// the index of the event in the call tree.
+    pub(crate) fn record(
+        &mut self,
+        limits: &EventLimits,
+        size: usize,
+        data: &[u8],
+    ) -> Result<u64, ABIError> {
+        if self.count >= limits.max_event_count {
+            return Err(ABIError::TooManyEvents(limits.max_event_count));
+        }
+        let data_size = self.data_size.saturating_add(size);
+        if data_size > limits.max_event_data_size {
+            return Err(ABIError::EventDataTooLarge {
+                size: data_size,
+                max: limits.max_event_data_size,
+            });
+        }
+        let index = self.count as u64;
+        self.count += 1;
+        self.data_size = data_size;
+        self.last.clear();
+        self.last.extend_from_slice(data);
+        Ok(index)
+    }
+
+    /// Number of events recorded so far
+    pub(crate) fn count(&self) -> u64 {
+        self.count as u64
+    }
+
+    /// Bytes of the last event recorded, if any
+    pub(crate) fn last(&self) -> Option<&[u8]> {
+        (self.count > 0).then_some(self.last.as_slice())
+    }
+}
+
+/// Answers of the interface about the access rights of an execution, which
+/// cannot change before it returns.
+///
+/// Each execution has its own: the caller differs between the frames of a
+/// call tree.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct AccessCache {
+    write_access: Option<bool>,
+    eoa: HashMap<String, bool>,
+}
+
+impl AccessCache {
+    /// `Interface::caller_has_write_access`, only asked once
+    pub(crate) fn caller_has_write_access(&mut self, interface: &dyn Interface) -> Result<bool> {
+        if let Some(write_access) = self.write_access {
+            return Ok(write_access);
+        }
+        let write_access = interface.caller_has_write_access()?;
+        self.write_access = Some(write_access);
+        Ok(write_access)
+    }
+
+    /// `Interface::is_address_eoa`, only asked once per address
+    pub(crate) fn is_address_eoa(
+        &mut self,
+        interface: &dyn Interface,
+        address: &str,
+    ) -> Result<bool> {
+        if let Some(&is_eoa) = self.eoa.get(address) {
+            return Ok(is_eoa);
+        }
+        let is_eoa = interface.is_address_eoa(address)?;
+        self.eoa.insert(address.to_string(), is_eoa);
+        Ok(is_eoa)
+    }
+}
+
+/// Conversions between public keys and addresses answered by the interface
+/// for an execution, keyed by the sha256 of their input.
+///
+/// They depend on their input alone, so a conversion is only asked once;
+/// the failed ones are not kept. At most `settings::max_cached_conversions`
+/// answers of each kind are kept.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ConversionCache {
+    addresses: HashMap<[u8; 32], String>,
+    addresses_from_bytes: HashMap<[u8; 32], String>,
+    public_keys: HashMap<[u8; 32], Vec<u8>>,
+    valid_addresses: HashMap<[u8; 32], bool>,
+}
+
+/// Answer of `convert` for `input`, from `answers` if it was asked before
+fn cached_conversion<T: Clone>(
+    answers: &mut HashMap<[u8; 32], T>,
+    input: &[u8],
+    convert: impl FnOnce() -> Result<T>,
+) -> Result<T> {
+    let key: [u8; 32] = Sha256::digest(input).into();
+    if let Some(answer) = answers.get(&key) {
+        return Ok(answer.clone());
+    }
+    let answer = convert()?;
+    if answers.len() < settings::max_cached_conversions() {
+        answers.insert(key, answer.clone());
+    }
+    Ok(answer)
+}
+
+impl ConversionCache {
+    /// `Interface::address_from_public_key`, only asked once per public key
+    pub(crate) fn address_from_public_key(
+        &mut self,
+        interface: &dyn Interface,
+        public_key: &str,
+    ) -> Result<String> {
+        cached_conversion(&mut self.addresses, public_key.as_bytes(), || {
+            interface.address_from_public_key(public_key)
+        })
+    }
+
+    /// `Interface::address_from_public_key_bytes`, only asked once per public
+    /// key
+    pub(crate) fn address_from_public_key_bytes(
+        &mut self,
+        interface: &dyn Interface,
+        public_key: &[u8],
+    ) -> Result<String> {
+        cached_conversion(&mut self.addresses_from_bytes, public_key, || {
+            interface.address_from_public_key_bytes(public_key)
+        })
+    }
+
+    /// `Interface::public_key_from_string`, only asked once per public key
+    pub(crate) fn public_key_from_string(
+        &mut self,
+        interface: &dyn Interface,
+        public_key: &str,
+    ) -> Result<Vec<u8>> {
+        cached_conversion(&mut self.public_keys, public_key.as_bytes(), || {
+            interface.public_key_from_string(public_key)
+        })
+    }
+
+    /// `Interface::validate_address`, only asked once per address
+    pub(crate) fn validate_address(
+        &mut self,
+        interface: &dyn Interface,
+        address: &str,
+    ) -> Result<bool> {
+        cached_conversion(&mut self.valid_addresses, address.as_bytes(), || {
+            interface.validate_address(address)
+        })
+    }
+}
+
+/// Span of an execution, a `call` span nested in the span of its caller for
+/// the nested calls. Only the lengths of the data are recorded.
+#[cfg(feature = "tracing")]
+fn execution_span(
+    interface: &dyn Interface,
+    function: &str,
+    param: &[u8],
+    limit: u64,
+    call_context: &CallContext,
+) -> tracing::Span {
+    // a local execution has no frame of its own and runs as its caller
+    let address = match call_context.stack.last() {
+        Some(frame) => frame.address.clone(),
+        None => interface
+            .get_call_stack()
+            .ok()
+            .and_then(|mut stack| stack.pop())
+            .unwrap_or_default(),
+    };
+    if call_context.depth > 0 {
+        tracing::info_span!(
+            "call",
+            address = %address,
+            function,
+            depth = call_context.depth,
+            param_len = param.len(),
+            gas_limit = limit,
+        )
+    } else {
+        tracing::info_span!(
+            "execution",
+            address = %address,
+            function,
+            param_len = param.len(),
+            gas_limit = limit,
+        )
+    }
+}
+
 /// Select and launch the adequate execution function
 pub(crate) fn exec(
     interface: &dyn Interface,
@@ -130,21 +730,189 @@ pub(crate) fn exec(
     param: &[u8],
     limit: u64,
     gas_costs: GasCosts,
+    call_context: CallContext,
 ) -> VMResult<(Response, Option<GasCalibrationResult>)> {
+    #[cfg(feature = "tracing")]
+    let _span = execution_span(interface, function, param, limit, &call_context).entered();
+    let Some(observer) = call_context.observer.clone() else {
+        return exec_module(
+            interface,
+            rt_module,
+            function,
+            param,
+            limit,
+            gas_costs,
+            call_context,
+        );
+    };
+    let depth = call_context.depth;
+    observer.on_event(
+        depth,
+        ExecutionEvent::ExecutionStarted {
+            function: function.to_string(),
+            gas_limit: limit,
+        },
+    );
+    let result = exec_module(
+        interface,
+        rt_module,
+        function,
+        param,
+        limit,
+        gas_costs,
+        call_context,
+    );
+    let (remaining_gas, error) = match &result {
+        Ok((response, _)) => (response.remaining_gas, None),
+        Err(err) => (
+            limit.saturating_sub(err.gas_used(limit)),
+            Some(err.to_string()),
+        ),
+    };
+    observer.on_event(
+        depth,
+        ExecutionEvent::ExecutionFinished {
+            function: function.to_string(),
+            remaining_gas,
+            error,
+        },
+    );
+    result
+}
+
+fn exec_module(
+    interface: &dyn Interface,
+    rt_module: RuntimeModule,
+    function: &str,
+    param: &[u8],
+    limit: u64,
+    gas_costs: GasCosts,
+    call_context: CallContext,
+) -> VMResult<(Response, Option<GasCalibrationResult>)> {
+    // the nested executions share the interface of their call tree
+    if call_context.depth == 0 {
+        check_interface_version(interface)?;
+        *call_context.root_function.lock() = Some(function.to_string());
+    }
+    let config = &call_context.config;
+    if param.len() > config.max_param_size {
+        return Err(VMError::ParamTooLarge {
+            len: param.len(),
+            max: config.max_param_size,
+        });
+    }
+    // the instrumentation of the module depends on the mode
+    if rt_module.gas_calibration() != config.gas_calibration {
+        return Err(VMError::InstanceError(format!(
+            "module compiled with gas_calibration = {} run with gas_calibration = {}",
+            rt_module.gas_calibration(),
+            config.gas_calibration
+        )));
+    }
+    #[cfg(feature = "calibration")]
+    let (depth, calibrations) = (call_context.depth, call_context.calibrations.clone());
     let response = match rt_module {
-        RuntimeModule::ASModule(module) => {
-            exec_as_module(interface, module, function, param, limit, gas_costs)?
-        }
-        RuntimeModule::WasmV1Module(module) => {
-            exec_wasmv1_module(interface, module, function, param, limit, gas_costs)
-                .map_err(|err| anyhow!("Failed to execute WasmV1 module: {}", err.to_string()))?
-        }
+        RuntimeModule::ASModule(module) => exec_as_module(
+            interface,
+            module,
+            function,
+            param,
+            limit,
+            gas_costs,
+            call_context,
+        )?,
+        RuntimeModule::WasmV1Module(module) => exec_wasmv1_module(
+            interface,
+            module,
+            function,
+            param,
+            limit,
+            gas_costs,
+            call_context,
+        )
+        .map_err(|err| err.with_prefix("Failed to execute WasmV1 module: "))?,
     };
+    #[cfg(feature = "calibration")]
+    if let (Some(calibrations), Some(result)) = (calibrations, &response.1) {
+        calibrations.lock().push((depth, result.clone()));
+    }
     Ok(response)
 }
 
+/// Check that `module` exports `function` with a signature accepted by
+/// `callable`, the exports it could be called instead being returned otherwise
+pub(crate) fn check_exported_function(
+    module: &wasmer::Module,
+    function: &str,
+    callable: impl Fn(&FunctionType) -> bool,
+) -> Result<(), Vec<String>> {
+    let mut available = Vec::new();
+    for export in module.exports().functions() {
+        if callable(export.ty()) {
+            if export.name() == function {
+                return Ok(());
+            }
+            available.push(export.name().to_string());
+        }
+    }
+    Err(available)
+}
+
+/// Refuse an `interface` whose version is not supported by the runtime
+pub(crate) fn check_interface_version(interface: &dyn Interface) -> VMResult<()> {
+    let got = interface.interface_version();
+    let supported = settings::MIN_INTERFACE_VERSION..=settings::INTERFACE_VERSION;
+    if !supported.contains(&got) {
+        return Err(VMError::IncompatibleInterface { got, supported });
+    }
+    Ok(())
+}
+
+/// Options of a top-level execution, shared by its nested calls
+#[derive(Clone, Default)]
+pub struct ExecutionOptions {
+    /// In `ExecutionMode::ReadOnly` the ABIs mutating the state fail before
+    /// they reach the interface, calls with coins included
+    pub mode: ExecutionMode,
+    /// Observer of the events of the execution and its nested calls
+    pub observer: Option<Arc<dyn ExecutionObserver>>,
+    /// Once fired, the execution fails with `ABIError::Interrupted` at the
+    /// next ABI call or loop header of the execution or of its nested calls
+    pub interrupt: Option<InterruptHandle>,
+    /// Provider of the engines of the stores, kept by the embedder to reuse
+    /// them across executions. Without one, each call tree builds its own.
+    pub engines: Option<EngineProvider>,
+}
+
+impl ExecutionOptions {
+    /// Context of the top-level execution of a module compiled with
+    /// `compiler`
+    pub(crate) fn call_context(self, config: &RuntimeConfig, compiler: Compiler) -> CallContext {
+        CallContext {
+            observer: self.observer.map(SharedObserver),
+            interrupt: self.interrupt,
+            engines: self.engines.unwrap_or_default(),
+            ..CallContext::root(config, compiler, self.mode)
+        }
+    }
+}
+
+impl Debug for ExecutionOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ExecutionOptions")
+            .field("mode", &self.mode)
+            .field("observer", &self.observer.is_some())
+            .field("interrupt", &self.interrupt)
+            .field("engines", &self.engines.is_some())
+            .finish()
+    }
+}
+
 /// Library Input, take a `module` wasm built with the massa environment,
-/// must have a main function inside written in AssemblyScript:
+/// must have a main function inside written in AssemblyScript.
+///
+/// The modules of the nested calls are compiled with the compiler of
+/// `rt_module`, see `Interface::get_module_with_compiler`:
 ///
 /// ```js
 /// import { print } from "massa-sc-std";
@@ -161,8 +929,37 @@ pub fn run_main(
     rt_module: RuntimeModule,
     limit: u64,
     gas_costs: GasCosts,
+    config: &RuntimeConfig,
+    options: ExecutionOptions,
 ) -> VMResult<Response> {
-    Ok(exec(interface, rt_module, settings::MAIN, b"", limit, gas_costs)?.0)
+    run_main_with_param(interface, rt_module, b"", limit, gas_costs, config, options)
+}
+
+/// Same as `run_main` with a parameter given to `main` the way `run_function`
+/// does, e.g. the arguments of a constructor.
+///
+/// The bytes returned by `main` are in `Response::ret`, except for the
+/// AssemblyScript modules given an empty `param`: as in `run_main`, what
+/// their `main` returns is ignored.
+pub fn run_main_with_param(
+    interface: &dyn Interface,
+    rt_module: RuntimeModule,
+    param: &[u8],
+    limit: u64,
+    gas_costs: GasCosts,
+    config: &RuntimeConfig,
+    options: ExecutionOptions,
+) -> VMResult<Response> {
+    run_function(
+        interface,
+        rt_module,
+        settings::MAIN,
+        param,
+        limit,
+        gas_costs,
+        config,
+        options,
+    )
 }
 
 /// Library Input, take a `module` wasm built with the massa environment,
@@ -176,6 +973,11 @@ pub fn run_main(
 ///     return 0;
 /// }
 /// ```
+///
+/// Executions can run concurrently on several threads, each with its own
+/// interface: the modules, the gas costs, the configuration and the caches
+/// they share are `Send + Sync`.
+#[allow(clippy::too_many_arguments)]
 pub fn run_function(
     interface: &dyn Interface,
     rt_module: RuntimeModule,
@@ -183,40 +985,262 @@ pub fn run_function(
     param: &[u8],
     limit: u64,
     gas_costs: GasCosts,
+    config: &RuntimeConfig,
+    options: ExecutionOptions,
+) -> VMResult<Response> {
+    let call_context = options.call_context(config, rt_module.compiler());
+    #[cfg(feature = "profiling")]
+    let profiler = call_context.profiler.clone();
+    let gas_breakdown = call_context.gas_breakdown.clone();
+    let response = exec(
+        interface,
+        rt_module,
+        function,
+        param,
+        limit,
+        gas_costs,
+        call_context,
+    )?
+    .0;
+    Ok(Response {
+        gas_breakdown: take_gas_breakdown(gas_breakdown),
+        #[cfg(feature = "profiling")]
+        profile: profiler.take(),
+        ..response
+    })
+}
+
+/// Same as `run_function` in `ExecutionMode::ReadOnly`, for the
+/// smart contract at `address` whose bytecode is `bytecode`. The execution is
+/// looked up in `cache` before the bytecode is compiled and added to it
+/// afterwards, see `crate::read_only_cache`.
+///
+/// `ledger_version` identifies the state of the ledger the call runs
+/// against, the embedder has to give another one whenever that state changes.
+/// The stores of the call tree run on the engines of `engines` as in
+/// `ExecutionOptions::engines`.
+#[allow(clippy::too_many_arguments)]
+pub fn run_read_only_cached(
+    interface: &dyn Interface,
+    cache: &dyn ReadOnlyCache,
+    address: &str,
+    bytecode: &[u8],
+    compiler: Compiler,
+    function: &str,
+    param: &[u8],
+    ledger_version: &[u8],
+    limit: u64,
+    gas_costs: GasCosts,
+    config: &RuntimeConfig,
+    engines: Option<EngineProvider>,
 ) -> VMResult<Response> {
-    Ok(exec(interface, rt_module, function, param, limit, gas_costs)?.0)
+    check_interface_version(interface)?;
+    let key = ReadOnlyCacheKey::new(
+        address,
+        function,
+        param,
+        limit,
+        ledger_version,
+        compiler.clone(),
+        &gas_costs,
+        config,
+    );
+    if let Some(cached) = cache.get(&key) {
+        cached.replay_events(interface).map_err(|err| {
+            VMError::InstanceError(format!("Could not emit the cached events: {}", err))
+        })?;
+        return Ok(cached.response);
+    }
+    let rt_module = RuntimeModule::new(bytecode, gas_costs.clone(), config, compiler.clone())
+        .map_err(|err| VMError::InstanceError(err.to_string()))?;
+    let recorder = ReadOnlyRecorder::default();
+    let options = ExecutionOptions {
+        mode: ExecutionMode::ReadOnly,
+        engines,
+        ..Default::default()
+    };
+    let call_context = CallContext {
+        read_only: Some(recorder.clone()),
+        ..options.call_context(config, compiler)
+    };
+    #[cfg(feature = "profiling")]
+    let profiler = call_context.profiler.clone();
+    let gas_breakdown = call_context.gas_breakdown.clone();
+    let (response, _) = exec(
+        interface,
+        rt_module,
+        function,
+        param,
+        limit,
+        gas_costs,
+        call_context,
+    )?;
+    let response = Response {
+        gas_breakdown: take_gas_breakdown(gas_breakdown),
+        #[cfg(feature = "profiling")]
+        profile: profiler.take(),
+        ..response
+    };
+    if let Ok(events) = recorder.finish() {
+        cache.insert(
+            key,
+            CachedExecution {
+                response: response.clone(),
+                events,
+            },
+        );
+    }
+    Ok(response)
 }
 
-/// Same as run_main but return a GasCalibrationResult
-#[cfg(feature = "gas_calibration")]
+/// Same as `run_function` for the wasmv1 modules, with the call and its
+/// response wrapped in the envelopes of `abi_call`.
+///
+/// `function` is given an encoded `CallRequest` naming it and holding `param`
+/// as `function_arg`, its address being left empty as the module is not run
+/// through a call. It must return an encoded `CallResponse`, which is
+/// decoded into the `TypedResponse`. AssemblyScript modules are rejected with
+/// `TypedCallError::UnsupportedModuleFormat` before being run.
+pub fn run_function_typed(
+    interface: &dyn Interface,
+    rt_module: RuntimeModule,
+    function: &str,
+    param: &[u8],
+    limit: u64,
+    gas_costs: GasCosts,
+    config: &RuntimeConfig,
+) -> Result<TypedResponse, TypedCallError> {
+    if let RuntimeModule::ASModule(_) = rt_module {
+        return Err(TypedCallError::UnsupportedModuleFormat);
+    }
+    let request = CallRequest {
+        target_sc_address: String::new(),
+        target_function_name: function.to_string(),
+        function_arg: param.to_vec(),
+        call_coins: None,
+    }
+    .encode_to_vec();
+    let call_context = CallContext::root(config, rt_module.compiler(), ExecutionMode::ReadWrite);
+    let events = call_context.events.clone();
+    let (response, _) = exec(
+        interface,
+        rt_module,
+        function,
+        &request,
+        limit,
+        gas_costs,
+        call_context,
+    )?;
+    match CallResponse::decode(response.ret.as_slice()) {
+        Ok(call_response) => Ok(TypedResponse {
+            data: call_response.data,
+            events_count: events.lock().count(),
+            remaining_gas: response.remaining_gas,
+            init_gas_cost: response.init_gas_cost,
+        }),
+        Err(error) => Err(TypedCallError::MalformedResponse {
+            function: function.to_string(),
+            error,
+            response,
+        }),
+    }
+}
+
+/// Gas breakdown of a finished call tree, for the response of its top-level
+/// execution
+fn take_gas_breakdown(
+    breakdown: Option<Arc<Mutex<Vec<(String, u64)>>>>,
+) -> Option<Vec<(String, u64)>> {
+    breakdown.map(|breakdown| std::mem::take(&mut *breakdown.lock()))
+}
+
+/// Same as run_main but return a GasCalibrationResult, `rt_module` must have
+/// been compiled with `RuntimeConfig::gas_calibration`. A non-calibrating
+/// configuration fails with `VMError::InstanceError`.
+#[cfg(feature = "calibration")]
 pub fn run_main_gc(
     interface: &dyn Interface,
     rt_module: RuntimeModule,
     param: &[u8],
     limit: u64,
     gas_costs: GasCosts,
+    config: &RuntimeConfig,
 ) -> VMResult<GasCalibrationResult> {
-    Ok(exec(
+    if !config.gas_calibration {
+        return Err(VMError::InstanceError(
+            "a calibration result requires RuntimeConfig::gas_calibration".to_string(),
+        ));
+    }
+    let call_context = CallContext::root(config, rt_module.compiler(), ExecutionMode::ReadWrite);
+    exec(
         interface,
         rt_module,
         settings::MAIN,
         param,
         limit,
         gas_costs,
+        call_context,
     )?
     .1
-    .unwrap())
+    .ok_or_else(|| {
+        VMError::InstanceError("the execution did not calibrate the gas costs".to_string())
+    })
+}
+
+/// Same as run_main but report the calibration counts of the whole call tree,
+/// the successful nested calls included. `rt_module` must have been compiled
+/// with `RuntimeConfig::gas_calibration`.
+#[cfg(feature = "calibration")]
+pub fn run_main_gas_calibration(
+    interface: &dyn Interface,
+    rt_module: RuntimeModule,
+    param: &[u8],
+    limit: u64,
+    gas_costs: GasCosts,
+    config: &RuntimeConfig,
+) -> VMResult<GasCalibrationReport> {
+    if !config.gas_calibration {
+        return Err(VMError::InstanceError(
+            "a calibration report requires RuntimeConfig::gas_calibration".to_string(),
+        ));
+    }
+    let calibrations = Arc::new(Mutex::new(Vec::new()));
+    let call_context = CallContext {
+        calibrations: Some(calibrations.clone()),
+        ..CallContext::root(config, rt_module.compiler(), ExecutionMode::ReadWrite)
+    };
+    let start = Instant::now();
+    exec(
+        interface,
+        rt_module,
+        settings::MAIN,
+        param,
+        limit,
+        gas_costs,
+        call_context,
+    )?;
+    let wall_time = start.elapsed();
+    let calibrations = calibrations.lock();
+    Ok(GasCalibrationReport::new(&calibrations, wall_time))
 }
 
 // tests for serialize and deserialize
 #[test]
+#[cfg(feature = "cranelift")]
 fn test_serialize_deserialize() {
     let bytecode = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/wasm/dummy.wat"));
 
     // ASModule
     {
         let module = RuntimeModule::ASModule(
-            ASModule::new(bytecode, 0, GasCosts::default(), Compiler::CL).unwrap(),
+            ASModule::new(
+                bytecode,
+                0,
+                GasCosts::default(),
+                &RuntimeConfig::default(),
+                Compiler::CL,
+            )
+            .unwrap(),
         );
 
         let serialized = module.serialize().unwrap();
@@ -225,10 +1249,15 @@ fn test_serialize_deserialize() {
             RuntimeModuleId::ASModuleId as u8
         );
 
-        let serialized2 = RuntimeModule::deserialize(&serialized, 0, GasCosts::default())
-            .unwrap()
-            .serialize()
-            .unwrap();
+        let serialized2 = RuntimeModule::deserialize(
+            &serialized,
+            0,
+            GasCosts::default(),
+            &RuntimeConfig::default(),
+        )
+        .unwrap()
+        .serialize()
+        .unwrap();
 
         assert_eq!(serialized, serialized2);
     }
@@ -236,7 +1265,14 @@ fn test_serialize_deserialize() {
     // WasmV1Module
     {
         let module = RuntimeModule::WasmV1Module(
-            WasmV1Module::compile(bytecode, 0, GasCosts::default(), Compiler::CL).unwrap(),
+            WasmV1Module::compile(
+                bytecode,
+                0,
+                GasCosts::default(),
+                &RuntimeConfig::default(),
+                Compiler::CL,
+            )
+            .unwrap(),
         );
 
         let serialized = module.serialize().unwrap();
@@ -245,11 +1281,49 @@ fn test_serialize_deserialize() {
             RuntimeModuleId::WasmV1ModuleId as u8
         );
 
-        let serialized2 = RuntimeModule::deserialize(&serialized, 0, GasCosts::default())
-            .unwrap()
-            .serialize()
-            .unwrap();
+        let serialized2 = RuntimeModule::deserialize(
+            &serialized,
+            0,
+            GasCosts::default(),
+            &RuntimeConfig::default(),
+        )
+        .unwrap()
+        .serialize()
+        .unwrap();
 
         assert_eq!(serialized, serialized2);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capped_refund() {
+        // under the cap
+        assert_eq!(capped_refund(10, 1000, 0, 20), 10);
+        // exactly at the cap
+        assert_eq!(capped_refund(200, 1000, 0, 20), 200);
+        // over the cap
+        assert_eq!(capped_refund(300, 1000, 0, 20), 200);
+        // previous refunds count against the cap
+        assert_eq!(capped_refund(100, 1000, 150, 20), 50);
+        assert_eq!(capped_refund(100, 1000, 200, 20), 0);
+        // no refund without a cap
+        assert_eq!(capped_refund(100, 1000, 0, 0), 0);
+        assert_eq!(capped_refund(u64::MAX, u64::MAX, 0, 100), u64::MAX);
+    }
+
+    #[test]
+    fn test_storage_refund_shared() {
+        let root = CallContext::default();
+        let nested = root.nested(1, None);
+        assert_eq!(root.storage_refund(150, 1000, 20), 150);
+        // the refunds of the caller count against the cap of a nested call
+        assert_eq!(nested.storage_refund(100, 1000, 20), 50);
+        assert_eq!(root.storage_refund(100, 1000, 20), 0);
+        assert_eq!(root.refunded_gas(), 200);
+        assert_eq!(nested.refunded_gas(), 200);
+    }
+}
@@ -0,0 +1,191 @@
+//! Host functions added by the embedder to the imports of the executions,
+//! for the sideband tooling such as local sandboxes and debuggers.
+//!
+//! The extensions are defined for the AssemblyScript and the wasmv1 modules
+//! alike, after the ABI filter is applied. They are not charged, they cannot
+//! replace an ABI of the runtime, and a strict set refuses them altogether so
+//! that a production node cannot enable them by mistake.
+
+use crate::as_execution::abi_name;
+use crate::error::{HostExtensionError, VMError, VMResult};
+use crate::GasCosts;
+use std::fmt::{self, Debug};
+use std::sync::Arc;
+use wasmer::{AsStoreMut, Function, FunctionType, Imports, RuntimeError, Value};
+
+/// Body of a host extension, its error traps the execution
+pub type HostCallback = Arc<dyn Fn(&[Value]) -> Result<Vec<Value>, String> + Send + Sync>;
+
+#[derive(Clone)]
+struct HostExtension {
+    namespace: String,
+    name: String,
+    signature: FunctionType,
+    callback: HostCallback,
+}
+
+impl PartialEq for HostExtension {
+    fn eq(&self, other: &Self) -> bool {
+        self.namespace == other.namespace
+            && self.name == other.name
+            && self.signature == other.signature
+            && Arc::ptr_eq(&self.callback, &other.callback)
+    }
+}
+
+/// Host functions added to the imports of the executions, see the module
+/// documentation
+#[derive(Clone, Default, PartialEq)]
+pub struct HostExtensions {
+    extensions: Vec<HostExtension>,
+    /// Refuse every extension, at registration and at execution
+    pub strict: bool,
+}
+
+impl HostExtensions {
+    /// Extensions refusing every registration
+    pub fn strict() -> Self {
+        Self {
+            strict: true,
+            ..Self::default()
+        }
+    }
+
+    /// Add `namespace`.`name` to the imports, refused if it is an ABI of the
+    /// runtime or an extension already registered
+    pub fn register(
+        &mut self,
+        namespace: impl Into<String>,
+        name: impl Into<String>,
+        signature: FunctionType,
+        callback: HostCallback,
+    ) -> Result<(), HostExtensionError> {
+        if self.strict {
+            return Err(HostExtensionError::Strict);
+        }
+        let (namespace, name) = (namespace.into(), name.into());
+        let builtin = matches!(namespace.as_str(), "env" | "massa")
+            && GasCosts::abi_names().contains(&abi_name(&namespace, &name).as_str());
+        if builtin || self.contains(&namespace, &name) {
+            return Err(HostExtensionError::Collision { namespace, name });
+        }
+        self.extensions.push(HostExtension {
+            namespace,
+            name,
+            signature,
+            callback,
+        });
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.extensions.is_empty()
+    }
+
+    /// Whether `namespace`.`name` is a registered extension, which the ABI
+    /// filter does not apply to
+    pub(crate) fn contains(&self, namespace: &str, name: &str) -> bool {
+        self.extensions
+            .iter()
+            .any(|extension| extension.namespace == namespace && extension.name == name)
+    }
+
+    /// Refuse the execution if there are extensions while the set is strict,
+    /// the flag having been set after their registration
+    pub(crate) fn check(&self) -> VMResult<()> {
+        if self.strict && !self.is_empty() {
+            return Err(VMError::InstanceError(
+                HostExtensionError::Strict.to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Define the extensions in `imports`
+    pub(crate) fn define(&self, store: &mut impl AsStoreMut, imports: &mut Imports) {
+        for extension in &self.extensions {
+            let callback = extension.callback.clone();
+            let function = Function::new(store, extension.signature.clone(), move |args| {
+                callback(args).map_err(RuntimeError::new)
+            });
+            imports.define(&extension.namespace, &extension.name, function);
+        }
+    }
+}
+
+impl Debug for HostExtensions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HostExtensions")
+            .field(
+                "extensions",
+                &self
+                    .extensions
+                    .iter()
+                    .map(|extension| format!("{}.{}", extension.namespace, extension.name))
+                    .collect::<Vec<_>>(),
+            )
+            .field("strict", &self.strict)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasmer::Type;
+
+    fn noop() -> HostCallback {
+        Arc::new(|_| Ok(Vec::new()))
+    }
+
+    fn signature() -> FunctionType {
+        FunctionType::new(vec![Type::I32], vec![])
+    }
+
+    #[test]
+    fn test_register() {
+        let mut extensions = HostExtensions::default();
+        extensions
+            .register("sandbox", "debug_log", signature(), noop())
+            .unwrap();
+        // same name in another namespace
+        extensions
+            .register("env", "debug_log", signature(), noop())
+            .unwrap();
+        assert!(matches!(
+            extensions.register("sandbox", "debug_log", signature(), noop()),
+            Err(HostExtensionError::Collision { .. })
+        ));
+        for (namespace, name) in [
+            ("massa", "assembly_script_print"),
+            ("massa", "abi_call"),
+            ("env", "abort"),
+            ("env", "Date.now"),
+        ] {
+            assert_eq!(
+                extensions
+                    .register(namespace, name, signature(), noop())
+                    .unwrap_err()
+                    .to_string(),
+                format!(
+                    "Host extension {}.{} collides with a registered function",
+                    namespace, name
+                )
+            );
+        }
+        assert!(extensions.check().is_ok());
+        extensions.strict = true;
+        assert!(extensions.check().is_err());
+    }
+
+    #[test]
+    fn test_strict() {
+        let mut extensions = HostExtensions::strict();
+        assert!(matches!(
+            extensions.register("sandbox", "debug_log", signature(), noop()),
+            Err(HostExtensionError::Strict)
+        ));
+        assert!(extensions.is_empty());
+        assert!(extensions.check().is_ok());
+    }
+}
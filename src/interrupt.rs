@@ -0,0 +1,32 @@
+//! Interruption of an execution by a supervising thread.
+//!
+//! The interruption is cooperative: it is noticed by the runtime when the
+//! execution calls an ABI or starts a nested call, and every
+//! `settings::deadline_check_interval` loop headers of an instance, so that
+//! a contract looping without calling any ABI stops too.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Handle given to an execution, and kept by another thread to interrupt it
+/// with `ABIError::Interrupted`, nested calls included
+#[derive(Clone, Debug, Default)]
+pub struct InterruptHandle {
+    interrupted: Arc<AtomicBool>,
+}
+
+impl InterruptHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Make the executions holding this handle fail at their next ABI call
+    /// or check of their loops. Cannot be undone.
+    pub fn interrupt(&self) {
+        self.interrupted.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_interrupted(&self) -> bool {
+        self.interrupted.load(Ordering::Relaxed)
+    }
+}
@@ -1,21 +1,61 @@
+mod amount;
 mod as_execution;
+mod bls;
+mod compilation_watchdog;
+mod digest;
+mod engine_provider;
 mod error;
 mod execution;
+mod host_extensions;
+mod interrupt;
+mod merkle;
 mod middlewares;
+mod module_cache;
+mod observer;
+mod panic_guard;
+mod profiling;
+mod random;
+mod read_only_cache;
+#[cfg(feature = "replay")]
+mod replay;
+mod session;
 mod settings;
 mod tunable_memory;
 mod types;
+mod validation;
 mod wasmv1_execution;
 
-pub use error::VMError;
-pub use execution::{run_function, run_main};
-pub use execution::{Compiler, RuntimeModule};
+pub use engine_provider::EngineProvider;
+pub use error::{
+    AbortInfo, GasCostsError, HostExtensionError, MerkleProofError, StorageLimitError,
+    TypedCallError, VMError, VMErrorCode,
+};
+pub use execution::{
+    run_function, run_function_typed, run_main, run_main_with_param, run_read_only_cached,
+};
+pub use execution::{Compiler, ExecutionMode, ExecutionOptions, RuntimeModule};
+pub use host_extensions::{HostCallback, HostExtensions};
+pub use interrupt::InterruptHandle;
+pub use module_cache::{BytecodeHash, CachedModuleHandle, ModuleCache};
+pub use observer::{ExecutionEvent, ExecutionObserver, RecordingObserver};
+pub use read_only_cache::{CachedEvent, CachedExecution, ReadOnlyCache, ReadOnlyCacheKey};
+pub use session::{get_remaining_gas, set_remaining_gas, ExecutionSession};
+pub use settings::{ABI_VERSION, INTERFACE_VERSION, MIN_INTERFACE_VERSION};
 pub use types::*;
 
-#[cfg(feature = "gas_calibration")]
-pub use execution::run_main_gc;
-#[cfg(feature = "gas_calibration")]
-pub use middlewares::gas_calibration::GasCalibrationResult;
+#[cfg(feature = "calibration")]
+pub use execution::{run_main_gas_calibration, run_main_gc};
+#[cfg(feature = "calibration")]
+pub use middlewares::gas_calibration::{
+    GasCalibrationCounts, GasCalibrationReport, GasCalibrationResult,
+};
+#[cfg(feature = "profiling")]
+pub use profiling::{AbiProfile, ExecutionProfile};
+#[cfg(feature = "replay")]
+pub use replay::{RecordingInterface, ReplayInterface};
+
+#[cfg(feature = "testing")]
+pub mod testing;
 
 #[cfg(test)]
 mod tests;
@@ -0,0 +1,207 @@
+//! Merkle proofs verified by the runtime itself, the hashes of a proof being
+//! too expensive to compute in AssemblyScript.
+//!
+//! A proof is the concatenation of its elements from the leaf up to the root,
+//! each one being the 32 bytes hash of the sibling followed by a direction
+//! byte: `0` if the sibling is on the left, `1` if it is on the right. A
+//! parent is the hash of its left child followed by its right one, and an
+//! empty proof is the one of a tree made of the leaf alone.
+
+use crate::error::MerkleProofError;
+use num_enum::TryFromPrimitive;
+
+/// Length of the hashes of the nodes
+const HASH_LEN: usize = 32;
+/// Length of a proof element, the sibling hash and its direction
+const ELEMENT_LEN: usize = HASH_LEN + 1;
+
+/// Hash of the nodes of a tree, as given to the ABI
+#[derive(Clone, Copy, Debug, PartialEq, Eq, TryFromPrimitive)]
+#[repr(i32)]
+pub(crate) enum MerkleHash {
+    /// `Interface::hash`, the default hash of Massa
+    Massa = 0,
+    Sha256 = 1,
+}
+
+impl MerkleHash {
+    pub(crate) fn from_discriminant(discriminant: i32) -> Result<Self, MerkleProofError> {
+        Self::try_from(discriminant).map_err(|_| MerkleProofError::UnknownHash(discriminant))
+    }
+}
+
+/// Depth of `proof`, refused if it is malformed or deeper than `max_depth`
+pub(crate) fn check_proof(proof: &[u8], max_depth: usize) -> Result<usize, MerkleProofError> {
+    if proof.len() % ELEMENT_LEN != 0 {
+        return Err(MerkleProofError::MalformedProof { len: proof.len() });
+    }
+    let depth = proof.len() / ELEMENT_LEN;
+    if depth > max_depth {
+        return Err(MerkleProofError::TooDeep {
+            depth,
+            max: max_depth,
+        });
+    }
+    for (index, element) in proof.chunks_exact(ELEMENT_LEN).enumerate() {
+        let direction = element[HASH_LEN];
+        if direction > 1 {
+            return Err(MerkleProofError::InvalidDirection { index, direction });
+        }
+    }
+    Ok(depth)
+}
+
+/// Whether `proof`, checked by `check_proof`, leads from `leaf` to `root`
+pub(crate) fn verify<E: From<MerkleProofError>>(
+    leaf: &[u8],
+    proof: &[u8],
+    root: &[u8],
+    mut hash: impl FnMut(&[u8]) -> Result<[u8; HASH_LEN], E>,
+) -> Result<bool, E> {
+    for (name, node) in [("leaf", leaf), ("root", root)] {
+        if node.len() != HASH_LEN {
+            return Err(MerkleProofError::InvalidHashLength {
+                name,
+                len: node.len(),
+            }
+            .into());
+        }
+    }
+    let mut node = [0; HASH_LEN];
+    node.copy_from_slice(leaf);
+    let mut pair = [0; 2 * HASH_LEN];
+    for element in proof.chunks_exact(ELEMENT_LEN) {
+        let (sibling, direction) = element.split_at(HASH_LEN);
+        if direction[0] == 0 {
+            pair[..HASH_LEN].copy_from_slice(sibling);
+            pair[HASH_LEN..].copy_from_slice(&node);
+        } else {
+            pair[..HASH_LEN].copy_from_slice(&node);
+            pair[HASH_LEN..].copy_from_slice(sibling);
+        }
+        node = hash(&pair)?;
+    }
+    Ok(node == root)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha2::{Digest, Sha256};
+
+    fn sha256(data: &[u8]) -> Result<[u8; HASH_LEN], MerkleProofError> {
+        Ok(Sha256::digest(data).into())
+    }
+
+    /// Levels of a full tree of `depth` levels, from the leaves to the root
+    fn tree(depth: u32) -> Vec<Vec<[u8; HASH_LEN]>> {
+        let mut levels = vec![(0..1usize << depth)
+            .map(|i| sha256(&i.to_le_bytes()).unwrap())
+            .collect::<Vec<_>>()];
+        while levels[levels.len() - 1].len() > 1 {
+            let parents = levels[levels.len() - 1]
+                .chunks_exact(2)
+                .map(|pair| sha256(&pair.concat()).unwrap())
+                .collect();
+            levels.push(parents);
+        }
+        levels
+    }
+
+    /// Proof of the `index`-th leaf of `tree`
+    fn proof(tree: &[Vec<[u8; HASH_LEN]>], index: usize) -> Vec<u8> {
+        let mut proof = Vec::new();
+        for (level, nodes) in tree[..tree.len() - 1].iter().enumerate() {
+            let position = index >> level;
+            let sibling = position ^ 1;
+            proof.extend_from_slice(&nodes[sibling]);
+            proof.push((sibling > position) as u8);
+        }
+        proof
+    }
+
+    #[test]
+    fn test_generated_trees() {
+        for (depth, indexes) in [(1, vec![0, 1]), (20, vec![0, 719_371, (1 << 20) - 1])] {
+            let tree = tree(depth);
+            let (leaves, root) = (&tree[0], tree[tree.len() - 1][0]);
+            for index in indexes {
+                let proof = proof(&tree, index);
+                assert_eq!(check_proof(&proof, 20), Ok(depth as usize));
+                assert_eq!(verify(&leaves[index], &proof, &root, sha256), Ok(true));
+                // another leaf, or the right leaf with another root
+                let other = leaves[index ^ 1];
+                assert_eq!(verify(&other, &proof, &root, sha256), Ok(false));
+                assert_eq!(verify(&leaves[index], &proof, &other, sha256), Ok(false));
+            }
+        }
+        // a lone leaf is the root
+        let leaf = sha256(b"leaf").unwrap();
+        assert_eq!(check_proof(&[], 0), Ok(0));
+        assert_eq!(verify(&leaf, &[], &leaf, sha256), Ok(true));
+    }
+
+    #[test]
+    fn test_depth_cap() {
+        // the proof of a leaf of a tree of `cap` levels, then one level more
+        let cap = 12;
+        let tree_at_cap = tree(cap);
+        let proof_at_cap = proof(&tree_at_cap, 7);
+        assert_eq!(check_proof(&proof_at_cap, cap as usize), Ok(cap as usize));
+        assert_eq!(
+            verify(
+                &tree_at_cap[0][7],
+                &proof_at_cap,
+                &tree_at_cap[cap as usize][0],
+                sha256
+            ),
+            Ok(true)
+        );
+        let proof = proof(&tree(cap + 1), 7);
+        assert_eq!(
+            check_proof(&proof, cap as usize),
+            Err(MerkleProofError::TooDeep {
+                depth: cap as usize + 1,
+                max: cap as usize
+            })
+        );
+    }
+
+    #[test]
+    fn test_malformed_proofs() {
+        let tree = tree(3);
+        let (leaves, root) = (&tree[0], tree[3][0]);
+        let mut proof = proof(&tree, 2);
+        assert_eq!(
+            check_proof(&proof[..proof.len() - 1], 64),
+            Err(MerkleProofError::MalformedProof { len: 3 * 33 - 1 })
+        );
+        proof[2 * ELEMENT_LEN - 1] = 2;
+        assert_eq!(
+            check_proof(&proof, 64),
+            Err(MerkleProofError::InvalidDirection {
+                index: 1,
+                direction: 2
+            })
+        );
+        assert_eq!(
+            verify(&leaves[2][..31], &[], &root, sha256),
+            Err(MerkleProofError::InvalidHashLength {
+                name: "leaf",
+                len: 31
+            })
+        );
+        assert_eq!(
+            verify(&leaves[2], &[], &[], sha256),
+            Err(MerkleProofError::InvalidHashLength {
+                name: "root",
+                len: 0
+            })
+        );
+        assert_eq!(MerkleHash::from_discriminant(1), Ok(MerkleHash::Sha256));
+        assert_eq!(
+            MerkleHash::from_discriminant(2),
+            Err(MerkleProofError::UnknownHash(2))
+        );
+    }
+}
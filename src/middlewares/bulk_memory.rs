@@ -0,0 +1,183 @@
+//! Instrumentation of the bulk memory operators.
+//!
+//! The metering charges `memory.copy`, `memory.fill` and `memory.init` a flat
+//! cost, whatever the number of bytes they handle. This middleware charges
+//! each of those bytes on top of it, before the operator runs: the length is
+//! the operand on top of the stack of the three of them.
+//!
+//! Must be pushed after the metering middleware, whose globals it uses and
+//! which then does not charge the instrumentation itself.
+
+use crate::middlewares::memory_grow::{METERING_POINTS_EXHAUSTED, METERING_REMAINING_POINTS};
+use crate::GasCosts;
+use std::fmt::{self, Debug};
+use std::sync::Mutex;
+use wasmer::{
+    wasmparser::{BlockType as WpTypeOrFuncType, Operator},
+    FunctionMiddleware, LocalFunctionIndex, MiddlewareError, MiddlewareReaderState,
+    ModuleMiddleware,
+};
+use wasmer_types::{
+    ExportIndex, GlobalIndex, GlobalInit, GlobalType, ModuleInfo, Mutability, Type,
+};
+
+#[derive(Clone, Copy, Debug)]
+struct BulkMemoryGlobalIndexes {
+    /// Bytes handled by the current operator
+    len: GlobalIndex,
+    /// Gas charged for them
+    cost: GlobalIndex,
+    remaining_points: GlobalIndex,
+    points_exhausted: GlobalIndex,
+}
+
+pub struct BulkMemoryMetering {
+    /// Gas per byte, bounded so that the cost of any operator fits in 64 bits
+    byte_cost: u64,
+    /// Set by `transform_module_info`, to `None` when the module is not
+    /// metered or the bytes are free
+    global_indexes: Mutex<Option<Option<BulkMemoryGlobalIndexes>>>,
+}
+
+#[derive(Debug)]
+pub struct FunctionBulkMemoryMetering {
+    byte_cost: u64,
+    global_indexes: Option<BulkMemoryGlobalIndexes>,
+}
+
+impl BulkMemoryMetering {
+    pub fn new(gas_costs: &GasCosts) -> Self {
+        Self {
+            byte_cost: gas_costs
+                .bulk_memory_byte_cost
+                .min(u64::MAX / u32::MAX as u64),
+            global_indexes: Mutex::new(None),
+        }
+    }
+}
+
+impl Debug for BulkMemoryMetering {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BulkMemoryMetering")
+            .field("byte_cost", &self.byte_cost)
+            .finish()
+    }
+}
+
+impl ModuleMiddleware for BulkMemoryMetering {
+    fn generate_function_middleware(
+        &self,
+        _local_function_index: LocalFunctionIndex,
+    ) -> Box<dyn FunctionMiddleware> {
+        Box::new(FunctionBulkMemoryMetering {
+            byte_cost: self.byte_cost,
+            global_indexes: self.global_indexes.lock().unwrap().unwrap(),
+        })
+    }
+
+    fn transform_module_info(&self, module_info: &mut ModuleInfo) {
+        let mut global_indexes = self.global_indexes.lock().unwrap();
+        if global_indexes.is_some() {
+            panic!("BulkMemoryMetering::transform_module_info: Attempting to use a `BulkMemoryMetering` middleware from multiple modules.");
+        }
+
+        let metering_global = |name: &str| match module_info.exports.get(name) {
+            Some(ExportIndex::Global(index)) => Some(*index),
+            _ => None,
+        };
+        let (Some(remaining_points), Some(points_exhausted), true) = (
+            metering_global(METERING_REMAINING_POINTS),
+            metering_global(METERING_POINTS_EXHAUSTED),
+            self.byte_cost > 0,
+        ) else {
+            *global_indexes = Some(None);
+            return;
+        };
+
+        let len = module_info
+            .globals
+            .push(GlobalType::new(Type::I32, Mutability::Var));
+        module_info
+            .global_initializers
+            .push(GlobalInit::I32Const(0));
+        let cost = module_info
+            .globals
+            .push(GlobalType::new(Type::I64, Mutability::Var));
+        module_info
+            .global_initializers
+            .push(GlobalInit::I64Const(0));
+
+        *global_indexes = Some(Some(BulkMemoryGlobalIndexes {
+            len,
+            cost,
+            remaining_points,
+            points_exhausted,
+        }));
+    }
+}
+
+impl FunctionMiddleware for FunctionBulkMemoryMetering {
+    fn feed<'a>(
+        &mut self,
+        operator: Operator<'a>,
+        state: &mut MiddlewareReaderState<'a>,
+    ) -> Result<(), MiddlewareError> {
+        let indexes = match self.global_indexes {
+            Some(indexes)
+                if matches!(
+                    operator,
+                    Operator::MemoryCopy { .. }
+                        | Operator::MemoryFill { .. }
+                        | Operator::MemoryInit { .. }
+                ) =>
+            {
+                indexes
+            }
+            _ => {
+                state.push_operator(operator);
+                return Ok(());
+            }
+        };
+        let len = indexes.len.as_u32();
+        let cost = indexes.cost.as_u32();
+        let remaining_points = indexes.remaining_points.as_u32();
+
+        state.extend(&[
+            // the length is on top of the stack
+            Operator::GlobalSet { global_index: len },
+            Operator::GlobalGet { global_index: len },
+            Operator::I64ExtendI32U,
+            Operator::I64Const {
+                value: self.byte_cost as i64,
+            },
+            Operator::I64Mul,
+            Operator::GlobalSet { global_index: cost },
+            // exhaust the points like the metering when they are not enough
+            Operator::GlobalGet {
+                global_index: remaining_points,
+            },
+            Operator::GlobalGet { global_index: cost },
+            Operator::I64LtU,
+            Operator::If {
+                blockty: WpTypeOrFuncType::Empty,
+            },
+            Operator::I32Const { value: 1 },
+            Operator::GlobalSet {
+                global_index: indexes.points_exhausted.as_u32(),
+            },
+            Operator::Unreachable,
+            Operator::End,
+            Operator::GlobalGet {
+                global_index: remaining_points,
+            },
+            Operator::GlobalGet { global_index: cost },
+            Operator::I64Sub,
+            Operator::GlobalSet {
+                global_index: remaining_points,
+            },
+            Operator::GlobalGet { global_index: len },
+            operator,
+        ]);
+        Ok(())
+    }
+}
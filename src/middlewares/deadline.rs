@@ -0,0 +1,234 @@
+//! Checks of the deadline at the loop headers.
+//!
+//! The ABIs fail once the deadline of the call tree has passed or once it was
+//! interrupted, but a contract looping without calling any of them would
+//! only stop when its gas is exhausted. Every loop header decrements a
+//! countdown, and when it reaches zero calls an import added to the module,
+//! which the runtimes resolve to `CallContext::check_deadline`, then rearms
+//! it.
+//!
+//! Adding the import shifts the indexes of the local functions, which are
+//! remapped in the module info and in the `call` operators. Must be pushed
+//! after the other middlewares, so that the instrumentation is not charged
+//! and the gas of an execution does not depend on it.
+
+use crate::settings;
+use std::fmt::{self, Debug};
+use std::sync::Mutex;
+use wasmer::{
+    wasmparser::{BlockType as WpTypeOrFuncType, Operator},
+    FunctionMiddleware, LocalFunctionIndex, MiddlewareError, MiddlewareReaderState,
+    ModuleMiddleware,
+};
+use wasmer_types::{
+    entity::PrimaryMap, ExportIndex, FunctionIndex, FunctionType, GlobalIndex, GlobalInit,
+    GlobalType, ImportIndex, ImportKey, ModuleInfo, Mutability, Type,
+};
+
+/// Namespace of the import checking the deadline, out of the ones of the ABIs
+pub(crate) const DEADLINE_CHECK_NAMESPACE: &str = "massa_runtime";
+/// Name of the import checking the deadline
+pub(crate) const DEADLINE_CHECK_NAME: &str = "check_deadline";
+
+#[derive(Clone, Copy, Debug)]
+struct DeadlineIndexes {
+    /// Index of the added import, the functions from there are shifted by one
+    check: FunctionIndex,
+    /// Loop headers left before the next check
+    countdown: GlobalIndex,
+}
+
+impl DeadlineIndexes {
+    /// Index of a function of the original module in the transformed one
+    fn shift(&self, index: u32) -> u32 {
+        if index >= self.check.as_u32() {
+            index + 1
+        } else {
+            index
+        }
+    }
+}
+
+pub struct DeadlineCheck {
+    /// Loop headers between two checks
+    interval: u32,
+    indexes: Mutex<Option<DeadlineIndexes>>,
+}
+
+#[derive(Debug)]
+pub struct FunctionDeadlineCheck {
+    interval: u32,
+    indexes: DeadlineIndexes,
+}
+
+impl DeadlineCheck {
+    pub fn new() -> Self {
+        Self {
+            interval: settings::deadline_check_interval().max(1),
+            indexes: Mutex::new(None),
+        }
+    }
+}
+
+impl Default for DeadlineCheck {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debug for DeadlineCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeadlineCheck")
+            .field("interval", &self.interval)
+            .finish()
+    }
+}
+
+impl ModuleMiddleware for DeadlineCheck {
+    fn generate_function_middleware(
+        &self,
+        _local_function_index: LocalFunctionIndex,
+    ) -> Box<dyn FunctionMiddleware> {
+        Box::new(FunctionDeadlineCheck {
+            interval: self.interval,
+            indexes: self.indexes.lock().unwrap().unwrap(),
+        })
+    }
+
+    fn transform_module_info(&self, module_info: &mut ModuleInfo) {
+        let mut indexes = self.indexes.lock().unwrap();
+        if indexes.is_some() {
+            panic!("DeadlineCheck::transform_module_info: Attempting to use a `DeadlineCheck` middleware from multiple modules.");
+        }
+
+        // the import goes after the imported functions, before the local ones
+        let check = FunctionIndex::from_u32(module_info.num_imported_functions as u32);
+        let shift = |index: FunctionIndex| {
+            if index >= check {
+                FunctionIndex::from_u32(index.as_u32() + 1)
+            } else {
+                index
+            }
+        };
+
+        let signature = match module_info
+            .signatures
+            .iter()
+            .find(|(_, ty)| ty.params().is_empty() && ty.results().is_empty())
+        {
+            Some((index, _)) => index,
+            None => module_info
+                .signatures
+                .push(FunctionType::new(Vec::<Type>::new(), Vec::<Type>::new())),
+        };
+        let mut signatures: Vec<_> = module_info.functions.values().copied().collect();
+        signatures.insert(check.as_u32() as usize, signature);
+        let mut functions = PrimaryMap::with_capacity(signatures.len());
+        for signature in signatures {
+            functions.push(signature);
+        }
+        module_info.functions = functions;
+
+        // the position of the import in the map is its index in the imports
+        // resolved at instantiation
+        let import_idx = module_info.imports.len() as u32;
+        module_info.imports.insert(
+            ImportKey {
+                module: DEADLINE_CHECK_NAMESPACE.to_string(),
+                field: DEADLINE_CHECK_NAME.to_string(),
+                import_idx,
+            },
+            ImportIndex::Function(check),
+        );
+        module_info.num_imported_functions += 1;
+
+        for export in module_info.exports.values_mut() {
+            if let ExportIndex::Function(index) = export {
+                *index = shift(*index);
+            }
+        }
+        module_info.start_function = module_info.start_function.map(shift);
+        for initializer in module_info.table_initializers.iter_mut() {
+            for index in initializer.elements.iter_mut() {
+                *index = shift(*index);
+            }
+        }
+        for elements in module_info.passive_elements.values_mut() {
+            for index in elements.iter_mut() {
+                *index = shift(*index);
+            }
+        }
+        for initializer in module_info.global_initializers.values_mut() {
+            if let GlobalInit::RefFunc(index) = initializer {
+                *index = shift(*index);
+            }
+        }
+        module_info.function_names = std::mem::take(&mut module_info.function_names)
+            .into_iter()
+            .map(|(index, name)| (shift(index), name))
+            .collect();
+
+        let countdown = module_info
+            .globals
+            .push(GlobalType::new(Type::I32, Mutability::Var));
+        module_info
+            .global_initializers
+            .push(GlobalInit::I32Const(self.interval as i32));
+
+        *indexes = Some(DeadlineIndexes { check, countdown });
+    }
+}
+
+impl FunctionMiddleware for FunctionDeadlineCheck {
+    fn feed<'a>(
+        &mut self,
+        operator: Operator<'a>,
+        state: &mut MiddlewareReaderState<'a>,
+    ) -> Result<(), MiddlewareError> {
+        match operator {
+            Operator::Call { function_index } => {
+                state.push_operator(Operator::Call {
+                    function_index: self.indexes.shift(function_index),
+                });
+            }
+            Operator::RefFunc { function_index } => {
+                state.push_operator(Operator::RefFunc {
+                    function_index: self.indexes.shift(function_index),
+                });
+            }
+            Operator::Loop { .. } => {
+                let countdown = self.indexes.countdown.as_u32();
+                state.extend(&[
+                    operator,
+                    Operator::GlobalGet {
+                        global_index: countdown,
+                    },
+                    Operator::I32Const { value: 1 },
+                    Operator::I32Sub,
+                    Operator::GlobalSet {
+                        global_index: countdown,
+                    },
+                    Operator::GlobalGet {
+                        global_index: countdown,
+                    },
+                    Operator::I32Eqz,
+                    Operator::If {
+                        blockty: WpTypeOrFuncType::Empty,
+                    },
+                    Operator::I32Const {
+                        value: self.interval as i32,
+                    },
+                    Operator::GlobalSet {
+                        global_index: countdown,
+                    },
+                    Operator::Call {
+                        function_index: self.indexes.check.as_u32(),
+                    },
+                    Operator::End,
+                ]);
+            }
+            _ => state.push_operator(operator),
+        }
+        Ok(())
+    }
+}
@@ -1,9 +1,10 @@
 use crate::middlewares::operator::{operator_field_str, OPERATOR_VARIANTS};
 use regex::{Regex, RegexSet};
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::{self, Debug};
 use std::sync::Mutex;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use wasmer::{
     wasmparser::Operator, AsStoreMut, Extern, FunctionMiddleware, Instance, LocalFunctionIndex,
     MiddlewareError, MiddlewareReaderState, ModuleMiddleware,
@@ -251,12 +252,83 @@ impl FunctionMiddleware for FunctionGasCalibration {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct GasCalibrationResult {
     pub counters: HashMap<String, u64>,
     pub timers: HashMap<String, f64>,
 }
 
+/// Counts of calibrating executions
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GasCalibrationCounts {
+    /// Number of executions counted
+    pub executions: u64,
+    /// Calls of each ABI imported by the executed modules, keyed by
+    /// `<module>.<name>` such as `massa.assembly_script_print`
+    pub abi_calls: BTreeMap<String, u64>,
+    /// Executions of each operator, keyed by name such as `I32Add`
+    pub operators: BTreeMap<String, u64>,
+}
+
+impl GasCalibrationCounts {
+    /// Calls of `abi`, 0 when no executed module imports it
+    pub fn calls(&self, abi: &str) -> u64 {
+        self.abi_calls.get(abi).copied().unwrap_or(0)
+    }
+
+    /// Executions of `operator`
+    pub fn operator_executions(&self, operator: &str) -> u64 {
+        self.operators.get(operator).copied().unwrap_or(0)
+    }
+
+    fn add(&mut self, result: &GasCalibrationResult) {
+        self.executions += 1;
+        for (counter, count) in &result.counters {
+            let (counts, name) = if let Some(abi) = counter.strip_prefix("Abi:call:") {
+                (&mut self.abi_calls, abi)
+            } else if let Some(operator) = counter.strip_prefix("Wasm:") {
+                (&mut self.operators, operator)
+            } else {
+                continue;
+            };
+            let total = counts.entry(name.to_string()).or_default();
+            *total = total.saturating_add(*count);
+        }
+    }
+}
+
+/// Report of a calibrating call tree, see `run_main_gas_calibration`
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GasCalibrationReport {
+    /// Counts of the whole call tree
+    pub total: GasCalibrationCounts,
+    /// Counts of the executions at each depth of the call tree, the
+    /// top-level one first
+    pub depths: Vec<GasCalibrationCounts>,
+    /// Wall time of the whole call tree
+    pub wall_time: Duration,
+}
+
+impl GasCalibrationReport {
+    /// Report of the `results` of the executions of a call tree, with their
+    /// depth
+    pub(crate) fn new(results: &[(u32, GasCalibrationResult)], wall_time: Duration) -> Self {
+        let mut report = Self {
+            wall_time,
+            ..Self::default()
+        };
+        for (depth, result) in results {
+            let depth = *depth as usize;
+            if report.depths.len() <= depth {
+                report.depths.resize_with(depth + 1, Default::default);
+            }
+            report.depths[depth].add(result);
+            report.total.add(result);
+        }
+        report
+    }
+}
+
 // #[cfg(feature = "gas_calibration")]
 pub fn get_gas_calibration_result(
     instance: &Instance,
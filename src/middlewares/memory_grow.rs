@@ -0,0 +1,240 @@
+//! Instrumentation of `memory.grow`.
+//!
+//! The pages requested by each `memory.grow` are charged on the metering
+//! points before the memory is grown, so every instance pays for its own
+//! memory, the ones of the nested calls included. In `MemoryLimitMode::Trap`,
+//! growing the memory above `CondomLimits::max_memory_pages` traps instead of
+//! returning -1; the trap is recognized by the runtime through an exported
+//! flag, the same way the metering reports an exhaustion.
+//!
+//! Must be pushed after the metering middleware, whose globals it uses and
+//! which then does not charge the instrumentation itself.
+
+use crate::{CondomLimits, GasCosts, MemoryLimitMode};
+use std::fmt::{self, Debug};
+use std::sync::Mutex;
+use wasmer::{
+    wasmparser::{BlockType as WpTypeOrFuncType, Operator},
+    AsStoreMut, FunctionMiddleware, Instance, LocalFunctionIndex, MiddlewareError,
+    MiddlewareReaderState, ModuleMiddleware, Value,
+};
+use wasmer_types::{
+    ExportIndex, GlobalIndex, GlobalInit, GlobalType, ModuleInfo, Mutability, Type,
+};
+
+/// Exported flag set when `memory.grow` traps above the memory limit
+const MEMORY_LIMIT_EXCEEDED: &str = "massa_memory_limit_exceeded";
+/// Exports of the metering globals
+pub(crate) const METERING_REMAINING_POINTS: &str = "wasmer_metering_remaining_points";
+pub(crate) const METERING_POINTS_EXHAUSTED: &str = "wasmer_metering_points_exhausted";
+
+#[derive(Clone, Copy, Debug)]
+struct MemoryGrowGlobalIndexes {
+    /// Pages requested by the current `memory.grow`
+    delta: GlobalIndex,
+    /// Gas charged for them
+    cost: GlobalIndex,
+    limit_exceeded: GlobalIndex,
+    /// Metering globals, absent when the module is not metered
+    remaining_points: Option<GlobalIndex>,
+    points_exhausted: Option<GlobalIndex>,
+}
+
+pub struct MemoryGrowMetering {
+    /// Gas per page grown, bounded so that the cost of any `memory.grow` fits
+    /// in 64 bits
+    page_cost: u64,
+    /// Trap when the memory would grow above this number of pages
+    trap_above: Option<u32>,
+    global_indexes: Mutex<Option<MemoryGrowGlobalIndexes>>,
+}
+
+#[derive(Debug)]
+pub struct FunctionMemoryGrowMetering {
+    page_cost: u64,
+    trap_above: Option<u32>,
+    global_indexes: MemoryGrowGlobalIndexes,
+}
+
+impl MemoryGrowMetering {
+    pub fn new(gas_costs: &GasCosts, limits: &CondomLimits) -> Self {
+        Self {
+            page_cost: gas_costs.memory_page_cost.min(u64::MAX / u32::MAX as u64),
+            trap_above: match limits.memory_limit_mode {
+                MemoryLimitMode::Trap => Some(limits.max_memory_pages),
+                MemoryLimitMode::Fail => None,
+            },
+            global_indexes: Mutex::new(None),
+        }
+    }
+}
+
+impl Debug for MemoryGrowMetering {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemoryGrowMetering")
+            .field("page_cost", &self.page_cost)
+            .field("trap_above", &self.trap_above)
+            .finish()
+    }
+}
+
+impl ModuleMiddleware for MemoryGrowMetering {
+    fn generate_function_middleware(
+        &self,
+        _local_function_index: LocalFunctionIndex,
+    ) -> Box<dyn FunctionMiddleware> {
+        Box::new(FunctionMemoryGrowMetering {
+            page_cost: self.page_cost,
+            trap_above: self.trap_above,
+            global_indexes: self.global_indexes.lock().unwrap().unwrap(),
+        })
+    }
+
+    fn transform_module_info(&self, module_info: &mut ModuleInfo) {
+        let mut global_indexes = self.global_indexes.lock().unwrap();
+        if global_indexes.is_some() {
+            panic!("MemoryGrowMetering::transform_module_info: Attempting to use a `MemoryGrowMetering` middleware from multiple modules.");
+        }
+
+        let metering_global = |name: &str| match module_info.exports.get(name) {
+            Some(ExportIndex::Global(index)) => Some(*index),
+            _ => None,
+        };
+        let remaining_points = metering_global(METERING_REMAINING_POINTS);
+        let points_exhausted = metering_global(METERING_POINTS_EXHAUSTED);
+
+        let delta = module_info
+            .globals
+            .push(GlobalType::new(Type::I32, Mutability::Var));
+        module_info
+            .global_initializers
+            .push(GlobalInit::I32Const(0));
+        let cost = module_info
+            .globals
+            .push(GlobalType::new(Type::I64, Mutability::Var));
+        module_info
+            .global_initializers
+            .push(GlobalInit::I64Const(0));
+        let limit_exceeded = module_info
+            .globals
+            .push(GlobalType::new(Type::I32, Mutability::Var));
+        module_info
+            .global_initializers
+            .push(GlobalInit::I32Const(0));
+        module_info.exports.insert(
+            MEMORY_LIMIT_EXCEEDED.to_string(),
+            ExportIndex::Global(limit_exceeded),
+        );
+
+        *global_indexes = Some(MemoryGrowGlobalIndexes {
+            delta,
+            cost,
+            limit_exceeded,
+            remaining_points,
+            points_exhausted,
+        });
+    }
+}
+
+impl FunctionMiddleware for FunctionMemoryGrowMetering {
+    fn feed<'a>(
+        &mut self,
+        operator: Operator<'a>,
+        state: &mut MiddlewareReaderState<'a>,
+    ) -> Result<(), MiddlewareError> {
+        let Operator::MemoryGrow { mem, mem_byte } = operator else {
+            state.push_operator(operator);
+            return Ok(());
+        };
+        let indexes = self.global_indexes;
+        let delta = indexes.delta.as_u32();
+
+        // the requested pages are on top of the stack
+        state.push_operator(Operator::GlobalSet {
+            global_index: delta,
+        });
+        if let Some(max_pages) = self.trap_above {
+            state.extend(&[
+                Operator::MemorySize { mem, mem_byte },
+                Operator::I64ExtendI32U,
+                Operator::GlobalGet {
+                    global_index: delta,
+                },
+                Operator::I64ExtendI32U,
+                Operator::I64Add,
+                Operator::I64Const {
+                    value: max_pages as i64,
+                },
+                Operator::I64GtU,
+                Operator::If {
+                    blockty: WpTypeOrFuncType::Empty,
+                },
+                Operator::I32Const { value: 1 },
+                Operator::GlobalSet {
+                    global_index: indexes.limit_exceeded.as_u32(),
+                },
+                Operator::Unreachable,
+                Operator::End,
+            ]);
+        }
+        if let (Some(remaining_points), Some(points_exhausted)) =
+            (indexes.remaining_points, indexes.points_exhausted)
+        {
+            if self.page_cost > 0 {
+                let remaining_points = remaining_points.as_u32();
+                let cost = indexes.cost.as_u32();
+                state.extend(&[
+                    Operator::GlobalGet {
+                        global_index: delta,
+                    },
+                    Operator::I64ExtendI32U,
+                    Operator::I64Const {
+                        value: self.page_cost as i64,
+                    },
+                    Operator::I64Mul,
+                    Operator::GlobalSet { global_index: cost },
+                    // exhaust the points like the metering when they are not
+                    // enough
+                    Operator::GlobalGet {
+                        global_index: remaining_points,
+                    },
+                    Operator::GlobalGet { global_index: cost },
+                    Operator::I64LtU,
+                    Operator::If {
+                        blockty: WpTypeOrFuncType::Empty,
+                    },
+                    Operator::I32Const { value: 1 },
+                    Operator::GlobalSet {
+                        global_index: points_exhausted.as_u32(),
+                    },
+                    Operator::Unreachable,
+                    Operator::End,
+                    Operator::GlobalGet {
+                        global_index: remaining_points,
+                    },
+                    Operator::GlobalGet { global_index: cost },
+                    Operator::I64Sub,
+                    Operator::GlobalSet {
+                        global_index: remaining_points,
+                    },
+                ]);
+            }
+        }
+        state.extend(&[
+            Operator::GlobalGet {
+                global_index: delta,
+            },
+            operator,
+        ]);
+        Ok(())
+    }
+}
+
+/// Whether the execution of `instance` trapped because its memory would have
+/// grown above the memory limit
+pub(crate) fn memory_limit_exceeded(instance: &Instance, store: &mut impl AsStoreMut) -> bool {
+    match instance.exports.get_global(MEMORY_LIMIT_EXCEEDED) {
+        Ok(global) => matches!(global.get(store), Value::I32(exceeded) if exceeded > 0),
+        Err(_) => false,
+    }
+}
@@ -1,3 +1,9 @@
+pub mod bulk_memory;
+pub mod deadline;
+#[cfg(feature = "dumper")]
 pub mod dumper;
+#[cfg(feature = "calibration")]
 pub mod gas_calibration;
+pub mod memory_grow;
 pub mod operator;
+pub mod stack_limit;
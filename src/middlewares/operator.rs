@@ -2111,7 +2111,7 @@ pub(crate) const OPERATOR_VARIANTS: [&str; 529] = [
 ];
 
 // From https://github.com/WebAssembly/threads/blob/main/proposals/threads/Overview.md
-#[cfg(feature = "gas_calibration")]
+#[cfg(test)]
 pub(crate) const _OPERATOR_THREAD: [&str; 67] = [
     // Load/Store
     "I32AtomicLoad8U",
@@ -2189,7 +2189,7 @@ pub(crate) const _OPERATOR_THREAD: [&str; 67] = [
 // From https://webassembly.github.io/spec/core/_download/WebAssembly.pdf
 // Section 7.6 Change History -> 7.6.1 Release 2.0 -> Non-trapping float-to-int
 // conversions https://github.com/WebAssembly/spec/blob/main/proposals/nontrapping-float-to-int-conversion/Overview.md
-#[cfg(feature = "gas_calibration")]
+#[cfg(test)]
 pub(crate) const _OPERATOR_NON_TRAPPING_FLOAT_TO_INT: [&str; 8] = [
     "I32TruncSatF32S",
     "I32TruncSatF32U",
@@ -2203,7 +2203,7 @@ pub(crate) const _OPERATOR_NON_TRAPPING_FLOAT_TO_INT: [&str; 8] = [
 
 // From https://webassembly.github.io/spec/core/_download/WebAssembly.pdf
 // Section 7.6 Change History -> 7.6.1 Release 2.0 -> Bulk memory
-#[cfg(feature = "gas_calibration")]
+#[cfg(test)]
 pub(crate) const _OPERATOR_BULK_MEMORY: [&str; 8] = [
     "MemoryFill",
     "MemoryInit",
@@ -2215,7 +2215,7 @@ pub(crate) const _OPERATOR_BULK_MEMORY: [&str; 8] = [
     "ElemDrop",
 ];
 
-#[cfg(feature = "gas_calibration")]
+#[cfg(test)]
 pub(crate) const _OPERATOR_VECTOR: [&str; 236] = [
     "V128Load",
     "V128Load8x8S",
@@ -2455,5 +2455,5 @@ pub(crate) const _OPERATOR_VECTOR: [&str; 236] = [
     "I64x2GeS",
 ];
 
-#[cfg(all(feature = "gas_calibration", test))]
+#[cfg(test)]
 pub const OPERATOR_CARDINALITY: usize = OPERATOR_VARIANTS.len();
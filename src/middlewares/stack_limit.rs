@@ -0,0 +1,166 @@
+//! Limit of the depth of the guest call stack.
+//!
+//! Every `call` and `call_indirect` increments a depth counter before the
+//! call and decrements it after, and traps when it goes above
+//! `CondomLimits::max_stack_depth`. The recursion of a contract is then
+//! bounded by a number of frames, whatever the native stack of the host. The
+//! trap is recognized by the runtime through an exported flag, the same way
+//! the metering reports an exhaustion.
+//!
+//! The counter is never reset: like the other globals, it belongs to an
+//! instance which is dropped after its execution.
+
+use crate::CondomLimits;
+use std::fmt::{self, Debug};
+use std::sync::Mutex;
+use wasmer::{
+    wasmparser::{BlockType as WpTypeOrFuncType, Operator},
+    AsStoreMut, FunctionMiddleware, Instance, LocalFunctionIndex, MiddlewareError,
+    MiddlewareReaderState, ModuleMiddleware, Value,
+};
+use wasmer_types::{
+    ExportIndex, GlobalIndex, GlobalInit, GlobalType, ModuleInfo, Mutability, Type,
+};
+
+/// Exported flag set when a call traps above the stack limit
+const STACK_LIMIT_EXCEEDED: &str = "massa_stack_limit_exceeded";
+
+#[derive(Clone, Copy, Debug)]
+struct StackLimitGlobalIndexes {
+    /// Number of calls in progress
+    depth: GlobalIndex,
+    limit_exceeded: GlobalIndex,
+}
+
+pub struct StackLimit {
+    max_depth: u32,
+    global_indexes: Mutex<Option<StackLimitGlobalIndexes>>,
+}
+
+#[derive(Debug)]
+pub struct FunctionStackLimit {
+    max_depth: u32,
+    global_indexes: StackLimitGlobalIndexes,
+}
+
+impl StackLimit {
+    pub fn new(limits: &CondomLimits) -> Self {
+        Self {
+            max_depth: limits.max_stack_depth,
+            global_indexes: Mutex::new(None),
+        }
+    }
+}
+
+impl Debug for StackLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StackLimit")
+            .field("max_depth", &self.max_depth)
+            .finish()
+    }
+}
+
+impl ModuleMiddleware for StackLimit {
+    fn generate_function_middleware(
+        &self,
+        _local_function_index: LocalFunctionIndex,
+    ) -> Box<dyn FunctionMiddleware> {
+        Box::new(FunctionStackLimit {
+            max_depth: self.max_depth,
+            global_indexes: self.global_indexes.lock().unwrap().unwrap(),
+        })
+    }
+
+    fn transform_module_info(&self, module_info: &mut ModuleInfo) {
+        let mut global_indexes = self.global_indexes.lock().unwrap();
+        if global_indexes.is_some() {
+            panic!("StackLimit::transform_module_info: Attempting to use a `StackLimit` middleware from multiple modules.");
+        }
+
+        let depth = module_info
+            .globals
+            .push(GlobalType::new(Type::I32, Mutability::Var));
+        module_info
+            .global_initializers
+            .push(GlobalInit::I32Const(0));
+        let limit_exceeded = module_info
+            .globals
+            .push(GlobalType::new(Type::I32, Mutability::Var));
+        module_info
+            .global_initializers
+            .push(GlobalInit::I32Const(0));
+        module_info.exports.insert(
+            STACK_LIMIT_EXCEEDED.to_string(),
+            ExportIndex::Global(limit_exceeded),
+        );
+
+        *global_indexes = Some(StackLimitGlobalIndexes {
+            depth,
+            limit_exceeded,
+        });
+    }
+}
+
+impl FunctionMiddleware for FunctionStackLimit {
+    fn feed<'a>(
+        &mut self,
+        operator: Operator<'a>,
+        state: &mut MiddlewareReaderState<'a>,
+    ) -> Result<(), MiddlewareError> {
+        if !matches!(
+            operator,
+            Operator::Call { .. } | Operator::CallIndirect { .. }
+        ) {
+            state.push_operator(operator);
+            return Ok(());
+        }
+        let depth = self.global_indexes.depth.as_u32();
+
+        // the arguments of the call stay below on the stack
+        state.extend(&[
+            Operator::GlobalGet {
+                global_index: depth,
+            },
+            Operator::I32Const { value: 1 },
+            Operator::I32Add,
+            Operator::GlobalSet {
+                global_index: depth,
+            },
+            Operator::GlobalGet {
+                global_index: depth,
+            },
+            Operator::I32Const {
+                value: self.max_depth as i32,
+            },
+            Operator::I32GtU,
+            Operator::If {
+                blockty: WpTypeOrFuncType::Empty,
+            },
+            Operator::I32Const { value: 1 },
+            Operator::GlobalSet {
+                global_index: self.global_indexes.limit_exceeded.as_u32(),
+            },
+            Operator::Unreachable,
+            Operator::End,
+            operator,
+            Operator::GlobalGet {
+                global_index: depth,
+            },
+            Operator::I32Const { value: 1 },
+            Operator::I32Sub,
+            Operator::GlobalSet {
+                global_index: depth,
+            },
+        ]);
+        Ok(())
+    }
+}
+
+/// Whether the execution of `instance` trapped because its call stack would
+/// have gone above the stack limit
+pub(crate) fn stack_limit_exceeded(instance: &Instance, store: &mut impl AsStoreMut) -> bool {
+    match instance.exports.get_global(STACK_LIMIT_EXCEEDED) {
+        Ok(global) => matches!(global.get(store), Value::I32(exceeded) if exceeded > 0),
+        Err(_) => false,
+    }
+}
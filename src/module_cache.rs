@@ -0,0 +1,609 @@
+//! Cache of compiled runtime modules.
+//!
+//! Compiling a module dominates the execution time of hot smart contracts.
+//! `ModuleCache` keeps the compiled modules around, keyed by the hash of
+//! their bytecode, the compiler used and a digest of the gas costs and of
+//! the configuration read by the compilation, and is meant to back the
+//! `Interface::get_module` implementations consulted by `call_module` and
+//! `local_call` before compiling, and `Interface::get_cached_module` for the
+//! calls whose bytecode is only fetched on a miss.
+//!
+//! The cache only saves time: a call is charged the compilation of its
+//! callee per byte of bytecode whether the module was found in it or not.
+//!
+//! The cache can be shared by the executions and a worker thread warming it
+//! up with `ModuleCache::precompile`, the compilations running without
+//! holding its lock.
+
+use crate::execution::{Compiler, RuntimeModule};
+use crate::{CondomLimits, FloatMode, GasCosts, RuntimeConfig};
+use anyhow::Result;
+use parking_lot::Mutex;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+/// Hash of a bytecode, used to identify cache entries
+pub type BytecodeHash = [u8; 32];
+
+/// Digest of the gas costs and of the configuration a module is compiled
+/// with
+type CostsDigest = [u8; 32];
+
+/// Gas costs and settings read by the compilation of a module: its metering
+/// and the limits it is validated against, as in the engine keys of
+/// `EngineProvider`. A module compiled under other costs or limits is not
+/// reused.
+#[derive(Debug)]
+struct CompilationCosts<'a> {
+    gas_calibration: bool,
+    operator_cost: u64,
+    /// Sorted by operator
+    operator_costs: Vec<(&'a str, u64)>,
+    memory_page_cost: u64,
+    bulk_memory_byte_cost: u64,
+    max_instance_cost: u64,
+    condom_limits: CondomLimits,
+    float_mode: FloatMode,
+    allow_mixed_abi_namespaces: bool,
+}
+
+impl CompilationCosts<'_> {
+    fn digest(gas_costs: &GasCosts, config: &RuntimeConfig) -> CostsDigest {
+        let mut operator_costs: Vec<_> = gas_costs
+            .operator_costs
+            .iter()
+            .map(|(operator, cost)| (operator.as_str(), *cost))
+            .collect();
+        operator_costs.sort_unstable();
+        let costs = CompilationCosts {
+            gas_calibration: config.gas_calibration,
+            operator_cost: gas_costs.operator_cost,
+            operator_costs,
+            memory_page_cost: gas_costs.memory_page_cost,
+            bulk_memory_byte_cost: gas_costs.bulk_memory_byte_cost,
+            max_instance_cost: gas_costs.max_instance_cost,
+            condom_limits: config.condom_limits,
+            float_mode: config.float_mode,
+            allow_mixed_abi_namespaces: config.allow_mixed_abi_namespaces,
+        };
+        Sha256::digest(format!("{:?}", costs)).into()
+    }
+}
+
+/// Key of a cache entry
+type CacheKey = (BytecodeHash, Compiler, CostsDigest);
+
+struct CacheEntry {
+    module: RuntimeModule,
+    /// Size accounted in the cache byte budget
+    size: usize,
+    /// Tick of the last access, used for LRU eviction
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct CacheState {
+    entries: HashMap<CacheKey, CacheEntry>,
+    /// Hash of the bytecode last bound to each address
+    addresses: HashMap<String, BytecodeHash>,
+    used_bytes: usize,
+    tick: u64,
+    compilation_count: u64,
+}
+
+impl CacheState {
+    fn next_tick(&mut self) -> u64 {
+        self.tick = self.tick.wrapping_add(1);
+        self.tick
+    }
+
+    /// Evict the least recently used entries until `size` bytes fit in the
+    /// `byte_budget`
+    fn make_room(&mut self, size: usize, byte_budget: usize) {
+        while self.used_bytes + size > byte_budget {
+            let Some(key) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+            if let Some(entry) = self.entries.remove(&key) {
+                self.used_bytes -= entry.size;
+            }
+        }
+    }
+
+    /// Remove every compiled module of the bytecode identified by `hash`
+    fn remove_hash(&mut self, hash: &BytecodeHash) {
+        let keys: Vec<_> = self
+            .entries
+            .keys()
+            .filter(|(entry_hash, _, _)| entry_hash == hash)
+            .cloned()
+            .collect();
+        for key in keys {
+            if let Some(entry) = self.entries.remove(&key) {
+                self.used_bytes -= entry.size;
+            }
+        }
+    }
+}
+
+/// Module compiled by `ModuleCache::precompile` ahead of its executions.
+///
+/// Cloning a handle does not copy the module. Its executions skip the cache
+/// lookup, `run_function` and the other entry points taking
+/// `CachedModuleHandle::module`.
+#[derive(Clone)]
+pub struct CachedModuleHandle {
+    hash: BytecodeHash,
+    module: RuntimeModule,
+}
+
+impl CachedModuleHandle {
+    /// Hash of the bytecode the module was compiled from
+    pub fn hash(&self) -> &BytecodeHash {
+        &self.hash
+    }
+
+    pub fn compiler(&self) -> Compiler {
+        self.module.compiler()
+    }
+
+    /// Compiled module, to be executed
+    pub fn module(&self) -> RuntimeModule {
+        self.module.clone()
+    }
+}
+
+impl From<CachedModuleHandle> for RuntimeModule {
+    fn from(handle: CachedModuleHandle) -> Self {
+        handle.module
+    }
+}
+
+/// LRU cache of compiled modules bounded by a byte budget.
+///
+/// The size of an entry is the size of the bytecode it was compiled from.
+pub struct ModuleCache {
+    byte_budget: usize,
+    state: Mutex<CacheState>,
+}
+
+impl ModuleCache {
+    pub fn new(byte_budget: usize) -> Self {
+        Self {
+            byte_budget,
+            state: Mutex::new(CacheState::default()),
+        }
+    }
+
+    /// Hash identifying `bytecode` in the cache
+    pub fn hash_bytecode(bytecode: &[u8]) -> BytecodeHash {
+        Sha256::digest(bytecode).into()
+    }
+
+    /// Get the compiled module of the bytecode identified by `hash` from the
+    /// cache, without compiling it when it is missing. Only a module compiled
+    /// with `compiler` and the same compilation costs and limits as
+    /// `gas_costs` and `config` is returned.
+    ///
+    /// Meant for `Interface::get_cached_module`.
+    pub fn get(
+        &self,
+        hash: &BytecodeHash,
+        gas_costs: &GasCosts,
+        config: &RuntimeConfig,
+        compiler: Compiler,
+    ) -> Option<RuntimeModule> {
+        self.get_by_key(&(*hash, compiler, CompilationCosts::digest(gas_costs, config)))
+    }
+
+    fn get_by_key(&self, key: &CacheKey) -> Option<RuntimeModule> {
+        let mut state = self.state.lock();
+        let tick = state.next_tick();
+        let entry = state.entries.get_mut(key)?;
+        entry.last_used = tick;
+        Some(entry.module.clone())
+    }
+
+    /// Get the compiled module of `bytecode` from the cache, compile and
+    /// insert it if it is missing
+    pub fn get_or_compile(
+        &self,
+        bytecode: &[u8],
+        gas_costs: GasCosts,
+        config: &RuntimeConfig,
+        compiler: Compiler,
+    ) -> Result<RuntimeModule> {
+        let hash = Self::hash_bytecode(bytecode);
+        self.get_or_compile_hashed(hash, bytecode, gas_costs, config, compiler)
+    }
+
+    /// Validate and compile `bytecode` ahead of its executions, and insert
+    /// it in the cache. Meant to be called from a worker thread with the
+    /// bytecode of the smart contracts likely to be called soon, while other
+    /// executions use the cache. A module already cached with the same
+    /// compiler, compilation costs and limits is not compiled again.
+    pub fn precompile(
+        &self,
+        bytecode: &[u8],
+        gas_costs: GasCosts,
+        config: &RuntimeConfig,
+        compiler: Compiler,
+    ) -> Result<CachedModuleHandle> {
+        let hash = Self::hash_bytecode(bytecode);
+        let module = self.get_or_compile_hashed(hash, bytecode, gas_costs, config, compiler)?;
+        Ok(CachedModuleHandle { hash, module })
+    }
+
+    fn get_or_compile_hashed(
+        &self,
+        hash: BytecodeHash,
+        bytecode: &[u8],
+        gas_costs: GasCosts,
+        config: &RuntimeConfig,
+        compiler: Compiler,
+    ) -> Result<RuntimeModule> {
+        let key = (
+            hash,
+            compiler.clone(),
+            CompilationCosts::digest(&gas_costs, config),
+        );
+        if let Some(module) = self.get_by_key(&key) {
+            return Ok(module);
+        }
+
+        // compile without holding the lock
+        let module = RuntimeModule::new(bytecode, gas_costs, config, compiler)?;
+
+        let mut state = self.state.lock();
+        state.compilation_count += 1;
+        let size = bytecode.len();
+        if size > self.byte_budget {
+            return Ok(module);
+        }
+        // compiled meanwhile by another thread, whose module is kept
+        if let Some(entry) = state.entries.get(&key) {
+            return Ok(entry.module.clone());
+        }
+        state.make_room(size, self.byte_budget);
+        let last_used = state.next_tick();
+        state.used_bytes += size;
+        state.entries.insert(
+            key,
+            CacheEntry {
+                module: module.clone(),
+                size,
+                last_used,
+            },
+        );
+        Ok(module)
+    }
+
+    /// Remove every compiled module of the bytecode identified by `hash`.
+    ///
+    /// Must be called when the bytecode of an address is overwritten.
+    pub fn invalidate(&self, hash: &BytecodeHash) {
+        self.state.lock().remove_hash(hash);
+    }
+
+    /// Record that `address` holds the bytecode identified by `hash`, for
+    /// `invalidate_address`. Meant for `Interface::init_call_hash`.
+    pub fn bind_address(&self, address: &str, hash: BytecodeHash) {
+        self.state
+            .lock()
+            .addresses
+            .insert(address.to_string(), hash);
+    }
+
+    /// Remove the compiled modules of the bytecode last bound to `address`,
+    /// unless another address is bound to the same bytecode.
+    ///
+    /// Meant for `Interface::invalidate_cached_module`.
+    pub fn invalidate_address(&self, address: &str) {
+        let mut state = self.state.lock();
+        let Some(hash) = state.addresses.remove(address) else {
+            return;
+        };
+        if !state.addresses.values().any(|bound| bound == &hash) {
+            state.remove_hash(&hash);
+        }
+    }
+
+    /// Number of compilations performed by the cache since its creation
+    pub fn compilation_count(&self) -> u64 {
+        self.state.lock().compilation_count
+    }
+
+    /// Number of bytes currently accounted in the cache
+    pub fn used_bytes(&self) -> usize {
+        self.state.lock().used_bytes
+    }
+
+    /// Number of cached modules
+    pub fn len(&self) -> usize {
+        self.state.lock().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BYTECODE: &[u8] =
+        include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/wasm/basic_main.wasm"));
+    const OTHER_BYTECODE: &[u8] =
+        include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/wasm/basic_func.wasm"));
+
+    #[test]
+    fn test_second_get_skips_compilation() {
+        let cache = ModuleCache::new(usize::MAX);
+        cache
+            .get_or_compile(
+                BYTECODE,
+                GasCosts::default(),
+                &RuntimeConfig::default(),
+                Compiler::SP,
+            )
+            .unwrap();
+        cache
+            .get_or_compile(
+                BYTECODE,
+                GasCosts::default(),
+                &RuntimeConfig::default(),
+                Compiler::SP,
+            )
+            .unwrap();
+        assert_eq!(cache.compilation_count(), 1);
+
+        // the compiler is part of the key
+        #[cfg(feature = "cranelift")]
+        {
+            cache
+                .get_or_compile(
+                    BYTECODE,
+                    GasCosts::default(),
+                    &RuntimeConfig::default(),
+                    Compiler::CL,
+                )
+                .unwrap();
+            assert_eq!(cache.compilation_count(), 2);
+            assert_eq!(cache.len(), 2);
+        }
+    }
+
+    #[test]
+    fn test_get_does_not_compile() {
+        let cache = ModuleCache::new(usize::MAX);
+        let hash = ModuleCache::hash_bytecode(BYTECODE);
+        assert!(cache
+            .get(
+                &hash,
+                &GasCosts::default(),
+                &RuntimeConfig::default(),
+                Compiler::SP
+            )
+            .is_none());
+        cache
+            .get_or_compile(
+                BYTECODE,
+                GasCosts::default(),
+                &RuntimeConfig::default(),
+                Compiler::SP,
+            )
+            .unwrap();
+        assert!(cache
+            .get(
+                &hash,
+                &GasCosts::default(),
+                &RuntimeConfig::default(),
+                Compiler::SP
+            )
+            .is_some());
+        assert_eq!(cache.compilation_count(), 1);
+    }
+
+    #[test]
+    fn test_invalidate() {
+        let cache = ModuleCache::new(usize::MAX);
+        cache
+            .get_or_compile(
+                BYTECODE,
+                GasCosts::default(),
+                &RuntimeConfig::default(),
+                Compiler::SP,
+            )
+            .unwrap();
+        cache.invalidate(&ModuleCache::hash_bytecode(BYTECODE));
+        assert!(cache.is_empty());
+        assert_eq!(cache.used_bytes(), 0);
+
+        cache
+            .get_or_compile(
+                BYTECODE,
+                GasCosts::default(),
+                &RuntimeConfig::default(),
+                Compiler::SP,
+            )
+            .unwrap();
+        assert_eq!(cache.compilation_count(), 2);
+    }
+
+    #[test]
+    fn test_precompile() {
+        let cache = ModuleCache::new(usize::MAX);
+        let handle = cache
+            .precompile(
+                BYTECODE,
+                GasCosts::default(),
+                &RuntimeConfig::default(),
+                Compiler::SP,
+            )
+            .unwrap();
+        assert_eq!(handle.hash(), &ModuleCache::hash_bytecode(BYTECODE));
+        assert_eq!(handle.compiler(), Compiler::SP);
+        assert_eq!(cache.compilation_count(), 1);
+
+        // the precompiled module is the cached one
+        assert!(cache
+            .get(
+                handle.hash(),
+                &GasCosts::default(),
+                &RuntimeConfig::default(),
+                Compiler::SP
+            )
+            .is_some());
+        cache
+            .get_or_compile(
+                BYTECODE,
+                GasCosts::default(),
+                &RuntimeConfig::default(),
+                Compiler::SP,
+            )
+            .unwrap();
+        cache
+            .precompile(
+                BYTECODE,
+                GasCosts::default(),
+                &RuntimeConfig::default(),
+                Compiler::SP,
+            )
+            .unwrap();
+        assert_eq!(cache.compilation_count(), 1);
+
+        // the bytecode is validated
+        assert!(cache
+            .precompile(
+                &[0, 1, 2],
+                GasCosts::default(),
+                &RuntimeConfig::default(),
+                Compiler::SP
+            )
+            .is_err());
+        assert_eq!(cache.len(), 1);
+
+        // the costs of the compilation are honoured
+        let costs = GasCosts {
+            operator_cost: GasCosts::default().operator_cost + 1,
+            ..GasCosts::default()
+        };
+        let handle = cache
+            .precompile(
+                BYTECODE,
+                costs.clone(),
+                &RuntimeConfig::default(),
+                Compiler::SP,
+            )
+            .unwrap();
+        assert_eq!(cache.compilation_count(), 2);
+        assert_eq!(cache.len(), 2);
+        assert!(cache
+            .get(
+                handle.hash(),
+                &costs,
+                &RuntimeConfig::default(),
+                Compiler::SP
+            )
+            .is_some());
+    }
+
+    #[test]
+    fn test_gas_costs_key() {
+        let cache = ModuleCache::new(usize::MAX);
+        let hash = ModuleCache::hash_bytecode(BYTECODE);
+        cache
+            .get_or_compile(
+                BYTECODE,
+                GasCosts::default(),
+                &RuntimeConfig::default(),
+                Compiler::SP,
+            )
+            .unwrap();
+
+        let mut costs = GasCosts::default();
+        let mut config = RuntimeConfig::default();
+        costs.operator_cost += 1;
+        assert!(cache.get(&hash, &costs, &config, Compiler::SP).is_none());
+        cache
+            .get_or_compile(BYTECODE, costs.clone(), &config, Compiler::SP)
+            .unwrap();
+        assert_eq!(cache.compilation_count(), 2);
+
+        // the costs and limits read at execution time only are not part of
+        // the key
+        costs.launch_cost += 1;
+        config.max_return_size += 1;
+        assert!(cache.get(&hash, &costs, &config, Compiler::SP).is_some());
+
+        config.condom_limits.max_exports += 1;
+        assert!(cache.get(&hash, &costs, &config, Compiler::SP).is_none());
+    }
+
+    #[test]
+    fn test_invalidate_address() {
+        let cache = ModuleCache::new(usize::MAX);
+        let hash = ModuleCache::hash_bytecode(BYTECODE);
+        cache
+            .get_or_compile(
+                BYTECODE,
+                GasCosts::default(),
+                &RuntimeConfig::default(),
+                Compiler::SP,
+            )
+            .unwrap();
+        cache.bind_address("first", hash);
+        cache.bind_address("second", hash);
+
+        // still the bytecode of the second address
+        cache.invalidate_address("first");
+        assert_eq!(cache.len(), 1);
+
+        cache.invalidate_address("second");
+        assert!(cache.is_empty());
+        assert_eq!(cache.used_bytes(), 0);
+
+        // unbound addresses are ignored
+        cache.invalidate_address("unknown");
+    }
+
+    #[test]
+    fn test_byte_budget_eviction() {
+        let cache = ModuleCache::new(BYTECODE.len().max(OTHER_BYTECODE.len()));
+        cache
+            .get_or_compile(
+                BYTECODE,
+                GasCosts::default(),
+                &RuntimeConfig::default(),
+                Compiler::SP,
+            )
+            .unwrap();
+        cache
+            .get_or_compile(
+                OTHER_BYTECODE,
+                GasCosts::default(),
+                &RuntimeConfig::default(),
+                Compiler::SP,
+            )
+            .unwrap();
+        assert_eq!(cache.len(), 1);
+        assert!(cache.used_bytes() <= BYTECODE.len().max(OTHER_BYTECODE.len()));
+
+        // the least recently used module was evicted
+        cache
+            .get_or_compile(
+                BYTECODE,
+                GasCosts::default(),
+                &RuntimeConfig::default(),
+                Compiler::SP,
+            )
+            .unwrap();
+        assert_eq!(cache.compilation_count(), 3);
+    }
+}
@@ -0,0 +1,89 @@
+//! Runtime-level events of the executions, reported to an
+//! `ExecutionObserver` for block explorers and debuggers.
+//!
+//! The observer is handed down to the nested executions through the
+//! `CallContext`. Without one, the events are not even built.
+
+use parking_lot::Mutex;
+use std::fmt::{self, Debug};
+use std::sync::Arc;
+
+/// Event of an execution of the call tree
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ExecutionEvent {
+    /// An execution of `function` starts, given `gas_limit`
+    ExecutionStarted { function: String, gas_limit: u64 },
+    /// The execution of `function` ended, with `error` if it failed
+    ExecutionFinished {
+        function: String,
+        remaining_gas: u64,
+        error: Option<String>,
+    },
+    /// An ABI was called and charged, leaving `remaining_gas`
+    AbiCalled { abi: String, remaining_gas: u64 },
+    /// An argument of `len` bytes was read from the guest memory by `abi`
+    AbiArgument { abi: String, len: usize },
+    /// A call to `function` of the smart contract at `address` starts, given
+    /// `gas_limit`
+    NestedCallStarted {
+        address: String,
+        function: String,
+        gas_limit: u64,
+    },
+    /// The call to `function` of the smart contract at `address` ended
+    NestedCallFinished {
+        address: String,
+        function: String,
+        remaining_gas: u64,
+        success: bool,
+    },
+}
+
+/// Receiver of the events of the executions of a call tree.
+///
+/// The observer only gets copies of the execution state and cannot change
+/// the outcome of an execution.
+pub trait ExecutionObserver: Send + Sync {
+    /// Called with the depth of the execution the event belongs to, 0 being
+    /// the top-level one
+    fn on_event(&self, depth: u32, event: ExecutionEvent);
+}
+
+/// Observer keeping every event with the depth of its execution
+#[derive(Debug, Default)]
+pub struct RecordingObserver {
+    events: Mutex<Vec<(u32, ExecutionEvent)>>,
+}
+
+impl RecordingObserver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Events recorded so far, in the order they occurred
+    pub fn events(&self) -> Vec<(u32, ExecutionEvent)> {
+        self.events.lock().clone()
+    }
+}
+
+impl ExecutionObserver for RecordingObserver {
+    fn on_event(&self, depth: u32, event: ExecutionEvent) {
+        self.events.lock().push((depth, event));
+    }
+}
+
+/// Observer of a call tree, shared by its executions
+#[derive(Clone)]
+pub(crate) struct SharedObserver(pub Arc<dyn ExecutionObserver>);
+
+impl SharedObserver {
+    pub(crate) fn on_event(&self, depth: u32, event: ExecutionEvent) {
+        self.0.on_event(depth, event);
+    }
+}
+
+impl Debug for SharedObserver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SharedObserver")
+    }
+}
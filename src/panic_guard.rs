@@ -0,0 +1,95 @@
+//! Panics of the host during an execution, turned into execution errors.
+//!
+//! wasmer catches a panic of a host function, unwinds the guest frames and
+//! resumes the panic where the guest was called, which would carry it into
+//! the node. The executions catch it there with `catch_abi_panic`, so that a
+//! faulty ABI or interface method fails the execution it was called by and
+//! nothing else: a panic in a nested call fails the nested call.
+//!
+//! The ABIs open an `AbiScope` for their whole body. A scope dropped while
+//! unwinding leaves the name of its ABI for the catch.
+
+use crate::as_execution::ABIError;
+use std::any::Any;
+use std::cell::RefCell;
+use std::panic::{self, AssertUnwindSafe};
+
+thread_local! {
+    /// ABI the panic being unwound went through, the innermost one
+    static PANICKED_ABI: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Body of a call to an ABI, naming the ABI if a panic unwinds through it
+#[must_use]
+pub(crate) struct AbiScope<'a>(&'a str);
+
+impl<'a> AbiScope<'a> {
+    pub(crate) fn new(abi_name: &'a str) -> Self {
+        Self(abi_name)
+    }
+}
+
+impl Drop for AbiScope<'_> {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            PANICKED_ABI.with(|abi| {
+                abi.borrow_mut().get_or_insert_with(|| self.0.to_string());
+            });
+        }
+    }
+}
+
+/// Run `run`, one of its panics being returned as an
+/// `ABIError::InternalError` naming the ABI that panicked
+pub(crate) fn catch_abi_panic<T>(run: impl FnOnce() -> T) -> Result<T, ABIError> {
+    PANICKED_ABI.with(|abi| abi.borrow_mut().take());
+    panic::catch_unwind(AssertUnwindSafe(run)).map_err(|payload| ABIError::InternalError {
+        abi: PANICKED_ABI
+            .with(|abi| abi.borrow_mut().take())
+            .unwrap_or_else(|| "the host".to_string()),
+        message: panic_message(&*payload),
+    })
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "non-string panic payload".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_catch_abi_panic() {
+        assert_eq!(catch_abi_panic(|| 7).unwrap(), 7);
+
+        // the innermost scope is named
+        let err = catch_abi_panic(|| {
+            let _outer = AbiScope::new("assembly_script_call");
+            let _inner = AbiScope::new("assembly_script_get_balance");
+            panic!("index out of bounds: {}", 3);
+        })
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Internal error in assembly_script_get_balance: index out of bounds: 3"
+        );
+
+        // a panic outside of an ABI, the name of the previous one is not kept
+        let err = catch_abi_panic(|| panic!("no ABI")).unwrap_err();
+        assert_eq!(err.to_string(), "Internal error in the host: no ABI");
+        // a scope dropped normally leaves nothing behind
+        drop(AbiScope::new("assembly_script_print"));
+        let err = catch_abi_panic(|| std::panic::panic_any(3)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Internal error in the host: non-string panic payload"
+        );
+    }
+}
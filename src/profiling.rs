@@ -0,0 +1,124 @@
+//! Per-ABI accounting of executions, enabled by the `profiling` feature.
+//!
+//! Without the feature only the `AbiCall` marker is compiled, so the ABIs do
+//! not pay for any recording.
+
+#[cfg(feature = "profiling")]
+use parking_lot::Mutex;
+#[cfg(feature = "profiling")]
+use std::{
+    collections::BTreeMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// Usage of a single ABI during an execution
+#[cfg(feature = "profiling")]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AbiProfile {
+    /// Number of calls to the ABI
+    pub calls: u64,
+    /// Time spent in the host, nested executions of the call ABIs included
+    pub host_time: Duration,
+    /// Gas charged by the ABI itself, nested executions excluded
+    pub gas: u64,
+}
+
+/// Usage of every ABI called during an execution and its nested calls
+#[cfg(feature = "profiling")]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ExecutionProfile {
+    pub abis: BTreeMap<String, AbiProfile>,
+}
+
+#[cfg(feature = "profiling")]
+impl ExecutionProfile {
+    fn entry(&mut self, abi_name: &str) -> &mut AbiProfile {
+        self.abis.entry(abi_name.to_string()).or_default()
+    }
+}
+
+/// Shared recorder of an `ExecutionProfile`, handed down to the nested
+/// executions through the `CallContext`
+#[cfg(feature = "profiling")]
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Profiler(Arc<Mutex<ExecutionProfile>>);
+
+#[cfg(feature = "profiling")]
+impl Profiler {
+    /// Start timing a call to `abi_name`, recorded when the returned value is
+    /// dropped
+    pub(crate) fn start_call(&self, abi_name: &str) -> AbiCall {
+        AbiCall {
+            profiler: self.clone(),
+            abi_name: abi_name.to_string(),
+            start: Instant::now(),
+        }
+    }
+
+    pub(crate) fn record_gas(&self, abi_name: &str, gas: u64) {
+        let mut profile = self.0.lock();
+        let entry = profile.entry(abi_name);
+        entry.gas = entry.gas.saturating_add(gas);
+    }
+
+    /// Take the recorded profile, leaving an empty one
+    pub(crate) fn take(&self) -> ExecutionProfile {
+        std::mem::take(&mut *self.0.lock())
+    }
+}
+
+/// Pending call to an ABI, recorded when dropped
+#[cfg(feature = "profiling")]
+#[must_use]
+pub(crate) struct AbiCall {
+    profiler: Profiler,
+    abi_name: String,
+    start: Instant,
+}
+
+#[cfg(feature = "profiling")]
+impl Drop for AbiCall {
+    fn drop(&mut self) {
+        let elapsed = self.start.elapsed();
+        let mut profile = self.profiler.0.lock();
+        let entry = profile.entry(&self.abi_name);
+        entry.calls += 1;
+        entry.host_time += elapsed;
+    }
+}
+
+/// Pending call to an ABI, nothing is recorded without the `profiling`
+/// feature
+#[cfg(not(feature = "profiling"))]
+#[must_use]
+pub(crate) struct AbiCall;
+
+#[cfg(all(test, feature = "profiling"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profiler_record() {
+        let profiler = Profiler::default();
+        let nested = profiler.clone();
+        {
+            let _call = profiler.start_call("assembly_script_print");
+            profiler.record_gas("assembly_script_print", 10);
+        }
+        {
+            let _call = nested.start_call("assembly_script_print");
+            nested.record_gas("assembly_script_print", 10);
+            nested.record_gas("assembly_script_print", 5);
+        }
+        drop(profiler.start_call("assembly_script_get_time"));
+
+        let profile = profiler.take();
+        assert_eq!(profile.abis.len(), 2);
+        let print = &profile.abis["assembly_script_print"];
+        assert_eq!((print.calls, print.gas), (2, 25));
+        let get_time = &profile.abis["assembly_script_get_time"];
+        assert_eq!((get_time.calls, get_time.gas), (1, 0));
+        assert!(profiler.take().abis.is_empty());
+    }
+}
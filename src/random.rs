@@ -0,0 +1,93 @@
+//! Deterministic randomness of a call tree.
+//!
+//! The 32 bytes seed is given by `Interface::get_random_seed`, which the node
+//! derives from the slot. It is asked once per top-level execution and shared
+//! by all its nested calls, so that every frame sees the same seed.
+//!
+//! The random bytes are expanded from the seed, every embedder has to use the
+//! same algorithm for the executions to agree. The `counter`-th expansion of
+//! a call tree, starting at 0, is made of the blocks
+//! `SHA-256(seed || counter || index)` for `index` = 0, 1, ..., with `counter`
+//! as 8 bytes and `index` as 4 bytes little-endian, concatenated and
+//! truncated to the requested length.
+
+use crate::Interface;
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+
+/// Seed of the randomness of a call tree
+pub(crate) type RandomSeed = [u8; 32];
+
+/// Seed of a call tree and number of expansions made from it
+#[derive(Debug, Default)]
+pub(crate) struct RandomState {
+    seed: Option<RandomSeed>,
+    counter: u64,
+}
+
+impl RandomState {
+    /// Seed of the call tree, asked to `interface` on the first call
+    pub(crate) fn seed(&mut self, interface: &dyn Interface) -> Result<RandomSeed> {
+        if let Some(seed) = self.seed {
+            return Ok(seed);
+        }
+        let seed = interface.get_random_seed()?;
+        self.seed = Some(seed);
+        Ok(seed)
+    }
+
+    /// Next `len` random bytes of the call tree
+    pub(crate) fn next_bytes(&mut self, interface: &dyn Interface, len: usize) -> Result<Vec<u8>> {
+        let seed = self.seed(interface)?;
+        let bytes = expand(&seed, self.counter, len);
+        self.counter = self.counter.wrapping_add(1);
+        Ok(bytes)
+    }
+}
+
+/// `counter`-th expansion of `seed` into `len` bytes, see the module
+/// documentation
+pub(crate) fn expand(seed: &RandomSeed, counter: u64, len: usize) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(len);
+    let mut index: u32 = 0;
+    while bytes.len() < len {
+        let block = Sha256::new()
+            .chain_update(seed)
+            .chain_update(counter.to_le_bytes())
+            .chain_update(index.to_le_bytes())
+            .finalize();
+        bytes.extend_from_slice(&block);
+        index = index.wrapping_add(1);
+    }
+    bytes.truncate(len);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seed() -> RandomSeed {
+        std::array::from_fn(|i| i as u8)
+    }
+
+    #[test]
+    fn test_expand() {
+        // pinned, the embedders have to agree on them
+        assert_eq!(
+            expand(&seed(), 0, 40),
+            [
+                250, 30, 126, 30, 200, 152, 207, 146, 107, 20, 55, 98, 6, 31, 110, 126, 48, 184,
+                116, 132, 211, 224, 229, 107, 121, 243, 48, 67, 12, 44, 0, 72, 137, 98, 145, 148,
+                199, 130, 255, 196,
+            ]
+        );
+        assert_eq!(
+            expand(&seed(), 1, 8),
+            [140, 135, 144, 243, 11, 234, 22, 196,]
+        );
+        // a shorter expansion is a prefix of a longer one
+        assert_eq!(expand(&seed(), 0, 8), expand(&seed(), 0, 40)[..8]);
+        assert!(expand(&seed(), 0, 0).is_empty());
+    }
+}
@@ -0,0 +1,247 @@
+//! Cache of the read-only executions, for the nodes answering the same
+//! simulated calls over and over.
+//!
+//! `run_read_only_cached` looks the call up in a `ReadOnlyCache` before
+//! compiling the bytecode. The key holds a ledger version token supplied by
+//! the embedder, which has to change whenever the state the call could read
+//! does. On a hit the events of the cached execution are emitted again
+//! through the interface and its `Response` is returned as is.
+//!
+//! An execution is only cached if its outcome only depends on the key: it
+//! succeeded, none of its ABI calls was tagged in `NONDETERMINISTIC_ABIS`,
+//! and none of its nested calls failed, the events of a failed call being
+//! rolled back by the interface in a way the runtime does not see.
+
+use crate::execution::Compiler;
+use crate::types::{GasCosts, Interface, Response, RuntimeConfig};
+use anyhow::Result;
+use parking_lot::Mutex;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+/// ABIs whose result does not only depend on the ledger, such as the time
+/// and the random numbers, refusing the caching of the executions calling
+/// them
+pub(crate) const NONDETERMINISTIC_ABIS: &[&str] = &[
+    "assembly_script_date_now",
+    "assembly_script_get_time",
+    "assembly_script_seed",
+    "assembly_script_unsafe_random",
+    "assembly_script_get_random_seed",
+    "assembly_script_random_bytes",
+    "abi_get_native_time",
+    "abi_unsafe_random",
+];
+
+/// Key of a cached read-only execution
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ReadOnlyCacheKey {
+    /// Address of the smart contract called
+    pub address: String,
+    pub function: String,
+    /// SHA-256 of the parameter of the call
+    pub param_hash: [u8; 32],
+    /// Gas given to the execution, the `Response` depends on it
+    pub gas_limit: u64,
+    /// Compiler of the module, whose compilation cost is part of the
+    /// `Response`
+    pub compiler: Compiler,
+    /// Digest of the gas costs of the execution, see `GasCosts::digest`
+    pub gas_costs_digest: [u8; 32],
+    /// Digest of the configuration of the execution, see
+    /// `RuntimeConfig::digest`
+    pub config_digest: [u8; 32],
+    /// Token of the state of the ledger the call ran against, supplied by the
+    /// embedder
+    pub ledger_version: Vec<u8>,
+}
+
+impl ReadOnlyCacheKey {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        address: &str,
+        function: &str,
+        param: &[u8],
+        gas_limit: u64,
+        ledger_version: &[u8],
+        compiler: Compiler,
+        gas_costs: &GasCosts,
+        config: &RuntimeConfig,
+    ) -> Self {
+        Self {
+            address: address.to_string(),
+            function: function.to_string(),
+            param_hash: Sha256::digest(param).into(),
+            gas_limit,
+            compiler,
+            gas_costs_digest: gas_costs.digest(),
+            config_digest: config.digest(),
+            ledger_version: ledger_version.to_vec(),
+        }
+    }
+}
+
+/// Event emitted by a cached execution, with the interface method it was
+/// given to
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CachedEvent {
+    /// `Interface::generate_event_indexed`, or `Interface::generate_event`
+    /// without an index
+    Text { index: Option<u64>, event: String },
+    /// `Interface::generate_event_binary`
+    Binary {
+        topics: Vec<[u8; 32]>,
+        data: Vec<u8>,
+    },
+    /// `Interface::generate_event_wasmv1`
+    WasmV1(Vec<u8>),
+}
+
+/// Outcome of a read-only execution kept by a `ReadOnlyCache`
+#[derive(Clone, Debug)]
+pub struct CachedExecution {
+    pub response: Response,
+    /// Events of the execution and its nested calls, in order
+    pub events: Vec<CachedEvent>,
+}
+
+impl CachedExecution {
+    /// Emit the events of the execution again through `interface`
+    pub(crate) fn replay_events(&self, interface: &dyn Interface) -> Result<()> {
+        for event in &self.events {
+            match event {
+                CachedEvent::Text {
+                    index: Some(index),
+                    event,
+                } => interface.generate_event_indexed(*index, event.clone())?,
+                CachedEvent::Text { index: None, event } => {
+                    interface.generate_event(event.clone())?
+                }
+                CachedEvent::Binary { topics, data } => {
+                    interface.generate_event_binary(topics, data)?
+                }
+                CachedEvent::WasmV1(event) => interface.generate_event_wasmv1(event.clone())?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Storage of the read-only executions given to `run_read_only_cached`. The
+/// eviction is left to the implementations.
+pub trait ReadOnlyCache: Send + Sync {
+    fn get(&self, key: &ReadOnlyCacheKey) -> Option<CachedExecution>;
+    fn insert(&self, key: ReadOnlyCacheKey, execution: CachedExecution);
+}
+
+/// What a call tree being cached did so far
+#[derive(Debug, Default)]
+struct Record {
+    events: Vec<CachedEvent>,
+    /// Why the execution cannot be cached, if it cannot
+    uncacheable: Option<String>,
+}
+
+/// Recorder of a call tree run by `run_read_only_cached`, shared by its
+/// executions
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ReadOnlyRecorder(Arc<Mutex<Record>>);
+
+impl ReadOnlyRecorder {
+    /// Account for a call to `abi`
+    pub(crate) fn abi_called(&self, abi: &str) {
+        if NONDETERMINISTIC_ABIS.contains(&abi) {
+            self.refuse(|| format!("nondeterministic ABI {}", abi));
+        }
+    }
+
+    pub(crate) fn event_emitted(&self, event: CachedEvent) {
+        self.0.lock().events.push(event);
+    }
+
+    pub(crate) fn nested_call_failed(&self, address: &str) {
+        self.refuse(|| format!("failed call to {}", address));
+    }
+
+    fn refuse(&self, reason: impl FnOnce() -> String) {
+        let mut record = self.0.lock();
+        if record.uncacheable.is_none() {
+            record.uncacheable = Some(reason());
+        }
+    }
+
+    /// Events recorded, or why the call tree cannot be cached
+    pub(crate) fn finish(&self) -> Result<Vec<CachedEvent>, String> {
+        let mut record = self.0.lock();
+        match record.uncacheable.take() {
+            Some(reason) => Err(reason),
+            None => Ok(std::mem::take(&mut record.events)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recorder() {
+        let recorder = ReadOnlyRecorder::default();
+        let nested = recorder.clone();
+        recorder.abi_called("assembly_script_get_data");
+        nested.event_emitted(CachedEvent::WasmV1(vec![1]));
+        assert_eq!(recorder.finish(), Ok(vec![CachedEvent::WasmV1(vec![1])]));
+
+        nested.abi_called("abi_unsafe_random");
+        recorder.nested_call_failed("AS1");
+        // the first reason is kept
+        assert_eq!(
+            recorder.finish(),
+            Err("nondeterministic ABI abi_unsafe_random".to_string())
+        );
+    }
+
+    #[test]
+    fn test_key() {
+        let gas_costs = GasCosts::default();
+        let config = RuntimeConfig::default();
+        let key = |address, param: &[u8], gas_limit, ledger_version: &[u8], gas_costs, config| {
+            ReadOnlyCacheKey::new(
+                address,
+                "f",
+                param,
+                gas_limit,
+                ledger_version,
+                Compiler::SP,
+                gas_costs,
+                config,
+            )
+        };
+        let mut other_costs = gas_costs.clone();
+        other_costs.storage_refund_per_byte += 1;
+        let mut other_config = config.clone();
+        other_config.max_return_size += 1;
+        let expected = key("AS1", b"param", 1_000, b"v1", &gas_costs, &config);
+        assert_eq!(
+            expected,
+            key(
+                "AS1",
+                b"param",
+                1_000,
+                b"v1",
+                &gas_costs.clone(),
+                &config.clone()
+            )
+        );
+        for other in [
+            key("AS2", b"param", 1_000, b"v1", &gas_costs, &config),
+            key("AS1", b"param2", 1_000, b"v1", &gas_costs, &config),
+            key("AS1", b"param", 2_000, b"v1", &gas_costs, &config),
+            key("AS1", b"param", 1_000, b"v2", &gas_costs, &config),
+            key("AS1", b"param", 1_000, b"v1", &other_costs, &config),
+            key("AS1", b"param", 1_000, b"v1", &gas_costs, &other_config),
+        ] {
+            assert_ne!(expected, other);
+        }
+    }
+}
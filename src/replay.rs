@@ -0,0 +1,1314 @@
+//! Recording of the calls an execution makes to its `Interface`, and replay
+//! of an execution from such a recording, to debug the consensus
+//! divergences away from the node.
+//!
+//! `RecordingInterface` wraps the interface of the node and logs each call,
+//! its arguments and what it returned. `ReplayInterface` serves the answers
+//! of the log to the same execution run again, the contract, the gas costs
+//! and the gas limit being the same: the first call differing from the log,
+//! by its method or its arguments, fails with both calls.
+//!
+//! The modules the interface compiled are logged as their bytecode and the
+//! limit they were compiled with, the replay compiles them again with its
+//! gas costs, which must be the ones of the node.
+
+use crate::as_execution::ASModule;
+use crate::execution::{Compiler, RuntimeModule};
+use crate::module_cache::BytecodeHash;
+use crate::types::{Interface, InterfaceClone, TransferError};
+use crate::wasmv1_execution::WasmV1Module;
+use crate::{GasCosts, RuntimeConfig};
+use anyhow::{anyhow, bail, Result};
+use massa_proto_rs::massa::model::v1::{
+    AddressCategory, ComparisonResult, NativeAmount, NativeTime, Slot,
+};
+use parking_lot::Mutex;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Display};
+use std::sync::Arc;
+
+/// Version of the format of the logs
+const LOG_VERSION: u8 = 1;
+
+/// Bytes of a logged byte array shown in a divergence, the longer ones are
+/// cut
+const SHOWN_BYTES: usize = 32;
+
+/// Argument or result of a logged call
+#[derive(Clone, Debug, PartialEq)]
+enum LogValue {
+    Unit,
+    Bool(bool),
+    Int(u64),
+    Signed(i64),
+    /// Bits of a `f64`, so that the NaNs compare equal
+    Float(u64),
+    Bytes(Vec<u8>),
+    Str(String),
+    None,
+    Some(Box<LogValue>),
+    List(Vec<LogValue>),
+}
+
+impl Display for LogValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogValue::Unit => write!(f, "()"),
+            LogValue::Bool(value) => write!(f, "{}", value),
+            LogValue::Int(value) => write!(f, "{}", value),
+            LogValue::Signed(value) => write!(f, "{}", value),
+            LogValue::Float(bits) => write!(f, "{}", f64::from_bits(*bits)),
+            LogValue::Bytes(bytes) => {
+                write!(f, "0x")?;
+                for byte in bytes.iter().take(SHOWN_BYTES) {
+                    write!(f, "{:02x}", byte)?;
+                }
+                if bytes.len() > SHOWN_BYTES {
+                    write!(f, "... ({} bytes)", bytes.len())?;
+                }
+                Ok(())
+            }
+            LogValue::Str(value) => write!(f, "{:?}", value),
+            LogValue::None => write!(f, "None"),
+            LogValue::Some(value) => write!(f, "Some({})", value),
+            LogValue::List(values) => {
+                write!(f, "[")?;
+                write_values(f, values)?;
+                write!(f, "]")
+            }
+        }
+    }
+}
+
+fn write_values(f: &mut fmt::Formatter<'_>, values: &[LogValue]) -> fmt::Result {
+    for (index, value) in values.iter().enumerate() {
+        if index > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", value)?;
+    }
+    Ok(())
+}
+
+const TAG_UNIT: u8 = 0;
+const TAG_FALSE: u8 = 1;
+const TAG_TRUE: u8 = 2;
+const TAG_INT: u8 = 3;
+const TAG_SIGNED: u8 = 4;
+const TAG_FLOAT: u8 = 5;
+const TAG_BYTES: u8 = 6;
+const TAG_STR: u8 = 7;
+const TAG_NONE: u8 = 8;
+const TAG_SOME: u8 = 9;
+const TAG_LIST: u8 = 10;
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    write_varint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+impl LogValue {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            LogValue::Unit => out.push(TAG_UNIT),
+            LogValue::Bool(false) => out.push(TAG_FALSE),
+            LogValue::Bool(true) => out.push(TAG_TRUE),
+            LogValue::Int(value) => {
+                out.push(TAG_INT);
+                write_varint(out, *value);
+            }
+            LogValue::Signed(value) => {
+                // zigzag, the small negative values stay short
+                out.push(TAG_SIGNED);
+                write_varint(out, ((*value << 1) ^ (*value >> 63)) as u64);
+            }
+            LogValue::Float(bits) => {
+                out.push(TAG_FLOAT);
+                out.extend_from_slice(&bits.to_le_bytes());
+            }
+            LogValue::Bytes(bytes) => {
+                out.push(TAG_BYTES);
+                write_bytes(out, bytes);
+            }
+            LogValue::Str(value) => {
+                out.push(TAG_STR);
+                write_bytes(out, value.as_bytes());
+            }
+            LogValue::None => out.push(TAG_NONE),
+            LogValue::Some(value) => {
+                out.push(TAG_SOME);
+                value.encode(out);
+            }
+            LogValue::List(values) => {
+                out.push(TAG_LIST);
+                write_varint(out, values.len() as u64);
+                for value in values {
+                    value.encode(out);
+                }
+            }
+        }
+    }
+}
+
+/// Decoder of a log
+struct LogReader<'a> {
+    input: &'a [u8],
+}
+
+impl<'a> LogReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if len > self.input.len() {
+            bail!("truncated log");
+        }
+        let (head, tail) = self.input.split_at(len);
+        self.input = tail;
+        Ok(head)
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn varint(&mut self) -> Result<u64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        bail!("malformed integer in the log")
+    }
+
+    fn len(&mut self) -> Result<usize> {
+        usize::try_from(self.varint()?).map_err(|_| anyhow!("malformed length in the log"))
+    }
+
+    fn bytes(&mut self) -> Result<Vec<u8>> {
+        let len = self.len()?;
+        Ok(self.take(len)?.to_vec())
+    }
+
+    fn string(&mut self) -> Result<String> {
+        String::from_utf8(self.bytes()?).map_err(|_| anyhow!("malformed string in the log"))
+    }
+
+    fn value(&mut self) -> Result<LogValue> {
+        Ok(match self.byte()? {
+            TAG_UNIT => LogValue::Unit,
+            TAG_FALSE => LogValue::Bool(false),
+            TAG_TRUE => LogValue::Bool(true),
+            TAG_INT => LogValue::Int(self.varint()?),
+            TAG_SIGNED => {
+                let zigzag = self.varint()?;
+                LogValue::Signed((zigzag >> 1) as i64 ^ -((zigzag & 1) as i64))
+            }
+            TAG_FLOAT => LogValue::Float(u64::from_le_bytes(self.take(8)?.try_into().unwrap())),
+            TAG_BYTES => LogValue::Bytes(self.bytes()?),
+            TAG_STR => LogValue::Str(self.string()?),
+            TAG_NONE => LogValue::None,
+            TAG_SOME => LogValue::Some(Box::new(self.value()?)),
+            TAG_LIST => {
+                let len = self.len()?;
+                // the length is not trusted to allocate
+                let mut values = Vec::new();
+                for _ in 0..len {
+                    values.push(self.value()?);
+                }
+                LogValue::List(values)
+            }
+            tag => bail!("unknown value tag {} in the log", tag),
+        })
+    }
+}
+
+/// Logged value of an argument or of a result
+trait ToLog {
+    fn to_log(&self) -> LogValue;
+}
+
+/// Result rebuilt from its logged value
+trait FromLog: Sized {
+    fn from_log(value: LogValue) -> Result<Self>;
+}
+
+fn unexpected<T>(expected: &str, value: &LogValue) -> Result<T> {
+    bail!("expected {} in the log, found {}", expected, value)
+}
+
+impl<T: ToLog + ?Sized> ToLog for &T {
+    fn to_log(&self) -> LogValue {
+        (**self).to_log()
+    }
+}
+
+impl ToLog for () {
+    fn to_log(&self) -> LogValue {
+        LogValue::Unit
+    }
+}
+
+impl FromLog for () {
+    fn from_log(value: LogValue) -> Result<Self> {
+        match value {
+            LogValue::Unit => Ok(()),
+            value => unexpected("nothing", &value),
+        }
+    }
+}
+
+impl ToLog for bool {
+    fn to_log(&self) -> LogValue {
+        LogValue::Bool(*self)
+    }
+}
+
+impl FromLog for bool {
+    fn from_log(value: LogValue) -> Result<Self> {
+        match value {
+            LogValue::Bool(value) => Ok(value),
+            value => unexpected("a boolean", &value),
+        }
+    }
+}
+
+macro_rules! log_unsigned {
+    ($($ty:ty),*) => {$(
+        impl ToLog for $ty {
+            fn to_log(&self) -> LogValue {
+                LogValue::Int(*self as u64)
+            }
+        }
+
+        impl FromLog for $ty {
+            fn from_log(value: LogValue) -> Result<Self> {
+                match value {
+                    LogValue::Int(value) => <$ty>::try_from(value)
+                        .map_err(|_| anyhow!("{} is out of the range of {}", value, stringify!($ty))),
+                    value => unexpected(stringify!($ty), &value),
+                }
+            }
+        }
+    )*};
+}
+
+log_unsigned!(u8, u32, u64, usize);
+
+impl ToLog for i64 {
+    fn to_log(&self) -> LogValue {
+        LogValue::Signed(*self)
+    }
+}
+
+impl FromLog for i64 {
+    fn from_log(value: LogValue) -> Result<Self> {
+        match value {
+            LogValue::Signed(value) => Ok(value),
+            value => unexpected("i64", &value),
+        }
+    }
+}
+
+impl ToLog for f64 {
+    fn to_log(&self) -> LogValue {
+        LogValue::Float(self.to_bits())
+    }
+}
+
+impl FromLog for f64 {
+    fn from_log(value: LogValue) -> Result<Self> {
+        match value {
+            LogValue::Float(bits) => Ok(f64::from_bits(bits)),
+            value => unexpected("f64", &value),
+        }
+    }
+}
+
+impl ToLog for str {
+    fn to_log(&self) -> LogValue {
+        LogValue::Str(self.to_string())
+    }
+}
+
+impl ToLog for String {
+    fn to_log(&self) -> LogValue {
+        LogValue::Str(self.clone())
+    }
+}
+
+impl FromLog for String {
+    fn from_log(value: LogValue) -> Result<Self> {
+        match value {
+            LogValue::Str(value) => Ok(value),
+            value => unexpected("a string", &value),
+        }
+    }
+}
+
+impl ToLog for [u8] {
+    fn to_log(&self) -> LogValue {
+        LogValue::Bytes(self.to_vec())
+    }
+}
+
+impl ToLog for Vec<u8> {
+    fn to_log(&self) -> LogValue {
+        LogValue::Bytes(self.clone())
+    }
+}
+
+impl FromLog for Vec<u8> {
+    fn from_log(value: LogValue) -> Result<Self> {
+        match value {
+            LogValue::Bytes(bytes) => Ok(bytes),
+            value => unexpected("bytes", &value),
+        }
+    }
+}
+
+impl ToLog for [u8; 32] {
+    fn to_log(&self) -> LogValue {
+        LogValue::Bytes(self.to_vec())
+    }
+}
+
+impl FromLog for [u8; 32] {
+    fn from_log(value: LogValue) -> Result<Self> {
+        let bytes = Vec::<u8>::from_log(value)?;
+        bytes.try_into().map_err(|bytes: Vec<u8>| {
+            anyhow!("expected 32 bytes in the log, found {}", bytes.len())
+        })
+    }
+}
+
+impl<T: ToLog> ToLog for Option<T> {
+    fn to_log(&self) -> LogValue {
+        match self {
+            Some(value) => LogValue::Some(Box::new(value.to_log())),
+            None => LogValue::None,
+        }
+    }
+}
+
+impl<T: FromLog> FromLog for Option<T> {
+    fn from_log(value: LogValue) -> Result<Self> {
+        match value {
+            LogValue::Some(value) => Ok(Some(T::from_log(*value)?)),
+            LogValue::None => Ok(None),
+            value => unexpected("an option", &value),
+        }
+    }
+}
+
+impl<A: ToLog, B: ToLog> ToLog for (A, B) {
+    fn to_log(&self) -> LogValue {
+        LogValue::List(vec![self.0.to_log(), self.1.to_log()])
+    }
+}
+
+impl<A: FromLog, B: FromLog> FromLog for (A, B) {
+    fn from_log(value: LogValue) -> Result<Self> {
+        match value {
+            LogValue::List(values) if values.len() == 2 => {
+                let mut values = values.into_iter();
+                Ok((
+                    A::from_log(values.next().unwrap())?,
+                    B::from_log(values.next().unwrap())?,
+                ))
+            }
+            value => unexpected("a pair", &value),
+        }
+    }
+}
+
+/// Logged as a list, the collections of the results
+macro_rules! log_list {
+    ($($ty:ty),*) => {$(
+        impl ToLog for $ty {
+            fn to_log(&self) -> LogValue {
+                LogValue::List(self.iter().map(|item| item.to_log()).collect())
+            }
+        }
+    )*};
+}
+
+log_list!(
+    [Vec<u8>],
+    [(Vec<u8>, Vec<u8>)],
+    [[u8; 32]],
+    Vec<Vec<u8>>,
+    Vec<String>,
+    BTreeSet<Vec<u8>>,
+    BTreeMap<Vec<u8>, Vec<u8>>
+);
+
+fn list_from_log<T: FromLog, C: FromIterator<T>>(value: LogValue) -> Result<C> {
+    match value {
+        LogValue::List(values) => values.into_iter().map(T::from_log).collect(),
+        value => unexpected("a list", &value),
+    }
+}
+
+impl FromLog for Vec<Vec<u8>> {
+    fn from_log(value: LogValue) -> Result<Self> {
+        list_from_log(value)
+    }
+}
+
+impl FromLog for Vec<String> {
+    fn from_log(value: LogValue) -> Result<Self> {
+        list_from_log(value)
+    }
+}
+
+impl FromLog for BTreeSet<Vec<u8>> {
+    fn from_log(value: LogValue) -> Result<Self> {
+        list_from_log(value)
+    }
+}
+
+impl FromLog for BTreeMap<Vec<u8>, Vec<u8>> {
+    fn from_log(value: LogValue) -> Result<Self> {
+        list_from_log(value)
+    }
+}
+
+impl ToLog for NativeAmount {
+    fn to_log(&self) -> LogValue {
+        (self.mantissa, self.scale).to_log()
+    }
+}
+
+impl FromLog for NativeAmount {
+    fn from_log(value: LogValue) -> Result<Self> {
+        let (mantissa, scale) = FromLog::from_log(value)?;
+        Ok(NativeAmount { mantissa, scale })
+    }
+}
+
+impl ToLog for NativeTime {
+    fn to_log(&self) -> LogValue {
+        self.milliseconds.to_log()
+    }
+}
+
+impl FromLog for NativeTime {
+    fn from_log(value: LogValue) -> Result<Self> {
+        Ok(NativeTime {
+            milliseconds: FromLog::from_log(value)?,
+        })
+    }
+}
+
+impl ToLog for Slot {
+    fn to_log(&self) -> LogValue {
+        (self.period, self.thread).to_log()
+    }
+}
+
+impl FromLog for Slot {
+    fn from_log(value: LogValue) -> Result<Self> {
+        let (period, thread) = FromLog::from_log(value)?;
+        Ok(Slot { period, thread })
+    }
+}
+
+/// Logged as their protobuf value
+macro_rules! log_proto_enum {
+    ($($ty:ty),*) => {$(
+        impl ToLog for $ty {
+            fn to_log(&self) -> LogValue {
+                LogValue::Signed(i32::from(*self) as i64)
+            }
+        }
+
+        impl FromLog for $ty {
+            fn from_log(value: LogValue) -> Result<Self> {
+                let raw = i32::try_from(i64::from_log(value)?)?;
+                <$ty>::try_from(raw)
+                    .map_err(|_| anyhow!("unknown {} {} in the log", stringify!($ty), raw))
+            }
+        }
+    )*};
+}
+
+log_proto_enum!(AddressCategory, ComparisonResult);
+
+impl ToLog for std::result::Result<(), TransferError> {
+    fn to_log(&self) -> LogValue {
+        match self {
+            Ok(()) => LogValue::Int(0),
+            Err(err) => LogValue::Int(*err as u64),
+        }
+    }
+}
+
+impl FromLog for std::result::Result<(), TransferError> {
+    fn from_log(value: LogValue) -> Result<Self> {
+        Ok(match u8::from_log(value)? {
+            0 => Ok(()),
+            1 => Err(TransferError::InsufficientBalance),
+            2 => Err(TransferError::InvalidAddress),
+            3 => Err(TransferError::Overflow),
+            code => bail!("unknown transfer error {} in the log", code),
+        })
+    }
+}
+
+impl ToLog for Compiler {
+    fn to_log(&self) -> LogValue {
+        LogValue::Str(format!("{:?}", self))
+    }
+}
+
+impl FromLog for Compiler {
+    fn from_log(value: LogValue) -> Result<Self> {
+        match value {
+            #[cfg(feature = "cranelift")]
+            LogValue::Str(name) if name == "CL" => Ok(Compiler::CL),
+            LogValue::Str(name) if name == "SP" => Ok(Compiler::SP),
+            value => unexpected("a supported compiler", &value),
+        }
+    }
+}
+
+/// How a module returned by the interface was compiled, for the replay to
+/// compile its bytecode the same way
+#[derive(Debug, PartialEq)]
+struct LoggedModule {
+    wasmv1: bool,
+    compiler: Compiler,
+    limit: u64,
+}
+
+impl LoggedModule {
+    fn new(module: &RuntimeModule) -> Self {
+        match module {
+            RuntimeModule::ASModule(module) => Self {
+                wasmv1: false,
+                compiler: module.compiler.clone(),
+                limit: module.initial_limit,
+            },
+            RuntimeModule::WasmV1Module(module) => Self {
+                wasmv1: true,
+                compiler: module.compiler.clone(),
+                limit: module.gas_limit_at_compilation,
+            },
+        }
+    }
+
+    fn compile(
+        self,
+        bytecode: &[u8],
+        gas_costs: GasCosts,
+        config: &RuntimeConfig,
+    ) -> Result<RuntimeModule> {
+        if !self.wasmv1 {
+            let module = ASModule::new(bytecode, self.limit, gas_costs, config, self.compiler)?;
+            return Ok(RuntimeModule::ASModule(module));
+        }
+        let module = WasmV1Module::compile(
+            bytecode.get(1..).unwrap_or_default(),
+            self.limit,
+            gas_costs,
+            config,
+            self.compiler,
+        )
+        .map_err(|err| anyhow!("Failed to compile WasmV1 module: {}", err))?;
+        Ok(RuntimeModule::WasmV1Module(module))
+    }
+}
+
+impl ToLog for LoggedModule {
+    fn to_log(&self) -> LogValue {
+        LogValue::List(vec![
+            self.wasmv1.to_log(),
+            self.compiler.to_log(),
+            self.limit.to_log(),
+        ])
+    }
+}
+
+impl FromLog for LoggedModule {
+    fn from_log(value: LogValue) -> Result<Self> {
+        match value {
+            LogValue::List(values) if values.len() == 3 => {
+                let mut values = values.into_iter();
+                Ok(Self {
+                    wasmv1: FromLog::from_log(values.next().unwrap())?,
+                    compiler: FromLog::from_log(values.next().unwrap())?,
+                    limit: FromLog::from_log(values.next().unwrap())?,
+                })
+            }
+            value => unexpected("a module", &value),
+        }
+    }
+}
+
+/// Call to the interface, with the message of its error if it failed
+#[derive(Clone, Debug, PartialEq)]
+struct LoggedCall {
+    method: String,
+    args: Vec<LogValue>,
+    result: std::result::Result<LogValue, String>,
+}
+
+impl Display for LoggedCall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}(", self.method)?;
+        write_values(f, &self.args)?;
+        write!(f, ")")
+    }
+}
+
+fn encode_log(calls: &[LoggedCall]) -> Vec<u8> {
+    let mut out = vec![LOG_VERSION];
+    write_varint(&mut out, calls.len() as u64);
+    for call in calls {
+        write_bytes(&mut out, call.method.as_bytes());
+        LogValue::List(call.args.clone()).encode(&mut out);
+        match &call.result {
+            Ok(value) => {
+                out.push(0);
+                value.encode(&mut out);
+            }
+            Err(err) => {
+                out.push(1);
+                write_bytes(&mut out, err.as_bytes());
+            }
+        }
+    }
+    out
+}
+
+fn decode_log(log: &[u8]) -> Result<Vec<LoggedCall>> {
+    let mut reader = LogReader { input: log };
+    let version = reader.byte()?;
+    if version != LOG_VERSION {
+        bail!(
+            "log of version {}, only the version {} is supported",
+            version,
+            LOG_VERSION
+        );
+    }
+    let count = reader.len()?;
+    let mut calls = Vec::new();
+    for _ in 0..count {
+        let method = reader.string()?;
+        let LogValue::List(args) = reader.value()? else {
+            bail!("malformed arguments of {} in the log", method);
+        };
+        let result = match reader.byte()? {
+            0 => Ok(reader.value()?),
+            1 => Err(reader.string()?),
+            _ => bail!("malformed result of {} in the log", method),
+        };
+        calls.push(LoggedCall {
+            method,
+            args,
+            result,
+        });
+    }
+    if !reader.input.is_empty() {
+        bail!("{} trailing bytes in the log", reader.input.len());
+    }
+    Ok(calls)
+}
+
+/// Methods of `Interface` logged with their result, the others are written
+/// by hand. `$callback` implements them for one of the interfaces.
+macro_rules! logged_methods {
+    ($callback:ident) => {
+        $callback! {
+            fn init_call(address: &str, raw_coins: u64) -> Vec<u8>;
+            fn init_call_wasmv1(address: &str, raw_coins: NativeAmount) -> Vec<u8>;
+            fn finish_call() -> ();
+            fn finish_try_call(success: bool) -> ();
+            fn init_local_call(raw_coins: u64) -> ();
+            fn finish_local_call(success: bool) -> ();
+            fn get_balance() -> u64;
+            fn get_balance_for(address: &str) -> u64;
+            fn get_balance_wasmv1(address: Option<String>) -> NativeAmount;
+            fn transfer_coins(to_address: &str, raw_amount: u64) -> ();
+            fn transfer_coins_checked(
+                to_address: &str,
+                raw_amount: u64,
+            ) -> std::result::Result<(), TransferError>;
+            fn transfer_coins_for(from_address: &str, to_address: &str, raw_amount: u64) -> ();
+            fn transfer_coins_wasmv1(
+                to_address: String,
+                raw_amount: NativeAmount,
+                from_address: Option<String>,
+            ) -> ();
+            fn get_call_coins() -> u64;
+            fn get_call_coins_wasmv1() -> NativeAmount;
+            fn raw_set_bytecode(bytecode: &[u8]) -> ();
+            fn raw_set_bytecode_for(address: &str, bytecode: &[u8]) -> ();
+            fn invalidate_cached_module(address: Option<&str>) -> ();
+            fn set_bytecode_wasmv1(bytecode: &[u8], address: Option<String>) -> ();
+            fn create_module(module: &[u8]) -> String;
+            fn create_module_with_datastore(
+                module: &[u8],
+                datastore: &[(Vec<u8>, Vec<u8>)],
+            ) -> String;
+            fn predict_created_address(seed: &[u8]) -> String;
+            fn create_module_with_seed(module: &[u8], seed: &[u8]) -> String;
+            fn print(message: &str) -> ();
+            fn get_keys(prefix: Option<&[u8]>) -> BTreeSet<Vec<u8>>;
+            fn get_keys_for(address: &str, prefix: Option<&[u8]>) -> BTreeSet<Vec<u8>>;
+            fn get_keys_paged(
+                prefix: Option<&[u8]>,
+                cursor: Option<&[u8]>,
+                limit: usize,
+            ) -> (Vec<Vec<u8>>, Option<Vec<u8>>);
+            fn get_ds_keys_wasmv1(prefix: &[u8], address: Option<String>) -> BTreeSet<Vec<u8>>;
+            fn raw_get_data(key: &[u8]) -> Vec<u8>;
+            fn raw_get_data_chunk(key: &[u8], offset: u64, len: u64) -> Vec<u8>;
+            fn prefetch(keys: &[Vec<u8>]) -> ();
+            fn raw_get_data_for(address: &str, key: &[u8]) -> Vec<u8>;
+            fn raw_get_data_batch(keys: &[Vec<u8>]) -> BTreeMap<Vec<u8>, Vec<u8>>;
+            fn get_ds_value_wasmv1(key: &[u8], address: Option<String>) -> Vec<u8>;
+            fn raw_set_data(key: &[u8], value: &[u8]) -> ();
+            fn raw_set_data_batch(entries: &[(Vec<u8>, Vec<u8>)]) -> ();
+            fn get_storage_cost(bytes: u64) -> u64;
+            fn reserve_storage(bytes: u64) -> bool;
+            fn raw_set_data_for(address: &str, key: &[u8], value: &[u8]) -> ();
+            fn set_ds_value_wasmv1(key: &[u8], value: &[u8], address: Option<String>) -> ();
+            fn raw_append_data(key: &[u8], value: &[u8]) -> ();
+            fn raw_append_data_for(address: &str, key: &[u8], value: &[u8]) -> ();
+            fn append_ds_value_wasmv1(key: &[u8], value: &[u8], address: Option<String>) -> ();
+            fn raw_delete_data(key: &[u8]) -> ();
+            fn raw_delete_data_for(address: &str, key: &[u8]) -> ();
+            fn delete_ds_entry_wasmv1(key: &[u8], address: Option<String>) -> ();
+            fn has_data(key: &[u8]) -> bool;
+            fn has_data_for(address: &str, key: &[u8]) -> bool;
+            fn ds_entry_exists_wasmv1(key: &[u8], address: Option<String>) -> bool;
+            fn raw_get_bytecode() -> Vec<u8>;
+            fn raw_get_bytecode_for(address: &str) -> Vec<u8>;
+            fn get_bytecode_wasmv1(address: Option<String>) -> Vec<u8>;
+            fn get_op_keys(prefix: Option<&[u8]>) -> Vec<Vec<u8>>;
+            fn get_op_keys_prefix(prefix: &[u8]) -> Vec<Vec<u8>>;
+            fn get_op_keys_wasmv1(prefix: &[u8]) -> Vec<Vec<u8>>;
+            fn op_entry_exists(key: &[u8]) -> bool;
+            fn get_op_data(key: &[u8]) -> Vec<u8>;
+            fn caller_has_write_access() -> bool;
+            fn hash(data: &[u8]) -> [u8; 32];
+            fn hash_blake3(bytes: &[u8]) -> [u8; 32];
+            fn signature_verify(data: &[u8], signature: &str, public_key: &str) -> bool;
+            fn evm_signature_verify(message: &[u8], signature: &[u8], public_key: &[u8]) -> bool;
+            fn evm_get_address_from_pubkey(public_key: &[u8]) -> Vec<u8>;
+            fn evm_get_pubkey_from_signature(hash: &[u8], signature: &[u8]) -> Vec<u8>;
+            fn is_address_eoa(address: &str) -> bool;
+            fn address_from_public_key(public_key: &str) -> String;
+            fn address_from_public_key_bytes(public_key: &[u8]) -> String;
+            fn public_key_from_string(public_key: &str) -> Vec<u8>;
+            fn validate_address(address: &str) -> bool;
+            fn get_time() -> u64;
+            fn unsafe_random() -> i64;
+            fn unsafe_random_f64() -> f64;
+            fn unsafe_random_wasmv1(num_bytes: u64) -> Vec<u8>;
+            fn get_random_seed() -> [u8; 32];
+            fn get_current_period() -> u64;
+            fn get_current_thread() -> u8;
+            fn get_current_slot() -> Slot;
+            fn slot_to_timestamp(slot: (u64, u8)) -> u64;
+            fn timestamp_to_slot(timestamp: u64) -> (u64, u8);
+            fn get_owned_addresses() -> Vec<String>;
+            fn get_call_stack() -> Vec<String>;
+            fn generate_event(event: String) -> ();
+            fn generate_event_indexed(index: u64, event: String) -> ();
+            fn generate_event_binary(topics: &[[u8; 32]], data: &[u8]) -> ();
+            fn generate_event_wasmv1(event: Vec<u8>) -> ();
+            fn send_message(
+                target_address: &str,
+                target_handler: &str,
+                validity_start: (u64, u8),
+                validity_end: (u64, u8),
+                max_gas: u64,
+                raw_fee: u64,
+                raw_coins: u64,
+                data: &[u8],
+                filter: Option<(&str, Option<&[u8]>)>,
+            ) -> ();
+            fn deferred_call_register(
+                target_address: &str,
+                target_function: &str,
+                target_slot: (u64, u8),
+                max_gas: u64,
+                params: &[u8],
+                raw_coins: u64,
+            ) -> String;
+            fn deferred_call_exists(id: &str) -> bool;
+            fn deferred_call_cancel(id: &str) -> ();
+            fn deferred_call_quote(target_slot: (u64, u8), max_gas: u64) -> u64;
+            fn get_origin_operation_id() -> Option<String>;
+            fn hash_sha256(bytes: &[u8]) -> [u8; 32];
+            fn hash_keccak256(bytes: &[u8]) -> [u8; 32];
+            fn chain_id() -> u64;
+            fn native_amount_from_str_wasmv1(amount: &str) -> NativeAmount;
+            fn native_amount_to_string_wasmv1(amount: &NativeAmount) -> String;
+            fn check_native_amount_wasmv1(amount: &NativeAmount) -> bool;
+            fn add_native_amount_wasmv1(
+                amount1: &NativeAmount,
+                amount2: &NativeAmount,
+            ) -> NativeAmount;
+            fn sub_native_amount_wasmv1(
+                amount1: &NativeAmount,
+                amount2: &NativeAmount,
+            ) -> NativeAmount;
+            fn scalar_mul_native_amount_wasmv1(amount: &NativeAmount, factor: u64) -> NativeAmount;
+            fn scalar_div_rem_native_amount_wasmv1(
+                dividend: &NativeAmount,
+                divisor: u64,
+            ) -> (NativeAmount, NativeAmount);
+            fn div_rem_native_amount_wasmv1(
+                dividend: &NativeAmount,
+                divisor: &NativeAmount,
+            ) -> (u64, NativeAmount);
+            fn check_address_wasmv1(to_check: &str) -> bool;
+            fn check_pubkey_wasmv1(to_check: &str) -> bool;
+            fn check_signature_wasmv1(to_check: &str) -> bool;
+            fn get_address_category_wasmv1(to_check: &str) -> AddressCategory;
+            fn get_address_version_wasmv1(address: &str) -> u64;
+            fn get_pubkey_version_wasmv1(pubkey: &str) -> u64;
+            fn get_signature_version_wasmv1(signature: &str) -> u64;
+            fn checked_add_native_time_wasmv1(time1: &NativeTime, time2: &NativeTime) -> NativeTime;
+            fn checked_sub_native_time_wasmv1(time1: &NativeTime, time2: &NativeTime) -> NativeTime;
+            fn checked_mul_native_time_wasmv1(time: &NativeTime, factor: u64) -> NativeTime;
+            fn checked_scalar_div_native_time_wasmv1(
+                dividend: &NativeTime,
+                divisor: u64,
+            ) -> (NativeTime, NativeTime);
+            fn checked_div_native_time_wasmv1(
+                dividend: &NativeTime,
+                divisor: &NativeTime,
+            ) -> (u64, NativeTime);
+            fn base58_check_to_bytes_wasmv1(s: &str) -> Vec<u8>;
+            fn compare_address_wasmv1(left: &str, right: &str) -> ComparisonResult;
+            fn compare_native_amount_wasmv1(
+                left: &NativeAmount,
+                right: &NativeAmount,
+            ) -> ComparisonResult;
+            fn compare_native_time_wasmv1(left: &NativeTime, right: &NativeTime) -> ComparisonResult;
+            fn compare_pub_key_wasmv1(left: &str, right: &str) -> ComparisonResult;
+        }
+    };
+}
+
+macro_rules! recorded_methods {
+    ($(fn $name:ident($($arg:ident: $ty:ty),* $(,)?) -> $ret:ty;)*) => {$(
+        fn $name(&self, $($arg: $ty),*) -> Result<$ret> {
+            let args = vec![$($arg.to_log()),*];
+            let result = self.inner.$name($($arg),*);
+            self.record(stringify!($name), args, logged_result(&result, ToLog::to_log));
+            result
+        }
+    )*};
+}
+
+macro_rules! replayed_methods {
+    ($(fn $name:ident($($arg:ident: $ty:ty),* $(,)?) -> $ret:ty;)*) => {$(
+        fn $name(&self, $($arg: $ty),*) -> Result<$ret> {
+            self.replay(stringify!($name), vec![$($arg.to_log()),*])
+        }
+    )*};
+}
+
+/// Logged result of a call, only the message of its error is kept
+fn logged_result<T>(
+    result: &Result<T>,
+    to_log: impl FnOnce(&T) -> LogValue,
+) -> std::result::Result<LogValue, String> {
+    result.as_ref().map(to_log).map_err(ToString::to_string)
+}
+
+/// `Interface` logging the calls made to `inner`, see `RecordingInterface::log`.
+///
+/// The clones share the log, the nested calls are logged with the others.
+/// A module `inner` returns from `get_cached_module` is logged as the
+/// bytecode of the address given to the previous `init_call_hash`, fetched
+/// without being logged.
+#[derive(Clone)]
+pub struct RecordingInterface<I> {
+    inner: I,
+    calls: Arc<Mutex<Vec<LoggedCall>>>,
+    /// Address of the last `init_call_hash`, whose module may be cached
+    call_address: Arc<Mutex<Option<String>>>,
+}
+
+impl<I: Interface + Clone + 'static> RecordingInterface<I> {
+    pub fn new(inner: I) -> Self {
+        Self {
+            inner,
+            calls: Default::default(),
+            call_address: Default::default(),
+        }
+    }
+
+    /// Calls logged so far, to serve to a `ReplayInterface`
+    pub fn log(&self) -> Vec<u8> {
+        encode_log(&self.calls.lock())
+    }
+
+    /// Number of calls logged so far
+    pub fn call_count(&self) -> usize {
+        self.calls.lock().len()
+    }
+
+    fn record(
+        &self,
+        method: &str,
+        args: Vec<LogValue>,
+        result: std::result::Result<LogValue, String>,
+    ) {
+        self.calls.lock().push(LoggedCall {
+            method: method.to_string(),
+            args,
+            result,
+        });
+    }
+
+    fn record_module(
+        &self,
+        method: &str,
+        args: Vec<LogValue>,
+        result: Result<RuntimeModule>,
+    ) -> Result<RuntimeModule> {
+        let logged = logged_result(&result, |module| LoggedModule::new(module).to_log());
+        self.record(method, args, logged);
+        result
+    }
+}
+
+impl<I: Interface + Clone + 'static> InterfaceClone for RecordingInterface<I> {
+    fn clone_box(&self) -> Box<dyn Interface> {
+        Box::new(self.clone())
+    }
+}
+
+impl<I: Interface + Clone + 'static> Interface for RecordingInterface<I> {
+    logged_methods!(recorded_methods);
+
+    fn interface_version(&self) -> u32 {
+        let version = self.inner.interface_version();
+        self.record("interface_version", vec![], Ok(version.to_log()));
+        version
+    }
+
+    fn init_call_hash(
+        &self,
+        address: &str,
+        raw_coins: u64,
+    ) -> Result<Option<(BytecodeHash, usize)>> {
+        *self.call_address.lock() = Some(address.to_string());
+        let args = vec![address.to_log(), raw_coins.to_log()];
+        let result = self.inner.init_call_hash(address, raw_coins);
+        self.record(
+            "init_call_hash",
+            args,
+            logged_result(&result, ToLog::to_log),
+        );
+        result
+    }
+
+    fn get_cached_module(
+        &self,
+        hash: &BytecodeHash,
+        compiler: Compiler,
+    ) -> Result<Option<RuntimeModule>> {
+        let args = vec![hash.to_log(), compiler.to_log()];
+        let result =
+            self.inner
+                .get_cached_module(hash, compiler)
+                .and_then(|module| {
+                    let Some(module) = module else {
+                        return Ok(None);
+                    };
+                    let address = self.call_address.lock().clone().ok_or_else(|| {
+                        anyhow!("cached module without a previous init_call_hash")
+                    })?;
+                    let bytecode = self.inner.raw_get_bytecode_for(&address)?;
+                    Ok(Some((module, bytecode)))
+                });
+        let logged = logged_result(&result, |module| {
+            module
+                .as_ref()
+                .map(|(module, bytecode)| (LoggedModule::new(module), bytecode))
+                .to_log()
+        });
+        self.record("get_cached_module", args, logged);
+        result.map(|module| module.map(|(module, _)| module))
+    }
+
+    fn get_module(&self, bytecode: &[u8], gas_limit: u64) -> Result<RuntimeModule> {
+        let args = vec![bytecode.to_log(), gas_limit.to_log()];
+        self.record_module(
+            "get_module",
+            args,
+            self.inner.get_module(bytecode, gas_limit),
+        )
+    }
+
+    fn get_module_with_compiler(
+        &self,
+        bytecode: &[u8],
+        gas_limit: u64,
+        compiler: Compiler,
+    ) -> Result<RuntimeModule> {
+        let args = vec![bytecode.to_log(), gas_limit.to_log(), compiler.to_log()];
+        let result = self
+            .inner
+            .get_module_with_compiler(bytecode, gas_limit, compiler);
+        self.record_module("get_module_with_compiler", args, result)
+    }
+
+    fn get_tmp_module(&self, bytecode: &[u8], gas_limit: u64) -> Result<RuntimeModule> {
+        let args = vec![bytecode.to_log(), gas_limit.to_log()];
+        let result = self.inner.get_tmp_module(bytecode, gas_limit);
+        self.record_module("get_tmp_module", args, result)
+    }
+
+    fn bytes_to_base58_check_wasmv1(&self, bytes: &[u8]) -> String {
+        let result = self.inner.bytes_to_base58_check_wasmv1(bytes);
+        self.record(
+            "bytes_to_base58_check_wasmv1",
+            vec![bytes.to_log()],
+            Ok(result.to_log()),
+        );
+        result
+    }
+
+    fn save_gas_remaining_before_subexecution(&self, gas_used_until: u64) {
+        self.inner
+            .save_gas_remaining_before_subexecution(gas_used_until);
+        self.record(
+            "save_gas_remaining_before_subexecution",
+            vec![gas_used_until.to_log()],
+            Ok(LogValue::Unit),
+        );
+    }
+}
+
+#[derive(Debug, Default)]
+struct ReplayState {
+    /// Index of the next call in the log
+    next: usize,
+    divergence: Option<String>,
+}
+
+/// `Interface` answering the calls of an execution with the results of a
+/// `RecordingInterface` log.
+///
+/// After a call diverging from the log every call fails with the
+/// divergence, also kept by `ReplayInterface::divergence` in case the
+/// execution handled the error. The clones share the progress in the log.
+#[derive(Clone)]
+pub struct ReplayInterface {
+    calls: Arc<Vec<LoggedCall>>,
+    state: Arc<Mutex<ReplayState>>,
+    gas_costs: GasCosts,
+    config: RuntimeConfig,
+}
+
+impl ReplayInterface {
+    /// Replay `log`, the modules being compiled with `gas_costs` and `config`
+    pub fn new(log: &[u8], gas_costs: GasCosts, config: RuntimeConfig) -> Result<Self> {
+        Ok(Self {
+            calls: Arc::new(decode_log(log)?),
+            state: Default::default(),
+            gas_costs,
+            config,
+        })
+    }
+
+    /// First call which diverged from the log, with the one expected
+    pub fn divergence(&self) -> Option<String> {
+        self.state.lock().divergence.clone()
+    }
+
+    /// Number of calls of the log not made yet
+    pub fn remaining_calls(&self) -> usize {
+        self.calls.len() - self.state.lock().next
+    }
+
+    /// Result logged for the next call, which must be `method(args)`
+    fn next_call(
+        &self,
+        method: &str,
+        args: Vec<LogValue>,
+    ) -> Result<std::result::Result<LogValue, String>> {
+        let mut state = self.state.lock();
+        if let Some(divergence) = &state.divergence {
+            bail!("{}", divergence);
+        }
+        let actual = LoggedCall {
+            method: method.to_string(),
+            args,
+            result: Ok(LogValue::Unit),
+        };
+        let index = state.next;
+        let divergence = match self.calls.get(index) {
+            Some(expected) if expected.method == actual.method && expected.args == actual.args => {
+                state.next += 1;
+                return Ok(expected.result.clone());
+            }
+            Some(expected) => format!(
+                "call {} diverges from the log: expected {}, got {}",
+                index, expected, actual
+            ),
+            None => format!(
+                "call {} diverges from the log: expected no more calls, got {}",
+                index, actual
+            ),
+        };
+        state.divergence = Some(divergence.clone());
+        bail!("{}", divergence)
+    }
+
+    fn replay<T: FromLog>(&self, method: &str, args: Vec<LogValue>) -> Result<T> {
+        match self.next_call(method, args)? {
+            Ok(value) => T::from_log(value),
+            Err(err) => Err(anyhow!(err)),
+        }
+    }
+
+    fn replay_module(
+        &self,
+        method: &str,
+        args: Vec<LogValue>,
+        bytecode: &[u8],
+    ) -> Result<RuntimeModule> {
+        let module: LoggedModule = self.replay(method, args)?;
+        module.compile(bytecode, self.gas_costs.clone(), &self.config)
+    }
+}
+
+impl InterfaceClone for ReplayInterface {
+    fn clone_box(&self) -> Box<dyn Interface> {
+        Box::new(self.clone())
+    }
+}
+
+impl Interface for ReplayInterface {
+    logged_methods!(replayed_methods);
+
+    fn interface_version(&self) -> u32 {
+        // a divergence fails the next call, the execution refuses the
+        // version 0 anyway
+        self.replay("interface_version", vec![]).unwrap_or_default()
+    }
+
+    fn init_call_hash(
+        &self,
+        address: &str,
+        raw_coins: u64,
+    ) -> Result<Option<(BytecodeHash, usize)>> {
+        self.replay("init_call_hash", vec![address.to_log(), raw_coins.to_log()])
+    }
+
+    fn get_cached_module(
+        &self,
+        hash: &BytecodeHash,
+        compiler: Compiler,
+    ) -> Result<Option<RuntimeModule>> {
+        let cached: Option<(LoggedModule, Vec<u8>)> =
+            self.replay("get_cached_module", vec![hash.to_log(), compiler.to_log()])?;
+        cached
+            .map(|(module, bytecode)| {
+                module.compile(&bytecode, self.gas_costs.clone(), &self.config)
+            })
+            .transpose()
+    }
+
+    fn get_module(&self, bytecode: &[u8], gas_limit: u64) -> Result<RuntimeModule> {
+        let args = vec![bytecode.to_log(), gas_limit.to_log()];
+        self.replay_module("get_module", args, bytecode)
+    }
+
+    fn get_module_with_compiler(
+        &self,
+        bytecode: &[u8],
+        gas_limit: u64,
+        compiler: Compiler,
+    ) -> Result<RuntimeModule> {
+        let args = vec![bytecode.to_log(), gas_limit.to_log(), compiler.to_log()];
+        self.replay_module("get_module_with_compiler", args, bytecode)
+    }
+
+    fn get_tmp_module(&self, bytecode: &[u8], gas_limit: u64) -> Result<RuntimeModule> {
+        let args = vec![bytecode.to_log(), gas_limit.to_log()];
+        self.replay_module("get_tmp_module", args, bytecode)
+    }
+
+    fn bytes_to_base58_check_wasmv1(&self, bytes: &[u8]) -> String {
+        // a divergence fails the next call
+        self.replay("bytes_to_base58_check_wasmv1", vec![bytes.to_log()])
+            .unwrap_or_default()
+    }
+
+    fn save_gas_remaining_before_subexecution(&self, gas_used_until: u64) {
+        // a divergence fails the next call
+        let _: Result<()> = self.replay(
+            "save_gas_remaining_before_subexecution",
+            vec![gas_used_until.to_log()],
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_encoding() {
+        let calls = vec![
+            LoggedCall {
+                method: "raw_set_data".to_string(),
+                args: vec![b"key".to_vec().to_log(), Vec::<u8>::new().to_log()],
+                result: Ok(LogValue::Unit),
+            },
+            LoggedCall {
+                method: "unsafe_random".to_string(),
+                args: vec![],
+                result: Ok((-3i64).to_log()),
+            },
+            LoggedCall {
+                method: "get_keys_paged".to_string(),
+                args: vec![
+                    None::<&[u8]>.to_log(),
+                    Some(&b"c"[..]).to_log(),
+                    7usize.to_log(),
+                ],
+                result: Ok((vec![b"a".to_vec()], Some(b"b".to_vec())).to_log()),
+            },
+            LoggedCall {
+                method: "get_balance".to_string(),
+                args: vec![],
+                result: Err("unknown address".to_string()),
+            },
+        ];
+        let log = encode_log(&calls);
+        assert_eq!(decode_log(&log).unwrap(), calls);
+        assert_eq!(
+            <(Vec<Vec<u8>>, Option<Vec<u8>>)>::from_log(calls[2].result.clone().unwrap()).unwrap(),
+            (vec![b"a".to_vec()], Some(b"b".to_vec()))
+        );
+        assert_eq!(i64::from_log((-3i64).to_log()).unwrap(), -3);
+        assert_eq!(i64::from_log(i64::MIN.to_log()).unwrap(), i64::MIN);
+
+        assert!(decode_log(&log[..log.len() - 1]).is_err());
+        let mut other_version = log.clone();
+        other_version[0] += 1;
+        assert!(decode_log(&other_version).is_err());
+        assert_eq!(calls[0].to_string(), "raw_set_data(0x6b6579, 0x)");
+    }
+}
@@ -0,0 +1,171 @@
+//! Executions of several functions of one instance against a single gas
+//! budget, for the embedders running batches of calls.
+//!
+//! An `ExecutionSession` instantiates an AssemblyScript module once, paying
+//! for it once, then runs as many of its functions as the budget allows. The
+//! calls share the instance, so a function sees the memory and the globals
+//! the previous ones left, and they form a single call tree: the limits of
+//! `RuntimeConfig` counted per call tree, such as the events or the execution
+//! time, cover the whole session.
+
+use crate::as_execution::env::{ASEnv, Metered};
+use crate::as_execution::{check_callable, execution_engine, run_instance, ASContext};
+use crate::error::{VMError, VMResult};
+use crate::execution::{check_interface_version, CallContext};
+use crate::middlewares::memory_grow::METERING_REMAINING_POINTS;
+use crate::{ExecutionMode, GasCosts, Interface, Response, RuntimeConfig, RuntimeModule};
+use wasmer::{AsStoreMut, FunctionEnv, Instance, Store};
+use wasmer_middlewares::metering::{self, MeteringPoints};
+
+/// Gas left to a metered `instance`
+pub fn get_remaining_gas(store: &mut impl AsStoreMut, instance: &Instance) -> VMResult<u64> {
+    check_metered(instance)?;
+    match metering::get_remaining_points(store, instance) {
+        MeteringPoints::Remaining(gas) => Ok(gas),
+        MeteringPoints::Exhausted => Ok(0),
+    }
+}
+
+/// Give `gas` to a metered `instance`, which can run again if it exhausted
+/// its gas
+pub fn set_remaining_gas(
+    store: &mut impl AsStoreMut,
+    instance: &Instance,
+    gas: u64,
+) -> VMResult<()> {
+    check_metered(instance)?;
+    metering::set_remaining_points(store, instance, gas);
+    Ok(())
+}
+
+/// The metering functions of wasmer panic on the instances it did not
+/// instrument, such as the calibrating ones
+fn check_metered(instance: &Instance) -> VMResult<()> {
+    match instance.exports.get_global(METERING_REMAINING_POINTS) {
+        Ok(_) => Ok(()),
+        Err(_) => Err(VMError::InstanceError(
+            "The instance is not metered".to_string(),
+        )),
+    }
+}
+
+/// Instance of a module whose functions are run one after the other against
+/// one gas budget
+pub struct ExecutionSession {
+    store: Store,
+    instance: Instance,
+    context: ASContext,
+    fenv: FunctionEnv<ASEnv>,
+    init_gas_cost: u64,
+}
+
+impl ExecutionSession {
+    /// Instantiate `rt_module` with a budget of `limit`, the cost of the
+    /// instantiation deducted. Only the AssemblyScript modules compiled
+    /// without `RuntimeConfig::gas_calibration` are supported.
+    pub fn new(
+        interface: &dyn Interface,
+        rt_module: RuntimeModule,
+        limit: u64,
+        gas_costs: GasCosts,
+        config: &RuntimeConfig,
+        mode: ExecutionMode,
+    ) -> VMResult<Self> {
+        check_interface_version(interface)?;
+        let module = match rt_module {
+            RuntimeModule::ASModule(module) => module,
+            RuntimeModule::WasmV1Module(_) => {
+                return Err(VMError::InstanceError(
+                    "Execution sessions only run AssemblyScript modules".to_string(),
+                ))
+            }
+        };
+        if config.gas_calibration || module.gas_calibration {
+            return Err(VMError::InstanceError(
+                "Execution sessions are metered, they cannot calibrate the gas costs".to_string(),
+            ));
+        }
+        config.host_extensions.check()?;
+        let call_context = CallContext::root(config, module.compiler.clone(), mode);
+        let mut store = Store::new(execution_engine(
+            &module.compiler,
+            &gas_costs,
+            config,
+            &call_context.engines,
+        ));
+        let mut context = ASContext::new(
+            interface,
+            module.binary_module.clone(),
+            gas_costs,
+            call_context,
+        );
+
+        interface.save_gas_remaining_before_subexecution(limit);
+        let (instance, fenv, init_rem_points) =
+            context.create_vm_instance_and_init_env(&mut store)?;
+        let init_gas_cost = module.initial_limit.saturating_sub(init_rem_points);
+        metering::set_remaining_points(&mut store, &instance, limit.saturating_sub(init_gas_cost));
+        let env = fenv.as_mut(&mut store);
+        env.set_gas_limit(limit.saturating_sub(init_gas_cost));
+        env.set_current_module(module);
+
+        Ok(Self {
+            store,
+            instance,
+            context,
+            fenv,
+            init_gas_cost,
+        })
+    }
+
+    /// Run `function` with the gas left by the previous calls, each call
+    /// paying `GasCosts::launch_cost`. A failed call leaves the session
+    /// usable, once given gas again if it exhausted it.
+    ///
+    /// The `init_gas_cost` of the responses is the one of the instantiation
+    /// of the session.
+    pub fn run_function(&mut self, function: &str, param: &[u8]) -> VMResult<Response> {
+        let max_param_size = self.context.env.get_config().max_param_size;
+        if param.len() > max_param_size {
+            return Err(VMError::ParamTooLarge {
+                len: param.len(),
+                max: max_param_size,
+            });
+        }
+        *self.context.env.get_call_context().root_function.lock() = Some(function.to_string());
+        let remaining_gas = self.remaining_gas();
+        check_callable(
+            &self.context,
+            &self.instance,
+            function,
+            self.init_gas_cost,
+            remaining_gas,
+        )?;
+        run_instance(
+            &self.context,
+            &mut self.store,
+            &self.instance,
+            &self.fenv,
+            function,
+            param,
+            self.init_gas_cost,
+        )
+    }
+
+    /// Gas left in the budget of the session
+    pub fn remaining_gas(&mut self) -> u64 {
+        get_remaining_gas(&mut self.store, &self.instance)
+            .expect("the instance of a session is metered")
+    }
+
+    /// Replace the gas left in the budget of the session
+    pub fn set_remaining_gas(&mut self, gas: u64) {
+        set_remaining_gas(&mut self.store, &self.instance, gas)
+            .expect("the instance of a session is metered")
+    }
+
+    /// Gas charged for the instantiation of the module
+    pub fn init_gas_cost(&self) -> u64 {
+        self.init_gas_cost
+    }
+}
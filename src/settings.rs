@@ -1,9 +1,96 @@
+use std::time::Duration;
+
 pub(crate) const MAIN: &str = "main";
 
+/// Highest ABI version implemented by the runtime
+pub const ABI_VERSION: u32 = 1;
+/// Version of the `Interface` trait the runtime is built against, to be
+/// reported by the embedders through `Interface::interface_version`
+pub const INTERFACE_VERSION: u32 = 1;
+/// Oldest `Interface` version the runtime runs with. The interfaces which do
+/// not report a version, predating the check, are at 0.
+pub const MIN_INTERFACE_VERSION: u32 = 1;
+/// Name of the custom section, or of the exported global, declaring the ABI
+/// version a module was compiled against
+pub(crate) const ABI_VERSION_SYMBOL: &str = "__massa_abi_version";
+
+/// Default number of threads of the blockchain, the thread of a slot is below
+/// it
+pub(crate) const THREAD_COUNT: u8 = 32;
+
+/// Default number of digits of the fractional part of the native amounts, a
+/// coin is `10^9` nano-coins
+pub(crate) const NATIVE_AMOUNT_DECIMALS: u32 = 9;
+
+/// Above this cost an ABI cost is considered a mistake of the cost files
+pub(crate) fn max_abi_cost() -> u64 {
+    1_000_000_000
+}
+
 pub(crate) fn max_number_of_pages() -> u32 {
     64
 }
 
+/// Gas charged for each page grown by `memory.grow`
+pub(crate) fn memory_page_cost() -> u64 {
+    5_000
+}
+
+/// Gas charged for each byte copied, filled or initialized by the bulk memory
+/// operators
+pub(crate) fn bulk_memory_byte_cost() -> u64 {
+    1
+}
+
+/// Executions after which a pooled store is dropped, releasing the instances
+/// of its past executions
+pub(crate) fn max_pooled_store_executions() -> u32 {
+    16
+}
+
+pub(crate) fn max_exports() -> usize {
+    1_024
+}
+
+pub(crate) fn max_imports() -> usize {
+    1_024
+}
+
+pub(crate) fn max_functions() -> usize {
+    65_536
+}
+
+pub(crate) fn max_module_size() -> usize {
+    10_000_000
+}
+
+pub(crate) fn max_data_segments_size() -> usize {
+    1_000_000
+}
+
+pub(crate) fn max_element_segments() -> usize {
+    1_024
+}
+
+/// Maximum size of the code of a function in bytes
+pub(crate) fn max_function_body_size() -> usize {
+    1_000_000
+}
+
+pub(crate) fn max_function_locals() -> usize {
+    10_000
+}
+
+/// Maximum number of blocks, loops and ifs nested in a function
+pub(crate) fn max_block_depth() -> usize {
+    512
+}
+
+/// Maximum number of nested guest calls in an instance
+pub(crate) fn max_stack_depth() -> u32 {
+    1_000
+}
+
 pub(crate) fn max_datastore_entry_count() -> usize {
     100_000
 }
@@ -11,3 +98,168 @@ pub(crate) fn max_datastore_entry_count() -> usize {
 pub(crate) fn max_op_datastore_entry_count() -> usize {
     128
 }
+
+pub(crate) fn max_datastore_key_length() -> usize {
+    u8::MAX as usize
+}
+
+pub(crate) fn max_datastore_value_length() -> usize {
+    10_000_000
+}
+
+pub(crate) fn max_recursion_depth() -> u32 {
+    32
+}
+
+pub(crate) fn max_call_stack_frames() -> usize {
+    32
+}
+
+/// Maximum number of engines kept by an `EngineProvider`
+pub(crate) fn max_cached_engines() -> usize {
+    16
+}
+
+/// Maximum number of answers of each kind kept by the `ConversionCache` of an
+/// execution
+pub(crate) fn max_cached_conversions() -> usize {
+    256
+}
+
+/// Maximum number of exported functions listed by `VMError::FunctionNotFound`
+pub(crate) fn max_listed_exports() -> usize {
+    32
+}
+
+pub(crate) fn max_call_frame_string_length() -> usize {
+    256
+}
+
+/// Maximum length of the operation id returned by `get_origin_operation_id`
+pub(crate) fn max_operation_id_length() -> usize {
+    128
+}
+
+/// Maximum total length of the addresses returned by `get_call_stack`
+pub(crate) fn max_call_stack_size() -> usize {
+    10_000
+}
+
+pub(crate) fn storage_refund_per_byte() -> u64 {
+    0
+}
+
+pub(crate) fn max_storage_refund_percent() -> u64 {
+    20
+}
+
+pub(crate) fn max_execution_time() -> Option<Duration> {
+    None
+}
+
+/// Loop headers run by an instance between two checks of the deadline
+pub(crate) fn deadline_check_interval() -> u32 {
+    1_000
+}
+
+pub(crate) fn max_compilation_time() -> Option<Duration> {
+    None
+}
+
+pub(crate) fn max_base58_check_input_len() -> usize {
+    1_024
+}
+
+pub(crate) fn max_constant_eq_input_len() -> usize {
+    4_096
+}
+
+pub(crate) fn max_random_bytes_len() -> usize {
+    1_024
+}
+
+pub(crate) fn max_balance_batch_len() -> usize {
+    64
+}
+
+pub(crate) fn max_hint_keys() -> usize {
+    256
+}
+
+pub(crate) fn max_digest_input_len() -> usize {
+    1_000_000
+}
+
+pub(crate) fn max_merkle_proof_depth() -> usize {
+    64
+}
+
+pub(crate) fn max_deferred_call_gas() -> u64 {
+    1_000_000_000
+}
+
+pub(crate) fn max_async_message_gas() -> u64 {
+    1_000_000_000
+}
+
+/// Maximum number of bytes copied from the guest memory by a single ABI read
+pub(crate) fn max_abi_read_len() -> usize {
+    10_000_000
+}
+
+/// Maximum number of UTF-16 code units of a string read from the guest
+/// memory by a single ABI
+pub(crate) fn max_string_len() -> usize {
+    1_000_000
+}
+
+/// Gas per UTF-16 code unit of a string read from the guest memory
+pub(crate) fn string_code_unit_cost() -> u64 {
+    1
+}
+
+/// Maximum size in bytes of the parameter given to an executed function
+pub(crate) fn max_param_size() -> usize {
+    10_000_000
+}
+
+/// Maximum size in bytes of the value returned by an executed function
+pub(crate) fn max_return_size() -> usize {
+    10_000_000
+}
+
+/// Gas per byte of the value returned by an executed function
+pub(crate) fn return_byte_cost() -> u64 {
+    1
+}
+
+/// Maximum number of bytes copied from the host to the guest memories by a
+/// call tree
+pub(crate) fn max_host_data_len() -> usize {
+    100_000_000
+}
+
+/// Gas per byte of the bytecode compiled for a nested call
+pub(crate) fn compilation_cost_per_byte() -> u64 {
+    1
+}
+
+/// Gas per byte of the bytecode of a created smart contract
+pub(crate) fn sc_creation_byte_cost() -> u64 {
+    1
+}
+
+/// Size in bytes of a binary event topic
+pub(crate) const EVENT_TOPIC_LEN: usize = 32;
+
+pub(crate) fn max_event_topics() -> usize {
+    4
+}
+
+pub(crate) fn max_event_count() -> usize {
+    1_000
+}
+
+pub(crate) fn max_event_data_size() -> usize {
+    1_000_000
+}
@@ -0,0 +1,1204 @@
+//! In-memory `Interface` to test smart contracts against the real runtime,
+//! enabled by the `testing` feature.
+//!
+//! ```ignore
+//! let interface = TestInterface::new();
+//! interface.set_balance(TEST_CALLER_ADDRESS, 1_000);
+//! let config = RuntimeConfig::default();
+//! let module = RuntimeModule::new(&bytecode, GasCosts::default(), &config, Compiler::SP)?;
+//! run_main(
+//!     &interface,
+//!     module,
+//!     1_000_000,
+//!     GasCosts::default(),
+//!     &config,
+//!     ExecutionOptions::default(),
+//! )?;
+//! assert_eq!(interface.get_events().len(), 1);
+//! ```
+//!
+//! The executions are deterministic: the created addresses, the time, the
+//! slot and the random numbers only depend on the calls made to the interface.
+//! The changes of a call made through `assembly_script_try_call` are rolled
+//! back when it fails, the changes of a failed top-level execution are kept.
+
+use anyhow::{anyhow, bail, Result};
+use massa_proto_rs::massa::model::v1::{
+    AddressCategory, ComparisonResult, NativeAmount, NativeTime, Slot,
+};
+use parking_lot::Mutex;
+use sha2::{Digest, Sha256};
+use sha3::Keccak256;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::{
+    CachedExecution, Compiler, GasCosts, Interface, InterfaceClone, ReadOnlyCache,
+    ReadOnlyCacheKey, RuntimeConfig, RuntimeModule,
+};
+
+/// Address executing the top-level calls of a `TestInterface`
+pub const TEST_CALLER_ADDRESS: &str = "AU1testcaller";
+
+/// Chain id returned by a `TestInterface` until `set_chain_id` is called
+pub const TEST_CHAIN_ID: u64 = 77;
+
+/// Timestamp (milliseconds) of the genesis for the slot conversions of a
+/// `TestInterface`
+pub const TEST_GENESIS_TIMESTAMP: u64 = 1_600_000_000_000;
+
+/// Duration (milliseconds) of a period for the slot conversions of a
+/// `TestInterface`, split between the `RuntimeConfig::thread_count` threads
+pub const TEST_PERIOD_DURATION: u64 = 16_000;
+
+/// Number of decimals of the raw coin amounts
+const COIN_DECIMALS: u32 = 9;
+
+/// Ledger entry of an address
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TestAccount {
+    /// Bytecode of the smart contract, empty for a user address
+    pub bytecode: Vec<u8>,
+    pub datastore: BTreeMap<Vec<u8>, Vec<u8>>,
+    /// Balance in raw coins
+    pub balance: u64,
+}
+
+/// Event generated by a smart contract
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TestEvent {
+    /// Address that generated the event
+    pub emitter: String,
+    /// Topics of the binary events, empty for the other events
+    pub topics: Vec<[u8; 32]>,
+    pub data: Vec<u8>,
+}
+
+/// Asynchronous message sent by a smart contract
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TestMessage {
+    pub sender: String,
+    pub target_address: String,
+    pub target_handler: String,
+    pub validity_start: (u64, u8),
+    pub validity_end: (u64, u8),
+    pub max_gas: u64,
+    pub raw_fee: u64,
+    pub raw_coins: u64,
+    pub data: Vec<u8>,
+}
+
+/// Call started by `init_call` and not finished yet
+#[derive(Clone, Debug)]
+struct PendingCall {
+    address: String,
+    raw_coins: u64,
+    /// Ledger before the call, restored if the call fails
+    ledger: BTreeMap<String, TestAccount>,
+    /// Number of events before the call
+    event_count: usize,
+    message_count: usize,
+}
+
+#[derive(Debug)]
+struct TestState {
+    ledger: BTreeMap<String, TestAccount>,
+    /// Calls in progress, the current address is the last one
+    calls: Vec<PendingCall>,
+    events: Vec<TestEvent>,
+    messages: Vec<TestMessage>,
+    op_datastore: BTreeMap<Vec<u8>, Vec<u8>>,
+    created_count: u64,
+    time: u64,
+    slot: (u64, u8),
+    chain_id: u64,
+    random_state: u64,
+}
+
+impl TestState {
+    fn current_address(&self) -> &str {
+        self.calls
+            .last()
+            .map(|call| call.address.as_str())
+            .unwrap_or(TEST_CALLER_ADDRESS)
+    }
+
+    fn account(&self, address: &str) -> Result<&TestAccount> {
+        self.ledger
+            .get(address)
+            .ok_or_else(|| anyhow!("address {} does not exist", address))
+    }
+
+    fn account_mut(&mut self, address: &str) -> Result<&mut TestAccount> {
+        self.ledger
+            .get_mut(address)
+            .ok_or_else(|| anyhow!("address {} does not exist", address))
+    }
+
+    fn current_account_mut(&mut self) -> Result<&mut TestAccount> {
+        let address = self.current_address().to_string();
+        self.account_mut(&address)
+    }
+
+    fn transfer(&mut self, from: &str, to: &str, raw_amount: u64) -> Result<()> {
+        let from_account = self.account_mut(from)?;
+        from_account.balance = match from_account.balance.checked_sub(raw_amount) {
+            Some(balance) => balance,
+            None => bail!("not enough coins at {} to transfer {}", from, raw_amount),
+        };
+        let to_account = self.ledger.entry(to.to_string()).or_default();
+        to_account.balance = to_account
+            .balance
+            .checked_add(raw_amount)
+            .ok_or_else(|| anyhow!("balance overflow at {}", to))?;
+        Ok(())
+    }
+
+    fn create_account(&mut self, bytecode: &[u8]) -> String {
+        self.created_count += 1;
+        let address = format!("AS{}", self.created_count);
+        self.ledger.insert(
+            address.clone(),
+            TestAccount {
+                bytecode: bytecode.to_vec(),
+                ..Default::default()
+            },
+        );
+        address
+    }
+
+    /// Address derived from the current address and `seed`
+    fn seeded_address(&self, seed: &[u8]) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.current_address().as_bytes());
+        hasher.update(seed);
+        format!("AS{}", bs58::encode(hasher.finalize()).into_string())
+    }
+
+    fn create_seeded_account(&mut self, bytecode: &[u8], seed: &[u8]) -> Result<String> {
+        let address = self.seeded_address(seed);
+        if self.ledger.contains_key(&address) {
+            bail!("address {} already exists", address);
+        }
+        self.ledger.insert(
+            address.clone(),
+            TestAccount {
+                bytecode: bytecode.to_vec(),
+                ..Default::default()
+            },
+        );
+        Ok(address)
+    }
+
+    fn push_event(&mut self, topics: Vec<[u8; 32]>, data: Vec<u8>) {
+        let emitter = self.current_address().to_string();
+        self.events.push(TestEvent {
+            emitter,
+            topics,
+            data,
+        });
+    }
+
+    /// splitmix64, deterministic and good enough for tests
+    fn next_random(&mut self) -> u64 {
+        self.random_state = self.random_state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.random_state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+/// In-memory `Interface` with a ledger, event capture and call bookkeeping.
+///
+/// Clones share the same state, so the interface given to `run_main` can
+/// be inspected after the execution.
+#[derive(Clone)]
+pub struct TestInterface {
+    state: Arc<Mutex<TestState>>,
+    gas_costs: GasCosts,
+    config: RuntimeConfig,
+}
+
+impl Default for TestInterface {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TestInterface {
+    /// Interface whose ledger only contains `TEST_CALLER_ADDRESS`, compiling
+    /// the called modules with the default gas costs and configuration
+    pub fn new() -> Self {
+        Self::with_gas_costs(GasCosts::default(), RuntimeConfig::default())
+    }
+
+    /// Interface compiling the called modules with the given gas costs and
+    /// configuration
+    pub fn with_gas_costs(gas_costs: GasCosts, config: RuntimeConfig) -> Self {
+        let mut ledger = BTreeMap::new();
+        ledger.insert(TEST_CALLER_ADDRESS.to_string(), TestAccount::default());
+        Self {
+            state: Arc::new(Mutex::new(TestState {
+                ledger,
+                calls: Vec::new(),
+                events: Vec::new(),
+                messages: Vec::new(),
+                op_datastore: BTreeMap::new(),
+                created_count: 0,
+                time: 0,
+                slot: (0, 0),
+                chain_id: TEST_CHAIN_ID,
+                random_state: 0,
+            })),
+            gas_costs,
+            config,
+        }
+    }
+
+    /// Create a smart contract at the next generated address, as
+    /// `assembly_script_create_sc` does
+    pub fn deploy(&self, bytecode: &[u8]) -> String {
+        self.state.lock().create_account(bytecode)
+    }
+
+    /// Ledger entry of `address`
+    pub fn get_account(&self, address: &str) -> Option<TestAccount> {
+        self.state.lock().ledger.get(address).cloned()
+    }
+
+    /// Datastore of `address`, empty if the address does not exist
+    pub fn get_datastore(&self, address: &str) -> BTreeMap<Vec<u8>, Vec<u8>> {
+        self.get_account(address)
+            .map(|account| account.datastore)
+            .unwrap_or_default()
+    }
+
+    /// Balance of `address` in raw coins, zero if the address does not exist
+    pub fn balance(&self, address: &str) -> u64 {
+        self.get_account(address)
+            .map(|account| account.balance)
+            .unwrap_or_default()
+    }
+
+    /// Set the balance of `address`, creating it if needed
+    pub fn set_balance(&self, address: &str, raw_amount: u64) {
+        self.state
+            .lock()
+            .ledger
+            .entry(address.to_string())
+            .or_default()
+            .balance = raw_amount;
+    }
+
+    /// Events generated so far, in order
+    pub fn get_events(&self) -> Vec<TestEvent> {
+        self.state.lock().events.clone()
+    }
+
+    /// Asynchronous messages sent so far, in order
+    pub fn get_messages(&self) -> Vec<TestMessage> {
+        self.state.lock().messages.clone()
+    }
+
+    /// Set the operation datastore of the executions
+    pub fn set_op_datastore(&self, op_datastore: BTreeMap<Vec<u8>, Vec<u8>>) {
+        self.state.lock().op_datastore = op_datastore;
+    }
+
+    /// Set the millisecond timestamp returned by `get_time`
+    pub fn set_time(&self, time: u64) {
+        self.state.lock().time = time;
+    }
+
+    /// Set the period and thread of the execution slot
+    pub fn set_slot(&self, period: u64, thread: u8) {
+        self.state.lock().slot = (period, thread);
+    }
+
+    /// Set the chain id of the network the contracts run on
+    pub fn set_chain_id(&self, chain_id: u64) {
+        self.state.lock().chain_id = chain_id;
+    }
+
+    /// Addresses of the calls in progress, the current address last
+    pub fn call_stack(&self) -> Vec<String> {
+        let state = self.state.lock();
+        std::iter::once(TEST_CALLER_ADDRESS.to_string())
+            .chain(state.calls.iter().map(|call| call.address.clone()))
+            .collect()
+    }
+
+    fn current_address(&self) -> String {
+        self.state.lock().current_address().to_string()
+    }
+
+    fn address_or_current(&self, address: Option<String>) -> String {
+        address.unwrap_or_else(|| self.current_address())
+    }
+
+    fn get_keys_at(&self, address: &str, prefix: &[u8]) -> Result<BTreeSet<Vec<u8>>> {
+        let state = self.state.lock();
+        Ok(state
+            .account(address)?
+            .datastore
+            .keys()
+            .filter(|key| key.starts_with(prefix))
+            .cloned()
+            .collect())
+    }
+
+    fn get_data_at(&self, address: &str, key: &[u8]) -> Result<Vec<u8>> {
+        let state = self.state.lock();
+        match state.account(address)?.datastore.get(key) {
+            Some(value) => Ok(value.clone()),
+            None => bail!("data entry not found at {}", address),
+        }
+    }
+
+    fn set_data_at(&self, address: &str, key: &[u8], value: &[u8]) -> Result<()> {
+        let mut state = self.state.lock();
+        state
+            .account_mut(address)?
+            .datastore
+            .insert(key.to_vec(), value.to_vec());
+        Ok(())
+    }
+
+    fn append_data_at(&self, address: &str, key: &[u8], value: &[u8]) -> Result<()> {
+        let mut state = self.state.lock();
+        match state.account_mut(address)?.datastore.get_mut(key) {
+            Some(current) => current.extend_from_slice(value),
+            None => bail!("data entry not found at {}", address),
+        }
+        Ok(())
+    }
+
+    fn delete_data_at(&self, address: &str, key: &[u8]) -> Result<()> {
+        let mut state = self.state.lock();
+        match state.account_mut(address)?.datastore.remove(key) {
+            Some(_) => Ok(()),
+            None => bail!("data entry not found at {}", address),
+        }
+    }
+
+    fn has_data_at(&self, address: &str, key: &[u8]) -> Result<bool> {
+        let state = self.state.lock();
+        Ok(state
+            .ledger
+            .get(address)
+            .map_or(false, |account| account.datastore.contains_key(key)))
+    }
+
+    fn get_bytecode_at(&self, address: &str) -> Result<Vec<u8>> {
+        let state = self.state.lock();
+        match state.ledger.get(address) {
+            Some(account) if !account.bytecode.is_empty() => Ok(account.bytecode.clone()),
+            _ => bail!("no smart contract at address {}", address),
+        }
+    }
+
+    fn set_bytecode_at(&self, address: &str, bytecode: &[u8]) -> Result<()> {
+        let mut state = self.state.lock();
+        state.account_mut(address)?.bytecode = bytecode.to_vec();
+        Ok(())
+    }
+}
+
+impl InterfaceClone for TestInterface {
+    fn clone_box(&self) -> Box<dyn Interface> {
+        Box::new(self.clone())
+    }
+}
+
+fn amount_to_raw(amount: &NativeAmount) -> Result<u64> {
+    if amount.scale > COIN_DECIMALS {
+        bail!("amount scale {} exceeds {}", amount.scale, COIN_DECIMALS);
+    }
+    amount
+        .mantissa
+        .checked_mul(10u64.pow(COIN_DECIMALS - amount.scale))
+        .ok_or_else(|| anyhow!("amount overflow"))
+}
+
+fn raw_to_amount(raw_amount: u64) -> NativeAmount {
+    NativeAmount {
+        mantissa: raw_amount,
+        scale: COIN_DECIMALS,
+    }
+}
+
+fn compare<T: Ord>(left: T, right: T) -> ComparisonResult {
+    match left.cmp(&right) {
+        std::cmp::Ordering::Less => ComparisonResult::Lower,
+        std::cmp::Ordering::Equal => ComparisonResult::Equal,
+        std::cmp::Ordering::Greater => ComparisonResult::Greater,
+    }
+}
+
+impl Interface for TestInterface {
+    fn interface_version(&self) -> u32 {
+        crate::INTERFACE_VERSION
+    }
+
+    fn init_call(&self, address: &str, raw_coins: u64) -> Result<Vec<u8>> {
+        let bytecode = self.get_bytecode_at(address)?;
+        let mut state = self.state.lock();
+        let call = PendingCall {
+            address: address.to_string(),
+            raw_coins,
+            ledger: state.ledger.clone(),
+            event_count: state.events.len(),
+            message_count: state.messages.len(),
+        };
+        let caller = state.current_address().to_string();
+        state.transfer(&caller, address, raw_coins)?;
+        state.calls.push(call);
+        Ok(bytecode)
+    }
+
+    fn init_call_wasmv1(&self, address: &str, raw_coins: NativeAmount) -> Result<Vec<u8>> {
+        self.init_call(address, amount_to_raw(&raw_coins)?)
+    }
+
+    fn finish_call(&self) -> Result<()> {
+        self.finish_try_call(true)
+    }
+
+    fn finish_try_call(&self, success: bool) -> Result<()> {
+        let mut state = self.state.lock();
+        let call = match state.calls.pop() {
+            Some(call) => call,
+            None => bail!("no call to finish"),
+        };
+        if !success {
+            state.ledger = call.ledger;
+            state.events.truncate(call.event_count);
+            state.messages.truncate(call.message_count);
+        }
+        Ok(())
+    }
+
+    fn init_local_call(&self, raw_coins: u64) -> Result<()> {
+        let mut state = self.state.lock();
+        let balance = state.current_account_mut()?.balance;
+        if balance < raw_coins {
+            bail!("not enough coins for a local call with {}", raw_coins);
+        }
+        Ok(())
+    }
+
+    fn finish_local_call(&self, _success: bool) -> Result<()> {
+        // the local execution runs at the caller address, the coins never
+        // left it
+        Ok(())
+    }
+
+    fn get_balance(&self) -> Result<u64> {
+        Ok(self.balance(&self.current_address()))
+    }
+
+    fn get_balance_for(&self, address: &str) -> Result<u64> {
+        Ok(self.balance(address))
+    }
+
+    fn get_balance_wasmv1(&self, address: Option<String>) -> Result<NativeAmount> {
+        Ok(raw_to_amount(
+            self.balance(&self.address_or_current(address)),
+        ))
+    }
+
+    fn transfer_coins(&self, to_address: &str, raw_amount: u64) -> Result<()> {
+        let mut state = self.state.lock();
+        let from_address = state.current_address().to_string();
+        state.transfer(&from_address, to_address, raw_amount)
+    }
+
+    fn transfer_coins_for(
+        &self,
+        from_address: &str,
+        to_address: &str,
+        raw_amount: u64,
+    ) -> Result<()> {
+        self.state
+            .lock()
+            .transfer(from_address, to_address, raw_amount)
+    }
+
+    fn transfer_coins_wasmv1(
+        &self,
+        to_address: String,
+        raw_amount: NativeAmount,
+        from_address: Option<String>,
+    ) -> Result<()> {
+        let from_address = self.address_or_current(from_address);
+        self.transfer_coins_for(&from_address, &to_address, amount_to_raw(&raw_amount)?)
+    }
+
+    fn get_call_coins(&self) -> Result<u64> {
+        Ok(self
+            .state
+            .lock()
+            .calls
+            .last()
+            .map_or(0, |call| call.raw_coins))
+    }
+
+    fn get_call_coins_wasmv1(&self) -> Result<NativeAmount> {
+        Ok(raw_to_amount(self.get_call_coins()?))
+    }
+
+    fn raw_set_bytecode(&self, bytecode: &[u8]) -> Result<()> {
+        self.set_bytecode_at(&self.current_address(), bytecode)
+    }
+
+    fn raw_set_bytecode_for(&self, address: &str, bytecode: &[u8]) -> Result<()> {
+        self.set_bytecode_at(address, bytecode)
+    }
+
+    fn set_bytecode_wasmv1(&self, bytecode: &[u8], address: Option<String>) -> Result<()> {
+        self.set_bytecode_at(&self.address_or_current(address), bytecode)
+    }
+
+    fn create_module(&self, module: &[u8]) -> Result<String> {
+        Ok(self.deploy(module))
+    }
+
+    fn predict_created_address(&self, seed: &[u8]) -> Result<String> {
+        Ok(self.state.lock().seeded_address(seed))
+    }
+
+    fn create_module_with_seed(&self, module: &[u8], seed: &[u8]) -> Result<String> {
+        self.state.lock().create_seeded_account(module, seed)
+    }
+
+    fn create_module_with_datastore(
+        &self,
+        module: &[u8],
+        datastore: &[(Vec<u8>, Vec<u8>)],
+    ) -> Result<String> {
+        let mut state = self.state.lock();
+        let address = state.create_account(module);
+        state
+            .account_mut(&address)?
+            .datastore
+            .extend(datastore.iter().cloned());
+        Ok(address)
+    }
+
+    fn print(&self, message: &str) -> Result<()> {
+        println!("{}", message);
+        Ok(())
+    }
+
+    fn get_keys(&self, prefix: Option<&[u8]>) -> Result<BTreeSet<Vec<u8>>> {
+        self.get_keys_at(&self.current_address(), prefix.unwrap_or_default())
+    }
+
+    fn get_keys_for(&self, address: &str, prefix: Option<&[u8]>) -> Result<BTreeSet<Vec<u8>>> {
+        self.get_keys_at(address, prefix.unwrap_or_default())
+    }
+
+    fn get_ds_keys_wasmv1(
+        &self,
+        prefix: &[u8],
+        address: Option<String>,
+    ) -> Result<BTreeSet<Vec<u8>>> {
+        self.get_keys_at(&self.address_or_current(address), prefix)
+    }
+
+    fn raw_get_data(&self, key: &[u8]) -> Result<Vec<u8>> {
+        self.get_data_at(&self.current_address(), key)
+    }
+
+    fn raw_get_data_for(&self, address: &str, key: &[u8]) -> Result<Vec<u8>> {
+        self.get_data_at(address, key)
+    }
+
+    fn get_ds_value_wasmv1(&self, key: &[u8], address: Option<String>) -> Result<Vec<u8>> {
+        self.get_data_at(&self.address_or_current(address), key)
+    }
+
+    fn raw_set_data(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.set_data_at(&self.current_address(), key, value)
+    }
+
+    fn raw_set_data_batch(&self, entries: &[(Vec<u8>, Vec<u8>)]) -> Result<()> {
+        let mut state = self.state.lock();
+        state
+            .current_account_mut()?
+            .datastore
+            .extend(entries.iter().cloned());
+        Ok(())
+    }
+
+    fn raw_set_data_for(&self, address: &str, key: &[u8], value: &[u8]) -> Result<()> {
+        self.set_data_at(address, key, value)
+    }
+
+    fn set_ds_value_wasmv1(&self, key: &[u8], value: &[u8], address: Option<String>) -> Result<()> {
+        self.set_data_at(&self.address_or_current(address), key, value)
+    }
+
+    fn raw_append_data(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.append_data_at(&self.current_address(), key, value)
+    }
+
+    fn raw_append_data_for(&self, address: &str, key: &[u8], value: &[u8]) -> Result<()> {
+        self.append_data_at(address, key, value)
+    }
+
+    fn append_ds_value_wasmv1(
+        &self,
+        key: &[u8],
+        value: &[u8],
+        address: Option<String>,
+    ) -> Result<()> {
+        self.append_data_at(&self.address_or_current(address), key, value)
+    }
+
+    fn raw_delete_data(&self, key: &[u8]) -> Result<()> {
+        self.delete_data_at(&self.current_address(), key)
+    }
+
+    fn raw_delete_data_for(&self, address: &str, key: &[u8]) -> Result<()> {
+        self.delete_data_at(address, key)
+    }
+
+    fn delete_ds_entry_wasmv1(&self, key: &[u8], address: Option<String>) -> Result<()> {
+        self.delete_data_at(&self.address_or_current(address), key)
+    }
+
+    fn has_data(&self, key: &[u8]) -> Result<bool> {
+        self.has_data_at(&self.current_address(), key)
+    }
+
+    fn has_data_for(&self, address: &str, key: &[u8]) -> Result<bool> {
+        self.has_data_at(address, key)
+    }
+
+    fn ds_entry_exists_wasmv1(&self, key: &[u8], address: Option<String>) -> Result<bool> {
+        self.has_data_at(&self.address_or_current(address), key)
+    }
+
+    fn raw_get_bytecode(&self) -> Result<Vec<u8>> {
+        let state = self.state.lock();
+        Ok(state.account(state.current_address())?.bytecode.clone())
+    }
+
+    fn raw_get_bytecode_for(&self, address: &str) -> Result<Vec<u8>> {
+        self.get_bytecode_at(address)
+    }
+
+    fn get_bytecode_wasmv1(&self, address: Option<String>) -> Result<Vec<u8>> {
+        self.get_bytecode_at(&self.address_or_current(address))
+    }
+
+    fn get_op_keys(&self, prefix: Option<&[u8]>) -> Result<Vec<Vec<u8>>> {
+        self.get_op_keys_wasmv1(prefix.unwrap_or_default())
+    }
+
+    fn get_op_keys_wasmv1(&self, prefix: &[u8]) -> Result<Vec<Vec<u8>>> {
+        Ok(self
+            .state
+            .lock()
+            .op_datastore
+            .keys()
+            .filter(|key| key.starts_with(prefix))
+            .cloned()
+            .collect())
+    }
+
+    fn op_entry_exists(&self, key: &[u8]) -> Result<bool> {
+        Ok(self.state.lock().op_datastore.contains_key(key))
+    }
+
+    fn get_op_data(&self, key: &[u8]) -> Result<Vec<u8>> {
+        match self.state.lock().op_datastore.get(key) {
+            Some(value) => Ok(value.clone()),
+            None => bail!("operation datastore entry not found"),
+        }
+    }
+
+    fn caller_has_write_access(&self) -> Result<bool> {
+        Ok(true)
+    }
+
+    fn hash(&self, data: &[u8]) -> Result<[u8; 32]> {
+        self.hash_sha256(data)
+    }
+
+    fn hash_blake3(&self, _bytes: &[u8]) -> Result<[u8; 32]> {
+        bail!("blake3 is not supported by TestInterface")
+    }
+
+    fn signature_verify(&self, _data: &[u8], _signature: &str, _public_key: &str) -> Result<bool> {
+        bail!("signatures are not supported by TestInterface")
+    }
+
+    fn evm_signature_verify(
+        &self,
+        _message: &[u8],
+        _signature: &[u8],
+        _public_key: &[u8],
+    ) -> Result<bool> {
+        bail!("EVM signatures are not supported by TestInterface")
+    }
+
+    fn evm_get_address_from_pubkey(&self, _public_key: &[u8]) -> Result<Vec<u8>> {
+        bail!("EVM public keys are not supported by TestInterface")
+    }
+
+    fn evm_get_pubkey_from_signature(&self, _hash: &[u8], _signature: &[u8]) -> Result<Vec<u8>> {
+        bail!("EVM signatures are not supported by TestInterface")
+    }
+
+    fn is_address_eoa(&self, address: &str) -> Result<bool> {
+        Ok(address.starts_with("AU"))
+    }
+
+    fn address_from_public_key(&self, _public_key: &str) -> Result<String> {
+        bail!("public keys are not supported by TestInterface")
+    }
+
+    fn validate_address(&self, address: &str) -> Result<bool> {
+        Ok(address.len() > 2 && (address.starts_with("AU") || address.starts_with("AS")))
+    }
+
+    fn get_time(&self) -> Result<u64> {
+        Ok(self.state.lock().time)
+    }
+
+    fn unsafe_random(&self) -> Result<i64> {
+        Ok(self.state.lock().next_random() as i64)
+    }
+
+    fn unsafe_random_f64(&self) -> Result<f64> {
+        // 53 random bits in [0, 1)
+        Ok((self.state.lock().next_random() >> 11) as f64 / (1u64 << 53) as f64)
+    }
+
+    fn unsafe_random_wasmv1(&self, num_bytes: u64) -> Result<Vec<u8>> {
+        let mut state = self.state.lock();
+        let mut bytes = Vec::new();
+        while (bytes.len() as u64) < num_bytes {
+            bytes.extend_from_slice(&state.next_random().to_le_bytes());
+        }
+        bytes.truncate(num_bytes as usize);
+        Ok(bytes)
+    }
+
+    fn get_random_seed(&self) -> Result<[u8; 32]> {
+        let (period, thread) = self.state.lock().slot;
+        Ok(Sha256::new()
+            .chain_update(period.to_le_bytes())
+            .chain_update([thread])
+            .finalize()
+            .into())
+    }
+
+    fn get_current_period(&self) -> Result<u64> {
+        Ok(self.state.lock().slot.0)
+    }
+
+    fn get_current_thread(&self) -> Result<u8> {
+        Ok(self.state.lock().slot.1)
+    }
+
+    fn get_current_slot(&self) -> Result<Slot> {
+        let (period, thread) = self.state.lock().slot;
+        Ok(Slot {
+            period,
+            thread: thread as u32,
+        })
+    }
+
+    fn slot_to_timestamp(&self, (period, thread): (u64, u8)) -> Result<u64> {
+        let slot_duration = TEST_PERIOD_DURATION / self.config.thread_count as u64;
+        period
+            .checked_mul(TEST_PERIOD_DURATION)
+            .and_then(|elapsed| elapsed.checked_add(thread as u64 * slot_duration))
+            .and_then(|elapsed| elapsed.checked_add(TEST_GENESIS_TIMESTAMP))
+            .ok_or_else(|| anyhow!("timestamp of slot ({}, {}) overflows", period, thread))
+    }
+
+    fn timestamp_to_slot(&self, timestamp: u64) -> Result<(u64, u8)> {
+        let Some(elapsed) = timestamp.checked_sub(TEST_GENESIS_TIMESTAMP) else {
+            bail!("timestamp {} is before the genesis", timestamp);
+        };
+        let slot_duration = TEST_PERIOD_DURATION / self.config.thread_count as u64;
+        Ok((
+            elapsed / TEST_PERIOD_DURATION,
+            ((elapsed % TEST_PERIOD_DURATION) / slot_duration) as u8,
+        ))
+    }
+
+    fn get_owned_addresses(&self) -> Result<Vec<String>> {
+        Ok(vec![self.current_address()])
+    }
+
+    fn get_call_stack(&self) -> Result<Vec<String>> {
+        Ok(self.call_stack())
+    }
+
+    fn generate_event(&self, event: String) -> Result<()> {
+        self.state.lock().push_event(Vec::new(), event.into_bytes());
+        Ok(())
+    }
+
+    fn generate_event_binary(&self, topics: &[[u8; 32]], data: &[u8]) -> Result<()> {
+        self.state.lock().push_event(topics.to_vec(), data.to_vec());
+        Ok(())
+    }
+
+    fn generate_event_wasmv1(&self, event: Vec<u8>) -> Result<()> {
+        self.state.lock().push_event(Vec::new(), event);
+        Ok(())
+    }
+
+    fn get_module(&self, bytecode: &[u8], gas_limit: u64) -> Result<RuntimeModule> {
+        self.get_module_with_compiler(bytecode, gas_limit, Compiler::default())
+    }
+
+    fn get_module_with_compiler(
+        &self,
+        bytecode: &[u8],
+        _gas_limit: u64,
+        compiler: Compiler,
+    ) -> Result<RuntimeModule> {
+        RuntimeModule::new(bytecode, self.gas_costs.clone(), &self.config, compiler)
+    }
+
+    fn get_tmp_module(&self, bytecode: &[u8], _gas_limit: u64) -> Result<RuntimeModule> {
+        RuntimeModule::new(bytecode, self.gas_costs.clone(), &self.config, Compiler::SP)
+    }
+
+    fn send_message(
+        &self,
+        target_address: &str,
+        target_handler: &str,
+        validity_start: (u64, u8),
+        validity_end: (u64, u8),
+        max_gas: u64,
+        raw_fee: u64,
+        raw_coins: u64,
+        data: &[u8],
+        _filter: Option<(&str, Option<&[u8]>)>,
+    ) -> Result<()> {
+        let mut state = self.state.lock();
+        let sender = state.current_address().to_string();
+        let account = state.account_mut(&sender)?;
+        account.balance = match raw_coins
+            .checked_add(raw_fee)
+            .and_then(|cost| account.balance.checked_sub(cost))
+        {
+            Some(balance) => balance,
+            None => bail!("not enough coins at {} to send the message", sender),
+        };
+        state.messages.push(TestMessage {
+            sender,
+            target_address: target_address.to_string(),
+            target_handler: target_handler.to_string(),
+            validity_start,
+            validity_end,
+            max_gas,
+            raw_fee,
+            raw_coins,
+            data: data.to_vec(),
+        });
+        Ok(())
+    }
+
+    fn get_origin_operation_id(&self) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    fn hash_sha256(&self, bytes: &[u8]) -> Result<[u8; 32]> {
+        Ok(Sha256::digest(bytes).into())
+    }
+
+    fn hash_keccak256(&self, bytes: &[u8]) -> Result<[u8; 32]> {
+        Ok(Keccak256::digest(bytes).into())
+    }
+
+    fn chain_id(&self) -> Result<u64> {
+        Ok(self.state.lock().chain_id)
+    }
+
+    fn native_amount_from_str_wasmv1(&self, amount: &str) -> Result<NativeAmount> {
+        let (integer, decimals) = amount.split_once('.').unwrap_or((amount, ""));
+        if decimals.len() > COIN_DECIMALS as usize {
+            bail!("too many decimals in amount {}", amount);
+        }
+        Ok(NativeAmount {
+            mantissa: format!("{}{}", integer, decimals).parse()?,
+            scale: decimals.len() as u32,
+        })
+    }
+
+    fn native_amount_to_string_wasmv1(&self, amount: &NativeAmount) -> Result<String> {
+        let raw_amount = amount_to_raw(amount)?;
+        let unit = 10u64.pow(COIN_DECIMALS);
+        let decimals = format!("{:09}", raw_amount % unit);
+        let decimals = decimals.trim_end_matches('0');
+        if decimals.is_empty() {
+            Ok((raw_amount / unit).to_string())
+        } else {
+            Ok(format!("{}.{}", raw_amount / unit, decimals))
+        }
+    }
+
+    fn check_native_amount_wasmv1(&self, amount: &NativeAmount) -> Result<bool> {
+        Ok(amount_to_raw(amount).is_ok())
+    }
+
+    fn add_native_amount_wasmv1(
+        &self,
+        amount1: &NativeAmount,
+        amount2: &NativeAmount,
+    ) -> Result<NativeAmount> {
+        amount_to_raw(amount1)?
+            .checked_add(amount_to_raw(amount2)?)
+            .map(raw_to_amount)
+            .ok_or_else(|| anyhow!("amount overflow"))
+    }
+
+    fn sub_native_amount_wasmv1(
+        &self,
+        amount1: &NativeAmount,
+        amount2: &NativeAmount,
+    ) -> Result<NativeAmount> {
+        amount_to_raw(amount1)?
+            .checked_sub(amount_to_raw(amount2)?)
+            .map(raw_to_amount)
+            .ok_or_else(|| anyhow!("amount underflow"))
+    }
+
+    fn scalar_mul_native_amount_wasmv1(
+        &self,
+        amount: &NativeAmount,
+        factor: u64,
+    ) -> Result<NativeAmount> {
+        amount_to_raw(amount)?
+            .checked_mul(factor)
+            .map(raw_to_amount)
+            .ok_or_else(|| anyhow!("amount overflow"))
+    }
+
+    fn scalar_div_rem_native_amount_wasmv1(
+        &self,
+        dividend: &NativeAmount,
+        divisor: u64,
+    ) -> Result<(NativeAmount, NativeAmount)> {
+        let dividend = amount_to_raw(dividend)?;
+        match (dividend.checked_div(divisor), dividend.checked_rem(divisor)) {
+            (Some(quotient), Some(remainder)) => {
+                Ok((raw_to_amount(quotient), raw_to_amount(remainder)))
+            }
+            _ => bail!("division by zero"),
+        }
+    }
+
+    fn div_rem_native_amount_wasmv1(
+        &self,
+        dividend: &NativeAmount,
+        divisor: &NativeAmount,
+    ) -> Result<(u64, NativeAmount)> {
+        let dividend = amount_to_raw(dividend)?;
+        let divisor = amount_to_raw(divisor)?;
+        match (dividend.checked_div(divisor), dividend.checked_rem(divisor)) {
+            (Some(quotient), Some(remainder)) => Ok((quotient, raw_to_amount(remainder))),
+            _ => bail!("division by zero"),
+        }
+    }
+
+    fn check_address_wasmv1(&self, to_check: &str) -> Result<bool> {
+        self.validate_address(to_check)
+    }
+
+    fn check_pubkey_wasmv1(&self, _to_check: &str) -> Result<bool> {
+        bail!("public keys are not supported by TestInterface")
+    }
+
+    fn check_signature_wasmv1(&self, _to_check: &str) -> Result<bool> {
+        bail!("signatures are not supported by TestInterface")
+    }
+
+    fn get_address_category_wasmv1(&self, to_check: &str) -> Result<AddressCategory> {
+        if to_check.starts_with("AU") {
+            Ok(AddressCategory::UserAddress)
+        } else if to_check.starts_with("AS") {
+            Ok(AddressCategory::ScAddress)
+        } else {
+            Ok(AddressCategory::Unspecified)
+        }
+    }
+
+    fn get_address_version_wasmv1(&self, _address: &str) -> Result<u64> {
+        Ok(0)
+    }
+
+    fn get_pubkey_version_wasmv1(&self, _pubkey: &str) -> Result<u64> {
+        bail!("public keys are not supported by TestInterface")
+    }
+
+    fn get_signature_version_wasmv1(&self, _signature: &str) -> Result<u64> {
+        bail!("signatures are not supported by TestInterface")
+    }
+
+    fn checked_add_native_time_wasmv1(
+        &self,
+        time1: &NativeTime,
+        time2: &NativeTime,
+    ) -> Result<NativeTime> {
+        let milliseconds = time1
+            .milliseconds
+            .checked_add(time2.milliseconds)
+            .ok_or_else(|| anyhow!("time overflow"))?;
+        Ok(NativeTime { milliseconds })
+    }
+
+    fn checked_sub_native_time_wasmv1(
+        &self,
+        time1: &NativeTime,
+        time2: &NativeTime,
+    ) -> Result<NativeTime> {
+        let milliseconds = time1
+            .milliseconds
+            .checked_sub(time2.milliseconds)
+            .ok_or_else(|| anyhow!("time underflow"))?;
+        Ok(NativeTime { milliseconds })
+    }
+
+    fn checked_mul_native_time_wasmv1(&self, time: &NativeTime, factor: u64) -> Result<NativeTime> {
+        let milliseconds = time
+            .milliseconds
+            .checked_mul(factor)
+            .ok_or_else(|| anyhow!("time overflow"))?;
+        Ok(NativeTime { milliseconds })
+    }
+
+    fn checked_scalar_div_native_time_wasmv1(
+        &self,
+        dividend: &NativeTime,
+        divisor: u64,
+    ) -> Result<(NativeTime, NativeTime)> {
+        match (
+            dividend.milliseconds.checked_div(divisor),
+            dividend.milliseconds.checked_rem(divisor),
+        ) {
+            (Some(quotient), Some(remainder)) => Ok((
+                NativeTime {
+                    milliseconds: quotient,
+                },
+                NativeTime {
+                    milliseconds: remainder,
+                },
+            )),
+            _ => bail!("division by zero"),
+        }
+    }
+
+    fn checked_div_native_time_wasmv1(
+        &self,
+        dividend: &NativeTime,
+        divisor: &NativeTime,
+    ) -> Result<(u64, NativeTime)> {
+        match (
+            dividend.milliseconds.checked_div(divisor.milliseconds),
+            dividend.milliseconds.checked_rem(divisor.milliseconds),
+        ) {
+            (Some(quotient), Some(remainder)) => Ok((
+                quotient,
+                NativeTime {
+                    milliseconds: remainder,
+                },
+            )),
+            _ => bail!("division by zero"),
+        }
+    }
+
+    fn base58_check_to_bytes_wasmv1(&self, _s: &str) -> Result<Vec<u8>> {
+        bail!("base58check is not supported by TestInterface")
+    }
+
+    fn bytes_to_base58_check_wasmv1(&self, bytes: &[u8]) -> String {
+        // not a real base58check encoding, only meant to be readable
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    fn compare_address_wasmv1(&self, left: &str, right: &str) -> Result<ComparisonResult> {
+        Ok(compare(left, right))
+    }
+
+    fn compare_native_amount_wasmv1(
+        &self,
+        left: &NativeAmount,
+        right: &NativeAmount,
+    ) -> Result<ComparisonResult> {
+        Ok(compare(amount_to_raw(left)?, amount_to_raw(right)?))
+    }
+
+    fn compare_native_time_wasmv1(
+        &self,
+        left: &NativeTime,
+        right: &NativeTime,
+    ) -> Result<ComparisonResult> {
+        Ok(compare(left.milliseconds, right.milliseconds))
+    }
+
+    fn compare_pub_key_wasmv1(&self, left: &str, right: &str) -> Result<ComparisonResult> {
+        Ok(compare(left, right))
+    }
+
+    fn save_gas_remaining_before_subexecution(&self, _gas_used_until: u64) {}
+}
+
+/// `ReadOnlyCache` keeping every execution in memory, counting its lookups
+#[derive(Debug, Default)]
+pub struct InMemoryReadOnlyCache {
+    entries: Mutex<HashMap<ReadOnlyCacheKey, CachedExecution>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl InMemoryReadOnlyCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Lookups that found an execution
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Lookups that did not find an execution
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+}
+
+impl ReadOnlyCache for InMemoryReadOnlyCache {
+    fn get(&self, key: &ReadOnlyCacheKey) -> Option<CachedExecution> {
+        let execution = self.entries.lock().get(key).cloned();
+        let counter = match execution {
+            Some(_) => &self.hits,
+            None => &self.misses,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        execution
+    }
+
+    fn insert(&self, key: ReadOnlyCacheKey, execution: CachedExecution) {
+        self.entries.lock().insert(key, execution);
+    }
+}
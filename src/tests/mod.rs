@@ -1,15 +1,190 @@
 use crate::as_execution::ASModule;
 use crate::types::{Interface, InterfaceClone};
-use crate::{Compiler, GasCosts, RuntimeModule};
+use crate::{BytecodeHash, Compiler, GasCosts, ModuleCache, RuntimeConfig, RuntimeModule};
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 use massa_proto_rs::massa::model::v1::*;
 use sha2::{Digest, Sha256};
 use sha3::Keccak256;
+use std::cell::{Cell, RefCell};
 use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, Mutex};
+
+/// Bytecode of the modules created through `TestInterface::create_module`
+static CREATED_MODULES: Mutex<BTreeMap<String, Vec<u8>>> = Mutex::new(BTreeMap::new());
+
+/// `create_module_with_seed` creates the smart contracts of this seed
+/// elsewhere than `predict_created_address` says
+pub(crate) const MISPREDICTED_SEED: &[u8] = b"mispredicted";
+
+/// `finish_call` fails for the calls to this address
+pub(crate) const FAILING_FINISH_CALL_ADDRESS: &str = "finish_call_failure";
+
+/// Balance of the current address
+pub(crate) const TEST_BALANCE: u64 = 1_000;
+
+/// Coins charged for each stored byte, the storage of more than
+/// `TEST_BALANCE / STORAGE_BYTE_COST` bytes cannot be reserved
+pub(crate) const STORAGE_BYTE_COST: u64 = 10;
+
+/// `validate_address` rejects this address
+pub(crate) const INVALID_ADDRESS: &str = "invalid_address";
+
+/// The balance of this address is `u64::MAX`
+pub(crate) const FULL_BALANCE_ADDRESS: &str = "full_balance";
+
+/// The balance of this address cannot be read
+pub(crate) const UNKNOWN_BALANCE_ADDRESS: &str = "unknown_balance";
+
+/// Reading the balance of this address panics, like a faulty interface
+pub(crate) const PANICKING_ADDRESS: &str = "panicking_address";
+
+/// Timestamp (milliseconds) of the genesis of the mocked chain
+pub(crate) const GENESIS_TIMESTAMP: u64 = 1_600_000_000_000;
+
+/// Duration (milliseconds) of a period of the mocked chain
+pub(crate) const PERIOD_DURATION: u64 = 16_000;
+
+/// Number of threads of the mocked chain
+pub(crate) const MOCK_THREAD_COUNT: u8 = 32;
+
+/// Random seed of every execution of the mocked chain
+pub(crate) const MOCK_RANDOM_SEED: [u8; 32] = [7; 32];
+
+/// A deferred call as registered through `TestInterface::deferred_call_register`
+pub(crate) type TestDeferredCall = (String, String, (u64, u8), u64, Vec<u8>, u64);
+
+/// Deferred calls registered and not cancelled, by id
+pub(crate) static DEFERRED_CALLS: Mutex<BTreeMap<String, TestDeferredCall>> =
+    Mutex::new(BTreeMap::new());
+
+/// Cancelling this deferred call fails as if it was registered by another
+/// address
+pub(crate) const FOREIGN_DEFERRED_CALL_ID: &str = "D_foreign";
+
+thread_local! {
+    /// Addresses of the calls started by `init_call` and not finished yet
+    static PENDING_CALLS: RefCell<Vec<String>> = RefCell::new(Vec::new());
+    /// Compilers requested through `get_module_with_compiler`
+    pub(crate) static REQUESTED_COMPILERS: RefCell<Vec<Compiler>> = RefCell::new(Vec::new());
+    /// Returned by `get_origin_operation_id`
+    pub(crate) static ORIGIN_OPERATION_ID: RefCell<Option<String>> = RefCell::new(None);
+    /// Calls started and finished through the interface, whatever the ABI
+    /// family of the caller
+    pub(crate) static INTERFACE_CALLS: RefCell<Vec<InterfaceCall>> = RefCell::new(Vec::new());
+    /// Events given to `generate_event_indexed`, with their index
+    pub(crate) static INDEXED_EVENTS: RefCell<Vec<(u64, String)>> = RefCell::new(Vec::new());
+    /// When set, the calls go through `init_call_hash` and the modules are
+    /// compiled and looked up in this cache
+    pub(crate) static MODULE_CACHE: RefCell<Option<Arc<ModuleCache>>> = RefCell::new(None);
+    /// When set, `get_module_with_compiler` compiles the modules with
+    /// `RuntimeConfig::gas_calibration`
+    pub(crate) static CALIBRATED_MODULES: Cell<bool> = Cell::new(false);
+    /// Number of bytecodes of called smart contracts fetched by the runtime
+    pub(crate) static BYTECODE_FETCHES: Cell<u64> = Cell::new(0);
+    /// Number of `get_random_seed` queries
+    pub(crate) static RANDOM_SEED_QUERIES: Cell<u64> = Cell::new(0);
+    /// Number of `get_current_slot` queries
+    pub(crate) static SLOT_QUERIES: Cell<u64> = Cell::new(0);
+    /// Number of `caller_has_write_access` and `is_address_eoa` queries
+    pub(crate) static ACCESS_QUERIES: Cell<u64> = Cell::new(0);
+    /// Number of conversions of public keys and addresses asked
+    pub(crate) static CONVERSION_QUERIES: Cell<u64> = Cell::new(0);
+    /// Keys of the datastore of the current address
+    pub(crate) static DATASTORE_KEYS: RefCell<BTreeSet<Vec<u8>>> = RefCell::new(BTreeSet::new());
+    /// Returned by `raw_get_data` for every key
+    pub(crate) static DATASTORE_VALUE: RefCell<Vec<u8>> = RefCell::new(Vec::new());
+    /// Prefetch hints and reads of the datastore of the current address, in
+    /// order
+    pub(crate) static DATASTORE_READS: RefCell<Vec<DatastoreRead>> = RefCell::new(Vec::new());
+    /// Batches given to `raw_set_data_batch`, in order
+    pub(crate) static DATASTORE_BATCHES: RefCell<Vec<Vec<(Vec<u8>, Vec<u8>)>>> =
+        RefCell::new(Vec::new());
+    /// Returned by `get_call_stack`
+    pub(crate) static CALL_STACK: RefCell<Vec<String>> = RefCell::new(Vec::new());
+    /// Returned by `interface_version`
+    pub(crate) static REPORTED_INTERFACE_VERSION: Cell<u32> = Cell::new(crate::INTERFACE_VERSION);
+    /// When set, returned as is by `get_op_keys` and `get_op_keys_wasmv1`
+    pub(crate) static OP_KEYS: RefCell<Option<Vec<Vec<u8>>>> = RefCell::new(None);
+}
+
+/// Keys of the operation datastore, out of order as the runtime sorts them
+fn op_keys() -> Vec<Vec<u8>> {
+    OP_KEYS
+        .with(|keys| keys.borrow().clone())
+        .unwrap_or_else(|| {
+            vec![
+                vec![254, 255],
+                vec![0, 1, 2, 3, 4, 5, 6, 11],
+                vec![127, 128],
+            ]
+        })
+}
+
+/// Cache of the calls going through `init_call_hash`, if enabled
+fn module_cache() -> Option<Arc<ModuleCache>> {
+    MODULE_CACHE.with(|cache| cache.borrow().clone())
+}
+
+/// Configuration the modules are compiled with by `get_module_with_compiler`
+fn module_config() -> RuntimeConfig {
+    RuntimeConfig {
+        gas_calibration: CALIBRATED_MODULES.with(Cell::get),
+        ..RuntimeConfig::default()
+    }
+}
+
+/// Call of the interface recorded in `INTERFACE_CALLS`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum InterfaceCall {
+    InitCall { address: String, raw_coins: u64 },
+    FinishCall,
+}
+
+/// Access to the datastore recorded in `DATASTORE_READS`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum DatastoreRead {
+    Prefetch(Vec<Vec<u8>>),
+    Get(Vec<u8>),
+}
+
+fn record_call(call: InterfaceCall) {
+    INTERFACE_CALLS.with(|calls| calls.borrow_mut().push(call));
+}
+
+/// Bytes of a public key of the mocked chain, written `P` followed by the
+/// bytes in hex
+fn public_key_bytes(public_key: &str) -> Result<Vec<u8>> {
+    let malformed = || anyhow!("malformed public key {:?}", public_key);
+    let hex = public_key.strip_prefix('P').ok_or_else(malformed)?;
+    if hex.is_empty() || hex.len() % 2 != 0 || !hex.is_ascii() {
+        return Err(malformed());
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| malformed()))
+        .collect()
+}
+
+/// Address of the public key of the mocked chain serialized as `public_key`
+fn public_key_address(public_key: &[u8]) -> Result<String> {
+    if public_key.is_empty() {
+        bail!("empty public key");
+    }
+    let hash = Sha256::digest(public_key);
+    Ok(hash[..8].iter().fold("AU".to_string(), |address, byte| {
+        address + &format!("{:02x}", byte)
+    }))
+}
+
+/// Coins of `amount` in nano-coins, if it is valid
+fn raw_coins(amount: &NativeAmount) -> Option<u64> {
+    let factor = 10u64.checked_pow(9u32.checked_sub(amount.scale)?)?;
+    amount.mantissa.checked_mul(factor)
+}
 
 #[derive(Clone)]
-struct TestInterface;
+pub(crate) struct TestInterface;
 
 impl InterfaceClone for TestInterface {
     fn clone_box(&self) -> Box<dyn Interface> {
@@ -18,21 +193,83 @@ impl InterfaceClone for TestInterface {
 }
 
 impl Interface for TestInterface {
+    fn interface_version(&self) -> u32 {
+        REPORTED_INTERFACE_VERSION.with(Cell::get)
+    }
+
     fn init_call(&self, address: &str, raw_coins: u64) -> Result<Vec<u8>> {
         println!("Init call to {}, with {} coins", address, raw_coins);
-        Ok(vec![])
+        PENDING_CALLS.with(|calls| calls.borrow_mut().push(address.to_string()));
+        record_call(InterfaceCall::InitCall {
+            address: address.to_string(),
+            raw_coins,
+        });
+        BYTECODE_FETCHES.with(|fetches| fetches.set(fetches.get() + 1));
+        let created = CREATED_MODULES.lock().unwrap();
+        Ok(created.get(address).cloned().unwrap_or_default())
     }
 
-    fn init_call_wasmv1(&self, address: &str, raw_coins: NativeAmount) -> Result<Vec<u8>> {
-        println!(
-            "Init call wasmv1 to {}, with {:?} coins",
-            address, raw_coins
-        );
-        Ok(vec![])
+    fn init_call_hash(
+        &self,
+        address: &str,
+        raw_coins: u64,
+    ) -> Result<Option<(BytecodeHash, usize)>> {
+        let Some(cache) = module_cache() else {
+            return Ok(None);
+        };
+        println!("Init call to {} by hash, with {} coins", address, raw_coins);
+        PENDING_CALLS.with(|calls| calls.borrow_mut().push(address.to_string()));
+        record_call(InterfaceCall::InitCall {
+            address: address.to_string(),
+            raw_coins,
+        });
+        let created = CREATED_MODULES.lock().unwrap();
+        let bytecode = created.get(address).map(Vec::as_slice).unwrap_or_default();
+        let hash = ModuleCache::hash_bytecode(bytecode);
+        cache.bind_address(address, hash);
+        Ok(Some((hash, bytecode.len())))
+    }
+
+    fn get_cached_module(
+        &self,
+        hash: &BytecodeHash,
+        compiler: Compiler,
+    ) -> Result<Option<RuntimeModule>> {
+        Ok(module_cache()
+            .and_then(|cache| cache.get(hash, &GasCosts::default(), &module_config(), compiler)))
+    }
+
+    fn init_call_wasmv1(&self, address: &str, coins: NativeAmount) -> Result<Vec<u8>> {
+        println!("Init call wasmv1 to {}, with {:?} coins", address, coins);
+        let Some(raw_coins) = raw_coins(&coins) else {
+            bail!("invalid amount of coins: {:?}", coins);
+        };
+        PENDING_CALLS.with(|calls| calls.borrow_mut().push(address.to_string()));
+        record_call(InterfaceCall::InitCall {
+            address: address.to_string(),
+            raw_coins,
+        });
+        let created = CREATED_MODULES.lock().unwrap();
+        Ok(created.get(address).cloned().unwrap_or_default())
     }
 
     fn finish_call(&self) -> Result<()> {
         println!("Finish call");
+        record_call(InterfaceCall::FinishCall);
+        let address = PENDING_CALLS.with(|calls| calls.borrow_mut().pop());
+        if address.as_deref() == Some(FAILING_FINISH_CALL_ADDRESS) {
+            bail!("finish_call failed for {}", FAILING_FINISH_CALL_ADDRESS);
+        }
+        Ok(())
+    }
+
+    fn init_local_call(&self, raw_coins: u64) -> Result<()> {
+        println!("Init local call with {} coins", raw_coins);
+        Ok(())
+    }
+
+    fn finish_local_call(&self, success: bool) -> Result<()> {
+        println!("Finish local call, success: {}", success);
         Ok(())
     }
 
@@ -79,7 +316,18 @@ impl Interface for TestInterface {
 
     fn address_from_public_key(&self, public_key: &str) -> Result<String> {
         println!("Address from public key {}", public_key);
-        Ok("".to_string())
+        CONVERSION_QUERIES.with(|queries| queries.set(queries.get() + 1));
+        public_key_address(&public_key_bytes(public_key)?)
+    }
+
+    fn address_from_public_key_bytes(&self, public_key: &[u8]) -> Result<String> {
+        CONVERSION_QUERIES.with(|queries| queries.set(queries.get() + 1));
+        public_key_address(public_key)
+    }
+
+    fn public_key_from_string(&self, public_key: &str) -> Result<Vec<u8>> {
+        CONVERSION_QUERIES.with(|queries| queries.set(queries.get() + 1));
+        public_key_bytes(public_key)
     }
 
     fn generate_event(&self, event: String) -> Result<()> {
@@ -87,9 +335,19 @@ impl Interface for TestInterface {
         Ok(())
     }
 
+    fn generate_event_indexed(&self, index: u64, event: String) -> Result<()> {
+        INDEXED_EVENTS.with(|events| events.borrow_mut().push((index, event.clone())));
+        self.generate_event(event)
+    }
+
+    fn generate_event_binary(&self, topics: &[[u8; 32]], data: &[u8]) -> Result<()> {
+        println!("Generate binary event {:?} with data {:?}", topics, data);
+        Ok(())
+    }
+
     fn get_call_stack(&self) -> Result<Vec<String>> {
         println!("Get call stack");
-        Ok(vec![])
+        Ok(CALL_STACK.with(|stack| stack.borrow().clone()))
     }
 
     fn get_current_period(&self) -> Result<u64> {
@@ -104,22 +362,63 @@ impl Interface for TestInterface {
 
     fn get_current_slot(&self) -> Result<Slot> {
         println!("Get current slot");
+        SLOT_QUERIES.with(|queries| queries.set(queries.get() + 1));
         Ok(Slot {
             period: 0,
             thread: 0,
         })
     }
 
+    fn slot_to_timestamp(&self, (period, thread): (u64, u8)) -> Result<u64> {
+        let slot_duration = PERIOD_DURATION / MOCK_THREAD_COUNT as u64;
+        period
+            .checked_mul(PERIOD_DURATION)
+            .and_then(|elapsed| elapsed.checked_add(thread as u64 * slot_duration))
+            .and_then(|elapsed| elapsed.checked_add(GENESIS_TIMESTAMP))
+            .ok_or_else(|| anyhow!("slot timestamp overflow"))
+    }
+
+    fn timestamp_to_slot(&self, timestamp: u64) -> Result<(u64, u8)> {
+        let Some(elapsed) = timestamp.checked_sub(GENESIS_TIMESTAMP) else {
+            bail!("timestamp {} is before the genesis", timestamp);
+        };
+        let slot_duration = PERIOD_DURATION / MOCK_THREAD_COUNT as u64;
+        Ok((
+            elapsed / PERIOD_DURATION,
+            ((elapsed % PERIOD_DURATION) / slot_duration) as u8,
+        ))
+    }
+
     fn get_module(&self, bytecode: &[u8], gas_limit: u64) -> Result<RuntimeModule> {
-        println!("Get module");
-        let as_module = ASModule::new(bytecode, gas_limit, GasCosts::default(), Compiler::CL)?;
+        self.get_module_with_compiler(bytecode, gas_limit, Compiler::default())
+    }
+
+    fn get_module_with_compiler(
+        &self,
+        bytecode: &[u8],
+        gas_limit: u64,
+        compiler: Compiler,
+    ) -> Result<RuntimeModule> {
+        println!("Get module with {:?}", compiler);
+        REQUESTED_COMPILERS.with(|compilers| compilers.borrow_mut().push(compiler.clone()));
+        let config = module_config();
+        if let Some(cache) = module_cache() {
+            return cache.get_or_compile(bytecode, GasCosts::default(), &config, compiler);
+        }
+        let as_module = ASModule::new(bytecode, gas_limit, GasCosts::default(), &config, compiler)?;
         let module = RuntimeModule::ASModule(as_module);
         Ok(module)
     }
 
     fn get_tmp_module(&self, bytecode: &[u8], gas_limit: u64) -> Result<RuntimeModule> {
         println!("Get tmp module");
-        let as_module = ASModule::new(bytecode, gas_limit, GasCosts::default(), Compiler::SP)?;
+        let as_module = ASModule::new(
+            bytecode,
+            gas_limit,
+            GasCosts::default(),
+            &RuntimeConfig::default(),
+            Compiler::SP,
+        )?;
         let module = RuntimeModule::ASModule(as_module);
         Ok(module)
     }
@@ -243,7 +542,20 @@ impl Interface for TestInterface {
 
     fn raw_get_data(&self, key: &[u8]) -> Result<Vec<u8>> {
         println!("Raw get data at {:?}", key);
-        Ok(vec![])
+        DATASTORE_READS.with(|reads| reads.borrow_mut().push(DatastoreRead::Get(key.to_vec())));
+        Ok(DATASTORE_VALUE.with(|value| value.borrow().clone()))
+    }
+
+    /// Recorded, then fails so that the tests check that the runtime ignores
+    /// the errors of the hints
+    fn prefetch(&self, keys: &[Vec<u8>]) -> Result<()> {
+        println!("Prefetch {:?}", keys);
+        DATASTORE_READS.with(|reads| {
+            reads
+                .borrow_mut()
+                .push(DatastoreRead::Prefetch(keys.to_vec()))
+        });
+        bail!("nothing to prefetch in the mocked datastore")
     }
 
     fn raw_get_data_for(&self, address: &str, key: &[u8]) -> Result<Vec<u8>> {
@@ -268,6 +580,22 @@ impl Interface for TestInterface {
         Ok(())
     }
 
+    fn raw_set_data_batch(&self, entries: &[(Vec<u8>, Vec<u8>)]) -> Result<()> {
+        println!("Raw set data batch with entries {:?}", entries);
+        DATASTORE_BATCHES.with(|batches| batches.borrow_mut().push(entries.to_vec()));
+        Ok(())
+    }
+
+    fn get_storage_cost(&self, bytes: u64) -> Result<u64> {
+        bytes
+            .checked_mul(STORAGE_BYTE_COST)
+            .ok_or_else(|| anyhow!("storage cost overflow"))
+    }
+
+    fn reserve_storage(&self, bytes: u64) -> Result<bool> {
+        Ok(self.get_storage_cost(bytes)? <= TEST_BALANCE)
+    }
+
     fn raw_set_data_for(&self, address: &str, key: &[u8], value: &[u8]) -> Result<()> {
         println!(
             "Raw set data for {} at {:?} with value {:?}",
@@ -312,13 +640,27 @@ impl Interface for TestInterface {
         Ok(bytes)
     }
 
+    fn get_random_seed(&self) -> Result<[u8; 32]> {
+        RANDOM_SEED_QUERIES.with(|queries| queries.set(queries.get() + 1));
+        Ok(MOCK_RANDOM_SEED)
+    }
+
     fn get_balance(&self) -> Result<u64> {
         println!("Get balance");
-        Ok(0)
+        Ok(TEST_BALANCE)
     }
 
-    fn get_balance_for(&self, _address: &str) -> Result<u64> {
+    fn get_balance_for(&self, address: &str) -> Result<u64> {
         println!("Get balance for");
+        if address == FULL_BALANCE_ADDRESS {
+            return Ok(u64::MAX);
+        }
+        if address == UNKNOWN_BALANCE_ADDRESS {
+            bail!("address {} does not exist", address);
+        }
+        if address == PANICKING_ADDRESS {
+            panic!("balance of {} is out of reach", address);
+        }
         Ok(0)
     }
 
@@ -326,6 +668,9 @@ impl Interface for TestInterface {
         match address {
             Some(address) => {
                 println!("Get balance for {:?}", address);
+                if address == PANICKING_ADDRESS {
+                    panic!("balance of {} is out of reach", address);
+                }
             }
             None => {
                 println!("Get balance for current address");
@@ -347,6 +692,17 @@ impl Interface for TestInterface {
             "Raw set bytecode for {} with bytecode {:?}",
             address, bytecode
         );
+        CREATED_MODULES
+            .lock()
+            .unwrap()
+            .insert(address.to_string(), bytecode.to_vec());
+        Ok(())
+    }
+
+    fn invalidate_cached_module(&self, address: Option<&str>) -> Result<()> {
+        if let (Some(cache), Some(address)) = (module_cache(), address) {
+            cache.invalidate_address(address);
+        }
         Ok(())
     }
 
@@ -399,7 +755,37 @@ impl Interface for TestInterface {
         } else {
             println!("Create module with module {:?}", module);
         }
-        Ok("sc_address".to_string())
+        let address = "sc_address".to_string();
+        CREATED_MODULES
+            .lock()
+            .unwrap()
+            .insert(address.clone(), module.to_vec());
+        Ok(address)
+    }
+
+    fn create_module_with_datastore(
+        &self,
+        module: &[u8],
+        datastore: &[(Vec<u8>, Vec<u8>)],
+    ) -> Result<String> {
+        println!("Create module with datastore {:?}", datastore);
+        self.create_module(module)
+    }
+
+    fn predict_created_address(&self, seed: &[u8]) -> Result<String> {
+        Ok(format!("seeded_{}", bs58::encode(seed).into_string()))
+    }
+
+    fn create_module_with_seed(&self, module: &[u8], seed: &[u8]) -> Result<String> {
+        let mut address = self.predict_created_address(seed)?;
+        if seed == MISPREDICTED_SEED {
+            address.push_str("_elsewhere");
+        }
+        CREATED_MODULES
+            .lock()
+            .unwrap()
+            .insert(address.clone(), module.to_vec());
+        Ok(address)
     }
 
     /// Print function for examples
@@ -428,11 +814,7 @@ impl Interface for TestInterface {
     }
 
     fn get_op_keys(&self, prefix: Option<&[u8]>) -> Result<Vec<Vec<u8>>> {
-        let data = vec![
-            vec![0, 1, 2, 3, 4, 5, 6, 11],
-            vec![127, 128],
-            vec![254, 255],
-        ];
+        let data = op_keys();
 
         match prefix {
             Some(prefix) => {
@@ -444,13 +826,18 @@ impl Interface for TestInterface {
         }
     }
 
+    fn get_op_keys_prefix(&self, prefix: &[u8]) -> Result<Vec<Vec<u8>>> {
+        println!("Get op keys with prefix {:?}", prefix);
+        Ok(self
+            .get_op_keys(None)?
+            .into_iter()
+            .filter(|key| key.starts_with(prefix))
+            .collect())
+    }
+
     fn get_op_keys_wasmv1(&self, prefix: &[u8]) -> Result<Vec<Vec<u8>>> {
         println!("Get op keys wasmv1 called with prefix {:?}", prefix);
-        Ok(vec![
-            vec![0, 1, 2, 3, 4, 5, 6, 11],
-            vec![127, 128],
-            vec![254, 255],
-        ])
+        Ok(op_keys())
     }
 
     fn op_entry_exists(&self, key: &[u8]) -> Result<bool> {
@@ -529,8 +916,8 @@ impl Interface for TestInterface {
         Ok("string amount".to_string())
     }
 
-    fn check_native_amount_wasmv1(&self, _amount: &NativeAmount) -> Result<bool> {
-        Ok(true)
+    fn check_native_amount_wasmv1(&self, amount: &NativeAmount) -> Result<bool> {
+        Ok(raw_coins(amount).is_some())
     }
 
     fn add_native_amount_wasmv1(
@@ -746,8 +1133,40 @@ impl Interface for TestInterface {
         }
     }
 
-    fn get_keys(&self, _prefix: Option<&[u8]>) -> Result<BTreeSet<Vec<u8>>> {
-        todo!()
+    fn get_keys(&self, prefix: Option<&[u8]>) -> Result<BTreeSet<Vec<u8>>> {
+        let prefix = prefix.unwrap_or_default();
+        Ok(DATASTORE_KEYS.with(|keys| {
+            keys.borrow()
+                .iter()
+                .filter(|key| key.starts_with(prefix))
+                .cloned()
+                .collect()
+        }))
+    }
+
+    fn get_keys_paged(
+        &self,
+        prefix: Option<&[u8]>,
+        cursor: Option<&[u8]>,
+        limit: usize,
+    ) -> Result<(Vec<Vec<u8>>, Option<Vec<u8>>)> {
+        let keys = self.get_keys(prefix)?;
+        // the cursor is the last key of the previous page, which must still
+        // be there
+        let start = match cursor {
+            Some(cursor) => match keys.iter().position(|key| key == cursor) {
+                Some(position) => position + 1,
+                None => bail!("stale cursor: {:?} was removed", cursor),
+            },
+            None => 0,
+        };
+        let page: Vec<_> = keys.iter().skip(start).take(limit).cloned().collect();
+        let next = if start + page.len() < keys.len() {
+            page.last().cloned()
+        } else {
+            None
+        };
+        Ok((page, next))
     }
 
     fn get_keys_for(&self, _address: &str, _prefix: Option<&[u8]>) -> Result<BTreeSet<Vec<u8>>> {
@@ -758,13 +1177,19 @@ impl Interface for TestInterface {
         todo!()
     }
 
-    fn raw_get_bytecode_for(&self, _address: &str) -> Result<Vec<u8>> {
-        todo!()
+    fn raw_get_bytecode_for(&self, address: &str) -> Result<Vec<u8>> {
+        BYTECODE_FETCHES.with(|fetches| fetches.set(fetches.get() + 1));
+        match CREATED_MODULES.lock().unwrap().get(address) {
+            Some(bytecode) => Ok(bytecode.clone()),
+            None => bail!("no smart contract at address {}", address),
+        }
     }
 
     fn caller_has_write_access(&self) -> Result<bool> {
         println!("caller_has_write_access");
-        Ok(true)
+        ACCESS_QUERIES.with(|queries| queries.set(queries.get() + 1));
+        // only the top-level caller has write access
+        Ok(PENDING_CALLS.with(|calls| calls.borrow().is_empty()))
     }
 
     fn evm_signature_verify(
@@ -777,7 +1202,8 @@ impl Interface for TestInterface {
             "verify_evm_signature: , _message: {:?}, _signature: {:?}, _public_key: {:?}",
             _message, _signature, _public_key
         );
-        Ok(true)
+        // only the all-zero signatures are forged
+        Ok(_signature.iter().any(|byte| *byte != 0))
     }
 
     fn evm_get_address_from_pubkey(&self, _public_key: &[u8]) -> Result<Vec<u8>> {
@@ -789,17 +1215,62 @@ impl Interface for TestInterface {
     }
 
     fn is_address_eoa(&self, _address: &str) -> Result<bool> {
+        ACCESS_QUERIES.with(|queries| queries.set(queries.get() + 1));
         Ok(true)
     }
 
-    fn validate_address(&self, _address: &str) -> Result<bool> {
-        println!("validate_address: {}", _address);
-        Ok(true)
+    fn validate_address(&self, address: &str) -> Result<bool> {
+        println!("validate_address: {}", address);
+        CONVERSION_QUERIES.with(|queries| queries.set(queries.get() + 1));
+        Ok(address != INVALID_ADDRESS)
+    }
+
+    fn deferred_call_register(
+        &self,
+        target_address: &str,
+        target_function: &str,
+        target_slot: (u64, u8),
+        max_gas: u64,
+        params: &[u8],
+        raw_coins: u64,
+    ) -> Result<String> {
+        let mut calls = DEFERRED_CALLS.lock().unwrap();
+        let id = format!("D{}", calls.len());
+        calls.insert(
+            id.clone(),
+            (
+                target_address.to_string(),
+                target_function.to_string(),
+                target_slot,
+                max_gas,
+                params.to_vec(),
+                raw_coins,
+            ),
+        );
+        Ok(id)
+    }
+
+    fn deferred_call_exists(&self, id: &str) -> Result<bool> {
+        Ok(DEFERRED_CALLS.lock().unwrap().contains_key(id))
+    }
+
+    fn deferred_call_cancel(&self, id: &str) -> Result<()> {
+        if id == FOREIGN_DEFERRED_CALL_ID {
+            bail!("not the owner of deferred call {}", id);
+        }
+        match DEFERRED_CALLS.lock().unwrap().remove(id) {
+            Some(_) => Ok(()),
+            None => bail!("deferred call {} not found", id),
+        }
+    }
+
+    fn deferred_call_quote(&self, target_slot: (u64, u8), max_gas: u64) -> Result<u64> {
+        Ok(max_gas + target_slot.0 * 1000 + target_slot.1 as u64)
     }
 
     fn get_origin_operation_id(&self) -> Result<Option<String>> {
         println!("get_origin_operation_id");
-        Ok(Some(String::new()))
+        Ok(ORIGIN_OPERATION_ID.with(|id| id.borrow().clone()))
     }
 
     fn chain_id(&self) -> Result<u64> {
@@ -811,7 +1282,8 @@ impl Interface for TestInterface {
     }
 }
 
-#[cfg(feature = "gas_calibration")]
+#[cfg(not(feature = "gas_calibration"))]
+pub mod tests_conformance;
 pub mod tests_gas_calibration;
 #[cfg(not(feature = "gas_calibration"))]
 pub mod tests_runtime;
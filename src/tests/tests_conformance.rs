@@ -0,0 +1,269 @@
+//! Conformance of the two ABI families.
+//!
+//! The same logical call, made by an AssemblyScript caller through
+//! `assembly_script_call` and by a wasmv1 caller through `abi_call`, must have
+//! the same effects on the interface and use the same gas in the callee.
+
+use super::tests_runtime::{wat_as_string, wat_bytes};
+use super::{InterfaceCall, TestInterface, CREATED_MODULES, INTERFACE_CALLS};
+use crate::{
+    run_function, Compiler, ExecutionEvent, ExecutionOptions, GasCosts, RecordingObserver,
+    RuntimeConfig, RuntimeModule,
+};
+use massa_proto_rs::massa::{abi::v1::CallRequest, model::v1::NativeAmount};
+use prost::Message;
+use serial_test::serial;
+use std::sync::Arc;
+
+const CALLEE_ADDRESS: &str = "conformance_callee";
+const GAS_LIMIT: u64 = 10_000_000;
+
+/// Coins sent with a call
+#[derive(Clone, Copy, Debug)]
+enum Coins {
+    /// Valid amount of nano-coins
+    Raw(u64),
+    /// Amount refused by both ABI families
+    Invalid,
+}
+
+/// Call of `function` of the callee with `coins`
+#[derive(Debug)]
+struct Scenario {
+    function: &'static str,
+    coins: Coins,
+}
+
+/// What a scenario did, compared between the ABI families
+#[derive(Debug, PartialEq, Eq)]
+struct Outcome {
+    interface_calls: Vec<InterfaceCall>,
+    /// Gas used by the callee, if it was started
+    callee_gas: Option<u64>,
+    success: bool,
+}
+
+/// AssemblyScript callee: `spend` loops and emits an event, `fail` emits an
+/// event and traps
+fn callee() -> Vec<u8> {
+    wasmer::wat2wasm(
+        format!(
+            r#"(module
+                (import "massa" "assembly_script_generate_event"
+                    (func $event (param i32)))
+                (memory (export "memory") 1)
+                {}
+                (func (export "__new") (param i32 i32) (result i32)
+                    (i32.store (i32.const 1020) (local.get 0))
+                    (i32.const 1024))
+                (func (export "spend") (local $i i32)
+                    (loop $next
+                        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                        (br_if $next (i32.lt_u (local.get $i) (i32.const 100))))
+                    (call $event (i32.const 32)))
+                (func (export "fail")
+                    (call $event (i32.const 32))
+                    unreachable))"#,
+            wat_as_string(32, "event"),
+        )
+        .as_bytes(),
+    )
+    .unwrap()
+    .to_vec()
+}
+
+/// AssemblyScript caller of the scenario, calling it from `call`
+fn as_caller(scenario: &Scenario) -> Vec<u8> {
+    let coins = match scenario.coins {
+        Coins::Raw(coins) => coins as i64,
+        Coins::Invalid => -1,
+    };
+    wasmer::wat2wasm(
+        format!(
+            r#"(module
+                (import "massa" "assembly_script_call"
+                    (func $call (param i32 i32 i32 i64) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 12) "\00\00\00\00")
+                {}
+                {}
+                (func (export "__new") (param i32 i32) (result i32)
+                    (i32.store (i32.const 1020) (local.get 0))
+                    (i32.const 1024))
+                (func (export "call")
+                    (drop (call $call (i32.const 64) (i32.const 160) (i32.const 16) (i64.const {})))))"#,
+            wat_as_string(64, CALLEE_ADDRESS),
+            wat_as_string(160, scenario.function),
+            coins,
+        )
+        .as_bytes(),
+    )
+    .unwrap()
+    .to_vec()
+}
+
+/// wasmv1 caller of the scenario, calling it from `call`
+fn wasmv1_caller(scenario: &Scenario) -> Vec<u8> {
+    let call_coins = match scenario.coins {
+        Coins::Raw(coins) if coins % 100_000_000 == 0 => NativeAmount {
+            mantissa: coins / 100_000_000,
+            scale: 1,
+        },
+        Coins::Raw(coins) => NativeAmount {
+            mantissa: coins,
+            scale: 9,
+        },
+        Coins::Invalid => NativeAmount {
+            mantissa: u64::MAX,
+            scale: 0,
+        },
+    };
+    let request = CallRequest {
+        target_sc_address: CALLEE_ADDRESS.to_string(),
+        target_function_name: scenario.function.to_string(),
+        function_arg: Vec::new(),
+        call_coins: Some(call_coins),
+    }
+    .encode_to_vec();
+    let mut bytecode = vec![1];
+    bytecode.extend(
+        wasmer::wat2wasm(
+            format!(
+                r#"(module
+                    (import "massa" "abi_call" (func $call (param i32) (result i32)))
+                    (memory (export "memory") 1)
+                    (data (i32.const 8) "\00\00\00\00")
+                    (data (i32.const 64) "{}{}")
+                    (func (export "__alloc") (param i32) (result i32)
+                        (i32.const 4096))
+                    (func (export "call") (param i32) (result i32)
+                        (drop (call $call (i32.const 64)))
+                        (i32.const 8)))"#,
+                wat_bytes(&(request.len() as u32).to_le_bytes()),
+                wat_bytes(&request),
+            )
+            .as_bytes(),
+        )
+        .unwrap()
+        .iter(),
+    );
+    bytecode
+}
+
+/// Run `call` of `caller`, recording the interface calls and the gas used by
+/// the callee
+fn run(caller: &[u8]) -> Outcome {
+    INTERFACE_CALLS.with(|calls| calls.borrow_mut().clear());
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    let runtime_module =
+        RuntimeModule::new(caller, gas_costs.clone(), &config, Compiler::SP).unwrap();
+    let observer = Arc::new(RecordingObserver::new());
+    let result = run_function(
+        &TestInterface,
+        runtime_module,
+        "call",
+        b"",
+        GAS_LIMIT,
+        gas_costs,
+        &config,
+        ExecutionOptions {
+            observer: Some(observer.clone()),
+            ..Default::default()
+        },
+    );
+
+    let mut callee_gas_limit = None;
+    let mut callee_gas = None;
+    for (depth, event) in observer.events() {
+        match event {
+            ExecutionEvent::ExecutionStarted { gas_limit, .. } if depth == 1 => {
+                callee_gas_limit = Some(gas_limit)
+            }
+            ExecutionEvent::ExecutionFinished { remaining_gas, .. } if depth == 1 => {
+                callee_gas = callee_gas_limit.map(|limit| limit - remaining_gas)
+            }
+            _ => (),
+        }
+    }
+    Outcome {
+        interface_calls: INTERFACE_CALLS.with(|calls| calls.take()),
+        callee_gas,
+        success: result.is_ok(),
+    }
+}
+
+#[test]
+#[serial]
+fn test_call_conformance() {
+    CREATED_MODULES
+        .lock()
+        .unwrap()
+        .insert(CALLEE_ADDRESS.to_string(), callee());
+    let init_call = |raw_coins| InterfaceCall::InitCall {
+        address: CALLEE_ADDRESS.to_string(),
+        raw_coins,
+    };
+
+    for (scenario, interface_calls, success) in [
+        (
+            Scenario {
+                function: "spend",
+                coins: Coins::Raw(0),
+            },
+            vec![init_call(0), InterfaceCall::FinishCall],
+            true,
+        ),
+        (
+            Scenario {
+                function: "spend",
+                coins: Coins::Raw(1_500_000_000),
+            },
+            vec![init_call(1_500_000_000), InterfaceCall::FinishCall],
+            true,
+        ),
+        (
+            Scenario {
+                function: "spend",
+                coins: Coins::Raw(7),
+            },
+            vec![init_call(7), InterfaceCall::FinishCall],
+            true,
+        ),
+        // a failed callee is never finished
+        (
+            Scenario {
+                function: "fail",
+                coins: Coins::Raw(0),
+            },
+            vec![init_call(0)],
+            false,
+        ),
+        // invalid coins are refused before the interface is told about the
+        // call
+        (
+            Scenario {
+                function: "spend",
+                coins: Coins::Invalid,
+            },
+            vec![],
+            false,
+        ),
+    ] {
+        let as_outcome = run(&as_caller(&scenario));
+        let wasmv1_outcome = run(&wasmv1_caller(&scenario));
+        assert_eq!(as_outcome, wasmv1_outcome, "{:?}", scenario);
+        assert_eq!(
+            as_outcome.interface_calls, interface_calls,
+            "{:?}",
+            scenario
+        );
+        assert_eq!(as_outcome.success, success, "{:?}", scenario);
+        assert_eq!(
+            as_outcome.callee_gas.is_some(),
+            !interface_calls.is_empty(),
+            "{:?}",
+            scenario
+        );
+    }
+}
@@ -3,14 +3,25 @@ use crate::middlewares::operator::{OPERATOR_CARDINALITY, OPERATOR_VARIANTS};
 use crate::middlewares::operator::{
     _OPERATOR_BULK_MEMORY, _OPERATOR_NON_TRAPPING_FLOAT_TO_INT, _OPERATOR_THREAD, _OPERATOR_VECTOR,
 };
-use crate::tests::TestInterface;
-use crate::{run_main_gc, types::Interface, GasCosts, RuntimeModule};
+use crate::tests::{TestInterface, CALIBRATED_MODULES, CREATED_MODULES};
+use crate::{
+    run_main, run_main_gas_calibration, run_main_gc, types::Interface, ExecutionOptions, GasCosts,
+    RuntimeConfig, RuntimeModule, VMError,
+};
 use std::collections::HashSet;
 
 use anyhow::Result;
 use more_asserts as ma;
 use serial_test::serial;
 
+/// Default configuration in calibration mode, whatever the features
+fn calibration_config() -> RuntimeConfig {
+    RuntimeConfig {
+        gas_calibration: true,
+        ..RuntimeConfig::default()
+    }
+}
+
 #[test]
 #[serial]
 fn test_basic_abi_call_counter() -> Result<()> {
@@ -21,9 +32,16 @@ fn test_basic_abi_call_counter() -> Result<()> {
     ));
 
     let gas_costs = GasCosts::default();
-    let runtime_module = RuntimeModule::new(bytecode, gas_costs.clone(), Compiler::SP)?;
-    let gas_calibration_result =
-        run_main_gc(&interface, runtime_module, b"", 100_000, gas_costs.clone())?;
+    let config = calibration_config();
+    let runtime_module = RuntimeModule::new(bytecode, gas_costs.clone(), &config, Compiler::SP)?;
+    let gas_calibration_result = run_main_gc(
+        &interface,
+        runtime_module,
+        b"",
+        100_000,
+        gas_costs.clone(),
+        &config,
+    )?;
     // println!("gas_calibration_result: {:?}", gas_calibration_result);
 
     // Note:
@@ -82,11 +100,13 @@ fn test_basic_abi_call_counter_wasmv1() -> Result<()> {
     ));
 
     let gas_costs = GasCosts::default();
+    let config = calibration_config();
     // let runtime_module =
     //     RuntimeModule::new(bytecode, 100_000, gas_costs.clone(),
     // Compiler::SP)?;
 
-    let runtime_module = RuntimeModule::new(module, gas_costs.clone(), Compiler::SP).unwrap();
+    let runtime_module =
+        RuntimeModule::new(module, gas_costs.clone(), &config, Compiler::SP).unwrap();
 
     match runtime_module.clone() {
         RuntimeModule::ASModule(_) => {
@@ -97,8 +117,14 @@ fn test_basic_abi_call_counter_wasmv1() -> Result<()> {
         }
     }
 
-    let gas_calibration_result =
-        run_main_gc(&*interface, runtime_module, b"", 100_000, gas_costs.clone())?;
+    let gas_calibration_result = run_main_gc(
+        &*interface,
+        runtime_module,
+        b"",
+        100_000,
+        gas_costs.clone(),
+        &config,
+    )?;
     // println!("gas_calibration_result: {:?}", gas_calibration_result);
 
     // Note:
@@ -150,6 +176,95 @@ fn test_basic_abi_call_counter_wasmv1() -> Result<()> {
     Ok(())
 }
 
+#[test]
+#[serial]
+fn test_calibration_and_metering_in_one_process() -> Result<()> {
+    let interface = TestInterface;
+    let bytecode = include_bytes!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/wasm/gc_abi_call_basic.wasm"
+    ));
+    let gas_costs = GasCosts::default();
+    let calibration = calibration_config();
+    let metering = RuntimeConfig {
+        gas_calibration: false,
+        ..RuntimeConfig::default()
+    };
+    let limit = 100_000_000;
+    let calibrated = RuntimeModule::new(bytecode, gas_costs.clone(), &calibration, Compiler::SP)?;
+    let metered = RuntimeModule::new(bytecode, gas_costs.clone(), &metering, Compiler::SP)?;
+    assert!(calibrated.gas_calibration());
+    assert!(!metered.gas_calibration());
+
+    // both modes alternate in the same process
+    for _ in 0..2 {
+        let result = run_main_gc(
+            &interface,
+            calibrated.clone(),
+            b"",
+            limit,
+            gas_costs.clone(),
+            &calibration,
+        )?;
+        assert_eq!(
+            result.counters.get("Abi:call:massa.assembly_script_print"),
+            Some(&2)
+        );
+        let response = run_main(
+            &interface,
+            metered.clone(),
+            limit,
+            gas_costs.clone(),
+            &metering,
+            ExecutionOptions::default(),
+        )?;
+        assert!(response.remaining_gas < limit);
+    }
+
+    // a module only runs in the mode it was compiled with
+    for (module, config) in [(calibrated, metering), (metered, calibration)] {
+        let err = run_main(
+            &interface,
+            module,
+            limit,
+            gas_costs.clone(),
+            &config,
+            ExecutionOptions::default(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, VMError::InstanceError(_)), "{}", err);
+    }
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_run_main_gc_without_calibration() -> Result<()> {
+    let interface = TestInterface;
+    let bytecode = include_bytes!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/wasm/gc_abi_call_basic.wasm"
+    ));
+    let gas_costs = GasCosts::default();
+    let metering = RuntimeConfig {
+        gas_calibration: false,
+        ..RuntimeConfig::default()
+    };
+    let runtime_module = RuntimeModule::new(bytecode, gas_costs.clone(), &metering, Compiler::SP)?;
+    // an error rather than a panic of the host
+    let err = run_main_gc(
+        &interface,
+        runtime_module,
+        b"",
+        100_000,
+        gas_costs,
+        &metering,
+    )
+    .unwrap_err();
+    assert!(matches!(err, VMError::InstanceError(_)), "{}", err);
+    Ok(())
+}
+
 #[test]
 #[serial]
 fn test_basic_abi_call_loop() -> Result<()> {
@@ -160,9 +275,16 @@ fn test_basic_abi_call_loop() -> Result<()> {
     ));
 
     let gas_costs = GasCosts::default();
-    let runtime_module = RuntimeModule::new(bytecode, gas_costs.clone(), Compiler::SP)?;
-    let gas_calibration_result =
-        run_main_gc(&interface, runtime_module, b"", 100_000, gas_costs.clone())?;
+    let config = calibration_config();
+    let runtime_module = RuntimeModule::new(bytecode, gas_costs.clone(), &config, Compiler::SP)?;
+    let gas_calibration_result = run_main_gc(
+        &interface,
+        runtime_module,
+        b"",
+        100_000,
+        gas_costs.clone(),
+        &config,
+    )?;
     assert_eq!(
         gas_calibration_result.counters.len(),
         2 + 5 + OPERATOR_CARDINALITY
@@ -191,9 +313,16 @@ fn test_basic_abi_call_loop_wasmv1() -> Result<()> {
     ));
 
     let gas_costs = GasCosts::default();
-    let runtime_module = RuntimeModule::new(bytecode, gas_costs.clone(), Compiler::SP)?;
-    let gas_calibration_result =
-        run_main_gc(&interface, runtime_module, b"", 100_000, gas_costs.clone())?;
+    let config = calibration_config();
+    let runtime_module = RuntimeModule::new(bytecode, gas_costs.clone(), &config, Compiler::SP)?;
+    let gas_calibration_result = run_main_gc(
+        &interface,
+        runtime_module,
+        b"",
+        100_000,
+        gas_costs.clone(),
+        &config,
+    )?;
     assert_eq!(
         gas_calibration_result.counters.len(),
         2 + 2 + OPERATOR_CARDINALITY
@@ -224,9 +353,16 @@ fn test_basic_op() -> Result<()> {
     ));
 
     let gas_costs = GasCosts::default();
-    let runtime_module = RuntimeModule::new(bytecode, gas_costs.clone(), Compiler::SP)?;
-    let gas_calibration_result =
-        run_main_gc(&interface, runtime_module, b"", 100_000, gas_costs.clone())?;
+    let config = calibration_config();
+    let runtime_module = RuntimeModule::new(bytecode, gas_costs.clone(), &config, Compiler::SP)?;
+    let gas_calibration_result = run_main_gc(
+        &interface,
+        runtime_module,
+        b"",
+        100_000,
+        gas_costs.clone(),
+        &config,
+    )?;
     // 1 for env.abort + 4 env.abort parameters
     assert_eq!(
         gas_calibration_result.counters.len(),
@@ -276,9 +412,16 @@ fn test_basic_op_wasmv1() -> Result<()> {
     ));
 
     let gas_costs = GasCosts::default();
-    let runtime_module = RuntimeModule::new(bytecode, gas_costs.clone(), Compiler::SP)?;
-    let gas_calibration_result =
-        run_main_gc(&interface, runtime_module, b"", 100_000, gas_costs.clone())?;
+    let config = calibration_config();
+    let runtime_module = RuntimeModule::new(bytecode, gas_costs.clone(), &config, Compiler::SP)?;
+    let gas_calibration_result = run_main_gc(
+        &interface,
+        runtime_module,
+        b"",
+        100_000,
+        gas_costs.clone(),
+        &config,
+    )?;
     // 1 for env.abort + 1 env.abort parameters
     assert_eq!(
         gas_calibration_result.counters.len(),
@@ -334,13 +477,15 @@ fn test_basic_abi_call_param_size() -> Result<()> {
     ));
 
     let gas_costs = GasCosts::default();
-    let runtime_module = RuntimeModule::new(bytecode, gas_costs.clone(), Compiler::SP)?;
+    let config = calibration_config();
+    let runtime_module = RuntimeModule::new(bytecode, gas_costs.clone(), &config, Compiler::SP)?;
     let gas_calibration_result = run_main_gc(
         &interface,
         runtime_module,
         b"9876543",
         100_000,
         gas_costs.clone(),
+        &config,
     )?;
     // println!("gas_calibration_result: {:?}", gas_calibration_result);
 
@@ -400,6 +545,148 @@ fn test_basic_abi_call_param_size() -> Result<()> {
     Ok(())
 }
 
+#[test]
+#[serial]
+fn test_calibration_report() -> Result<()> {
+    let interface = TestInterface;
+    let bytecode = include_bytes!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/wasm/gc_abi_call_basic.wasm"
+    ));
+
+    let gas_costs = GasCosts::default();
+    let config = calibration_config();
+    let runtime_module = RuntimeModule::new(bytecode, gas_costs.clone(), &config, Compiler::SP)?;
+    let report = run_main_gas_calibration(
+        &interface,
+        runtime_module,
+        b"",
+        100_000,
+        gas_costs.clone(),
+        &config,
+    )?;
+    assert_eq!(report.depths, vec![report.total.clone()]);
+    assert_eq!(report.total.executions, 1);
+    assert_eq!(report.total.calls("massa.assembly_script_print"), 2);
+    assert_eq!(report.total.calls("env.abort"), 0);
+    // not imported by the module
+    assert_eq!(report.total.calls("massa.assembly_script_call"), 0);
+    assert_eq!(report.total.operators.len(), OPERATOR_CARDINALITY);
+    ma::assert_gt!(report.total.operator_executions("I32Const"), 0);
+    ma::assert_gt!(report.wall_time, std::time::Duration::ZERO);
+
+    #[cfg(feature = "json")]
+    {
+        let json = serde_json::to_string(&report)?;
+        assert_eq!(
+            serde_json::from_str::<crate::GasCalibrationReport>(&json)?,
+            report
+        );
+    }
+
+    // the module is not instrumented without the calibration mode
+    let metering = RuntimeConfig {
+        gas_calibration: false,
+        ..RuntimeConfig::default()
+    };
+    let runtime_module = RuntimeModule::new(bytecode, gas_costs.clone(), &metering, Compiler::SP)?;
+    assert!(run_main_gas_calibration(
+        &interface,
+        runtime_module,
+        b"",
+        100_000,
+        gas_costs,
+        &metering
+    )
+    .is_err());
+
+    Ok(())
+}
+
+/// Data segment of the AssemblyScript string `string` at `ptr`
+fn wat_ascii_string(ptr: u32, string: &str) -> String {
+    let utf16: String = string.chars().map(|c| format!("{}\\00", c)).collect();
+    format!(
+        r#"(data (i32.const {}) "\{:02x}\00\00\00{}")"#,
+        ptr - 4,
+        2 * string.len(),
+        utf16
+    )
+}
+
+#[test]
+#[serial]
+fn test_calibration_report_nested_calls() -> Result<()> {
+    let interface = TestInterface;
+    let callee = wasmer::wat2wasm(
+        format!(
+            r#"(module
+                (import "massa" "assembly_script_print" (func $print (param i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 12) "\00\00\00\00")
+                {}
+                (func (export "run") (result i32)
+                    (call $print (i32.const 32))
+                    (i32.const 16)))"#,
+            wat_ascii_string(32, "hi")
+        )
+        .as_bytes(),
+    )?;
+    CREATED_MODULES
+        .lock()
+        .unwrap()
+        .insert("calibrated".to_string(), callee.to_vec());
+    let caller = wasmer::wat2wasm(
+        format!(
+            r#"(module
+                (import "massa" "assembly_script_call"
+                    (func $call (param i32 i32 i32 i64) (result i32)))
+                (import "massa" "assembly_script_print" (func $print (param i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 12) "\00\00\00\00")
+                {}
+                {}
+                (func (export "__new") (param i32 i32) (result i32)
+                    (i32.store (i32.const 1020) (local.get 0))
+                    (i32.const 1024))
+                (func (export "main")
+                    (drop (call $call (i32.const 64) (i32.const 160) (i32.const 16) (i64.const 0)))
+                    (drop (call $call (i32.const 64) (i32.const 160) (i32.const 16) (i64.const 0)))))"#,
+            wat_ascii_string(64, "calibrated"),
+            wat_ascii_string(160, "run"),
+        )
+        .as_bytes(),
+    )?;
+
+    let gas_costs = GasCosts::default();
+    let config = calibration_config();
+    let runtime_module = RuntimeModule::new(&caller, gas_costs.clone(), &config, Compiler::SP)?;
+    CALIBRATED_MODULES.with(|calibrated| calibrated.set(true));
+    let report =
+        run_main_gas_calibration(&interface, runtime_module, b"", 100_000, gas_costs, &config);
+    CALIBRATED_MODULES.with(|calibrated| calibrated.set(false));
+    let report = report?;
+
+    assert_eq!(report.depths.len(), 2);
+    let (caller, callee) = (&report.depths[0], &report.depths[1]);
+    assert_eq!(caller.executions, 1);
+    assert_eq!(caller.calls("massa.assembly_script_call"), 2);
+    assert_eq!(caller.calls("massa.assembly_script_print"), 0);
+    assert_eq!(callee.executions, 2);
+    assert_eq!(callee.calls("massa.assembly_script_call"), 0);
+    assert_eq!(callee.calls("massa.assembly_script_print"), 2);
+    assert_eq!(report.total.executions, 3);
+    assert_eq!(report.total.calls("massa.assembly_script_call"), 2);
+    assert_eq!(report.total.calls("massa.assembly_script_print"), 2);
+    assert_eq!(
+        report.total.operator_executions("I32Const"),
+        caller.operator_executions("I32Const") + callee.operator_executions("I32Const")
+    );
+    ma::assert_gt!(callee.operator_executions("I32Const"), 0);
+
+    Ok(())
+}
+
 #[test]
 fn test_operators_definition() {
     // Check that OPERATOR_* are ~ "valid"
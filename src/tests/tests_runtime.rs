@@ -1,15 +1,23 @@
-use crate::as_execution::{ASContext, ASModule};
-use crate::tests::TestInterface;
-use crate::Compiler;
+use crate::as_execution::{ABIError, ASContext, ASModule};
+use crate::tests::{
+    TestInterface, ACCESS_QUERIES, BYTECODE_FETCHES, CREATED_MODULES, DATASTORE_KEYS,
+    DATASTORE_VALUE, DEFERRED_CALLS, FAILING_FINISH_CALL_ADDRESS, FOREIGN_DEFERRED_CALL_ID,
+    FULL_BALANCE_ADDRESS, GENESIS_TIMESTAMP, INDEXED_EVENTS, INVALID_ADDRESS, MISPREDICTED_SEED,
+    MODULE_CACHE, OP_KEYS, ORIGIN_OPERATION_ID, PERIOD_DURATION, REQUESTED_COMPILERS,
+    STORAGE_BYTE_COST, TEST_BALANCE, UNKNOWN_BALANCE_ADDRESS,
+};
 use crate::{
-    run_function, run_main,
-    types::{GasCosts, Interface},
-    RuntimeModule,
+    run_function, run_function_typed, run_main, run_main_with_param,
+    types::{AddressValidator, FloatMode, GasCosts, Interface, TransferError},
+    AbortInfo, ExecutionOptions, ModuleCache, RuntimeConfig, RuntimeModule, TypedCallError,
+    VMError, VMErrorCode,
 };
+use crate::{Compiler, CondomLimits, ExecutionMode, MemoryLimitMode};
 use rand::Rng;
 use serial_test::serial;
 use wasmer::Store;
 use wasmer::WasmPtr;
+use wasmer::{imports, Instance};
 
 #[cfg(feature = "execution-trace")]
 use crate::{AbiTrace, AbiTraceType, AbiTraceValue};
@@ -25,9 +33,19 @@ fn test_exhaustive_smart_contract() {
         "/wasm/test_exhaustive_smart_contract.wasm_add"
     ));
     let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
 
-    let runtime_module = RuntimeModule::new(module, gas_costs.clone(), Compiler::SP).unwrap();
-    run_main(&interface, runtime_module, 100_000_000, gas_costs).unwrap();
+    let runtime_module =
+        RuntimeModule::new(module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+    run_main(
+        &interface,
+        runtime_module,
+        100_000_000,
+        gas_costs,
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap();
 }
 
 #[test]
@@ -40,9 +58,19 @@ fn test_native_time_arithmetic_abis() {
         "/wasm/test_native_time_arithmetic.wasm_add"
     ));
     let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
 
-    let runtime_module = RuntimeModule::new(module, gas_costs.clone(), Compiler::SP).unwrap();
-    run_main(&interface, runtime_module, 100_000_000, gas_costs).unwrap();
+    let runtime_module =
+        RuntimeModule::new(module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+    run_main(
+        &interface,
+        runtime_module,
+        100_000_000,
+        gas_costs,
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap();
 }
 
 #[test]
@@ -55,9 +83,19 @@ fn test_structs_check_and_version_abis() {
         "/wasm/test_structs_check_and_version.wasm_add"
     ));
     let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
 
-    let runtime_module = RuntimeModule::new(module, gas_costs.clone(), Compiler::SP).unwrap();
-    run_main(&interface, runtime_module, 100_000_000, gas_costs).unwrap();
+    let runtime_module =
+        RuntimeModule::new(module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+    run_main(
+        &interface,
+        runtime_module,
+        100_000_000,
+        gas_costs,
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap();
 }
 
 #[test]
@@ -70,9 +108,19 @@ fn test_datastore_abis() {
         "/wasm/test_datastore.wasm_add"
     ));
     let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
 
-    let runtime_module = RuntimeModule::new(module, gas_costs.clone(), Compiler::SP).unwrap();
-    run_main(&interface, runtime_module, 100_000_000, gas_costs).unwrap();
+    let runtime_module =
+        RuntimeModule::new(module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+    run_main(
+        &interface,
+        runtime_module,
+        100_000_000,
+        gas_costs,
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap();
 }
 
 #[test]
@@ -85,9 +133,19 @@ fn test_ledger_op_keys_abis() {
         "/wasm/test_ledger_op_keys.wasm_add"
     ));
     let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
 
-    let runtime_module = RuntimeModule::new(module, gas_costs.clone(), Compiler::SP).unwrap();
-    run_main(&interface, runtime_module, 100_000_000, gas_costs).unwrap();
+    let runtime_module =
+        RuntimeModule::new(module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+    run_main(
+        &interface,
+        runtime_module,
+        100_000_000,
+        gas_costs,
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap();
 }
 
 #[test]
@@ -101,8 +159,18 @@ fn test_metering_safety() {
     ));
 
     let gas_costs = GasCosts::default();
-    let runtime_module = RuntimeModule::new(bytecode, gas_costs.clone(), Compiler::SP).unwrap();
-    let resp = run_main(&interface, runtime_module, 100_000, gas_costs.clone()).unwrap();
+    let config = RuntimeConfig::default();
+    let runtime_module =
+        RuntimeModule::new(bytecode, gas_costs.clone(), &config, Compiler::SP).unwrap();
+    let resp = run_main(
+        &interface,
+        runtime_module,
+        100_000,
+        gas_costs.clone(),
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap();
     assert_ne!(resp.remaining_gas, 42);
 }
 
@@ -117,8 +185,18 @@ fn test_instantiation_safety() {
     ));
 
     let gas_costs = GasCosts::default();
-    let runtime_module = RuntimeModule::new(bytecode, gas_costs.clone(), Compiler::SP).unwrap();
-    let error = run_main(&interface, runtime_module, 100_000, gas_costs.clone()).unwrap_err();
+    let config = RuntimeConfig::default();
+    let runtime_module =
+        RuntimeModule::new(bytecode, gas_costs.clone(), &config, Compiler::SP).unwrap();
+    let error = run_main(
+        &interface,
+        runtime_module,
+        100_000,
+        gas_costs.clone(),
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap_err();
     let expected_error = "ABI calls are not available during instantiation";
     assert!(error.to_string().contains(expected_error));
 }
@@ -128,11 +206,132 @@ fn test_instantiation_safety() {
 /// Test basic main-only SC execution
 fn test_run_main() {
     let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
     let interface: Box<dyn Interface> = Box::new(TestInterface);
     let module = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/wasm/basic_main.wasm"));
 
-    let runtime_module = RuntimeModule::new(module, gas_costs.clone(), Compiler::SP).unwrap();
-    run_main(&*interface, runtime_module, 100_000, gas_costs).unwrap();
+    let runtime_module =
+        RuntimeModule::new(module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+    run_main(
+        &*interface,
+        runtime_module,
+        100_000,
+        gas_costs,
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap();
+}
+
+#[test]
+#[serial]
+/// Test that the parameter of `run_main_with_param` reaches `main` and that
+/// the bytes it returns are in the response
+fn test_run_main_with_param() {
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    let interface = TestInterface;
+    // constructors echoing their parameter
+    let as_module = wasmer::wat2wasm(
+        br#"(module
+            (memory (export "memory") 1)
+            (data (i32.const 12) "\00\00\00\00")
+            (func (export "__new") (param i32 i32) (result i32)
+                (i32.store (i32.const 1020) (local.get 0))
+                (i32.const 1024))
+            (func (export "main") (param i32) (result i32) (local.get 0)))"#,
+    )
+    .unwrap();
+    let mut wasmv1_module = vec![1];
+    wasmv1_module.extend(
+        wasmer::wat2wasm(
+            br#"(module
+                (memory (export "memory") 1)
+                (func (export "__alloc") (param i32) (result i32)
+                    (i32.store (i32.const 1020) (local.get 0))
+                    (i32.const 1024))
+                (func (export "main") (param i32) (result i32)
+                    (i32.sub (local.get 0) (i32.const 4))))"#,
+        )
+        .unwrap()
+        .iter(),
+    );
+    let run = |bytecode: &[u8], param: &[u8]| {
+        let runtime_module =
+            RuntimeModule::new(bytecode, gas_costs.clone(), &config, Compiler::SP).unwrap();
+        run_main_with_param(
+            &interface,
+            runtime_module,
+            param,
+            100_000,
+            gas_costs.clone(),
+            &config,
+            ExecutionOptions::default(),
+        )
+        .unwrap()
+    };
+
+    assert_eq!(
+        run(&as_module, b"constructor args").ret,
+        b"constructor args"
+    );
+    assert_eq!(
+        run(&wasmv1_module, b"constructor args").ret,
+        b"constructor args"
+    );
+
+    // without a parameter, the return value of an AssemblyScript main is ignored
+    let runtime_module =
+        RuntimeModule::new(&as_module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+    let legacy = run_main(
+        &interface,
+        runtime_module,
+        100_000,
+        gas_costs.clone(),
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap();
+    let empty = run(&as_module, b"");
+    assert!(legacy.ret.is_empty());
+    assert!(empty.ret.is_empty());
+    assert_eq!(legacy.remaining_gas, empty.remaining_gas);
+    assert_eq!(legacy.init_gas_cost, empty.init_gas_cost);
+}
+
+#[test]
+#[serial]
+/// Test that exhausting the gas returns a typed out of gas error
+fn test_out_of_gas_error() {
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    let interface: Box<dyn Interface> = Box::new(TestInterface);
+    let bytecode = include_bytes!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/wasm/infinite_loop.wat"
+    ));
+
+    let module = ASModule::new(
+        bytecode,
+        gas_costs.max_instance_cost,
+        gas_costs.clone(),
+        &config,
+        Compiler::SP,
+    )
+    .unwrap();
+    let error = run_main(
+        &*interface,
+        RuntimeModule::ASModule(module),
+        100_000,
+        gas_costs,
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap_err();
+    match error {
+        VMError::OutOfGas { at_function, .. } => assert_eq!(at_function, "main"),
+        _ => panic!("expected an out of gas error, got: {}", error),
+    }
 }
 
 #[cfg(feature = "execution-trace")]
@@ -141,11 +340,21 @@ fn test_run_main() {
 /// Test basic main-only SC execution
 fn test_run_main_get_execution_traces() {
     let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
     let interface: Box<dyn Interface> = Box::new(TestInterface);
     let module = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/wasm/basic_main.wasm"));
 
-    let runtime_module = RuntimeModule::new(module, gas_costs.clone(), Compiler::SP).unwrap();
-    let resp = run_main(&*interface, runtime_module, 100_000, gas_costs).unwrap();
+    let runtime_module =
+        RuntimeModule::new(module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+    let resp = run_main(
+        &*interface,
+        runtime_module,
+        100_000,
+        gas_costs,
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap();
 
     assert_eq!(resp.trace.is_empty(), false);
     assert_eq!(
@@ -183,14 +392,7 @@ fn test_run_register_wasmv1() {
     //     }
     // }
 
-    // let res = run_function(
-    //     &*interface,
-    //     runtime_module,
-    //     "initialize",
-    //     b"",
-    //     100_000,
-    //     gas_costs,
-    // )
+    // let res = run_function(//     &*interface, //     runtime_module, //     "initialize", //     b"", //     100_000, //     gas_costs, //, ExecutionOptions::default())
     // .unwrap();
     // println!("{:?}", res);
 }
@@ -218,7 +420,7 @@ fn test_run_register_wasmv1() {
 //             println!("Module type WasmV1Module");
 //         }
 //     }
-//     run_main(&*interface, runtime_module, 100_000_000, gas_costs).unwrap();
+//     run_main(&*interface, runtime_module, 100_000_000, gas_costs, ExecutionOptions::default()).unwrap();
 // }
 
 #[test]
@@ -226,13 +428,15 @@ fn test_run_register_wasmv1() {
 /// Test test_get_current_period_and_thread
 fn test_get_current_period_and_thread_wasmv1_as() {
     let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
     let interface: Box<dyn Interface> = Box::new(TestInterface);
     let module = include_bytes!(concat!(
         env!("CARGO_MANIFEST_DIR"),
         "/wasm/test_period_thread.wasm_add"
     ));
 
-    let runtime_module = RuntimeModule::new(module, gas_costs.clone(), Compiler::SP).unwrap();
+    let runtime_module =
+        RuntimeModule::new(module, gas_costs.clone(), &config, Compiler::SP).unwrap();
 
     match runtime_module.clone() {
         RuntimeModule::ASModule(_) => {
@@ -242,7 +446,15 @@ fn test_get_current_period_and_thread_wasmv1_as() {
             println!("Module type WasmV1Module");
         }
     }
-    run_main(&*interface, runtime_module, 100_000_000, gas_costs).unwrap();
+    run_main(
+        &*interface,
+        runtime_module,
+        100_000_000,
+        gas_costs,
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap();
 }
 
 #[test]
@@ -250,13 +462,15 @@ fn test_get_current_period_and_thread_wasmv1_as() {
 /// Test test_native_hash
 fn test_native_hash_wasmv1_as() {
     let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
     let interface: Box<dyn Interface> = Box::new(TestInterface);
     let module = include_bytes!(concat!(
         env!("CARGO_MANIFEST_DIR"),
         "/wasm/test_hash.wasm_add"
     ));
 
-    let runtime_module = RuntimeModule::new(module, gas_costs.clone(), Compiler::SP).unwrap();
+    let runtime_module =
+        RuntimeModule::new(module, gas_costs.clone(), &config, Compiler::SP).unwrap();
 
     match runtime_module.clone() {
         RuntimeModule::ASModule(_) => {
@@ -266,7 +480,15 @@ fn test_native_hash_wasmv1_as() {
             println!("Module type WasmV1Module");
         }
     }
-    run_main(&*interface, runtime_module, 100_000_000, gas_costs).unwrap();
+    run_main(
+        &*interface,
+        runtime_module,
+        100_000_000,
+        gas_costs,
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap();
 }
 
 #[test]
@@ -274,13 +496,15 @@ fn test_native_hash_wasmv1_as() {
 /// This test call the main function of a SC that calls generate_event abi
 fn test_generate_event_wasmv1_as() {
     let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
     let interface: Box<dyn Interface> = Box::new(TestInterface);
     let module = include_bytes!(concat!(
         env!("CARGO_MANIFEST_DIR"),
         "/wasm/test_generate_event.wasm_add"
     ));
 
-    let runtime_module = RuntimeModule::new(module, gas_costs.clone(), Compiler::SP).unwrap();
+    let runtime_module =
+        RuntimeModule::new(module, gas_costs.clone(), &config, Compiler::SP).unwrap();
 
     match runtime_module.clone() {
         RuntimeModule::ASModule(_) => {
@@ -290,7 +514,15 @@ fn test_generate_event_wasmv1_as() {
             println!("Module type WasmV1Module");
         }
     }
-    run_main(&*interface, runtime_module, 100_000_000, gas_costs).unwrap();
+    run_main(
+        &*interface,
+        runtime_module,
+        100_000_000,
+        gas_costs,
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap();
 }
 
 #[test]
@@ -298,13 +530,15 @@ fn test_generate_event_wasmv1_as() {
 /// This test arithmetic operations on native amount
 fn test_native_amount_arithmetic_wasmv1_as() {
     let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
     let interface: Box<dyn Interface> = Box::new(TestInterface);
     let module = include_bytes!(concat!(
         env!("CARGO_MANIFEST_DIR"),
         "/wasm/test_native_amount_arithmetic.wasm_add"
     ));
 
-    let runtime_module = RuntimeModule::new(module, gas_costs.clone(), Compiler::SP).unwrap();
+    let runtime_module =
+        RuntimeModule::new(module, gas_costs.clone(), &config, Compiler::SP).unwrap();
 
     match runtime_module.clone() {
         RuntimeModule::ASModule(_) => {
@@ -314,7 +548,15 @@ fn test_native_amount_arithmetic_wasmv1_as() {
             println!("Module type WasmV1Module");
         }
     }
-    run_main(&*interface, runtime_module, 100_000_000, gas_costs).unwrap();
+    run_main(
+        &*interface,
+        runtime_module,
+        100_000_000,
+        gas_costs,
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap();
 }
 
 #[test]
@@ -322,13 +564,15 @@ fn test_native_amount_arithmetic_wasmv1_as() {
 /// This test call the main function of a SC that will abort
 fn test_abort_wasmv1_as() {
     let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
     let interface: Box<dyn Interface> = Box::new(TestInterface);
     let module = include_bytes!(concat!(
         env!("CARGO_MANIFEST_DIR"),
         "/wasm/test_abort.wasm_add"
     ));
 
-    let runtime_module = RuntimeModule::new(module, gas_costs.clone(), Compiler::SP).unwrap();
+    let runtime_module =
+        RuntimeModule::new(module, gas_costs.clone(), &config, Compiler::SP).unwrap();
 
     match runtime_module.clone() {
         RuntimeModule::ASModule(_) => {
@@ -339,7 +583,14 @@ fn test_abort_wasmv1_as() {
         }
     }
 
-    let res = run_main(&*interface, runtime_module, 100_000, gas_costs);
+    let res = run_main(
+        &*interface,
+        runtime_module,
+        100_000,
+        gas_costs,
+        &config,
+        ExecutionOptions::default(),
+    );
 
     match res {
         Err(e) if e.to_string().contains("abort test message") => {
@@ -361,13 +612,15 @@ fn test_abort_wasmv1_as() {
 /// This test call the main function of a SC that will abort
 fn test_assert_in_release_wasmv1_as() {
     let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
     let interface: Box<dyn Interface> = Box::new(TestInterface);
     let module = include_bytes!(concat!(
         env!("CARGO_MANIFEST_DIR"),
         "/wasm/test_assert_in_release.wasm_add"
     ));
 
-    let runtime_module = RuntimeModule::new(module, gas_costs.clone(), Compiler::SP).unwrap();
+    let runtime_module =
+        RuntimeModule::new(module, gas_costs.clone(), &config, Compiler::SP).unwrap();
 
     match runtime_module.clone() {
         RuntimeModule::ASModule(_) => {
@@ -378,7 +631,14 @@ fn test_assert_in_release_wasmv1_as() {
         }
     }
 
-    let res = run_main(&*interface, runtime_module, 100_000_000, gas_costs);
+    let res = run_main(
+        &*interface,
+        runtime_module,
+        100_000_000,
+        gas_costs,
+        &config,
+        ExecutionOptions::default(),
+    );
 
     match res {
         Err(e) if e.to_string().contains("expected assert") => {
@@ -400,13 +660,15 @@ fn test_assert_in_release_wasmv1_as() {
 /// This test call the main function of a SC that calls transfer_coins abi
 fn test_transfer_coins_wasmv1_as() {
     let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
     let interface: Box<dyn Interface> = Box::new(TestInterface);
     let module = include_bytes!(concat!(
         env!("CARGO_MANIFEST_DIR"),
         "/wasm/test_transfer_coins.wasm_add"
     ));
 
-    let runtime_module = RuntimeModule::new(module, gas_costs.clone(), Compiler::SP).unwrap();
+    let runtime_module =
+        RuntimeModule::new(module, gas_costs.clone(), &config, Compiler::SP).unwrap();
 
     match runtime_module.clone() {
         RuntimeModule::ASModule(_) => {
@@ -417,7 +679,15 @@ fn test_transfer_coins_wasmv1_as() {
         }
     }
 
-    let _resp = run_main(&*interface, runtime_module, 100_000, gas_costs).unwrap();
+    let _resp = run_main(
+        &*interface,
+        runtime_module,
+        100_000,
+        gas_costs,
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap();
 
     #[cfg(feature = "execution-trace")]
     {
@@ -442,13 +712,15 @@ fn test_transfer_coins_wasmv1_as() {
 /// This test call the main function of a SC that calls bs58 encode/decode abi
 fn test_bs58_to_from_wasmv1_as() {
     let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
     let interface: Box<dyn Interface> = Box::new(TestInterface);
     let module = include_bytes!(concat!(
         env!("CARGO_MANIFEST_DIR"),
         "/wasm/test_bs58_to_from.wasm_add"
     ));
 
-    let runtime_module = RuntimeModule::new(module, gas_costs.clone(), Compiler::SP).unwrap();
+    let runtime_module =
+        RuntimeModule::new(module, gas_costs.clone(), &config, Compiler::SP).unwrap();
 
     match runtime_module.clone() {
         RuntimeModule::ASModule(_) => {
@@ -458,7 +730,15 @@ fn test_bs58_to_from_wasmv1_as() {
             println!("Module type WasmV1Module");
         }
     }
-    run_main(&*interface, runtime_module, 100_000_000, gas_costs).unwrap();
+    run_main(
+        &*interface,
+        runtime_module,
+        100_000_000,
+        gas_costs,
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap();
 }
 
 #[test]
@@ -466,13 +746,15 @@ fn test_bs58_to_from_wasmv1_as() {
 /// This test call the main function of a SC that calls comparisons abis
 fn test_compare_wasmv1_as() {
     let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
     let interface: Box<dyn Interface> = Box::new(TestInterface);
     let module = include_bytes!(concat!(
         env!("CARGO_MANIFEST_DIR"),
         "/wasm/test_compare.wasm_add"
     ));
 
-    let runtime_module = RuntimeModule::new(module, gas_costs.clone(), Compiler::SP).unwrap();
+    let runtime_module =
+        RuntimeModule::new(module, gas_costs.clone(), &config, Compiler::SP).unwrap();
 
     match runtime_module.clone() {
         RuntimeModule::ASModule(_) => {
@@ -482,7 +764,15 @@ fn test_compare_wasmv1_as() {
             println!("Module type WasmV1Module");
         }
     }
-    run_main(&*interface, runtime_module, 100_000_000, gas_costs).unwrap();
+    run_main(
+        &*interface,
+        runtime_module,
+        100_000_000,
+        gas_costs,
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap();
 }
 
 #[test]
@@ -490,11 +780,23 @@ fn test_compare_wasmv1_as() {
 /// Test basic function-only SC execution
 fn test_run_function() {
     let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
     let interface: Box<dyn Interface> = Box::new(TestInterface);
     let module = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/wasm/basic_func.wasm"));
 
-    let runtime_module = RuntimeModule::new(module, gas_costs.clone(), Compiler::SP).unwrap();
-    run_function(&*interface, runtime_module, "ping", b"", 100_000, gas_costs).unwrap();
+    let runtime_module =
+        RuntimeModule::new(module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+    run_function(
+        &*interface,
+        runtime_module,
+        "ping",
+        b"",
+        100_000,
+        gas_costs,
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap();
 }
 
 // NOTE: this test is outdated as module are now pre-compiled with max_instance_cost
@@ -509,7 +811,7 @@ fn test_run_function() {
 
 //     // Test giving not enough gas to create the instance
 //     let runtime_module = RuntimeModule::new(module, 100, gas_costs.clone(), Compiler::SP).unwrap();
-//     let error = run_main(&*interface, runtime_module, 100_000, gas_costs.clone())
+//     let error = run_main(&*interface, runtime_module, 100_000, gas_costs.clone(), ExecutionOptions::default())
 //         .unwrap_err()
 //         .to_string();
 //     assert!(
@@ -520,7 +822,7 @@ fn test_run_function() {
 //     // Test giving enough gas to create the instance but not enough for the VM
 //     let runtime_module =
 //         RuntimeModule::new(module, 100_000, gas_costs.clone(), Compiler::SP).unwrap();
-//     let error = run_main(&*interface, runtime_module, 100, gas_costs)
+//     let error = run_main(&*interface, runtime_module, 100, gas_costs, ExecutionOptions::default())
 //         .unwrap_err()
 //         .to_string();
 //     assert!(error.contains("Not enough gas to launch the virtual machine"));
@@ -531,11 +833,79 @@ fn test_run_function() {
 /// Test that a no-main SC executed through `run_main` fails as expected
 fn test_run_main_without_main() {
     let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
     let interface: Box<dyn Interface> = Box::new(TestInterface);
     let module = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/wasm/no_main.wasm"));
-    let runtime_module = RuntimeModule::new(module, gas_costs.clone(), Compiler::SP).unwrap();
-    run_main(&*interface, runtime_module, 100_000, gas_costs)
-        .expect_err("An error should spawn here");
+    let runtime_module =
+        RuntimeModule::new(module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+    run_main(
+        &*interface,
+        runtime_module,
+        100_000,
+        gas_costs,
+        &config,
+        ExecutionOptions::default(),
+    )
+    .expect_err("An error should spawn here");
+}
+
+#[test]
+#[serial]
+fn test_engine_reuse() {
+    use crate::EngineProvider;
+
+    let interface = TestInterface;
+    let module = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/wasm/empty_main.wasm"));
+    let engines = EngineProvider::new();
+    let run = |gas_costs: &GasCosts| {
+        let runtime_module = RuntimeModule::new(
+            module,
+            gas_costs.clone(),
+            &RuntimeConfig::default(),
+            Compiler::SP,
+        )
+        .unwrap();
+        run_main(
+            &interface,
+            runtime_module,
+            10_000_000,
+            gas_costs.clone(),
+            &RuntimeConfig::default(),
+            ExecutionOptions {
+                engines: Some(engines.clone()),
+                ..Default::default()
+            },
+        )
+        .unwrap()
+    };
+
+    // the executions given the provider share its engine
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    let first = run(&gas_costs);
+    assert_eq!(run(&gas_costs.clone()).remaining_gas, first.remaining_gas);
+    assert_eq!(engines.len(), 1);
+
+    // the executions without one do not fill it
+    let runtime_module =
+        RuntimeModule::new(module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+    let response = run_main(
+        &interface,
+        runtime_module,
+        10_000_000,
+        gas_costs.clone(),
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap();
+    assert_eq!(response.remaining_gas, first.remaining_gas);
+    assert_eq!(engines.len(), 1);
+
+    // other operator costs get an engine of their own
+    let mut costlier = gas_costs.clone();
+    costlier.operator_cost += 1;
+    assert!(run(&costlier).remaining_gas < first.remaining_gas);
+    assert_eq!(engines.len(), 2);
 }
 
 #[test]
@@ -546,15 +916,19 @@ fn test_run_main_without_main() {
 /// This test ensure that this initial cost is correctly debited.
 fn test_run_empty_main() {
     let mut gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
     let interface: Box<dyn Interface> = Box::new(TestInterface);
     let module = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/wasm/empty_main.wasm"));
     gas_costs.launch_cost = 0;
-    let runtime_module = RuntimeModule::new(module, gas_costs.clone(), Compiler::SP).unwrap();
+    let runtime_module =
+        RuntimeModule::new(module, gas_costs.clone(), &config, Compiler::SP).unwrap();
     let a = run_main(
         &*interface,
         runtime_module.clone(),
         10_000_000,
         gas_costs.clone(),
+        &config,
+        ExecutionOptions::default(),
     )
     .expect("Failed to run empty_main.wasm");
     // Here we avoid hard-coding a value (that can change in future wasmer
@@ -564,8 +938,15 @@ fn test_run_empty_main() {
     let mut rng = rand::thread_rng();
     let cost = rng.gen_range(1..1_000_000);
     gas_costs.launch_cost = cost;
-    let b = run_main(&*interface, runtime_module, 10_000_000, gas_costs)
-        .expect("Failed to run empty_main.wasm");
+    let b = run_main(
+        &*interface,
+        runtime_module,
+        10_000_000,
+        gas_costs,
+        &config,
+        ExecutionOptions::default(),
+    )
+    .expect("Failed to run empty_main.wasm");
     // Between 2 calls, the metering cost should be the difference
     assert_eq!(a.remaining_gas - b.remaining_gas, cost);
 }
@@ -587,12 +968,7 @@ fn test_run_main_rust_wasmv1() {
     //     RuntimeModule::new(module, gas_costs.clone(), Compiler::SP)
     //         .unwrap();
 
-    // let a = match run_main(
-    //     &*interface,
-    //     runtime_module.clone(),
-    //     10_000_000,
-    //     gas_costs.clone(),
-    // ) { Ok(a) => a, Err(e) => { println!("e: {}", e); panic!("Failed to run
+    // let a = match run_main(//     &*interface, //     runtime_module.clone(), //     10_000_000, //     gas_costs.clone(), //, ExecutionOptions::default()) { Ok(a) => a, Err(e) => { println!("e: {}", e); panic!("Failed to run
     //   main") }
     // };
 
@@ -603,7 +979,7 @@ fn test_run_main_rust_wasmv1() {
     // let mut rng = rand::thread_rng();
     // let cost = rng.gen_range(1..1_000_000);
     // gas_costs.launch_cost = cost;
-    // let b = run_main(&*interface, runtime_module, 10_000_000, gas_costs)
+    // let b = run_main(&*interface, runtime_module, 10_000_000, gas_costs, ExecutionOptions::default())
     //     .expect("Failed to run empty_main.wasm");
     // dbg!(b.ret);
     // // Between 2 calls, the metering cost should be the difference
@@ -619,11 +995,20 @@ fn test_run_main_rust_wasmv1() {
 /// * getOpData
 fn test_op_fn() {
     let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
     let interface: Box<dyn Interface> = Box::new(TestInterface);
     let module = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/wasm/op_fn.wasm"));
-    let runtime_module = RuntimeModule::new(module, gas_costs.clone(), Compiler::SP).unwrap();
-    run_main(&*interface, runtime_module, 10_000_000, gas_costs.clone())
-        .expect("Failed to run op_fn.wasm");
+    let runtime_module =
+        RuntimeModule::new(module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+    run_main(
+        &*interface,
+        runtime_module,
+        10_000_000,
+        gas_costs.clone(),
+        &config,
+        ExecutionOptions::default(),
+    )
+    .expect("Failed to run op_fn.wasm");
 }
 
 /// Test `seed`, `Date.now`, `console.log` and `abort`
@@ -633,14 +1018,23 @@ fn test_op_fn() {
 #[serial]
 fn test_builtins() {
     let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
     let interface: Box<dyn Interface> = Box::new(TestInterface);
     let module = include_bytes!(concat!(
         env!("CARGO_MANIFEST_DIR"),
         "/wasm/use_builtins.wasm"
     ));
-    let runtime_module = RuntimeModule::new(module, gas_costs.clone(), Compiler::SP).unwrap();
+    let runtime_module =
+        RuntimeModule::new(module, gas_costs.clone(), &config, Compiler::SP).unwrap();
     // let before = chrono::offset::Utc::now().timestamp_millis();
-    match run_main(&*interface, runtime_module, 10_000_000, gas_costs.clone()) {
+    match run_main(
+        &*interface,
+        runtime_module,
+        10_000_000,
+        gas_costs.clone(),
+        &config,
+        ExecutionOptions::default(),
+    ) {
         Err(e) => {
             let msg = e.to_string();
             // make sure the error was caused by a manual abort
@@ -666,13 +1060,15 @@ fn test_builtins() {
 /// These are AS functions that we choose to handle in the VM
 fn test_builtin_assert_and_exit() {
     let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
     let interface: Box<dyn Interface> = Box::new(TestInterface);
     let module = include_bytes!(concat!(
         env!("CARGO_MANIFEST_DIR"),
         "/wasm/use_builtin_assert.wasm"
     ));
 
-    let runtime_module = RuntimeModule::new(module, gas_costs.clone(), Compiler::SP).unwrap();
+    let runtime_module =
+        RuntimeModule::new(module, gas_costs.clone(), &config, Compiler::SP).unwrap();
     match run_function(
         &*interface,
         runtime_module,
@@ -680,6 +1076,8 @@ fn test_builtin_assert_and_exit() {
         b"",
         100_000,
         gas_costs.clone(),
+        &config,
+        ExecutionOptions::default(),
     ) {
         Err(e) => {
             assert!(e.to_string().contains("Result is not true!"))
@@ -687,7 +1085,8 @@ fn test_builtin_assert_and_exit() {
         _ => panic!("test should return an error!"),
     }
 
-    let runtime_module = RuntimeModule::new(module, gas_costs.clone(), Compiler::SP).unwrap();
+    let runtime_module =
+        RuntimeModule::new(module, gas_costs.clone(), &config, Compiler::SP).unwrap();
     if let Ok(_) = run_function(
         &*interface,
         runtime_module,
@@ -695,6 +1094,8 @@ fn test_builtin_assert_and_exit() {
         b"",
         100_000,
         gas_costs.clone(),
+        &config,
+        ExecutionOptions::default(),
     ) {
         panic!("test should return an error!");
     }
@@ -704,7 +1105,8 @@ fn test_builtin_assert_and_exit() {
         "/wasm/use_builtin_exit.wasm"
     ));
 
-    let runtime_module = RuntimeModule::new(module, gas_costs.clone(), Compiler::SP).unwrap();
+    let runtime_module =
+        RuntimeModule::new(module, gas_costs.clone(), &config, Compiler::SP).unwrap();
     match run_function(
         &*interface,
         runtime_module,
@@ -712,6 +1114,8 @@ fn test_builtin_assert_and_exit() {
         b"",
         100_000,
         gas_costs.clone(),
+        &config,
+        ExecutionOptions::default(),
     ) {
         Err(e) => {
             assert!(e.to_string().contains("exit with code: 0"))
@@ -719,7 +1123,8 @@ fn test_builtin_assert_and_exit() {
         _ => panic!("test should return an error!"),
     }
 
-    let runtime_module = RuntimeModule::new(module, gas_costs.clone(), Compiler::SP).unwrap();
+    let runtime_module =
+        RuntimeModule::new(module, gas_costs.clone(), &config, Compiler::SP).unwrap();
     match run_function(
         &*interface,
         runtime_module,
@@ -727,6 +1132,8 @@ fn test_builtin_assert_and_exit() {
         b"",
         100_000,
         gas_costs,
+        &config,
+        ExecutionOptions::default(),
     ) {
         Err(e) => {
             assert!(e.to_string().contains("exit with code: 2"))
@@ -740,6 +1147,7 @@ fn test_builtin_assert_and_exit() {
 /// Test WASM files compiled with unsupported builtin functions
 fn test_unsupported_builtins() {
     let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
     let interface: Box<dyn Interface> = Box::new(TestInterface);
 
     // Test for hrtime
@@ -747,9 +1155,17 @@ fn test_unsupported_builtins() {
         env!("CARGO_MANIFEST_DIR"),
         "/wasm/unsupported_builtin_hrtime.wasm"
     ));
-    let runtime_module = RuntimeModule::new(module, gas_costs.clone(), Compiler::SP).unwrap();
+    let runtime_module =
+        RuntimeModule::new(module, gas_costs.clone(), &config, Compiler::SP).unwrap();
 
-    match run_main(&*interface, runtime_module, 10_000_000, gas_costs.clone()) {
+    match run_main(
+        &*interface,
+        runtime_module,
+        10_000_000,
+        gas_costs.clone(),
+        &config,
+        ExecutionOptions::default(),
+    ) {
         Err(e) => {
             assert!(e
                 .to_string()
@@ -763,9 +1179,17 @@ fn test_unsupported_builtins() {
         env!("CARGO_MANIFEST_DIR"),
         "/wasm/unsupported_builtin_random_values.wasm"
     ));
-    let runtime_module = RuntimeModule::new(module, gas_costs.clone(), Compiler::SP).unwrap();
+    let runtime_module =
+        RuntimeModule::new(module, gas_costs.clone(), &config, Compiler::SP).unwrap();
 
-    match run_main(&*interface, runtime_module, 10_000_000, gas_costs.clone()) {
+    match run_main(
+        &*interface,
+        runtime_module,
+        10_000_000,
+        gas_costs.clone(),
+        &config,
+        ExecutionOptions::default(),
+    ) {
         Err(e) => {
             assert!(e
                 .to_string()
@@ -784,9 +1208,10 @@ fn test_unsupported_builtins() {
 fn test_wat() {
     {
         let gas_costs = GasCosts::default();
+        let config = RuntimeConfig::default();
         let bytecode = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/wasm/dummy.wat"));
 
-        let runtime_module = RuntimeModule::new(bytecode, gas_costs.clone(), Compiler::SP);
+        let runtime_module = RuntimeModule::new(bytecode, gas_costs.clone(), &config, Compiler::SP);
 
         match runtime_module {
             Ok(_) => assert!(false, ".wat are not supported anymore"),
@@ -800,11 +1225,21 @@ fn test_wat() {
     }
     {
         let gas_costs = GasCosts::default();
+        let config = RuntimeConfig::default();
         let interface: Box<dyn Interface> = Box::new(TestInterface);
         let bytecode = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/wasm/dummy.wasm"));
 
-        let runtime_module = RuntimeModule::new(bytecode, gas_costs.clone(), Compiler::SP).unwrap();
-        let response = run_main(&*interface, runtime_module, 100_000, gas_costs.clone()).unwrap();
+        let runtime_module =
+            RuntimeModule::new(bytecode, gas_costs.clone(), &config, Compiler::SP).unwrap();
+        let response = run_main(
+            &*interface,
+            runtime_module,
+            100_000,
+            gas_costs.clone(),
+            &config,
+            ExecutionOptions::default(),
+        )
+        .unwrap();
 
         // Note: for now, exec main always return an empty vec
         let excepted: Vec<u8> = Vec::new();
@@ -812,30 +1247,85 @@ fn test_wat() {
     }
 }
 
+#[test]
+#[serial]
+#[cfg(feature = "cranelift")]
+/// Test that a serialized module can be deserialized and executed, and that
+/// corrupted or unserializable artifacts are reported as errors
+fn test_precompiled_module() {
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    let interface: Box<dyn Interface> = Box::new(TestInterface);
+    let bytecode = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/wasm/basic_main.wasm"));
+
+    let runtime_module =
+        RuntimeModule::new(bytecode, gas_costs.clone(), &config, Compiler::CL).unwrap();
+    let artifact = runtime_module.serialize().unwrap();
+    let runtime_module = RuntimeModule::deserialize(
+        &artifact,
+        gas_costs.max_instance_cost,
+        gas_costs.clone(),
+        &config,
+    )
+    .unwrap();
+    run_main(
+        &*interface,
+        runtime_module,
+        100_000,
+        gas_costs.clone(),
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap();
+
+    let mut corrupted = artifact.clone();
+    corrupted.truncate(artifact.len() / 2);
+    corrupted[1..9].copy_from_slice(b"corrupt!");
+    let err = RuntimeModule::deserialize(
+        &corrupted,
+        gas_costs.max_instance_cost,
+        gas_costs.clone(),
+        &config,
+    )
+    .unwrap_err();
+    assert!(err.to_string().contains("Incompatible artifact"));
+
+    let err = RuntimeModule::deserialize(
+        &[42],
+        gas_costs.max_instance_cost,
+        gas_costs.clone(),
+        &config,
+    )
+    .unwrap_err();
+    assert!(err.to_string().contains("Incompatible artifact"));
+
+    let runtime_module =
+        RuntimeModule::new(bytecode, gas_costs.clone(), &config, Compiler::SP).unwrap();
+    assert!(runtime_module.serialize().is_err());
+}
+
 #[test]
 #[serial]
 /// Test a WASM execution using features disabled in engine (simd & threads)
+#[cfg(not(feature = "simd"))]
 fn test_features_disabled() {
     let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
 
     let module = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/wasm/simd.wasm"));
-    match RuntimeModule::new(module, gas_costs.clone(), Compiler::SP) {
+    match RuntimeModule::new(module, gas_costs.clone(), &config, Compiler::SP) {
         Err(e) => {
             // println!("Error: {}", e);
-            assert!(e
-                .to_string()
-                .starts_with("Validation error: SIMD support is not enabled"));
+            assert_eq!(e.to_string(), "Forbidden WebAssembly feature: simd");
         }
         _ => panic!("Failed to run use_builtins.wasm"),
     }
 
     let module = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/wasm/threads.wasm"));
-    match RuntimeModule::new(module, gas_costs.clone(), Compiler::SP) {
+    match RuntimeModule::new(module, gas_costs.clone(), &config, Compiler::SP) {
         Err(e) => {
             // println!("Error: {}", e);
-            assert!(e
-                .to_string()
-                .starts_with("Validation error: threads support is not enabled"));
+            assert_eq!(e.to_string(), "Forbidden WebAssembly feature: threads");
         }
         _ => panic!("Failed to run use_builtins.wasm"),
     }
@@ -851,9 +1341,21 @@ fn test_class_id() {
         env!("CARGO_MANIFEST_DIR"),
         "/wasm/return_basic.wasm"
     ));
-    let module = ASModule::new(bytecode, 100_000, GasCosts::default(), Compiler::SP).unwrap();
+    let module = ASModule::new(
+        bytecode,
+        100_000,
+        GasCosts::default(),
+        &RuntimeConfig::default(),
+        Compiler::SP,
+    )
+    .unwrap();
     let mut store = Store::new(module._engine);
-    let mut context = ASContext::new(&*interface, module.binary_module, GasCosts::default());
+    let mut context = ASContext::new(
+        &*interface,
+        module.binary_module,
+        GasCosts::default(),
+        Default::default(),
+    );
     let (instance, _function_env, _) = context.create_vm_instance_and_init_env(&mut store).unwrap();
 
     // setup test specific context
@@ -965,3 +1467,8389 @@ fn test_ser() {
     assert!(s_atv1.find("foo").is_some());
     assert!(s_atv1.find("slot").is_some());
 }
+
+#[test]
+#[serial]
+/// Test that the NaNs produced by float instructions have the canonical bit
+/// pattern whatever the compiler
+fn test_nan_canonicalization() {
+    let bytecode = br#"(module
+        (func (export "nan") (result i32)
+            (i32.reinterpret_f32 (f32.div (f32.const 0) (f32.const 0)))))"#;
+    for compiler in [
+        #[cfg(feature = "cranelift")]
+        Compiler::CL,
+        Compiler::SP,
+    ] {
+        let module = ASModule::new(
+            bytecode,
+            100_000,
+            GasCosts::default(),
+            &RuntimeConfig::default(),
+            compiler,
+        )
+        .unwrap();
+        let mut store = Store::new(module._engine.clone());
+        let instance = Instance::new(&mut store, &module.binary_module, &imports! {}).unwrap();
+        let nan = instance
+            .exports
+            .get_typed_function::<(), i32>(&store, "nan")
+            .unwrap()
+            .call(&mut store)
+            .unwrap();
+        assert_eq!(nan as u32, 0x7fc0_0000);
+    }
+}
+
+#[test]
+#[serial]
+/// Test that modules using floats are rejected in strict float mode
+fn test_float_mode_reject() {
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig {
+        float_mode: FloatMode::Reject,
+        ..RuntimeConfig::default()
+    };
+    let module = wasmer::wat2wasm(
+        br#"(module
+            (memory (export "memory") 1)
+            (func (export "main") (drop (f64.sqrt (f64.const 2)))))"#,
+    )
+    .unwrap();
+
+    let err = RuntimeModule::new(&module, gas_costs, &config, Compiler::SP).unwrap_err();
+    assert_eq!(err.to_string(), "Forbidden WebAssembly feature: floats");
+    // accepted in the default mode
+    assert!(RuntimeModule::new(
+        &module,
+        GasCosts::default(),
+        &RuntimeConfig::default(),
+        Compiler::SP
+    )
+    .is_ok());
+}
+
+#[test]
+#[serial]
+#[cfg(feature = "profiling")]
+/// Test that the profile of an execution counts every ABI call
+fn test_execution_profile() {
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    let interface = TestInterface;
+    let module = wasmer::wat2wasm(
+        br#"(module
+            (import "massa" "assembly_script_get_call_coins" (func $coins (result i64)))
+            (import "massa" "assembly_script_get_time" (func $time (result i64)))
+            (import "massa" "assembly_script_get_remaining_gas" (func $gas (result i64)))
+            (memory (export "memory") 1)
+            (func (export "main")
+                (drop (call $coins))
+                (drop (call $time))
+                (drop (call $coins))
+                (drop (call $gas))
+                (drop (call $coins))))"#,
+    )
+    .unwrap();
+
+    let runtime_module =
+        RuntimeModule::new(&module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+    let response = run_main(
+        &interface,
+        runtime_module,
+        100_000,
+        gas_costs.clone(),
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap();
+
+    let profile = &response.profile;
+    assert_eq!(profile.abis.len(), 3);
+    for (abi, calls) in [
+        ("assembly_script_get_call_coins", 3),
+        ("assembly_script_get_time", 1),
+        ("assembly_script_get_remaining_gas", 1),
+    ] {
+        let abi_profile = &profile.abis[abi];
+        assert_eq!(abi_profile.calls, calls, "{}", abi);
+        assert_eq!(abi_profile.gas, calls * gas_costs.abi_costs[abi], "{}", abi);
+    }
+}
+
+#[test]
+#[serial]
+/// Test that a second reading of the remaining gas is lower than the first by
+/// at least the cost of the ABI
+fn test_get_remaining_gas_decreases() {
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    let interface = TestInterface;
+    let cost = gas_costs.abi_costs["assembly_script_get_remaining_gas"];
+    assert!(cost > 0);
+    let module = wasmer::wat2wasm(
+        format!(
+            r#"(module
+                (import "massa" "assembly_script_get_remaining_gas" (func $gas (result i64)))
+                (memory (export "memory") 1)
+                (func (export "main")
+                    (local $first i64)
+                    (local $second i64)
+                    (local.set $first (call $gas))
+                    (local.set $second (call $gas))
+                    (if (i64.ge_u (local.get $second) (local.get $first))
+                        (then unreachable))
+                    (if (i64.lt_u
+                            (i64.sub (local.get $first) (local.get $second))
+                            (i64.const {}))
+                        (then unreachable))))"#,
+            cost
+        )
+        .as_bytes(),
+    )
+    .unwrap();
+
+    let runtime_module =
+        RuntimeModule::new(&module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+    run_main(
+        &interface,
+        runtime_module,
+        100_000,
+        gas_costs,
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap();
+}
+
+#[test]
+#[serial]
+/// Test that a module can call its own exports in a fresh instance
+fn test_local_call_self() {
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    let interface = TestInterface;
+    // AS strings are UTF-16 with their byte length stored right before them
+    let module = wasmer::wat2wasm(
+        br#"(module
+            (import "massa" "assembly_script_local_call_self"
+                (func $local_call_self (param i32 i32) (result i32)))
+            (memory (export "memory") 1)
+            (global $entered (mut i32) (i32.const 0))
+            (data (i32.const 12) "\0a\00\00\00i\00n\00n\00e\00r\00")
+            (data (i32.const 32) "\00\00\00\00")
+            (data (i32.const 44) "\0e\00\00\00m\00i\00s\00s\00i\00n\00g\00")
+            (func (export "__new") (param i32 i32) (result i32) (i32.const 1024))
+            (func $inner (export "inner")
+                ;; the caller instance already set the global
+                (if (global.get $entered) (then unreachable)))
+            (func (export "main")
+                (global.set $entered (i32.const 1))
+                (drop (call $local_call_self (i32.const 16) (i32.const 36))))
+            (func (export "call_missing")
+                (drop (call $local_call_self (i32.const 48) (i32.const 36)))))"#,
+    )
+    .unwrap();
+
+    let runtime_module =
+        RuntimeModule::new(&module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+    run_main(
+        &interface,
+        runtime_module.clone(),
+        100_000,
+        gas_costs.clone(),
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap();
+
+    let err = run_function(
+        &interface,
+        runtime_module,
+        "call_missing",
+        b"",
+        100_000,
+        gas_costs,
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("Function missing is not exported by the current module"));
+}
+
+#[test]
+#[serial]
+/// Test reading back the bytecode of a created smart contract
+fn test_get_bytecode_for() {
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    let interface = TestInterface;
+    let bytecode = b"\0asm\x01\0\0\0";
+    // `__new` stores the allocated size right before the returned pointer,
+    // like the AssemblyScript runtime does
+    let module = wasmer::wat2wasm(
+        br#"(module
+            (import "massa" "assembly_script_create_sc"
+                (func $create_sc (param i32) (result i32)))
+            (import "massa" "assembly_script_get_bytecode_for"
+                (func $get_bytecode_for (param i32) (result i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 60) "\08\00\00\00\00asm\01\00\00\00")
+            (data (i32.const 108) "\0e\00\00\00u\00n\00k\00n\00o\00w\00n\00")
+            (func (export "__new") (param i32 i32) (result i32)
+                (i32.store (i32.const 1020) (local.get 0))
+                (i32.const 1024))
+            (func (export "roundtrip") (result i32)
+                (call $get_bytecode_for (call $create_sc (i32.const 64))))
+            (func (export "get_unknown") (result i32)
+                (call $get_bytecode_for (i32.const 112))))"#,
+    )
+    .unwrap();
+
+    let runtime_module =
+        RuntimeModule::new(&module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+    let response = run_function(
+        &interface,
+        runtime_module.clone(),
+        "roundtrip",
+        b"",
+        100_000,
+        gas_costs.clone(),
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap();
+    assert_eq!(response.ret, bytecode);
+
+    // a missing smart contract is an error, not an empty bytecode
+    let err = run_function(
+        &interface,
+        runtime_module,
+        "get_unknown",
+        b"",
+        100_000,
+        gas_costs,
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("no smart contract at address unknown"));
+}
+
+#[test]
+#[serial]
+/// Test that a smart contract is not created if one of its initial datastore
+/// entries is invalid
+fn test_create_sc_with_data() {
+    let interface = TestInterface;
+    // one entry with the key "k" and an 8 bytes value
+    let module = wasmer::wat2wasm(
+        br#"(module
+            (import "massa" "assembly_script_create_sc_with_data"
+                (func $create_sc_with_data (param i32 i32) (result i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 60) "\08\00\00\00\00asm\01\00\00\00")
+            (data (i32.const 92)
+                "\12\00\00\00\01\00\00\00\01k\08\00\00\00\01\02\03\04\05\06\07\08")
+            (func (export "__new") (param i32 i32) (result i32)
+                (i32.store (i32.const 1020) (local.get 0))
+                (i32.const 1024))
+            (func (export "main")
+                (drop (call $create_sc_with_data (i32.const 64) (i32.const 96)))))"#,
+    )
+    .unwrap();
+
+    // the value is over the limit, nothing is created
+    CREATED_MODULES.lock().unwrap().clear();
+    let gas_costs = GasCosts::default();
+    let mut config = RuntimeConfig::default();
+    config.storage_limits.max_value_len = 4;
+    let runtime_module =
+        RuntimeModule::new(&module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+    let err = run_main(
+        &interface,
+        runtime_module,
+        100_000,
+        gas_costs,
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("Datastore value of 8 bytes exceeds the maximum of 4 bytes"));
+    assert!(CREATED_MODULES.lock().unwrap().is_empty());
+
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    let runtime_module =
+        RuntimeModule::new(&module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+    run_main(
+        &interface,
+        runtime_module,
+        100_000,
+        gas_costs,
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap();
+    assert_eq!(
+        CREATED_MODULES.lock().unwrap().get("sc_address").unwrap(),
+        b"\0asm\x01\0\0\0"
+    );
+}
+
+#[test]
+#[serial]
+/// Test that a smart contract created with a seed is at the predicted address
+fn test_predict_sc_address() {
+    let interface = TestInterface;
+    let buffer = |ptr: u32, bytes: &[u8]| {
+        format!(
+            r#"(data (i32.const {}) "{}{}")"#,
+            ptr - 4,
+            wat_bytes(&(bytes.len() as u32).to_le_bytes()),
+            wat_bytes(bytes)
+        )
+    };
+    let module = wasmer::wat2wasm(
+        format!(
+            r#"(module
+                (import "massa" "assembly_script_predict_sc_address"
+                    (func $predict (param i32) (result i32)))
+                (import "massa" "assembly_script_create_sc_with_seed"
+                    (func $create_sc_with_seed (param i32 i32) (result i32)))
+                (import "massa" "assembly_script_generate_event"
+                    (func $event (param i32)))
+                (memory (export "memory") 1)
+                {}
+                {}
+                {}
+                (func (export "__new") (param i32 i32) (result i32)
+                    (i32.store (i32.const 1020) (local.get 0))
+                    (i32.const 1024))
+                (func (export "predict")
+                    (call $event (call $predict (i32.const 64))))
+                (func (export "create")
+                    (call $event (call $create_sc_with_seed (i32.const 32) (i32.const 64))))
+                (func (export "mispredicted")
+                    (drop (call $create_sc_with_seed (i32.const 32) (i32.const 96)))))"#,
+            buffer(32, b"\0asm\x01\0\0\0"),
+            buffer(64, b"factory seed"),
+            buffer(96, MISPREDICTED_SEED),
+        )
+        .as_bytes(),
+    )
+    .unwrap();
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    let runtime_module =
+        RuntimeModule::new(&module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+    let run = |function: &str| {
+        run_function(
+            &interface,
+            runtime_module.clone(),
+            function,
+            b"",
+            1_000_000,
+            gas_costs.clone(),
+            &config,
+            ExecutionOptions::default(),
+        )
+    };
+
+    INDEXED_EVENTS.with(|events| events.borrow_mut().clear());
+    run("predict").unwrap();
+    run("create").unwrap();
+    let predicted = interface.predict_created_address(b"factory seed").unwrap();
+    assert_eq!(
+        INDEXED_EVENTS.with(|events| events.take()),
+        [(0, predicted.clone()), (0, predicted.clone())]
+    );
+    assert_eq!(
+        CREATED_MODULES.lock().unwrap().remove(&predicted).unwrap(),
+        b"\0asm\x01\0\0\0"
+    );
+
+    // an interface creating the contract elsewhere is caught
+    let err = run("mispredicted").unwrap_err().to_string();
+    let predicted = interface
+        .predict_created_address(MISPREDICTED_SEED)
+        .unwrap();
+    CREATED_MODULES
+        .lock()
+        .unwrap()
+        .remove(&format!("{}_elsewhere", predicted));
+    assert!(
+        err.contains(&format!(
+            "the smart contract was created at {}_elsewhere instead of the predicted address {}",
+            predicted, predicted
+        )),
+        "{}",
+        err
+    );
+}
+
+#[test]
+#[serial]
+fn test_storage_cost_abis() {
+    let interface = TestInterface;
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    let max = config.storage_limits.max_value_len as i64;
+    // traps unless `call` returns `expected`
+    let run = |call: &str, arg: i64, expected: i64| {
+        let wat = format!(
+            r#"(module
+                (import "massa" "assembly_script_get_storage_cost"
+                    (func $cost (param i64) (result i64)))
+                (import "massa" "assembly_script_reserve_storage"
+                    (func $reserve (param i64) (result i32)))
+                (memory (export "memory") 1)
+                (func (export "main")
+                    (if (i64.ne ({}) (i64.const {}))
+                        (then unreachable))))"#,
+            match call {
+                "cost" => format!("call $cost (i64.const {})", arg),
+                _ => format!("i64.extend_i32_u (call $reserve (i64.const {}))", arg),
+            },
+            expected
+        );
+        let bytecode = wasmer::wat2wasm(wat.as_bytes()).unwrap();
+        let runtime_module =
+            RuntimeModule::new(&bytecode, gas_costs.clone(), &config, Compiler::SP).unwrap();
+        run_main(
+            &interface,
+            runtime_module,
+            10_000_000,
+            gas_costs.clone(),
+            &config,
+            ExecutionOptions::default(),
+        )
+    };
+
+    // linear in the number of bytes, up to the size of a datastore value
+    run("cost", 0, 0).unwrap();
+    run("cost", 7, 7 * STORAGE_BYTE_COST as i64).unwrap();
+    run("cost", max, max * STORAGE_BYTE_COST as i64).unwrap();
+    // the balance covers the storage of TEST_BALANCE / STORAGE_BYTE_COST bytes
+    let affordable = (TEST_BALANCE / STORAGE_BYTE_COST) as i64;
+    run("reserve", 0, 1).unwrap();
+    run("reserve", affordable, 1).unwrap();
+    run("reserve", affordable + 1, 0).unwrap();
+    run("reserve", max, 0).unwrap();
+
+    // checked before reaching the interface
+    for call in ["cost", "reserve"] {
+        let err = run(call, max + 1, 0).unwrap_err().to_string();
+        assert!(
+            err.contains(&format!(
+                "Datastore value of {} bytes exceeds the maximum of {} bytes",
+                max + 1,
+                max
+            )),
+            "{}",
+            err
+        );
+        let err = run(call, -1, 0).unwrap_err().to_string();
+        assert!(
+            err.contains("Negative number of storage bytes: -1"),
+            "{}",
+            err
+        );
+    }
+}
+
+#[test]
+#[serial]
+/// Test that an execution calling ABIs in a loop is stopped at its deadline
+fn test_max_execution_time() {
+    let interface = TestInterface;
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig {
+        max_execution_time: Some(std::time::Duration::from_millis(1)),
+        ..RuntimeConfig::default()
+    };
+    let module = wasmer::wat2wasm(
+        br#"(module
+            (import "massa" "assembly_script_get_time" (func $time (result i64)))
+            (memory (export "memory") 1)
+            (func (export "main")
+                (loop $busy
+                    (drop (call $time))
+                    (br $busy))))"#,
+    )
+    .unwrap();
+
+    let runtime_module =
+        RuntimeModule::new(&module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+    let start = std::time::Instant::now();
+    let err = run_main(
+        &interface,
+        runtime_module,
+        u64::MAX / 2,
+        gas_costs,
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap_err();
+    assert!(start.elapsed() < std::time::Duration::from_secs(10));
+    assert!(
+        err.to_string()
+            .contains("the maximum execution time was exceeded"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+#[serial]
+/// Test that the compilations exceeding their maximum time fail, the ones of
+/// the nested calls included
+fn test_compilation_timeout() {
+    let interface = TestInterface;
+    let max_time = std::time::Duration::from_nanos(1);
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig {
+        max_compilation_time: Some(max_time),
+        ..RuntimeConfig::default()
+    };
+    let module =
+        wasmer::wat2wasm(br#"(module (memory (export "memory") 1) (func (export "main")))"#)
+            .unwrap();
+    let expect_timeout = |bytecode: &[u8]| {
+        let Err(err) = RuntimeModule::new(bytecode, gas_costs.clone(), &config, Compiler::SP)
+        else {
+            panic!("the compilation did not time out");
+        };
+        match err.downcast_ref::<ABIError>() {
+            Some(ABIError::CompilationTimeout { max_time: got }) => assert_eq!(*got, max_time),
+            _ => panic!("unexpected error: {}", err),
+        }
+    };
+    expect_timeout(&module);
+    let mut wasmv1_module = vec![1];
+    wasmv1_module.extend_from_slice(&module);
+    expect_timeout(&wasmv1_module);
+
+    // within the bound
+    let generous = RuntimeConfig {
+        max_compilation_time: Some(std::time::Duration::from_secs(60)),
+        ..RuntimeConfig::default()
+    };
+    let runtime_module =
+        RuntimeModule::new(&module, gas_costs.clone(), &generous, Compiler::SP).unwrap();
+    run_main(
+        &interface,
+        runtime_module,
+        10_000_000,
+        gas_costs.clone(),
+        &generous,
+        ExecutionOptions::default(),
+    )
+    .unwrap();
+
+    // the callee is compiled by the interface
+    CREATED_MODULES
+        .lock()
+        .unwrap()
+        .insert("slow_callee".to_string(), module);
+    let caller = wasmer::wat2wasm(
+        br#"(module
+            (import "massa" "assembly_script_call"
+                (func $call (param i32 i32 i32 i64) (result i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 28) "\08\00\00\00m\00a\00i\00n\00")
+            (data (i32.const 44) "\00\00\00\00")
+            (data (i32.const 60) "\16\00\00\00s\00l\00o\00w\00_\00c\00a\00l\00l\00e\00e\00")
+            (func (export "__new") (param i32 i32) (result i32)
+                (i32.store (i32.const 1020) (local.get 0))
+                (i32.const 1024))
+            (func (export "main")
+                (drop (call $call (i32.const 64) (i32.const 32) (i32.const 48) (i64.const 0)))))"#,
+    )
+    .unwrap();
+    let runtime_module = RuntimeModule::new(
+        &caller,
+        GasCosts::default(),
+        &RuntimeConfig::default(),
+        Compiler::SP,
+    )
+    .unwrap();
+    let err = run_main(
+        &interface,
+        runtime_module,
+        10_000_000,
+        gas_costs,
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap_err();
+    CREATED_MODULES.lock().unwrap().remove("slow_callee");
+    assert!(
+        err.to_string()
+            .contains("Compilation timeout: the compilation exceeded the maximum of 1ns"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+#[serial]
+/// Test the limits of the binary events next to the legacy string events
+fn test_generate_event_binary() {
+    let interface = TestInterface;
+    // buffers of 4 and 5 zeroed topics, 16 bytes of data and the "hi" string
+    let module = wasmer::wat2wasm(
+        br#"(module
+            (import "massa" "assembly_script_generate_event"
+                (func $event (param i32)))
+            (import "massa" "assembly_script_generate_event_binary"
+                (func $event_binary (param i32 i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 60) "\80\00\00\00")
+            (data (i32.const 252) "\a0\00\00\00")
+            (data (i32.const 508) "\10\00\00\00")
+            (data (i32.const 1028) "\04\00\00\00h\00i\00")
+            (func (export "main")
+                (call $event (i32.const 1032))
+                (call $event_binary (i32.const 64) (i32.const 512)))
+            (func (export "five_topics")
+                (call $event_binary (i32.const 256) (i32.const 512))))"#,
+    )
+    .unwrap();
+
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    let runtime_module =
+        RuntimeModule::new(&module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+    run_main(
+        &interface,
+        runtime_module.clone(),
+        100_000,
+        gas_costs.clone(),
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap();
+    let err = run_function(
+        &interface,
+        runtime_module,
+        "five_topics",
+        b"",
+        100_000,
+        gas_costs,
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("Event with 5 topics exceeds the maximum of 4 topics"),
+        "{}",
+        err
+    );
+
+    // 4 topics and 16 bytes of data do not fit in 100 bytes
+    let gas_costs = GasCosts::default();
+    let mut config = RuntimeConfig::default();
+    config.event_limits.max_event_data_size = 100;
+    let runtime_module =
+        RuntimeModule::new(&module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+    let err = run_main(
+        &interface,
+        runtime_module,
+        100_000,
+        gas_costs,
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("Events of 144 bytes exceed the maximum of 100 bytes per execution"),
+        "{}",
+        err
+    );
+}
+
+/// Escape bytes to be embedded in a WAT data segment
+pub(crate) fn wat_bytes(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("\\{:02x}", b)).collect()
+}
+
+#[test]
+#[serial]
+/// Test that the events of a nested call count against the budget of the
+/// top-level execution
+fn test_max_event_count_nested() {
+    let interface = TestInterface;
+    // emits two events
+    let callee = wasmer::wat2wasm(
+        br#"(module
+            (import "massa" "assembly_script_generate_event" (func $event (param i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 12) "\04\00\00\00h\00i\00")
+            (func (export "emit")
+                (call $event (i32.const 16))
+                (call $event (i32.const 16))))"#,
+    )
+    .unwrap();
+    // emits an event, calls the callee and emits another event
+    let caller = format!(
+        r#"(module
+            (import "massa" "assembly_script_generate_event" (func $event (param i32)))
+            (import "massa" "assembly_script_create_sc" (func $create_sc (param i32) (result i32)))
+            (import "massa" "assembly_script_call"
+                (func $call (param i32 i32 i32 i64) (result i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 12) "\04\00\00\00h\00i\00")
+            (data (i32.const 28) "\08\00\00\00e\00m\00i\00t\00")
+            (data (i32.const 44) "\00\00\00\00")
+            (data (i32.const 60) "{}{}")
+            (func (export "__new") (param i32 i32) (result i32)
+                (i32.store (i32.const 1020) (local.get 0))
+                (i32.const 1024))
+            (func (export "main")
+                (call $event (i32.const 16))
+                (drop (call $call
+                    (call $create_sc (i32.const 64)) (i32.const 32) (i32.const 48) (i64.const 0)))
+                (call $event (i32.const 16))))"#,
+        wat_bytes(&(callee.len() as u32).to_le_bytes()),
+        wat_bytes(&callee),
+    );
+    let caller = wasmer::wat2wasm(caller.as_bytes()).unwrap();
+
+    let gas_costs = GasCosts::default();
+    let mut config = RuntimeConfig::default();
+    config.event_limits.max_event_count = 4;
+    let runtime_module =
+        RuntimeModule::new(&caller, gas_costs.clone(), &config, Compiler::SP).unwrap();
+    // the budget is reset for every top-level execution
+    for _ in 0..2 {
+        run_main(
+            &interface,
+            runtime_module.clone(),
+            10_000_000,
+            gas_costs.clone(),
+            &config,
+            ExecutionOptions::default(),
+        )
+        .unwrap();
+    }
+
+    config.event_limits.max_event_count = 3;
+    let err = run_main(
+        &interface,
+        runtime_module,
+        10_000_000,
+        gas_costs,
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("Maximum number of events per execution reached: 3"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+#[serial]
+/// Test that the event count and the last event read by a contract include
+/// the events of its nested calls
+fn test_event_count_and_last_event() {
+    let interface = TestInterface;
+    // emits "hi" and "bye"
+    let callee = wasmer::wat2wasm(
+        br#"(module
+            (import "massa" "assembly_script_generate_event" (func $event (param i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 12) "\04\00\00\00h\00i\00")
+            (data (i32.const 28) "\06\00\00\00b\00y\00e\00")
+            (func (export "emit")
+                (call $event (i32.const 16))
+                (call $event (i32.const 32))))"#,
+    )
+    .unwrap();
+    // checks that there is no event yet, emits "hi", calls the callee and
+    // checks that its last event is the one of the callee
+    let caller = format!(
+        r#"(module
+            (import "massa" "assembly_script_generate_event" (func $event (param i32)))
+            (import "massa" "assembly_script_create_sc" (func $create_sc (param i32) (result i32)))
+            (import "massa" "assembly_script_call"
+                (func $call (param i32 i32 i32 i64) (result i32)))
+            (import "massa" "assembly_script_get_event_count" (func $count (result i64)))
+            (import "massa" "assembly_script_get_last_event" (func $last (result i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 12) "\04\00\00\00h\00i\00")
+            (data (i32.const 28) "\08\00\00\00e\00m\00i\00t\00")
+            (data (i32.const 44) "\00\00\00\00")
+            (data (i32.const 60) "{}{}")
+            (func (export "__new") (param i32 i32) (result i32)
+                (i32.store (i32.const 1020) (local.get 0))
+                (i32.const 1024))
+            (func (export "main")
+                (if (i64.ne (call $count) (i64.const 0)) (then unreachable))
+                (i32.store (i32.const 1020) (i32.const -1))
+                (drop (call $last))
+                (if (i32.ne (i32.load (i32.const 1020)) (i32.const 0)) (then unreachable))
+                (call $event (i32.const 16))
+                (drop (call $call
+                    (call $create_sc (i32.const 64)) (i32.const 32) (i32.const 48) (i64.const 0)))
+                (if (i64.ne (call $count) (i64.const 3)) (then unreachable))
+                (drop (call $last))
+                ;; "bye"
+                (if (i32.ne (i32.load (i32.const 1020)) (i32.const 3)) (then unreachable))
+                (if (i32.ne (i32.load (i32.const 1024)) (i32.const 0x657962)) (then unreachable))))"#,
+        wat_bytes(&(callee.len() as u32).to_le_bytes()),
+        wat_bytes(&callee),
+    );
+    let caller = wasmer::wat2wasm(caller.as_bytes()).unwrap();
+
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    let runtime_module =
+        RuntimeModule::new(&caller, gas_costs.clone(), &config, Compiler::SP).unwrap();
+    run_main(
+        &interface,
+        runtime_module,
+        10_000_000,
+        gas_costs.clone(),
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap();
+
+    // the data of the last event is charged per byte
+    let mut expensive = gas_costs.clone();
+    expensive
+        .abi_costs
+        .insert("assembly_script_get_last_event_per_byte".to_string(), 1_000);
+    let run = |gas_costs: GasCosts| {
+        let runtime_module =
+            RuntimeModule::new(&caller, gas_costs.clone(), &config, Compiler::SP).unwrap();
+        run_main(
+            &interface,
+            runtime_module,
+            10_000_000,
+            gas_costs,
+            &config,
+            ExecutionOptions::default(),
+        )
+        .unwrap()
+        .remaining_gas
+    };
+    assert_eq!(run(gas_costs) - run(expensive), 3 * 999);
+}
+
+#[test]
+#[serial]
+fn test_finish_call_failure() {
+    let interface = TestInterface;
+    // returns "abc"
+    let callee = wasmer::wat2wasm(
+        br#"(module
+            (memory (export "memory") 1)
+            (data (i32.const 12) "\03\00\00\00abc")
+            (func (export "run") (result i32)
+                (i32.const 16)))"#,
+    )
+    .unwrap();
+    CREATED_MODULES
+        .lock()
+        .unwrap()
+        .insert(FAILING_FINISH_CALL_ADDRESS.to_string(), callee);
+    // calls `run` at the address whose `finish_call` fails
+    let caller = wasmer::wat2wasm(
+        br#"(module
+            (import "massa" "assembly_script_call"
+                (func $call (param i32 i32 i32 i64) (result i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 28) "\06\00\00\00r\00u\00n\00")
+            (data (i32.const 44) "\00\00\00\00")
+            (data (i32.const 60)
+                "\26\00\00\00f\00i\00n\00i\00s\00h\00_\00c\00a\00l\00l\00_\00f\00a\00i\00l\00u\00r\00e\00")
+            (func (export "__new") (param i32 i32) (result i32)
+                (i32.store (i32.const 1020) (local.get 0))
+                (i32.const 1024))
+            (func (export "main")
+                (drop (call $call (i32.const 64) (i32.const 32) (i32.const 48) (i64.const 0)))))"#,
+    )
+    .unwrap();
+
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    let runtime_module =
+        RuntimeModule::new(&caller, gas_costs.clone(), &config, Compiler::SP).unwrap();
+    let err = run_main(
+        &interface,
+        runtime_module,
+        10_000_000,
+        gas_costs,
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap_err();
+    CREATED_MODULES
+        .lock()
+        .unwrap()
+        .remove(FAILING_FINISH_CALL_ADDRESS);
+    match err {
+        VMError::FinishCallError {
+            error,
+            ret,
+            remaining_gas,
+            ..
+        } => {
+            assert!(error.contains("finish_call failed"), "{}", error);
+            assert_eq!(ret, b"abc");
+            assert!(remaining_gas > 0 && remaining_gas < 10_000_000);
+        }
+        err => panic!("unexpected error: {}", err),
+    }
+}
+
+#[test]
+#[serial]
+fn test_function_not_found() {
+    let interface = TestInterface;
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    let run = |bytecode: &[u8], function: &str| {
+        let runtime_module =
+            RuntimeModule::new(bytecode, gas_costs.clone(), &config, Compiler::SP).unwrap();
+        run_function(
+            &interface,
+            runtime_module,
+            function,
+            b"",
+            10_000_000,
+            gas_costs.clone(),
+            &config,
+            ExecutionOptions::default(),
+        )
+        .unwrap_err()
+    };
+    let not_found = |err: VMError| match err {
+        VMError::FunctionNotFound {
+            function,
+            available,
+            error,
+            remaining_gas,
+            ..
+        } => {
+            assert!(remaining_gas > 0 && remaining_gas < 10_000_000);
+            (function, available, error)
+        }
+        err => panic!("unexpected error: {}", err),
+    };
+
+    // no function exported
+    let empty = wasmer::wat2wasm(br#"(module (memory (export "memory") 1))"#).unwrap();
+    let (function, available, error) = not_found(run(&empty, "main"));
+    assert_eq!(function, "main");
+    assert!(available.is_empty());
+    assert!(
+        error.contains("function main not found, the module exports []"),
+        "{}",
+        error
+    );
+
+    // exported with a signature the runtime cannot call, not listed
+    let wrong_signature = wasmer::wat2wasm(
+        br#"(module
+            (memory (export "memory") 1)
+            (func (export "main") (param i32 i32))
+            (func (export "other") (param i32) (result i32) (local.get 0)))"#,
+    )
+    .unwrap();
+    let (_, available, _) = not_found(run(&wrong_signature, "main"));
+    assert_eq!(available, vec!["other".to_string()]);
+    assert_eq!(
+        run(&wrong_signature, "missing").code(),
+        VMErrorCode::FunctionNotFound
+    );
+
+    // the listed exports are capped
+    let many = (0..40)
+        .map(|i| format!(r#"(func (export "f{}"))"#, i))
+        .collect::<String>();
+    let many =
+        wasmer::wat2wasm(format!(r#"(module (memory (export "memory") 1) {})"#, many).as_bytes())
+            .unwrap();
+    let (_, available, _) = not_found(run(&many, "main"));
+    assert_eq!(available.len(), 32);
+    assert_eq!(available[0], "f0");
+
+    // the wasmv1 functions take and return an address
+    let mut wasmv1_module = vec![1];
+    wasmv1_module.extend(
+        wasmer::wat2wasm(
+            br#"(module
+                (memory (export "memory") 1)
+                (func (export "__alloc") (param i32) (result i32) (i32.const 1024))
+                (func (export "main")))"#,
+        )
+        .unwrap()
+        .iter(),
+    );
+    let (_, available, _) = not_found(run(&wasmv1_module, "main"));
+    assert_eq!(available, vec!["__alloc".to_string()]);
+
+    // a call to a missing function of a callee fails the caller with the
+    // same error
+    CREATED_MODULES
+        .lock()
+        .unwrap()
+        .insert("callee".to_string(), wrong_signature);
+    let caller = wasmer::wat2wasm(
+        br#"(module
+            (import "massa" "assembly_script_call"
+                (func $call (param i32 i32 i32 i64) (result i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 28) "\06\00\00\00r\00u\00n\00")
+            (data (i32.const 44) "\00\00\00\00")
+            (data (i32.const 60) "\0c\00\00\00c\00a\00l\00l\00e\00e\00")
+            (func (export "__new") (param i32 i32) (result i32)
+                (i32.store (i32.const 1020) (local.get 0))
+                (i32.const 1024))
+            (func (export "main")
+                (drop (call $call (i32.const 64) (i32.const 32) (i32.const 48) (i64.const 0)))))"#,
+    )
+    .unwrap();
+    let err = run(&caller, "main");
+    CREATED_MODULES.lock().unwrap().remove("callee");
+    let (function, available, _) = not_found(err);
+    assert_eq!(function, "run");
+    assert_eq!(available, vec!["other".to_string()]);
+}
+
+#[test]
+fn test_bls_verify() {
+    use blst::min_pk::SecretKey;
+
+    let interface = TestInterface;
+    let secret_key = SecretKey::key_gen(&[1; 32], &[]).unwrap();
+    let public_key = secret_key.sk_to_pk().compress();
+    let signature = secret_key
+        .sign(
+            b"message",
+            b"BLS_SIG_BLS12381G2_XMD:SHA-256_SSWU_RO_POP_",
+            &[],
+        )
+        .compress();
+    // traps unless the signature of "message" is valid
+    let module = |public_key: &[u8]| {
+        let wat = format!(
+            r#"(module
+                (import "massa" "assembly_script_bls_verify"
+                    (func $bls_verify (param i32 i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 12) "\07\00\00\00message")
+                (data (i32.const 32) "{}{}")
+                (data (i32.const 144) "{}{}")
+                (func (export "main")
+                    (if (i32.eqz (call $bls_verify (i32.const 16) (i32.const 36) (i32.const 148)))
+                        (then unreachable))))"#,
+            wat_bytes(&(signature.len() as u32).to_le_bytes()),
+            wat_bytes(&signature),
+            wat_bytes(&(public_key.len() as u32).to_le_bytes()),
+            wat_bytes(public_key),
+        );
+        wasmer::wat2wasm(wat.as_bytes()).unwrap().to_vec()
+    };
+
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    let runtime_module = RuntimeModule::new(
+        &module(&public_key),
+        gas_costs.clone(),
+        &config,
+        Compiler::SP,
+    )
+    .unwrap();
+    run_main(
+        &interface,
+        runtime_module,
+        10_000_000,
+        gas_costs.clone(),
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap();
+
+    // malformed public key
+    let runtime_module = RuntimeModule::new(
+        &module(&public_key[..47]),
+        gas_costs.clone(),
+        &config,
+        Compiler::SP,
+    )
+    .unwrap();
+    let err = run_main(
+        &interface,
+        runtime_module,
+        10_000_000,
+        gas_costs,
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap_err();
+    assert!(
+        err.to_string().contains("invalid BLS public key length"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn test_evm_signature_verify() {
+    let interface = TestInterface;
+    // traps unless the verification of "message" returns `expected`
+    let module = |signature: &[u8], public_key: &[u8], expected: i32| {
+        let wat = format!(
+            r#"(module
+                (import "massa" "assembly_script_evm_signature_verify"
+                    (func $evm_signature_verify (param i32 i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 12) "\07\00\00\00message")
+                (data (i32.const 32) "{}{}")
+                (data (i32.const 144) "{}{}")
+                (func (export "main")
+                    (if (i32.ne
+                            (call $evm_signature_verify (i32.const 16) (i32.const 36) (i32.const 148))
+                            (i32.const {}))
+                        (then unreachable))))"#,
+            wat_bytes(&(signature.len() as u32).to_le_bytes()),
+            wat_bytes(signature),
+            wat_bytes(&(public_key.len() as u32).to_le_bytes()),
+            wat_bytes(public_key),
+            expected,
+        );
+        wasmer::wat2wasm(wat.as_bytes()).unwrap().to_vec()
+    };
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    let run = |signature: &[u8], public_key: &[u8], expected: i32| {
+        let runtime_module = RuntimeModule::new(
+            &module(signature, public_key, expected),
+            gas_costs.clone(),
+            &config,
+            Compiler::SP,
+        )
+        .unwrap();
+        run_main(
+            &interface,
+            runtime_module,
+            10_000_000,
+            gas_costs.clone(),
+            &config,
+            ExecutionOptions::default(),
+        )
+    };
+    let signature = [7u8; 65];
+    let public_key = [1u8; 64];
+    let prefixed_public_key = [&[0x04][..], &public_key].concat();
+    let compressed_public_key = [&[0x02][..], &public_key[..32]].concat();
+
+    // valid signatures, for each format of the public key
+    run(&signature, &public_key, 1).unwrap();
+    run(&signature, &prefixed_public_key, 1).unwrap();
+    run(&signature, &compressed_public_key, 1).unwrap();
+    // a well-formed signature rejected by the interface
+    run(&[0u8; 65], &compressed_public_key, 0).unwrap();
+
+    // malformed inputs never reach the interface
+    let err = run(&signature[..64], &public_key, 1).unwrap_err();
+    assert!(
+        err.to_string().contains("invalid EVM signature length"),
+        "{}",
+        err
+    );
+    for public_key in [&public_key[..32], &[0x04; 33][..], &[1u8; 65][..]] {
+        let err = run(&signature, public_key, 1).unwrap_err();
+        assert!(
+            err.to_string().contains("invalid EVM public key"),
+            "{}",
+            err
+        );
+    }
+}
+
+#[test]
+fn test_get_op_keys_prefix() {
+    let interface = TestInterface;
+    // traps unless the keys returned for the prefix are the `expected` buffer
+    let module = |prefix: &[u8], expected: &[u8]| {
+        let wat = format!(
+            r#"(module
+                (import "massa" "assembly_script_get_op_keys_prefix"
+                    (func $get_op_keys_prefix (param i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 12) "{}{}")
+                (data (i32.const 60) "{}")
+                (func (export "__new") (param i32 i32) (result i32)
+                    (i32.store (i32.const 1020) (local.get 0))
+                    (i32.const 1024))
+                (func (export "main")
+                    (local $keys i32)
+                    (local $i i32)
+                    (local.set $keys (call $get_op_keys_prefix (i32.const 16)))
+                    (if (i32.ne (i32.load (i32.sub (local.get $keys) (i32.const 4))) (i32.const {}))
+                        (then unreachable))
+                    (block $done
+                        (loop $next
+                            (br_if $done (i32.eq (local.get $i) (i32.const {})))
+                            (if (i32.ne
+                                    (i32.load8_u (i32.add (local.get $keys) (local.get $i)))
+                                    (i32.load8_u (i32.add (i32.const 60) (local.get $i))))
+                                (then unreachable))
+                            (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                            (br $next)))))"#,
+            wat_bytes(&(prefix.len() as u32).to_le_bytes()),
+            wat_bytes(prefix),
+            wat_bytes(expected),
+            expected.len(),
+            expected.len(),
+        );
+        wasmer::wat2wasm(wat.as_bytes()).unwrap().to_vec()
+    };
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    let run = |prefix: &[u8], expected: &[u8], config: &RuntimeConfig| {
+        let runtime_module = RuntimeModule::new(
+            &module(prefix, expected),
+            gas_costs.clone(),
+            config,
+            Compiler::SP,
+        )
+        .unwrap();
+        run_main(
+            &interface,
+            runtime_module,
+            10_000_000,
+            gas_costs.clone(),
+            config,
+            ExecutionOptions::default(),
+        )
+    };
+
+    // binary prefixes, not valid UTF-8
+    run(&[254], &[1, 0, 0, 0, 2, 254, 255], &config).unwrap();
+    run(&[127, 128], &[1, 0, 0, 0, 2, 127, 128], &config).unwrap();
+    run(&[0, 2], &[], &config).unwrap();
+    // an empty prefix returns every key, as `assembly_script_get_op_keys`
+    let all_keys = [
+        &[3, 0, 0, 0, 8, 0, 1, 2, 3, 4, 5, 6, 11][..],
+        &[2, 127, 128],
+        &[2, 254, 255],
+    ]
+    .concat();
+    run(&[], &all_keys, &config).unwrap();
+
+    // more keys than allowed by the storage limits
+    let mut capped_config = config;
+    capped_config.storage_limits.max_keys_per_query = 2;
+    run(&[254], &[1, 0, 0, 0, 2, 254, 255], &capped_config).unwrap();
+    let err = run(&[], &all_keys, &capped_config).unwrap_err();
+    assert!(
+        err.to_string().contains("Too many datastore entries"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+#[serial]
+fn test_op_keys_sorted() {
+    use rand::seq::SliceRandom;
+    use std::collections::BTreeSet;
+
+    let interface = TestInterface;
+    // random keys, with one duplicate, given to the guest in a random order
+    let mut rng = rand::thread_rng();
+    let mut keys: Vec<Vec<u8>> = (0..200)
+        .map(|_| {
+            let len = rng.gen_range(1..=3);
+            (0..len).map(|_| rng.gen()).collect()
+        })
+        .collect();
+    keys.push(keys[17].clone());
+    keys.shuffle(&mut rng);
+    let sorted: BTreeSet<Vec<u8>> = keys.iter().cloned().collect();
+    let mut expected = (sorted.len() as u32).to_le_bytes().to_vec();
+    for key in &sorted {
+        expected.push(key.len() as u8);
+        expected.extend_from_slice(key);
+    }
+    OP_KEYS.with(|op_keys| *op_keys.borrow_mut() = Some(keys.clone()));
+
+    // traps unless the keys returned are the `expected` buffer
+    let wat = format!(
+        r#"(module
+            (import "massa" "assembly_script_get_op_keys"
+                (func $get_op_keys (result i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 2048) "{}")
+            (func (export "__new") (param i32 i32) (result i32)
+                (i32.store (i32.const 8188) (local.get 0))
+                (i32.const 8192))
+            (func (export "main")
+                (local $keys i32)
+                (local $i i32)
+                (local.set $keys (call $get_op_keys))
+                (if (i32.ne (i32.load (i32.sub (local.get $keys) (i32.const 4))) (i32.const {}))
+                    (then unreachable))
+                (block $done
+                    (loop $next
+                        (br_if $done (i32.eq (local.get $i) (i32.const {})))
+                        (if (i32.ne
+                                (i32.load8_u (i32.add (local.get $keys) (local.get $i)))
+                                (i32.load8_u (i32.add (i32.const 2048) (local.get $i))))
+                            (then unreachable))
+                        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                        (br $next)))))"#,
+        wat_bytes(&expected),
+        expected.len(),
+        expected.len(),
+    );
+    let module = wasmer::wat2wasm(wat.as_bytes()).unwrap().to_vec();
+    let run = |gas_costs: GasCosts, config: &RuntimeConfig| {
+        let runtime_module =
+            RuntimeModule::new(&module, gas_costs.clone(), config, Compiler::SP).unwrap();
+        run_main(
+            &interface,
+            runtime_module,
+            10_000_000,
+            gas_costs,
+            config,
+            ExecutionOptions::default(),
+        )
+    };
+
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    let sorted_once = run(gas_costs.clone(), &config).unwrap();
+    // the sort of the 201 keys is charged for 201 * 8 comparisons
+    let mut expensive_sort = gas_costs.clone();
+    *expensive_sort
+        .abi_costs
+        .get_mut("assembly_script_get_op_keys_per_comparison")
+        .unwrap() += 10;
+    let expensive = run(expensive_sort, &config).unwrap();
+    assert_eq!(
+        sorted_once.remaining_gas - expensive.remaining_gas,
+        10 * 201 * 8
+    );
+
+    // the keys are counted before being sorted, the duplicate included
+    let mut capped_config = config;
+    capped_config.storage_limits.max_keys_per_query = keys.len() - 1;
+    let err = run(gas_costs, &capped_config).unwrap_err();
+    OP_KEYS.with(|op_keys| op_keys.borrow_mut().take());
+    assert!(
+        err.to_string().contains("Too many datastore entries"),
+        "{}",
+        err
+    );
+}
+
+/// AssemblyScript string at `ptr`, preceded by its length in bytes
+pub(crate) fn wat_as_string(ptr: u32, string: &str) -> String {
+    let utf16: Vec<u8> = string.encode_utf16().flat_map(u16::to_le_bytes).collect();
+    format!(
+        r#"(data (i32.const {}) "{}{}")"#,
+        ptr - 4,
+        wat_bytes(&(utf16.len() as u32).to_le_bytes()),
+        wat_bytes(&utf16)
+    )
+}
+
+#[test]
+#[serial]
+fn test_deferred_calls() {
+    let interface = TestInterface;
+    let wat = format!(
+        r#"(module
+            (import "massa" "assembly_script_deferred_call_register"
+                (func $register (param i32 i32 i64 i32 i64 i32 i64) (result i32)))
+            (import "massa" "assembly_script_deferred_call_exists"
+                (func $exists (param i32) (result i32)))
+            (import "massa" "assembly_script_deferred_call_cancel" (func $cancel (param i32)))
+            (import "massa" "assembly_script_deferred_call_quote"
+                (func $quote (param i64 i32 i64) (result i64)))
+            (memory (export "memory") 1)
+            {}
+            {}
+            (data (i32.const 44) "\03\00\00\00\01\02\03")
+            {}
+            (func (export "__new") (param i32 i32) (result i32)
+                (i32.store (i32.const 1020) (local.get 0))
+                (i32.const 1024))
+            (func $register_at (param $thread i32) (param $max_gas i64) (param $coins i64)
+                (result i32)
+                (call $register (i32.const 16) (i32.const 32) (i64.const 10) (local.get $thread)
+                    (local.get $max_gas) (i32.const 48) (local.get $coins)))
+            (func (export "register")
+                (local $id i32)
+                (local.set $id (call $register_at (i32.const 3) (i64.const 5000) (i64.const 42)))
+                (if (i32.ne (call $exists (local.get $id)) (i32.const 1)) (then unreachable)))
+            (func (export "register_and_cancel")
+                (local $id i32)
+                (local.set $id (call $register_at (i32.const 3) (i64.const 5000) (i64.const 42)))
+                (call $cancel (local.get $id))
+                (if (i32.ne (call $exists (local.get $id)) (i32.const 0)) (then unreachable)))
+            (func (export "invalid_thread")
+                (drop (call $register_at (i32.const 32) (i64.const 5000) (i64.const 42))))
+            (func (export "negative_coins")
+                (drop (call $register_at (i32.const 3) (i64.const 5000) (i64.const -1))))
+            (func (export "zero_max_gas")
+                (drop (call $register_at (i32.const 3) (i64.const 0) (i64.const 42))))
+            (func (export "huge_max_gas")
+                (drop (call $register_at (i32.const 3) (i64.const 1000000000000) (i64.const 42))))
+            (func (export "cancel_foreign")
+                (call $cancel (i32.const 64)))
+            (func (export "quote")
+                (if (i64.ne (call $quote (i64.const 10) (i32.const 3) (i64.const 5000))
+                        (i64.const 15003))
+                    (then unreachable))))"#,
+        wat_as_string(16, "AS12"),
+        wat_as_string(32, "f"),
+        wat_as_string(64, FOREIGN_DEFERRED_CALL_ID),
+    );
+    let module = wasmer::wat2wasm(wat.as_bytes()).unwrap().to_vec();
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    let run = |function: &str| {
+        let runtime_module =
+            RuntimeModule::new(&module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+        run_function(
+            &interface,
+            runtime_module,
+            function,
+            b"",
+            10_000_000,
+            gas_costs.clone(),
+            &config,
+            ExecutionOptions::default(),
+        )
+    };
+    DEFERRED_CALLS.lock().unwrap().clear();
+
+    run("register").unwrap();
+    assert_eq!(
+        DEFERRED_CALLS.lock().unwrap().get("D0"),
+        Some(&(
+            "AS12".to_string(),
+            "f".to_string(),
+            (10, 3),
+            5000,
+            vec![1, 2, 3],
+            42
+        ))
+    );
+    run("register_and_cancel").unwrap();
+    assert_eq!(DEFERRED_CALLS.lock().unwrap().len(), 1);
+    run("quote").unwrap();
+
+    // rejected before reaching the interface
+    for (function, message) in [
+        ("invalid_thread", "invalid deferred call target thread"),
+        (
+            "negative_coins",
+            "negative amount of coins in deferred call",
+        ),
+        ("zero_max_gas", "deferred call max gas must be positive"),
+        ("huge_max_gas", "exceeds the maximum"),
+    ] {
+        let err = run(function).unwrap_err();
+        assert!(err.to_string().contains(message), "{}: {}", function, err);
+    }
+    assert_eq!(DEFERRED_CALLS.lock().unwrap().len(), 1);
+
+    // errors of the interface are returned as is
+    let err = run("cancel_foreign").unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("not the owner of deferred call D_foreign"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+/// Test that the ABI names known by the gas costs are the registered ABIs
+fn test_abi_names_are_registered() {
+    let interface = TestInterface;
+    let module = ASModule::new(
+        include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/wasm/basic_main.wasm")),
+        100_000,
+        GasCosts::default(),
+        &RuntimeConfig::default(),
+        Compiler::SP,
+    )
+    .unwrap();
+    let mut store = Store::new(module._engine);
+    let context = ASContext::new(
+        &interface,
+        module.binary_module,
+        GasCosts::default(),
+        Default::default(),
+    );
+    let (as_imports, _) = context.resolver(&mut store);
+    let mut registered: Vec<String> = (&as_imports)
+        .into_iter()
+        .filter(|((namespace, _), _)| namespace == "massa")
+        .map(|((_, name), _)| name)
+        .collect();
+    // AssemblyScript builtins, charged under the name of their ABI
+    registered.extend(
+        [
+            "abort",
+            "seed",
+            "date_now",
+            "console_log",
+            "console_info",
+            "console_warn",
+            "console_error",
+            "console_debug",
+            "trace",
+        ]
+        .map(|name| format!("assembly_script_{}", name)),
+    );
+    let mut store = Store::default();
+    let wasmv1_imports =
+        crate::wasmv1_execution::register_abis(&mut store, Default::default(), &Default::default());
+    registered.extend((&wasmv1_imports).into_iter().map(|((_, name), _)| name));
+    registered.sort();
+
+    let mut names = GasCosts::abi_names();
+    names.sort();
+    assert_eq!(registered, names);
+}
+
+#[test]
+#[serial]
+#[cfg(feature = "cranelift")]
+/// Test that a contract and its nested calls use the compiler of the
+/// top-level module, with the same gas usage and results for both compilers
+fn test_compiler_per_execution() {
+    let interface = TestInterface;
+    // sums 0..1000 in a loop and returns the sum
+    let callee = wasmer::wat2wasm(
+        br#"(module
+            (memory (export "memory") 1)
+            (data (i32.const 12) "\04\00\00\00")
+            (func (export "sum") (result i32)
+                (local $i i32)
+                (local $sum i32)
+                (block $done
+                    (loop $next
+                        (br_if $done (i32.eq (local.get $i) (i32.const 1000)))
+                        (local.set $sum (i32.add (local.get $sum) (local.get $i)))
+                        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                        (br $next)))
+                (i32.store (i32.const 16) (local.get $sum))
+                (i32.const 16)))"#,
+    )
+    .unwrap();
+    CREATED_MODULES
+        .lock()
+        .unwrap()
+        .insert("compilers".to_string(), callee);
+    // returns the sum computed by the callee
+    let caller = wasmer::wat2wasm(
+        br#"(module
+            (import "massa" "assembly_script_call"
+                (func $call (param i32 i32 i32 i64) (result i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 28) "\06\00\00\00s\00u\00m\00")
+            (data (i32.const 44) "\00\00\00\00")
+            (data (i32.const 60) "\12\00\00\00c\00o\00m\00p\00i\00l\00e\00r\00s\00")
+            (func (export "__new") (param i32 i32) (result i32)
+                (i32.store (i32.const 1020) (local.get 0))
+                (i32.const 1024))
+            (func (export "run") (result i32)
+                (call $call (i32.const 64) (i32.const 32) (i32.const 48) (i64.const 0))))"#,
+    )
+    .unwrap();
+
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    let responses: Vec<_> = [Compiler::SP, Compiler::CL]
+        .into_iter()
+        .map(|compiler| {
+            REQUESTED_COMPILERS.with(|compilers| compilers.borrow_mut().clear());
+            let runtime_module =
+                RuntimeModule::new(&caller, gas_costs.clone(), &config, compiler.clone()).unwrap();
+            let response = run_function(
+                &interface,
+                runtime_module,
+                "run",
+                b"",
+                10_000_000,
+                gas_costs.clone(),
+                &config,
+                ExecutionOptions::default(),
+            )
+            .unwrap();
+            REQUESTED_COMPILERS.with(|compilers| assert_eq!(*compilers.borrow(), [compiler]));
+            response
+        })
+        .collect();
+    CREATED_MODULES.lock().unwrap().remove("compilers");
+
+    assert_eq!(responses[0].ret, 499_500u32.to_le_bytes());
+    assert_eq!(responses[0].ret, responses[1].ret);
+    assert_eq!(responses[0].remaining_gas, responses[1].remaining_gas);
+    assert_eq!(responses[0].init_gas_cost, responses[1].init_gas_cost);
+}
+
+#[test]
+#[serial]
+/// Test that the operators are charged from the operator table of the gas
+/// costs, identically whatever the compiler
+fn test_operator_costs() {
+    let interface = TestInterface;
+    // 2000 `i32.add` executed
+    let module = wasmer::wat2wasm(
+        br#"(module
+            (memory (export "memory") 1)
+            (func (export "main")
+                (local $i i32)
+                (local $sum i32)
+                (block $done
+                    (loop $next
+                        (br_if $done (i32.eq (local.get $i) (i32.const 1000)))
+                        (local.set $sum (i32.add (local.get $sum) (local.get $i)))
+                        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                        (br $next)))))"#,
+    )
+    .unwrap();
+    let run = |gas_costs: GasCosts, compiler: Compiler| {
+        let runtime_module = RuntimeModule::new(
+            &module,
+            gas_costs.clone(),
+            &RuntimeConfig::default(),
+            compiler,
+        )
+        .unwrap();
+        run_main(
+            &interface,
+            runtime_module,
+            10_000_000,
+            gas_costs,
+            &RuntimeConfig::default(),
+            ExecutionOptions::default(),
+        )
+        .unwrap()
+        .remaining_gas
+    };
+    let flat = GasCosts::default();
+    let mut priced = GasCosts::default();
+    priced.operator_costs.insert("I32Add".to_string(), 100);
+    // names that are not operators are never looked up
+    priced
+        .operator_costs
+        .insert("NotAnOperator".to_string(), 1_000_000);
+
+    let mut remaining_gas = Vec::new();
+    for compiler in [
+        Compiler::SP,
+        #[cfg(feature = "cranelift")]
+        Compiler::CL,
+    ] {
+        let flat_remaining_gas = run(flat.clone(), compiler.clone());
+        let priced_remaining_gas = run(priced.clone(), compiler);
+        assert_eq!(
+            flat_remaining_gas - priced_remaining_gas,
+            2000 * (100 - flat.operator_cost)
+        );
+        remaining_gas.push((flat_remaining_gas, priced_remaining_gas));
+    }
+    assert!(remaining_gas.windows(2).all(|pair| pair[0] == pair[1]));
+}
+
+#[test]
+#[serial]
+fn test_read_only_mode() {
+    use massa_proto_rs::massa::{abi::v1::CallRequest, model::v1::NativeAmount};
+    use prost::Message;
+
+    let interface = TestInterface;
+    // the mutating ABIs, the write check comes before any argument is read
+    let writes: &[(&str, &str, &str)] = &[
+        ("create_sc", "i32", "(result i32)"),
+        ("create_sc_with_data", "i32 i32", "(result i32)"),
+        ("set_data", "i32 i32", ""),
+        ("set_data_batch", "i32", ""),
+        ("set_data_for", "i32 i32 i32", ""),
+        ("delete_data", "i32", ""),
+        ("delete_data_for", "i32 i32", ""),
+        ("append_data", "i32 i32", ""),
+        ("append_data_for", "i32 i32 i32", ""),
+        ("transfer_coins", "i32 i64", ""),
+        ("transfer_coins_for", "i32 i32 i64", ""),
+        ("transfer_coins_checked", "i32 i64", "(result i32)"),
+        (
+            "send_message",
+            "i32 i32 i64 i32 i64 i32 i64 i64 i64 i32 i32 i32",
+            "",
+        ),
+        ("send_async_message", "i32", ""),
+        (
+            "deferred_call_register",
+            "i32 i32 i64 i32 i64 i32 i64",
+            "(result i32)",
+        ),
+        ("deferred_call_cancel", "i32", ""),
+        ("set_bytecode", "i32", ""),
+        ("set_bytecode_for", "i32 i32", ""),
+    ];
+    let zero = |ty: &str| format!("({}.const 0)", ty);
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    let run = |module: &[u8], function: &str, mode: ExecutionMode| {
+        let runtime_module =
+            RuntimeModule::new(module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+        run_function(
+            &interface,
+            runtime_module,
+            function,
+            b"",
+            10_000_000,
+            gas_costs.clone(),
+            &config,
+            ExecutionOptions {
+                mode,
+                ..Default::default()
+            },
+        )
+    };
+    let assert_read_only = |result: Result<_, VMError>, abi_name: &str| {
+        let err = result.unwrap_err().to_string();
+        assert!(
+            err.contains(&format!(
+                "Write in read-only mode: {} is not allowed",
+                abi_name
+            )),
+            "{}",
+            err
+        );
+    };
+
+    for (name, params, result) in writes {
+        let args: Vec<String> = params.split(' ').map(zero).collect();
+        let call = format!("(call $abi {})", args.join(" "));
+        let wat = format!(
+            r#"(module
+                (import "massa" "assembly_script_{name}" (func $abi (param {params}) {result}))
+                (memory (export "memory") 1)
+                (func (export "run") {}))"#,
+            if result.is_empty() {
+                call
+            } else {
+                format!("(drop {})", call)
+            },
+        );
+        let module = wasmer::wat2wasm(wat.as_bytes()).unwrap();
+        let abi_name = format!("assembly_script_{}", name);
+        assert_read_only(run(&module, "run", ExecutionMode::ReadOnly), &abi_name);
+    }
+
+    // reads and calls without coins are allowed, nested calls inherit the mode
+    let callee = wasmer::wat2wasm(
+        br#"(module
+            (import "massa" "assembly_script_set_data" (func $set_data (param i32 i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 12) "\01\00\00\00k")
+            (func (export "run") (result i32)
+                (call $set_data (i32.const 16) (i32.const 16))
+                (i32.const 16)))"#,
+    )
+    .unwrap();
+    CREATED_MODULES
+        .lock()
+        .unwrap()
+        .insert("read_only_callee".to_string(), callee);
+    let wat = format!(
+        r#"(module
+            (import "massa" "assembly_script_has_data" (func $has_data (param i32) (result i32)))
+            (import "massa" "assembly_script_get_data" (func $get_data (param i32) (result i32)))
+            (import "massa" "assembly_script_set_data" (func $set_data (param i32 i32)))
+            (import "massa" "assembly_script_call"
+                (func $call (param i32 i32 i32 i64) (result i32)))
+            (import "massa" "assembly_script_local_execution_with_coins"
+                (func $local_execution (param i32 i32 i32 i64) (result i32)))
+            (import "massa" "assembly_script_try_call"
+                (func $try_call (param i32 i32 i32 i64) (result i32)))
+            (import "massa" "assembly_script_call_with_gas_limit"
+                (func $call_with_gas_limit (param i32 i32 i32 i64 i64) (result i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 12) "\01\00\00\00k")
+            {}
+            {}
+            (func (export "__new") (param i32 i32) (result i32)
+                (i32.store (i32.const 1020) (local.get 0))
+                (i32.const 1024))
+            (func (export "read")
+                (drop (call $has_data (i32.const 16)))
+                (drop (call $get_data (i32.const 16))))
+            (func (export "write")
+                (call $set_data (i32.const 16) (i32.const 16)))
+            (func (export "nested_write")
+                (drop (call $call (i32.const 32) (i32.const 80) (i32.const 16) (i64.const 0))))
+            (func (export "call_with_coins")
+                (drop (call $call (i32.const 32) (i32.const 80) (i32.const 16) (i64.const 1))))
+            (func (export "local_execution_with_coins")
+                (drop (call $local_execution
+                    (i32.const 16) (i32.const 80) (i32.const 16) (i64.const 1))))
+            (func (export "try_call_with_coins")
+                (drop (call $try_call (i32.const 32) (i32.const 80) (i32.const 16) (i64.const 1))))
+            (func (export "call_with_gas_limit_with_coins")
+                (drop (call $call_with_gas_limit
+                    (i32.const 32) (i32.const 80) (i32.const 16) (i64.const 1) (i64.const 1000)))))"#,
+        wat_as_string(32, "read_only_callee"),
+        wat_as_string(80, "run"),
+    );
+    let module = wasmer::wat2wasm(wat.as_bytes()).unwrap();
+
+    run(&module, "read", ExecutionMode::ReadOnly).unwrap();
+    run(&module, "write", ExecutionMode::ReadWrite).unwrap();
+    run(&module, "nested_write", ExecutionMode::ReadWrite).unwrap();
+    assert_read_only(
+        run(&module, "write", ExecutionMode::ReadOnly),
+        "assembly_script_set_data",
+    );
+    assert_read_only(
+        run(&module, "nested_write", ExecutionMode::ReadOnly),
+        "assembly_script_set_data",
+    );
+    assert_read_only(
+        run(&module, "call_with_coins", ExecutionMode::ReadOnly),
+        "assembly_script_call",
+    );
+    assert_read_only(
+        run(
+            &module,
+            "local_execution_with_coins",
+            ExecutionMode::ReadOnly,
+        ),
+        "assembly_script_local_execution_with_coins",
+    );
+    for abi in ["try_call", "call_with_gas_limit"] {
+        assert_read_only(
+            run(
+                &module,
+                &format!("{}_with_coins", abi),
+                ExecutionMode::ReadOnly,
+            ),
+            &format!("assembly_script_{}", abi),
+        );
+    }
+
+    // the wasmv1 calls with coins are refused as well
+    let request = CallRequest {
+        target_sc_address: "read_only_callee".to_string(),
+        target_function_name: "run".to_string(),
+        function_arg: Vec::new(),
+        call_coins: Some(NativeAmount {
+            mantissa: 1,
+            scale: 0,
+        }),
+    }
+    .encode_to_vec();
+    let mut wasmv1_module = vec![1];
+    wasmv1_module.extend(
+        wasmer::wat2wasm(
+            format!(
+                r#"(module
+                    (import "massa" "abi_call" (func $call (param i32) (result i32)))
+                    (memory (export "memory") 1)
+                    (data (i32.const 64) "{}{}")
+                    (func (export "__alloc") (param i32) (result i32)
+                        (i32.store (i32.const 4092) (local.get 0))
+                        (i32.const 4096))
+                    (func (export "call_with_coins") (param i32) (result i32)
+                        (call $call (i32.const 64))))"#,
+                wat_bytes(&(request.len() as u32).to_le_bytes()),
+                wat_bytes(&request),
+            )
+            .as_bytes(),
+        )
+        .unwrap()
+        .iter(),
+    );
+    assert_read_only(
+        run(&wasmv1_module, "call_with_coins", ExecutionMode::ReadOnly),
+        "abi_call",
+    );
+    CREATED_MODULES.lock().unwrap().remove("read_only_callee");
+}
+
+#[test]
+#[serial]
+fn test_invalid_pointers() {
+    let interface = TestInterface;
+    let module = wasmer::wat2wasm(
+        br#"(module
+            (import "massa" "assembly_script_has_data" (func $has_data (param i32) (result i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 12) "\10\00\00\00")
+            (data (i32.const 60) "\ff\ff\00\00")
+            (func (export "null") (drop (call $has_data (i32.const 0))))
+            (func (export "out_of_memory") (drop (call $has_data (i32.const 70000))))
+            (func (export "negative") (drop (call $has_data (i32.const -1))))
+            (func (export "overflowing_len") (drop (call $has_data (i32.const 64))))
+            (func (export "long_read") (drop (call $has_data (i32.const 16)))))"#,
+    )
+    .unwrap();
+    let run = |function: &str, config: &RuntimeConfig| {
+        let runtime_module =
+            RuntimeModule::new(&module, GasCosts::default(), config, Compiler::SP).unwrap();
+        run_function(
+            &interface,
+            runtime_module,
+            function,
+            b"",
+            10_000_000,
+            GasCosts::default(),
+            config,
+            ExecutionOptions::default(),
+        )
+        .unwrap_err()
+        .to_string()
+    };
+
+    for (function, message) in [
+        (
+            "null",
+            "Invalid pointer in assembly_script_has_data: 4 bytes at offset 0 are out of the 65536 bytes of memory",
+        ),
+        (
+            "out_of_memory",
+            "Invalid pointer in assembly_script_has_data: 4 bytes at offset 70000 are out of the 65536 bytes of memory",
+        ),
+        (
+            "negative",
+            "Invalid pointer in assembly_script_has_data: 4 bytes at offset 4294967295 are out of the 65536 bytes of memory",
+        ),
+        (
+            "overflowing_len",
+            "Invalid pointer in assembly_script_has_data: 65535 bytes at offset 64 are out of the 65536 bytes of memory",
+        ),
+    ] {
+        let err = run(function, &RuntimeConfig::default());
+        assert!(err.contains(message), "{}: {}", function, err);
+    }
+    run_function(
+        &interface,
+        RuntimeModule::new(
+            &module,
+            GasCosts::default(),
+            &RuntimeConfig::default(),
+            Compiler::SP,
+        )
+        .unwrap(),
+        "long_read",
+        b"",
+        10_000_000,
+        GasCosts::default(),
+        &RuntimeConfig::default(),
+        ExecutionOptions::default(),
+    )
+    .unwrap();
+    let config = RuntimeConfig {
+        max_abi_read_len: 8,
+        ..RuntimeConfig::default()
+    };
+    let err = run("long_read", &config);
+    assert!(
+        err.contains("Read of 16 bytes in assembly_script_has_data exceeds the maximum of 8 bytes"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+#[serial]
+fn test_invalid_pointers_wasmv1() {
+    let interface = TestInterface;
+    let module = |alloc_offset: u32| {
+        let wat = format!(
+            r#"(module
+                (import "massa" "abi_get_remaining_gas" (func $gas (param i32) (result i32)))
+                (memory (export "memory") 1)
+                (func (export "__alloc") (param i32) (result i32) (i32.const {}))
+                (func (export "bogus_arg") (param i32) (result i32)
+                    (call $gas (i32.const 70000)))
+                (func (export "overflowing_len") (param i32) (result i32)
+                    (i32.store (i32.const 2000) (i32.const 0xffff))
+                    (call $gas (i32.const 2000)))
+                (func (export "long_read") (param i32) (result i32)
+                    (i32.store (i32.const 2000) (i32.const 16))
+                    (call $gas (i32.const 2000)))
+                (func (export "bogus_ret") (param i32) (result i32)
+                    (i32.const 70000)))"#,
+            alloc_offset
+        );
+        let mut bytecode = vec![1];
+        bytecode.extend(wasmer::wat2wasm(wat.as_bytes()).unwrap().iter());
+        bytecode
+    };
+    let run = |bytecode: &[u8], function: &str, config: &RuntimeConfig| {
+        let runtime_module =
+            RuntimeModule::new(bytecode, GasCosts::default(), config, Compiler::SP).unwrap();
+        run_function(
+            &interface,
+            runtime_module,
+            function,
+            b"abc",
+            10_000_000,
+            GasCosts::default(),
+            config,
+            ExecutionOptions::default(),
+        )
+        .unwrap_err()
+        .to_string()
+    };
+
+    let valid = module(1024);
+    for (function, message) in [
+        (
+            "bogus_arg",
+            "Invalid pointer in abi_get_remaining_gas: 4 bytes at offset 70000 are out of the 65536 bytes of memory",
+        ),
+        (
+            "overflowing_len",
+            "Invalid pointer in abi_get_remaining_gas: 65535 bytes at offset 2000 are out of the 65536 bytes of memory",
+        ),
+        (
+            "bogus_ret",
+            "Invalid pointer in bogus_ret: 4 bytes at offset 70000 are out of the 65536 bytes of memory",
+        ),
+    ] {
+        let err = run(&valid, function, &RuntimeConfig::default());
+        assert!(err.contains(message), "{}: {}", function, err);
+    }
+    let config = RuntimeConfig {
+        max_abi_read_len: 8,
+        ..RuntimeConfig::default()
+    };
+    let err = run(&valid, "long_read", &config);
+    assert!(
+        err.contains("Read of 16 bytes in abi_get_remaining_gas exceeds the maximum of 8 bytes"),
+        "{}",
+        err
+    );
+
+    // the allocation of the argument is checked before it is written
+    let err = run(&module(65535), "bogus_arg", &RuntimeConfig::default());
+    assert!(
+        err.contains(
+            "Invalid pointer in bogus_arg: 3 bytes at offset 65535 are out of the 65536 bytes of memory"
+        ),
+        "{}",
+        err
+    );
+}
+
+#[test]
+#[serial]
+fn test_memory_grow() {
+    let interface = TestInterface;
+    let module = wasmer::wat2wasm(
+        br#"(module
+            (memory (export "memory") 1)
+            (data (i32.const 12) "\00\00\00\00")
+            (func $grow_3 (param $pages i32)
+                (local $i i32)
+                (loop $next
+                    (if (i32.eq (memory.grow (local.get $pages)) (i32.const -1))
+                        (then unreachable))
+                    (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                    (br_if $next (i32.lt_u (local.get $i) (i32.const 3)))))
+            (func (export "grow_3") (call $grow_3 (i32.const 1)))
+            (func (export "run") (result i32) (call $grow_3 (i32.const 1)) (i32.const 16))
+            (func (export "nop") (result i32) (call $grow_3 (i32.const 0)) (i32.const 16))
+            (func (export "grow_forever")
+                (loop $next
+                    (drop (memory.grow (i32.const 1)))
+                    (br $next)))
+            (func (export "grow_too_much")
+                (if (i32.ne (memory.grow (i32.const 10)) (i32.const -1)) (then unreachable))))"#,
+    )
+    .unwrap();
+    let settings = |page_cost: u64, memory_limit_mode: MemoryLimitMode| {
+        let gas_costs = GasCosts {
+            memory_page_cost: page_cost,
+            ..GasCosts::default()
+        };
+        let config = RuntimeConfig {
+            condom_limits: CondomLimits {
+                max_memory_pages: 4,
+                memory_limit_mode,
+                ..CondomLimits::default()
+            },
+            ..RuntimeConfig::default()
+        };
+        (gas_costs, config)
+    };
+    let run = |module: &[u8],
+               function: &str,
+               (gas_costs, config): (GasCosts, RuntimeConfig),
+               compiler: Compiler| {
+        let runtime_module =
+            RuntimeModule::new(module, gas_costs.clone(), &config, compiler).unwrap();
+        run_function(
+            &interface,
+            runtime_module,
+            function,
+            b"",
+            1_000_000,
+            gas_costs,
+            &config,
+            ExecutionOptions::default(),
+        )
+    };
+    // calls `run` or `nop` on the module above, which is compiled with the
+    // default gas costs
+    CREATED_MODULES
+        .lock()
+        .unwrap()
+        .insert("memory_grow".to_string(), module.clone());
+    let caller = format!(
+        r#"(module
+            (import "massa" "assembly_script_call"
+                (func $call (param i32 i32 i32 i64) (result i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 12) "\00\00\00\00")
+            {}
+            {}
+            (func (export "__new") (param i32 i32) (result i32)
+                (i32.store (i32.const 1020) (local.get 0))
+                (i32.const 1024))
+            (func (export "call")
+                (drop (call $call (i32.const 32) (i32.const 64) (i32.const 16) (i64.const 0))))
+            (func (export "call_nop")
+                (drop (call $call (i32.const 32) (i32.const 80) (i32.const 16) (i64.const 0)))))"#,
+        wat_as_string(32, "memory_grow"),
+        wat_as_string(64, "run"),
+        wat_as_string(80, "nop"),
+    );
+    let caller = wasmer::wat2wasm(caller.as_bytes()).unwrap();
+
+    for compiler in [
+        Compiler::SP,
+        #[cfg(feature = "cranelift")]
+        Compiler::CL,
+    ] {
+        // each page grown is charged
+        let free = run(
+            &module,
+            "grow_3",
+            settings(0, MemoryLimitMode::Trap),
+            compiler.clone(),
+        )
+        .unwrap()
+        .remaining_gas;
+        let charged = run(
+            &module,
+            "grow_3",
+            settings(1_000, MemoryLimitMode::Trap),
+            compiler.clone(),
+        )
+        .unwrap()
+        .remaining_gas;
+        assert_eq!(free - charged, 3 * 1_000);
+        // by the callees as well
+        let no_growth = run(&caller, "call_nop", Default::default(), compiler.clone())
+            .unwrap()
+            .remaining_gas;
+        let growth = run(&caller, "call", Default::default(), compiler.clone())
+            .unwrap()
+            .remaining_gas;
+        assert_eq!(no_growth - growth, 3 * GasCosts::default().memory_page_cost);
+        let err = run(
+            &module,
+            "grow_3",
+            settings(400_000, MemoryLimitMode::Trap),
+            compiler.clone(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, VMError::OutOfGas { .. }), "{}", err);
+
+        // the 5th page is above the limit
+        let err = run(
+            &module,
+            "grow_forever",
+            settings(1_000, MemoryLimitMode::Trap),
+            compiler.clone(),
+        )
+        .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("Memory limit exceeded: memory.grow above the maximum of 4 pages"),
+            "{}",
+            err
+        );
+        // memory.grow keeps returning -1 until the gas runs out
+        let err = run(
+            &module,
+            "grow_forever",
+            settings(1_000, MemoryLimitMode::Fail),
+            compiler.clone(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, VMError::OutOfGas { .. }), "{}", err);
+        run(
+            &module,
+            "grow_too_much",
+            settings(1_000, MemoryLimitMode::Fail),
+            compiler,
+        )
+        .unwrap();
+    }
+    CREATED_MODULES.lock().unwrap().remove("memory_grow");
+}
+
+#[test]
+#[serial]
+fn test_bulk_memory() {
+    let interface = TestInterface;
+    let functions = [
+        (
+            "copy_1k",
+            "(memory.copy (i32.const 65536) (i32.const 0) (i32.const 1024))",
+        ),
+        (
+            "copy_64k",
+            "(memory.copy (i32.const 65536) (i32.const 0) (i32.const 65536))",
+        ),
+        (
+            "fill_1k",
+            "(memory.fill (i32.const 65536) (i32.const 7) (i32.const 1024))",
+        ),
+        (
+            "fill_64k",
+            "(memory.fill (i32.const 65536) (i32.const 7) (i32.const 65536))",
+        ),
+        (
+            "sign_extend",
+            "(if (i32.ne (i32.extend8_s (i32.const 255)) (i32.const -1)) (then unreachable))
+            (if (i64.ne (i64.extend32_s (i64.const 0x80000000)) (i64.const -2147483648))
+                (then unreachable))",
+        ),
+    ];
+    // the same functions for both ABI versions, the wasmv1 ones taking and
+    // returning the buffer of the call
+    let as_module = wasmer::wat2wasm(
+        format!(
+            r#"(module
+                (memory (export "memory") 2)
+                {})"#,
+            functions
+                .iter()
+                .map(|(name, body)| format!(r#"(func (export "{}") {})"#, name, body))
+                .collect::<String>()
+        )
+        .as_bytes(),
+    )
+    .unwrap()
+    .to_vec();
+    let mut wasmv1_module = vec![1];
+    wasmv1_module.extend(
+        wasmer::wat2wasm(
+            format!(
+                r#"(module
+                    (memory (export "memory") 2)
+                    (func (export "__alloc") (param i32) (result i32)
+                        (i32.store (i32.const 1020) (local.get 0))
+                        (i32.const 1024))
+                    {})"#,
+                functions
+                    .iter()
+                    .map(|(name, body)| format!(
+                        r#"(func (export "{}") (param i32) (result i32)
+                            {}
+                            (i32.sub (local.get 0) (i32.const 4)))"#,
+                        name, body
+                    ))
+                    .collect::<String>()
+            )
+            .as_bytes(),
+        )
+        .unwrap()
+        .iter(),
+    );
+    let gas_costs = |byte_cost: u64| GasCosts {
+        bulk_memory_byte_cost: byte_cost,
+        ..GasCosts::default()
+    };
+    let run = |module: &[u8], function: &str, gas_costs: GasCosts, compiler: Compiler| {
+        let runtime_module = RuntimeModule::new(
+            module,
+            gas_costs.clone(),
+            &RuntimeConfig::default(),
+            compiler,
+        )
+        .unwrap();
+        run_function(
+            &interface,
+            runtime_module,
+            function,
+            b"",
+            1_000_000,
+            gas_costs,
+            &RuntimeConfig::default(),
+            ExecutionOptions::default(),
+        )
+    };
+
+    for module in [&as_module, &wasmv1_module] {
+        for compiler in [
+            Compiler::SP,
+            #[cfg(feature = "cranelift")]
+            Compiler::CL,
+        ] {
+            let remaining_gas = |function: &str, byte_cost: u64| {
+                run(module, function, gas_costs(byte_cost), compiler.clone())
+                    .unwrap()
+                    .remaining_gas
+            };
+            // the gas scales with the bytes handled, which are free at a cost
+            // of 0
+            for (short, long) in [("copy_1k", "copy_64k"), ("fill_1k", "fill_64k")] {
+                assert_eq!(remaining_gas(short, 0), remaining_gas(long, 0));
+                assert_eq!(
+                    remaining_gas(short, 3) - remaining_gas(long, 3),
+                    3 * (65_536 - 1_024)
+                );
+            }
+            let default_cost = GasCosts::default().bulk_memory_byte_cost;
+            assert_eq!(
+                remaining_gas("copy_1k", default_cost) - remaining_gas("copy_64k", default_cost),
+                default_cost * (65_536 - 1_024)
+            );
+            run(module, "sign_extend", GasCosts::default(), compiler.clone()).unwrap();
+
+            // a copy the gas cannot pay for is not run
+            let err = run(module, "copy_64k", gas_costs(100), compiler.clone()).unwrap_err();
+            assert!(matches!(err, VMError::OutOfGas { .. }), "{}", err);
+        }
+    }
+}
+
+#[test]
+#[serial]
+fn test_transfer_coins_checked() {
+    let interface = TestInterface;
+    let wat = format!(
+        r#"(module
+            (import "massa" "assembly_script_transfer_coins_checked"
+                (func $transfer (param i32 i64) (result i32)))
+            (memory (export "memory") 1)
+            {}
+            {}
+            {}
+            (func (export "__new") (param i32 i32) (result i32)
+                (i32.store (i32.const 1020) (local.get 0))
+                (i32.const 1024))
+            (func (export "transfer") (result i32)
+                (call $transfer (i32.const 32) (i64.const 100)))
+            (func (export "insufficient_balance") (result i32)
+                (call $transfer (i32.const 32) (i64.const {})))
+            (func (export "invalid_address") (result i32)
+                (call $transfer (i32.const 64) (i64.const 100)))
+            (func (export "overflow") (result i32)
+                (call $transfer (i32.const 128) (i64.const 1)))
+            (func (export "negative_amount") (result i32)
+                (call $transfer (i32.const 32) (i64.const -1))))"#,
+        wat_as_string(32, "AU12"),
+        wat_as_string(64, INVALID_ADDRESS),
+        wat_as_string(128, FULL_BALANCE_ADDRESS),
+        TEST_BALANCE + 1,
+    );
+    let module = wasmer::wat2wasm(wat.as_bytes()).unwrap();
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    let run = |function: &str| {
+        let runtime_module =
+            RuntimeModule::new(&module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+        run_function(
+            &interface,
+            runtime_module,
+            function,
+            b"",
+            1_000_000,
+            gas_costs.clone(),
+            &config,
+            ExecutionOptions::default(),
+        )
+    };
+
+    assert_eq!(run("transfer").unwrap().ret, [1]);
+    for (function, error) in [
+        ("insufficient_balance", TransferError::InsufficientBalance),
+        ("invalid_address", TransferError::InvalidAddress),
+        ("overflow", TransferError::Overflow),
+    ] {
+        assert_eq!(run(function).unwrap().ret, [0, error as u8], "{}", function);
+    }
+    // still checked by the runtime
+    let err = run("negative_amount").unwrap_err();
+    assert!(err.to_string().contains("Negative raw amount."), "{}", err);
+}
+
+#[test]
+#[serial]
+fn test_instance_pool_isolation() {
+    let interface = TestInterface;
+    // fails unless the memory, the data segments and the globals are the
+    // ones of a new instance, then modifies them all
+    let bytecode = wasmer::wat2wasm(
+        br#"(module
+            (memory (export "memory") 1)
+            (data (i32.const 16) "\2a\00\00\00")
+            (global $count (mut i32) (i32.const 0))
+            (func (export "run")
+                (if (i32.ne (i32.load (i32.const 2048)) (i32.const 0)) (then unreachable))
+                (if (i32.ne (i32.load (i32.const 16)) (i32.const 42)) (then unreachable))
+                (if (i32.ne (global.get $count) (i32.const 0)) (then unreachable))
+                (if (i32.ne (memory.size) (i32.const 1)) (then unreachable))
+                (i32.store (i32.const 2048) (i32.const 1))
+                (i32.store (i32.const 16) (i32.const 0))
+                (global.set $count (i32.const 1))
+                (drop (memory.grow (i32.const 1)))))"#,
+    )
+    .unwrap();
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    let pooled = RuntimeModule::new_with_instance_pool(
+        &bytecode,
+        gas_costs.clone(),
+        &config,
+        Compiler::SP,
+        1,
+    )
+    .unwrap();
+    let pool_len = |module: &RuntimeModule| match module {
+        RuntimeModule::ASModule(module) => module.instance_pool.as_ref().unwrap().len(),
+        RuntimeModule::WasmV1Module(_) => unreachable!(),
+    };
+    let run = |module: &RuntimeModule| {
+        run_function(
+            &interface,
+            module.clone(),
+            "run",
+            b"",
+            1_000_000,
+            gas_costs.clone(),
+            &config,
+            ExecutionOptions::default(),
+        )
+        .unwrap()
+    };
+
+    let unpooled =
+        run(&RuntimeModule::new(&bytecode, gas_costs.clone(), &config, Compiler::SP).unwrap());
+    let first = run(&pooled);
+    assert_eq!(pool_len(&pooled), 1);
+    // the pooled store is reused by a fresh instance
+    let second = run(&pooled);
+    assert_eq!(pool_len(&pooled), 1);
+    for response in [&first, &second] {
+        assert_eq!(response.remaining_gas, unpooled.remaining_gas);
+        assert_eq!(response.init_gas_cost, unpooled.init_gas_cost);
+    }
+
+    // the stores of failed executions are not pooled
+    let failing = RuntimeModule::new_with_instance_pool(
+        &wasmer::wat2wasm(
+            br#"(module (memory (export "memory") 1) (func (export "run") unreachable))"#,
+        )
+        .unwrap(),
+        gas_costs.clone(),
+        &config,
+        Compiler::SP,
+        1,
+    )
+    .unwrap();
+    run_function(
+        &interface,
+        failing.clone(),
+        "run",
+        b"",
+        1_000_000,
+        gas_costs.clone(),
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap_err();
+    assert_eq!(pool_len(&failing), 0);
+}
+
+#[test]
+#[serial]
+fn test_vm_error_codes() {
+    use crate::VMErrorCode;
+
+    let interface = TestInterface;
+    let limit = 1_000_000;
+    let module = wasmer::wat2wasm(
+        format!(
+            r#"(module
+                (import "env" "abort" (func $abort (param i32 i32 i32 i32)))
+                (import "env" "process.exit" (func $exit (param i32)))
+                (import "massa" "assembly_script_set_data" (func $set_data (param i32 i32)))
+                (import "massa" "assembly_script_get_time" (func $time (result i64)))
+                (import "massa" "assembly_script_call"
+                    (func $call (param i32 i32 i32 i64) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 12) "\00\00\00\00")
+                {}
+                {}
+                {}
+                {}
+                (func (export "__new") (param i32 i32) (result i32)
+                    (i32.store (i32.const 1020) (local.get 0))
+                    (i32.const 1024))
+                (func (export "trap") unreachable)
+                (func (export "loop") (loop $next (br $next)))
+                (func (export "abort")
+                    (call $abort (i32.const 32) (i32.const 64) (i32.const 1) (i32.const 2)))
+                (func (export "exit") (call $exit (i32.const 2)))
+                (func (export "set_data") (call $set_data (i32.const 16) (i32.const 16)))
+                (func (export "busy") (loop $next (drop (call $time)) (br $next)))
+                (func (export "call_abort")
+                    (drop (call $call (i32.const 96) (i32.const 128) (i32.const 16) (i64.const 0)))))"#,
+            wat_as_string(32, "failed"),
+            wat_as_string(64, "sc.ts"),
+            wat_as_string(96, "error_codes"),
+            wat_as_string(128, "abort"),
+        )
+        .as_bytes(),
+    )
+    .unwrap();
+    CREATED_MODULES
+        .lock()
+        .unwrap()
+        .insert("error_codes".to_string(), module.clone());
+    let run = |function: &str, gas_costs: GasCosts, mode: ExecutionMode| {
+        let runtime_module = RuntimeModule::new(
+            &module,
+            gas_costs.clone(),
+            &RuntimeConfig::default(),
+            Compiler::SP,
+        )
+        .unwrap();
+        run_function(
+            &interface,
+            runtime_module,
+            function,
+            b"",
+            limit,
+            gas_costs,
+            &RuntimeConfig::default(),
+            ExecutionOptions {
+                mode,
+                ..Default::default()
+            },
+        )
+        .unwrap_err()
+    };
+
+    for (function, gas_costs, mode, code) in [
+        (
+            "trap",
+            GasCosts::default(),
+            ExecutionMode::ReadWrite,
+            VMErrorCode::Execution,
+        ),
+        (
+            "loop",
+            GasCosts::default(),
+            ExecutionMode::ReadWrite,
+            VMErrorCode::OutOfGas,
+        ),
+        (
+            "abort",
+            GasCosts::default(),
+            ExecutionMode::ReadWrite,
+            VMErrorCode::Aborted,
+        ),
+        (
+            "exit",
+            GasCosts::default(),
+            ExecutionMode::ReadWrite,
+            VMErrorCode::Aborted,
+        ),
+        (
+            "set_data",
+            GasCosts::default(),
+            ExecutionMode::ReadOnly,
+            VMErrorCode::ForbiddenAbi,
+        ),
+        // the kind of the failed callee is kept
+        (
+            "call_abort",
+            GasCosts::default(),
+            ExecutionMode::ReadWrite,
+            VMErrorCode::Aborted,
+        ),
+    ] {
+        let err = run(function, gas_costs, mode);
+        assert_eq!(err.code(), code, "{}: {}", function, err);
+        let gas_used = err.gas_used(limit);
+        assert!(gas_used > 0 && gas_used <= limit, "{}: {}", function, err);
+    }
+    // with enough gas to reach the deadline
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig {
+        max_execution_time: Some(std::time::Duration::from_millis(1)),
+        ..RuntimeConfig::default()
+    };
+    let runtime_module =
+        RuntimeModule::new(&module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+    let err = run_function(
+        &interface,
+        runtime_module,
+        "busy",
+        b"",
+        u64::MAX / 2,
+        gas_costs,
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap_err();
+    assert_eq!(err.code(), VMErrorCode::Timeout, "{}", err);
+
+    match run("abort", GasCosts::default(), ExecutionMode::ReadWrite) {
+        VMError::Aborted {
+            message,
+            abort_info,
+            error,
+            ..
+        } => {
+            assert_eq!(message, "error: failed at sc.ts:1 col: 2");
+            // the message of the error is unchanged
+            assert!(error.contains("Runtime error: error: failed at sc.ts:1 col: 2"));
+            assert_eq!(
+                abort_info,
+                Some(AbortInfo {
+                    message: "failed".to_string(),
+                    file: "sc.ts".to_string(),
+                    line: 1,
+                    col: 2,
+                })
+            );
+        }
+        err => panic!("unexpected error: {}", err),
+    }
+    match run("exit", GasCosts::default(), ExecutionMode::ReadWrite) {
+        VMError::Aborted { abort_info, .. } => assert_eq!(abort_info, None),
+        err => panic!("unexpected error: {}", err),
+    }
+    match run("set_data", GasCosts::default(), ExecutionMode::ReadOnly) {
+        VMError::ForbiddenAbi { abi, .. } => assert_eq!(abi, "assembly_script_set_data"),
+        err => panic!("unexpected error: {}", err),
+    }
+    let err = run("call_abort", GasCosts::default(), ExecutionMode::ReadWrite);
+    // the location of the abort of the callee is kept
+    match &err {
+        VMError::Aborted { abort_info, .. } => {
+            assert_eq!(abort_info.as_ref().unwrap().file, "sc.ts")
+        }
+        err => panic!("unexpected error: {}", err),
+    }
+    let call_stack = err.call_stack().unwrap();
+    assert_eq!(call_stack.len(), 1);
+    assert_eq!(call_stack[0].address, "error_codes");
+    assert_eq!(
+        run("loop", GasCosts::default(), ExecutionMode::ReadWrite).gas_used(limit),
+        limit
+    );
+    CREATED_MODULES.lock().unwrap().remove("error_codes");
+
+    // failing start function
+    let module = wasmer::wat2wasm(
+        br#"(module
+            (memory (export "memory") 1)
+            (func $start unreachable)
+            (start $start)
+            (func (export "main")))"#,
+    )
+    .unwrap();
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    let runtime_module =
+        RuntimeModule::new(&module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+    let err = run_main(
+        &interface,
+        runtime_module,
+        limit,
+        gas_costs,
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap_err();
+    assert_eq!(err.code(), VMErrorCode::Instance, "{}", err);
+    assert_eq!(err.gas_used(limit), limit);
+
+    // WasmV1 abort
+    let mut module = vec![1];
+    module.extend(
+        wasmer::wat2wasm(
+            br#"(module
+                (import "massa" "abi_abort" (func $abort (param i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 16) "\06\00\00\00failed")
+                (func (export "__alloc") (param i32) (result i32) (i32.const 1024))
+                (func (export "main") (param i32) (result i32) (call $abort (i32.const 16))))"#,
+        )
+        .unwrap()
+        .iter(),
+    );
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    let runtime_module =
+        RuntimeModule::new(&module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+    match run_main(
+        &interface,
+        runtime_module,
+        limit,
+        gas_costs,
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap_err()
+    {
+        VMError::Aborted {
+            message,
+            abort_info,
+            error,
+            ..
+        } => {
+            assert_eq!(message, "failed");
+            assert!(error.contains("Guest program abort: failed"), "{}", error);
+            assert_eq!(abort_info, None);
+        }
+        err => panic!("unexpected error: {}", err),
+    }
+}
+
+#[test]
+#[serial]
+/// Test that the error of a contract failing three calls deep carries the
+/// whole call stack, read through the accessor and matched with `..`
+fn test_call_stack_three_deep() {
+    let interface = TestInterface;
+    // each address calls the next one, the last one traps
+    let module = wasmer::wat2wasm(
+        format!(
+            r#"(module
+                (import "massa" "assembly_script_call"
+                    (func $call (param i32 i32 i32 i64) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 12) "\00\00\00\00")
+                {}
+                {}
+                {}
+                {}
+                {}
+                {}
+                (func (export "__new") (param i32 i32) (result i32)
+                    (i32.store (i32.const 1020) (local.get 0))
+                    (i32.const 1024))
+                (func (export "main")
+                    (drop (call $call (i32.const 32) (i32.const 128) (i32.const 16) (i64.const 0))))
+                (func (export "run_b")
+                    (drop (call $call (i32.const 64) (i32.const 160) (i32.const 16) (i64.const 0))))
+                (func (export "run_c")
+                    (drop (call $call (i32.const 96) (i32.const 192) (i32.const 16) (i64.const 0))))
+                (func (export "fail") unreachable))"#,
+            wat_as_string(32, "deep_a"),
+            wat_as_string(64, "deep_b"),
+            wat_as_string(96, "deep_c"),
+            wat_as_string(128, "run_b"),
+            wat_as_string(160, "run_c"),
+            wat_as_string(192, "fail"),
+        )
+        .as_bytes(),
+    )
+    .unwrap();
+    for address in ["deep_a", "deep_b", "deep_c"] {
+        CREATED_MODULES
+            .lock()
+            .unwrap()
+            .insert(address.to_string(), module.clone());
+    }
+
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    let runtime_module =
+        RuntimeModule::new(&module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+    let err = run_main(
+        &interface,
+        runtime_module,
+        10_000_000,
+        gas_costs,
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap_err();
+    let frames: Vec<_> = err
+        .call_stack()
+        .unwrap()
+        .iter()
+        .map(|frame| (frame.address.as_str(), frame.function.as_str()))
+        .collect();
+    assert_eq!(
+        frames,
+        vec![("deep_a", "run_b"), ("deep_b", "run_c"), ("deep_c", "fail")]
+    );
+    // the gas given to each call is at most the one given to its caller
+    let call_stack = err.call_stack().unwrap();
+    assert!(call_stack
+        .windows(2)
+        .all(|calls| calls[1].gas_at_entry <= calls[0].gas_at_entry));
+    match err {
+        VMError::ExecutionError { error, .. } => {
+            assert!(error.contains("unreachable"), "{}", error)
+        }
+        err => panic!("unexpected error: {}", err),
+    }
+    for address in ["deep_a", "deep_b", "deep_c"] {
+        CREATED_MODULES.lock().unwrap().remove(address);
+    }
+}
+
+#[test]
+#[serial]
+/// Test that a callee exhausting the gas fails its caller with a typed out of
+/// gas error naming the function of the callee
+fn test_nested_out_of_gas() {
+    let interface = TestInterface;
+    let module = wasmer::wat2wasm(
+        format!(
+            r#"(module
+                (import "massa" "assembly_script_call"
+                    (func $call (param i32 i32 i32 i64) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 12) "\00\00\00\00")
+                {}
+                {}
+                (func (export "__new") (param i32 i32) (result i32)
+                    (i32.store (i32.const 1020) (local.get 0))
+                    (i32.const 1024))
+                (func (export "main")
+                    (drop (call $call (i32.const 32) (i32.const 64) (i32.const 16) (i64.const 0))))
+                (func (export "spin")
+                    (loop $forever (br $forever))))"#,
+            wat_as_string(32, "spinner"),
+            wat_as_string(64, "spin"),
+        )
+        .as_bytes(),
+    )
+    .unwrap();
+    CREATED_MODULES
+        .lock()
+        .unwrap()
+        .insert("spinner".to_string(), module.clone());
+
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    let runtime_module =
+        RuntimeModule::new(&module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+    let err = run_main(
+        &interface,
+        runtime_module,
+        1_000_000,
+        gas_costs,
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap_err();
+    match err {
+        VMError::OutOfGas { at_function, .. } => assert_eq!(at_function, "spin"),
+        err => panic!("expected an out of gas error, got: {}", err),
+    }
+    CREATED_MODULES.lock().unwrap().remove("spinner");
+}
+
+/// Error of a contract aborting with the message `message`, made of UTF-16
+/// code units that do not have to be valid, at `contract.ts:42:7`
+fn abort_with_message(message: &[u8]) -> VMError {
+    let interface = TestInterface;
+    let module = wasmer::wat2wasm(
+        format!(
+            r#"(module
+                (import "env" "abort" (func $abort (param i32 i32 i32 i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 60) "{}{}")
+                {}
+                (func (export "main")
+                    (call $abort (i32.const 64) (i32.const 16) (i32.const 42) (i32.const 7))))"#,
+            wat_bytes(&(message.len() as u32).to_le_bytes()),
+            wat_bytes(message),
+            wat_as_string(16, "contract.ts"),
+        )
+        .as_bytes(),
+    )
+    .unwrap();
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    let runtime_module =
+        RuntimeModule::new(&module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+    run_main(
+        &interface,
+        runtime_module,
+        1_000_000,
+        gas_costs,
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap_err()
+}
+
+#[test]
+#[serial]
+fn test_abort_info() {
+    let utf16: Vec<u8> = "out of range"
+        .encode_utf16()
+        .flat_map(u16::to_le_bytes)
+        .collect();
+    match abort_with_message(&utf16) {
+        VMError::Aborted {
+            message,
+            abort_info,
+            ..
+        } => {
+            assert_eq!(message, "error: out of range at contract.ts:42 col: 7");
+            assert_eq!(
+                abort_info,
+                Some(AbortInfo {
+                    message: "out of range".to_string(),
+                    file: "contract.ts".to_string(),
+                    line: 42,
+                    col: 7,
+                })
+            );
+        }
+        err => panic!("unexpected error: {}", err),
+    }
+
+    // `a`, a lone high surrogate, `b` and a lone low surrogate
+    match abort_with_message(&[b'a', 0, 0x00, 0xd8, b'b', 0, 0x00, 0xdc]) {
+        VMError::Aborted { abort_info, .. } => {
+            let abort_info = abort_info.unwrap();
+            assert_eq!(abort_info.message, "a\u{fffd}b\u{fffd}");
+            assert_eq!(abort_info.line, 42);
+        }
+        err => panic!("unexpected error: {}", err),
+    }
+
+    // random code units, the surrogates being frequent
+    let mut rng = rand::thread_rng();
+    for _ in 0..64 {
+        let message: Vec<u8> = (0..rng.gen_range(0..32))
+            .map(|_| match rng.gen_range(0..4) {
+                0 => rng.gen_range(0xd8..=0xdf),
+                _ => rng.gen(),
+            })
+            .collect();
+        match abort_with_message(&message) {
+            VMError::Aborted { abort_info, .. } => {
+                let abort_info = abort_info.unwrap();
+                assert_eq!(abort_info.file, "contract.ts");
+                assert_eq!((abort_info.line, abort_info.col), (42, 7));
+            }
+            err => panic!("unexpected error for {:?}: {}", message, err),
+        }
+    }
+}
+
+#[test]
+#[serial]
+fn test_abi_filter() {
+    use crate::{AbiFilter, ForbiddenAbiMode};
+
+    let interface = TestInterface;
+    let limit = 1_000_000;
+    let module = wasmer::wat2wasm(
+        br#"(module
+            (import "massa" "assembly_script_set_data" (func $set_data (param i32 i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 12) "\00\00\00\00")
+            (func (export "set_data") (call $set_data (i32.const 16) (i32.const 16)))
+            (func (export "nop")))"#,
+    )
+    .unwrap();
+    let run = |function: &str, config: &RuntimeConfig| {
+        let runtime_module =
+            RuntimeModule::new(&module, GasCosts::default(), config, Compiler::SP).unwrap();
+        run_function(
+            &interface,
+            runtime_module,
+            function,
+            b"",
+            limit,
+            GasCosts::default(),
+            config,
+            ExecutionOptions::default(),
+        )
+    };
+    let denied = RuntimeConfig {
+        abi_filter: AbiFilter::deny(["assembly_script_set_data"]),
+        ..RuntimeConfig::default()
+    };
+
+    // the denied ABI is charged before failing
+    let nop_remaining_gas = run("nop", &denied).unwrap().remaining_gas;
+    match run("set_data", &denied).unwrap_err() {
+        VMError::ForbiddenAbi {
+            abi,
+            error,
+            remaining_gas,
+            ..
+        } => {
+            assert_eq!(abi, "assembly_script_set_data");
+            assert!(error.contains("Forbidden ABI"), "{}", error);
+            assert!(
+                nop_remaining_gas - remaining_gas
+                    >= GasCosts::default().abi_costs["assembly_script_set_data"]
+            );
+        }
+        err => panic!("unexpected error: {}", err),
+    }
+    let allowed_elsewhere = RuntimeConfig {
+        abi_filter: AbiFilter::allow(["assembly_script_print"]),
+        ..RuntimeConfig::default()
+    };
+    assert!(matches!(
+        run("set_data", &allowed_elsewhere).unwrap_err(),
+        VMError::ForbiddenAbi { .. }
+    ));
+    run("set_data", &RuntimeConfig::default()).unwrap();
+
+    // or refused when the module is linked
+    let link_error = RuntimeConfig {
+        abi_filter: denied
+            .abi_filter
+            .clone()
+            .with_mode(ForbiddenAbiMode::LinkError),
+        ..RuntimeConfig::default()
+    };
+    match run("nop", &link_error).unwrap_err() {
+        VMError::InstanceError(error) => assert!(
+            error.contains("\"massa\".\"assembly_script_set_data\""),
+            "{}",
+            error
+        ),
+        err => panic!("unexpected error: {}", err),
+    }
+}
+
+#[test]
+fn test_get_origin_operation_id() {
+    let interface = TestInterface;
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    let module = wasmer::wat2wasm(
+        br#"(module
+            (import "massa" "assembly_script_get_origin_operation_id" (func $origin (result i32)))
+            (memory (export "memory") 1)
+            (func (export "__new") (param i32 i32) (result i32)
+                (i32.store (i32.const 1020) (local.get 0))
+                (i32.const 1024))
+            (func (export "origin") (param i32) (result i32) (call $origin)))"#,
+    )
+    .unwrap();
+    let run = |operation_id: Option<String>| {
+        ORIGIN_OPERATION_ID.with(|id| *id.borrow_mut() = operation_id);
+        let runtime_module =
+            RuntimeModule::new(&module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+        run_function(
+            &interface,
+            runtime_module,
+            "origin",
+            b"",
+            100_000,
+            gas_costs.clone(),
+            &config,
+            ExecutionOptions::default(),
+        )
+    };
+    let utf16 = |s: &str| -> Vec<u8> { s.encode_utf16().flat_map(u16::to_le_bytes).collect() };
+
+    let operation_id = "O12kB3aRLJh2tF9Mv5vZBc7fK5Vh3Rw5oMvKfPoV6ShqttM5Htg5";
+    assert_eq!(
+        run(Some(operation_id.to_string())).unwrap().ret,
+        utf16(operation_id)
+    );
+    // not triggered by an operation
+    assert!(run(None).unwrap().ret.is_empty());
+    let err = run(Some("O".repeat(1_000))).unwrap_err();
+    assert!(err.to_string().contains("exceeds the maximum"), "{}", err);
+    ORIGIN_OPERATION_ID.with(|id| *id.borrow_mut() = None);
+}
+
+#[test]
+#[serial]
+fn test_failed_nested_execution_gas() {
+    let interface = TestInterface;
+    let limit = 1_000_000;
+    let module = wasmer::wat2wasm(
+        format!(
+            r#"(module
+                (import "massa" "assembly_script_call"
+                    (func $call (param i32 i32 i32 i64) (result i32)))
+                (import "massa" "assembly_script_local_call_self"
+                    (func $call_self (param i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 12) "\00\00\00\00")
+                {}
+                {}
+                {}
+                (func (export "__new") (param i32 i32) (result i32)
+                    (i32.store (i32.const 1020) (local.get 0))
+                    (i32.const 1024))
+                (func (export "fail") unreachable)
+                (func (export "burn") (local $i i32)
+                    (loop $next
+                        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                        (br_if $next (i32.lt_u (local.get $i) (i32.const 1000))))
+                    unreachable)
+                (func (export "call_fail")
+                    (drop (call $call (i32.const 32) (i32.const 128) (i32.const 16) (i64.const 0))))
+                (func (export "call_burn")
+                    (drop (call $call (i32.const 32) (i32.const 96) (i32.const 16) (i64.const 0))))
+                (func (export "self_fail")
+                    (drop (call $call_self (i32.const 128) (i32.const 16))))
+                (func (export "self_burn")
+                    (drop (call $call_self (i32.const 96) (i32.const 16)))))"#,
+            wat_as_string(32, "gas_scope"),
+            wat_as_string(96, "burn"),
+            wat_as_string(128, "fail"),
+        )
+        .as_bytes(),
+    )
+    .unwrap();
+    CREATED_MODULES
+        .lock()
+        .unwrap()
+        .insert("gas_scope".to_string(), module.clone());
+    let gas_used = |function: &str| {
+        let gas_costs = GasCosts::default();
+        let config = RuntimeConfig::default();
+        let runtime_module =
+            RuntimeModule::new(&module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+        run_function(
+            &interface,
+            runtime_module,
+            function,
+            b"",
+            limit,
+            gas_costs,
+            &config,
+            ExecutionOptions::default(),
+        )
+        .unwrap_err()
+        .gas_used(limit)
+    };
+
+    // the callee burning gas before failing costs more to its caller
+    for (fail, burn) in [("call_fail", "call_burn"), ("self_fail", "self_burn")] {
+        let (fail_gas, burn_gas) = (gas_used(fail), gas_used(burn));
+        assert!(
+            burn_gas >= fail_gas + 1_000,
+            "{}: {} {}",
+            burn,
+            burn_gas,
+            fail_gas
+        );
+        assert!(burn_gas < limit);
+    }
+}
+
+#[test]
+#[serial]
+fn test_gas_breakdown() {
+    let interface = TestInterface;
+    // `light` returns at once, `heavy` loops before returning
+    let callee = wasmer::wat2wasm(
+        br#"(module
+            (memory (export "memory") 1)
+            (func (export "light"))
+            (func (export "heavy")
+                (local $i i32)
+                (loop $busy
+                    (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                    (br_if $busy (i32.lt_u (local.get $i) (i32.const 1000))))))"#,
+    )
+    .unwrap();
+    for address in ["breakdown_first", "breakdown_second"] {
+        CREATED_MODULES
+            .lock()
+            .unwrap()
+            .insert(address.to_string(), callee.to_vec());
+    }
+    let caller = wasmer::wat2wasm(
+        format!(
+            r#"(module
+                (import "massa" "assembly_script_call"
+                    (func $call (param i32 i32 i32 i64) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 12) "\00\00\00\00")
+                {}
+                {}
+                {}
+                {}
+                (func (export "__new") (param i32 i32) (result i32)
+                    (i32.store (i32.const 1020) (local.get 0))
+                    (i32.const 1024))
+                (func (export "once")
+                    (drop (call $call (i32.const 32) (i32.const 128) (i32.const 16) (i64.const 0))))
+                (func (export "main")
+                    (drop (call $call (i32.const 32) (i32.const 128) (i32.const 16) (i64.const 0)))
+                    (drop (call $call (i32.const 80) (i32.const 160) (i32.const 16) (i64.const 0)))
+                    (drop (call $call (i32.const 32) (i32.const 128) (i32.const 16) (i64.const 0)))))"#,
+            wat_as_string(32, "breakdown_first"),
+            wat_as_string(80, "breakdown_second"),
+            wat_as_string(128, "light"),
+            wat_as_string(160, "heavy"),
+        )
+        .as_bytes(),
+    )
+    .unwrap();
+    let run = |function: &str, collect_gas_breakdown: bool| {
+        let gas_costs = GasCosts::default();
+        let config = RuntimeConfig {
+            collect_gas_breakdown,
+            ..RuntimeConfig::default()
+        };
+        let runtime_module =
+            RuntimeModule::new(&caller, gas_costs.clone(), &config, Compiler::SP).unwrap();
+        run_function(
+            &interface,
+            runtime_module,
+            function,
+            b"",
+            10_000_000,
+            gas_costs,
+            &config,
+            ExecutionOptions::default(),
+        )
+        .unwrap()
+        .gas_breakdown
+    };
+
+    // not collected by default
+    assert_eq!(run("main", false), None);
+    let once = run("once", true).unwrap();
+    assert_eq!(once.len(), 1);
+    let (address, light_gas) = once[0].clone();
+    assert_eq!(address, "breakdown_first");
+    assert!(light_gas > 0);
+    // the calls to the same address are summed, in the order of the first
+    let breakdown = run("main", true).unwrap();
+    assert_eq!(breakdown.len(), 2);
+    assert_eq!(breakdown[0], ("breakdown_first".to_string(), 2 * light_gas));
+    assert_eq!(breakdown[1].0, "breakdown_second");
+    assert!(breakdown[1].1 > light_gas);
+
+    for address in ["breakdown_first", "breakdown_second"] {
+        CREATED_MODULES.lock().unwrap().remove(address);
+    }
+}
+
+#[test]
+#[serial]
+/// Test that the gas of a failed callee is in the breakdown whether it was
+/// called by `call` or by `try_call`
+fn test_gas_breakdown_failed_call() {
+    let interface = TestInterface;
+    let module = wasmer::wat2wasm(
+        format!(
+            r#"(module
+                (import "massa" "assembly_script_call"
+                    (func $call (param i32 i32 i32 i64) (result i32)))
+                (import "massa" "assembly_script_try_call"
+                    (func $try_call (param i32 i32 i32 i64) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 12) "\00\00\00\00")
+                {}
+                {}
+                {}
+                {}
+                (func (export "__new") (param i32 i32) (result i32)
+                    (i32.store (i32.const 1020) (local.get 0))
+                    (i32.const 1024))
+                (func (export "try_relay")
+                    (drop (call $try_call (i32.const 32) (i32.const 128) (i32.const 16) (i64.const 0))))
+                (func (export "try_fail")
+                    (drop (call $try_call (i32.const 80) (i32.const 176) (i32.const 16) (i64.const 0))))
+                (func (export "relay")
+                    (drop (call $call (i32.const 80) (i32.const 176) (i32.const 16) (i64.const 0))))
+                (func (export "fail")
+                    (local $i i32)
+                    (loop $busy
+                        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                        (br_if $busy (i32.lt_u (local.get $i) (i32.const 1000))))
+                    unreachable))"#,
+            wat_as_string(32, "bd_relay"),
+            wat_as_string(80, "bd_failing"),
+            wat_as_string(128, "relay"),
+            wat_as_string(176, "fail"),
+        )
+        .as_bytes(),
+    )
+    .unwrap();
+    for address in ["bd_relay", "bd_failing"] {
+        CREATED_MODULES
+            .lock()
+            .unwrap()
+            .insert(address.to_string(), module.clone());
+    }
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig {
+        collect_gas_breakdown: true,
+        ..RuntimeConfig::default()
+    };
+    let run = |function: &str| {
+        let runtime_module =
+            RuntimeModule::new(&module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+        run_function(
+            &interface,
+            runtime_module,
+            function,
+            b"",
+            10_000_000,
+            gas_costs.clone(),
+            &config,
+            ExecutionOptions::default(),
+        )
+        .unwrap()
+        .gas_breakdown
+        .unwrap()
+    };
+
+    let direct = run("try_fail");
+    assert_eq!(direct.len(), 1);
+    assert_eq!(direct[0].0, "bd_failing");
+    assert!(direct[0].1 > 0);
+    // the callee failing a `call` is reported as well, with the same gas
+    let relayed = run("try_relay");
+    assert_eq!(relayed.len(), 2);
+    let gas_of = |address: &str| {
+        relayed
+            .iter()
+            .find(|(callee, _)| callee == address)
+            .map(|(_, gas)| *gas)
+            .unwrap()
+    };
+    assert_eq!(gas_of("bd_failing"), direct[0].1);
+    // the relay used the gas of its callee and its own
+    assert!(gas_of("bd_relay") > gas_of("bd_failing"));
+
+    for address in ["bd_relay", "bd_failing"] {
+        CREATED_MODULES.lock().unwrap().remove(address);
+    }
+}
+
+#[test]
+#[serial]
+fn test_indexed_events() {
+    let interface = TestInterface;
+    let callee = wasmer::wat2wasm(
+        format!(
+            r#"(module
+                (import "massa" "assembly_script_generate_event"
+                    (func $event (param i32)))
+                (memory (export "memory") 1)
+                {}
+                (func (export "__new") (param i32 i32) (result i32)
+                    (i32.store (i32.const 1020) (local.get 0))
+                    (i32.const 1024))
+                (func (export "emit")
+                    (call $event (i32.const 32))))"#,
+            wat_as_string(32, "callee event"),
+        )
+        .as_bytes(),
+    )
+    .unwrap();
+    CREATED_MODULES
+        .lock()
+        .unwrap()
+        .insert("indexed_events".to_string(), callee.to_vec());
+    let caller = wasmer::wat2wasm(
+        format!(
+            r#"(module
+                (import "massa" "assembly_script_generate_event"
+                    (func $event (param i32)))
+                (import "massa" "assembly_script_call"
+                    (func $call (param i32 i32 i32 i64) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 12) "\00\00\00\00")
+                {}
+                {}
+                {}
+                {}
+                (func (export "__new") (param i32 i32) (result i32)
+                    (i32.store (i32.const 1020) (local.get 0))
+                    (i32.const 1024))
+                (func (export "main")
+                    (call $event (i32.const 32))
+                    (drop (call $call (i32.const 96) (i32.const 160) (i32.const 16) (i64.const 0)))
+                    (call $event (i32.const 64))))"#,
+            wat_as_string(32, "first"),
+            wat_as_string(64, "last"),
+            wat_as_string(96, "indexed_events"),
+            wat_as_string(160, "emit"),
+        )
+        .as_bytes(),
+    )
+    .unwrap();
+    INDEXED_EVENTS.with(|events| events.borrow_mut().clear());
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    let runtime_module =
+        RuntimeModule::new(&caller, gas_costs.clone(), &config, Compiler::SP).unwrap();
+    run_main(
+        &interface,
+        runtime_module,
+        1_000_000,
+        gas_costs,
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap();
+
+    // the event of the callee is between the ones of its caller
+    assert_eq!(
+        INDEXED_EVENTS.with(|events| events.take()),
+        [
+            (0, "first".to_string()),
+            (1, "callee event".to_string()),
+            (2, "last".to_string())
+        ]
+    );
+}
+
+#[test]
+#[serial]
+fn test_string_reads() {
+    let interface = TestInterface;
+    let module = wasmer::wat2wasm(
+        format!(
+            r#"(module
+                (import "massa" "assembly_script_generate_event"
+                    (func $event (param i32)))
+                (memory (export "memory") 1)
+                {}
+                {}
+                (data (i32.const 92) "\08\00\00\00a\00\00\d8b\00\00\dc")
+                (func (export "short") (call $event (i32.const 32)))
+                (func (export "long") (call $event (i32.const 64)))
+                (func (export "surrogates") (call $event (i32.const 96))))"#,
+            wat_as_string(32, "abcd"),
+            wat_as_string(64, "abcde"),
+        )
+        .as_bytes(),
+    )
+    .unwrap();
+    let run = |function: &str, gas_costs: GasCosts, config: &RuntimeConfig| {
+        INDEXED_EVENTS.with(|events| events.borrow_mut().clear());
+        let runtime_module =
+            RuntimeModule::new(&module, gas_costs.clone(), config, Compiler::SP).unwrap();
+        let result = run_function(
+            &interface,
+            runtime_module,
+            function,
+            b"",
+            1_000_000,
+            gas_costs,
+            config,
+            ExecutionOptions::default(),
+        );
+        let events: Vec<String> = INDEXED_EVENTS
+            .with(|events| events.take())
+            .into_iter()
+            .map(|(_, event)| event)
+            .collect();
+        (result, events)
+    };
+
+    // the length is capped in code units
+    let capped = RuntimeConfig {
+        max_string_len: 4,
+        ..RuntimeConfig::default()
+    };
+    let (result, events) = run("short", GasCosts::default(), &capped);
+    result.unwrap();
+    assert_eq!(events, ["abcd"]);
+    let (result, events) = run("long", GasCosts::default(), &capped);
+    let err = result.unwrap_err().to_string();
+    assert!(
+        err.contains("String of 5 UTF-16 code units in assembly_script_generate_event exceeds the maximum of 4 code units"),
+        "{}",
+        err
+    );
+    assert!(events.is_empty());
+
+    // the lone surrogates are replaced
+    let (result, events) = run("surrogates", GasCosts::default(), &RuntimeConfig::default());
+    result.unwrap();
+    assert_eq!(events, ["a\u{fffd}b\u{fffd}"]);
+
+    // the code units are charged before the conversion
+    let priced = |cost: u64| GasCosts {
+        string_code_unit_cost: cost,
+        ..GasCosts::default()
+    };
+    let free = run("long", priced(0), &RuntimeConfig::default())
+        .0
+        .unwrap()
+        .remaining_gas;
+    let charged = run("long", priced(10), &RuntimeConfig::default())
+        .0
+        .unwrap()
+        .remaining_gas;
+    assert_eq!(free - charged, 5 * 10);
+    let (result, events) = run("long", priced(1_000_000), &RuntimeConfig::default());
+    let err = result.unwrap_err();
+    assert!(matches!(err, VMError::OutOfGas { .. }), "{}", err);
+    assert!(events.is_empty());
+}
+
+#[test]
+fn test_digests() {
+    use sha2::{Digest, Sha256};
+    use sha3::Sha3_256;
+
+    let interface = TestInterface;
+    let as_module = |abi: &str| {
+        wasmer::wat2wasm(
+            format!(
+                r#"(module
+                    (import "massa" "{}" (func $digest (param i32) (result i32)))
+                    (memory (export "memory") 1)
+                    (func (export "__new") (param i32 i32) (result i32)
+                        (i32.store (i32.const 1020) (local.get 0))
+                        (i32.const 1024))
+                    (func (export "digest") (param i32) (result i32)
+                        (call $digest (local.get 0))))"#,
+                abi
+            )
+            .as_bytes(),
+        )
+        .unwrap()
+        .to_vec()
+    };
+    // the arguments and the return values are prefixed by their length
+    let wasmv1_module = |abi: &str| {
+        let mut bytecode = vec![1];
+        bytecode.extend(
+            wasmer::wat2wasm(
+                format!(
+                    r#"(module
+                        (import "massa" "{}" (func $digest (param i32) (result i32)))
+                        (memory (export "memory") 1)
+                        (func (export "__alloc") (param i32) (result i32)
+                            (i32.store (i32.const 1020) (local.get 0))
+                            (i32.const 1024))
+                        (func (export "digest") (param i32) (result i32)
+                            (i32.sub
+                                (call $digest (i32.sub (local.get 0) (i32.const 4)))
+                                (i32.const 4))))"#,
+                    abi
+                )
+                .as_bytes(),
+            )
+            .unwrap()
+            .iter(),
+        );
+        bytecode
+    };
+    let run = |bytecode: &[u8], max_digest_input_len: usize| {
+        let gas_costs = GasCosts::default();
+        let config = RuntimeConfig {
+            max_digest_input_len,
+            ..RuntimeConfig::default()
+        };
+        let runtime_module =
+            RuntimeModule::new(bytecode, gas_costs.clone(), &config, Compiler::SP).unwrap();
+        run_function(
+            &interface,
+            runtime_module,
+            "digest",
+            b"abc",
+            1_000_000,
+            gas_costs,
+            &config,
+            ExecutionOptions::default(),
+        )
+    };
+
+    for (as_abi, wasmv1_abi, expected) in [
+        (
+            "assembly_script_sha256",
+            "abi_sha256",
+            Sha256::digest(b"abc").to_vec(),
+        ),
+        (
+            "assembly_script_sha3_256",
+            "abi_sha3_256",
+            Sha3_256::digest(b"abc").to_vec(),
+        ),
+    ] {
+        for bytecode in [as_module(as_abi), wasmv1_module(wasmv1_abi)] {
+            // the input is exactly at the maximum length
+            assert_eq!(run(&bytecode, 3).unwrap().ret, expected, "{}", as_abi);
+            let err = run(&bytecode, 2).unwrap_err();
+            assert!(
+                err.to_string()
+                    .contains("digest input of 3 bytes exceeds the maximum of 2 bytes"),
+                "{}: {}",
+                as_abi,
+                err
+            );
+        }
+    }
+}
+
+#[test]
+fn test_gas_overflow() {
+    let interface = TestInterface;
+    let module = wasmer::wat2wasm(
+        br#"(module
+            (import "massa" "assembly_script_keccak256_hash"
+                (func $hash (param i32) (result i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 12) "\03\00\00\00abc")
+            (func (export "__new") (param i32 i32) (result i32)
+                (i32.store (i32.const 1020) (local.get 0))
+                (i32.const 1024))
+            (func (export "main")
+                (drop (call $hash (i32.const 16)))))"#,
+    )
+    .unwrap();
+    let run = |per_byte: u64| {
+        let mut gas_costs = GasCosts::default();
+        let config = RuntimeConfig::default();
+        gas_costs.abi_costs.insert(
+            "assembly_script_keccak256_hash_per_byte".to_string(),
+            per_byte,
+        );
+        let runtime_module =
+            RuntimeModule::new(&module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+        run_main(
+            &interface,
+            runtime_module,
+            1_000_000,
+            gas_costs,
+            &config,
+            ExecutionOptions::default(),
+        )
+        .unwrap_err()
+    };
+
+    // the cost of the 3 bytes fits in 64 bits but not in the gas limit
+    let err = run(u64::MAX / 3);
+    assert!(matches!(err, VMError::OutOfGas { .. }), "{}", err);
+    // it overflows instead of wrapping to a cheap charge
+    let err = run(u64::MAX / 3 + 1);
+    assert!(
+        err.to_string()
+            .contains("Gas overflow in assembly_script_keccak256_hash"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+/// This test hashes known Keccak-256 test vectors through the
+/// AssemblyScript and wasmv1 ABIs, charged per byte of input
+fn test_keccak256_vectors() {
+    use massa_proto_rs::massa::abi::v1::{
+        abi_response, resp_result, AbiResponse, Keccak256Request,
+    };
+    use prost::Message;
+
+    let interface = TestInterface;
+    let as_module = wasmer::wat2wasm(
+        br#"(module
+            (import "massa" "assembly_script_keccak256_hash"
+                (func $hash (param i32) (result i32)))
+            (memory (export "memory") 1)
+            (func (export "__new") (param i32 i32) (result i32)
+                (i32.store (i32.const 1020) (local.get 0))
+                (i32.const 1024))
+            (func (export "hash") (param i32) (result i32)
+                (call $hash (local.get 0))))"#,
+    )
+    .unwrap()
+    .to_vec();
+    let mut wasmv1_module = vec![1];
+    wasmv1_module.extend(
+        wasmer::wat2wasm(
+            br#"(module
+                (import "massa" "abi_hash_keccak256"
+                    (func $hash (param i32) (result i32)))
+                (memory (export "memory") 1)
+                (func (export "__alloc") (param i32) (result i32)
+                    (i32.store (i32.const 1020) (local.get 0))
+                    (i32.const 1024))
+                (func (export "hash") (param i32) (result i32)
+                    (i32.sub (call $hash (i32.sub (local.get 0) (i32.const 4)))
+                        (i32.const 4))))"#,
+        )
+        .unwrap()
+        .iter(),
+    );
+    let run = |bytecode: &[u8], param: &[u8], per_byte: u64| {
+        let mut gas_costs = GasCosts::default();
+        let config = RuntimeConfig::default();
+        for abi in ["assembly_script_keccak256_hash", "abi_hash_keccak256"] {
+            gas_costs
+                .abi_costs
+                .insert(format!("{}_per_byte", abi), per_byte);
+        }
+        let runtime_module =
+            RuntimeModule::new(bytecode, gas_costs.clone(), &config, Compiler::SP).unwrap();
+        let response = run_function(
+            &interface,
+            runtime_module,
+            "hash",
+            param,
+            10_000_000,
+            gas_costs,
+            &config,
+            ExecutionOptions::default(),
+        )
+        .unwrap();
+        (response.ret, response.remaining_gas)
+    };
+    let hash_as = |data: &[u8], per_byte: u64| run(&as_module, data, per_byte);
+    let hash_wasmv1 = |data: &[u8], per_byte: u64| {
+        let request = Keccak256Request {
+            data: data.to_vec(),
+        };
+        let (ret, remaining_gas) = run(&wasmv1_module, &request.encode_to_vec(), per_byte);
+        match AbiResponse::decode(ret.as_slice()).unwrap().resp.unwrap() {
+            abi_response::Resp::Res(res) => match res.res.unwrap() {
+                resp_result::Res::Keccak256Result(res) => (res.hash, remaining_gas),
+                res => panic!("unexpected result: {:?}", res),
+            },
+            abi_response::Resp::Error(err) => panic!("unexpected error: {}", err.message),
+        }
+    };
+    let hex = |digest: &str| -> Vec<u8> {
+        (0..digest.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&digest[i..i + 2], 16).unwrap())
+            .collect()
+    };
+
+    let fox: &[u8] = b"The quick brown fox jumps over the lazy dog";
+    for (data, expected) in [
+        (
+            &b""[..],
+            "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470",
+        ),
+        (
+            &b"abc"[..],
+            "4e03657aea45a94fc7d47ba826c8d667c0d1e6e33a64a036ec44f58fa12d6c45",
+        ),
+        (
+            fox,
+            "4d741b6f1eb29cb2a9b9911c82f56fa8d73b04959d3d9d222895df6c0b28aa15",
+        ),
+    ] {
+        assert_eq!(hash_as(data, 1).0, hex(expected));
+        assert_eq!(hash_wasmv1(data, 1).0, hex(expected));
+    }
+
+    // each byte of input is charged before hashing
+    for hash in [
+        &hash_as as &dyn Fn(&[u8], u64) -> (Vec<u8>, u64),
+        &hash_wasmv1,
+    ] {
+        let (_, cheap) = hash(fox, 1);
+        let (_, expensive) = hash(fox, 1_001);
+        assert_eq!(cheap - expensive, 1_000 * fox.len() as u64);
+    }
+}
+
+#[test]
+#[serial]
+fn test_execution_observer() {
+    use crate::{ExecutionEvent, RecordingObserver};
+    use std::sync::Arc;
+
+    let interface = TestInterface;
+    let module = wasmer::wat2wasm(
+        format!(
+            r#"(module
+                (import "massa" "assembly_script_call"
+                    (func $call (param i32 i32 i32 i64) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 12) "\00\00\00\00")
+                {}
+                {}
+                {}
+                (func (export "__new") (param i32 i32) (result i32)
+                    (i32.store (i32.const 1020) (local.get 0))
+                    (i32.const 1024))
+                (func (export "top")
+                    (drop (call $call (i32.const 32) (i32.const 96) (i32.const 16) (i64.const 0))))
+                (func (export "middle")
+                    (drop (call $call (i32.const 32) (i32.const 128) (i32.const 16) (i64.const 0))))
+                (func (export "leaf")))"#,
+            wat_as_string(32, "observed"),
+            wat_as_string(96, "middle"),
+            wat_as_string(128, "leaf"),
+        )
+        .as_bytes(),
+    )
+    .unwrap();
+    CREATED_MODULES
+        .lock()
+        .unwrap()
+        .insert("observed".to_string(), module.clone());
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    let runtime_module =
+        RuntimeModule::new(&module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+    let observer = Arc::new(RecordingObserver::new());
+    crate::run_function(
+        &interface,
+        runtime_module,
+        "top",
+        b"",
+        1_000_000,
+        gas_costs,
+        ExecutionOptions {
+            observer: Some(observer.clone()),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let events = observer.events();
+
+    let executions: Vec<(u32, String)> = events
+        .iter()
+        .filter_map(|(depth, event)| match event {
+            ExecutionEvent::ExecutionStarted { function, .. } => Some((*depth, function.clone())),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        executions,
+        [
+            (0, "top".to_string()),
+            (1, "middle".to_string()),
+            (2, "leaf".to_string())
+        ]
+    );
+
+    // the call to the leaf is nested in the call to the middle
+    let calls: Vec<(u32, &str, bool)> = events
+        .iter()
+        .filter_map(|(depth, event)| match event {
+            ExecutionEvent::NestedCallStarted { function, .. } => {
+                Some((*depth, function.as_str(), true))
+            }
+            ExecutionEvent::NestedCallFinished {
+                function, success, ..
+            } => {
+                assert!(success);
+                Some((*depth, function.as_str(), false))
+            }
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        calls,
+        [
+            (0, "middle", true),
+            (1, "leaf", true),
+            (1, "leaf", false),
+            (0, "middle", false)
+        ]
+    );
+    assert!(events.iter().any(|(depth, event)| *depth == 1
+        && matches!(event, ExecutionEvent::AbiCalled { abi, .. } if abi == "assembly_script_call")));
+}
+
+#[test]
+fn test_send_message_validation() {
+    use massa_proto_rs::massa::{
+        abi::v1::{AsyncMessageFilter, SendAsyncMessageRequest},
+        model::v1::Slot,
+    };
+    use prost::Message;
+
+    let interface = TestInterface;
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig {
+        max_async_message_gas: 10_000,
+        storage_limits: crate::StorageLimits {
+            max_key_len: 2,
+            ..Default::default()
+        },
+        ..RuntimeConfig::default()
+    };
+    let request = |start: (u64, u32), end: (u64, u32), execution_gas: u64, key: &[u8]| {
+        SendAsyncMessageRequest {
+            target_address: "target".to_string(),
+            target_handler: "handler".to_string(),
+            validity_start: Some(Slot {
+                period: start.0,
+                thread: start.1,
+            }),
+            validity_end: Some(Slot {
+                period: end.0,
+                thread: end.1,
+            }),
+            execution_gas,
+            raw_fee: 1,
+            raw_coins: 1,
+            data: vec![],
+            filter: Some(AsyncMessageFilter {
+                target_address: "filter".to_string(),
+                target_key: Some(key.to_vec()),
+            }),
+        }
+        .encode_to_vec()
+    };
+    // (start period, start thread, end period, end thread, max gas, fee, coins,
+    // filter key offset)
+    let cases: [(&str, &str, Option<&str>); 8] = [
+        ("valid", "1 0 2 0 1000 0 0 288", None),
+        (
+            "start_after_end",
+            "3 0 2 0 1000 0 0 288",
+            Some("validity start (period 3, thread 0) is after validity end (period 2, thread 0)"),
+        ),
+        (
+            "start_thread",
+            "1 32 2 0 1000 0 0 288",
+            Some("invalid validity start thread"),
+        ),
+        (
+            "end_thread",
+            "1 0 2 32 1000 0 0 288",
+            Some("invalid validity end thread"),
+        ),
+        (
+            "max_gas",
+            "1 0 2 0 10001 0 0 288",
+            Some("async message max gas 10001 exceeds the maximum of 10000"),
+        ),
+        ("fee", "1 0 2 0 1000 -1 0 288", Some("negative raw_fee")),
+        ("coins", "1 0 2 0 1000 0 -1 288", Some("negative coins")),
+        (
+            "filter_key",
+            "1 0 2 0 1000 0 0 320",
+            Some("filter datastore key of 3 bytes exceeds the maximum of 2 bytes"),
+        ),
+    ];
+    let mut functions = String::new();
+    for (name, args, _) in cases {
+        let args: Vec<&str> = args.split(' ').collect();
+        functions.push_str(&format!(
+            r#"(func (export "{}") (call $send (i32.const 32) (i32.const 64)
+                (i64.const {}) (i32.const {}) (i64.const {}) (i32.const {})
+                (i64.const {}) (i64.const {}) (i64.const {})
+                (i32.const 16) (i32.const 96) (i32.const {})))
+            "#,
+            name, args[0], args[1], args[2], args[3], args[4], args[5], args[6], args[7]
+        ));
+    }
+    let buffer = |ptr: u32, bytes: &[u8]| {
+        format!(
+            r#"(data (i32.const {}) "{}{}")"#,
+            ptr - 4,
+            wat_bytes(&(bytes.len() as u32).to_le_bytes()),
+            wat_bytes(bytes)
+        )
+    };
+    let valid_request = request((1, 0), (2, 0), 1000, b"ab");
+    let invalid_request = request((3, 0), (2, 0), 1000, b"ab");
+    let module = wasmer::wat2wasm(
+        format!(
+            r#"(module
+                (import "massa" "assembly_script_send_message"
+                    (func $send (param i32 i32 i64 i32 i64 i32 i64 i64 i64 i32 i32 i32)))
+                (import "massa" "assembly_script_send_async_message"
+                    (func $send_async (param i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 12) "\00\00\00\00")
+                {}
+                {}
+                {}
+                {}
+                {}
+                {}
+                {}
+                (func (export "valid_async") (call $send_async (i32.const 1028)))
+                (func (export "invalid_async") (call $send_async (i32.const 2052)))
+                {})"#,
+            wat_as_string(32, "target"),
+            wat_as_string(64, "handler"),
+            wat_as_string(96, "filter"),
+            buffer(288, b"ab"),
+            buffer(320, b"abc"),
+            buffer(1028, &valid_request),
+            buffer(2052, &invalid_request),
+            functions,
+        )
+        .as_bytes(),
+    )
+    .unwrap();
+    let run = |function: &str| {
+        let runtime_module =
+            RuntimeModule::new(&module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+        run_function(
+            &interface,
+            runtime_module,
+            function,
+            b"",
+            1_000_000,
+            gas_costs.clone(),
+            &config,
+            ExecutionOptions::default(),
+        )
+    };
+
+    for (name, _, expected_error) in cases {
+        match (run(name), expected_error) {
+            (Ok(_), None) => {}
+            (Err(err), Some(expected)) => {
+                assert!(err.to_string().contains(expected), "{}: {}", name, err)
+            }
+            (result, _) => panic!("{}: unexpected result {:?}", name, result.map(|_| ())),
+        }
+    }
+    run("valid_async").unwrap();
+    let err = run("invalid_async").unwrap_err();
+    assert!(err.to_string().contains("is after validity end"), "{}", err);
+}
+
+#[test]
+#[serial]
+fn test_stack_overflow() {
+    let interface = TestInterface;
+    let limit = 10_000_000;
+    let module = wasmer::wat2wasm(
+        format!(
+            r#"(module
+                (import "massa" "assembly_script_try_call"
+                    (func $try_call (param i32 i32 i32 i64) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 12) "\00\00\00\00")
+                {}
+                {}
+                {}
+                (func (export "__new") (param i32 i32) (result i32)
+                    (i32.store (i32.const 1020) (local.get 0))
+                    (i32.const 1024))
+                (func $recurse (export "recurse") (call $recurse))
+                (func (export "fail") unreachable)
+                (func (export "try_recurse")
+                    (drop (call $try_call (i32.const 32) (i32.const 96) (i32.const 16) (i64.const 0))))
+                (func (export "try_fail")
+                    (drop (call $try_call (i32.const 32) (i32.const 128) (i32.const 16) (i64.const 0)))))"#,
+            wat_as_string(32, "stack_limit"),
+            wat_as_string(96, "recurse"),
+            wat_as_string(128, "fail"),
+        )
+        .as_bytes(),
+    )
+    .unwrap();
+    CREATED_MODULES
+        .lock()
+        .unwrap()
+        .insert("stack_limit".to_string(), module.clone());
+    let run = |function: &str, config: &RuntimeConfig| {
+        let runtime_module =
+            RuntimeModule::new(&module, GasCosts::default(), config, Compiler::SP).unwrap();
+        run_function(
+            &interface,
+            runtime_module,
+            function,
+            b"",
+            limit,
+            GasCosts::default(),
+            config,
+            ExecutionOptions::default(),
+        )
+    };
+
+    // the recursion is stopped at the configured depth, long before the gas
+    // or the native stack run out
+    let config = RuntimeConfig {
+        condom_limits: CondomLimits {
+            max_stack_depth: 100,
+            ..CondomLimits::default()
+        },
+        ..RuntimeConfig::default()
+    };
+    let err = run("recurse", &config).unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("Stack overflow: the guest calls exceed the maximum depth of 100"),
+        "{}",
+        err
+    );
+    assert!(
+        matches!(err, VMError::ExecutionError { remaining_gas, .. } if remaining_gas > 0),
+        "{:?}",
+        err
+    );
+
+    // the overflow of a callee is caught by its caller, which pays for the
+    // calls made before it
+    let fail_gas = limit
+        - run("try_fail", &RuntimeConfig::default())
+            .unwrap()
+            .remaining_gas;
+    let recurse_gas = limit
+        - run("try_recurse", &RuntimeConfig::default())
+            .unwrap()
+            .remaining_gas;
+    let max_depth = RuntimeConfig::default().condom_limits.max_stack_depth as u64;
+    assert!(
+        recurse_gas >= fail_gas + max_depth,
+        "{} {}",
+        recurse_gas,
+        fail_gas
+    );
+    assert!(recurse_gas < limit);
+}
+
+#[test]
+#[serial]
+fn test_call_with_gas_limit() {
+    let interface = TestInterface;
+    let limit = 10_000_000;
+    let callee = wasmer::wat2wasm(
+        br#"(module
+            (memory (export "memory") 1)
+            (func (export "__new") (param i32 i32) (result i32)
+                (i32.store (i32.const 1020) (local.get 0))
+                (i32.const 1024))
+            (func (export "loop") (loop $next (br $next)))
+            (func (export "cheap")))"#,
+    )
+    .unwrap()
+    .to_vec();
+    CREATED_MODULES
+        .lock()
+        .unwrap()
+        .insert("gas_limit".to_string(), callee);
+    // calls `loop` or `cheap` of the callee with the gas limit `max_gas`
+    let caller = |max_gas: i64, looping: bool| {
+        wasmer::wat2wasm(
+            format!(
+                r#"(module
+                    (import "massa" "assembly_script_call_with_gas_limit"
+                        (func $call_limited (param i32 i32 i32 i64 i64) (result i32)))
+                    (memory (export "memory") 1)
+                    (data (i32.const 12) "\00\00\00\00")
+                    {}
+                    {}
+                    (func (export "__new") (param i32 i32) (result i32)
+                        (i32.store (i32.const 1020) (local.get 0))
+                        (i32.const 1024))
+                    (func (export "main")
+                        (drop (call $call_limited
+                            (i32.const 32) (i32.const 96) (i32.const 16) (i64.const 0)
+                            (i64.const {})))))"#,
+                wat_as_string(32, "gas_limit"),
+                wat_as_string(96, if looping { "loop" } else { "cheap" }),
+                max_gas
+            )
+            .as_bytes(),
+        )
+        .unwrap()
+    };
+    let run = |module: &[u8]| {
+        BYTECODE_FETCHES.with(|fetches| fetches.set(0));
+        let gas_costs = GasCosts::default();
+        let config = RuntimeConfig::default();
+        let runtime_module =
+            RuntimeModule::new(module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+        let result = run_main(
+            &interface,
+            runtime_module,
+            limit,
+            gas_costs,
+            &config,
+            ExecutionOptions::default(),
+        );
+        (result, BYTECODE_FETCHES.with(|fetches| fetches.get()))
+    };
+
+    // a callee exhausting its limit fails the call, the caller keeping the
+    // rest of its gas
+    let (result, _) = run(&caller(100_000, true));
+    let err = result.unwrap_err();
+    assert_eq!(err.code(), VMErrorCode::Execution, "{}", err);
+    assert!(
+        err.to_string()
+            .contains("Call to gas_limit:loop exhausted its gas limit of 100000"),
+        "{}",
+        err
+    );
+    let gas_used = err.gas_used(limit);
+    assert!(gas_used > 100_000 && gas_used < limit / 2, "{}", gas_used);
+
+    // the unused part of the limit goes back to the caller: the gas used does
+    // not depend on the limit
+    let gas_used = |max_gas| limit - run(&caller(max_gas, false)).0.unwrap().remaining_gas;
+    assert_eq!(gas_used(100_000), gas_used(200_000));
+
+    // a limit of 0 fails before the callee is fetched
+    let (result, fetches) = run(&caller(0, false));
+    let err = result.unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("Call to gas_limit:cheap with a gas limit of 0"),
+        "{}",
+        err
+    );
+    assert_eq!(fetches, 0);
+
+    // a limit above the remaining gas gives the remaining gas
+    let (result, _) = run(&caller(i64::MAX, false));
+    result.unwrap();
+    let (result, _) = run(&caller(i64::MAX, true));
+    assert_eq!(result.unwrap_err().code(), VMErrorCode::OutOfGas);
+    CREATED_MODULES.lock().unwrap().remove("gas_limit");
+}
+
+#[test]
+#[serial]
+fn test_abi_panic() {
+    use crate::tests::PANICKING_ADDRESS;
+    use massa_proto_rs::massa::abi::v1::GetBalanceRequest;
+    use prost::Message;
+
+    let interface = TestInterface;
+    let limit = 10_000_000;
+    let module = wasmer::wat2wasm(
+        format!(
+            r#"(module
+                (import "massa" "assembly_script_get_balance_for"
+                    (func $balance (param i32) (result i64)))
+                (import "massa" "assembly_script_call"
+                    (func $call (param i32 i32 i32 i64) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 12) "\00\00\00\00")
+                {}
+                {}
+                {}
+                (func (export "__new") (param i32 i32) (result i32)
+                    (i32.store (i32.const 1020) (local.get 0))
+                    (i32.const 1024))
+                (func (export "balance") (drop (call $balance (i32.const 32))))
+                (func (export "call_balance")
+                    (drop (call $call (i32.const 96) (i32.const 160) (i32.const 16) (i64.const 0)))))"#,
+            wat_as_string(32, PANICKING_ADDRESS),
+            wat_as_string(96, "panicking_callee"),
+            wat_as_string(160, "balance"),
+        )
+        .as_bytes(),
+    )
+    .unwrap();
+    CREATED_MODULES
+        .lock()
+        .unwrap()
+        .insert("panicking_callee".to_string(), module.clone());
+    let run = |function: &str| {
+        let gas_costs = GasCosts::default();
+        let config = RuntimeConfig::default();
+        let runtime_module =
+            RuntimeModule::new(&module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+        run_function(
+            &interface,
+            runtime_module,
+            function,
+            b"",
+            limit,
+            gas_costs,
+            &config,
+            ExecutionOptions::default(),
+        )
+        .unwrap_err()
+    };
+
+    // the panic fails the execution, and the next ones run
+    for _ in 0..2 {
+        let err = run("balance");
+        assert_eq!(err.code(), VMErrorCode::Execution, "{}", err);
+        assert!(
+            err.to_string().contains(
+                "Internal error in assembly_script_get_balance_for: \
+                 balance of panicking_address is out of reach"
+            ),
+            "{}",
+            err
+        );
+        assert!(err.gas_used(limit) < limit);
+    }
+    // a panic in a nested call fails the nested call
+    let err = run("call_balance");
+    assert!(
+        err.to_string()
+            .contains("Internal error in assembly_script_get_balance_for"),
+        "{}",
+        err
+    );
+    assert_eq!(err.call_stack().unwrap()[0].address, "panicking_callee");
+    CREATED_MODULES.lock().unwrap().remove("panicking_callee");
+
+    // WasmV1
+    let request = GetBalanceRequest {
+        address: Some(PANICKING_ADDRESS.to_string()),
+    }
+    .encode_to_vec();
+    let mut module = vec![1];
+    module.extend(
+        wasmer::wat2wasm(
+            format!(
+                r#"(module
+                    (import "massa" "abi_get_balance" (func $balance (param i32) (result i32)))
+                    (memory (export "memory") 1)
+                    (data (i32.const 60) "{}{}")
+                    (func (export "__alloc") (param i32) (result i32) (i32.const 1024))
+                    (func (export "main") (param i32) (result i32) (call $balance (i32.const 64))))"#,
+                wat_bytes(&(request.len() as u32).to_le_bytes()),
+                wat_bytes(&request),
+            )
+            .as_bytes(),
+        )
+        .unwrap()
+        .iter(),
+    );
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    let runtime_module =
+        RuntimeModule::new(&module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+    let err = run_main(
+        &interface,
+        runtime_module,
+        limit,
+        gas_costs,
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap_err();
+    assert_eq!(err.code(), VMErrorCode::Execution, "{}", err);
+    assert!(
+        err.to_string()
+            .contains("Internal error in abi_get_balance: balance of panicking_address"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn test_typed_function() {
+    use massa_proto_rs::massa::abi::v1::{CallRequest, GenerateEventRequest};
+    use prost::Message;
+
+    let interface = TestInterface;
+    let event = GenerateEventRequest {
+        event: "echo".to_string(),
+    }
+    .encode_to_vec();
+    // `echo` emits an event and returns the request it was given as the data
+    // of its `CallResponse`, prepending the tag and the length of the field
+    let mut module = vec![1];
+    module.extend(
+        wasmer::wat2wasm(
+            format!(
+                r#"(module
+                    (import "massa" "abi_generate_event" (func $event (param i32) (result i32)))
+                    (memory (export "memory") 1)
+                    (data (i32.const 64) "{}{}")
+                    (data (i32.const 128) "\02\00\00\00\ff\ff")
+                    (func (export "__alloc") (param i32) (result i32)
+                        (i32.store (i32.const 1020) (local.get 0))
+                        (i32.const 1024))
+                    (func (export "echo") (param i32) (result i32) (local $len i32)
+                        (drop (call $event (i32.const 64)))
+                        (local.set $len (i32.load (i32.sub (local.get 0) (i32.const 4))))
+                        (i32.store (i32.sub (local.get 0) (i32.const 6))
+                            (i32.add (local.get $len) (i32.const 2)))
+                        (i32.store8 (i32.sub (local.get 0) (i32.const 2)) (i32.const 10))
+                        (i32.store8 (i32.sub (local.get 0) (i32.const 1)) (local.get $len))
+                        (i32.sub (local.get 0) (i32.const 6)))
+                    (func (export "garbage") (param i32) (result i32)
+                        (i32.const 128)))"#,
+                wat_bytes(&(event.len() as u32).to_le_bytes()),
+                wat_bytes(&event),
+            )
+            .as_bytes(),
+        )
+        .unwrap()
+        .iter(),
+    );
+    let run = |module: &[u8], function: &str| {
+        let gas_costs = GasCosts::default();
+        let config = RuntimeConfig::default();
+        let runtime_module =
+            RuntimeModule::new(module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+        run_function_typed(
+            &interface,
+            runtime_module,
+            function,
+            b"argument",
+            100_000,
+            gas_costs,
+            &config,
+        )
+    };
+
+    let response = run(&module, "echo").unwrap();
+    assert_eq!(
+        CallRequest::decode(response.data.as_slice()).unwrap(),
+        CallRequest {
+            target_sc_address: String::new(),
+            target_function_name: "echo".to_string(),
+            function_arg: b"argument".to_vec(),
+            call_coins: None,
+        }
+    );
+    assert_eq!(response.events_count, 1);
+    assert!(response.remaining_gas < 100_000 - response.init_gas_cost);
+
+    match run(&module, "garbage").unwrap_err() {
+        TypedCallError::MalformedResponse {
+            function, response, ..
+        } => {
+            assert_eq!(function, "garbage");
+            assert_eq!(response.ret, vec![0xff, 0xff]);
+        }
+        err => panic!("unexpected error: {}", err),
+    }
+
+    let as_module = wasmer::wat2wasm(
+        br#"(module
+            (memory (export "memory") 1)
+            (func (export "echo") (param i32) (result i32) (local.get 0)))"#,
+    )
+    .unwrap();
+    assert!(matches!(
+        run(&as_module, "echo").unwrap_err(),
+        TypedCallError::UnsupportedModuleFormat
+    ));
+}
+
+#[test]
+#[serial]
+fn test_cached_callee_bytecode_not_fetched() {
+    use std::sync::Arc;
+
+    let interface = TestInterface;
+    let callee = wasmer::wat2wasm(
+        br#"(module
+            (memory (export "memory") 1)
+            (data (i32.const 12) "\00\00\00\00")
+            (func (export "run") (result i32) (i32.const 16)))"#,
+    )
+    .unwrap();
+    CREATED_MODULES
+        .lock()
+        .unwrap()
+        .insert("cached_callee".to_string(), callee.to_vec());
+    let caller = wasmer::wat2wasm(
+        format!(
+            r#"(module
+                (import "massa" "assembly_script_call"
+                    (func $call (param i32 i32 i32 i64) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 12) "\00\00\00\00")
+                {}
+                {}
+                (func (export "__new") (param i32 i32) (result i32)
+                    (i32.store (i32.const 1020) (local.get 0))
+                    (i32.const 1024))
+                (func (export "call_twice")
+                    (drop (call $call (i32.const 32) (i32.const 96) (i32.const 16) (i64.const 0)))
+                    (drop (call $call (i32.const 32) (i32.const 96) (i32.const 16) (i64.const 0)))))"#,
+            wat_as_string(32, "cached_callee"),
+            wat_as_string(96, "run"),
+        )
+        .as_bytes(),
+    )
+    .unwrap();
+    let run = || {
+        let before = BYTECODE_FETCHES.with(|fetches| fetches.get());
+        let gas_costs = GasCosts::default();
+        let config = RuntimeConfig::default();
+        let runtime_module =
+            RuntimeModule::new(&caller, gas_costs.clone(), &config, Compiler::SP).unwrap();
+        let response = run_function(
+            &interface,
+            runtime_module,
+            "call_twice",
+            b"",
+            1_000_000,
+            gas_costs,
+            &config,
+            ExecutionOptions::default(),
+        )
+        .unwrap();
+        (
+            BYTECODE_FETCHES.with(|fetches| fetches.get()) - before,
+            response.remaining_gas,
+        )
+    };
+    let (_, uncached_gas) = run();
+    let cache = Arc::new(ModuleCache::new(usize::MAX));
+    MODULE_CACHE.with(|module_cache| *module_cache.borrow_mut() = Some(cache.clone()));
+
+    // only the first call misses the cache
+    let (fetches, cold_gas) = run();
+    assert_eq!(fetches, 1);
+    assert_eq!(cache.compilation_count(), 1);
+    let (fetches, warm_gas) = run();
+    assert_eq!(fetches, 0);
+    assert_eq!(cache.compilation_count(), 1);
+    // the compilation is charged whether the module was cached or not
+    assert_eq!(cold_gas, warm_gas);
+    assert_eq!(cold_gas, uncached_gas);
+    MODULE_CACHE.with(|module_cache| *module_cache.borrow_mut() = None);
+}
+
+#[test]
+#[serial]
+/// Test that the modules precompiled in the cache are not compiled again,
+/// whether executed through their handle or called by another module
+fn test_precompiled_modules() {
+    use std::sync::Arc;
+
+    let interface = TestInterface;
+    let callee = wasmer::wat2wasm(
+        br#"(module
+            (memory (export "memory") 1)
+            (data (i32.const 12) "\00\00\00\00")
+            (func (export "run") (result i32) (i32.const 16)))"#,
+    )
+    .unwrap();
+    CREATED_MODULES
+        .lock()
+        .unwrap()
+        .insert("precompiled_callee".to_string(), callee.to_vec());
+    let caller = wasmer::wat2wasm(
+        format!(
+            r#"(module
+                (import "massa" "assembly_script_call"
+                    (func $call (param i32 i32 i32 i64) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 12) "\00\00\00\00")
+                {}
+                {}
+                (func (export "__new") (param i32 i32) (result i32)
+                    (i32.store (i32.const 1020) (local.get 0))
+                    (i32.const 1024))
+                (func (export "call")
+                    (drop (call $call (i32.const 32) (i32.const 96) (i32.const 16) (i64.const 0)))))"#,
+            wat_as_string(32, "precompiled_callee"),
+            wat_as_string(96, "run"),
+        )
+        .as_bytes(),
+    )
+    .unwrap();
+    let cache = Arc::new(ModuleCache::new(usize::MAX));
+    MODULE_CACHE.with(|module_cache| *module_cache.borrow_mut() = Some(cache.clone()));
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    cache
+        .precompile(&callee, gas_costs.clone(), &config, Compiler::SP)
+        .unwrap();
+    let handle = cache
+        .precompile(&caller, gas_costs.clone(), &config, Compiler::SP)
+        .unwrap();
+    assert_eq!(cache.compilation_count(), 2);
+
+    let fetches = BYTECODE_FETCHES.with(|fetches| fetches.get());
+    for _ in 0..3 {
+        run_function(
+            &interface,
+            handle.clone().into(),
+            "call",
+            b"",
+            1_000_000,
+            gas_costs.clone(),
+            &config,
+            ExecutionOptions::default(),
+        )
+        .unwrap();
+    }
+    assert_eq!(cache.compilation_count(), 2);
+    assert_eq!(BYTECODE_FETCHES.with(|fetches| fetches.get()), fetches);
+    MODULE_CACHE.with(|module_cache| *module_cache.borrow_mut() = None);
+}
+
+#[test]
+#[serial]
+/// Test modules being precompiled by some threads while other threads
+/// precompile and execute the same modules
+fn test_concurrent_precompilation() {
+    use std::sync::Arc;
+
+    let bytecodes: Vec<Vec<u8>> = (0..8)
+        .map(|i| {
+            wasmer::wat2wasm(
+                format!(
+                    r#"(module
+                        (memory (export "memory") 1)
+                        (global i32 (i32.const {}))
+                        (func (export "run")))"#,
+                    i
+                )
+                .as_bytes(),
+            )
+            .unwrap()
+            .to_vec()
+        })
+        .collect();
+    let cache = Arc::new(ModuleCache::new(usize::MAX));
+    std::thread::scope(|scope| {
+        for worker in 0..4 {
+            let (cache, bytecodes) = (cache.clone(), &bytecodes);
+            scope.spawn(move || {
+                let gas_costs = GasCosts::default();
+                let config = RuntimeConfig::default();
+                for bytecode in bytecodes.iter().cycle().skip(worker).take(16) {
+                    let handle = cache
+                        .precompile(bytecode, gas_costs.clone(), &config, Compiler::SP)
+                        .unwrap();
+                    // half of the threads only warm the cache up
+                    if worker % 2 == 1 {
+                        run_function(
+                            &TestInterface,
+                            handle.module(),
+                            "run",
+                            b"",
+                            1_000_000,
+                            gas_costs.clone(),
+                            &config,
+                            ExecutionOptions::default(),
+                        )
+                        .unwrap();
+                    }
+                }
+            });
+        }
+    });
+    assert_eq!(cache.len(), bytecodes.len());
+    // the concurrent precompilations of a module may each compile it
+    assert!(cache.compilation_count() >= bytecodes.len() as u64);
+    assert!(cache.compilation_count() <= 4 * bytecodes.len() as u64);
+    let compilations = cache.compilation_count();
+    for bytecode in &bytecodes {
+        cache
+            .precompile(
+                bytecode,
+                GasCosts::default(),
+                &RuntimeConfig::default(),
+                Compiler::SP,
+            )
+            .unwrap();
+    }
+    assert_eq!(cache.compilation_count(), compilations);
+}
+
+#[test]
+#[serial]
+fn test_access_cache_per_frame() {
+    let interface = TestInterface;
+    // `top` checks its access rights around a call to `nested`, whose caller
+    // is not the top-level one, each check being made twice
+    let module = wasmer::wat2wasm(
+        format!(
+            r#"(module
+                (import "massa" "assembly_script_caller_has_write_access"
+                    (func $write_access (result i32)))
+                (import "massa" "assembly_script_is_address_eoa"
+                    (func $is_eoa (param i32) (result i32)))
+                (import "massa" "assembly_script_call"
+                    (func $call (param i32 i32 i32 i64) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 12) "\00\00\00\00")
+                {}
+                {}
+                (func (export "__new") (param i32 i32) (result i32)
+                    (i32.store (i32.const 1020) (local.get 0))
+                    (i32.const 1024))
+                (func $check (param $write_access i32)
+                    (if (i32.ne (call $write_access) (local.get $write_access)) (then unreachable))
+                    (if (i32.ne (call $write_access) (local.get $write_access)) (then unreachable))
+                    (if (i32.eqz (call $is_eoa (i32.const 32))) (then unreachable))
+                    (if (i32.eqz (call $is_eoa (i32.const 32))) (then unreachable)))
+                (func (export "nested") (result i32)
+                    (call $check (i32.const 0))
+                    (i32.const 16))
+                (func (export "top")
+                    (call $check (i32.const 1))
+                    (drop (call $call (i32.const 32) (i32.const 96) (i32.const 16) (i64.const 0)))
+                    (call $check (i32.const 1))))"#,
+            wat_as_string(32, "access_cache"),
+            wat_as_string(96, "nested"),
+        )
+        .as_bytes(),
+    )
+    .unwrap();
+    CREATED_MODULES
+        .lock()
+        .unwrap()
+        .insert("access_cache".to_string(), module.clone());
+
+    let queries = ACCESS_QUERIES.with(|queries| queries.get());
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    let runtime_module =
+        RuntimeModule::new(&module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+    run_function(
+        &interface,
+        runtime_module,
+        "top",
+        b"",
+        1_000_000,
+        gas_costs,
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap();
+    // each frame asks the interface once per question
+    assert_eq!(ACCESS_QUERIES.with(|queries| queries.get()) - queries, 4);
+}
+
+#[test]
+#[serial]
+/// Test the conversions between public keys and addresses, each one being
+/// asked once per execution to the interface
+fn test_public_key_conversions() {
+    use crate::tests::CONVERSION_QUERIES;
+
+    let interface = TestInterface;
+    let public_key = [1, 2, 3, 4, 5];
+    let public_key_string = "P0102030405";
+    let wat = format!(
+        r#"(module
+            (import "massa" "assembly_script_address_from_public_key"
+                (func $address (param i32) (result i32)))
+            (import "massa" "assembly_script_address_from_public_key_bytes"
+                (func $address_of_bytes (param i32) (result i32)))
+            (import "massa" "assembly_script_public_key_from_string"
+                (func $public_key (param i32) (result i32)))
+            (import "massa" "assembly_script_validate_address"
+                (func $validate (param i32) (result i32)))
+            (memory (export "memory") 1)
+            {}
+            (data (i32.const 92) "{}{}")
+            {}
+            (data (i32.const 220) "\00\00\00\00")
+            (func (export "__new") (param i32 i32) (result i32)
+                (i32.store (i32.const 1020) (local.get 0))
+                (i32.const 1024))
+            (func (export "public_key") (result i32)
+                (call $public_key (i32.const 32)))
+            (func (export "address") (result i32)
+                (call $address (i32.const 32)))
+            (func (export "address_of_bytes") (result i32)
+                (call $address_of_bytes (i32.const 96)))
+            (func (export "round_trip") (result i32)
+                (call $address_of_bytes (call $public_key (i32.const 32))))
+            (func (export "malformed_public_key") (result i32)
+                (call $public_key (i32.const 160)))
+            (func (export "empty_public_key") (result i32)
+                (call $address_of_bytes (i32.const 224)))
+            (func (export "repeated") (result i32)
+                (drop (call $validate (i32.const 160)))
+                (drop (call $validate (i32.const 160)))
+                (drop (call $address (i32.const 32)))
+                (drop (call $address_of_bytes (call $public_key (i32.const 32))))
+                (call $address_of_bytes (call $public_key (i32.const 32)))))"#,
+        wat_as_string(32, public_key_string),
+        wat_bytes(&(public_key.len() as u32).to_le_bytes()),
+        wat_bytes(&public_key),
+        wat_as_string(160, "P0g"),
+    );
+    let module = wasmer::wat2wasm(wat.as_bytes()).unwrap();
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    let run = |function: &str| {
+        let runtime_module =
+            RuntimeModule::new(&module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+        run_function(
+            &interface,
+            runtime_module,
+            function,
+            b"",
+            1_000_000,
+            gas_costs.clone(),
+            &config,
+            ExecutionOptions::default(),
+        )
+    };
+    let utf16 =
+        |string: String| -> Vec<u8> { string.encode_utf16().flat_map(u16::to_le_bytes).collect() };
+
+    let address = interface
+        .address_from_public_key_bytes(&public_key)
+        .unwrap();
+    assert_eq!(run("public_key").unwrap().ret, public_key);
+    assert_eq!(run("address").unwrap().ret, utf16(address.clone()));
+    assert_eq!(run("address_of_bytes").unwrap().ret, utf16(address.clone()));
+    assert_eq!(run("round_trip").unwrap().ret, utf16(address.clone()));
+    for function in ["malformed_public_key", "empty_public_key"] {
+        assert!(run(function).is_err(), "{}", function);
+    }
+
+    let queries = CONVERSION_QUERIES.with(|queries| queries.get());
+    assert_eq!(run("repeated").unwrap().ret, utf16(address));
+    // one query per conversion and input
+    assert_eq!(
+        CONVERSION_QUERIES.with(|queries| queries.get()) - queries,
+        4
+    );
+    // the cache is not shared by the executions
+    run("repeated").unwrap();
+    assert_eq!(
+        CONVERSION_QUERIES.with(|queries| queries.get()) - queries,
+        8
+    );
+}
+
+#[test]
+#[serial]
+fn test_interrupt() {
+    use crate::{InterruptHandle, VMErrorCode};
+
+    let interface = TestInterface;
+    let module = wasmer::wat2wasm(
+        format!(
+            r#"(module
+                (import "massa" "assembly_script_get_time" (func $time (result i64)))
+                (import "massa" "assembly_script_call"
+                    (func $call (param i32 i32 i32 i64) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 12) "\00\00\00\00")
+                {}
+                {}
+                (func (export "__new") (param i32 i32) (result i32)
+                    (i32.store (i32.const 1020) (local.get 0))
+                    (i32.const 1024))
+                (func (export "busy") (loop $next (drop (call $time)) (br $next)))
+                (func (export "call_busy")
+                    (drop (call $call (i32.const 32) (i32.const 96) (i32.const 16) (i64.const 0)))))"#,
+            wat_as_string(32, "interrupted"),
+            wat_as_string(96, "busy"),
+        )
+        .as_bytes(),
+    )
+    .unwrap();
+    CREATED_MODULES
+        .lock()
+        .unwrap()
+        .insert("interrupted".to_string(), module.clone());
+
+    // the interruption reaches the nested calls
+    for function in ["busy", "call_busy"] {
+        let interrupt = InterruptHandle::new();
+        let supervisor = {
+            let interrupt = interrupt.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(std::time::Duration::from_millis(50));
+                interrupt.interrupt();
+            })
+        };
+        let gas_costs = GasCosts::default();
+        let config = RuntimeConfig::default();
+        let runtime_module =
+            RuntimeModule::new(&module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+        let err = run_function(
+            &interface,
+            runtime_module,
+            function,
+            b"",
+            u64::MAX / 2,
+            gas_costs,
+            &config,
+            ExecutionOptions {
+                interrupt: Some(interrupt),
+                ..Default::default()
+            },
+        )
+        .unwrap_err();
+        supervisor.join().unwrap();
+        assert_eq!(
+            err.code(),
+            VMErrorCode::Interrupted,
+            "{}: {}",
+            function,
+            err
+        );
+        assert!(
+            err.to_string()
+                .contains("Execution interrupted through its interrupt handle"),
+            "{}: {}",
+            function,
+            err
+        );
+    }
+}
+
+#[test]
+#[serial]
+/// Test that the loops calling no ABI are stopped at the deadline and by the
+/// interrupt handle, the calls to the local functions of the loops being
+/// unaffected by the check imported in the modules
+fn test_loop_deadline() {
+    use crate::{InterruptHandle, VMErrorCode};
+
+    let interface = TestInterface;
+    let as_module = wasmer::wat2wasm(
+        br#"(module
+            (memory (export "memory") 1)
+            (global $count (mut i32) (i32.const 0))
+            (func $step (global.set $count (i32.add (global.get $count) (i32.const 1))))
+            (func (export "busy") (loop $next (br $next)))
+            (func (export "count")
+                (loop $next
+                    (call $step)
+                    (br_if $next (i32.lt_u (global.get $count) (i32.const 5000))))
+                (if (i32.ne (global.get $count) (i32.const 5000)) (then unreachable))))"#,
+    )
+    .unwrap();
+    let mut wasmv1_module = vec![1];
+    wasmv1_module.extend(
+        wasmer::wat2wasm(
+            br#"(module
+                (memory (export "memory") 1)
+                (func (export "__alloc") (param i32) (result i32) (i32.const 1024))
+                (func (export "busy") (param i32) (result i32)
+                    (loop $next (br $next))
+                    (i32.const 0)))"#,
+        )
+        .unwrap()
+        .iter(),
+    );
+
+    // the local calls of a loop still reach their function
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    let runtime_module =
+        RuntimeModule::new(&as_module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+    run_function(
+        &interface,
+        runtime_module,
+        "count",
+        b"",
+        u64::MAX / 2,
+        gas_costs,
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap();
+
+    for module in [&as_module, &wasmv1_module] {
+        let gas_costs = GasCosts::default();
+        let config = RuntimeConfig {
+            max_execution_time: Some(std::time::Duration::from_millis(50)),
+            ..RuntimeConfig::default()
+        };
+        let runtime_module =
+            RuntimeModule::new(module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+        let start = std::time::Instant::now();
+        let err = run_function(
+            &interface,
+            runtime_module,
+            "busy",
+            b"",
+            u64::MAX / 2,
+            gas_costs,
+            &config,
+            ExecutionOptions::default(),
+        )
+        .unwrap_err();
+        assert!(start.elapsed() < std::time::Duration::from_secs(10));
+        assert_eq!(err.code(), VMErrorCode::Timeout, "{}", err);
+
+        let interrupt = InterruptHandle::new();
+        let supervisor = {
+            let interrupt = interrupt.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(std::time::Duration::from_millis(50));
+                interrupt.interrupt();
+            })
+        };
+        let gas_costs = GasCosts::default();
+        let config = RuntimeConfig::default();
+        let runtime_module =
+            RuntimeModule::new(module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+        let err = run_function(
+            &interface,
+            runtime_module,
+            "busy",
+            b"",
+            u64::MAX / 2,
+            gas_costs,
+            &config,
+            ExecutionOptions {
+                interrupt: Some(interrupt),
+                ..Default::default()
+            },
+        )
+        .unwrap_err();
+        supervisor.join().unwrap();
+        assert_eq!(err.code(), VMErrorCode::Interrupted, "{}", err);
+    }
+}
+
+#[test]
+fn test_get_keys_paged() {
+    let interface = TestInterface;
+    let module = wasmer::wat2wasm(
+        br#"(module
+            (import "massa" "assembly_script_get_keys_paged"
+                (func $get_keys_paged (param i32 i32 i32) (result i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 12) "\00\00\00\00")
+            (func (export "__new") (param i32 i32) (result i32)
+                (i32.store (i32.const 1020) (local.get 0))
+                (i32.const 1024))
+            (func (export "page") (param i32) (result i32)
+                (call $get_keys_paged (i32.const 16) (local.get 0) (i32.const 2)))
+            (func (export "empty_page") (param i32) (result i32)
+                (call $get_keys_paged (i32.const 16) (local.get 0) (i32.const 0))))"#,
+    )
+    .unwrap();
+    DATASTORE_KEYS.with(|keys| {
+        *keys.borrow_mut() = ["a", "b", "c", "d", "e"]
+            .iter()
+            .map(|key| key.as_bytes().to_vec())
+            .collect()
+    });
+    let run = |function: &str, cursor: &[u8]| {
+        let gas_costs = GasCosts::default();
+        let config = RuntimeConfig::default();
+        let runtime_module =
+            RuntimeModule::new(&module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+        run_function(
+            &interface,
+            runtime_module,
+            function,
+            cursor,
+            100_000,
+            gas_costs,
+            &config,
+            ExecutionOptions::default(),
+        )
+    };
+    // cursor of the next page and keys of a page
+    let parse = |page: &[u8]| {
+        let cursor_len = u32::from_le_bytes(page[..4].try_into().unwrap()) as usize;
+        let (cursor, mut keys) = page[4..].split_at(cursor_len);
+        let mut parsed = Vec::new();
+        if !keys.is_empty() {
+            keys = &keys[4..];
+            while let Some((&len, rest)) = keys.split_first() {
+                parsed.push(String::from_utf8(rest[..len as usize].to_vec()).unwrap());
+                keys = &rest[len as usize..];
+            }
+        }
+        (cursor.to_vec(), parsed)
+    };
+
+    let mut cursor = Vec::new();
+    let mut pages = Vec::new();
+    let mut gas_used = Vec::new();
+    loop {
+        let response = run("page", &cursor).unwrap();
+        gas_used.push(100_000 - response.remaining_gas);
+        let (next, keys) = parse(&response.ret);
+        pages.push(keys);
+        if next.is_empty() {
+            break;
+        }
+        cursor = next;
+    }
+    assert_eq!(pages, vec![vec!["a", "b"], vec!["c", "d"], vec!["e"]]);
+    // each key returned is charged
+    let per_key = GasCosts::default().abi_costs["assembly_script_get_keys_paged_per_entry"];
+    assert!(gas_used[0] >= gas_used[2] + per_key);
+
+    // stale cursors are reported by the interface, malformed ones by the
+    // runtime
+    let (stale, _) = parse(&run("page", b"").unwrap().ret);
+    DATASTORE_KEYS.with(|keys| keys.borrow_mut().remove(&b"b".to_vec()));
+    let err = run("page", &stale).unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("Could not get the page of keys: stale cursor"),
+        "{}",
+        err
+    );
+    let err = run("page", b"\x07b").unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("Invalid datastore cursor: unknown version 7"),
+        "{}",
+        err
+    );
+    let err = run("empty_page", b"").unwrap_err();
+    assert!(err.to_string().contains("Invalid page limit 0"), "{}", err);
+}
+
+#[test]
+fn test_runtime_version() {
+    let interface = TestInterface;
+    let module = wasmer::wat2wasm(
+        br#"(module
+            (import "massa" "assembly_script_runtime_version"
+                (func $runtime_version (result i32)))
+            (memory (export "memory") 1)
+            (func (export "__new") (param i32 i32) (result i32)
+                (i32.const 1024))
+            (func (export "main")
+                (if (i32.ne (call $runtime_version) (i32.const 1))
+                    (then unreachable))))"#,
+    )
+    .unwrap();
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    let runtime_module =
+        RuntimeModule::new(&module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+    run_main(
+        &interface,
+        runtime_module,
+        100_000,
+        gas_costs.clone(),
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap();
+
+    // a module declaring a future version is refused before its compilation
+    let mut future = module.to_vec();
+    let name = b"__massa_abi_version";
+    future.extend([0, (1 + name.len() + 4) as u8, name.len() as u8]);
+    future.extend(name);
+    future.extend(2u32.to_le_bytes());
+    let err = RuntimeModule::new(&future, gas_costs, &config, Compiler::SP).unwrap_err();
+    assert!(err.to_string().contains(
+        "Unsupported ABI version: the module requires version 2, the runtime supports up to 1"
+    ));
+}
+
+#[test]
+fn test_constant_eq() {
+    let interface = TestInterface;
+    let buffer = |ptr: u32, bytes: &[u8]| {
+        format!(
+            r#"(data (i32.const {}) "{}{}")"#,
+            ptr - 4,
+            wat_bytes(&(bytes.len() as u32).to_le_bytes()),
+            wat_bytes(bytes)
+        )
+    };
+    // the result of the comparison, or the error
+    let run = |a: &[u8], b: &[u8]| {
+        let module = wasmer::wat2wasm(
+            format!(
+                r#"(module
+                    (import "massa" "assembly_script_constant_eq"
+                        (func $constant_eq (param i32 i32) (result i32)))
+                    (memory (export "memory") 1)
+                    {}
+                    {}
+                    (func (export "__new") (param i32 i32) (result i32)
+                        (i32.const 1024))
+                    (func (export "main")
+                        (if (call $constant_eq (i32.const 64) (i32.const 512))
+                            (then unreachable))))"#,
+                buffer(64, a),
+                buffer(512, b),
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+        let gas_costs = GasCosts::default();
+        let config = RuntimeConfig {
+            max_constant_eq_input_len: 32,
+            ..RuntimeConfig::default()
+        };
+        let runtime_module =
+            RuntimeModule::new(&module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+        run_main(
+            &interface,
+            runtime_module,
+            100_000,
+            gas_costs,
+            &config,
+            ExecutionOptions::default(),
+        )
+    };
+    // a trap tells the inputs are equal
+    let equal = |a: &[u8], b: &[u8]| match run(a, b) {
+        Ok(_) => false,
+        Err(err) if err.to_string().contains("unreachable") => true,
+        Err(err) => panic!("unexpected error: {}", err),
+    };
+
+    assert!(equal(b"secret", b"secret"));
+    assert!(equal(b"", b""));
+    assert!(!equal(b"secret", b"secreT"));
+    assert!(!equal(b"secret", b"secrets"));
+    assert!(!equal(b"", b"secret"));
+    // at the limit
+    assert!(equal(&[7; 32], &[7; 32]));
+    assert!(!equal(&[7; 32], &[8; 32]));
+    // any input above the limit is refused, even with the other one short
+    let err = run(&[7; 33], b"").unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("Constant-time comparison input of 33 bytes exceeds the maximum of 32 bytes"));
+}
+
+#[test]
+fn test_verify_merkle_proof() {
+    use sha2::{Digest, Sha256};
+    let interface = TestInterface;
+    let buffer = |ptr: u32, bytes: &[u8]| {
+        format!(
+            r#"(data (i32.const {}) "{}{}")"#,
+            ptr - 4,
+            wat_bytes(&(bytes.len() as u32).to_le_bytes()),
+            wat_bytes(bytes)
+        )
+    };
+    // the result of the verification, or the error
+    let run = |leaf: &[u8], proof: &[u8], root: &[u8], hash_algo: i32| {
+        let module = wasmer::wat2wasm(
+            format!(
+                r#"(module
+                    (import "massa" "assembly_script_verify_merkle_proof"
+                        (func $verify (param i32 i32 i32 i32) (result i32)))
+                    (memory (export "memory") 1)
+                    {}
+                    {}
+                    {}
+                    (func (export "__new") (param i32 i32) (result i32)
+                        (i32.const 1024))
+                    (func (export "main")
+                        (if (call $verify (i32.const 64) (i32.const 128) (i32.const 512)
+                                (i32.const {}))
+                            (then unreachable))))"#,
+                buffer(64, leaf),
+                buffer(128, proof),
+                buffer(512, root),
+                hash_algo,
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+        let gas_costs = GasCosts::default();
+        let config = RuntimeConfig {
+            max_merkle_proof_depth: 2,
+            ..RuntimeConfig::default()
+        };
+        let runtime_module =
+            RuntimeModule::new(&module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+        run_main(
+            &interface,
+            runtime_module,
+            100_000,
+            gas_costs,
+            &config,
+            ExecutionOptions::default(),
+        )
+    };
+    // a trap tells the proof is valid
+    let valid = |leaf: &[u8], proof: &[u8], root: &[u8], hash_algo: i32| match run(
+        leaf, proof, root, hash_algo,
+    ) {
+        Ok(_) => false,
+        Err(err) if err.to_string().contains("unreachable") => true,
+        Err(err) => panic!("unexpected error: {}", err),
+    };
+    let error = |leaf: &[u8], proof: &[u8], root: &[u8], hash_algo: i32| {
+        run(leaf, proof, root, hash_algo).unwrap_err().to_string()
+    };
+
+    // a tree of two leaves, the sibling of the leaf on its right
+    let (leaf, sibling) = ([1; 32], [2; 32]);
+    let root: [u8; 32] = Sha256::new()
+        .chain_update(leaf)
+        .chain_update(sibling)
+        .finalize()
+        .into();
+    let proof = [&sibling[..], &[1]].concat();
+    assert!(valid(&leaf, &proof, &root, 1));
+    assert!(!valid(&sibling, &proof, &root, 1));
+    // the sibling on the left
+    assert!(!valid(&leaf, &[&sibling[..], &[0]].concat(), &root, 1));
+    // the Massa hash of the test interface is always zero
+    assert!(valid(&leaf, &proof, &[0; 32], 0));
+    assert!(!valid(&leaf, &proof, &root, 0));
+
+    // malformed proofs fail the call
+    assert!(error(&leaf, &proof[..32], &root, 1)
+        .contains("Malformed proof of 32 bytes, not a whole number of 33 bytes elements"));
+    assert!(error(&leaf, &[&sibling[..], &[2]].concat(), &root, 1)
+        .contains("Invalid direction 2 of the proof element 0, expected 0 or 1"));
+    assert!(error(&leaf, &proof.repeat(3), &root, 1)
+        .contains("Proof of depth 3 exceeds the maximum of 2"));
+    assert!(error(&leaf[..31], &proof, &root, 1)
+        .contains("The leaf hash is 31 bytes long instead of 32"));
+    assert!(error(&leaf, &proof, &root, 2).contains("Unknown hash algorithm 2"));
+}
+
+#[test]
+fn test_native_amount_abis() {
+    let interface = TestInterface;
+    let module = wasmer::wat2wasm(
+        format!(
+            r#"(module
+                (import "massa" "assembly_script_checked_add_native_amount"
+                    (func $add (param i64 i64) (result i32)))
+                (import "massa" "assembly_script_checked_div_native_amount"
+                    (func $div (param i64 i64) (result i32)))
+                (import "massa" "assembly_script_native_amount_from_string"
+                    (func $from_string (param i32) (result i32)))
+                (import "massa" "assembly_script_native_amount_to_string"
+                    (func $to_string (param i64) (result i32)))
+                (memory (export "memory") 1)
+                {}
+                {}
+                (func (export "__new") (param i32 i32) (result i32)
+                    (i32.store (i32.const 1020) (local.get 0))
+                    (i32.const 1024))
+                (func (export "add_max") (result i32)
+                    (call $add (i64.const -1) (i64.const 1)))
+                (func (export "add") (result i32)
+                    (call $add (i64.const -2) (i64.const 1)))
+                (func (export "div_zero") (result i32)
+                    (call $div (i64.const 7) (i64.const 0)))
+                (func (export "parse") (result i32)
+                    (call $from_string (i32.const 64)))
+                (func (export "parse_malformed") (result i32)
+                    (call $from_string (i32.const 128)))
+                (func (export "format") (result i32)
+                    (call $to_string (i64.const 1500000000))))"#,
+            wat_as_string(64, "1.5"),
+            wat_as_string(128, "1.5.0"),
+        )
+        .as_bytes(),
+    )
+    .unwrap();
+    let run = |function: &str| {
+        let gas_costs = GasCosts::default();
+        let config = RuntimeConfig::default();
+        let runtime_module =
+            RuntimeModule::new(&module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+        // never a trap, the invalid operations are encoded in the result
+        run_function(
+            &interface,
+            runtime_module,
+            function,
+            b"",
+            100_000,
+            gas_costs,
+            &config,
+            ExecutionOptions::default(),
+        )
+        .unwrap()
+        .ret
+    };
+    let amount = |amount: u64| [&[0][..], &amount.to_le_bytes()].concat();
+
+    assert_eq!(run("add_max"), vec![1]);
+    assert_eq!(run("add"), amount(u64::MAX));
+    assert_eq!(run("div_zero"), vec![3]);
+    assert_eq!(run("parse"), amount(1_500_000_000));
+    assert_eq!(run("parse_malformed"), vec![4]);
+    let utf16: Vec<u8> = "1.5".encode_utf16().flat_map(u16::to_le_bytes).collect();
+    assert_eq!(run("format"), utf16);
+}
+
+#[test]
+fn test_slot_conversions() {
+    let interface = TestInterface;
+    let module = wasmer::wat2wasm(
+        br#"(module
+            (import "massa" "assembly_script_get_current_slot"
+                (func $current_slot (result i32)))
+            (import "massa" "assembly_script_slot_to_timestamp"
+                (func $slot_to_timestamp (param i64 i32) (result i64)))
+            (import "massa" "assembly_script_timestamp_to_slot"
+                (func $timestamp_to_slot (param i64) (result i32)))
+            (memory (export "memory") 1)
+            (func (export "__new") (param i32 i32) (result i32)
+                (i32.store (i32.const 1020) (local.get 0))
+                (i32.const 1024))
+            (func (export "current_slot") (result i32)
+                (call $current_slot))
+            ;; the slot is read from the 12 bytes of the param: period and
+            ;; thread, the timestamp is returned in a buffer
+            (func (export "slot_to_timestamp") (param $slot i32) (result i32)
+                (i64.store (i32.const 2048)
+                    (call $slot_to_timestamp
+                        (i64.load (local.get $slot))
+                        (i32.load (i32.add (local.get $slot) (i32.const 8)))))
+                (i32.store (i32.const 2044) (i32.const 8))
+                (i32.const 2048))
+            (func (export "timestamp_to_slot") (param $timestamp i32) (result i32)
+                (call $timestamp_to_slot (i64.load (local.get $timestamp)))))"#,
+    )
+    .unwrap();
+    let run = |function: &str, param: &[u8], thread_count: u8| {
+        let gas_costs = GasCosts::default();
+        let config = RuntimeConfig {
+            thread_count,
+            ..RuntimeConfig::default()
+        };
+        let runtime_module =
+            RuntimeModule::new(&module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+        run_function(
+            &interface,
+            runtime_module,
+            function,
+            param,
+            100_000,
+            gas_costs,
+            &config,
+            ExecutionOptions::default(),
+        )
+    };
+    let slot_to_timestamp = |period: i64, thread: i32, thread_count: u8| {
+        let param = [&period.to_le_bytes()[..], &thread.to_le_bytes()].concat();
+        run("slot_to_timestamp", &param, thread_count)
+            .map(|response| u64::from_le_bytes(response.ret.try_into().unwrap()))
+    };
+    let timestamp_to_slot = |timestamp: i64, thread_count: u8| {
+        run("timestamp_to_slot", &timestamp.to_le_bytes(), thread_count).map(|response| {
+            let (period, thread) = response.ret.split_at(8);
+            (u64::from_le_bytes(period.try_into().unwrap()), thread[0])
+        })
+    };
+    let thread_duration = PERIOD_DURATION / 32;
+
+    // the mock executes in slot 0
+    assert_eq!(run("current_slot", b"", 32).unwrap().ret, vec![0; 9]);
+    assert_eq!(slot_to_timestamp(0, 0, 32).unwrap(), GENESIS_TIMESTAMP);
+    assert_eq!(
+        timestamp_to_slot(GENESIS_TIMESTAMP as i64, 32).unwrap(),
+        (0, 0)
+    );
+    let timestamp = GENESIS_TIMESTAMP + 2 * PERIOD_DURATION + 3 * thread_duration;
+    assert_eq!(slot_to_timestamp(2, 3, 32).unwrap(), timestamp);
+    assert_eq!(timestamp_to_slot(timestamp as i64, 32).unwrap(), (2, 3));
+    // within the slot
+    assert_eq!(timestamp_to_slot(timestamp as i64 + 1, 32).unwrap(), (2, 3));
+
+    // far future
+    let elapsed = i64::MAX as u64 - GENESIS_TIMESTAMP;
+    assert_eq!(
+        timestamp_to_slot(i64::MAX, 32).unwrap(),
+        (
+            elapsed / PERIOD_DURATION,
+            ((elapsed % PERIOD_DURATION) / thread_duration) as u8
+        )
+    );
+    assert!(slot_to_timestamp(i64::MAX, 0, 32)
+        .unwrap_err()
+        .to_string()
+        .contains("slot timestamp overflow"));
+
+    // out of range values
+    assert!(timestamp_to_slot(GENESIS_TIMESTAMP as i64 - 1, 32)
+        .unwrap_err()
+        .to_string()
+        .contains("is before the genesis"));
+    assert!(timestamp_to_slot(-1, 32)
+        .unwrap_err()
+        .to_string()
+        .contains("Negative timestamp"));
+    assert!(slot_to_timestamp(-1, 0, 32)
+        .unwrap_err()
+        .to_string()
+        .contains("Negative slot period"));
+    for thread in [32, -1] {
+        assert!(slot_to_timestamp(0, thread, 32)
+            .unwrap_err()
+            .to_string()
+            .contains(&format!(
+                "Invalid slot thread {}: the thread count is 32",
+                thread
+            )));
+    }
+    // the thread count of the configuration is the one checked
+    assert_eq!(
+        slot_to_timestamp(0, 3, 4).unwrap(),
+        GENESIS_TIMESTAMP + 3 * thread_duration
+    );
+    assert!(slot_to_timestamp(0, 4, 4).is_err());
+    assert!(
+        timestamp_to_slot((GENESIS_TIMESTAMP + 4 * thread_duration) as i64, 4)
+            .unwrap_err()
+            .to_string()
+            .contains("Invalid thread 4 of the slot")
+    );
+}
+
+#[test]
+fn test_get_balances() {
+    let interface = TestInterface;
+    let module = wasmer::wat2wasm(
+        format!(
+            r#"(module
+                (import "massa" "assembly_script_get_balances"
+                    (func $get_balances (param i32) (result i32)))
+                (import "massa" "assembly_script_get_balance_for"
+                    (func $get_balance_for (param i32) (result i64)))
+                (memory (export "memory") 1)
+                {}
+                (func (export "__new") (param i32 i32) (result i32)
+                    (i32.store (i32.const 1020) (local.get 0))
+                    (i32.const 1024))
+                (func (export "get_balances") (param i32) (result i32)
+                    (call $get_balances (local.get 0)))
+                (func (export "get_invalid_balance")
+                    (drop (call $get_balance_for (i32.const 64)))))"#,
+            wat_as_string(64, "AU1bad!"),
+        )
+        .as_bytes(),
+    )
+    .unwrap();
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig {
+        address_validator: AddressValidator {
+            min_len: 4,
+            max_len: 32,
+            prefixes: vec![
+                "AU".to_string(),
+                "full_".to_string(),
+                "unknown_".to_string(),
+            ],
+            charset: Some("abcdefghijklmnopqrstuvwxyz_0123456789".to_string()),
+        },
+        max_balance_batch_len: 6,
+        ..RuntimeConfig::default()
+    };
+    let run = |function: &str, addresses: &[&str]| {
+        let mut param = (addresses.len() as u32).to_le_bytes().to_vec();
+        for address in addresses {
+            param.push(address.len() as u8);
+            param.extend(address.as_bytes());
+        }
+        let runtime_module =
+            RuntimeModule::new(&module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+        run_function(
+            &interface,
+            runtime_module,
+            function,
+            &param,
+            100_000,
+            gas_costs.clone(),
+            &config,
+            ExecutionOptions::default(),
+        )
+    };
+    let balance = |amount: u64| [&[0][..], &amount.to_le_bytes()].concat();
+
+    let response = run(
+        "get_balances",
+        &[
+            FULL_BALANCE_ADDRESS,
+            "AU1bad!",
+            UNKNOWN_BALANCE_ADDRESS,
+            "XY12",
+            "AU",
+            "AU12",
+        ],
+    )
+    .unwrap();
+    // the invalid addresses do not fail the batch
+    assert_eq!(
+        response.ret,
+        [
+            &6u32.to_le_bytes()[..],
+            &balance(u64::MAX),
+            &[1],
+            &[2],
+            &[1],
+            &[1],
+            &balance(0),
+        ]
+        .concat()
+    );
+    // charged for each address
+    let single = run("get_balances", &["AU12"]).unwrap();
+    let per_entry = gas_costs.abi_costs["assembly_script_get_balances_per_entry"];
+    assert!(single.remaining_gas >= response.remaining_gas + 5 * per_entry);
+    assert_eq!(
+        run("get_balances", &[]).unwrap().ret,
+        0u32.to_le_bytes().to_vec()
+    );
+
+    let err = run("get_balances", &["AU12"; 7]).unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("Too many addresses: 7, the maximum is 6"));
+    let err = run("get_invalid_balance", &[]).unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("Invalid address AU1bad!: character '!' is not allowed"));
+}
+
+#[test]
+/// Pin the gas used by a fixture: the launch, the ABIs at their `GasCosts`
+/// prices and the guest instructions, the ones of `__new` included, each
+/// charged once
+fn test_abi_gas_policy() {
+    let interface = TestInterface;
+    let module = wasmer::wat2wasm(
+        br#"(module
+            (import "massa" "assembly_script_get_time" (func $time (result i64)))
+            (import "massa" "assembly_script_sha256" (func $sha256 (param i32) (result i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 12) "\03\00\00\00abc")
+            (func (export "__new") (param i32 i32) (result i32)
+                (i32.store (i32.const 1020) (local.get 0))
+                (i32.const 1024))
+            (func (export "main")
+                (drop (call $time))
+                (drop (call $sha256 (i32.const 16)))))"#,
+    )
+    .unwrap();
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    let runtime_module =
+        RuntimeModule::new(&module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+    let limit = 100_000;
+    let response = run_main(
+        &interface,
+        runtime_module,
+        limit,
+        gas_costs.clone(),
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap();
+
+    let abi_costs = &gas_costs.abi_costs;
+    // call, drop, i32.const, call, drop and end in `main`, then i32.const,
+    // local.get, i32.store, i32.const and end in `__new` to allocate the hash
+    let instructions = (6 + 5) * gas_costs.operator_cost;
+    let expected = gas_costs.launch_cost
+        + abi_costs["assembly_script_get_time"]
+        + abi_costs["assembly_script_sha256"]
+        + 3 * abi_costs["assembly_script_sha256_per_byte"]
+        + instructions;
+    assert_eq!(
+        limit - response.init_gas_cost - response.remaining_gas,
+        expected
+    );
+}
+
+#[test]
+fn test_host_extensions() {
+    let interface = TestInterface;
+    let module = wasmer::wat2wasm(
+        br#"(module
+            (import "sandbox" "debug_log" (func $debug_log (param i32)))
+            (memory (export "memory") 1)
+            (func (export "__new") (param i32 i32) (result i32)
+                (i32.const 1024))
+            (func (export "main")
+                (call $debug_log (i32.const 7))
+                (call $debug_log (i32.const 42))))"#,
+    )
+    .unwrap();
+    let logged = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let callback_logged = logged.clone();
+    let gas_costs = GasCosts::default();
+    let mut config = RuntimeConfig::default();
+    config
+        .host_extensions
+        .register(
+            "sandbox",
+            "debug_log",
+            wasmer::FunctionType::new(vec![wasmer::Type::I32], vec![]),
+            std::sync::Arc::new(move |args: &[wasmer::Value]| {
+                callback_logged.lock().unwrap().push(args[0].unwrap_i32());
+                Ok(Vec::new())
+            }),
+        )
+        .unwrap();
+    let runtime_module =
+        RuntimeModule::new(&module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+    run_main(
+        &interface,
+        runtime_module,
+        100_000,
+        gas_costs.clone(),
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap();
+    assert_eq!(*logged.lock().unwrap(), vec![7, 42]);
+
+    // without the extension the import cannot be resolved
+    let runtime_module = RuntimeModule::new(
+        &module,
+        GasCosts::default(),
+        &RuntimeConfig::default(),
+        Compiler::SP,
+    )
+    .unwrap();
+    assert!(run_main(
+        &interface,
+        runtime_module,
+        100_000,
+        GasCosts::default(),
+        &RuntimeConfig::default(),
+        ExecutionOptions::default()
+    )
+    .is_err());
+
+    // a set made strict after the registration refuses the execution
+    config.host_extensions.strict = true;
+    let runtime_module =
+        RuntimeModule::new(&module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+    let err = run_main(
+        &interface,
+        runtime_module,
+        100_000,
+        gas_costs,
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("Host extensions are disallowed by the strict flag"));
+    assert_eq!(logged.lock().unwrap().len(), 2);
+}
+
+/// Module returning its parameter, with an `__new` allocating every buffer at
+/// the same offset
+const ECHO_PARAM_WAT: &str = r#"(module
+    (memory (export "memory") 32)
+    (func (export "__new") (param i32 i32) (result i32)
+        (i32.store (i32.const 1020) (local.get 0))
+        (i32.const 1024))
+    (func (export "echo") (param i32) (result i32)
+        (local.get 0)))"#;
+
+#[test]
+fn test_param_sizes() {
+    use crate::VMErrorCode;
+
+    let interface = TestInterface;
+    let module = wasmer::wat2wasm(ECHO_PARAM_WAT.as_bytes()).unwrap();
+    let gas_costs = GasCosts::default();
+    let mut config = RuntimeConfig::default();
+    config.max_param_size = 100_000;
+    let run = |param: &[u8]| {
+        let runtime_module =
+            RuntimeModule::new(&module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+        run_function(
+            &interface,
+            runtime_module,
+            "echo",
+            param,
+            1_000_000,
+            gas_costs.clone(),
+            &config,
+            ExecutionOptions::default(),
+        )
+    };
+
+    assert!(run(&[]).unwrap().ret.is_empty());
+    let param: Vec<u8> = (0..config.max_param_size)
+        .map(|i| (i % 251) as u8)
+        .collect();
+    assert_eq!(run(&param).unwrap().ret, param);
+
+    let mut too_large = param;
+    too_large.push(0);
+    let err = run(&too_large).unwrap_err();
+    assert_eq!(err.code(), VMErrorCode::ParamTooLarge, "{}", err);
+    assert_eq!(
+        err.to_string(),
+        "VM instance error: parameter of 100001 bytes exceeds the maximum of 100000 bytes"
+    );
+}
+
+#[test]
+fn test_return_sizes() {
+    use crate::VMErrorCode;
+
+    let interface = TestInterface;
+    let as_module = wasmer::wat2wasm(ECHO_PARAM_WAT.as_bytes())
+        .unwrap()
+        .to_vec();
+    let mut wasmv1_module = vec![1];
+    wasmv1_module.extend(
+        wasmer::wat2wasm(
+            br#"(module
+                (memory (export "memory") 32)
+                (func (export "__alloc") (param i32) (result i32)
+                    (i32.store (i32.const 1020) (local.get 0))
+                    (i32.const 1024))
+                (func (export "echo") (param i32) (result i32)
+                    (i32.sub (local.get 0) (i32.const 4))))"#,
+        )
+        .unwrap()
+        .iter(),
+    );
+    let gas_costs = |return_byte_cost: u64| GasCosts {
+        return_byte_cost,
+        ..GasCosts::default()
+    };
+    let config = RuntimeConfig {
+        max_return_size: 1_000,
+        ..RuntimeConfig::default()
+    };
+    let run = |module: &[u8], param: &[u8], gas_costs: GasCosts| {
+        let runtime_module =
+            RuntimeModule::new(module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+        run_function(
+            &interface,
+            runtime_module,
+            "echo",
+            param,
+            1_000_000,
+            gas_costs,
+            &config,
+            ExecutionOptions::default(),
+        )
+    };
+
+    let param: Vec<u8> = (0..1_000).map(|i| (i % 251) as u8).collect();
+    let mut too_large = param.clone();
+    too_large.push(0);
+    for module in [&as_module, &wasmv1_module] {
+        let free = run(module, &param, gas_costs(0)).unwrap();
+        assert_eq!(free.ret, param);
+        // the returned bytes are charged
+        let charged = run(module, &param, gas_costs(3)).unwrap();
+        assert_eq!(charged.ret, param);
+        assert_eq!(free.remaining_gas - charged.remaining_gas, 3 * 1_000);
+
+        // the parameter is not limited by the maximum of the returned value
+        let err = run(module, &too_large, gas_costs(3)).unwrap_err();
+        assert_eq!(err.code(), VMErrorCode::ReturnTooLarge, "{}", err);
+        assert!(
+            matches!(
+                err,
+                VMError::ReturnTooLarge {
+                    len: 1_001,
+                    max: 1_000,
+                    ..
+                }
+            ),
+            "{}",
+            err
+        );
+        assert!(
+            err.to_string()
+                .contains("Return value of 1001 bytes exceeds the maximum of 1000 bytes"),
+            "{}",
+            err
+        );
+
+        // the bytes are charged before they are copied
+        let err = run(module, &param, gas_costs(1_000_000)).unwrap_err();
+        assert!(matches!(err, VMError::OutOfGas { .. }), "{}", err);
+    }
+}
+
+#[test]
+#[serial]
+/// Test that the value returned by a nested call is limited like the one of
+/// the top-level execution, failing the caller
+fn test_nested_return_size() {
+    use crate::VMErrorCode;
+
+    let interface = TestInterface;
+    let callee = wasmer::wat2wasm(
+        br#"(module
+            (memory (export "memory") 1)
+            (func (export "returns") (result i32)
+                (i32.store (i32.const 1020) (i32.const 101))
+                (i32.const 1024)))"#,
+    )
+    .unwrap();
+    CREATED_MODULES
+        .lock()
+        .unwrap()
+        .insert("large_return".to_string(), callee.to_vec());
+    let caller = wasmer::wat2wasm(
+        format!(
+            r#"(module
+                (import "massa" "assembly_script_call"
+                    (func $call (param i32 i32 i32 i64) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 12) "\00\00\00\00")
+                {}
+                {}
+                (func (export "__new") (param i32 i32) (result i32)
+                    (i32.store (i32.const 1020) (local.get 0))
+                    (i32.const 1024))
+                (func (export "call") (result i32)
+                    (call $call (i32.const 32) (i32.const 96) (i32.const 16) (i64.const 0))))"#,
+            wat_as_string(32, "large_return"),
+            wat_as_string(96, "returns"),
+        )
+        .as_bytes(),
+    )
+    .unwrap();
+    let run = |max_return_size: usize| {
+        let gas_costs = GasCosts::default();
+        let config = RuntimeConfig {
+            max_return_size,
+            ..RuntimeConfig::default()
+        };
+        let runtime_module =
+            RuntimeModule::new(&caller, gas_costs.clone(), &config, Compiler::SP).unwrap();
+        run_function(
+            &interface,
+            runtime_module,
+            "call",
+            b"",
+            1_000_000,
+            gas_costs,
+            &config,
+            ExecutionOptions::default(),
+        )
+    };
+
+    // the caller returns the value of the callee
+    assert_eq!(run(101).unwrap().ret, [0; 101]);
+    let err = run(100).unwrap_err();
+    assert_eq!(err.code(), VMErrorCode::ReturnTooLarge, "{}", err);
+    assert!(
+        matches!(
+            err,
+            VMError::ReturnTooLarge {
+                len: 101,
+                max: 100,
+                ..
+            }
+        ),
+        "{}",
+        err
+    );
+}
+
+#[test]
+/// Test that the gas charged by an ABI before it fails is reported as used
+/// by the failed execution
+fn test_failed_abi_gas() {
+    let interface = TestInterface;
+    let module = wasmer::wat2wasm(
+        format!(
+            r#"(module
+                (import "massa" "assembly_script_get_balance_for"
+                    (func $balance (param i32) (result i64)))
+                (memory (export "memory") 1)
+                {}
+                (func (export "balance")
+                    (drop (call $balance (i32.const 32)))))"#,
+            wat_as_string(32, &"X".repeat(100)),
+        )
+        .as_bytes(),
+    )
+    .unwrap();
+    let limit = 1_000_000;
+    let gas_used = |string_code_unit_cost: u64| {
+        // the string is charged before the address is refused
+        let gas_costs = GasCosts {
+            string_code_unit_cost,
+            ..GasCosts::default()
+        };
+        let config = RuntimeConfig {
+            address_validator: AddressValidator {
+                prefixes: vec!["AU".to_string()],
+                ..AddressValidator::default()
+            },
+            ..RuntimeConfig::default()
+        };
+        let runtime_module =
+            RuntimeModule::new(&module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+        let err = run_function(
+            &interface,
+            runtime_module,
+            "balance",
+            b"",
+            limit,
+            gas_costs,
+            &config,
+            ExecutionOptions::default(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, VMError::ExecutionError { .. }), "{}", err);
+        assert!(err.to_string().contains("Invalid address"), "{}", err);
+        err.gas_used(limit)
+    };
+
+    let free = gas_used(0);
+    assert!(free < limit);
+    assert_eq!(gas_used(5) - free, 5 * 100);
+}
+
+#[test]
+#[serial]
+fn test_interface_version() {
+    use crate::tests::REPORTED_INTERFACE_VERSION;
+    use crate::{ReadOnlyCache, VMErrorCode, INTERFACE_VERSION, MIN_INTERFACE_VERSION};
+
+    let interface = TestInterface;
+    let module = wasmer::wat2wasm(ECHO_PARAM_WAT.as_bytes()).unwrap();
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    let run = |version: u32| {
+        REPORTED_INTERFACE_VERSION.with(|reported| reported.set(version));
+        let runtime_module =
+            RuntimeModule::new(&module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+        let result = run_function(
+            &interface,
+            runtime_module,
+            "echo",
+            b"param",
+            1_000_000,
+            gas_costs.clone(),
+            &config,
+            ExecutionOptions::default(),
+        );
+        REPORTED_INTERFACE_VERSION.with(|reported| reported.set(INTERFACE_VERSION));
+        result
+    };
+
+    assert_eq!(run(INTERFACE_VERSION).unwrap().ret, b"param");
+    assert_eq!(run(MIN_INTERFACE_VERSION).unwrap().ret, b"param");
+    // an interface predating the handshake, then one newer than the runtime
+    for version in [0, INTERFACE_VERSION + 1] {
+        let err = run(version).unwrap_err();
+        assert_eq!(err.code(), VMErrorCode::IncompatibleInterface, "{}", err);
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "VM instance error: interface version {} is not supported, the runtime supports {}..={}",
+                version, MIN_INTERFACE_VERSION, INTERFACE_VERSION
+            )
+        );
+    }
+
+    // refused before the bytecode, invalid here, is compiled
+    struct NoCache;
+    impl ReadOnlyCache for NoCache {
+        fn get(&self, _key: &crate::ReadOnlyCacheKey) -> Option<crate::CachedExecution> {
+            None
+        }
+        fn insert(&self, _key: crate::ReadOnlyCacheKey, _execution: crate::CachedExecution) {}
+    }
+    REPORTED_INTERFACE_VERSION.with(|reported| reported.set(0));
+    let err = crate::run_read_only_cached(
+        &interface,
+        &NoCache,
+        "AS12",
+        b"not wasm",
+        Compiler::SP,
+        "echo",
+        b"",
+        b"v1",
+        1_000_000,
+        gas_costs.clone(),
+        None,
+    )
+    .unwrap_err();
+    REPORTED_INTERFACE_VERSION.with(|reported| reported.set(INTERFACE_VERSION));
+    assert_eq!(err.code(), VMErrorCode::IncompatibleInterface, "{}", err);
+}
+
+#[test]
+#[serial]
+fn test_execution_session() {
+    use crate::ExecutionSession;
+
+    let interface = TestInterface;
+    let module = wasmer::wat2wasm(
+        br#"(module
+            (memory (export "memory") 1)
+            (global $calls (mut i32) (i32.const 0))
+            (func $spin (param $n i32)
+                (local $i i32)
+                (loop $next
+                    (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                    (br_if $next (i32.lt_u (local.get $i) (local.get $n))))
+                (global.set $calls (i32.add (global.get $calls) (i32.const 1))))
+            (func (export "one") (call $spin (i32.const 10)))
+            (func (export "two") (call $spin (i32.const 100)))
+            (func (export "three") (call $spin (i32.const 1000)))
+            (func (export "check_calls")
+                (if (i32.ne (global.get $calls) (i32.const 3)) (then unreachable))))"#,
+    )
+    .unwrap();
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    let limit = 1_000_000;
+    let rt_module =
+        || RuntimeModule::new(&module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+    let functions = ["one", "two", "three"];
+    // gas used by each function run on its own, its instantiation included
+    let used_alone: Vec<u64> = functions
+        .iter()
+        .map(|function| {
+            let response = run_function(
+                &interface,
+                rt_module(),
+                function,
+                b"",
+                limit,
+                gas_costs.clone(),
+                &config,
+                ExecutionOptions::default(),
+            )
+            .unwrap();
+            limit - response.remaining_gas
+        })
+        .collect();
+
+    let mut session = ExecutionSession::new(
+        &interface,
+        rt_module(),
+        limit,
+        gas_costs.clone(),
+        &config,
+        ExecutionMode::ReadWrite,
+    )
+    .unwrap();
+    let init_gas_cost = session.init_gas_cost();
+    let mut remaining_gas = session.remaining_gas();
+    assert_eq!(remaining_gas, limit - init_gas_cost);
+    for (function, used_alone) in functions.iter().zip(used_alone) {
+        let response = session.run_function(function, b"").unwrap();
+        assert!(response.remaining_gas < remaining_gas, "{}", function);
+        // the instantiation is only charged once
+        assert_eq!(
+            remaining_gas - response.remaining_gas,
+            used_alone - init_gas_cost
+        );
+        assert_eq!(response.init_gas_cost, init_gas_cost);
+        remaining_gas = response.remaining_gas;
+    }
+    assert_eq!(session.remaining_gas(), remaining_gas);
+    // the calls share the instance
+    session.run_function("check_calls", b"").unwrap();
+
+    // a session out of gas runs again once given some
+    session.set_remaining_gas(gas_costs.launch_cost + 100);
+    let err = session.run_function("three", b"").unwrap_err();
+    assert!(matches!(err, VMError::OutOfGas { .. }), "{}", err);
+    assert_eq!(session.remaining_gas(), 0);
+    session.set_remaining_gas(remaining_gas);
+    assert!(session.run_function("one", b"").unwrap().remaining_gas < remaining_gas);
+
+    let mut wasmv1_module = vec![1];
+    wasmv1_module.extend(module.iter());
+    let wasmv1_module =
+        RuntimeModule::new(&wasmv1_module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+    let err = ExecutionSession::new(
+        &interface,
+        wasmv1_module,
+        limit,
+        gas_costs,
+        &config,
+        ExecutionMode::ReadWrite,
+    )
+    .err()
+    .unwrap();
+    assert_eq!(
+        err.to_string(),
+        "VM instance error: Execution sessions only run AssemblyScript modules"
+    );
+}
+
+#[test]
+#[ignore]
+/// Compare the injection of a 1 MB parameter with the `BufferPtr` allocation
+/// it replaced, run with `--ignored --nocapture`
+fn bench_param_injection() {
+    use as_ffi_bindings::{BufferPtr, Read, Write};
+    use std::time::Instant;
+
+    const ROUNDS: u32 = 50;
+    let interface: Box<dyn Interface> = Box::new(TestInterface);
+    let bytecode = wasmer::wat2wasm(ECHO_PARAM_WAT.as_bytes()).unwrap();
+    let module = ASModule::new(
+        &bytecode,
+        100_000,
+        GasCosts::default(),
+        &RuntimeConfig::default(),
+        Compiler::SP,
+    )
+    .unwrap();
+    let mut store = Store::new(module._engine);
+    let mut context = ASContext::new(
+        &*interface,
+        module.binary_module,
+        GasCosts::default(),
+        Default::default(),
+    );
+    let _ = context.create_vm_instance_and_init_env(&mut store).unwrap();
+    let param: Vec<u8> = (0..1_000_000).map(|i| (i % 251) as u8).collect();
+
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        let ptr =
+            *BufferPtr::alloc(&param.to_vec(), context.env.get_ffi_env(), &mut store).unwrap();
+        let memory = context.env.get_ffi_env().memory.as_ref().unwrap();
+        assert_eq!(ptr.read(memory, &store).unwrap(), param);
+    }
+    let before = start.elapsed() / ROUNDS;
+
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        let offset = context
+            .env
+            .write_buffer(&mut store, "echo", &param)
+            .unwrap();
+        assert_eq!(
+            context.env.read_buffer(&store, "echo", offset).unwrap(),
+            param
+        );
+    }
+    let after = start.elapsed() / ROUNDS;
+    println!(
+        "1 MB parameter round trip: {:?} with BufferPtr, {:?} with a single write",
+        before, after
+    );
+}
+
+#[test]
+#[ignore]
+/// Time 10k trivial ABI calls against the copies of the gas costs each of
+/// them made before they were shared, run with `--ignored --nocapture`
+fn bench_trivial_abi_calls() {
+    use std::time::Instant;
+
+    const CALLS: u32 = 10_000;
+    let interface = TestInterface;
+    let module = wasmer::wat2wasm(
+        format!(
+            r#"(module
+                (import "massa" "assembly_script_get_call_coins"
+                    (func $get_call_coins (result i64)))
+                (import "massa" "assembly_script_get_remaining_gas"
+                    (func $get_remaining_gas (result i64)))
+                (memory (export "memory") 1)
+                (func (export "__new") (param i32 i32) (result i32)
+                    (i32.const 1024))
+                (func (export "main") (local $i i32)
+                    (loop $calls
+                        (drop (call $get_call_coins))
+                        (drop (call $get_remaining_gas))
+                        (local.set $i (i32.add (local.get $i) (i32.const 2)))
+                        (br_if $calls (i32.lt_u (local.get $i) (i32.const {}))))))"#,
+            CALLS
+        )
+        .as_bytes(),
+    )
+    .unwrap();
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    let runtime_module =
+        RuntimeModule::new(&module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+
+    let start = Instant::now();
+    run_main(
+        &interface,
+        runtime_module,
+        1_000_000_000,
+        gas_costs.clone(),
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap();
+    let calls = start.elapsed();
+
+    // an environment copy and a gas costs copy per call
+    let start = Instant::now();
+    for _ in 0..2 * CALLS {
+        std::hint::black_box(gas_costs.clone());
+    }
+    let copies = start.elapsed();
+    println!(
+        "{} trivial ABI calls: {:?}, the gas costs copies they no longer make: {:?}",
+        CALLS, calls, copies
+    );
+}
+
+#[test]
+#[serial]
+fn test_set_bytecode_upgrade() {
+    use std::sync::Arc;
+
+    let interface = TestInterface;
+    let version = |version: u8| {
+        wasmer::wat2wasm(
+            format!(
+                r#"(module
+                    (memory (export "memory") 1)
+                    (data (i32.const 12) "\01\00\00\00\{:02x}")
+                    (func (export "version") (result i32) (i32.const 16)))"#,
+                version
+            )
+            .as_bytes(),
+        )
+        .unwrap()
+        .to_vec()
+    };
+    // calls `version` on the contract before and after upgrading it to
+    // `bytecode`, returns what the upgraded contract returned
+    let caller = |bytecode: &[u8]| {
+        wasmer::wat2wasm(
+            format!(
+                r#"(module
+                    (import "massa" "assembly_script_call"
+                        (func $call (param i32 i32 i32 i64) (result i32)))
+                    (import "massa" "assembly_script_set_bytecode_for"
+                        (func $set_bytecode_for (param i32 i32)))
+                    (memory (export "memory") 1)
+                    (data (i32.const 12) "\00\00\00\00")
+                    {}
+                    {}
+                    (data (i32.const 2044) "{}{}")
+                    (func (export "__new") (param i32 i32) (result i32)
+                        (i32.store (i32.const 1020) (local.get 0))
+                        (i32.const 1024))
+                    (func (export "upgrade") (result i32)
+                        (if (i32.ne
+                                (i32.load8_u (call $call (i32.const 32) (i32.const 96) (i32.const 16) (i64.const 0)))
+                                (i32.const 1))
+                            (then unreachable))
+                        (call $set_bytecode_for (i32.const 32) (i32.const 2048))
+                        (call $call (i32.const 32) (i32.const 96) (i32.const 16) (i64.const 0))))"#,
+                wat_as_string(32, "upgradable"),
+                wat_as_string(96, "version"),
+                wat_bytes(&(bytecode.len() as u32).to_le_bytes()),
+                wat_bytes(bytecode)
+            )
+            .as_bytes(),
+        )
+        .unwrap()
+    };
+    let cache = Arc::new(ModuleCache::new(usize::MAX));
+    MODULE_CACHE.with(|module_cache| *module_cache.borrow_mut() = Some(cache.clone()));
+    let gas_costs = GasCosts::default();
+    let mut config = RuntimeConfig::default();
+    config.float_mode = FloatMode::Reject;
+    let run = |bytecode: &[u8]| {
+        CREATED_MODULES
+            .lock()
+            .unwrap()
+            .insert("upgradable".to_string(), version(1));
+        let runtime_module =
+            RuntimeModule::new(&caller(bytecode), gas_costs.clone(), &config, Compiler::SP)
+                .unwrap();
+        run_function(
+            &interface,
+            runtime_module,
+            "upgrade",
+            b"",
+            10_000_000,
+            gas_costs.clone(),
+            &config,
+            ExecutionOptions::default(),
+        )
+    };
+
+    // the call following the upgrade runs the new bytecode, whose module is
+    // the only one left in the cache
+    assert_eq!(run(&version(2)).unwrap().ret, [2]);
+    assert_eq!(cache.len(), 1);
+    assert!(cache
+        .get(
+            &ModuleCache::hash_bytecode(&version(1)),
+            &GasCosts::default(),
+            Compiler::SP
+        )
+        .is_none());
+    assert!(cache
+        .get(
+            &ModuleCache::hash_bytecode(&version(2)),
+            &GasCosts::default(),
+            Compiler::SP
+        )
+        .is_some());
+
+    // an upgrade is validated like a deployment
+    let floats = wasmer::wat2wasm(
+        br#"(module
+            (func (export "version") (result f32) (f32.const 2)))"#,
+    )
+    .unwrap();
+    let err = run(&floats).unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("Forbidden WebAssembly feature: floats"),
+        "{}",
+        err
+    );
+    assert_eq!(
+        CREATED_MODULES.lock().unwrap().remove("upgradable"),
+        Some(version(1))
+    );
+    MODULE_CACHE.with(|module_cache| *module_cache.borrow_mut() = None);
+}
+
+#[test]
+#[serial]
+fn test_random_seed() {
+    use crate::random::expand;
+    use crate::tests::{MOCK_RANDOM_SEED, RANDOM_SEED_QUERIES};
+
+    let interface = TestInterface;
+    let imports = r#"
+        (import "massa" "assembly_script_get_random_seed" (func $seed (result i32)))
+        (import "massa" "assembly_script_random_bytes" (func $random_bytes (param i32) (result i32)))
+        (import "massa" "assembly_script_call" (func $call (param i32 i32 i32 i64) (result i32)))
+        (memory (export "memory") 1)
+        (func (export "__new") (param i32 i32) (result i32)
+            (i32.store (i32.const 1020) (local.get 0))
+            (i32.const 1024))"#;
+    let callee = wasmer::wat2wasm(
+        format!(
+            r#"(module {}
+                (func (export "seed") (result i32) (call $seed))
+                (func (export "bytes") (result i32) (call $random_bytes (i32.const 16))))"#,
+            imports
+        )
+        .as_bytes(),
+    )
+    .unwrap();
+    CREATED_MODULES
+        .lock()
+        .unwrap()
+        .insert("random_callee".to_string(), callee.to_vec());
+    // each function draws from the randomness before calling the callee
+    let caller = wasmer::wat2wasm(
+        format!(
+            r#"(module {}
+                (data (i32.const 12) "\00\00\00\00")
+                {}
+                {}
+                {}
+                (func (export "seed") (result i32)
+                    (drop (call $seed))
+                    (call $call (i32.const 32) (i32.const 96) (i32.const 16) (i64.const 0)))
+                (func (export "bytes") (result i32)
+                    (drop (call $random_bytes (i32.const 16)))
+                    (call $call (i32.const 32) (i32.const 128) (i32.const 16) (i64.const 0)))
+                (func (export "own_bytes") (result i32)
+                    (call $random_bytes (i32.const 16)))
+                (func (export "too_many") (result i32)
+                    (call $random_bytes (i32.const 1025))))"#,
+            imports,
+            wat_as_string(32, "random_callee"),
+            wat_as_string(96, "seed"),
+            wat_as_string(128, "bytes"),
+        )
+        .as_bytes(),
+    )
+    .unwrap();
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    let run = |function: &str| {
+        RANDOM_SEED_QUERIES.with(|queries| queries.set(0));
+        let runtime_module =
+            RuntimeModule::new(&caller, gas_costs.clone(), &config, Compiler::SP).unwrap();
+        let result = run_function(
+            &interface,
+            runtime_module,
+            function,
+            b"",
+            10_000_000,
+            gas_costs.clone(),
+            &config,
+            ExecutionOptions::default(),
+        );
+        (result, RANDOM_SEED_QUERIES.with(|queries| queries.get()))
+    };
+
+    // the nested call sees the seed asked by its caller
+    let (result, queries) = run("seed");
+    assert_eq!(result.unwrap().ret, MOCK_RANDOM_SEED);
+    assert_eq!(queries, 1);
+
+    // the expansions are counted over the call tree
+    let (result, queries) = run("own_bytes");
+    assert_eq!(result.unwrap().ret, expand(&MOCK_RANDOM_SEED, 0, 16));
+    assert_eq!(queries, 1);
+    let (result, queries) = run("bytes");
+    let nested = result.unwrap().ret;
+    assert_eq!(nested, expand(&MOCK_RANDOM_SEED, 1, 16));
+    assert_ne!(nested, expand(&MOCK_RANDOM_SEED, 0, 16));
+    assert_eq!(queries, 1);
+
+    let err = run("too_many").0.unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("Random bytes request of 1025 bytes exceeds the maximum of 1024 bytes"),
+        "{}",
+        err
+    );
+    CREATED_MODULES.lock().unwrap().remove("random_callee");
+}
+
+#[test]
+#[serial]
+fn test_current_slot_per_call_tree() {
+    use crate::tests::SLOT_QUERIES;
+
+    let interface = TestInterface;
+    let imports = r#"
+        (import "massa" "assembly_script_get_current_slot" (func $slot (result i32)))
+        (import "massa" "assembly_script_get_current_period" (func $period (result i64)))
+        (import "massa" "assembly_script_get_current_thread" (func $thread (result i32)))
+        (import "massa" "assembly_script_call" (func $call (param i32 i32 i32 i64) (result i32)))
+        (memory (export "memory") 1)
+        (func (export "__new") (param i32 i32) (result i32)
+            (i32.store (i32.const 1020) (local.get 0))
+            (i32.const 1024))"#;
+    let callee = wasmer::wat2wasm(
+        format!(
+            r#"(module {}
+                (func (export "slot") (result i32) (call $slot)))"#,
+            imports
+        )
+        .as_bytes(),
+    )
+    .unwrap();
+    CREATED_MODULES
+        .lock()
+        .unwrap()
+        .insert("slot_callee".to_string(), callee.to_vec());
+    let caller = wasmer::wat2wasm(
+        format!(
+            r#"(module {}
+                (data (i32.const 12) "\00\00\00\00")
+                {}
+                {}
+                ;; every getter, then the slot again
+                (func (export "repeated") (result i32)
+                    (drop (call $slot))
+                    (drop (call $period))
+                    (drop (call $thread))
+                    (call $slot))
+                ;; the slot of the caller, then the one of the callee
+                (func (export "nested") (result i32)
+                    (drop (call $slot))
+                    (call $call (i32.const 32) (i32.const 96) (i32.const 16) (i64.const 0))))"#,
+            imports,
+            wat_as_string(32, "slot_callee"),
+            wat_as_string(96, "slot"),
+        )
+        .as_bytes(),
+    )
+    .unwrap();
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    let run = |function: &str| {
+        SLOT_QUERIES.with(|queries| queries.set(0));
+        let runtime_module =
+            RuntimeModule::new(&caller, gas_costs.clone(), &config, Compiler::SP).unwrap();
+        let response = run_function(
+            &interface,
+            runtime_module,
+            function,
+            b"",
+            10_000_000,
+            gas_costs.clone(),
+            &config,
+            ExecutionOptions::default(),
+        )
+        .unwrap();
+        (response.ret, SLOT_QUERIES.with(|queries| queries.get()))
+    };
+
+    // the mock executes in slot 0, asked once for the whole call tree
+    assert_eq!(run("repeated"), (vec![0; 9], 1));
+    assert_eq!(run("nested"), (vec![0; 9], 1));
+    // each top-level execution asks again
+    assert_eq!(run("repeated").1, 1);
+    CREATED_MODULES.lock().unwrap().remove("slot_callee");
+}
+
+#[test]
+#[serial]
+fn test_callee_compilation_charged() {
+    use std::sync::Arc;
+
+    let interface = TestInterface;
+    let callee = wasmer::wat2wasm(
+        format!(
+            r#"(module
+                (memory (export "memory") 1)
+                (data (i32.const 12) "\00\00\00\00")
+                (data (i32.const 1024) "{}")
+                (func (export "run") (result i32) (i32.const 16)))"#,
+            wat_bytes(&[0xab; 50_000])
+        )
+        .as_bytes(),
+    )
+    .unwrap();
+    CREATED_MODULES
+        .lock()
+        .unwrap()
+        .insert("large_callee".to_string(), callee.to_vec());
+    let caller = wasmer::wat2wasm(
+        format!(
+            r#"(module
+                (import "massa" "assembly_script_call"
+                    (func $call (param i32 i32 i32 i64) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 12) "\00\00\00\00")
+                {}
+                {}
+                (func (export "__new") (param i32 i32) (result i32)
+                    (i32.store (i32.const 1020) (local.get 0))
+                    (i32.const 1024))
+                (func (export "call")
+                    (drop (call $call (i32.const 32) (i32.const 96) (i32.const 16) (i64.const 0)))))"#,
+            wat_as_string(32, "large_callee"),
+            wat_as_string(96, "run"),
+        )
+        .as_bytes(),
+    )
+    .unwrap();
+    let mut gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    gas_costs.compilation_cost_per_byte = 100;
+    let compilation_cost = callee.len() as u64 * 100;
+    let run = |limit: u64| {
+        REQUESTED_COMPILERS.with(|compilers| compilers.borrow_mut().clear());
+        let runtime_module =
+            RuntimeModule::new(&caller, gas_costs.clone(), &config, Compiler::SP).unwrap();
+        let result = run_function(
+            &interface,
+            runtime_module,
+            "call",
+            b"",
+            limit,
+            gas_costs.clone(),
+            &config,
+            ExecutionOptions::default(),
+        );
+        let compiled = REQUESTED_COMPILERS.with(|compilers| !compilers.borrow().is_empty());
+        (result, compiled)
+    };
+
+    // the caller cannot pay for the compilation, which is not attempted
+    let (result, compiled) = run(1_000_000);
+    let err = result.unwrap_err();
+    assert!(matches!(err, VMError::OutOfGas { .. }), "{}", err);
+    assert!(err
+        .to_string()
+        .contains("limit reached at: compilation of large_callee"));
+    assert!(!compiled);
+
+    // paid for by the caller when it can
+    let limit = compilation_cost + 1_000_000;
+    let (result, compiled) = run(limit);
+    assert!(compiled);
+    let uncached_gas = limit - result.unwrap().remaining_gas;
+    assert!(uncached_gas > compilation_cost);
+
+    // free on a module cache hit
+    let cache = Arc::new(ModuleCache::new(usize::MAX));
+    cache
+        .get_or_compile(
+            &callee,
+            GasCosts::default(),
+            &RuntimeConfig::default(),
+            Compiler::SP,
+        )
+        .unwrap();
+    MODULE_CACHE.with(|module_cache| *module_cache.borrow_mut() = Some(cache.clone()));
+    let (result, _) = run(1_000_000);
+    MODULE_CACHE.with(|module_cache| *module_cache.borrow_mut() = None);
+    assert!(1_000_000 - result.unwrap().remaining_gas <= uncached_gas - compilation_cost);
+    assert_eq!(cache.compilation_count(), 1);
+    CREATED_MODULES.lock().unwrap().remove("large_callee");
+}
+
+/// Spans and events recorded by `test_tracing_spans`
+#[cfg(feature = "tracing")]
+#[derive(Default)]
+struct RecordedTrace {
+    /// Name, parent index and fields of each span, in creation order
+    spans: Vec<(&'static str, Option<usize>, Vec<(String, String)>)>,
+    /// Message, enclosing span index and fields of each event
+    events: Vec<(String, Option<usize>, Vec<(String, String)>)>,
+    /// Indexes of the entered spans, the innermost last
+    entered: Vec<usize>,
+}
+
+#[cfg(feature = "tracing")]
+#[derive(Clone, Default)]
+struct RecordingSubscriber(std::sync::Arc<std::sync::Mutex<RecordedTrace>>);
+
+#[cfg(feature = "tracing")]
+struct FieldRecorder<'a>(&'a mut Vec<(String, String)>);
+
+#[cfg(feature = "tracing")]
+impl tracing::field::Visit for FieldRecorder<'_> {
+    fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+        self.0
+            .push((field.name().to_string(), format!("{:?}", value)));
+    }
+}
+
+#[cfg(feature = "tracing")]
+impl tracing::Subscriber for RecordingSubscriber {
+    fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, span: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+        let mut trace = self.0.lock().unwrap();
+        let mut fields = Vec::new();
+        span.record(&mut FieldRecorder(&mut fields));
+        let parent = trace.entered.last().copied();
+        trace.spans.push((span.metadata().name(), parent, fields));
+        tracing::span::Id::from_u64(trace.spans.len() as u64)
+    }
+
+    fn record(&self, _: &tracing::span::Id, _: &tracing::span::Record<'_>) {}
+
+    fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+
+    fn event(&self, event: &tracing::Event<'_>) {
+        let mut trace = self.0.lock().unwrap();
+        let mut fields = Vec::new();
+        event.record(&mut FieldRecorder(&mut fields));
+        let message = fields
+            .iter()
+            .position(|(name, _)| name == "message")
+            .map(|index| fields.remove(index).1)
+            .unwrap_or_default();
+        let span = trace.entered.last().copied();
+        trace.events.push((message, span, fields));
+    }
+
+    fn enter(&self, span: &tracing::span::Id) {
+        let mut trace = self.0.lock().unwrap();
+        trace.entered.push(span.into_u64() as usize - 1);
+    }
+
+    fn exit(&self, _: &tracing::span::Id) {
+        self.0.lock().unwrap().entered.pop();
+    }
+}
+
+#[test]
+#[serial]
+#[cfg(feature = "tracing")]
+fn test_tracing_spans() {
+    let interface = TestInterface;
+    let callee = wasmer::wat2wasm(
+        format!(
+            r#"(module
+                (import "massa" "assembly_script_generate_event" (func $event (param i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 12) "\00\00\00\00")
+                {}
+                (func (export "run") (result i32)
+                    (call $event (i32.const 32))
+                    (i32.const 16)))"#,
+            wat_as_string(32, "secret"),
+        )
+        .as_bytes(),
+    )
+    .unwrap();
+    CREATED_MODULES
+        .lock()
+        .unwrap()
+        .insert("traced_callee".to_string(), callee.to_vec());
+    let caller = wasmer::wat2wasm(
+        format!(
+            r#"(module
+                (import "massa" "assembly_script_call"
+                    (func $call (param i32 i32 i32 i64) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 12) "\00\00\00\00")
+                {}
+                {}
+                (func (export "__new") (param i32 i32) (result i32)
+                    (i32.store (i32.const 1020) (local.get 0))
+                    (i32.const 1024))
+                (func (export "call")
+                    (drop (call $call (i32.const 32) (i32.const 96) (i32.const 16) (i64.const 0)))))"#,
+            wat_as_string(32, "traced_callee"),
+            wat_as_string(96, "run"),
+        )
+        .as_bytes(),
+    )
+    .unwrap();
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    let subscriber = RecordingSubscriber::default();
+    tracing::subscriber::with_default(subscriber.clone(), || {
+        let runtime_module =
+            RuntimeModule::new(&caller, gas_costs.clone(), &config, Compiler::SP).unwrap();
+        run_function(
+            &interface,
+            runtime_module,
+            "call",
+            b"",
+            100_000_000,
+            gas_costs.clone(),
+            &config,
+            ExecutionOptions::default(),
+        )
+        .unwrap();
+    });
+    CREATED_MODULES.lock().unwrap().remove("traced_callee");
+
+    let trace = subscriber.0.lock().unwrap();
+    let names: Vec<_> = trace.spans.iter().map(|(name, ..)| *name).collect();
+    assert_eq!(names, ["execution", "call"]);
+    assert_eq!(trace.spans[0].1, None);
+    assert_eq!(trace.spans[1].1, Some(0));
+    let field = |span: usize, name: &str| {
+        trace.spans[span]
+            .2
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value.clone())
+    };
+    assert_eq!(field(0, "function").as_deref(), Some("\"call\""));
+    assert_eq!(field(1, "address").as_deref(), Some("traced_callee"));
+    assert_eq!(field(1, "function").as_deref(), Some("\"run\""));
+    assert_eq!(field(1, "depth").as_deref(), Some("1"));
+
+    let event = |message: &str, abi: &str| {
+        let abi = ("abi".to_string(), format!("{:?}", abi));
+        trace
+            .events
+            .iter()
+            .find(|(event, _, fields)| event == message && fields.contains(&abi))
+            .unwrap_or_else(|| panic!("no {} event for {}", message, abi.1))
+    };
+    // the callee is compiled by its caller, then runs its ABIs in its span
+    let compilations: Vec<_> = trace
+        .events
+        .iter()
+        .filter(|(message, ..)| message == "compilation")
+        .map(|(_, span, _)| *span)
+        .collect();
+    assert_eq!(compilations, [None, Some(0)]);
+    assert_eq!(event("abi call", "assembly_script_call").1, Some(0));
+    let generate_event = event("abi call", "assembly_script_generate_event");
+    assert_eq!(generate_event.1, Some(1));
+    let argument = event("abi argument", "assembly_script_generate_event");
+    assert_eq!(argument.1, Some(1));
+    assert!(argument.2.contains(&("len".to_string(), "6".to_string())));
+    assert_eq!(
+        trace
+            .events
+            .iter()
+            .filter(|(message, ..)| message == "instantiation")
+            .count(),
+        2
+    );
+
+    // only the lengths of the data are recorded
+    let fields = trace
+        .spans
+        .iter()
+        .map(|(_, _, fields)| fields)
+        .chain(trace.events.iter().map(|(_, _, fields)| fields));
+    for (_, value) in fields.flatten() {
+        assert!(!value.contains("secret"), "{}", value);
+    }
+}
+
+#[test]
+#[serial]
+fn test_spent_coins() {
+    let interface = TestInterface;
+    // `spend` pays 100 coins to `pay`, then tries to pay 50 coins to `fail`
+    // and 7 coins to `pay`, and traps if the spent coins do not add up
+    let module = wasmer::wat2wasm(
+        format!(
+            r#"(module
+                (import "massa" "assembly_script_call"
+                    (func $call (param i32 i32 i32 i64) (result i32)))
+                (import "massa" "assembly_script_try_call"
+                    (func $try_call (param i32 i32 i32 i64) (result i32)))
+                (import "massa" "assembly_script_get_spent_coins"
+                    (func $spent (result i64)))
+                (memory (export "memory") 1)
+                (data (i32.const 12) "\00\00\00\00")
+                {}
+                {}
+                {}
+                (func (export "__new") (param i32 i32) (result i32)
+                    (i32.store (i32.const 1020) (local.get 0))
+                    (i32.const 1024))
+                (func $expect_spent (param i64)
+                    (if (i64.ne (call $spent) (local.get 0)) (then unreachable)))
+                (func (export "pay") (call $expect_spent (i64.const 0)))
+                (func (export "fail") unreachable)
+                (func (export "spend")
+                    (call $expect_spent (i64.const 0))
+                    (drop (call $call (i32.const 32) (i32.const 96) (i32.const 16) (i64.const 100)))
+                    (call $expect_spent (i64.const 100))
+                    (drop (call $try_call (i32.const 32) (i32.const 128) (i32.const 16) (i64.const 50)))
+                    (call $expect_spent (i64.const 100))
+                    (drop (call $try_call (i32.const 32) (i32.const 96) (i32.const 16) (i64.const 7)))
+                    (call $expect_spent (i64.const 107))))"#,
+            wat_as_string(32, "coins_payer"),
+            wat_as_string(96, "pay"),
+            wat_as_string(128, "fail"),
+        )
+        .as_bytes(),
+    )
+    .unwrap();
+    CREATED_MODULES
+        .lock()
+        .unwrap()
+        .insert("coins_payer".to_string(), module.clone());
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    let runtime_module =
+        RuntimeModule::new(&module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+    let result = run_function(
+        &interface,
+        runtime_module,
+        "spend",
+        b"",
+        100_000_000,
+        gas_costs,
+        &config,
+        ExecutionOptions::default(),
+    );
+    CREATED_MODULES.lock().unwrap().remove("coins_payer");
+    result.unwrap();
+}
+
+#[test]
+#[serial]
+fn test_host_data_limit() {
+    let interface = TestInterface;
+    let module = wasmer::wat2wasm(
+        format!(
+            r#"(module
+                (import "massa" "assembly_script_get_data" (func $get_data (param i32) (result i32)))
+                (import "massa" "assembly_script_call"
+                    (func $call (param i32 i32 i32 i64) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 12) "\00\00\00\00")
+                (data (i32.const 20) "\01\00\00\00k")
+                {}
+                {}
+                (func (export "__new") (param i32 i32) (result i32)
+                    (i32.store (i32.const 1020) (local.get 0))
+                    (i32.const 1024))
+                (func $read (export "read") (drop (call $get_data (i32.const 24))))
+                (func (export "read_twice") (call $read) (call $read))
+                (func (export "read_nested")
+                    (call $read)
+                    (drop (call $call (i32.const 32) (i32.const 96) (i32.const 16) (i64.const 0)))))"#,
+            wat_as_string(32, "host_data"),
+            wat_as_string(96, "read"),
+        )
+        .as_bytes(),
+    )
+    .unwrap();
+    CREATED_MODULES
+        .lock()
+        .unwrap()
+        .insert("host_data".to_string(), module.clone());
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig {
+        max_host_data_len: 1_000,
+        ..RuntimeConfig::default()
+    };
+    let run = |function: &str, value_len: usize| {
+        DATASTORE_VALUE.with(|value| *value.borrow_mut() = vec![42; value_len]);
+        let runtime_module =
+            RuntimeModule::new(&module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+        run_function(
+            &interface,
+            runtime_module,
+            function,
+            b"",
+            100_000_000,
+            gas_costs.clone(),
+            &config,
+            ExecutionOptions::default(),
+        )
+    };
+    let expect_exceeded = |result: Result<_, VMError>, len: usize, total: usize| {
+        let err = result.unwrap_err();
+        assert!(
+            err.to_string().contains(&format!(
+                "Host data limit exceeded: copying {} bytes would bring the data copied to the \
+                 guest to {} bytes, the maximum is 1000",
+                len, total
+            )),
+            "{}",
+            err
+        );
+    };
+
+    run("read", 1_000).unwrap();
+    expect_exceeded(run("read", 1_001), 1_001, 1_001);
+    // the limit is shared by the values of an execution
+    run("read_twice", 500).unwrap();
+    expect_exceeded(run("read_twice", 501), 501, 1_002);
+    // and by its nested calls
+    run("read_nested", 500).unwrap();
+    expect_exceeded(run("read_nested", 501), 501, 1_002);
+    DATASTORE_VALUE.with(|value| value.borrow_mut().clear());
+    CREATED_MODULES.lock().unwrap().remove("host_data");
+}
+
+#[cfg(feature = "replay")]
+#[test]
+#[serial]
+/// Test that an execution recorded with `RecordingInterface` replays to the
+/// same response, and that a different contract is stopped at its first
+/// call differing from the recording
+fn test_record_and_replay() {
+    use crate::{RecordingInterface, ReplayInterface};
+
+    // prints and returns "hi"
+    let callee = wasmer::wat2wasm(
+        br#"(module
+            (import "massa" "assembly_script_print" (func $print (param i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 12) "\04\00\00\00h\00i\00")
+            (func (export "run") (result i32)
+                (call $print (i32.const 16))
+                (i32.const 16)))"#,
+    )
+    .unwrap();
+    // reads its balance, writes `value`, reads it back, calls the callee
+    // and emits an event
+    let caller = |value: &[u8]| {
+        let caller = format!(
+            r#"(module
+                (import "massa" "assembly_script_call"
+                    (func $call (param i32 i32 i32 i64) (result i32)))
+                (import "massa" "assembly_script_set_data" (func $set_data (param i32 i32)))
+                (import "massa" "assembly_script_get_data" (func $get_data (param i32) (result i32)))
+                (import "massa" "assembly_script_has_data" (func $has_data (param i32) (result i32)))
+                (import "massa" "assembly_script_get_balance" (func $balance (result i64)))
+                (import "massa" "assembly_script_generate_event" (func $event (param i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 12) "\01\00\00\00k")
+                (data (i32.const 28) "\06\00\00\00r\00u\00n\00")
+                (data (i32.const 44) "\00\00\00\00")
+                (data (i32.const 60) "\0c\00\00\00c\00a\00l\00l\00e\00e\00")
+                (data (i32.const 92) "{}{}")
+                (func (export "__new") (param i32 i32) (result i32)
+                    (i32.store (i32.const 1020) (local.get 0))
+                    (i32.const 1024))
+                (func (export "main")
+                    (drop (call $balance))
+                    (call $set_data (i32.const 16) (i32.const 96))
+                    (drop (call $has_data (i32.const 16)))
+                    (drop (call $get_data (i32.const 16)))
+                    (drop (call $call (i32.const 64) (i32.const 32) (i32.const 48) (i64.const 0)))
+                    (call $event (i32.const 64))))"#,
+            wat_bytes(&(value.len() as u32).to_le_bytes()),
+            wat_bytes(value),
+        );
+        wasmer::wat2wasm(caller.as_bytes()).unwrap()
+    };
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    let run = |interface: &dyn Interface, value: &[u8]| {
+        let runtime_module =
+            RuntimeModule::new(&caller(value), gas_costs.clone(), &config, Compiler::SP).unwrap();
+        run_main(
+            interface,
+            runtime_module,
+            10_000_000,
+            gas_costs.clone(),
+            &config,
+            ExecutionOptions::default(),
+        )
+    };
+
+    CREATED_MODULES
+        .lock()
+        .unwrap()
+        .insert("callee".to_string(), callee);
+    let recording = RecordingInterface::new(TestInterface);
+    let recorded = run(&recording, b"value").unwrap();
+    CREATED_MODULES.lock().unwrap().remove("callee");
+    let log = recording.log();
+    assert!(recording.call_count() > 6);
+
+    // the callee comes from the recording
+    let replay = ReplayInterface::new(&log, gas_costs.clone(), &config).unwrap();
+    let replayed = run(&replay, b"value").unwrap();
+    assert_eq!(replayed.ret, recorded.ret);
+    assert_eq!(replayed.remaining_gas, recorded.remaining_gas);
+    assert_eq!(replayed.init_gas_cost, recorded.init_gas_cost);
+    assert_eq!(replay.remaining_calls(), 0);
+    assert!(replay.divergence().is_none());
+
+    let replay = ReplayInterface::new(&log, gas_costs.clone(), &config).unwrap();
+    let err = run(&replay, b"other").unwrap_err();
+    let divergence = replay.divergence().unwrap();
+    assert!(err.to_string().contains(&divergence), "{}", err);
+    assert!(
+        divergence.contains(
+            "diverges from the log: expected raw_set_data(0x6b, 0x76616c7565), \
+             got raw_set_data(0x6b, 0x6f74686572)"
+        ),
+        "{}",
+        divergence
+    );
+    assert!(replay.remaining_calls() > 0);
+}
+
+#[test]
+#[serial]
+/// Test that the storage refunds of a call tree are capped together, the
+/// ones of the nested calls reaching the `remaining_gas` of the response,
+/// and that reading the size of the deleted entries is charged
+fn test_storage_refund_nested() {
+    use massa_proto_rs::massa::abi::v1::DeleteDsEntryRequest;
+    use prost::Message;
+
+    let interface = TestInterface;
+    // deletes an entry in two nested calls, then in the top-level one
+    let module = wasmer::wat2wasm(
+        format!(
+            r#"(module
+                (import "massa" "assembly_script_call"
+                    (func $call (param i32 i32 i32 i64) (result i32)))
+                (import "massa" "assembly_script_delete_data" (func $delete (param i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 12) "\01\00\00\00k")
+                (data (i32.const 44) "\00\00\00\00")
+                {}
+                {}
+                (func (export "__new") (param i32 i32) (result i32)
+                    (i32.store (i32.const 1020) (local.get 0))
+                    (i32.const 1024))
+                (func (export "clear") (call $delete (i32.const 16)))
+                (func (export "main")
+                    (drop (call $call (i32.const 64) (i32.const 128) (i32.const 48) (i64.const 0)))
+                    (drop (call $call (i32.const 64) (i32.const 128) (i32.const 48) (i64.const 0)))
+                    (call $delete (i32.const 16))))"#,
+            wat_as_string(64, "refunder"),
+            wat_as_string(128, "clear"),
+        )
+        .as_bytes(),
+    )
+    .unwrap();
+    CREATED_MODULES
+        .lock()
+        .unwrap()
+        .insert("refunder".to_string(), module.clone());
+    DATASTORE_VALUE.with(|value| *value.borrow_mut() = vec![42; 1_000]);
+    let gas_limit = 100_000_000;
+    let run = |refund_per_byte: u64, max_percent: u64| {
+        let gas_costs = GasCosts {
+            storage_refund_per_byte: refund_per_byte,
+            max_storage_refund_percent: max_percent,
+            ..GasCosts::default()
+        };
+        let config = RuntimeConfig::default();
+        let runtime_module =
+            RuntimeModule::new(&module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+        run_main(
+            &interface,
+            runtime_module,
+            gas_limit,
+            gas_costs,
+            &config,
+            ExecutionOptions::default(),
+        )
+        .unwrap()
+        .remaining_gas
+    };
+
+    // the size of each of the three entries is read and charged before
+    // refunding anything
+    let without_refunds = run(0, 20);
+    let not_refunded = run(1_000_000, 0);
+    let read_cost = GasCosts::default().abi_costs["assembly_script_get_data"];
+    assert_eq!(not_refunded, without_refunds - 3 * read_cost);
+
+    // the refunds of the nested calls count against the cap of the top-level
+    // one, which would otherwise refund its own 20% on top of theirs
+    let refunded = run(1_000_000, 20);
+    let refund = refunded - not_refunded;
+    assert!(refund > 0);
+    assert!(
+        refund <= (gas_limit - not_refunded) / 5,
+        "{} refunded for {} spent",
+        refund,
+        gas_limit - not_refunded
+    );
+    DATASTORE_VALUE.with(|value| value.borrow_mut().clear());
+    CREATED_MODULES.lock().unwrap().remove("refunder");
+
+    // the wasmv1 deletions are refunded as well
+    let request = DeleteDsEntryRequest {
+        key: b"k".to_vec(),
+        address: None,
+    }
+    .encode_to_vec();
+    let mut wasmv1_module = vec![1];
+    wasmv1_module.extend(
+        wasmer::wat2wasm(
+            format!(
+                r#"(module
+                    (import "massa" "abi_delete_ds_entry" (func $delete (param i32) (result i32)))
+                    (memory (export "memory") 1)
+                    (data (i32.const 64) "{}{}")
+                    (func (export "__alloc") (param i32) (result i32)
+                        (i32.store (i32.const 4092) (local.get 0))
+                        (i32.const 4096))
+                    (func (export "main") (param i32) (result i32)
+                        (call $delete (i32.const 64))))"#,
+                wat_bytes(&(request.len() as u32).to_le_bytes()),
+                wat_bytes(&request),
+            )
+            .as_bytes(),
+        )
+        .unwrap()
+        .iter(),
+    );
+    let read_cost = 50;
+    let run = |refund_per_byte: u64, max_percent: u64| {
+        let mut gas_costs = GasCosts {
+            storage_refund_per_byte: refund_per_byte,
+            max_storage_refund_percent: max_percent,
+            ..GasCosts::default()
+        };
+        let config = RuntimeConfig::default();
+        gas_costs
+            .abi_costs
+            .insert("abi_get_ds_value".to_string(), read_cost);
+        let runtime_module =
+            RuntimeModule::new(&wasmv1_module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+        run_main(
+            &interface,
+            runtime_module,
+            gas_limit,
+            gas_costs,
+            &config,
+            ExecutionOptions::default(),
+        )
+        .unwrap()
+        .remaining_gas
+    };
+    let without_refunds = run(0, 20);
+    let not_refunded = run(1_000_000, 0);
+    assert_eq!(not_refunded, without_refunds - read_cost);
+    let refund = run(1_000_000, 20) - not_refunded;
+    assert!(refund > 0 && refund <= (gas_limit - not_refunded) / 5);
+}
+
+#[test]
+/// The types shared by the executions of a node running them on several
+/// threads
+fn test_shared_types_are_send_sync() {
+    use crate::{CachedModuleHandle, EngineProvider, EventLimits, InterruptHandle, StorageLimits};
+
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<GasCosts>();
+    assert_send_sync::<CondomLimits>();
+    assert_send_sync::<StorageLimits>();
+    assert_send_sync::<EventLimits>();
+    assert_send_sync::<EngineProvider>();
+    assert_send_sync::<ModuleCache>();
+    assert_send_sync::<CachedModuleHandle>();
+    assert_send_sync::<RuntimeModule>();
+    assert_send_sync::<InterruptHandle>();
+    assert_send_sync::<Box<dyn Interface>>();
+    assert_send_sync::<VMError>();
+}
+
+#[test]
+/// Test the same module run by several threads, each with its own interface
+fn test_concurrent_executions() {
+    let module = wasmer::wat2wasm(
+        br#"(module
+            (import "massa" "assembly_script_get_balance" (func $balance (result i64)))
+            (memory (export "memory") 1)
+            (data (i32.const 12) "\0c\00\00\00")
+            (func (export "run") (result i32)
+                (local $i i32)
+                (local $sum i32)
+                (block $done
+                    (loop $next
+                        (br_if $done (i32.eq (local.get $i) (i32.const 1000)))
+                        (local.set $sum (i32.add (local.get $sum) (local.get $i)))
+                        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                        (br $next)))
+                (i32.store (i32.const 16) (local.get $sum))
+                (i64.store (i32.const 20) (call $balance))
+                (i32.const 16)))"#,
+    )
+    .unwrap();
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    let runtime_module =
+        RuntimeModule::new(&module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+    let responses: Vec<_> = std::thread::scope(|scope| {
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let (runtime_module, gas_costs) = (runtime_module.clone(), gas_costs.clone());
+                scope.spawn(move || {
+                    let interface = TestInterface;
+                    (0..10)
+                        .map(|_| {
+                            let response = run_function(
+                                &interface,
+                                runtime_module.clone(),
+                                "run",
+                                b"",
+                                1_000_000,
+                                gas_costs.clone(),
+                                &config,
+                                ExecutionOptions::default(),
+                            )
+                            .unwrap();
+                            (response.ret, response.remaining_gas)
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        threads
+            .into_iter()
+            .flat_map(|thread| thread.join().unwrap())
+            .collect()
+    });
+
+    let mut expected = 499_500u32.to_le_bytes().to_vec();
+    expected.extend(TEST_BALANCE.to_le_bytes());
+    assert_eq!(responses.len(), 80);
+    assert_eq!(responses[0].0, expected);
+    assert!(responses.iter().all(|response| *response == responses[0]));
+}
+
+#[test]
+/// This test converts amounts to and from strings with the decimals of the
+/// runtime, charging the characters of the strings
+fn test_native_amount_strings_wasmv1() {
+    use massa_proto_rs::massa::{
+        abi::v1::{
+            abi_response, resp_result, AbiResponse, NativeAmountFromStringRequest,
+            NativeAmountToStringRequest,
+        },
+        model::v1::NativeAmount,
+    };
+    use prost::Message;
+
+    let interface = TestInterface;
+    let mut module = vec![1];
+    module.extend(
+        wasmer::wat2wasm(
+            br#"(module
+                (import "massa" "abi_native_amount_to_string"
+                    (func $to_string (param i32) (result i32)))
+                (import "massa" "abi_native_amount_from_string"
+                    (func $from_string (param i32) (result i32)))
+                (memory (export "memory") 1)
+                (func (export "__alloc") (param i32) (result i32)
+                    (i32.store (i32.const 1020) (local.get 0))
+                    (i32.const 1024))
+                (func (export "to_string") (param i32) (result i32)
+                    (i32.sub (call $to_string (i32.sub (local.get 0) (i32.const 4)))
+                        (i32.const 4)))
+                (func (export "from_string") (param i32) (result i32)
+                    (i32.sub (call $from_string (i32.sub (local.get 0) (i32.const 4)))
+                        (i32.const 4))))"#,
+        )
+        .unwrap()
+        .iter(),
+    );
+    let run = |function: &str, request: Vec<u8>, gas_costs: GasCosts, config: &RuntimeConfig| {
+        let runtime_module =
+            RuntimeModule::new(&module, gas_costs.clone(), config, Compiler::SP).unwrap();
+        let response = run_function(
+            &interface,
+            runtime_module,
+            function,
+            &request,
+            1_000_000,
+            gas_costs,
+            config,
+            ExecutionOptions::default(),
+        )
+        .unwrap();
+        let resp = AbiResponse::decode(response.ret.as_slice())
+            .unwrap()
+            .resp
+            .unwrap();
+        (resp, response.remaining_gas)
+    };
+    let to_string = |mantissa: u64, scale: u32, gas_costs: GasCosts, config: &RuntimeConfig| {
+        let request = NativeAmountToStringRequest {
+            to_convert: Some(NativeAmount { mantissa, scale }),
+        };
+        match run("to_string", request.encode_to_vec(), gas_costs, config) {
+            (abi_response::Resp::Res(res), remaining_gas) => match res.res.unwrap() {
+                resp_result::Res::NativeAmountToStringResult(res) => {
+                    (Ok(res.converted_amount), remaining_gas)
+                }
+                res => panic!("unexpected result: {:?}", res),
+            },
+            (abi_response::Resp::Error(err), remaining_gas) => (Err(err.message), remaining_gas),
+        }
+    };
+    let from_string = |string: &str, gas_costs: GasCosts, config: &RuntimeConfig| {
+        let request = NativeAmountFromStringRequest {
+            to_convert: string.to_string(),
+        };
+        match run("from_string", request.encode_to_vec(), gas_costs, config) {
+            (abi_response::Resp::Res(res), remaining_gas) => match res.res.unwrap() {
+                resp_result::Res::NativeAmountFromStringResult(res) => {
+                    (Ok(res.converted_amount.unwrap()), remaining_gas)
+                }
+                res => panic!("unexpected result: {:?}", res),
+            },
+            (abi_response::Resp::Error(err), remaining_gas) => (Err(err.message), remaining_gas),
+        }
+    };
+
+    for (mantissa, scale, string) in [
+        (0, 9, "0"),
+        (u64::MAX, 9, "18446744073.709551615"),
+        (1_500_000_000, 9, "1.5"),
+        (15, 1, "1.5"),
+        (42, 0, "42"),
+    ] {
+        assert_eq!(
+            to_string(
+                mantissa,
+                scale,
+                GasCosts::default(),
+                &RuntimeConfig::default()
+            )
+            .0,
+            Ok(string.to_string())
+        );
+    }
+    assert!(
+        to_string(1, 10, GasCosts::default(), &RuntimeConfig::default())
+            .0
+            .is_err()
+    );
+    assert!(
+        to_string(u64::MAX, 0, GasCosts::default(), &RuntimeConfig::default())
+            .0
+            .is_err()
+    );
+    let decimals = RuntimeConfig {
+        native_amount_decimals: 2,
+        ..RuntimeConfig::default()
+    };
+    assert_eq!(
+        to_string(1_250, 2, GasCosts::default(), &decimals).0,
+        Ok("12.5".to_string())
+    );
+    assert_eq!(
+        from_string("12.5", GasCosts::default(), &decimals).0,
+        Ok(NativeAmount {
+            mantissa: 1_250,
+            scale: 2
+        })
+    );
+
+    for (string, mantissa) in [
+        ("0", 0),
+        ("18446744073.709551615", u64::MAX),
+        ("1.500000000", 1_500_000_000),
+        ("007.50", 7_500_000_000),
+    ] {
+        assert_eq!(
+            from_string(string, GasCosts::default(), &RuntimeConfig::default()).0,
+            Ok(NativeAmount { mantissa, scale: 9 })
+        );
+    }
+    for (string, code) in [
+        ("", 1),
+        ("+1", 2),
+        ("-1", 2),
+        ("1,5", 3),
+        ("1 000", 3),
+        ("1'000", 3),
+        ("1.000,5", 3),
+        ("\u{661}", 3),
+        ("0.0000000001", 4),
+        ("18446744073.709551616", 5),
+    ] {
+        let err = from_string(string, GasCosts::default(), &RuntimeConfig::default())
+            .0
+            .unwrap_err();
+        assert!(
+            err.starts_with(&format!("Invalid amount (code {})", code)),
+            "{}: {}",
+            string,
+            err
+        );
+    }
+
+    // the characters are charged on top of the flat cost
+    let mut free = GasCosts::default();
+    free.abi_costs
+        .insert("abi_native_amount_to_string_per_byte".to_string(), 0);
+    free.abi_costs
+        .insert("abi_native_amount_from_string_per_byte".to_string(), 0);
+    let string = "18446744073.709551615";
+    assert_eq!(
+        to_string(u64::MAX, 9, free.clone(), &RuntimeConfig::default()).1
+            - to_string(u64::MAX, 9, GasCosts::default(), &RuntimeConfig::default()).1,
+        string.len() as u64
+    );
+    assert_eq!(
+        from_string(string, free, &RuntimeConfig::default()).1
+            - from_string(string, GasCosts::default(), &RuntimeConfig::default()).1,
+        string.len() as u64
+    );
+}
+
+#[test]
+#[serial]
+/// Test that the prefetch hints reach the interface before the reads they
+/// announce, and that the executions return the same without them
+fn test_hint_keys() {
+    use crate::tests::{DatastoreRead, DATASTORE_READS};
+
+    let interface = TestInterface;
+    DATASTORE_VALUE.with(|value| *value.borrow_mut() = b"value".to_vec());
+    let hinted_keys = [&2u32.to_le_bytes()[..], &[2], b"k1", &[2], b"k2"].concat();
+    let module = wasmer::wat2wasm(
+        format!(
+            r#"(module
+                (import "massa" "assembly_script_hint_keys" (func $hint (param i32)))
+                (import "massa" "assembly_script_get_data"
+                    (func $get (param i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 28) "{}{}")
+                (data (i32.const 60) "\02\00\00\00k1")
+                (data (i32.const 92) "\02\00\00\00k2")
+                (func (export "__new") (param i32 i32) (result i32)
+                    (i32.store (i32.const 1020) (local.get 0))
+                    (i32.const 1024))
+                (func (export "hinted") (result i32)
+                    (call $hint (i32.const 32))
+                    (drop (call $get (i32.const 64)))
+                    (call $get (i32.const 96)))
+                (func (export "plain") (result i32)
+                    (drop (call $get (i32.const 64)))
+                    (call $get (i32.const 96))))"#,
+            wat_bytes(&(hinted_keys.len() as u32).to_le_bytes()),
+            wat_bytes(&hinted_keys),
+        )
+        .as_bytes(),
+    )
+    .unwrap();
+    let run = |function: &str, gas_costs: GasCosts, config: &RuntimeConfig| {
+        DATASTORE_READS.with(|reads| reads.borrow_mut().clear());
+        let runtime_module =
+            RuntimeModule::new(&module, gas_costs.clone(), config, Compiler::SP).unwrap();
+        let result = run_function(
+            &interface,
+            runtime_module,
+            function,
+            b"",
+            1_000_000,
+            gas_costs,
+            config,
+            ExecutionOptions::default(),
+        );
+        (result, DATASTORE_READS.with(|reads| reads.take()))
+    };
+
+    let (hinted, reads) = run("hinted", GasCosts::default(), &RuntimeConfig::default());
+    let hinted = hinted.unwrap();
+    assert_eq!(
+        reads,
+        vec![
+            DatastoreRead::Prefetch(vec![b"k1".to_vec(), b"k2".to_vec()]),
+            DatastoreRead::Get(b"k1".to_vec()),
+            DatastoreRead::Get(b"k2".to_vec()),
+        ]
+    );
+    // the failure of the prefetch of the mock is not seen by the guest
+    let (plain, reads) = run("plain", GasCosts::default(), &RuntimeConfig::default());
+    let plain = plain.unwrap();
+    assert_eq!(
+        reads,
+        vec![
+            DatastoreRead::Get(b"k1".to_vec()),
+            DatastoreRead::Get(b"k2".to_vec()),
+        ]
+    );
+    assert_eq!(hinted.ret, b"value");
+    assert_eq!(hinted.ret, plain.ret);
+
+    // each key is charged
+    let mut free = GasCosts::default();
+    free.abi_costs
+        .insert("assembly_script_hint_keys_per_entry".to_string(), 0);
+    let free = run("hinted", free, &RuntimeConfig::default()).0.unwrap();
+    assert_eq!(free.remaining_gas - hinted.remaining_gas, 2);
+
+    // too many keys fail before reaching the interface
+    let capped = RuntimeConfig {
+        max_hint_keys: 1,
+        ..RuntimeConfig::default()
+    };
+    let (err, reads) = run("hinted", GasCosts::default(), &capped);
+    let err = err.unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("Too many keys hinted: 2, the maximum is 1"),
+        "{}",
+        err
+    );
+    assert!(reads.is_empty());
+    DATASTORE_VALUE.with(|value| value.borrow_mut().clear());
+}
+
+#[test]
+#[serial]
+/// Test that `assembly_script_get_keys_values` counts and charges the keys
+/// before reading their values
+fn test_get_keys_values() {
+    use crate::tests::{DatastoreRead, DATASTORE_READS};
+
+    let interface = TestInterface;
+    DATASTORE_KEYS
+        .with(|keys| *keys.borrow_mut() = [b"a".to_vec(), b"b".to_vec(), b"c".to_vec()].into());
+    DATASTORE_VALUE.with(|value| *value.borrow_mut() = b"v".to_vec());
+    let module = wasmer::wat2wasm(
+        br#"(module
+            (import "massa" "assembly_script_get_keys_values"
+                (func $get_keys_values (param i32) (result i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 12) "\00\00\00\00")
+            (func (export "__new") (param i32 i32) (result i32)
+                (i32.store (i32.const 1020) (local.get 0))
+                (i32.const 1024))
+            (func (export "main") (result i32)
+                (call $get_keys_values (i32.const 16))))"#,
+    )
+    .unwrap();
+    let run = |gas_costs: GasCosts, config: &RuntimeConfig| {
+        DATASTORE_READS.with(|reads| reads.borrow_mut().clear());
+        let runtime_module =
+            RuntimeModule::new(&module, gas_costs.clone(), config, Compiler::SP).unwrap();
+        let result = run_function(
+            &interface,
+            runtime_module,
+            "main",
+            b"",
+            1_000_000,
+            gas_costs,
+            config,
+            ExecutionOptions::default(),
+        );
+        (result, DATASTORE_READS.with(|reads| reads.take()))
+    };
+
+    let (response, reads) = run(GasCosts::default(), &RuntimeConfig::default());
+    let response = response.unwrap();
+    let mut expected = 3u32.to_le_bytes().to_vec();
+    for key in [b"a", b"b", b"c"] {
+        expected.extend([&[1u8][..], key, &1u32.to_le_bytes(), b"v"].concat());
+    }
+    assert_eq!(response.ret, expected);
+    assert_eq!(
+        reads,
+        vec![
+            DatastoreRead::Get(b"a".to_vec()),
+            DatastoreRead::Get(b"b".to_vec()),
+            DatastoreRead::Get(b"c".to_vec()),
+        ]
+    );
+
+    // each entry is charged
+    let mut free = GasCosts::default();
+    free.abi_costs
+        .insert("assembly_script_get_keys_values_per_entry".to_string(), 0);
+    let free = run(free, &RuntimeConfig::default()).0.unwrap();
+    let per_entry = GasCosts::default().abi_costs["assembly_script_get_keys_values_per_entry"];
+    assert_eq!(free.remaining_gas - response.remaining_gas, 3 * per_entry);
+
+    // too many keys fail before any value is read
+    let mut capped = RuntimeConfig::default();
+    capped.storage_limits.max_keys_per_query = 2;
+    let (err, reads) = run(GasCosts::default(), &capped);
+    let err = err.unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("Too many datastore entries: 3, the maximum is 2"),
+        "{}",
+        err
+    );
+    assert!(reads.is_empty(), "{:?}", reads);
+
+    // without enough gas for the entries, no value is read either
+    let mut expensive = GasCosts::default();
+    expensive.abi_costs.insert(
+        "assembly_script_get_keys_values_per_entry".to_string(),
+        1_000_000,
+    );
+    let (err, reads) = run(expensive, &RuntimeConfig::default());
+    assert!(err.is_err());
+    assert!(reads.is_empty(), "{:?}", reads);
+
+    DATASTORE_KEYS.with(|keys| keys.borrow_mut().clear());
+    DATASTORE_VALUE.with(|value| value.borrow_mut().clear());
+}
+
+#[test]
+#[serial]
+/// Test the calls re-entering a smart contract of the call stack under each
+/// `Reentrancy`
+fn test_reentrancy() {
+    use crate::tests::CALL_STACK;
+    use crate::Reentrancy;
+    use massa_proto_rs::massa::{abi::v1::CallRequest, model::v1::NativeAmount};
+    use prost::Message;
+
+    let interface = TestInterface;
+    // `run` calls `function` of `target` with a one byte parameter, `ping`
+    // calls it without parameter when it is given one
+    let module = |target: &str, function: &str| {
+        wasmer::wat2wasm(
+            format!(
+                r#"(module
+                    (import "massa" "assembly_script_call"
+                        (func $call (param i32 i32 i32 i64) (result i32)))
+                    (memory (export "memory") 1)
+                    (data (i32.const 12) "\00\00\00\00")
+                    (data (i32.const 220) "\01\00\00\00\2a")
+                    {}
+                    {}
+                    (func (export "__new") (param i32 i32) (result i32)
+                        (i32.store (i32.const 1020) (local.get 0))
+                        (i32.const 1024))
+                    (func (export "run") (result i32)
+                        (call $call (i32.const 32) (i32.const 128) (i32.const 224) (i64.const 0)))
+                    (func (export "ping") (param i32) (result i32)
+                        (if (result i32) (i32.load (i32.sub (local.get 0) (i32.const 4)))
+                            (then (call $call (i32.const 32) (i32.const 128) (i32.const 16)
+                                (i64.const 0)))
+                            (else (i32.const 16))))
+                    (func (export "other") (result i32) (i32.const 16)))"#,
+                wat_as_string(32, target),
+                wat_as_string(128, function),
+            )
+            .as_bytes(),
+        )
+        .unwrap()
+        .to_vec()
+    };
+    for (address, target, function) in [
+        ("reentrant_a", "reentrant_b", "run"),
+        ("reentrant_b", "reentrant_a", "run"),
+        ("chain_a", "chain_b", "run"),
+        ("chain_b", "chain_c", "run"),
+        ("chain_c", "chain_c", "other"),
+        ("ping", "ping", "ping"),
+        ("root", "root", "other"),
+    ] {
+        CREATED_MODULES
+            .lock()
+            .unwrap()
+            .insert(address.to_string(), module(target, function));
+    }
+    CALL_STACK.with(|stack| *stack.borrow_mut() = vec!["root".to_string()]);
+    let run = |target: &str, function: &str, reentrancy: Reentrancy| {
+        let gas_costs = GasCosts::default();
+        let config = RuntimeConfig {
+            reentrancy,
+            ..RuntimeConfig::default()
+        };
+        let runtime_module = RuntimeModule::new(
+            &module(target, function),
+            gas_costs.clone(),
+            &config,
+            Compiler::SP,
+        )
+        .unwrap();
+        run_function(
+            &interface,
+            runtime_module,
+            "run",
+            b"",
+            10_000_000,
+            gas_costs,
+            &config,
+            ExecutionOptions::default(),
+        )
+    };
+    let denied = |result: crate::error::VMResult<crate::Response>, call: &str| {
+        let err = result.unwrap_err();
+        assert!(
+            err.to_string()
+                .contains(&format!("Re-entrant call to {} denied", call)),
+            "{}",
+            err
+        );
+    };
+
+    // A -> B -> A
+    denied(
+        run("reentrant_a", "run", Reentrancy::Deny),
+        "reentrant_a:run",
+    );
+    denied(
+        run("reentrant_a", "run", Reentrancy::DenySameFunction),
+        "reentrant_a:run",
+    );
+    let err = run("reentrant_a", "run", Reentrancy::Allow).unwrap_err();
+    assert!(err.to_string().contains("Maximum call depth"), "{}", err);
+
+    // A -> B -> C
+    for reentrancy in [
+        Reentrancy::Allow,
+        Reentrancy::Deny,
+        Reentrancy::DenySameFunction,
+    ] {
+        run("chain_a", "run", reentrancy).unwrap();
+    }
+
+    // A -> A, the same function
+    denied(run("ping", "ping", Reentrancy::Deny), "ping:ping");
+    denied(
+        run("ping", "ping", Reentrancy::DenySameFunction),
+        "ping:ping",
+    );
+    run("ping", "ping", Reentrancy::Allow).unwrap();
+
+    // the top-level smart contract, the last address of the call stack of
+    // the interface, running `run`
+    denied(run("root", "run", Reentrancy::DenySameFunction), "root:run");
+    run("root", "other", Reentrancy::DenySameFunction).unwrap();
+    denied(run("root", "other", Reentrancy::Deny), "root:other");
+    run("root", "run", Reentrancy::Allow).unwrap_err();
+
+    // A (AssemblyScript) -> B (wasmv1) -> A, denied by the wasmv1 call
+    let request = CallRequest {
+        target_sc_address: "mixed_as".to_string(),
+        target_function_name: "run".to_string(),
+        function_arg: Vec::new(),
+        call_coins: Some(NativeAmount {
+            mantissa: 0,
+            scale: 0,
+        }),
+    }
+    .encode_to_vec();
+    let mut wasmv1_module = vec![1];
+    wasmv1_module.extend(
+        wasmer::wat2wasm(
+            format!(
+                r#"(module
+                    (import "massa" "abi_call" (func $call (param i32) (result i32)))
+                    (memory (export "memory") 1)
+                    (data (i32.const 8) "\00\00\00\00")
+                    (data (i32.const 64) "{}{}")
+                    (func (export "__alloc") (param i32) (result i32)
+                        (i32.store (i32.const 4092) (local.get 0))
+                        (i32.const 4096))
+                    (func (export "run") (param i32) (result i32)
+                        (drop (call $call (i32.const 64)))
+                        (i32.const 8)))"#,
+                wat_bytes(&(request.len() as u32).to_le_bytes()),
+                wat_bytes(&request),
+            )
+            .as_bytes(),
+        )
+        .unwrap()
+        .iter(),
+    );
+    CREATED_MODULES
+        .lock()
+        .unwrap()
+        .insert("mixed_as".to_string(), module("mixed_v1", "run"));
+    CREATED_MODULES
+        .lock()
+        .unwrap()
+        .insert("mixed_v1".to_string(), wasmv1_module);
+    denied(run("mixed_as", "run", Reentrancy::Deny), "mixed_as:run");
+    denied(
+        run("mixed_as", "run", Reentrancy::DenySameFunction),
+        "mixed_as:run",
+    );
+    let err = run("mixed_as", "run", Reentrancy::Allow).unwrap_err();
+    assert!(err.to_string().contains("Maximum call depth"), "{}", err);
+    CALL_STACK.with(|stack| stack.borrow_mut().clear());
+}
+
+#[test]
+#[serial]
+/// Test reading the datastore value of a key by chunks
+fn test_get_data_chunk() {
+    let interface = TestInterface;
+    DATASTORE_VALUE.with(|value| *value.borrow_mut() = b"0123456789".to_vec());
+    let chunk = |name: &str, offset: i64, len: i32| {
+        format!(
+            r#"(func (export "{}") (result i32)
+                (call $chunk (i32.const 32) (i64.const {}) (i32.const {})))"#,
+            name, offset, len
+        )
+    };
+    let module = wasmer::wat2wasm(
+        format!(
+            r#"(module
+                (import "massa" "assembly_script_get_data_chunk"
+                    (func $chunk (param i32 i64 i32) (result i32)))
+                (import "massa" "assembly_script_get_data_len"
+                    (func $len (param i32) (result i64)))
+                (memory (export "memory") 1)
+                (data (i32.const 28) "\01\00\00\00k")
+                (data (i32.const 60) "\08\00\00\00")
+                (func (export "__new") (param i32 i32) (result i32)
+                    (i32.store (i32.const 1020) (local.get 0))
+                    (i32.const 1024))
+                (func (export "len") (result i32)
+                    (i64.store (i32.const 64) (call $len (i32.const 32)))
+                    (i32.const 64))
+                {} {} {} {} {} {} {})"#,
+            chunk("middle", 3, 4),
+            chunk("tail", 8, 4),
+            chunk("past_end", 20, 4),
+            chunk("zero", 3, 0),
+            chunk("cap", 0, 10),
+            chunk("over_cap", 0, 11),
+            chunk("negative", -1, 4),
+        )
+        .as_bytes(),
+    )
+    .unwrap();
+    let capped = RuntimeConfig {
+        max_abi_read_len: 10,
+        ..RuntimeConfig::default()
+    };
+    let run = |function: &str| {
+        let runtime_module =
+            RuntimeModule::new(&module, GasCosts::default(), &capped, Compiler::SP).unwrap();
+        run_function(
+            &interface,
+            runtime_module,
+            function,
+            b"",
+            1_000_000,
+            GasCosts::default(),
+            &capped,
+            ExecutionOptions::default(),
+        )
+    };
+
+    let len = run("len").unwrap();
+    assert_eq!(len.ret, 10u64.to_le_bytes());
+    let middle = run("middle").unwrap();
+    assert_eq!(middle.ret, b"3456");
+    assert_eq!(run("tail").unwrap().ret, b"89");
+    // past the end of the value is an empty chunk, not an error
+    let past_end = run("past_end").unwrap();
+    assert!(past_end.ret.is_empty());
+    let zero = run("zero").unwrap();
+    assert!(zero.ret.is_empty());
+    // only the returned bytes are charged
+    assert_eq!(zero.remaining_gas - middle.remaining_gas, 4);
+    assert_eq!(past_end.remaining_gas, zero.remaining_gas);
+
+    // the length is bounded by the cap of a single copy
+    assert_eq!(run("cap").unwrap().ret, b"0123456789");
+    let err = run("over_cap").unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("Chunk of 11 bytes requested, the maximum is 10"),
+        "{}",
+        err
+    );
+    let err = run("negative").unwrap_err();
+    assert!(err.to_string().contains("Negative offset."), "{}", err);
+
+    // the default implementation of the interface slices the whole value
+    assert_eq!(
+        interface.raw_get_data_chunk(b"k", 8, 4).unwrap(),
+        b"89".to_vec()
+    );
+    assert!(interface
+        .raw_get_data_chunk(b"k", u64::MAX, u64::MAX)
+        .unwrap()
+        .is_empty());
+    assert_eq!(
+        interface.raw_get_data_chunk(b"k", 2, u64::MAX).unwrap(),
+        b"23456789".to_vec()
+    );
+}
+
+#[test]
+#[serial]
+/// Test that the bytecode of a created smart contract is charged per byte and
+/// validated before it reaches the interface
+fn test_create_sc_validation() {
+    let interface = TestInterface;
+    let module = wasmer::wat2wasm(
+        br#"(module
+            (import "massa" "assembly_script_create_sc"
+                (func $create_sc (param i32) (result i32)))
+            (import "massa" "assembly_script_create_sc_with_data"
+                (func $create_sc_with_data (param i32 i32) (result i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 60) "\08\00\00\00\00asm\01\00\00\00")
+            (data (i32.const 92) "\06\00\00\00\00asm\01\00")
+            (data (i32.const 124) "\05\00\00\00hello")
+            (data (i32.const 152) "\0b\00\00\00\01\00\00\00\01k\01\00\00\00v")
+            (func (export "__new") (param i32 i32) (result i32)
+                (i32.store (i32.const 1020) (local.get 0))
+                (i32.const 1024))
+            (func (export "valid") (result i32)
+                (call $create_sc (i32.const 64)))
+            (func (export "truncated") (result i32)
+                (call $create_sc (i32.const 96)))
+            (func (export "blob") (result i32)
+                (call $create_sc (i32.const 128)))
+            (func (export "with_data") (result i32)
+                (call $create_sc_with_data (i32.const 64) (i32.const 156))))"#,
+    )
+    .unwrap();
+    // compiled with the default limits, which the module itself has to pass
+    let runtime_module = RuntimeModule::new(
+        &module,
+        GasCosts::default(),
+        &RuntimeConfig::default(),
+        Compiler::SP,
+    )
+    .unwrap();
+    let run = |function: &str, gas_costs: GasCosts, config: &RuntimeConfig| {
+        CREATED_MODULES.lock().unwrap().clear();
+        let result = run_function(
+            &interface,
+            runtime_module.clone(),
+            function,
+            b"",
+            1_000_000,
+            gas_costs,
+            config,
+            ExecutionOptions::default(),
+        );
+        (result, CREATED_MODULES.lock().unwrap().remove("sc_address"))
+    };
+    let rejected = |function: &str, config: &RuntimeConfig, message: &str| {
+        let (result, created) = run(function, GasCosts::default(), config);
+        let err = result.unwrap_err();
+        assert!(err.to_string().contains(message), "{}", err);
+        assert_eq!(created, None);
+    };
+
+    let (valid, created) = run("valid", GasCosts::default(), &RuntimeConfig::default());
+    let valid = valid.unwrap();
+    assert_eq!(created.unwrap(), b"\0asm\x01\0\0\0");
+    // each byte of the bytecode is charged
+    let free = GasCosts {
+        sc_creation_byte_cost: 0,
+        ..GasCosts::default()
+    };
+    let free = run("valid", free, &RuntimeConfig::default()).0.unwrap();
+    assert_eq!(free.remaining_gas - valid.remaining_gas, 8);
+    // once, the per byte cost of the ABI charging its datastore only
+    let (with_data, created) = run("with_data", GasCosts::default(), &RuntimeConfig::default());
+    let with_data = with_data.unwrap();
+    assert_eq!(created.unwrap(), b"\0asm\x01\0\0\0");
+    let free_bytecode = GasCosts {
+        sc_creation_byte_cost: 0,
+        ..GasCosts::default()
+    };
+    let free_bytecode = run("with_data", free_bytecode, &RuntimeConfig::default())
+        .0
+        .unwrap();
+    assert_eq!(free_bytecode.remaining_gas - with_data.remaining_gas, 8);
+    let mut free_datastore = GasCosts::default();
+    free_datastore.abi_costs.insert(
+        "assembly_script_create_sc_with_data_per_byte".to_string(),
+        0,
+    );
+    let free_datastore = run("with_data", free_datastore, &RuntimeConfig::default())
+        .0
+        .unwrap();
+    assert_eq!(free_datastore.remaining_gas - with_data.remaining_gas, 11);
+
+    rejected("truncated", &RuntimeConfig::default(), "Invalid bytecode");
+    let over_limit = RuntimeConfig {
+        condom_limits: CondomLimits {
+            max_module_size: 7,
+            ..CondomLimits::default()
+        },
+        ..RuntimeConfig::default()
+    };
+    rejected(
+        "valid",
+        &over_limit,
+        "Module limit module size exceeded: 8, the configured maximum is 7",
+    );
+
+    // data blobs are only created when the embedder allows them, within the
+    // size limit
+    rejected(
+        "blob",
+        &RuntimeConfig::default(),
+        "Invalid bytecode: not a WebAssembly module",
+    );
+    let blobs = RuntimeConfig {
+        allow_non_wasm_contracts: true,
+        ..RuntimeConfig::default()
+    };
+    let (blob, created) = run("blob", GasCosts::default(), &blobs);
+    blob.unwrap();
+    assert_eq!(created.unwrap(), b"hello");
+    rejected("truncated", &blobs, "Invalid bytecode");
+    let capped_blobs = RuntimeConfig {
+        condom_limits: CondomLimits {
+            max_module_size: 4,
+            ..CondomLimits::default()
+        },
+        ..blobs
+    };
+    rejected(
+        "blob",
+        &capped_blobs,
+        "Module limit module size exceeded: 5, the configured maximum is 4",
+    );
+}
+
+#[test]
+#[serial]
+/// Test that a smart contract calling itself stops at the maximum recursion
+/// depth, in both runtimes
+fn test_self_recursive_contract() {
+    use massa_proto_rs::massa::{abi::v1::CallRequest, model::v1::NativeAmount};
+    use prost::Message;
+
+    let interface = TestInterface;
+    let as_module = wasmer::wat2wasm(
+        format!(
+            r#"(module
+                (import "massa" "assembly_script_call"
+                    (func $call (param i32 i32 i32 i64) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 12) "\00\00\00\00")
+                {}
+                {}
+                (func (export "__new") (param i32 i32) (result i32)
+                    (i32.store (i32.const 1020) (local.get 0))
+                    (i32.const 1024))
+                (func (export "run") (result i32)
+                    (call $call (i32.const 32) (i32.const 128) (i32.const 16) (i64.const 0))))"#,
+            wat_as_string(32, "recursive_as"),
+            wat_as_string(128, "run"),
+        )
+        .as_bytes(),
+    )
+    .unwrap()
+    .to_vec();
+    let request = CallRequest {
+        target_sc_address: "recursive_v1".to_string(),
+        target_function_name: "run".to_string(),
+        function_arg: Vec::new(),
+        call_coins: Some(NativeAmount {
+            mantissa: 0,
+            scale: 0,
+        }),
+    }
+    .encode_to_vec();
+    let mut wasmv1_module = vec![1];
+    wasmv1_module.extend(
+        wasmer::wat2wasm(
+            format!(
+                r#"(module
+                    (import "massa" "abi_call" (func $call (param i32) (result i32)))
+                    (memory (export "memory") 1)
+                    (data (i32.const 8) "\00\00\00\00")
+                    (data (i32.const 64) "{}{}")
+                    (func (export "__alloc") (param i32) (result i32)
+                        (i32.store (i32.const 4092) (local.get 0))
+                        (i32.const 4096))
+                    (func (export "run") (param i32) (result i32)
+                        (drop (call $call (i32.const 64)))
+                        (i32.const 8)))"#,
+                wat_bytes(&(request.len() as u32).to_le_bytes()),
+                wat_bytes(&request),
+            )
+            .as_bytes(),
+        )
+        .unwrap()
+        .iter(),
+    );
+    for (address, module) in [("recursive_as", as_module), ("recursive_v1", wasmv1_module)] {
+        CREATED_MODULES
+            .lock()
+            .unwrap()
+            .insert(address.to_string(), module.clone());
+        for max_recursion_depth in [0, 3] {
+            let gas_costs = GasCosts::default();
+            let config = RuntimeConfig {
+                max_recursion_depth,
+                ..RuntimeConfig::default()
+            };
+            let runtime_module =
+                RuntimeModule::new(&module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+            let err = run_function(
+                &interface,
+                runtime_module,
+                "run",
+                b"",
+                100_000_000,
+                gas_costs,
+                &config,
+                ExecutionOptions::default(),
+            )
+            .unwrap_err();
+            assert!(
+                err.to_string().contains(&format!(
+                    "Maximum call depth reached: {}",
+                    max_recursion_depth
+                )),
+                "{}: {}",
+                address,
+                err
+            );
+        }
+    }
+}
+
+#[test]
+#[serial]
+/// Test that a batch is charged by its size and written at once, or not at all
+/// when one of its entries is invalid
+fn test_set_data_batch() {
+    use crate::tests::DATASTORE_BATCHES;
+
+    let interface = TestInterface;
+    let module = wasmer::wat2wasm(
+        br#"(module
+            (import "massa" "assembly_script_set_data_batch"
+                (func $set_data_batch (param i32)))
+            (memory (export "memory") 1)
+            (func (export "__new") (param i32 i32) (result i32)
+                (i32.store (i32.const 1020) (local.get 0))
+                (i32.const 1024))
+            (func (export "main") (param i32) (result i32)
+                (call $set_data_batch (local.get 0))
+                (i32.const 0)))"#,
+    )
+    .unwrap();
+    let serialize = |entries: &[(Vec<u8>, Vec<u8>)]| {
+        let mut buffer = (entries.len() as u32).to_le_bytes().to_vec();
+        for (key, value) in entries {
+            buffer.push(key.len() as u8);
+            buffer.extend_from_slice(key);
+            buffer.extend((value.len() as u32).to_le_bytes());
+            buffer.extend_from_slice(value);
+        }
+        buffer
+    };
+    let run = |entries: &[(Vec<u8>, Vec<u8>)], gas_costs: GasCosts, config: &RuntimeConfig| {
+        DATASTORE_BATCHES.with(|batches| batches.borrow_mut().clear());
+        let runtime_module =
+            RuntimeModule::new(&module, gas_costs.clone(), config, Compiler::SP).unwrap();
+        let result = run_function(
+            &interface,
+            runtime_module,
+            "main",
+            &serialize(entries),
+            1_000_000,
+            gas_costs,
+            config,
+            ExecutionOptions::default(),
+        );
+        (result, DATASTORE_BATCHES.with(|batches| batches.take()))
+    };
+    let entries = |count: usize| -> Vec<(Vec<u8>, Vec<u8>)> {
+        (0..count)
+            .map(|i| (vec![b'k', i as u8], vec![b'v'; 10]))
+            .collect()
+    };
+
+    // the whole batch is written in one call
+    let (response, batches) = run(&entries(2), GasCosts::default(), &RuntimeConfig::default());
+    response.unwrap();
+    assert_eq!(batches, vec![entries(2)]);
+
+    // the gas of the entries grows with the size of the batch
+    let per_byte = GasCosts::default().abi_costs["assembly_script_set_data_batch_per_byte"];
+    assert!(per_byte > 0);
+    let mut free = GasCosts::default();
+    free.abi_costs
+        .insert("assembly_script_set_data_batch_per_byte".to_string(), 0);
+    for count in [2, 6] {
+        let charged = run(
+            &entries(count),
+            GasCosts::default(),
+            &RuntimeConfig::default(),
+        )
+        .0
+        .unwrap();
+        let free = run(&entries(count), free.clone(), &RuntimeConfig::default())
+            .0
+            .unwrap();
+        assert_eq!(
+            free.remaining_gas - charged.remaining_gas,
+            serialize(&entries(count)).len() as u64 * per_byte
+        );
+    }
+
+    // an entry over the limits rejects the whole batch before any write
+    let mut limited = RuntimeConfig::default();
+    limited.storage_limits.max_value_len = 10;
+    let mut invalid = entries(3);
+    invalid[2].1.push(b'v');
+    let (err, batches) = run(&invalid, GasCosts::default(), &limited);
+    let err = err.unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("Datastore value of 11 bytes exceeds the maximum of 10 bytes"),
+        "{}",
+        err
+    );
+    assert!(batches.is_empty(), "{:?}", batches);
+    limited.storage_limits.max_key_len = 1;
+    let (err, batches) = run(&entries(3), GasCosts::default(), &limited);
+    assert!(err
+        .unwrap_err()
+        .to_string()
+        .contains("Datastore key of 2 bytes"));
+    assert!(batches.is_empty(), "{:?}", batches);
+}
@@ -8,7 +8,8 @@ use wasmer::{
 /// A custom tunables that allows you to set a memory limit.
 ///
 /// After adjusting the memory limits, it delegates all other logic
-/// to the base tunables.
+/// to the base tunables. The gas of `memory.grow` and the trap above the
+/// limit are handled by `middlewares::memory_grow`.
 pub struct LimitingTunables<T: Tunables> {
     /// The maximum a linear memory is allowed to be (in Wasm pages, 64 KiB
     /// each). Since Wasmer ensures there is only none or one memory, this
@@ -1,14 +1,28 @@
-use anyhow::{anyhow, bail, Result};
+#[cfg(feature = "json")]
+use anyhow::anyhow;
+use anyhow::{bail, Result};
 use massa_proto_rs::massa::model::v1::{
     AddressCategory, ComparisonResult, NativeAmount, NativeTime, Slot,
 };
-use serde::{de::DeserializeOwned, Serialize};
+#[cfg(feature = "json")]
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+#[cfg(feature = "json")]
+use std::path::{Path, PathBuf};
 use std::{
-    collections::{BTreeSet, HashMap},
-    path::PathBuf,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    ops::Bound,
+    time::Duration,
 };
+use wasmer::wasmparser::Operator;
 
-use crate::execution::RuntimeModule;
+use crate::error::{GasCostsError, StorageLimitError};
+use crate::execution::{Compiler, RuntimeModule};
+use crate::host_extensions::HostExtensions;
+use crate::middlewares::operator::operator_field_str;
+use crate::module_cache::BytecodeHash;
+use crate::settings;
 
 #[cfg(feature = "execution-trace")]
 #[derive(Debug, Clone, PartialEq, Serialize)]
@@ -144,16 +158,83 @@ pub struct AbiTrace {
 }
 
 /// That's what is returned when a module is executed correctly since the end
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Response {
     /// returned value from the module call
     pub ret: Vec<u8>,
     /// number of gas that remain after the execution (metering)
     pub remaining_gas: u64,
-    /// number of gas required for the instance creation
+    /// number of gas required for the instance creation. The compilation is
+    /// not part of it, whether the module came from a cache must not change
+    /// the gas of an execution: the nested calls charge it per byte of the
+    /// bytecode of their callee instead.
     pub init_gas_cost: u64,
     #[cfg(feature = "execution-trace")]
     pub trace: Vec<AbiTrace>,
+    /// gas used by each smart contract called by the execution and its
+    /// nested calls, with `RuntimeConfig::collect_gas_breakdown`. The calls to an
+    /// address are summed, each of them counting the gas of its own nested
+    /// calls. Only filled for the top-level execution.
+    pub gas_breakdown: Option<Vec<(String, u64)>>,
+    /// per-ABI usage of the execution and its nested calls, only filled for
+    /// the top-level execution
+    #[cfg(feature = "profiling")]
+    pub profile: crate::ExecutionProfile,
+}
+
+/// Response of `run_function_typed`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TypedResponse {
+    /// `data` of the `CallResponse` returned by the function
+    pub data: Vec<u8>,
+    /// Number of events emitted by the execution and its nested calls
+    pub events_count: u64,
+    /// number of gas that remain after the execution
+    pub remaining_gas: u64,
+    /// number of gas required for the instance creation, already deducted
+    /// from `remaining_gas`
+    pub init_gas_cost: u64,
+}
+
+/// Call leading to an execution, reported in the execution errors
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CallFrame {
+    pub address: String,
+    pub function: String,
+    /// Gas given to the called function
+    pub gas_at_entry: u64,
+}
+
+impl CallFrame {
+    /// Create a frame, the strings are truncated to
+    /// `settings::max_call_frame_string_length` bytes
+    pub(crate) fn new(address: &str, function: &str, gas_at_entry: u64) -> Self {
+        fn truncate(value: &str) -> String {
+            let mut end = value.len().min(settings::max_call_frame_string_length());
+            while !value.is_char_boundary(end) {
+                end -= 1;
+            }
+            value[..end].to_string()
+        }
+        Self {
+            address: truncate(address),
+            function: truncate(function),
+            gas_at_entry,
+        }
+    }
+}
+
+/// Reason why `Interface::transfer_coins_checked` did not transfer the coins,
+/// given to the contract as its code
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransferError {
+    /// The sender does not have the coins
+    InsufficientBalance = 1,
+    /// The target address is not a valid address
+    InvalidAddress = 2,
+    /// The balance of the target would overflow
+    Overflow = 3,
 }
 
 pub trait InterfaceClone {
@@ -166,47 +247,948 @@ impl Clone for Box<dyn Interface> {
     }
 }
 
+/// Limits on the datastore entries handled by the ABIs, checked by the runtime
+/// before reaching the `Interface` so that every embedder rejects the same
+/// entries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StorageLimits {
+    pub max_key_len: usize,
+    pub max_value_len: usize,
+    /// Maximum number of entries returned by a single keys query, checked
+    /// before the keys are sorted
+    pub max_keys_per_query: usize,
+    pub allow_empty_keys: bool,
+}
+
+impl Default for StorageLimits {
+    fn default() -> Self {
+        Self {
+            max_key_len: settings::max_datastore_key_length(),
+            max_value_len: settings::max_datastore_value_length(),
+            max_keys_per_query: settings::max_datastore_entry_count(),
+            allow_empty_keys: true,
+        }
+    }
+}
+
+impl StorageLimits {
+    pub fn check_key(&self, key: &[u8]) -> Result<(), StorageLimitError> {
+        if key.is_empty() && !self.allow_empty_keys {
+            return Err(StorageLimitError::EmptyKey);
+        }
+        if key.len() > self.max_key_len {
+            return Err(StorageLimitError::KeyTooLong {
+                len: key.len(),
+                max: self.max_key_len,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn check_value(&self, value: &[u8]) -> Result<(), StorageLimitError> {
+        self.check_value_len(value.len())
+    }
+
+    pub fn check_value_len(&self, len: usize) -> Result<(), StorageLimitError> {
+        if len > self.max_value_len {
+            return Err(StorageLimitError::ValueTooLong {
+                len,
+                max: self.max_value_len,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn check_entry(&self, key: &[u8], value: &[u8]) -> Result<(), StorageLimitError> {
+        self.check_key(key)?;
+        self.check_value(value)
+    }
+
+    pub fn check_entry_count(&self, count: usize) -> Result<(), StorageLimitError> {
+        if count > self.max_keys_per_query {
+            return Err(StorageLimitError::TooManyEntries {
+                count,
+                max: self.max_keys_per_query,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Sort `keys` bytewise and drop the duplicates, so that the guests see the
+/// keys of an `Interface` returning them in any order in the same one.
+///
+/// Returns the number of comparisons the sort is charged for, `n * ceil(log2
+/// n)` for `n` keys.
+pub(crate) fn sort_keys(keys: &mut Vec<Vec<u8>>) -> usize {
+    let count = keys.len();
+    keys.sort_unstable();
+    keys.dedup();
+    match count {
+        0 | 1 => 0,
+        n => n * (usize::BITS - (n - 1).leading_zeros()) as usize,
+    }
+}
+
+/// Limits on the events emitted by an execution
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EventLimits {
+    /// Maximum number of topics of a binary event
+    pub max_topics: usize,
+    /// Maximum number of events emitted by an execution
+    pub max_event_count: usize,
+    /// Maximum total size in bytes of the events emitted by an execution
+    pub max_event_data_size: usize,
+}
+
+impl Default for EventLimits {
+    fn default() -> Self {
+        Self {
+            max_topics: settings::max_event_topics(),
+            max_event_count: settings::max_event_count(),
+            max_event_data_size: settings::max_event_data_size(),
+        }
+    }
+}
+
+/// Limits on the modules accepted by the runtime, checked when the bytecode is
+/// loaded and when the instance memory is created.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct CondomLimits {
+    /// Maximum number of 64KiB pages of the instance memory
+    pub max_memory_pages: u32,
+    /// Behavior of `memory.grow` above `max_memory_pages`
+    pub memory_limit_mode: MemoryLimitMode,
+    pub max_exports: usize,
+    pub max_imports: usize,
+    /// Maximum number of functions, imported ones included
+    pub max_functions: usize,
+    /// Maximum size of the bytecode in bytes
+    pub max_module_size: usize,
+    /// Maximum number of bytes of the data segments, active and passive ones
+    /// together
+    pub max_data_segments_size: usize,
+    pub max_element_segments: usize,
+    /// Maximum size of the code of a function in bytes, its locals included
+    pub max_function_body_size: usize,
+    /// Maximum number of locals of a function, its parameters excluded
+    pub max_function_locals: usize,
+    /// Maximum number of blocks, loops and ifs nested in a function, deep
+    /// nestings being slow to compile
+    pub max_block_depth: usize,
+    /// Handling of the start function, run when the module is instantiated
+    pub start_function_mode: StartFunctionMode,
+    /// Maximum number of guest calls in progress in an instance, the
+    /// execution fails with `ABIError::StackOverflow` above it. Applied when
+    /// the module is compiled.
+    pub max_stack_depth: u32,
+}
+
+impl Default for CondomLimits {
+    fn default() -> Self {
+        Self {
+            max_memory_pages: settings::max_number_of_pages(),
+            memory_limit_mode: MemoryLimitMode::default(),
+            max_exports: settings::max_exports(),
+            max_imports: settings::max_imports(),
+            max_functions: settings::max_functions(),
+            max_module_size: settings::max_module_size(),
+            max_data_segments_size: settings::max_data_segments_size(),
+            max_element_segments: settings::max_element_segments(),
+            max_function_body_size: settings::max_function_body_size(),
+            max_function_locals: settings::max_function_locals(),
+            max_block_depth: settings::max_block_depth(),
+            start_function_mode: StartFunctionMode::default(),
+            max_stack_depth: settings::max_stack_depth(),
+        }
+    }
+}
+
+/// Behavior of the `memory.grow` instructions asking for more pages than
+/// `CondomLimits::max_memory_pages`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum MemoryLimitMode {
+    /// The execution fails with `ABIError::MemoryLimitExceeded`
+    #[default]
+    Trap,
+    /// `memory.grow` returns -1, as in the specification
+    Fail,
+}
+
+/// ABIs an execution may import, by the name under which they are charged in
+/// the gas costs: `assembly_script_set_data` or `abi_set_ds_value` for
+/// instance. Checked when the import object of a module is built, for the
+/// AssemblyScript and the wasmv1 ABIs alike.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AbiFilter {
+    /// Only these ABIs are allowed when set, all of them otherwise
+    pub allow: Option<HashSet<String>>,
+    /// ABIs refused even when they are in `allow`
+    pub deny: HashSet<String>,
+    /// Behavior of the modules importing a refused ABI
+    pub mode: ForbiddenAbiMode,
+}
+
+impl AbiFilter {
+    /// Filter refusing the ABIs of `names`
+    pub fn deny<I: IntoIterator<Item = S>, S: Into<String>>(names: I) -> Self {
+        Self {
+            deny: names.into_iter().map(Into::into).collect(),
+            ..Self::default()
+        }
+    }
+
+    /// Filter allowing only the ABIs of `names`
+    pub fn allow<I: IntoIterator<Item = S>, S: Into<String>>(names: I) -> Self {
+        Self {
+            allow: Some(names.into_iter().map(Into::into).collect()),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_mode(mut self, mode: ForbiddenAbiMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn is_allowed(&self, abi_name: &str) -> bool {
+        !self.deny.contains(abi_name)
+            && self
+                .allow
+                .as_ref()
+                .map_or(true, |allow| allow.contains(abi_name))
+    }
+
+    /// Whether every ABI is allowed
+    pub(crate) fn is_empty(&self) -> bool {
+        self.allow.is_none() && self.deny.is_empty()
+    }
+}
+
+/// Format of the addresses given to the balance ABIs, checked by the
+/// runtime before they reach the interface. The encoding of the addresses
+/// belongs to the node, which configures the checks: the default validator
+/// accepts every address.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AddressValidator {
+    pub min_len: usize,
+    pub max_len: usize,
+    /// The addresses start with one of these prefixes, when there are some
+    pub prefixes: Vec<String>,
+    /// Characters allowed after the prefix, all of them when `None`
+    pub charset: Option<String>,
+}
+
+impl Default for AddressValidator {
+    fn default() -> Self {
+        Self {
+            min_len: 0,
+            max_len: usize::MAX,
+            prefixes: Vec::new(),
+            charset: None,
+        }
+    }
+}
+
+impl AddressValidator {
+    /// Reason why `address` is refused, if it is
+    pub fn validate(&self, address: &str) -> std::result::Result<(), String> {
+        if address.len() < self.min_len || address.len() > self.max_len {
+            return Err(format!(
+                "length {} is not between {} and {}",
+                address.len(),
+                self.min_len,
+                self.max_len
+            ));
+        }
+        let body = if self.prefixes.is_empty() {
+            address
+        } else {
+            // the longest prefix, for the prefixes starting with another one
+            let Some(prefix) = self
+                .prefixes
+                .iter()
+                .filter(|prefix| address.starts_with(prefix.as_str()))
+                .max_by_key(|prefix| prefix.len())
+            else {
+                return Err(format!(
+                    "does not start with one of {}",
+                    self.prefixes.join(", ")
+                ));
+            };
+            &address[prefix.len()..]
+        };
+        if let Some(charset) = &self.charset {
+            if let Some(c) = body.chars().find(|c| !charset.contains(*c)) {
+                return Err(format!("character {:?} is not allowed", c));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Behavior of the modules importing an ABI refused by their `AbiFilter`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ForbiddenAbiMode {
+    /// The ABI is charged as usual when called, then fails with
+    /// `ABIError::ForbiddenAbi`
+    #[default]
+    Stub,
+    /// The module fails to be instantiated
+    LinkError,
+}
+
+/// Handling of the `start` section of the modules
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum StartFunctionMode {
+    /// The start function is metered, its gas being part of the cost of the
+    /// instance creation
+    #[default]
+    Metered,
+    /// Modules with a start function are rejected when loaded
+    Reject,
+}
+
+/// Handling of the floating point instructions, whose NaN payloads differ
+/// between architectures
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FloatMode {
+    /// The NaNs produced by float instructions are canonicalized by the
+    /// compilers
+    #[default]
+    Canonicalize,
+    /// Modules using float types or instructions are rejected when loaded
+    Reject,
+}
+
+/// Handling of the calls re-entering a smart contract of the call stack,
+/// such as A calling B calling back A. The local calls stay at the address of
+/// their caller and are never re-entrant.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Reentrancy {
+    /// Every call is allowed
+    #[default]
+    Allow,
+    /// Calling a smart contract of the call stack fails
+    Deny,
+    /// Calling a function a smart contract of the call stack is executing
+    /// fails, the other functions of that smart contract can be called
+    DenySameFunction,
+}
+
+/// Settings of the runtime other than the gas costs: the limits of the
+/// executions and of the modules they load, and the behaviors of the ABIs.
+/// Given next to the `GasCosts` to the compilations and the executions.
+#[derive(Clone, Debug)]
+pub struct RuntimeConfig {
+    /// Maximum number of nested calls allowed from the top-level execution.
+    pub max_recursion_depth: u32,
+    /// Handling of the re-entrant calls of the AssemblyScript and wasmv1
+    /// ABIs, checked before the interface is told about the call.
+    pub reentrancy: Reentrancy,
+    /// Limits on the datastore entries read and written by the ABIs.
+    pub storage_limits: StorageLimits,
+    /// Limits on the modules loaded by the executions.
+    pub condom_limits: CondomLimits,
+    /// Handling of the floating point instructions of the modules loaded by
+    /// the executions.
+    pub float_mode: FloatMode,
+    /// Wall-clock time after which an execution and its nested calls are
+    /// aborted, checked whenever an ABI is called. Executions that do not call
+    /// ABIs are only bounded by their gas.
+    pub max_execution_time: Option<Duration>,
+    /// Wall-clock time after which the compilation of a module by the
+    /// runtime fails with `ABIError::CompilationTimeout`, nested calls
+    /// included. The compilation cannot be interrupted, it is then left to
+    /// finish on a thread of its own.
+    pub max_compilation_time: Option<Duration>,
+    /// Limits on the events emitted by the executions.
+    pub event_limits: EventLimits,
+    /// Maximum length of the inputs of the base58check ABIs, in bytes.
+    pub max_base58_check_input_len: usize,
+    /// Maximum length of the inputs of the constant-time comparison ABI, in
+    /// bytes.
+    pub max_constant_eq_input_len: usize,
+    /// Maximum number of bytes returned by a single random bytes ABI call.
+    pub max_random_bytes_len: usize,
+    /// Maximum length of the inputs of the digest ABIs, in bytes.
+    pub max_digest_input_len: usize,
+    /// Maximum number of elements of a proof given to the Merkle proof ABI.
+    pub max_merkle_proof_depth: usize,
+    /// Number of threads of the blockchain, the threads of the slots handed
+    /// to the ABIs are below it.
+    pub thread_count: u8,
+    /// Digits of the fractional part of the amounts converted to and from
+    /// strings by the wasmv1 ABIs.
+    pub native_amount_decimals: u32,
+    /// Maximum gas of a deferred call.
+    pub max_deferred_call_gas: u64,
+    /// Maximum gas of an asynchronous message.
+    pub max_async_message_gas: u64,
+    /// Maximum length of a buffer or string read from the guest memory by an
+    /// ABI, in bytes.
+    pub max_abi_read_len: usize,
+    /// Maximum length of a string read from the guest memory by an ABI, in
+    /// UTF-16 code units, checked before it is converted.
+    pub max_string_len: usize,
+    /// Maximum size in bytes of the parameter given to an executed function.
+    pub max_param_size: usize,
+    /// Maximum size in bytes of the value returned by an executed function,
+    /// the nested calls included.
+    pub max_return_size: usize,
+    /// Maximum number of bytes the ABIs of an execution and of its nested
+    /// calls may copy from the host to the guest memories.
+    pub max_host_data_len: usize,
+    /// ABIs the executions may import.
+    pub abi_filter: AbiFilter,
+    /// Checks of the addresses given to the balance ABIs.
+    pub address_validator: AddressValidator,
+    /// Maximum number of addresses of a batched balance query.
+    pub max_balance_batch_len: usize,
+    /// Maximum number of keys of a prefetch hint.
+    pub max_hint_keys: usize,
+    /// Host functions added by the embedder to the imports.
+    pub host_extensions: HostExtensions,
+    /// Accept the modules importing both the AssemblyScript and the wasmv1
+    /// ABIs, for the migration tooling. Refused otherwise.
+    pub allow_mixed_abi_namespaces: bool,
+    /// Accept the creation of smart contracts whose bytecode is not a
+    /// WebAssembly module, for the embedders storing data blobs as contracts.
+    /// Refused otherwise.
+    ///
+    /// Refusing them is a consensus change: the runtime used to create any
+    /// bytecode, so a network which did has to keep this set until all its
+    /// nodes agree on the activation.
+    pub allow_non_wasm_contracts: bool,
+    /// Compile and run the modules to calibrate the gas costs: the operators
+    /// are counted instead of charged, the ABIs are free and the gas is
+    /// unlimited. A module runs in the mode it was compiled with. Set by
+    /// default with the `gas_calibration` feature.
+    pub gas_calibration: bool,
+    /// Whether the top-level executions report the gas used by each called
+    /// smart contract in `Response::gas_breakdown`, off by default to spare
+    /// its allocations.
+    pub collect_gas_breakdown: bool,
+}
+
+impl RuntimeConfig {
+    /// SHA-256 of the settings, the same for equal configurations whatever
+    /// the order the sets of their `AbiFilter` were filled in
+    pub(crate) fn digest(&self) -> [u8; 32] {
+        let sorted = |names: &HashSet<String>| names.iter().collect::<BTreeSet<_>>();
+        // the fields without a set, in their `Debug` form
+        let others = RuntimeConfig {
+            abi_filter: AbiFilter {
+                mode: self.abi_filter.mode,
+                ..AbiFilter::default()
+            },
+            ..self.clone()
+        };
+        let description = format!(
+            "{:?} {:?} {:?}",
+            self.abi_filter.allow.as_ref().map(sorted),
+            sorted(&self.abi_filter.deny),
+            others
+        );
+        Sha256::digest(description).into()
+    }
+}
+
+impl Default for RuntimeConfig {
+    fn default() -> Self {
+        Self {
+            max_recursion_depth: settings::max_recursion_depth(),
+            reentrancy: Reentrancy::default(),
+            storage_limits: StorageLimits::default(),
+            condom_limits: CondomLimits::default(),
+            float_mode: FloatMode::default(),
+            max_execution_time: settings::max_execution_time(),
+            max_compilation_time: settings::max_compilation_time(),
+            event_limits: EventLimits::default(),
+            max_base58_check_input_len: settings::max_base58_check_input_len(),
+            max_constant_eq_input_len: settings::max_constant_eq_input_len(),
+            max_random_bytes_len: settings::max_random_bytes_len(),
+            max_digest_input_len: settings::max_digest_input_len(),
+            max_merkle_proof_depth: settings::max_merkle_proof_depth(),
+            thread_count: settings::THREAD_COUNT,
+            native_amount_decimals: settings::NATIVE_AMOUNT_DECIMALS,
+            max_deferred_call_gas: settings::max_deferred_call_gas(),
+            max_async_message_gas: settings::max_async_message_gas(),
+            max_abi_read_len: settings::max_abi_read_len(),
+            max_string_len: settings::max_string_len(),
+            max_param_size: settings::max_param_size(),
+            max_return_size: settings::max_return_size(),
+            max_host_data_len: settings::max_host_data_len(),
+            abi_filter: AbiFilter::default(),
+            address_validator: AddressValidator::default(),
+            max_balance_batch_len: settings::max_balance_batch_len(),
+            max_hint_keys: settings::max_hint_keys(),
+            host_extensions: HostExtensions::default(),
+            allow_mixed_abi_namespaces: false,
+            allow_non_wasm_contracts: false,
+            gas_calibration: cfg!(feature = "gas_calibration"),
+            collect_gas_breakdown: false,
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct GasCosts {
     pub(crate) abi_costs: HashMap<String, u64>,
-    pub(crate) operator_cost: u64,
+    /// Cost of the wasm operators missing from `operator_costs`.
+    pub operator_cost: u64,
+    /// Cost of the wasm operators by name, `I32Add` for instance, charged the
+    /// same whatever the compiler. Empty by default, every operator then
+    /// costs `operator_cost`.
+    pub operator_costs: HashMap<String, u64>,
     pub(crate) launch_cost: u64,
     pub cl_compilation_cost: u64,
     pub sp_compilation_cost: u64,
     pub max_instance_cost: u64,
+    /// Gas credited back for each byte of datastore entry deleted.
+    pub storage_refund_per_byte: u64,
+    /// Maximum share of the gas spent by an execution, in percent, that the
+    /// storage refunds of its whole call tree can credit back.
+    pub max_storage_refund_percent: u64,
+    /// Sanity bound of the ABI costs checked by `validate`.
+    pub max_abi_cost: u64,
+    /// Gas per UTF-16 code unit of a string read from the guest memory by an
+    /// ABI, charged before it is converted.
+    pub string_code_unit_cost: u64,
+    /// Gas per byte of the value returned by an executed function, charged
+    /// before the value is copied out of the guest memory.
+    pub return_byte_cost: u64,
+    /// Gas charged to the caller for each byte of the bytecode of a called
+    /// smart contract compiled for the call.
+    pub compilation_cost_per_byte: u64,
+    /// Gas charged for each byte of the bytecode of a created smart contract,
+    /// on top of the cost of the creation ABI.
+    pub sc_creation_byte_cost: u64,
+    /// Gas charged for each 64KiB page requested by `memory.grow`, by the
+    /// top-level execution and the nested calls alike.
+    pub memory_page_cost: u64,
+    /// Gas charged for each byte handled by `memory.copy`, `memory.fill` and
+    /// `memory.init`, on top of their operator cost.
+    pub bulk_memory_byte_cost: u64,
+}
+
+/// Read the ABI and wasm operator cost files
+#[cfg(feature = "json")]
+fn read_cost_files(
+    abi_cost_file: &Path,
+    wasm_abi_file: &Path,
+) -> std::result::Result<(HashMap<String, u64>, HashMap<String, u64>), GasCostsError> {
+    let read = |path: &Path| -> std::result::Result<HashMap<String, u64>, GasCostsError> {
+        let file_error = |error: String| GasCostsError::File {
+            path: path.display().to_string(),
+            error,
+        };
+        let content = std::fs::read_to_string(path).map_err(|e| file_error(e.to_string()))?;
+        serde_json::from_str(&content).map_err(|e| file_error(e.to_string()))
+    };
+    Ok((read(abi_cost_file)?, read(wasm_abi_file)?))
+}
+
+/// Costs of the bytes, entries and comparisons processed by the ABIs, the
+/// `<abi>_per_byte`, `<abi>_per_entry` and `<abi>_per_comparison` entries of
+/// `GasCosts::abi_costs`. The ones missing from the cost files take these
+/// values, an ABI never processes its input for free.
+const DEFAULT_UNIT_COSTS: &[(&str, u64)] = &[
+    ("abi_get_op_keys_per_comparison", 1),
+    ("abi_hash_keccak256_per_byte", 1),
+    ("abi_native_amount_from_string_per_byte", 1),
+    ("abi_native_amount_to_string_per_byte", 1),
+    ("abi_set_bytecode_per_byte", 1),
+    ("abi_sha256_per_byte", 1),
+    ("abi_sha3_256_per_byte", 1),
+    ("assembly_script_base58_check_decode_per_byte", 3),
+    ("assembly_script_base58_check_encode_per_byte", 3),
+    ("assembly_script_bls_aggregate_public_keys_per_entry", 100),
+    ("assembly_script_bls_aggregate_verify_per_byte", 1),
+    ("assembly_script_bls_aggregate_verify_per_entry", 1500),
+    ("assembly_script_bls_verify_per_byte", 1),
+    ("assembly_script_constant_eq_per_byte", 1),
+    ("assembly_script_create_sc_with_data_per_byte", 1),
+    ("assembly_script_generate_event_binary_per_byte", 1),
+    ("assembly_script_get_balances_per_entry", 41),
+    ("assembly_script_get_bytecode_for_per_byte", 1),
+    ("assembly_script_get_bytecode_per_byte", 1),
+    ("assembly_script_get_call_stack_per_byte", 1),
+    ("assembly_script_get_data_chunk_per_byte", 1),
+    ("assembly_script_get_keys_paged_per_entry", 10),
+    ("assembly_script_get_keys_values_per_byte", 1),
+    ("assembly_script_get_keys_values_per_entry", 85),
+    ("assembly_script_get_last_event_per_byte", 1),
+    ("assembly_script_get_op_keys_per_comparison", 1),
+    ("assembly_script_get_op_keys_prefix_per_comparison", 1),
+    ("assembly_script_get_op_keys_prefix_per_entry", 10),
+    ("assembly_script_hint_keys_per_entry", 1),
+    ("assembly_script_keccak256_hash_per_byte", 1),
+    ("assembly_script_random_bytes_per_byte", 1),
+    ("assembly_script_set_bytecode_for_per_byte", 1),
+    ("assembly_script_set_bytecode_per_byte", 1),
+    ("assembly_script_set_data_batch_per_byte", 1),
+    ("assembly_script_sha256_per_byte", 1),
+    ("assembly_script_sha3_256_per_byte", 1),
+    ("assembly_script_verify_merkle_proof_per_entry", 83),
+];
+
+/// Suffixes of the unit costs of `DEFAULT_UNIT_COSTS`
+const UNIT_COST_SUFFIXES: &[&str] = &["_per_byte", "_per_entry", "_per_comparison"];
+
+/// Whether the ABI cost `name` is a unit cost, which is not rounded
+fn is_unit_cost(name: &str) -> bool {
+    UNIT_COST_SUFFIXES
+        .iter()
+        .any(|suffix| name.ends_with(suffix))
+}
+
+/// Add the unit costs missing from `abi_costs` with their default value
+fn add_default_unit_costs(abi_costs: &mut HashMap<String, u64>) {
+    for (name, cost) in DEFAULT_UNIT_COSTS {
+        abi_costs.entry(name.to_string()).or_insert(*cost);
+    }
+}
+
+/// Costs every way of building the gas costs has to give
+struct BaseCosts {
+    operator: u64,
+    launch: u64,
+    cl_compilation: u64,
+    sp_compilation: u64,
+    max_instance: u64,
 }
 
 impl GasCosts {
+    /// Gas costs of `abi_costs` and `base`, the unit costs missing from
+    /// `abi_costs` and the other costs being set to their default
+    fn with_base_costs(mut abi_costs: HashMap<String, u64>, base: BaseCosts) -> Self {
+        add_default_unit_costs(&mut abi_costs);
+        Self {
+            abi_costs,
+            operator_cost: base.operator,
+            operator_costs: HashMap::new(),
+            launch_cost: base.launch,
+            cl_compilation_cost: base.cl_compilation,
+            sp_compilation_cost: base.sp_compilation,
+            max_instance_cost: base.max_instance,
+            storage_refund_per_byte: settings::storage_refund_per_byte(),
+            max_storage_refund_percent: settings::max_storage_refund_percent(),
+            string_code_unit_cost: settings::string_code_unit_cost(),
+            return_byte_cost: settings::return_byte_cost(),
+            compilation_cost_per_byte: settings::compilation_cost_per_byte(),
+            sc_creation_byte_cost: settings::sc_creation_byte_cost(),
+            memory_page_cost: settings::memory_page_cost(),
+            bulk_memory_byte_cost: settings::bulk_memory_byte_cost(),
+            max_abi_cost: settings::max_abi_cost(),
+        }
+    }
+
+    /// Load the gas costs from the JSON ABI and wasm operator cost files
+    #[cfg(feature = "json")]
     pub fn new(abi_cost_file: PathBuf, wasm_abi_file: PathBuf) -> Result<Self> {
-        let abi_cost_file = std::fs::read_to_string(abi_cost_file)?;
-        let mut abi_costs: HashMap<String, u64> = serde_json::from_str(&abi_cost_file)?;
-        abi_costs.iter_mut().for_each(|(_, v)| {
-            let unit_digit = *v % 10;
-            if unit_digit > 5 {
-                *v += 10 - unit_digit;
-            } else {
-                *v -= unit_digit;
-            }
-        });
-        let wasm_abi_file = std::fs::read_to_string(wasm_abi_file)?;
-        let wasm_costs: HashMap<String, u64> = serde_json::from_str(&wasm_abi_file)?;
-        Ok(Self {
-            operator_cost: wasm_costs.values().copied().sum::<u64>() / wasm_costs.len() as u64,
-            launch_cost: *abi_costs
+        let (mut abi_costs, wasm_costs) = read_cost_files(&abi_cost_file, &wasm_abi_file)?;
+        // Unit costs are too small to be rounded
+        abi_costs
+            .iter_mut()
+            .filter(|(k, _)| !is_unit_cost(k))
+            .for_each(|(_, v)| {
+                let unit_digit = *v % 10;
+                if unit_digit > 5 {
+                    *v += 10 - unit_digit;
+                } else {
+                    *v -= unit_digit;
+                }
+            });
+        let base = BaseCosts {
+            operator: wasm_costs.values().copied().sum::<u64>() / wasm_costs.len() as u64,
+            launch: *abi_costs
                 .get("launch")
                 .ok_or_else(|| anyhow!("launch cost not found in ABI gas cost file."))?,
-            cl_compilation_cost: *abi_costs
+            cl_compilation: *abi_costs
                 .get("cl_compilation")
                 .ok_or_else(|| anyhow!("cl_compilation cost not found in ABI gas cost file."))?,
-            sp_compilation_cost: *abi_costs
+            sp_compilation: *abi_costs
                 .get("sp_compilation")
                 .ok_or_else(|| anyhow!("sp_compilation cost not found in ABI gas cost file."))?,
-            max_instance_cost: *abi_costs
+            max_instance: *abi_costs
                 .get("max_instance")
                 .ok_or_else(|| anyhow!("max_instance cost not found in ABI gas cost file."))?,
-            abi_costs,
+        };
+        Ok(Self::with_base_costs(abi_costs, base))
+    }
+
+    /// Cost charged by the metering for `operator`, unknown operators cost
+    /// `operator_cost`
+    pub(crate) fn cost_of_operator(&self, operator: &Operator) -> u64 {
+        self.operator_costs
+            .get(operator_field_str(operator))
+            .copied()
+            .unwrap_or(self.operator_cost)
+    }
+
+    /// SHA-256 of the costs, the same for equal gas costs whatever the order
+    /// their maps were filled in
+    pub(crate) fn digest(&self) -> [u8; 32] {
+        let sorted = |costs: &HashMap<String, u64>| costs.iter().collect::<BTreeMap<_, _>>();
+        // the fields without a map, in their `Debug` form
+        let others = GasCosts {
+            abi_costs: HashMap::new(),
+            operator_costs: HashMap::new(),
+            ..self.clone()
+        };
+        let description = format!(
+            "{:?} {:?} {:?}",
+            sorted(&self.abi_costs),
+            sorted(&self.operator_costs),
+            others
+        );
+        Sha256::digest(description).into()
+    }
+
+    /// Build gas costs cost by cost, see `GasCostsBuilder`
+    pub fn builder() -> GasCostsBuilder {
+        GasCostsBuilder::default()
+    }
+
+    /// Load the gas costs from the ABI and wasm operator cost files like
+    /// `new`, reporting every missing, zero or too large cost at once.
+    ///
+    /// ABI costs are rounded to the ten like in `new`, apart from the ones
+    /// below ten which rounding would make free.
+    #[cfg(feature = "json")]
+    pub fn from_files(
+        abi_cost_file: PathBuf,
+        wasm_abi_file: PathBuf,
+    ) -> std::result::Result<Self, GasCostsError> {
+        let (abi_costs, wasm_costs) = read_cost_files(&abi_cost_file, &wasm_abi_file)?;
+        let mut builder = Self::builder();
+        if !wasm_costs.is_empty() {
+            builder = builder
+                .operator_cost(wasm_costs.values().copied().sum::<u64>() / wasm_costs.len() as u64);
+        }
+        for (name, cost) in abi_costs {
+            builder = match name.as_str() {
+                "launch" => builder.launch_cost(cost),
+                "cl_compilation" => builder.cl_compilation_cost(cost),
+                "sp_compilation" => builder.sp_compilation_cost(cost),
+                "max_instance" => builder.max_instance_cost(cost),
+                _ if is_unit_cost(&name) || cost < 10 => builder.abi_cost(name, cost),
+                _ => {
+                    let unit_digit = cost % 10;
+                    let rounded = if unit_digit > 5 {
+                        cost + 10 - unit_digit
+                    } else {
+                        cost - unit_digit
+                    };
+                    builder.abi_cost(name, rounded)
+                }
+            };
+        }
+        builder.build()
+    }
+
+    /// Names of the ABIs implemented by the runtime, the AssemblyScript ones
+    /// first. Each of them needs a cost, their unit costs, `<abi>_per_byte`
+    /// and the like, are not listed.
+    pub fn abi_names() -> Vec<&'static str> {
+        crate::as_execution::ABI_NAMES
+            .iter()
+            .chain(crate::wasmv1_execution::ABI_NAMES)
+            .copied()
+            .collect()
+    }
+
+    /// Unit cost `name` of an ABI, `<abi>_per_byte` for instance, taking its
+    /// default value when missing from `abi_costs`. `None` when the ABI has
+    /// no such cost.
+    pub(crate) fn unit_cost(&self, name: &str) -> Option<u64> {
+        self.abi_costs.get(name).copied().or_else(|| {
+            DEFAULT_UNIT_COSTS
+                .iter()
+                .find(|(unit, _)| *unit == name)
+                .map(|(_, cost)| *cost)
         })
     }
+
+    /// Check that every ABI of `abi_names` and every unit cost has a cost
+    /// and that no cost is zero or, for the ABI costs, above `max_abi_cost`
+    pub fn validate(&self) -> std::result::Result<(), GasCostsError> {
+        let missing: Vec<String> = Self::abi_names()
+            .into_iter()
+            .chain(DEFAULT_UNIT_COSTS.iter().map(|(name, _)| *name))
+            .filter(|name| !self.abi_costs.contains_key(*name))
+            .map(String::from)
+            .collect();
+        let mut zero: Vec<String> = [
+            ("operator", self.operator_cost),
+            ("launch", self.launch_cost),
+            ("cl_compilation", self.cl_compilation_cost),
+            ("sp_compilation", self.sp_compilation_cost),
+            ("max_instance", self.max_instance_cost),
+        ]
+        .into_iter()
+        .filter(|(_, cost)| *cost == 0)
+        .map(|(name, _)| name.to_string())
+        .collect();
+        let mut abi_zero = Vec::new();
+        let mut too_large = Vec::new();
+        for (name, cost) in &self.abi_costs {
+            if *cost == 0 {
+                abi_zero.push(name.clone());
+            } else if *cost > self.max_abi_cost {
+                too_large.push(name.clone());
+            }
+        }
+        abi_zero.sort();
+        zero.extend(abi_zero);
+        too_large.sort();
+        if missing.is_empty() && zero.is_empty() && too_large.is_empty() {
+            Ok(())
+        } else {
+            Err(GasCostsError::Invalid {
+                missing,
+                zero,
+                too_large,
+                max: self.max_abi_cost,
+            })
+        }
+    }
+}
+
+/// Builder of validated `GasCosts`.
+///
+/// The costs of the cost files have to be set, the other costs take their
+/// default values and can be changed on the built `GasCosts`. The limits are
+/// in `RuntimeConfig`.
+#[derive(Clone, Debug)]
+pub struct GasCostsBuilder {
+    abi_costs: HashMap<String, u64>,
+    operator_cost: Option<u64>,
+    operator_costs: HashMap<String, u64>,
+    launch_cost: Option<u64>,
+    cl_compilation_cost: Option<u64>,
+    sp_compilation_cost: Option<u64>,
+    max_instance_cost: Option<u64>,
+    max_abi_cost: u64,
+}
+
+impl Default for GasCostsBuilder {
+    fn default() -> Self {
+        Self {
+            abi_costs: HashMap::new(),
+            operator_cost: None,
+            operator_costs: HashMap::new(),
+            launch_cost: None,
+            cl_compilation_cost: None,
+            sp_compilation_cost: None,
+            max_instance_cost: None,
+            max_abi_cost: settings::max_abi_cost(),
+        }
+    }
+}
+
+impl GasCostsBuilder {
+    /// Cost of an ABI, or of one of its unit costs like `<abi>_per_byte`.
+    /// The unit costs left unset take their default value.
+    pub fn abi_cost(mut self, name: impl Into<String>, cost: u64) -> Self {
+        self.abi_costs.insert(name.into(), cost);
+        self
+    }
+
+    /// Cost of the wasm operators without a cost of their own
+    pub fn operator_cost(mut self, cost: u64) -> Self {
+        self.operator_cost = Some(cost);
+        self
+    }
+
+    /// Cost of the wasm operator `name`, `I32Add` for instance
+    pub fn operator_cost_for(mut self, name: impl Into<String>, cost: u64) -> Self {
+        self.operator_costs.insert(name.into(), cost);
+        self
+    }
+
+    /// Cost of launching the virtual machine
+    pub fn launch_cost(mut self, cost: u64) -> Self {
+        self.launch_cost = Some(cost);
+        self
+    }
+
+    /// Cost of a compilation with Cranelift
+    pub fn cl_compilation_cost(mut self, cost: u64) -> Self {
+        self.cl_compilation_cost = Some(cost);
+        self
+    }
+
+    /// Cost of a compilation with Singlepass
+    pub fn sp_compilation_cost(mut self, cost: u64) -> Self {
+        self.sp_compilation_cost = Some(cost);
+        self
+    }
+
+    /// Maximum cost of the instantiation of a module
+    pub fn max_instance_cost(mut self, cost: u64) -> Self {
+        self.max_instance_cost = Some(cost);
+        self
+    }
+
+    /// Sanity bound of the ABI costs, `settings::max_abi_cost` by default
+    pub fn max_abi_cost(mut self, max: u64) -> Self {
+        self.max_abi_cost = max;
+        self
+    }
+
+    /// Build the gas costs, failing with every unset, zero or too large cost
+    pub fn build(self) -> std::result::Result<GasCosts, GasCostsError> {
+        let unset: Vec<String> = [
+            ("operator", self.operator_cost),
+            ("launch", self.launch_cost),
+            ("cl_compilation", self.cl_compilation_cost),
+            ("sp_compilation", self.sp_compilation_cost),
+            ("max_instance", self.max_instance_cost),
+        ]
+        .into_iter()
+        .filter(|(_, cost)| cost.is_none())
+        .map(|(name, _)| name.to_string())
+        .collect();
+        let base = BaseCosts {
+            operator: self.operator_cost.unwrap_or_default(),
+            launch: self.launch_cost.unwrap_or_default(),
+            cl_compilation: self.cl_compilation_cost.unwrap_or_default(),
+            sp_compilation: self.sp_compilation_cost.unwrap_or_default(),
+            max_instance: self.max_instance_cost.unwrap_or_default(),
+        };
+        let gas_costs = GasCosts {
+            operator_costs: self.operator_costs,
+            max_abi_cost: self.max_abi_cost,
+            ..GasCosts::with_base_costs(self.abi_costs, base)
+        };
+        match gas_costs.validate() {
+            Ok(()) if unset.is_empty() => Ok(gas_costs),
+            Ok(()) => Err(GasCostsError::Invalid {
+                missing: unset,
+                zero: Vec::new(),
+                too_large: Vec::new(),
+                max: gas_costs.max_abi_cost,
+            }),
+            // the unset costs are reported as missing rather than zero
+            Err(GasCostsError::Invalid {
+                missing,
+                zero,
+                too_large,
+                max,
+            }) => Err(GasCostsError::Invalid {
+                zero: zero
+                    .into_iter()
+                    .filter(|name| !unset.contains(name))
+                    .collect(),
+                missing: unset.into_iter().chain(missing).collect(),
+                too_large,
+                max,
+            }),
+            Err(err) => Err(err),
+        }
+    }
 }
 
 #[cfg(any(test, feature = "gas_calibration", feature = "testing"))]
@@ -214,23 +1196,43 @@ impl Default for GasCosts {
     fn default() -> Self {
         let mut abi_costs = HashMap::new();
         abi_costs.insert(String::from("assembly_script_address_from_public_key"), 147);
+        abi_costs.insert(
+            String::from("assembly_script_address_from_public_key_bytes"),
+            147,
+        );
+        abi_costs.insert(String::from("assembly_script_public_key_from_string"), 106);
         abi_costs.insert(String::from("assembly_script_validate_address"), 4);
         abi_costs.insert(String::from("assembly_script_append_data"), 162);
         abi_costs.insert(String::from("assembly_script_append_data_for"), 200);
         abi_costs.insert(String::from("assembly_script_call"), 30466);
         abi_costs.insert(String::from("assembly_script_create_sc"), 160);
+        abi_costs.insert(String::from("assembly_script_create_sc_with_data"), 160);
+        abi_costs.insert(String::from("assembly_script_create_sc_with_seed"), 160);
+        abi_costs.insert(String::from("assembly_script_predict_sc_address"), 100);
         abi_costs.insert(String::from("assembly_script_delete_data"), 78);
         abi_costs.insert(String::from("assembly_script_delete_data_for"), 120);
         abi_costs.insert(String::from("assembly_script_generate_event"), 36);
+        abi_costs.insert(String::from("assembly_script_generate_event_binary"), 36);
+        abi_costs.insert(String::from("assembly_script_get_event_count"), 9);
+        abi_costs.insert(String::from("assembly_script_get_last_event"), 36);
         abi_costs.insert(String::from("assembly_script_get_balance"), 4);
         abi_costs.insert(String::from("assembly_script_get_balance_for"), 41);
+        abi_costs.insert(String::from("assembly_script_get_balances"), 20);
+        abi_costs.insert(String::from("assembly_script_hint_keys"), 10);
         abi_costs.insert(String::from("assembly_script_get_call_coins"), 9);
+        abi_costs.insert(String::from("assembly_script_get_spent_coins"), 9);
         abi_costs.insert(String::from("assembly_script_get_call_stack"), 56);
         abi_costs.insert(String::from("assembly_script_get_current_slot"), 9);
+        abi_costs.insert(String::from("assembly_script_slot_to_timestamp"), 9);
+        abi_costs.insert(String::from("assembly_script_timestamp_to_slot"), 9);
         abi_costs.insert(String::from("assembly_script_get_data"), 85);
+        abi_costs.insert(String::from("assembly_script_get_data_chunk"), 85);
+        abi_costs.insert(String::from("assembly_script_get_data_len"), 85);
         abi_costs.insert(String::from("assembly_script_get_data_for"), 139);
         abi_costs.insert(String::from("assembly_script_get_keys"), 26);
         abi_costs.insert(String::from("assembly_script_get_keys_for"), 48);
+        abi_costs.insert(String::from("assembly_script_get_keys_paged"), 26);
+        abi_costs.insert(String::from("assembly_script_get_keys_values"), 26);
         abi_costs.insert(String::from("assembly_script_get_op_data"), 71);
         abi_costs.insert(String::from("assembly_script_get_op_keys"), 138);
         abi_costs.insert(String::from("assembly_script_get_op_keys_prefix"), 138);
@@ -239,19 +1241,60 @@ impl Default for GasCosts {
         abi_costs.insert(String::from("assembly_script_get_time"), 4);
         abi_costs.insert(String::from("assembly_script_has_data"), 69);
         abi_costs.insert(String::from("assembly_script_has_data_for"), 115);
+        abi_costs.insert(String::from("assembly_script_get_storage_cost"), 50);
+        abi_costs.insert(String::from("assembly_script_reserve_storage"), 100);
         abi_costs.insert(String::from("assembly_script_has_op_key"), 78);
         abi_costs.insert(String::from("assembly_script_hash"), 83);
         abi_costs.insert(String::from("assembly_script_hash_sha256"), 83);
+        abi_costs.insert(String::from("assembly_script_base58_check_encode"), 20);
+        abi_costs.insert(String::from("assembly_script_base58_check_decode"), 20);
+        abi_costs.insert(String::from("assembly_script_constant_eq"), 12);
+        abi_costs.insert(
+            String::from("assembly_script_checked_add_native_amount"),
+            11,
+        );
+        abi_costs.insert(
+            String::from("assembly_script_checked_sub_native_amount"),
+            11,
+        );
+        abi_costs.insert(
+            String::from("assembly_script_checked_mul_native_amount"),
+            11,
+        );
+        abi_costs.insert(
+            String::from("assembly_script_checked_div_native_amount"),
+            11,
+        );
+        abi_costs.insert(
+            String::from("assembly_script_native_amount_from_string"),
+            11,
+        );
+        abi_costs.insert(String::from("assembly_script_native_amount_to_string"), 11);
         abi_costs.insert(String::from("assembly_script_keccak256_hash"), 83);
+        abi_costs.insert(String::from("assembly_script_sha256"), 83);
+        abi_costs.insert(String::from("assembly_script_verify_merkle_proof"), 83);
+        abi_costs.insert(String::from("assembly_script_sha3_256"), 83);
         abi_costs.insert(String::from("assembly_script_print"), 35);
         abi_costs.insert(String::from("assembly_script_send_message"), 316);
+        abi_costs.insert(String::from("assembly_script_send_async_message"), 316);
+        abi_costs.insert(String::from("assembly_script_deferred_call_register"), 316);
+        abi_costs.insert(String::from("assembly_script_deferred_call_exists"), 60);
+        abi_costs.insert(String::from("assembly_script_deferred_call_cancel"), 100);
+        abi_costs.insert(String::from("assembly_script_deferred_call_quote"), 60);
         abi_costs.insert(String::from("assembly_script_get_origin_operation_id"), 200);
         abi_costs.insert(String::from("assembly_script_set_bytecode"), 74);
         abi_costs.insert(String::from("assembly_script_set_bytecode_for"), 129);
         abi_costs.insert(String::from("assembly_script_set_data"), 158);
         abi_costs.insert(String::from("assembly_script_set_data_for"), 165);
+        abi_costs.insert(String::from("assembly_script_set_data_batch"), 158);
         abi_costs.insert(String::from("assembly_script_signature_verify"), 98);
         abi_costs.insert(String::from("assembly_script_evm_signature_verify"), 264);
+        abi_costs.insert(String::from("assembly_script_bls_verify"), 3000);
+        abi_costs.insert(String::from("assembly_script_bls_aggregate_verify"), 1500);
+        abi_costs.insert(
+            String::from("assembly_script_bls_aggregate_public_keys"),
+            50,
+        );
         abi_costs.insert(
             String::from("assembly_script_evm_get_address_from_pubkey"),
             11,
@@ -263,10 +1306,20 @@ impl Default for GasCosts {
         abi_costs.insert(String::from("assembly_script_is_address_eoa"), 11);
         abi_costs.insert(String::from("assembly_script_transfer_coins"), 62);
         abi_costs.insert(String::from("assembly_script_transfer_coins_for"), 102);
+        abi_costs.insert(String::from("assembly_script_transfer_coins_checked"), 62);
         abi_costs.insert(String::from("assembly_script_unsafe_random"), 11);
+        abi_costs.insert(String::from("assembly_script_get_random_seed"), 11);
+        abi_costs.insert(String::from("assembly_script_random_bytes"), 20);
         abi_costs.insert(String::from("assembly_script_call"), 11);
+        abi_costs.insert(String::from("assembly_script_try_call"), 11);
+        abi_costs.insert(String::from("assembly_script_call_with_gas_limit"), 11);
         abi_costs.insert(String::from("assembly_script_local_call"), 11);
+        abi_costs.insert(String::from("assembly_script_local_call_self"), 11);
         abi_costs.insert(String::from("assembly_script_local_execution"), 11);
+        abi_costs.insert(
+            String::from("assembly_script_local_execution_with_coins"),
+            11,
+        );
         abi_costs.insert(String::from("assembly_script_get_bytecode"), 11);
         abi_costs.insert(String::from("assembly_script_get_bytecode_for"), 11);
         abi_costs.insert(String::from("assembly_script_caller_has_write_access"), 11);
@@ -281,19 +1334,40 @@ impl Default for GasCosts {
         abi_costs.insert(String::from("assembly_script_console_error"), 36);
         abi_costs.insert(String::from("assembly_script_trace"), 36);
         abi_costs.insert(String::from("assembly_script_chain_id"), 9);
-        Self {
-            abi_costs,
-            operator_cost: 1,
-            launch_cost: 10_000,
-            sp_compilation_cost: 314_000_000,
-            cl_compilation_cost: 745_000_000,
-            max_instance_cost: 2_100_000,
+        abi_costs.insert(String::from("assembly_script_runtime_version"), 9);
+        abi_costs.insert(String::from("assembly_script_get_current_period"), 9);
+        abi_costs.insert(String::from("assembly_script_get_current_thread"), 9);
+        abi_costs.insert(String::from("abi_chain_id"), 9);
+        // the wasmv1 ABIs are not calibrated yet
+        for name in crate::wasmv1_execution::ABI_NAMES {
+            abi_costs.entry(name.to_string()).or_insert(11);
         }
+        Self::with_base_costs(
+            abi_costs,
+            BaseCosts {
+                operator: 1,
+                launch: 10_000,
+                cl_compilation: 745_000_000,
+                sp_compilation: 314_000_000,
+                max_instance: 2_100_000,
+            },
+        )
     }
 }
 
+/// Chain id returned by the interfaces which do not implement `chain_id`
+pub const UNKNOWN_CHAIN_ID: u64 = u64::MAX;
+
 #[allow(unused_variables)]
 pub trait Interface: Send + Sync + InterfaceClone {
+    /// Version of this trait the interface implements, checked against
+    /// `MIN_INTERFACE_VERSION..=INTERFACE_VERSION` before every execution
+    /// started by the embedder. Defaults to 0, which is refused: an interface
+    /// has to report the version it was written for.
+    fn interface_version(&self) -> u32 {
+        0
+    }
+
     /// Prepare the execution of a module at the given address and transfer a
     /// given amount of coins
     fn init_call(&self, address: &str, raw_coins: u64) -> Result<Vec<u8>>;
@@ -303,9 +1377,61 @@ pub trait Interface: Send + Sync + InterfaceClone {
 
     fn init_call_wasmv1(&self, address: &str, raw_coins: NativeAmount) -> Result<Vec<u8>>;
 
+    /// Same as `init_call`, returning the hash and the length of the bytecode
+    /// at the given address instead of the bytecode itself.
+    ///
+    /// The runtime then looks for its compiled module with
+    /// `get_cached_module` and only fetches the bytecode with
+    /// `raw_get_bytecode_for` when it is missing. Defaults to `None`, the
+    /// runtime then calls `init_call`.
+    ///
+    /// The compilation of the callee is charged by the length returned, the
+    /// module being cached or not, so that the gas of the call is the same on
+    /// every node. It must be the length of the bytecode `init_call` would
+    /// return, which is checked when the bytecode is fetched.
+    fn init_call_hash(
+        &self,
+        address: &str,
+        raw_coins: u64,
+    ) -> Result<Option<(BytecodeHash, usize)>> {
+        Ok(None)
+    }
+
+    /// Compiled module of the bytecode identified by `hash` for `compiler`
+    /// if the interface has one at hand. Must neither compile nor charge the
+    /// compilation, which only `get_module_with_compiler` does.
+    ///
+    /// Defaults to `None`
+    fn get_cached_module(
+        &self,
+        hash: &BytecodeHash,
+        compiler: Compiler,
+    ) -> Result<Option<RuntimeModule>> {
+        Ok(None)
+    }
+
     /// Finish a call
     fn finish_call(&self) -> Result<()>;
 
+    /// Finish a call made through `assembly_script_try_call`.
+    /// The changes of the callee must be rolled back if `success` is false,
+    /// the default implementation only calls `finish_call`.
+    fn finish_try_call(&self, success: bool) -> Result<()> {
+        self.finish_call()
+    }
+
+    /// Prepare a local execution and debit the given amount of coins from the
+    /// caller
+    fn init_local_call(&self, raw_coins: u64) -> Result<()> {
+        bail!("unimplemented function init_local_call in interface")
+    }
+
+    /// Finish a local execution prepared with `init_local_call`.
+    /// The coins must be credited back to the caller if `success` is false.
+    fn finish_local_call(&self, success: bool) -> Result<()> {
+        bail!("unimplemented function finish_local_call in interface")
+    }
+
     /// Get the SCE ledger balance for the current address.
     /// Defaults to zero if the address is not found.
     fn get_balance(&self) -> Result<u64>;
@@ -320,6 +1446,32 @@ pub trait Interface: Send + Sync + InterfaceClone {
     /// target address.
     fn transfer_coins(&self, to_address: &str, raw_amount: u64) -> Result<()>;
 
+    /// Same as `transfer_coins`, the failures the caller can handle are
+    /// returned as a `TransferError` instead of an error.
+    ///
+    /// The default implementation checks the address and the balances with
+    /// the other methods of the interface before calling `transfer_coins`.
+    fn transfer_coins_checked(
+        &self,
+        to_address: &str,
+        raw_amount: u64,
+    ) -> Result<Result<(), TransferError>> {
+        if !self.validate_address(to_address)? {
+            return Ok(Err(TransferError::InvalidAddress));
+        }
+        if self.get_balance()? < raw_amount {
+            return Ok(Err(TransferError::InsufficientBalance));
+        }
+        if self
+            .get_balance_for(to_address)?
+            .checked_add(raw_amount)
+            .is_none()
+        {
+            return Ok(Err(TransferError::Overflow));
+        }
+        self.transfer_coins(to_address, raw_amount).map(Ok)
+    }
+
     /// Transfer an amount from the specified address to a target address.
     fn transfer_coins_for(
         &self,
@@ -353,11 +1505,43 @@ pub trait Interface: Send + Sync + InterfaceClone {
     /// rights.
     fn raw_set_bytecode_for(&self, address: &str, bytecode: &[u8]) -> Result<()>;
 
+    /// Drop the compiled modules cached for the bytecode of `address`, or of
+    /// the current address if `None`. Called by the runtime right before it
+    /// replaces that bytecode, while the interface can still resolve it.
+    ///
+    /// Defaults to doing nothing
+    fn invalidate_cached_module(&self, address: Option<&str>) -> Result<()> {
+        Ok(())
+    }
+
     fn set_bytecode_wasmv1(&self, bytecode: &[u8], address: Option<String>) -> Result<()>;
 
     /// Requires a new address that contains the sent &[u8]
     fn create_module(&self, module: &[u8]) -> Result<String>;
 
+    /// Create a smart contract with the given datastore entries.
+    /// Either the contract is created with every entry written or it is not
+    /// created at all.
+    fn create_module_with_datastore(
+        &self,
+        module: &[u8],
+        datastore: &[(Vec<u8>, Vec<u8>)],
+    ) -> Result<String> {
+        bail!("unimplemented function create_module_with_datastore in interface")
+    }
+
+    /// Address `create_module_with_seed` would give to a smart contract
+    /// created by the current address with `seed`, without creating it
+    fn predict_created_address(&self, seed: &[u8]) -> Result<String> {
+        bail!("unimplemented function predict_created_address in interface")
+    }
+
+    /// Create a smart contract at the address derived from `seed` and the
+    /// current address, the one `predict_created_address` returns
+    fn create_module_with_seed(&self, module: &[u8], seed: &[u8]) -> Result<String> {
+        bail!("unimplemented function create_module_with_seed in interface")
+    }
+
     /// Print function for examples
     fn print(&self, message: &str) -> Result<()>;
 
@@ -369,6 +1553,37 @@ pub trait Interface: Send + Sync + InterfaceClone {
     /// Will only return keys with a given prefix if provided in args
     fn get_keys_for(&self, address: &str, prefix: Option<&[u8]>) -> Result<BTreeSet<Vec<u8>>>;
 
+    /// Return up to `limit` datastore keys, in order, with the given prefix if
+    /// provided, and the cursor of the next page, `None` once every key was
+    /// returned. The page starts after the position given by `cursor`, a
+    /// cursor returned by a previous call that the runtime does not
+    /// interpret, or at the first key without one.
+    ///
+    /// Defaults to paging the keys of `get_keys`, the cursor being the last
+    /// key of the page.
+    fn get_keys_paged(
+        &self,
+        prefix: Option<&[u8]>,
+        cursor: Option<&[u8]>,
+        limit: usize,
+    ) -> Result<(Vec<Vec<u8>>, Option<Vec<u8>>)> {
+        let keys = self.get_keys(prefix)?;
+        let mut page: Vec<Vec<u8>> = match cursor {
+            Some(cursor) => keys
+                .range::<[u8], _>((Bound::Excluded(cursor), Bound::Unbounded))
+                .take(limit.saturating_add(1))
+                .cloned()
+                .collect(),
+            None => keys.into_iter().take(limit.saturating_add(1)).collect(),
+        };
+        if page.len() <= limit {
+            return Ok((page, None));
+        }
+        page.truncate(limit);
+        let next = page.last().cloned();
+        Ok((page, next))
+    }
+
     fn get_ds_keys_wasmv1(
         &self,
         prefix: &[u8],
@@ -378,14 +1593,62 @@ pub trait Interface: Send + Sync + InterfaceClone {
     /// Return the datastore value of the corresponding key
     fn raw_get_data(&self, key: &[u8]) -> Result<Vec<u8>>;
 
+    /// Return at most `len` bytes of the datastore value of the corresponding
+    /// key from `offset`, nothing when `offset` is past its end. Implemented
+    /// on top of `raw_get_data` by default, interfaces able to read a window
+    /// of a value without loading all of it should override it.
+    fn raw_get_data_chunk(&self, key: &[u8], offset: u64, len: u64) -> Result<Vec<u8>> {
+        let data = self.raw_get_data(key)?;
+        let start = usize::try_from(offset).map_or(data.len(), |offset| offset.min(data.len()));
+        let end = usize::try_from(len)
+            .map_or(data.len(), |len| start.saturating_add(len).min(data.len()));
+        Ok(data[start..end].to_vec())
+    }
+
     /// Requires the data at the address
     fn raw_get_data_for(&self, address: &str, key: &[u8]) -> Result<Vec<u8>>;
 
+    /// Hint that the datastore entries of `keys` are about to be read, so
+    /// that their values can be loaded ahead of the reads. Nothing depends on
+    /// it, not even its errors, which the runtime ignores.
+    fn prefetch(&self, _keys: &[Vec<u8>]) -> Result<()> {
+        Ok(())
+    }
+
+    /// Return the datastore entries of `keys`, read at once. Defaults to
+    /// reading their values one by one with `raw_get_data`.
+    fn raw_get_data_batch(&self, keys: &[Vec<u8>]) -> Result<BTreeMap<Vec<u8>, Vec<u8>>> {
+        keys.iter()
+            .map(|key| {
+                let value = self.raw_get_data(key)?;
+                Ok((key.clone(), value))
+            })
+            .collect()
+    }
+
     fn get_ds_value_wasmv1(&self, key: &[u8], address: Option<String>) -> Result<Vec<u8>>;
 
     /// Set the datastore value for the corresponding key
     fn raw_set_data(&self, key: &[u8], value: &[u8]) -> Result<()>;
 
+    /// Set several datastore values at once.
+    /// Either every entry is written or none of them.
+    fn raw_set_data_batch(&self, entries: &[(Vec<u8>, Vec<u8>)]) -> Result<()> {
+        bail!("unimplemented function raw_set_data_batch in interface")
+    }
+
+    /// Coins the current address is charged for storing `bytes` more bytes
+    fn get_storage_cost(&self, bytes: u64) -> Result<u64> {
+        bail!("unimplemented function get_storage_cost in interface")
+    }
+
+    /// Set aside the coins the current address needs to store `bytes` more
+    /// bytes, the next writes being charged on them. Returns false, reserving
+    /// nothing, when the balance does not cover them.
+    fn reserve_storage(&self, bytes: u64) -> Result<bool> {
+        bail!("unimplemented function reserve_storage in interface")
+    }
+
     /// Set the datastore value for the corresponding key of the given address
     fn raw_set_data_for(&self, address: &str, key: &[u8], value: &[u8]) -> Result<()>;
 
@@ -428,14 +1691,23 @@ pub trait Interface: Send + Sync + InterfaceClone {
     /// Returns bytecode of the current address
     fn raw_get_bytecode(&self) -> Result<Vec<u8>>;
 
-    /// Returns bytecode of the target address
+    /// Returns bytecode of the target address.
+    /// Must fail if there is no smart contract at the address, so that it is
+    /// not mistaken for an empty bytecode.
     fn raw_get_bytecode_for(&self, address: &str) -> Result<Vec<u8>>;
 
     fn get_bytecode_wasmv1(&self, address: Option<String>) -> Result<Vec<u8>>;
 
-    /// Return operation datastore keys
+    /// Return operation datastore keys, in any order: the runtime sorts them
+    /// before giving them to the guest
     fn get_op_keys(&self, prefix: Option<&[u8]>) -> Result<Vec<Vec<u8>>>;
 
+    /// Return the operation datastore keys starting with the given binary
+    /// prefix
+    fn get_op_keys_prefix(&self, prefix: &[u8]) -> Result<Vec<Vec<u8>>> {
+        self.get_op_keys(Some(prefix))
+    }
+
     fn get_op_keys_wasmv1(&self, prefix: &[u8]) -> Result<Vec<Vec<u8>>>;
 
     /// Check if operation in datastore exists
@@ -477,6 +1749,16 @@ pub trait Interface: Send + Sync + InterfaceClone {
     /// Convert a public key to an address
     fn address_from_public_key(&self, public_key: &str) -> Result<String>;
 
+    /// Convert a public key given as its serialized bytes to an address
+    fn address_from_public_key_bytes(&self, public_key: &[u8]) -> Result<String> {
+        bail!("unimplemented function address_from_public_key_bytes in interface")
+    }
+
+    /// Serialized bytes of a public key given as a string
+    fn public_key_from_string(&self, public_key: &str) -> Result<Vec<u8>> {
+        bail!("unimplemented function public_key_from_string in interface")
+    }
+
     /// Validate an address
     fn validate_address(&self, address: &str) -> Result<bool>;
 
@@ -492,15 +1774,43 @@ pub trait Interface: Send + Sync + InterfaceClone {
     /// Returns a random number (unsafe: can be predicted and manipulated)
     fn unsafe_random_wasmv1(&self, num_bytes: u64) -> Result<Vec<u8>>;
 
-    /// Returns the period of the current execution slot
-    fn get_current_period(&self) -> Result<u64>;
+    /// Seed of the deterministic randomness of the current execution, which
+    /// the node derives from the slot. Asked once per top-level execution.
+    fn get_random_seed(&self) -> Result<[u8; 32]> {
+        bail!("unimplemented function get_random_seed in interface")
+    }
+
+    /// Returns the period of the current execution slot.
+    ///
+    /// Deprecated: the runtime reads the slot with `get_current_slot` only,
+    /// this one is kept for the embedders calling it.
+    fn get_current_period(&self) -> Result<u64> {
+        Ok(self.get_current_slot()?.period)
+    }
 
-    /// Returns the thread of the current execution slot
-    fn get_current_thread(&self) -> Result<u8>;
+    /// Returns the thread of the current execution slot.
+    ///
+    /// Deprecated like `get_current_period`.
+    fn get_current_thread(&self) -> Result<u8> {
+        Ok(u8::try_from(self.get_current_slot()?.thread)?)
+    }
 
-    /// Returns the current execution slot
+    /// Returns the current execution slot. Asked once per top-level
+    /// execution, all the frames of the call tree see the same slot.
     fn get_current_slot(&self) -> Result<Slot>;
 
+    /// Timestamp (milliseconds) of the start of `slot` (period, thread),
+    /// depending on the genesis timestamp and the slot duration of the node
+    fn slot_to_timestamp(&self, slot: (u64, u8)) -> Result<u64> {
+        bail!("unimplemented function slot_to_timestamp in interface")
+    }
+
+    /// Slot (period, thread) in progress at `timestamp` (milliseconds), an
+    /// error before the genesis
+    fn timestamp_to_slot(&self, timestamp: u64) -> Result<(u64, u8)> {
+        bail!("unimplemented function timestamp_to_slot in interface")
+    }
+
     /// Expect to return a list of owned addresses
     ///
     /// Required on smart-contract execute the imported function
@@ -516,6 +1826,18 @@ pub trait Interface: Send + Sync + InterfaceClone {
     /// Generate a smart contract event
     fn generate_event(&self, _event: String) -> Result<()>;
 
+    /// Generate a smart contract event, `index` being its position among the
+    /// events of the whole call tree, nested calls included. Drops the index
+    /// by default.
+    fn generate_event_indexed(&self, _index: u64, event: String) -> Result<()> {
+        self.generate_event(event)
+    }
+
+    /// Generate a smart contract event made of indexed topics and opaque data
+    fn generate_event_binary(&self, topics: &[[u8; 32]], data: &[u8]) -> Result<()> {
+        bail!("unimplemented function generate_event_binary in interface")
+    }
+
     /// Generate a smart contract event
     fn generate_event_wasmv1(&self, _event: Vec<u8>) -> Result<()>;
 
@@ -527,6 +1849,20 @@ pub trait Interface: Send + Sync + InterfaceClone {
     /// Returns a CL compiled module and the remaining gas after loading
     fn get_module(&self, bytecode: &[u8], gas_limit: u64) -> Result<RuntimeModule>;
 
+    /// Same as `get_module` for the nested calls of an execution, with the
+    /// compiler of its top-level module. Compiled modules must be cached per
+    /// compiler.
+    ///
+    /// Defaults to `get_module`, which lets the interface pick the compiler
+    fn get_module_with_compiler(
+        &self,
+        bytecode: &[u8],
+        gas_limit: u64,
+        compiler: Compiler,
+    ) -> Result<RuntimeModule> {
+        self.get_module(bytecode, gas_limit)
+    }
+
     /// Compile a temportary module from the given bytecode
     ///
     /// Returns a SP compiled module and the remaining gas after loading
@@ -546,6 +1882,9 @@ pub trait Interface: Send + Sync + InterfaceClone {
     /// * `raw_fee` - Fee to be paid for message execution
     /// * `coins` - Coins of the sender
     /// * `data` - Message data
+    ///
+    /// The slots, the gas and the filter key are checked by the runtime
+    /// beforehand.
     #[allow(clippy::too_many_arguments)]
     fn send_message(
         &self,
@@ -560,8 +1899,40 @@ pub trait Interface: Send + Sync + InterfaceClone {
         filter: Option<(&str, Option<&[u8]>)>,
     ) -> Result<()>;
 
+    /// Schedule a call of `target_function` at `target_address` during
+    /// `target_slot` (period, thread), paid by the current address.
+    ///
+    /// Returns the id of the deferred call.
+    fn deferred_call_register(
+        &self,
+        target_address: &str,
+        target_function: &str,
+        target_slot: (u64, u8),
+        max_gas: u64,
+        params: &[u8],
+        raw_coins: u64,
+    ) -> Result<String> {
+        bail!("unimplemented function deferred_call_register in interface")
+    }
+
+    /// Check whether a deferred call is still scheduled
+    fn deferred_call_exists(&self, id: &str) -> Result<bool> {
+        bail!("unimplemented function deferred_call_exists in interface")
+    }
+
+    /// Cancel a deferred call registered by the current address
+    fn deferred_call_cancel(&self, id: &str) -> Result<()> {
+        bail!("unimplemented function deferred_call_cancel in interface")
+    }
+
+    /// Fee to pay to register a deferred call of `max_gas` during
+    /// `target_slot` (period, thread)
+    fn deferred_call_quote(&self, target_slot: (u64, u8), max_gas: u64) -> Result<u64> {
+        bail!("unimplemented function deferred_call_quote in interface")
+    }
+
     // Returns the operation id that originated the current execution if there
-    // is one
+    // is one, `None` for an asynchronous message or a deferred call
     fn get_origin_operation_id(&self) -> Result<Option<String>>;
 
     // Sha256 hash bytes
@@ -570,8 +1941,11 @@ pub trait Interface: Send + Sync + InterfaceClone {
     // Keccak256 hash bytes
     fn hash_keccak256(&self, bytes: &[u8]) -> Result<[u8; 32]>;
 
-    // Return the current chain id
-    fn chain_id(&self) -> Result<u64>;
+    // Return the current chain id, `UNKNOWN_CHAIN_ID` if the embedder does not
+    // know the network it runs on
+    fn chain_id(&self) -> Result<u64> {
+        Ok(UNKNOWN_CHAIN_ID)
+    }
 
     fn native_amount_from_str_wasmv1(&self, amount: &str) -> Result<NativeAmount>;
 
@@ -672,6 +2046,9 @@ pub trait Interface: Send + Sync + InterfaceClone {
     fn save_gas_remaining_before_subexecution(&self, gas_used_until: u64);
 }
 
+/// Datastore accessors (de)serializing JSON values, the `raw_*` methods of
+/// `Interface` being the binary ones
+#[cfg(feature = "json")]
 impl dyn Interface {
     pub fn get_data<T: DeserializeOwned>(&self, key: &[u8]) -> Result<T> {
         Ok(serde_json::from_str::<T>(std::str::from_utf8(
@@ -695,3 +2072,279 @@ impl dyn Interface {
         self.raw_set_data_for(address, key, serde_json::to_string::<T>(value)?.as_bytes())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Write `costs` to a JSON file of the temporary directory
+    fn write_cost_file(name: &str, costs: &HashMap<String, u64>) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "massa_sc_runtime_{}_{}.json",
+            name,
+            std::process::id()
+        ));
+        std::fs::write(&path, serde_json::to_string(costs).unwrap()).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_default_gas_costs_are_valid() {
+        assert_eq!(GasCosts::default().validate(), Ok(()));
+        let names = GasCosts::abi_names();
+        assert_eq!(names.iter().collect::<BTreeSet<_>>().len(), names.len());
+    }
+
+    #[test]
+    fn test_gas_costs_digest() {
+        let gas_costs = GasCosts::default();
+        // the same costs inserted in another order
+        let mut names: Vec<_> = gas_costs.abi_costs.keys().cloned().collect();
+        names.sort_unstable();
+        let mut reordered = gas_costs.clone();
+        reordered.abi_costs = names
+            .iter()
+            .rev()
+            .map(|name| (name.clone(), gas_costs.abi_costs[name]))
+            .collect();
+        assert_eq!(gas_costs.digest(), reordered.digest());
+
+        let mut other_cost = gas_costs.clone();
+        *other_cost.abi_costs.get_mut(&names[0]).unwrap() += 1;
+        let mut other_page_cost = gas_costs.clone();
+        other_page_cost.memory_page_cost += 1;
+        for other in [other_cost, other_page_cost] {
+            assert_ne!(gas_costs.digest(), other.digest());
+        }
+    }
+
+    #[test]
+    fn test_runtime_config_digest() {
+        let config = RuntimeConfig {
+            abi_filter: AbiFilter::deny(["assembly_script_print", "abi_transfer_coins"]),
+            ..RuntimeConfig::default()
+        };
+        let reordered = RuntimeConfig {
+            abi_filter: AbiFilter::deny(["abi_transfer_coins", "assembly_script_print"]),
+            ..RuntimeConfig::default()
+        };
+        assert_eq!(config.digest(), reordered.digest());
+
+        let mut other_filter = config.clone();
+        other_filter.abi_filter = AbiFilter::deny(["assembly_script_print"]);
+        let mut other_limit = config.clone();
+        other_limit.condom_limits.max_exports += 1;
+        for other in [other_filter, other_limit] {
+            assert_ne!(config.digest(), other.digest());
+        }
+    }
+
+    #[test]
+    fn test_missing_unit_costs() {
+        let mut gas_costs = GasCosts::default();
+        gas_costs
+            .abi_costs
+            .remove("assembly_script_keccak256_hash_per_byte");
+        gas_costs.abi_costs.remove("abi_hash_keccak256_per_byte");
+        let Err(GasCostsError::Invalid { missing, .. }) = gas_costs.validate() else {
+            panic!("missing unit costs accepted");
+        };
+        assert_eq!(
+            missing,
+            [
+                "abi_hash_keccak256_per_byte",
+                "assembly_script_keccak256_hash_per_byte"
+            ]
+        );
+        // they are still charged
+        assert_eq!(
+            gas_costs.unit_cost("assembly_script_keccak256_hash_per_byte"),
+            Some(1)
+        );
+        assert_eq!(gas_costs.unit_cost("assembly_script_print_per_byte"), None);
+    }
+
+    #[test]
+    fn test_address_validator() {
+        assert_eq!(AddressValidator::default().validate(""), Ok(()));
+        let validator = AddressValidator {
+            min_len: 3,
+            max_len: 8,
+            prefixes: vec!["A".to_string(), "AS".to_string()],
+            charset: Some("123".to_string()),
+        };
+        assert_eq!(validator.validate("A123"), Ok(()));
+        // the longest prefix is removed before the charset check
+        assert_eq!(validator.validate("AS12"), Ok(()));
+        assert_eq!(
+            validator.validate("A1"),
+            Err("length 2 is not between 3 and 8".to_string())
+        );
+        assert_eq!(
+            validator.validate("A12312312"),
+            Err("length 9 is not between 3 and 8".to_string())
+        );
+        assert_eq!(
+            validator.validate("B123"),
+            Err("does not start with one of A, AS".to_string())
+        );
+        assert_eq!(
+            validator.validate("A124"),
+            Err("character '4' is not allowed".to_string())
+        );
+    }
+
+    #[test]
+    fn test_gas_costs_from_files() {
+        let defaults = GasCosts::default();
+        let mut abi_costs = defaults.abi_costs.clone();
+        abi_costs.insert("launch".to_string(), defaults.launch_cost);
+        abi_costs.insert("cl_compilation".to_string(), defaults.cl_compilation_cost);
+        abi_costs.insert("sp_compilation".to_string(), defaults.sp_compilation_cost);
+        abi_costs.insert("max_instance".to_string(), defaults.max_instance_cost);
+        let wasm_costs = HashMap::from([("Wasm:I32Add".to_string(), 1)]);
+        let wasm_file = write_cost_file("wasm_costs", &wasm_costs);
+
+        let mut incomplete = abi_costs.clone();
+        incomplete.remove("assembly_script_get_data");
+        incomplete.remove("abi_transfer_coins");
+        let err = GasCosts::from_files(
+            write_cost_file("incomplete", &incomplete),
+            wasm_file.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            GasCostsError::Invalid {
+                missing: vec![
+                    "assembly_script_get_data".to_string(),
+                    "abi_transfer_coins".to_string()
+                ],
+                zero: Vec::new(),
+                too_large: Vec::new(),
+                max: settings::max_abi_cost(),
+            }
+        );
+
+        let gas_costs =
+            GasCosts::from_files(write_cost_file("complete", &abi_costs), wasm_file).unwrap();
+        assert_eq!(gas_costs.operator_cost, 1);
+        // rounded to the ten, apart from the costs below ten
+        assert_eq!(gas_costs.abi_costs["assembly_script_get_data"], 80);
+        assert_eq!(gas_costs.abi_costs["assembly_script_validate_address"], 4);
+
+        // the unit costs missing from the files take their default value,
+        // and are not rounded
+        let mut without_units = abi_costs.clone();
+        without_units.retain(|name, _| !is_unit_cost(name));
+        let without_units = write_cost_file("without_units", &without_units);
+        for gas_costs in [
+            GasCosts::from_files(without_units.clone(), wasm_file.clone()).unwrap(),
+            GasCosts::new(without_units, wasm_file.clone()).unwrap(),
+        ] {
+            for (name, cost) in DEFAULT_UNIT_COSTS {
+                assert_eq!(gas_costs.abi_costs.get(*name), Some(cost), "{}", name);
+            }
+        }
+
+        let err =
+            GasCosts::from_files(PathBuf::from("/nonexistent/abi_costs.json"), PathBuf::new())
+                .unwrap_err();
+        assert!(matches!(err, GasCostsError::File { .. }), "{}", err);
+    }
+
+    #[test]
+    fn test_gas_costs_builder() {
+        let err = GasCosts::builder()
+            .launch_cost(0)
+            .abi_cost("assembly_script_print", 0)
+            .abi_cost("assembly_script_call", 2_000)
+            .max_abi_cost(1_000)
+            .build()
+            .unwrap_err();
+        let GasCostsError::Invalid {
+            missing,
+            zero,
+            too_large,
+            max,
+        } = err
+        else {
+            panic!("unexpected error: {}", err);
+        };
+        assert_eq!(
+            missing[..4],
+            [
+                "operator",
+                "cl_compilation",
+                "sp_compilation",
+                "max_instance"
+            ]
+        );
+        assert_eq!(missing.len(), 4 + GasCosts::abi_names().len() - 2);
+        assert_eq!(zero, ["launch", "assembly_script_print"]);
+        assert_eq!(too_large, ["assembly_script_call"]);
+        assert_eq!(max, 1_000);
+
+        let defaults = GasCosts::default();
+        let builder = defaults.abi_costs.iter().fold(
+            GasCosts::builder()
+                .operator_cost(1)
+                .launch_cost(defaults.launch_cost)
+                .cl_compilation_cost(defaults.cl_compilation_cost)
+                .sp_compilation_cost(defaults.sp_compilation_cost)
+                .max_instance_cost(defaults.max_instance_cost),
+            |builder, (name, cost)| builder.abi_cost(name, *cost),
+        );
+        assert_eq!(builder.build().unwrap().abi_costs, defaults.abi_costs);
+    }
+
+    #[test]
+    fn test_storage_limits_boundaries() {
+        let limits = StorageLimits {
+            max_key_len: 4,
+            max_value_len: 8,
+            max_keys_per_query: 2,
+            allow_empty_keys: false,
+        };
+
+        assert!(limits.check_key(&[0; 4]).is_ok());
+        assert_eq!(
+            limits.check_key(&[0; 5]),
+            Err(StorageLimitError::KeyTooLong { len: 5, max: 4 })
+        );
+        assert_eq!(limits.check_key(&[]), Err(StorageLimitError::EmptyKey));
+        let allow_empty = StorageLimits {
+            allow_empty_keys: true,
+            ..limits
+        };
+        assert!(allow_empty.check_key(&[]).is_ok());
+
+        assert!(limits.check_value(&[]).is_ok());
+        assert!(limits.check_value(&[0; 8]).is_ok());
+        assert_eq!(
+            limits.check_value(&[0; 9]),
+            Err(StorageLimitError::ValueTooLong { len: 9, max: 8 })
+        );
+        assert!(limits.check_entry(&[0; 4], &[0; 9]).is_err());
+
+        assert!(limits.check_entry_count(2).is_ok());
+        assert_eq!(
+            limits.check_entry_count(3),
+            Err(StorageLimitError::TooManyEntries { count: 3, max: 2 })
+        );
+    }
+
+    #[test]
+    fn test_sort_keys() {
+        let mut keys = vec![vec![2], vec![1, 255], vec![], vec![1], vec![2]];
+        assert_eq!(sort_keys(&mut keys), 5 * 3);
+        assert_eq!(keys, vec![vec![], vec![1], vec![1, 255], vec![2]]);
+        for (count, comparisons) in [(0, 0), (1, 0), (2, 2), (3, 6), (4, 8), (1025, 1025 * 11)] {
+            let mut keys = (0..count as u32)
+                .rev()
+                .map(|i| i.to_be_bytes().to_vec())
+                .collect();
+            assert_eq!(sort_keys(&mut keys), comparisons);
+        }
+    }
+}
@@ -0,0 +1,1016 @@
+//! Validation of the bytecode before its compilation.
+//!
+//! Wasmer rejects the WebAssembly features disabled in the engines but with
+//! errors coming from deep inside the compiler. This pass rejects them
+//! beforehand with a typed error naming the forbidden feature. It also
+//! enforces the `CondomLimits` that bound the cost of the compilation and
+//! instantiation, the `StartFunctionMode` included, and the `FloatMode`.
+//!
+//! A module may declare the ABI version it was compiled against, in a
+//! `__massa_abi_version` custom section holding a little-endian `u32` or as an
+//! exported immutable `i32` global. A module requiring a version above the
+//! one of the runtime would fail to link on its newest imports, it is
+//! refused here with the versions at stake instead. Modules without a
+//! declaration are the ones compiled before the versioning and are accepted.
+//!
+//! A module importing both the AssemblyScript and the wasmv1 ABIs is refused,
+//! the gas of the two is not accounted the same way, unless
+//! `RuntimeConfig::allow_mixed_abi_namespaces` is set.
+//!
+//! Malformed modules are refused with `ABIError::InvalidBytecode`, rather
+//! than left to the compiler: the bytecode of a created smart contract is
+//! not compiled before it is stored. Payloads that are not WebAssembly at all
+//! are only bounded in size, and refused unless
+//! `RuntimeConfig::allow_non_wasm_contracts` is set.
+
+use crate::as_execution::{ABIError, ABIResult};
+use crate::settings::{ABI_VERSION, ABI_VERSION_SYMBOL};
+use crate::{CondomLimits, FloatMode, RuntimeConfig, RuntimeModule, StartFunctionMode};
+use wasmer::wasmparser::{
+    ExternalKind, FunctionBody, MemoryType, Operator, Parser, Payload, Type, TypeRef, ValType,
+    Validator, WasmFeatures,
+};
+
+/// Magic number starting the WebAssembly modules
+const WASM_MAGIC: &[u8] = b"\0asm";
+/// First byte of the bytecode of the wasmv1 modules, followed by the module
+const WASMV1_MODULE_PREFIX: u8 = 1;
+/// Import namespace of the ABIs of both versions
+const ABI_NAMESPACE: &str = "massa";
+/// Prefix of the AssemblyScript ABIs
+const LEGACY_ABI_PREFIX: &str = "assembly_script_";
+/// Prefix of the wasmv1 ABIs
+const WASMV1_ABI_PREFIX: &str = "abi_";
+
+/// Features accepted by the runtime, they must match the `FEATURES` of the
+/// engines
+fn allowed_features() -> WasmFeatures {
+    WasmFeatures {
+        mutable_global: true,
+        saturating_float_to_int: true,
+        sign_extension: true,
+        reference_types: false,
+        multi_value: false,
+        bulk_memory: true,
+        simd: cfg!(feature = "simd"),
+        relaxed_simd: false,
+        threads: false,
+        tail_call: false,
+        multi_memory: false,
+        exceptions: false,
+        memory64: false,
+        extended_const: false,
+        ..WasmFeatures::default()
+    }
+}
+
+/// Reject the bytecode exceeding the `condom_limits` or using memory64,
+/// threads, multiple memories, SIMD (unless the `simd` feature is enabled) or
+/// floats in `FloatMode::Reject`, or requiring an ABI version above the one
+/// of the runtime
+///
+/// Bytecode that is invalid for other reasons fails with
+/// `ABIError::InvalidBytecode`.
+pub(crate) fn validate_bytecode(bytecode: &[u8], config: &RuntimeConfig) -> ABIResult<()> {
+    let limits = &config.condom_limits;
+    check_limit("module size", bytecode.len(), limits.max_module_size)?;
+    check_sections(bytecode, limits)?;
+    check_abi_namespaces(bytecode, config)?;
+    if let Some(required) = declared_abi_version(bytecode)? {
+        if required > ABI_VERSION {
+            return Err(ABIError::UnsupportedAbiVersion {
+                required,
+                supported: ABI_VERSION,
+            });
+        }
+    }
+    if config.float_mode == FloatMode::Reject {
+        check_no_floats(bytecode)?;
+    }
+
+    let features = allowed_features();
+    let Err(malformed) = Validator::new_with_features(features).validate_all(bytecode) else {
+        return Ok(());
+    };
+    // find the feature that would make the bytecode valid
+    let candidates: [(&'static str, fn(&mut WasmFeatures)); 2] = [
+        ("threads", |features| features.threads = true),
+        ("simd", |features| features.simd = true),
+    ];
+    for (name, enable) in candidates {
+        let mut extended = features;
+        enable(&mut extended);
+        if Validator::new_with_features(extended)
+            .validate_all(bytecode)
+            .is_ok()
+        {
+            return Err(ABIError::ForbiddenWasmFeature(name));
+        }
+    }
+    Err(ABIError::InvalidBytecode(malformed.to_string()))
+}
+
+/// Validate the bytecode of a contract as `RuntimeModule::new` does before
+/// compiling it, skipping the prefix of the wasmv1 modules
+pub(crate) fn validate_contract_bytecode(bytecode: &[u8], config: &RuntimeConfig) -> ABIResult<()> {
+    validate_bytecode(contract_module(bytecode), config)
+}
+
+/// Validate the bytecode of a smart contract about to be created, which is
+/// not compiled before it is stored
+pub(crate) fn validate_created_bytecode(bytecode: &[u8], config: &RuntimeConfig) -> ABIResult<()> {
+    let module = contract_module(bytecode);
+    if !module.starts_with(WASM_MAGIC) {
+        if !config.allow_non_wasm_contracts {
+            return Err(ABIError::InvalidBytecode(
+                "not a WebAssembly module".to_string(),
+            ));
+        }
+        let max = config.condom_limits.max_module_size;
+        return check_limit("module size", bytecode.len(), max);
+    }
+    validate_bytecode(module, config)
+}
+
+/// Same as `check_abi_namespaces` for the bytecode of a contract, for the
+/// callers that have yet to charge its compilation
+pub(crate) fn check_contract_abi_namespaces(
+    bytecode: &[u8],
+    config: &RuntimeConfig,
+) -> ABIResult<()> {
+    check_abi_namespaces(contract_module(bytecode), config)
+}
+
+/// Module of the bytecode of a contract, without the prefix of the wasmv1
+/// modules
+fn contract_module(bytecode: &[u8]) -> &[u8] {
+    match bytecode.split_first() {
+        Some((&WASMV1_MODULE_PREFIX, module)) => module,
+        _ => bytecode,
+    }
+}
+
+/// Same as `check_abi_namespaces` for a compiled module, for the callers
+/// running a module cached by the interface
+pub(crate) fn check_module_abi_namespaces(
+    module: &RuntimeModule,
+    config: &RuntimeConfig,
+) -> ABIResult<()> {
+    let binary_module = match module {
+        RuntimeModule::ASModule(module) => &module.binary_module,
+        RuntimeModule::WasmV1Module(module) => &module.binary_module,
+    };
+    check_import_namespaces(
+        binary_module
+            .imports()
+            .map(|import| (import.module(), import.name())),
+        config,
+    )
+}
+
+/// Refuse a module importing both an AssemblyScript and a wasmv1 ABI, unless
+/// the mixed modules are allowed
+fn check_abi_namespaces(bytecode: &[u8], config: &RuntimeConfig) -> ABIResult<()> {
+    if config.allow_mixed_abi_namespaces {
+        return Ok(());
+    }
+    let mut imports = Vec::new();
+    for payload in Parser::new(0).parse_all(bytecode) {
+        // malformed bytecode is reported by the compiler
+        let Ok(payload) = payload else {
+            return Ok(());
+        };
+        let Payload::ImportSection(reader) = payload else {
+            continue;
+        };
+        for import in reader {
+            let Ok(import) = import else {
+                return Ok(());
+            };
+            imports.push((import.module, import.name));
+        }
+        // there is a single import section
+        break;
+    }
+    check_import_namespaces(imports, config)
+}
+
+/// Refuse the `(module, name)` imports of both an AssemblyScript and a wasmv1
+/// ABI, unless the mixed modules are allowed
+fn check_import_namespaces<'a>(
+    imports: impl IntoIterator<Item = (&'a str, &'a str)>,
+    config: &RuntimeConfig,
+) -> ABIResult<()> {
+    if config.allow_mixed_abi_namespaces {
+        return Ok(());
+    }
+    let (mut legacy, mut wasmv1) = (None, None);
+    for (module, name) in imports {
+        if module != ABI_NAMESPACE {
+            continue;
+        }
+        if name.starts_with(LEGACY_ABI_PREFIX) {
+            legacy.get_or_insert(name);
+        } else if name.starts_with(WASMV1_ABI_PREFIX) {
+            wasmv1.get_or_insert(name);
+        }
+    }
+    match (legacy, wasmv1) {
+        (Some(legacy), Some(wasmv1)) => Err(ABIError::MixedAbiNamespaces {
+            legacy: legacy.to_string(),
+            wasmv1: wasmv1.to_string(),
+        }),
+        _ => Ok(()),
+    }
+}
+
+fn check_limit(limit: &'static str, value: usize, max: usize) -> ABIResult<()> {
+    if value > max {
+        return Err(ABIError::ModuleLimitExceeded { limit, value, max });
+    }
+    Ok(())
+}
+
+/// Check the memories, the start function, the number of imports, exports,
+/// functions and element segments, the size of the data segments declared in
+/// the sections, and the functions bodies
+fn check_sections(bytecode: &[u8], limits: &CondomLimits) -> ABIResult<()> {
+    let mut memory_count = 0;
+    let mut check_memory = |memory: MemoryType| {
+        memory_count += 1;
+        if memory.memory64 {
+            return Err(ABIError::ForbiddenWasmFeature("memory64"));
+        }
+        if memory.shared {
+            return Err(ABIError::ForbiddenWasmFeature("threads"));
+        }
+        if memory_count > 1 {
+            return Err(ABIError::ForbiddenWasmFeature("multi_memory"));
+        }
+        Ok(())
+    };
+    let mut function_count: usize = 0;
+    for payload in Parser::new(0).parse_all(bytecode) {
+        // malformed bytecode is reported by the compiler
+        let Ok(payload) = payload else {
+            return Ok(());
+        };
+        match payload {
+            Payload::ImportSection(reader) => {
+                check_limit("imports", reader.get_count() as usize, limits.max_imports)?;
+                for import in reader {
+                    let Ok(import) = import else {
+                        return Ok(());
+                    };
+                    match import.ty {
+                        TypeRef::Memory(memory) => check_memory(memory)?,
+                        TypeRef::Func(_) => function_count += 1,
+                        _ => {}
+                    }
+                }
+            }
+            Payload::FunctionSection(reader) => {
+                function_count = function_count.saturating_add(reader.get_count() as usize);
+                check_limit("functions", function_count, limits.max_functions)?;
+            }
+            Payload::MemorySection(reader) => {
+                for memory in reader {
+                    let Ok(memory) = memory else {
+                        return Ok(());
+                    };
+                    check_memory(memory)?;
+                }
+            }
+            Payload::ExportSection(reader) => {
+                check_limit("exports", reader.get_count() as usize, limits.max_exports)?;
+            }
+            Payload::StartSection { .. } => {
+                if limits.start_function_mode == StartFunctionMode::Reject {
+                    check_limit("start functions", 1, 0)?;
+                }
+            }
+            Payload::ElementSection(reader) => {
+                check_limit(
+                    "element segments",
+                    reader.get_count() as usize,
+                    limits.max_element_segments,
+                )?;
+            }
+            Payload::CodeSectionEntry(body) => check_function_body(body, limits)?,
+            // declared after the code
+            Payload::DataSection(reader) => {
+                let mut size: usize = 0;
+                for data in reader {
+                    let Ok(data) = data else {
+                        return Ok(());
+                    };
+                    size = size.saturating_add(data.data.len());
+                }
+                check_limit("data segments size", size, limits.max_data_segments_size)?;
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Check the size, the locals and the nesting depth of a function, whose
+/// compilation time they drive
+fn check_function_body(body: FunctionBody, limits: &CondomLimits) -> ABIResult<()> {
+    check_limit(
+        "function body size",
+        body.range().len(),
+        limits.max_function_body_size,
+    )?;
+    let Ok(locals) = body.get_locals_reader() else {
+        return Ok(());
+    };
+    let mut local_count: usize = 0;
+    for local in locals {
+        let Ok((count, _)) = local else {
+            return Ok(());
+        };
+        local_count = local_count.saturating_add(count as usize);
+    }
+    check_limit("function locals", local_count, limits.max_function_locals)?;
+    let Ok(operators) = body.get_operators_reader() else {
+        return Ok(());
+    };
+    // the body itself is a block closed by the last `end`
+    let mut depth: usize = 0;
+    for operator in operators {
+        let Ok(operator) = operator else {
+            return Ok(());
+        };
+        match operator {
+            Operator::Block { .. } | Operator::Loop { .. } | Operator::If { .. } => {
+                depth += 1;
+                check_limit("block nesting depth", depth, limits.max_block_depth)?;
+            }
+            Operator::End => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// ABI version declared by the module, from its custom section or else from
+/// its exported global
+fn declared_abi_version(bytecode: &[u8]) -> ABIResult<Option<u32>> {
+    let mut section_version = None;
+    let mut global_version = None;
+    let mut imported_globals: u32 = 0;
+    // constant initial values of the immutable i32 globals of the module
+    let mut globals = Vec::new();
+    for payload in Parser::new(0).parse_all(bytecode) {
+        // malformed bytecode is reported by the compiler
+        let Ok(payload) = payload else {
+            return Ok(None);
+        };
+        match payload {
+            Payload::CustomSection(reader) if reader.name() == ABI_VERSION_SYMBOL => {
+                let Ok(version) = <[u8; 4]>::try_from(reader.data()) else {
+                    return Err(ABIError::MalformedAbiVersion(
+                        "the custom section must hold a 4 bytes little-endian version",
+                    ));
+                };
+                section_version = Some(u32::from_le_bytes(version));
+            }
+            Payload::ImportSection(reader) => {
+                for import in reader {
+                    let Ok(import) = import else {
+                        return Ok(None);
+                    };
+                    if matches!(import.ty, TypeRef::Global(_)) {
+                        imported_globals += 1;
+                    }
+                }
+            }
+            Payload::GlobalSection(reader) => {
+                for global in reader {
+                    let Ok(global) = global else {
+                        return Ok(None);
+                    };
+                    let mut operators = global.init_expr.get_operators_reader();
+                    let value = match (operators.read(), operators.read()) {
+                        (Ok(Operator::I32Const { value }), Ok(Operator::End))
+                            if global.ty.content_type == ValType::I32 && !global.ty.mutable =>
+                        {
+                            Some(value)
+                        }
+                        _ => None,
+                    };
+                    globals.push(value);
+                }
+            }
+            Payload::ExportSection(reader) => {
+                for export in reader {
+                    let Ok(export) = export else {
+                        return Ok(None);
+                    };
+                    if export.name != ABI_VERSION_SYMBOL {
+                        continue;
+                    }
+                    let value = match export.kind {
+                        ExternalKind::Global => export
+                            .index
+                            .checked_sub(imported_globals)
+                            .and_then(|index| globals.get(index as usize).copied().flatten()),
+                        _ => None,
+                    };
+                    let Some(value) = value else {
+                        return Err(ABIError::MalformedAbiVersion(
+                            "the export must be an immutable i32 global with a constant value",
+                        ));
+                    };
+                    global_version = Some(value as u32);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(section_version.or(global_version))
+}
+
+fn is_float(ty: ValType) -> bool {
+    matches!(ty, ValType::F32 | ValType::F64)
+}
+
+/// Reject the float types and instructions
+fn check_no_floats(bytecode: &[u8]) -> ABIResult<()> {
+    let forbidden = Err(ABIError::ForbiddenWasmFeature("floats"));
+    for payload in Parser::new(0).parse_all(bytecode) {
+        // malformed bytecode is reported by the compiler
+        let Ok(payload) = payload else {
+            return Ok(());
+        };
+        match payload {
+            Payload::TypeSection(reader) => {
+                for ty in reader {
+                    let Ok(Type::Func(func_type)) = ty else {
+                        return Ok(());
+                    };
+                    if func_type
+                        .params()
+                        .iter()
+                        .chain(func_type.results())
+                        .any(|ty| is_float(*ty))
+                    {
+                        return forbidden;
+                    }
+                }
+            }
+            Payload::GlobalSection(reader) => {
+                for global in reader {
+                    let Ok(global) = global else {
+                        return Ok(());
+                    };
+                    if is_float(global.ty.content_type) {
+                        return forbidden;
+                    }
+                }
+            }
+            Payload::CodeSectionEntry(body) => {
+                let Ok(locals) = body.get_locals_reader() else {
+                    return Ok(());
+                };
+                for local in locals {
+                    let Ok((_, ty)) = local else {
+                        return Ok(());
+                    };
+                    if is_float(ty) {
+                        return forbidden;
+                    }
+                }
+                let Ok(operators) = body.get_operators_reader() else {
+                    return Ok(());
+                };
+                for operator in operators {
+                    let Ok(operator) = operator else {
+                        return Ok(());
+                    };
+                    if is_float_operator(&operator) {
+                        return forbidden;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn is_float_operator(operator: &Operator) -> bool {
+    matches!(
+        operator,
+        Operator::F32Load { .. }
+            | Operator::F64Load { .. }
+            | Operator::F32Store { .. }
+            | Operator::F64Store { .. }
+            | Operator::F32Const { .. }
+            | Operator::F64Const { .. }
+            | Operator::F32Eq
+            | Operator::F32Ne
+            | Operator::F32Lt
+            | Operator::F32Gt
+            | Operator::F32Le
+            | Operator::F32Ge
+            | Operator::F64Eq
+            | Operator::F64Ne
+            | Operator::F64Lt
+            | Operator::F64Gt
+            | Operator::F64Le
+            | Operator::F64Ge
+            | Operator::F32Abs
+            | Operator::F32Neg
+            | Operator::F32Ceil
+            | Operator::F32Floor
+            | Operator::F32Trunc
+            | Operator::F32Nearest
+            | Operator::F32Sqrt
+            | Operator::F32Add
+            | Operator::F32Sub
+            | Operator::F32Mul
+            | Operator::F32Div
+            | Operator::F32Min
+            | Operator::F32Max
+            | Operator::F32Copysign
+            | Operator::F64Abs
+            | Operator::F64Neg
+            | Operator::F64Ceil
+            | Operator::F64Floor
+            | Operator::F64Trunc
+            | Operator::F64Nearest
+            | Operator::F64Sqrt
+            | Operator::F64Add
+            | Operator::F64Sub
+            | Operator::F64Mul
+            | Operator::F64Div
+            | Operator::F64Min
+            | Operator::F64Max
+            | Operator::F64Copysign
+            | Operator::I32TruncF32S
+            | Operator::I32TruncF32U
+            | Operator::I32TruncF64S
+            | Operator::I32TruncF64U
+            | Operator::I64TruncF32S
+            | Operator::I64TruncF32U
+            | Operator::I64TruncF64S
+            | Operator::I64TruncF64U
+            | Operator::F32ConvertI32S
+            | Operator::F32ConvertI32U
+            | Operator::F32ConvertI64S
+            | Operator::F32ConvertI64U
+            | Operator::F32DemoteF64
+            | Operator::F64ConvertI32S
+            | Operator::F64ConvertI32U
+            | Operator::F64ConvertI64S
+            | Operator::F64ConvertI64U
+            | Operator::F64PromoteF32
+            | Operator::I32ReinterpretF32
+            | Operator::I64ReinterpretF64
+            | Operator::F32ReinterpretI32
+            | Operator::F64ReinterpretI64
+            | Operator::I32TruncSatF32S
+            | Operator::I32TruncSatF32U
+            | Operator::I32TruncSatF64S
+            | Operator::I32TruncSatF64U
+            | Operator::I64TruncSatF32S
+            | Operator::I64TruncSatF32U
+            | Operator::I64TruncSatF64S
+            | Operator::I64TruncSatF64U
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validate_wat(wat: &str) -> ABIResult<()> {
+        validate_wat_with(wat, &RuntimeConfig::default())
+    }
+
+    fn validate_wat_with(wat: &str, config: &RuntimeConfig) -> ABIResult<()> {
+        validate_bytecode(&wasmer::wat2wasm(wat.as_bytes()).unwrap(), config)
+    }
+
+    fn with_limits(condom_limits: CondomLimits) -> RuntimeConfig {
+        RuntimeConfig {
+            condom_limits,
+            ..RuntimeConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_allowed_module() {
+        assert!(validate_wat(
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "main") (memory.fill (i32.const 0) (i32.const 0) (i32.const 1))))"#
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_memory64() {
+        assert!(matches!(
+            validate_wat("(module (memory i64 1))"),
+            Err(ABIError::ForbiddenWasmFeature("memory64"))
+        ));
+    }
+
+    #[test]
+    fn test_threads() {
+        assert!(matches!(
+            validate_wat("(module (memory 1 1 shared))"),
+            Err(ABIError::ForbiddenWasmFeature("threads"))
+        ));
+        assert!(matches!(
+            validate_wat(r#"(module (import "env" "memory" (memory 1 1 shared)))"#),
+            Err(ABIError::ForbiddenWasmFeature("threads"))
+        ));
+        // atomic instructions on a non shared memory
+        assert!(matches!(
+            validate_wat("(module (memory 1 1) (func (drop (i32.atomic.load (i32.const 0)))))"),
+            Err(ABIError::ForbiddenWasmFeature("threads"))
+        ));
+    }
+
+    #[test]
+    fn test_multiple_memories() {
+        assert!(matches!(
+            validate_wat("(module (memory 1) (memory 1))"),
+            Err(ABIError::ForbiddenWasmFeature("multi_memory"))
+        ));
+        assert!(matches!(
+            validate_wat(r#"(module (import "env" "memory" (memory 1)) (memory 1))"#),
+            Err(ABIError::ForbiddenWasmFeature("multi_memory"))
+        ));
+    }
+
+    #[test]
+    #[cfg(not(feature = "simd"))]
+    fn test_simd() {
+        assert!(matches!(
+            validate_wat("(module (func (drop (v128.const i64x2 0 0))))"),
+            Err(ABIError::ForbiddenWasmFeature("simd"))
+        ));
+        assert!(matches!(
+            validate_wat("(module (func (param v128)))"),
+            Err(ABIError::ForbiddenWasmFeature("simd"))
+        ));
+    }
+
+    #[test]
+    fn test_invalid_module() {
+        assert!(matches!(
+            validate_bytecode(b"\0asm garbage", &RuntimeConfig::default()),
+            Err(ABIError::InvalidBytecode(_))
+        ));
+    }
+
+    #[test]
+    fn test_created_bytecode() {
+        let config = RuntimeConfig::default();
+        assert!(validate_created_bytecode(b"\0asm\x01\0\0\0", &config).is_ok());
+        // the prefix of the wasmv1 modules is skipped
+        assert!(validate_created_bytecode(b"\x01\0asm\x01\0\0\0", &config).is_ok());
+        assert!(matches!(
+            validate_created_bytecode(b"\0asm garbage", &config),
+            Err(ABIError::InvalidBytecode(_))
+        ));
+        assert!(matches!(
+            validate_created_bytecode(b"data", &config),
+            Err(ABIError::InvalidBytecode(_))
+        ));
+
+        let blobs = RuntimeConfig {
+            allow_non_wasm_contracts: true,
+            ..RuntimeConfig::default()
+        };
+        assert!(validate_created_bytecode(b"data", &blobs).is_ok());
+        // a module is still validated
+        assert!(matches!(
+            validate_created_bytecode(b"\0asm garbage", &blobs),
+            Err(ABIError::InvalidBytecode(_))
+        ));
+    }
+
+    fn module_with(imports: usize, functions: usize, exports: usize) -> String {
+        let mut wat = String::from("(module");
+        for i in 0..imports {
+            wat.push_str(&format!(r#" (import "env" "f{}" (func))"#, i));
+        }
+        for i in 0..functions {
+            wat.push_str(&format!(" (func $f{})", i));
+        }
+        for i in 0..exports {
+            wat.push_str(&format!(r#" (export "e{}" (func $f0))"#, i));
+        }
+        wat.push(')');
+        wat
+    }
+
+    fn assert_limit_exceeded(result: ABIResult<()>, expected_limit: &str, expected_max: usize) {
+        match result {
+            Err(ABIError::ModuleLimitExceeded { limit, value, max }) => {
+                assert_eq!(limit, expected_limit);
+                assert_eq!(value, max + 1);
+                assert_eq!(max, expected_max);
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_module_limits() {
+        let limits = with_limits(CondomLimits {
+            max_exports: 3,
+            max_imports: 2,
+            max_functions: 5,
+            ..CondomLimits::default()
+        });
+
+        // at the limits
+        assert!(validate_wat_with(&module_with(2, 3, 3), &limits).is_ok());
+        // one above each limit
+        assert_limit_exceeded(
+            validate_wat_with(&module_with(2, 3, 4), &limits),
+            "exports",
+            3,
+        );
+        assert_limit_exceeded(
+            validate_wat_with(&module_with(3, 1, 1), &limits),
+            "imports",
+            2,
+        );
+        // imported functions count as functions
+        assert_limit_exceeded(
+            validate_wat_with(&module_with(2, 4, 1), &limits),
+            "functions",
+            5,
+        );
+
+        let bytecode = wasmer::wat2wasm(module_with(0, 1, 1).as_bytes()).unwrap();
+        let size_limits = with_limits(CondomLimits {
+            max_module_size: bytecode.len(),
+            ..CondomLimits::default()
+        });
+        assert!(validate_bytecode(&bytecode, &size_limits).is_ok());
+        let size_limits = with_limits(CondomLimits {
+            max_module_size: bytecode.len() - 1,
+            ..CondomLimits::default()
+        });
+        assert_limit_exceeded(
+            validate_bytecode(&bytecode, &size_limits),
+            "module size",
+            bytecode.len() - 1,
+        );
+    }
+
+    #[test]
+    fn test_segment_limits() {
+        let limits = with_limits(CondomLimits {
+            max_data_segments_size: 8,
+            max_element_segments: 1,
+            ..CondomLimits::default()
+        });
+        // the active and passive segments are summed
+        assert!(validate_wat_with(
+            r#"(module (memory 1) (data (i32.const 0) "abcd") (data "efgh"))"#,
+            &limits
+        )
+        .is_ok());
+        assert_limit_exceeded(
+            validate_wat_with(
+                r#"(module (memory 1) (data (i32.const 0) "abcd") (data "efghi"))"#,
+                &limits,
+            ),
+            "data segments size",
+            8,
+        );
+        // after a code section
+        assert_limit_exceeded(
+            validate_wat_with(
+                r#"(module (memory 1) (func) (data (i32.const 0) "abcdefghi"))"#,
+                &limits,
+            ),
+            "data segments size",
+            8,
+        );
+
+        let elements = "(module (table 2 funcref) (func $f) (elem (i32.const 0) $f)";
+        assert!(validate_wat_with(&format!("{})", elements), &limits).is_ok());
+        assert_limit_exceeded(
+            validate_wat_with(&format!("{} (elem (i32.const 1) $f))", elements), &limits),
+            "element segments",
+            1,
+        );
+    }
+
+    #[test]
+    fn test_function_body_limits() {
+        let limits = with_limits(CondomLimits {
+            max_function_body_size: 3,
+            max_function_locals: 2,
+            max_block_depth: 2,
+            ..CondomLimits::default()
+        });
+        // no locals and `nop end`
+        assert!(validate_wat_with("(module (func (nop)))", &limits).is_ok());
+        assert_limit_exceeded(
+            validate_wat_with("(module (func (nop) (nop)))", &limits),
+            "function body size",
+            3,
+        );
+
+        let limits = with_limits(CondomLimits {
+            max_function_locals: 2,
+            max_block_depth: 2,
+            ..CondomLimits::default()
+        });
+        assert!(
+            validate_wat_with("(module (func (param i32 i32) (local i64 i64)))", &limits).is_ok()
+        );
+        assert_limit_exceeded(
+            validate_wat_with("(module (func (local i32 i32) (local i64)))", &limits),
+            "function locals",
+            2,
+        );
+        // the depth is the one of the deepest nesting
+        assert!(validate_wat_with(
+            "(module (func (block (loop)) (block (block)) (if (i32.const 0) (then (block)))))",
+            &limits
+        )
+        .is_ok());
+        assert_limit_exceeded(
+            validate_wat_with("(module (func (block (block (loop)))))", &limits),
+            "block nesting depth",
+            2,
+        );
+
+        // crafted to take long to compile
+        let depth = 2 * crate::settings::max_block_depth();
+        let nested = format!(
+            "(module (func {}{}))",
+            "(block ".repeat(depth),
+            ")".repeat(depth)
+        );
+        assert_limit_exceeded(
+            validate_wat(&nested),
+            "block nesting depth",
+            crate::settings::max_block_depth(),
+        );
+    }
+
+    #[test]
+    fn test_start_function_mode() {
+        let wat = "(module (func $start (drop (i32.const 1))) (start $start))";
+        // metered by default
+        assert!(validate_wat(wat).is_ok());
+        let reject = with_limits(CondomLimits {
+            start_function_mode: StartFunctionMode::Reject,
+            ..CondomLimits::default()
+        });
+        assert_limit_exceeded(validate_wat_with(wat, &reject), "start functions", 0);
+        assert!(validate_wat_with("(module (func $f))", &reject).is_ok());
+    }
+
+    #[test]
+    fn test_float_mode() {
+        let strict = RuntimeConfig {
+            float_mode: FloatMode::Reject,
+            ..RuntimeConfig::default()
+        };
+        let float_modules = [
+            "(module (func (drop (f32.const 1))))",
+            "(module (func (param i32) (drop (f64.convert_i32_s (local.get 0)))))",
+            "(module (func (result f64) (unreachable)))",
+            "(module (global f32 (f32.const 0)))",
+            "(module (func (local f64)))",
+        ];
+        for wat in float_modules {
+            // accepted by default
+            assert!(validate_wat(wat).is_ok());
+            assert!(matches!(
+                validate_wat_with(wat, &strict),
+                Err(ABIError::ForbiddenWasmFeature("floats"))
+            ));
+        }
+        assert!(validate_wat_with("(module (func (drop (i64.const 1))))", &strict).is_ok());
+    }
+
+    /// Custom section `name` holding `data`, to append to a module
+    fn custom_section(name: &str, data: &[u8]) -> Vec<u8> {
+        // the sizes of the fixtures fit in a single LEB128 byte
+        let content_len = 1 + name.len() + data.len();
+        assert!(content_len < 0x80);
+        let mut section = vec![0, content_len as u8, name.len() as u8];
+        section.extend(name.as_bytes());
+        section.extend(data);
+        section
+    }
+
+    fn validate_with_version_section(data: &[u8]) -> ABIResult<()> {
+        let mut bytecode = wasmer::wat2wasm(b"(module (func (export \"main\")))")
+            .unwrap()
+            .to_vec();
+        bytecode.extend(custom_section(ABI_VERSION_SYMBOL, data));
+        validate_bytecode(&bytecode, &RuntimeConfig::default())
+    }
+
+    #[test]
+    fn test_abi_version_section() {
+        // older and current versions
+        assert!(validate_with_version_section(&0u32.to_le_bytes()).is_ok());
+        assert!(validate_with_version_section(&ABI_VERSION.to_le_bytes()).is_ok());
+        // future version
+        assert!(matches!(
+            validate_with_version_section(&(ABI_VERSION + 1).to_le_bytes()),
+            Err(ABIError::UnsupportedAbiVersion {
+                required,
+                supported: ABI_VERSION,
+            }) if required == ABI_VERSION + 1
+        ));
+        assert!(matches!(
+            validate_with_version_section(&[1, 0]),
+            Err(ABIError::MalformedAbiVersion(_))
+        ));
+        // modules without a declaration predate the versioning
+        assert!(validate_wat("(module (func (export \"main\")))").is_ok());
+    }
+
+    #[test]
+    fn test_abi_version_global() {
+        let module = |global: &str| {
+            format!(
+                r#"(module
+                    (import "env" "base_version" (global i32))
+                    {}
+                    (export "{}" (global 1)))"#,
+                global, ABI_VERSION_SYMBOL
+            )
+        };
+        assert!(validate_wat(&module("(global i32 (i32.const 1))")).is_ok());
+        assert!(matches!(
+            validate_wat(&module(&format!(
+                "(global i32 (i32.const {}))",
+                ABI_VERSION + 1
+            ))),
+            Err(ABIError::UnsupportedAbiVersion { .. })
+        ));
+        for malformed in [
+            "(global (mut i32) (i32.const 1))",
+            "(global i64 (i64.const 1))",
+            "(global i32 (global.get 0))",
+        ] {
+            assert!(matches!(
+                validate_wat(&module(malformed)),
+                Err(ABIError::MalformedAbiVersion(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_mixed_abi_namespaces() {
+        let module = |imports: &[&str]| {
+            let imports: String = imports
+                .iter()
+                .map(|name| format!(r#"(import "massa" "{}" (func (param i32)))"#, name))
+                .collect();
+            wasmer::wat2wasm(format!("(module {})", imports).as_bytes())
+                .unwrap()
+                .to_vec()
+        };
+        let legacy = module(&["assembly_script_print", "assembly_script_call"]);
+        let wasmv1 = module(&["abi_generate_event", "abi_call"]);
+        let mixed = module(&["assembly_script_print", "abi_generate_event", "abi_call"]);
+        let mut config = RuntimeConfig::default();
+        assert!(validate_bytecode(&legacy, &config).is_ok());
+        assert!(validate_bytecode(&wasmv1, &config).is_ok());
+        let err = validate_bytecode(&mixed, &config).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Mixed ABI namespaces: the module imports both the AssemblyScript ABI \
+             assembly_script_print and the wasmv1 ABI abi_generate_event"
+        );
+        // the prefix of the wasmv1 modules is skipped
+        let prefixed = [&[WASMV1_MODULE_PREFIX][..], &mixed].concat();
+        assert!(matches!(
+            check_contract_abi_namespaces(&prefixed, &config),
+            Err(ABIError::MixedAbiNamespaces { .. })
+        ));
+        // only the ABI namespace is considered
+        let other = wasmer::wat2wasm(
+            br#"(module
+                (import "massa" "assembly_script_print" (func (param i32)))
+                (import "env" "abi_call" (func (param i32))))"#,
+        )
+        .unwrap();
+        assert!(validate_bytecode(&other, &config).is_ok());
+
+        config.allow_mixed_abi_namespaces = true;
+        assert!(validate_bytecode(&mixed, &config).is_ok());
+        assert!(check_contract_abi_namespaces(&prefixed, &config).is_ok());
+    }
+}
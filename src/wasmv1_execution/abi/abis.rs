@@ -1,17 +1,29 @@
 use super::{
     super::{env::ABIEnv, WasmV1Error},
-    handler::{handle_abi, handle_abi_raw},
+    handler::{handle_abi, handle_abi_raw, handle_forbidden_abi},
 };
 use function_name::named;
 use massa_proto_rs::massa::{
     abi::v1::{self as proto, *},
-    model::v1::NativeTime,
+    model::v1::{NativeAmount, NativeTime},
+};
+use wasmer::{
+    imports, AsStoreMut, Function, FunctionEnv, FunctionEnvMut, FunctionType, Imports,
+    RuntimeError, Type, Value,
 };
-use wasmer::{imports, AsStoreMut, Function, FunctionEnv, FunctionEnvMut, Imports};
 
-use crate::Interface;
+use crate::amount;
+use crate::as_execution::{check_async_message, check_evm_signature_inputs};
+use crate::compilation_watchdog::{compilation_timeout, compile_nested_module};
+use crate::digest::{NativeDigest, DIGESTS};
+use crate::middlewares::deadline::{DEADLINE_CHECK_NAME, DEADLINE_CHECK_NAMESPACE};
+use crate::observer::ExecutionEvent;
+use crate::read_only_cache::CachedEvent;
+use crate::types::sort_keys;
+use crate::validation::{validate_contract_bytecode, validate_created_bytecode};
 #[cfg(feature = "execution-trace")]
 use crate::{into_trace_value, AbiTrace, AbiTraceType};
+use crate::{settings, AbiFilter, CallFrame, Compiler, ForbiddenAbiMode, Interface, RuntimeConfig};
 #[cfg(feature = "execution-trace")]
 use rust_decimal::prelude::ToPrimitive;
 #[cfg(feature = "execution-trace")]
@@ -42,22 +54,106 @@ macro_rules! resp_ok {
     };
 }
 
-/// Register all ABIs to a store
-pub fn register_abis(store: &mut impl AsStoreMut, shared_abi_env: ABIEnv) -> Imports {
+/// Names of the ABIs registered by `register_abis`, under which they are
+/// charged in the gas costs
+pub(crate) const ABI_NAMES: &[&str] = &[
+    "abi_abort",
+    "abi_add_native_amount",
+    "abi_address_from_public_key",
+    "abi_append_ds_value",
+    "abi_base58_check_to_bytes",
+    "abi_bytes_to_base58_check",
+    "abi_call",
+    "abi_caller_has_write_access",
+    "abi_check_address",
+    "abi_check_native_amount",
+    "abi_check_pubkey",
+    "abi_check_signature",
+    "abi_checked_add_native_time",
+    "abi_checked_div_native_time",
+    "abi_checked_mul_native_time",
+    "abi_checked_scalar_div_native_time",
+    "abi_checked_sub_native_time",
+    "abi_compare_address",
+    "abi_compare_native_amount",
+    "abi_compare_native_time",
+    "abi_compare_pub_key",
+    "abi_create_sc",
+    "abi_delete_ds_entry",
+    "abi_div_rem_native_amount",
+    "abi_ds_entry_exists",
+    "abi_function_exists",
+    "abi_generate_event",
+    "abi_get_address_category",
+    "abi_get_address_version",
+    "abi_get_balance",
+    "abi_get_bytecode",
+    "abi_get_call_coins",
+    "abi_get_call_stack",
+    "abi_get_current_slot",
+    "abi_get_ds_keys",
+    "abi_get_ds_value",
+    "abi_get_native_time",
+    "abi_get_op_data",
+    "abi_get_op_keys",
+    "abi_get_origin_operation_id",
+    "abi_get_owned_addresses",
+    "abi_get_pubkey_version",
+    "abi_get_remaining_gas",
+    "abi_get_signature_version",
+    "abi_op_entry_exists",
+    "abi_hash_blake3",
+    "abi_hash_keccak256",
+    "abi_hash_sha256",
+    "abi_sha256",
+    "abi_sha3_256",
+    "abi_local_call",
+    "abi_local_execution",
+    "abi_native_amount_from_string",
+    "abi_native_amount_to_string",
+    "abi_scalar_div_rem_native_amount",
+    "abi_scalar_mul_native_amount",
+    "abi_send_async_message",
+    "abi_set_bytecode",
+    "abi_set_ds_value",
+    "abi_sub_native_amount",
+    "abi_transfer_coins",
+    "abi_unsafe_random",
+    "abi_verify_signature",
+    "abi_evm_verify_signature",
+    "abi_evm_get_address_from_pubkey",
+    "abi_evm_get_pubkey_from_signature",
+    "abi_is_address_eoa",
+    "abi_chain_id",
+];
+
+/// Register all ABIs to a store, the ones refused by `abi_filter` being
+/// replaced by stubs unless they are refused at link time
+pub fn register_abis(
+    store: &mut impl AsStoreMut,
+    shared_abi_env: ABIEnv,
+    abi_filter: &AbiFilter,
+) -> Imports {
     let fn_env = FunctionEnv::new(store, shared_abi_env);
+    let stubbed =
+        |name: &str| abi_filter.mode == ForbiddenAbiMode::Stub && !abi_filter.is_allowed(name);
 
     // helper macro to ease the construction of the imports
     macro_rules! abis {
         ($($name:expr => $func:ident),*) => {
             imports! {
                 "massa" => {
-                    $( $name => Function::new_typed_with_env(store, &fn_env, $func) ),*
+                    $( $name => if stubbed($name) {
+                        forbidden_abi(store, &fn_env, $name)
+                    } else {
+                        Function::new_typed_with_env(store, &fn_env, $func)
+                    } ),*
                 }
             }
         };
     }
 
-    abis!(
+    let mut imports = abis!(
         "abi_abort" => abi_abort,
         "abi_add_native_amount" => abi_add_native_amount,
         "abi_address_from_public_key" => abi_address_from_public_key,
@@ -124,6 +220,79 @@ pub fn register_abis(store: &mut impl AsStoreMut, shared_abi_env: ABIEnv) -> Imp
         "abi_evm_get_pubkey_from_signature" => abi_evm_get_pubkey_from_signature,
         "abi_is_address_eoa" => abi_is_address_eoa,
         "abi_chain_id" => abi_chain_id
+    );
+    for digest in DIGESTS {
+        let abi = if stubbed(digest.wasmv1_abi) {
+            forbidden_abi(store, &fn_env, digest.wasmv1_abi)
+        } else {
+            digest_abi(store, &fn_env, digest)
+        };
+        imports.define("massa", digest.wasmv1_abi, abi);
+    }
+    imports.define(
+        DEADLINE_CHECK_NAMESPACE,
+        DEADLINE_CHECK_NAME,
+        Function::new_typed_with_env(store, &fn_env, check_deadline),
+    );
+    imports
+}
+
+/// Check of the deadline injected at the loop headers by `DeadlineCheck`,
+/// not charged. The loops of the start function are not checked, the
+/// environment being set after the instantiation.
+fn check_deadline(store_env: FunctionEnvMut<ABIEnv>) -> Result<(), WasmV1Error> {
+    let env_mutex = store_env.data().clone();
+    let env_lock = env_mutex.lock();
+    match env_lock.as_ref() {
+        Some(exec_env) => Ok(exec_env.get_call_context().check_deadline()?),
+        None => Ok(()),
+    }
+}
+
+/// ABI computing `digest` of its raw argument
+fn digest_abi(
+    store: &mut impl AsStoreMut,
+    fn_env: &FunctionEnv<ABIEnv>,
+    digest: &'static NativeDigest,
+) -> Function {
+    let ty = FunctionType::new(vec![Type::I32], vec![Type::I32]);
+    Function::new_with_env(
+        store,
+        fn_env,
+        ty,
+        move |store_env: FunctionEnvMut<ABIEnv>, args: &[Value]| {
+            let ptr = handle_abi_raw(
+                digest.wasmv1_abi,
+                store_env,
+                args[0].unwrap_i32(),
+                |handler, data: Vec<u8>| -> Result<Vec<u8>, WasmV1Error> {
+                    handler.try_subtract_gas_per_byte(digest.wasmv1_abi, data.len())?;
+                    digest
+                        .compute(&data, handler.get_config().max_digest_input_len)
+                        .map_err(WasmV1Error::RuntimeError)
+                },
+            )
+            .map_err(|err| RuntimeError::user(Box::new(err)))?;
+            Ok(vec![Value::I32(ptr)])
+        },
+    )
+}
+
+/// Stand-in of an ABI refused by the ABI filter of the execution
+fn forbidden_abi(
+    store: &mut impl AsStoreMut,
+    fn_env: &FunctionEnv<ABIEnv>,
+    abi_name: &'static str,
+) -> Function {
+    let ty = FunctionType::new(vec![Type::I32], vec![Type::I32]);
+    Function::new_with_env(
+        store,
+        fn_env,
+        ty,
+        move |store_env: FunctionEnvMut<ABIEnv>, _: &[Value]| {
+            let err = handle_forbidden_abi(abi_name, store_env);
+            Err(RuntimeError::user(Box::new(err)))
+        },
     )
 }
 
@@ -138,29 +307,102 @@ fn abi_call(store_env: FunctionEnvMut<ABIEnv>, arg_offset: i32) -> Result<i32, W
             let amount = req
                 .call_coins
                 .ok_or_else(|| WasmV1Error::RuntimeError("No coins provided".into()))?;
+            // like `assembly_script_call`, invalid coins are refused before
+            // the interface is told about the call
+            let valid_amount = handler
+                .exec_env
+                .get_interface()
+                .check_native_amount_wasmv1(&amount)
+                .map_err(|err| {
+                    WasmV1Error::RuntimeError(format!("Could not check coins: {}", err))
+                })?;
+            if !valid_amount {
+                return Err(WasmV1Error::RuntimeError(
+                    "Invalid amount of coins in call".into(),
+                ));
+            }
+            if amount.mantissa != 0 {
+                handler
+                    .exec_env
+                    .get_call_context()
+                    .check_write(function_name!())?;
+            }
 
             #[cfg(feature = "execution-trace")]
             let amount_ = Decimal::try_from_i128_with_scale(amount.mantissa as i128, amount.scale)
                 .unwrap_or_default();
 
+            let call_depth = handler.get_next_call_depth()?;
+            let remaining_gas = handler.get_remaining_gas();
             let interface = handler.exec_env.get_interface();
+            let call_context = handler
+                .exec_env
+                .get_call_context()
+                .nested_call(
+                    call_depth,
+                    CallFrame::new(
+                        &req.target_sc_address,
+                        &req.target_function_name,
+                        remaining_gas,
+                    ),
+                    interface,
+                )
+                .map_err(|err| {
+                    WasmV1Error::RuntimeError(format!("Could not get call stack: {}", err))
+                })?;
+            call_context.check_reentrancy(handler.get_config().reentrancy)?;
             let bytecode = interface
                 .init_call_wasmv1(&req.target_sc_address, amount)
                 .map_err(|err| {
                     WasmV1Error::RuntimeError(format!("Could not init call: {}", err))
                 })?;
-            let remaining_gas = handler.get_remaining_gas();
-            let interface = handler.exec_env.get_interface();
-            let module = helper_get_module(interface, bytecode, remaining_gas)?;
-            let response = crate::execution::run_function(
+            let module = helper_get_module(
+                interface,
+                handler.exec_env.get_config(),
+                handler.exec_env.get_call_context().compiler.clone(),
+                bytecode,
+                remaining_gas,
+            )?;
+            let finished = |remaining_gas, success| ExecutionEvent::NestedCallFinished {
+                address: req.target_sc_address.clone(),
+                function: req.target_function_name.clone(),
+                remaining_gas,
+                success,
+            };
+            handler
+                .exec_env
+                .get_call_context()
+                .observe(|| ExecutionEvent::NestedCallStarted {
+                    address: req.target_sc_address.clone(),
+                    function: req.target_function_name.clone(),
+                    gas_limit: remaining_gas,
+                });
+            let response = match crate::execution::exec(
                 interface,
                 module,
                 &req.target_function_name,
                 &req.function_arg,
                 remaining_gas,
                 handler.get_gas_costs().clone(),
-            )
-            .map_err(|err| WasmV1Error::RuntimeError(format!("Could not run function: {}", err)))?;
+                call_context,
+            ) {
+                Ok((response, _)) => response,
+                Err(err) => {
+                    let remaining_gas = remaining_gas.saturating_sub(err.gas_used(remaining_gas));
+                    let call_context = handler.exec_env.get_call_context();
+                    call_context.record_read_only(|recorder| {
+                        recorder.nested_call_failed(&req.target_sc_address)
+                    });
+                    call_context.observe(|| finished(remaining_gas, false));
+                    return Err(WasmV1Error::NestedCall(err));
+                }
+            };
+            let call_context = handler.exec_env.get_call_context();
+            call_context.record_callee_gas(
+                &req.target_sc_address,
+                remaining_gas.saturating_sub(response.remaining_gas),
+            );
+            call_context.observe(|| finished(response.remaining_gas, true));
             handler.set_remaining_gas(response.remaining_gas);
             let interface = handler.exec_env.get_interface();
             interface.finish_call().map_err(|err| {
@@ -176,7 +418,7 @@ fn abi_call(store_env: FunctionEnvMut<ABIEnv>, arg_offset: i32) -> Result<i32, W
                             into_trace_value!(req.target_sc_address),
                             into_trace_value!(req.target_function_name),
                             into_trace_value!(req.function_arg),
-                            into_trace_value!(amount_.to_i64().unwrap()),
+                            into_trace_value!(amount_.to_i64().unwrap_or(i64::MAX)),
                         ],
                         return_value: AbiTraceType::ByteArray(response.ret.clone()),
                         sub_calls: Some(response.trace),
@@ -197,20 +439,29 @@ fn abi_local_call(store_env: FunctionEnvMut<ABIEnv>, arg_offset: i32) -> Result<
         store_env,
         arg_offset,
         |handler, req: CallRequest| {
+            let call_depth = handler.get_next_call_depth()?;
             let bytecode = helper_get_bytecode(handler, req.target_sc_address.clone())?;
             let remaining_gas = handler.get_remaining_gas();
             let interface = handler.exec_env.get_interface();
-            let module = helper_get_module(interface, bytecode.clone(), remaining_gas)?;
+            let module = helper_get_module(
+                interface,
+                handler.exec_env.get_config(),
+                handler.exec_env.get_call_context().compiler.clone(),
+                bytecode.clone(),
+                remaining_gas,
+            )?;
 
-            let response = crate::execution::run_function(
+            let response = crate::execution::exec(
                 interface,
                 module,
                 &req.target_function_name,
                 &req.function_arg,
                 remaining_gas,
                 handler.get_gas_costs().clone(),
+                handler.exec_env.get_call_context().nested(call_depth, None),
             )
-            .map_err(|err| WasmV1Error::RuntimeError(format!("Could not run function: {}", err)))?;
+            .map_err(WasmV1Error::NestedCall)?
+            .0;
             handler.set_remaining_gas(response.remaining_gas);
 
             #[cfg(feature = "execution-trace")]
@@ -242,6 +493,11 @@ fn abi_create_sc(store_env: FunctionEnvMut<ABIEnv>, arg_offset: i32) -> Result<i
         store_env,
         arg_offset,
         |handler, req: CreateScRequest| -> Result<AbiResponse, WasmV1Error> {
+            let byte_cost = handler.get_gas_costs().sc_creation_byte_cost;
+            handler.charge(function_name!(), 0, byte_cost, req.bytecode.len())?;
+            if let Err(e) = validate_created_bytecode(&req.bytecode, handler.get_config()) {
+                return resp_err!(e);
+            }
             let interface = handler.exec_env.get_interface();
             match interface.create_module(&req.bytecode) {
                 Ok(sc_address) => {
@@ -270,8 +526,9 @@ fn abi_get_current_slot(
             //     param_size_update(&env, &mut ctx, &fname, to_address.len(),
             // true); }
 
-            let interface = handler.exec_env.get_interface();
-            match interface.get_current_slot() {
+            // the slot of the call tree, asked once to the interface
+            let env = &handler.exec_env;
+            match env.get_call_context().current_slot(env.get_interface()) {
                 Ok(slot) => resp_ok!(GetCurrentSlotResult, {
                     slot: Some(slot)
                 }),
@@ -346,6 +603,7 @@ fn abi_hash_keccak256(
             //     param_size_update(&env, &mut ctx, &fname, to_address.len(),
             // true); }
 
+            handler.try_subtract_gas_per_byte(function_name!(), req.data.len())?;
             let interface = handler.exec_env.get_interface();
             match interface.hash_keccak256(&req.data) {
                 Ok(hash) => resp_ok!(Keccak256Result, { hash: hash.to_vec() }),
@@ -435,10 +693,29 @@ fn abi_generate_event(
         store_env,
         arg_offset,
         |handler, req: GenerateEventRequest| -> Result<AbiResponse, WasmV1Error> {
-            let interface = handler.exec_env.get_interface();
-            interface.generate_event_wasmv1(req.event).map_err(|err| {
-                WasmV1Error::RuntimeError(format!("Failed to generate event: {}", err))
-            })?;
+            handler
+                .exec_env
+                .get_call_context()
+                .events
+                .lock()
+                .record(
+                    &handler.get_config().event_limits,
+                    req.event.len(),
+                    &req.event,
+                )
+                .map_err(|err| WasmV1Error::RuntimeError(err.to_string()))?;
+            let interface = handler.exec_env.get_interface();
+            interface
+                .generate_event_wasmv1(req.event.clone())
+                .map_err(|err| {
+                    WasmV1Error::RuntimeError(format!("Failed to generate event: {}", err))
+                })?;
+            handler
+                .exec_env
+                .get_call_context()
+                .record_read_only(|recorder| {
+                    recorder.event_emitted(CachedEvent::WasmV1(req.event))
+                });
 
             resp_ok!(GenerateEventResult, {})
         },
@@ -453,9 +730,9 @@ fn abi_abort(store_env: FunctionEnvMut<ABIEnv>, arg_offset: i32) -> Result<i32,
         store_env,
         arg_offset,
         |_handler, req: Vec<u8>| -> Result<Vec<u8>, WasmV1Error> {
-            let msg = format!("Guest program abort: {}", String::from_utf8_lossy(&req));
-
-            Err(WasmV1Error::RuntimeError(msg))
+            Err(WasmV1Error::Aborted(
+                String::from_utf8_lossy(&req).into_owned(),
+            ))
         },
     )
 }
@@ -470,6 +747,10 @@ fn abi_set_ds_value(
         store_env,
         arg_offset,
         |handler, req: SetDsValueRequest| -> Result<AbiResponse, WasmV1Error> {
+            let limits = handler.exec_env.get_config().storage_limits;
+            if let Err(e) = limits.check_entry(&req.key, &req.value) {
+                return resp_err!(e);
+            }
             let interface = handler.exec_env.get_interface();
             if let Err(e) = interface.set_ds_value_wasmv1(&req.key, &req.value, None) {
                 return resp_err!(e);
@@ -489,6 +770,10 @@ fn abi_get_ds_value(
         store_env,
         arg_offset,
         |handler, req: GetDsValueRequest| -> Result<AbiResponse, WasmV1Error> {
+            let limits = handler.exec_env.get_config().storage_limits;
+            if let Err(e) = limits.check_key(&req.key) {
+                return resp_err!(e);
+            }
             let interface = handler.exec_env.get_interface();
             match interface.get_ds_value_wasmv1(&req.key, req.address) {
                 Ok(value) => resp_ok!(GetDsValueResult, { value }),
@@ -508,10 +793,30 @@ fn abi_delete_ds_entry(
         store_env,
         arg_offset,
         |handler, req: DeleteDsEntryRequest| -> Result<AbiResponse, WasmV1Error> {
+            let limits = handler.exec_env.get_config().storage_limits;
+            if let Err(e) = limits.check_key(&req.key) {
+                return resp_err!(e);
+            }
+            // freed entries are refunded, their size is read before the
+            // deletion like `abi_get_ds_value` does
+            let refund_per_byte = handler.get_gas_costs().storage_refund_per_byte;
+            if refund_per_byte > 0 {
+                let read_cost = handler.get_gas_cost("abi_get_ds_value");
+                handler.charge(function_name!(), read_cost, 0, 0)?;
+            }
             let interface = handler.exec_env.get_interface();
+            let freed_bytes = if refund_per_byte > 0 {
+                match interface.get_ds_value_wasmv1(&req.key, req.address.clone()) {
+                    Ok(value) => req.key.len() + value.len(),
+                    Err(e) => return resp_err!(e),
+                }
+            } else {
+                0
+            };
             if let Err(e) = interface.delete_ds_entry_wasmv1(&req.key, req.address) {
                 return resp_err!(e);
             }
+            handler.refund_storage_gas(refund_per_byte.saturating_mul(freed_bytes as u64));
             resp_ok!(DeleteDsEntryResult, {})
         },
     )
@@ -527,6 +832,10 @@ fn abi_append_ds_value(
         store_env,
         arg_offset,
         |handler, req: AppendDsValueRequest| -> Result<AbiResponse, WasmV1Error> {
+            let limits = handler.exec_env.get_config().storage_limits;
+            if let Err(e) = limits.check_entry(&req.key, &req.value) {
+                return resp_err!(e);
+            }
             let interface = handler.exec_env.get_interface();
             if let Err(e) = interface.append_ds_value_wasmv1(&req.key, &req.value, req.address) {
                 return resp_err!(e);
@@ -546,6 +855,10 @@ fn abi_ds_entry_exists(
         store_env,
         arg_offset,
         |handler, req: DsEntryExistsRequest| -> Result<AbiResponse, WasmV1Error> {
+            let limits = handler.exec_env.get_config().storage_limits;
+            if let Err(e) = limits.check_key(&req.key) {
+                return resp_err!(e);
+            }
             let interface = handler.exec_env.get_interface();
             match interface.ds_entry_exists_wasmv1(&req.key, req.address) {
                 Ok(has_data) => resp_ok!(DsEntryExistsResult, { has_data }),
@@ -600,7 +913,14 @@ fn abi_set_bytecode(
         store_env,
         arg_offset,
         |handler, req: SetBytecodeRequest| -> Result<AbiResponse, WasmV1Error> {
+            handler.try_subtract_gas_per_byte(function_name!(), req.bytecode.len())?;
+            if let Err(e) = validate_contract_bytecode(&req.bytecode, handler.get_config()) {
+                return resp_err!(e);
+            }
             let interface = handler.exec_env.get_interface();
+            if let Err(e) = interface.invalidate_cached_module(req.address.as_deref()) {
+                return resp_err!(e);
+            }
             match interface.set_bytecode_wasmv1(&req.bytecode, req.address) {
                 Ok(_) => resp_ok!(SetBytecodeResult, {}),
                 Err(e) => resp_err!(e),
@@ -617,8 +937,12 @@ fn abi_get_ds_keys(store_env: FunctionEnvMut<ABIEnv>, arg_offset: i32) -> Result
         arg_offset,
         |handler, req: GetDsKeysRequest| -> Result<AbiResponse, WasmV1Error> {
             let interface = handler.exec_env.get_interface();
+            let limits = handler.exec_env.get_config().storage_limits;
             match interface.get_ds_keys_wasmv1(&req.prefix, req.address) {
                 Ok(res) => {
+                    if let Err(e) = limits.check_entry_count(res.len()) {
+                        return resp_err!(e);
+                    }
                     resp_ok!(GetDsKeysResult, { keys: res.into_iter().collect()})
                 }
                 Err(e) => resp_err!(e),
@@ -635,8 +959,16 @@ fn abi_get_op_keys(store_env: FunctionEnvMut<ABIEnv>, arg_offset: i32) -> Result
         arg_offset,
         |handler, req: GetOpKeysRequest| -> Result<AbiResponse, WasmV1Error> {
             let interface = handler.exec_env.get_interface();
+            let limits = handler.exec_env.get_config().storage_limits;
             match interface.get_op_keys_wasmv1(&req.prefix) {
-                Ok(keys) => resp_ok!(GetOpKeysResult, { keys }),
+                Ok(mut keys) => {
+                    if let Err(e) = limits.check_entry_count(keys.len()) {
+                        return resp_err!(e);
+                    }
+                    let comparisons = sort_keys(&mut keys);
+                    handler.try_subtract_gas_per_comparison(function_name!(), comparisons)?;
+                    resp_ok!(GetOpKeysResult, { keys })
+                }
                 Err(e) => resp_err!(e),
             }
         },
@@ -653,6 +985,10 @@ fn abi_op_entry_exists(
         store_env,
         arg_offset,
         |handler, req: OpEntryExistsRequest| -> Result<AbiResponse, WasmV1Error> {
+            let limits = handler.exec_env.get_config().storage_limits;
+            if let Err(e) = limits.check_key(&req.key) {
+                return resp_err!(e);
+            }
             let interface = handler.exec_env.get_interface();
             match interface.op_entry_exists(&req.key) {
                 Ok(has_key) => resp_ok!(OpEntryExistsResult, { has_key }),
@@ -669,9 +1005,18 @@ fn abi_get_op_data(store_env: FunctionEnvMut<ABIEnv>, arg_offset: i32) -> Result
         store_env,
         arg_offset,
         |handler, req: GetOpDataRequest| -> Result<AbiResponse, WasmV1Error> {
+            let limits = handler.exec_env.get_config().storage_limits;
+            if let Err(e) = limits.check_key(&req.key) {
+                return resp_err!(e);
+            }
             let interface = handler.exec_env.get_interface();
             match interface.get_op_data(&req.key) {
-                Ok(value) => resp_ok!(GetOpDataResult, { value }),
+                Ok(value) => {
+                    if let Err(e) = limits.check_value(&value) {
+                        return resp_err!(e);
+                    }
+                    resp_ok!(GetOpDataResult, { value })
+                }
                 Err(e) => resp_err!(e),
             }
         },
@@ -688,6 +1033,9 @@ fn abi_evm_verify_signature(
         store_env,
         arg_offset,
         |handler, req: EvmVerifySigRequest| -> Result<AbiResponse, WasmV1Error> {
+            if let Err(err) = check_evm_signature_inputs(&req.sig, &req.pub_key) {
+                return resp_err!(err);
+            }
             let interface = handler.exec_env.get_interface();
             match interface.evm_signature_verify(&req.message, &req.sig, &req.pub_key) {
                 Ok(is_verified) => {
@@ -753,8 +1101,7 @@ fn abi_is_address_eoa(
         store_env,
         arg_offset,
         |handler, req: IsAddressEoaRequest| -> Result<AbiResponse, WasmV1Error> {
-            let interface = handler.exec_env.get_interface();
-            match interface.is_address_eoa(&req.address) {
+            match handler.exec_env.is_address_eoa(&req.address) {
                 Ok(is_eoa) => resp_ok!(IsAddressEoaResult, { is_eoa }),
                 Err(e) => resp_err!(e),
             }
@@ -935,6 +1282,15 @@ fn abi_send_async_message(
                 .filter
                 .as_ref()
                 .map(|f| (f.target_address.as_str(), f.target_key.as_deref()));
+            if let Err(e) = check_async_message(
+                handler.get_gas_costs(),
+                (start.period, start_thread),
+                (end.period, end_thread),
+                req.execution_gas,
+                filter,
+            ) {
+                return resp_err!(e);
+            }
 
             let interface = handler.exec_env.get_interface();
             match interface.send_message(
@@ -999,6 +1355,15 @@ fn abi_get_origin_operation_id(
         |handler, _req: GetOriginOperationIdRequest| -> Result<AbiResponse, WasmV1Error> {
             let interface = handler.exec_env.get_interface();
             match interface.get_origin_operation_id() {
+                Ok(Some(operation_id))
+                    if operation_id.len() > settings::max_operation_id_length() =>
+                {
+                    resp_err!(format!(
+                        "Operation id of {} bytes exceeds the maximum of {} bytes",
+                        operation_id.len(),
+                        settings::max_operation_id_length()
+                    ))
+                }
                 Ok(operation_id) => {
                     resp_ok!(GetOriginOperationIdResult, { operation_id })
                 }
@@ -1018,19 +1383,21 @@ fn abi_local_execution(
         store_env,
         arg_offset,
         |handler, req: LocalExecutionRequest| {
+            let call_depth = handler.get_next_call_depth()?;
             let remaining_gas = handler.get_remaining_gas();
             let module = helper_get_tmp_module(handler, req.bytecode.clone(), remaining_gas)?;
 
             let interface = handler.exec_env.get_interface();
-            match crate::execution::run_function(
+            match crate::execution::exec(
                 interface,
                 module,
                 &req.target_function_name,
                 &req.function_arg,
                 remaining_gas,
                 handler.get_gas_costs().clone(),
+                handler.exec_env.get_call_context().nested(call_depth, None),
             ) {
-                Ok(response) => {
+                Ok((response, _)) => {
                     handler.set_remaining_gas(response.remaining_gas);
 
                     #[cfg(feature = "execution-trace")]
@@ -1067,8 +1434,7 @@ fn abi_caller_has_write_access(
         store_env,
         arg_offset,
         |handler, _req: CallerHasWriteAccessRequest| -> Result<AbiResponse, WasmV1Error> {
-            let interface = handler.exec_env.get_interface();
-            match interface.caller_has_write_access() {
+            match handler.exec_env.caller_has_write_access() {
                 Ok(has_write_access) => {
                     resp_ok!(CallerHasWriteAccessResult, { has_write_access })
                 }
@@ -1094,15 +1460,18 @@ fn abi_function_exists(
                 return resp_err!("No SC found at the given address");
             };
 
-            let remaining_gas = if cfg!(feature = "gas_calibration") {
-                u64::MAX
-            } else {
-                handler.get_remaining_gas()
-            };
+            // unlimited when calibrating the gas costs
+            let remaining_gas = handler.get_remaining_gas();
 
             // FIXME set updated value to store_env
             let interface = handler.exec_env.get_interface();
-            let Ok(module) = helper_get_module(interface, bytecode, remaining_gas) else {
+            let Ok(module) = helper_get_module(
+                interface,
+                handler.exec_env.get_config(),
+                handler.exec_env.get_call_context().compiler.clone(),
+                bytecode,
+                remaining_gas,
+            ) else {
                 return resp_ok!(FunctionExistsResult, {
                     exists: false
                 });
@@ -1128,16 +1497,28 @@ fn helper_get_bytecode(
     Ok(bytecode)
 }
 
+/// Module compiled by `interface` within the `max_compilation_time` of
+/// `config`
 fn helper_get_module(
     // handler: &mut super::handler::ABIHandler,
     interface: &dyn Interface,
+    config: &RuntimeConfig,
+    compiler: Compiler,
     bytecode: Vec<u8>,
     remaining_gas: u64,
 ) -> Result<crate::RuntimeModule, WasmV1Error> {
     // let interface = handler.exec_env.get_interface();
-    interface
-        .get_module(&bytecode, remaining_gas)
-        .map_err(|err| WasmV1Error::RuntimeError(format!("Could not get module: {}", err)))
+    compile_nested_module(
+        interface,
+        bytecode,
+        remaining_gas,
+        compiler,
+        config.max_compilation_time,
+    )
+    .map_err(|err| match compilation_timeout(&err) {
+        Some(max_time) => WasmV1Error::CompilationTimeout(max_time),
+        None => WasmV1Error::RuntimeError(format!("Could not get module: {}", err)),
+    })
 }
 
 fn helper_get_tmp_module(
@@ -1312,6 +1693,8 @@ fn abi_div_rem_native_amount(
     )
 }
 
+/// Convert an amount to a decimal string with the decimals of the runtime,
+/// without trailing zeros
 #[named]
 fn abi_native_amount_to_string(
     store_env: FunctionEnvMut<ABIEnv>,
@@ -1325,18 +1708,29 @@ fn abi_native_amount_to_string(
             let Some(amount) = req.to_convert else {
                 return resp_err!("No amount to convert");
             };
+            let decimals = handler.get_config().native_amount_decimals;
+            // an amount of a smaller scale is a multiple of the runtime unit
+            let mantissa = decimals
+                .checked_sub(amount.scale)
+                .and_then(|shift| 10u64.checked_pow(shift))
+                .and_then(|factor| amount.mantissa.checked_mul(factor));
+            let Some(mantissa) = mantissa else {
+                return resp_err!(format!(
+                    "Amount {} of scale {} has no value with {} decimals",
+                    amount.mantissa, amount.scale, decimals
+                ));
+            };
 
-            let interface = handler.exec_env.get_interface();
-            match interface.native_amount_to_string_wasmv1(&amount) {
-                Ok(converted_amount) => {
-                    resp_ok!(NativeAmountToStringResult, { converted_amount })
-                }
-                Err(e) => resp_err!(e),
-            }
+            let converted_amount = amount::to_decimal_string(mantissa, decimals);
+            handler.try_subtract_gas_per_byte(function_name!(), converted_amount.len())?;
+            resp_ok!(NativeAmountToStringResult, { converted_amount })
         },
     )
 }
 
+/// Parse a decimal string with at most the decimals of the runtime into an
+/// amount, the error message of a malformed string starts with the code of
+/// its `AmountParseError`
 #[named]
 fn abi_native_amount_from_string(
     store_env: FunctionEnvMut<ABIEnv>,
@@ -1347,12 +1741,14 @@ fn abi_native_amount_from_string(
         store_env,
         arg_offset,
         |handler, req: NativeAmountFromStringRequest| -> Result<AbiResponse, WasmV1Error> {
-            let interface = handler.exec_env.get_interface();
-            let Ok(amount) = interface.native_amount_from_str_wasmv1(&req.to_convert) else {
-                return resp_err!("Invalid amount");
-            };
-
-            resp_ok!(NativeAmountFromStringResult, { converted_amount: Some(amount) })
+            handler.try_subtract_gas_per_byte(function_name!(), req.to_convert.len())?;
+            let decimals = handler.get_config().native_amount_decimals;
+            match amount::from_decimal_string(&req.to_convert, decimals) {
+                Ok(mantissa) => resp_ok!(NativeAmountFromStringResult, {
+                    converted_amount: Some(NativeAmount { mantissa, scale: decimals })
+                }),
+                Err(err) => resp_err!(format!("Invalid amount (code {}): {}", err as u8, err)),
+            }
         },
     )
 }
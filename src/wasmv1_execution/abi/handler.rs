@@ -1,8 +1,22 @@
 use super::super::env::{ABIEnv, ExecutionEnv};
-use crate::{wasmv1_execution::WasmV1Error, GasCosts};
+use crate::as_execution::check_call_depth;
+use crate::as_execution::env::checked_cost;
+use crate::panic_guard::AbiScope;
+use crate::{observer::ExecutionEvent, wasmv1_execution::WasmV1Error, GasCosts, RuntimeConfig};
 use std::io::Cursor;
 use wasmer::FunctionEnvMut;
 
+/// ABIs mutating the state, refused in read-only mode
+const WRITE_ABI_NAMES: &[&str] = &[
+    "abi_set_ds_value",
+    "abi_append_ds_value",
+    "abi_delete_ds_entry",
+    "abi_transfer_coins",
+    "abi_create_sc",
+    "abi_send_async_message",
+    "abi_set_bytecode",
+];
+
 /// Handle an ABI call by providing helpers to read arguments, return values,
 /// and so on
 pub fn handle_abi<F, Req, Resp>(
@@ -16,24 +30,32 @@ where
     Req: prost::Message + Default,
     Resp: prost::Message,
 {
+    let _scope = AbiScope::new(abi_name);
     // get environment and interface
     let env_mutex = store_env.data().clone();
     let mut env_lock = env_mutex.lock();
     let exec_env = env_lock.as_mut().ok_or_else(|| {
         WasmV1Error::InstanciationError("ABIs cannot be called at initialization time.".into())
     })?;
+    exec_env.get_call_context().check_deadline()?;
+    if WRITE_ABI_NAMES.contains(&abi_name) {
+        exec_env.get_call_context().check_write(abi_name)?;
+    }
 
     // create handler
     let mut handler = ABIHandler {
+        abi_name,
         store_env: &mut store_env,
         exec_env,
     };
 
+    #[cfg(feature = "profiling")]
+    let _call = handler.profiler().start_call(abi_name);
+
     // apply gas cost
     let gas_cost = handler.get_gas_cost(abi_name);
-    if gas_cost > 0 {
-        handler.try_subtract_gas(gas_cost)?;
-    }
+    handler.charge(abi_name, gas_cost, 0, 0)?;
+    handler.observe_call();
 
     // read argument
     let arg: Req = handler.read_arg(arg_offset)?;
@@ -45,6 +67,36 @@ where
     handler.return_value(response)
 }
 
+/// Handle a call to an ABI refused by the ABI filter of the execution: it is
+/// charged like the ABI, then fails with the returned error
+pub fn handle_forbidden_abi(abi_name: &str, mut store_env: FunctionEnvMut<ABIEnv>) -> WasmV1Error {
+    let env_mutex = store_env.data().clone();
+    let mut env_lock = env_mutex.lock();
+    let Some(exec_env) = env_lock.as_mut() else {
+        return WasmV1Error::InstanciationError(
+            "ABIs cannot be called at initialization time.".into(),
+        );
+    };
+    if let Err(err) = exec_env.get_call_context().check_deadline() {
+        return err.into();
+    }
+    let mut handler = ABIHandler {
+        abi_name,
+        store_env: &mut store_env,
+        exec_env,
+    };
+
+    #[cfg(feature = "profiling")]
+    let _call = handler.profiler().start_call(abi_name);
+
+    let gas_cost = handler.get_gas_cost(abi_name);
+    if let Err(err) = handler.charge(abi_name, gas_cost, 0, 0) {
+        return err;
+    }
+    handler.observe_call();
+    WasmV1Error::ForbiddenAbi(abi_name.to_string())
+}
+
 /// Handle an ABI call that cannot rely on encoding
 /// its providing helpers to read arguments, return values, and so on in the
 /// form of Vec<u8>
@@ -57,25 +109,33 @@ pub fn handle_abi_raw<F>(
 where
     F: FnOnce(&mut ABIHandler, Vec<u8>) -> Result<Vec<u8>, WasmV1Error>,
 {
+    let _scope = AbiScope::new(abi_name);
     // get environment and interface
     let env_mutex = store_env.data().clone();
     let mut env_lock = env_mutex.lock();
     let exec_env = env_lock.as_mut().ok_or_else(|| {
         WasmV1Error::InstanciationError("ABIs cannot be called at initialization time.".into())
     })?;
+    exec_env.get_call_context().check_deadline()?;
+    if WRITE_ABI_NAMES.contains(&abi_name) {
+        exec_env.get_call_context().check_write(abi_name)?;
+    }
     // let interface = exec_env.get_interface_mut();
 
     // create handler
     let mut handler = ABIHandler {
+        abi_name,
         store_env: &mut store_env,
         exec_env,
     };
 
+    #[cfg(feature = "profiling")]
+    let _call = handler.profiler().start_call(abi_name);
+
     // apply gas cost
     let gas_cost = handler.get_gas_cost(abi_name);
-    if gas_cost > 0 {
-        handler.try_subtract_gas(gas_cost)?;
-    }
+    handler.charge(abi_name, gas_cost, 0, 0)?;
+    handler.observe_call();
 
     // read argument
     let arg: Vec<u8> = handler.read_arg_raw(arg_offset)?;
@@ -89,6 +149,8 @@ where
 
 /// A helper structure to handle ABI calls
 pub struct ABIHandler<'a, 'b> {
+    /// Name of the ABI being handled
+    pub(crate) abi_name: &'b str,
     pub(crate) store_env: &'b mut FunctionEnvMut<'a, ABIEnv>,
     pub(crate) exec_env: &'b mut ExecutionEnv,
 }
@@ -101,10 +163,11 @@ impl<'a, 'b> ABIHandler<'a, 'b> {
     {
         let byte_vec = self
             .exec_env
-            .take_buffer(&mut self.store_env, arg_offset)
+            .take_buffer(&mut self.store_env, self.abi_name, arg_offset)
             .map_err(|err| {
                 WasmV1Error::RuntimeError(format!("Could not read ABI argument: {}", err))
             })?;
+        self.observe_argument(byte_vec.len());
         M::decode(&mut Cursor::new(&byte_vec)).map_err(|err| {
             WasmV1Error::RuntimeError(format!("Could not deserialize ABI argument: {}", err))
         })
@@ -115,10 +178,11 @@ impl<'a, 'b> ABIHandler<'a, 'b> {
     pub fn read_arg_raw(&mut self, arg_offset: i32) -> Result<Vec<u8>, WasmV1Error> {
         let byte_vec = self
             .exec_env
-            .take_buffer(&mut self.store_env, arg_offset)
+            .take_buffer(&mut self.store_env, self.abi_name, arg_offset)
             .map_err(|err| {
                 WasmV1Error::RuntimeError(format!("Could not read ABI argument: {}", err))
             })?;
+        self.observe_argument(byte_vec.len());
 
         Ok(byte_vec)
     }
@@ -133,7 +197,7 @@ impl<'a, 'b> ABIHandler<'a, 'b> {
             WasmV1Error::RuntimeError(format!("Could not serialize ABI return value: {}", err))
         })?;
         self.exec_env
-            .create_buffer(&mut self.store_env, &buf)
+            .create_buffer(&mut self.store_env, self.abi_name, &buf)
             .map_err(|err| {
                 WasmV1Error::RuntimeError(format!("Could not write ABI return value: {}", err))
             })
@@ -142,17 +206,99 @@ impl<'a, 'b> ABIHandler<'a, 'b> {
     /// Return a raw value aka a Vec<u8> any encoding is up to the caller
     pub fn return_value_raw(&mut self, value: &[u8]) -> Result<i32, WasmV1Error> {
         self.exec_env
-            .create_buffer(&mut self.store_env, value)
+            .create_buffer(&mut self.store_env, self.abi_name, value)
             .map_err(|err| {
                 WasmV1Error::RuntimeError(format!("Could not write ABI return value: {}", err))
             })
     }
 
-    /// Try subtracting gas
-    pub fn try_subtract_gas(&mut self, gas: u64) -> Result<(), WasmV1Error> {
+    /// Charge `base + per_unit * units` to the ABI `abi_name`.
+    ///
+    /// As for the AssemblyScript ABIs, this is the only way an ABI pays for
+    /// its host-side work: the guest instructions allocating and freeing the
+    /// buffers of its argument and result are metered, and the gas of the
+    /// nested calls is the one they used themselves.
+    pub fn charge(
+        &mut self,
+        abi_name: &str,
+        base: u64,
+        per_unit: u64,
+        units: usize,
+    ) -> Result<(), WasmV1Error> {
+        let cost = checked_cost(base, per_unit, units).ok_or_else(|| {
+            WasmV1Error::RuntimeError(format!(
+                "Gas overflow in {}: its cost does not fit in 64 bits",
+                abi_name
+            ))
+        })?;
+        if cost == 0 {
+            return Ok(());
+        }
         self.exec_env
-            .try_subtract_gas(&mut self.store_env, gas)
-            .map_err(|err| WasmV1Error::RuntimeError(format!("ABI gas error: {}", err)))
+            .try_subtract_gas(&mut self.store_env, cost)
+            .map_err(|err| WasmV1Error::RuntimeError(format!("ABI gas error: {}", err)))?;
+        #[cfg(feature = "profiling")]
+        self.profiler().record_gas(abi_name, cost);
+        Ok(())
+    }
+
+    /// Charge the length dependent cost of an ABI, found under
+    /// `<abi_name>_per_byte` in the gas costs
+    pub fn try_subtract_gas_per_byte(
+        &mut self,
+        abi_name: &str,
+        len: usize,
+    ) -> Result<(), WasmV1Error> {
+        let per_byte = self.get_unit_cost(&format!("{}_per_byte", abi_name))?;
+        self.charge(abi_name, 0, per_byte, len)
+    }
+
+    /// Charge the sort of `abi_name`, found under `<abi_name>_per_comparison`
+    /// in the gas costs, for `count` comparisons
+    pub fn try_subtract_gas_per_comparison(
+        &mut self,
+        abi_name: &str,
+        count: usize,
+    ) -> Result<(), WasmV1Error> {
+        let per_comparison = self.get_unit_cost(&format!("{}_per_comparison", abi_name))?;
+        self.charge(abi_name, 0, per_comparison, count)
+    }
+
+    /// Recorder of the current call tree
+    #[cfg(feature = "profiling")]
+    pub(crate) fn profiler(&self) -> &crate::profiling::Profiler {
+        &self.exec_env.get_call_context().profiler
+    }
+
+    /// Report the call of the ABI to the observer of the call tree
+    fn observe_call(&mut self) {
+        #[cfg(feature = "tracing")]
+        tracing::trace!(abi = self.abi_name, "abi call");
+        self.exec_env
+            .get_call_context()
+            .record_read_only(|recorder| recorder.abi_called(self.abi_name));
+        if self.exec_env.get_call_context().observer.is_none() {
+            return;
+        }
+        let remaining_gas = self.get_remaining_gas();
+        self.exec_env
+            .get_call_context()
+            .observe(|| ExecutionEvent::AbiCalled {
+                abi: self.abi_name.to_string(),
+                remaining_gas,
+            });
+    }
+
+    /// Report an argument of `len` bytes to the observer of the call tree
+    fn observe_argument(&self, len: usize) {
+        #[cfg(feature = "tracing")]
+        tracing::trace!(abi = self.abi_name, len, "abi argument");
+        self.exec_env
+            .get_call_context()
+            .observe(|| ExecutionEvent::AbiArgument {
+                abi: self.abi_name.to_string(),
+                len,
+            });
     }
 
     /// Get remaining gas
@@ -165,11 +311,36 @@ impl<'a, 'b> ABIHandler<'a, 'b> {
         self.exec_env.set_remaining_gas(&mut self.store_env, gas)
     }
 
+    /// Credit back `amount` of gas for freed storage, see
+    /// `CallContext::storage_refund`
+    pub fn refund_storage_gas(&mut self, amount: u64) {
+        if amount == 0 || self.get_config().gas_calibration {
+            return;
+        }
+        let remaining_gas = self.get_remaining_gas();
+        let refund = self.exec_env.storage_refund(amount, remaining_gas);
+        self.set_remaining_gas(remaining_gas.saturating_add(refund));
+    }
+
+    /// Get the depth of a call made from the current execution, fails if it
+    /// goes over the configured maximum
+    pub fn get_next_call_depth(&self) -> Result<u32, WasmV1Error> {
+        Ok(check_call_depth(
+            self.exec_env.get_call_context().depth,
+            self.exec_env.get_config().max_recursion_depth,
+        )?)
+    }
+
     /// Get gas costs
     pub fn get_gas_costs(&self) -> &GasCosts {
         self.exec_env.get_gas_costs()
     }
 
+    /// Get the settings of the call tree
+    pub fn get_config(&self) -> &RuntimeConfig {
+        self.exec_env.get_config()
+    }
+
     /// Get gas cost
     pub fn get_gas_cost(&self, abi_name: &str) -> u64 {
         *self
@@ -180,6 +351,15 @@ impl<'a, 'b> ABIHandler<'a, 'b> {
             .unwrap_or(&0)
     }
 
+    /// Get the unit cost `name`, `<abi>_per_byte` for instance, failing when
+    /// it is missing rather than charging nothing
+    fn get_unit_cost(&self, name: &str) -> Result<u64, WasmV1Error> {
+        self.exec_env
+            .get_gas_costs()
+            .unit_cost(name)
+            .ok_or_else(|| WasmV1Error::RuntimeError(format!("gas cost {} is missing", name)))
+    }
+
     /// Get the memory maximum size in bytes
     pub fn get_max_mem_size(&mut self) -> u64 {
         self.exec_env.get_max_mem_size(self.store_env)
@@ -2,3 +2,4 @@ mod abis;
 mod handler;
 
 pub use abis::register_abis;
+pub(crate) use abis::ABI_NAMES;
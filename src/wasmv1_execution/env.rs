@@ -1,8 +1,9 @@
 use std::sync::Arc;
 
 use super::{ffi::Ffi, WasmV1Error};
+use crate::execution::{AccessCache, CallContext};
 use crate::types::Interface;
-use crate::GasCosts;
+use crate::{GasCosts, RuntimeConfig};
 use parking_lot::Mutex;
 use wasmer::{AsStoreMut, AsStoreRef, Imports, Instance, InstantiationError, TypedFunction};
 use wasmer_middlewares::metering::{self, MeteringPoints};
@@ -27,6 +28,15 @@ pub struct ExecutionEnv {
     ffi: Ffi,
     /// Gas cost of instance creation
     init_gas_cost: u64,
+    /// Position of the current execution in the call tree
+    call_context: CallContext,
+    /// Gas available to the execution after the instance creation
+    gas_limit: u64,
+    /// Gas credited back by the storage refunds of the call tree when the
+    /// execution started
+    refunded_at_start: u64,
+    /// Access rights of the current execution already asked to the interface
+    access_cache: AccessCache,
     #[cfg(feature = "execution-trace")]
     pub trace: Vec<AbiTrace>,
 }
@@ -39,6 +49,7 @@ impl ExecutionEnv {
         module: &super::WasmV1Module,
         interface: &dyn Interface,
         gas_costs: GasCosts,
+        call_context: CallContext,
         import_object: &Imports,
     ) -> Result<Self, WasmV1Error> {
         // Create the instance
@@ -69,7 +80,7 @@ impl ExecutionEnv {
 
         // Infer the gas cost of instance creation (_start function call)
         let mut init_gas_cost = 0;
-        if cfg!(not(feature = "gas_calibration")) {
+        if !call_context.config.gas_calibration {
             init_gas_cost = match metering::get_remaining_points(store, &instance) {
                 MeteringPoints::Remaining(remaining_points) => module
                     .gas_limit_at_compilation
@@ -92,6 +103,10 @@ impl ExecutionEnv {
             instance,
             ffi,
             init_gas_cost,
+            call_context,
+            gas_limit: 0,
+            refunded_at_start: 0,
+            access_cache: AccessCache::default(),
             #[cfg(feature = "execution-trace")]
             trace: Default::default(),
         })
@@ -102,11 +117,48 @@ impl ExecutionEnv {
         self.init_gas_cost
     }
 
+    /// Get the position of the current execution in the call tree
+    pub(crate) fn get_call_context(&self) -> &CallContext {
+        &self.call_context
+    }
+
+    /// Set the gas available to the execution, when it starts
+    pub(crate) fn set_gas_limit(&mut self, gas_limit: u64) {
+        self.gas_limit = gas_limit;
+        self.refunded_at_start = self.call_context.refunded_gas();
+    }
+
+    /// Part of the refund `amount` credited back to the execution, with
+    /// `remaining_gas` left, see `CallContext::storage_refund`
+    pub(crate) fn storage_refund(&self, amount: u64, remaining_gas: u64) -> u64 {
+        // the refunds since the execution started were added to its gas
+        let gas_spent = self.gas_limit.saturating_sub(remaining_gas).saturating_add(
+            self.call_context
+                .refunded_gas()
+                .saturating_sub(self.refunded_at_start),
+        );
+        self.call_context.storage_refund(
+            amount,
+            gas_spent,
+            self.gas_costs.max_storage_refund_percent,
+        )
+    }
+
     /// Get interface.
     pub fn get_interface(&self) -> &dyn Interface {
         &*self.interface
     }
 
+    /// Whether the caller has write access, asked once per execution
+    pub(crate) fn caller_has_write_access(&mut self) -> anyhow::Result<bool> {
+        self.access_cache.caller_has_write_access(&*self.interface)
+    }
+
+    /// Whether `address` is a user address, asked once per execution
+    pub(crate) fn is_address_eoa(&mut self, address: &str) -> anyhow::Result<bool> {
+        self.access_cache.is_address_eoa(&*self.interface, address)
+    }
+
     /// Get a typed guest function from the instance.
     pub fn get_func(
         &self,
@@ -130,7 +182,7 @@ impl ExecutionEnv {
         store: &mut impl AsStoreMut,
         gas: u64,
     ) -> Result<(), WasmV1Error> {
-        if cfg!(feature = "gas_calibration") {
+        if self.call_context.config.gas_calibration {
             return Ok(());
         }
 
@@ -156,7 +208,7 @@ impl ExecutionEnv {
 
     /// Get remaining gas.
     pub fn get_remaining_gas(&self, store: &mut impl AsStoreMut) -> u64 {
-        if cfg!(feature = "gas_calibration") {
+        if self.call_context.config.gas_calibration {
             return u64::MAX;
         }
 
@@ -168,29 +220,68 @@ impl ExecutionEnv {
 
     /// Set remaining gas.
     pub fn set_remaining_gas(&self, store: &mut impl AsStoreMut, remaining_gas: u64) {
-        if cfg!(not(feature = "gas_calibration")) {
+        if !self.call_context.config.gas_calibration {
             metering::set_remaining_points(store, &self.instance, remaining_gas);
         }
     }
 
-    /// Read buffer from guest memory,
+    /// Read buffer from guest memory for `abi`,
     /// try to deallocate it.
     pub fn take_buffer(
         &self,
         store: &mut impl AsStoreMut,
+        abi: &str,
+        offset: i32,
+    ) -> Result<Vec<u8>, WasmV1Error> {
+        self.ffi.take_buffer(
+            store,
+            abi,
+            offset,
+            self.call_context.config.max_abi_read_len,
+        )
+    }
+
+    /// Length of the value returned by `function` at `offset`, refused above
+    /// `RuntimeConfig::max_return_size` bytes
+    pub fn return_value_len(
+        &self,
+        store: &impl AsStoreRef,
+        function: &str,
+        offset: i32,
+    ) -> Result<u64, WasmV1Error> {
+        let len = self.ffi.buffer_len(store, function, offset)?;
+        let max = self.call_context.config.max_return_size;
+        if len > max as u64 {
+            return Err(WasmV1Error::ReturnTooLarge { len, max });
+        }
+        Ok(len)
+    }
+
+    /// Read the value returned by `function` at `offset`, try to deallocate
+    /// it
+    pub fn take_return_value(
+        &self,
+        store: &mut impl AsStoreMut,
+        function: &str,
         offset: i32,
     ) -> Result<Vec<u8>, WasmV1Error> {
-        self.ffi.take_buffer(store, offset)
+        self.ffi.take_buffer(
+            store,
+            function,
+            offset,
+            self.call_context.config.max_return_size,
+        )
     }
 
-    /// Allocate a buffer into guest memory,
+    /// Allocate a buffer into guest memory for `abi`,
     /// write data into it.
     pub fn create_buffer(
         &self,
         store: &mut impl AsStoreMut,
+        abi: &str,
         data: &[u8],
     ) -> Result<i32, WasmV1Error> {
-        self.ffi.create_buffer(store, data)
+        self.ffi.create_buffer(store, abi, data)
     }
 
     /// Get gas costs.
@@ -198,6 +289,11 @@ impl ExecutionEnv {
         &self.gas_costs
     }
 
+    /// Get the settings of the call tree
+    pub fn get_config(&self) -> &RuntimeConfig {
+        &self.call_context.config
+    }
+
     /// Get the memory maximum size in bytes
     pub fn get_max_mem_size(&self, store: &mut impl AsStoreMut) -> u64 {
         self.ffi.get_max_mem_size(&store)
@@ -1,3 +1,5 @@
+use crate::as_execution::ABIError;
+use crate::{CallFrame, VMError};
 use displaydoc::Display;
 use thiserror::Error;
 
@@ -7,4 +9,136 @@ pub enum WasmV1Error {
     RuntimeError(String),
     /// Instanciation error: {0}
     InstanciationError(String),
+    /// Invalid pointer in {abi}: {len} bytes at offset {offset} are out of the {mem_size} bytes of memory
+    InvalidPointer {
+        abi: String,
+        offset: u64,
+        len: u64,
+        mem_size: u64,
+    },
+    /// Read of {len} bytes in {abi} exceeds the maximum of {max} bytes
+    ReadTooLarge { abi: String, len: u64, max: usize },
+    /// Return value of {len} bytes exceeds the maximum of {max} bytes
+    ReturnTooLarge { len: u64, max: usize },
+    /// Runtime error: Guest program abort: {0}
+    Aborted(String),
+    /// Runtime error: Write in read-only mode: {0} is not allowed
+    WriteInReadOnlyMode(String),
+    /// Runtime error: Forbidden ABI: {0} is not allowed in this execution
+    ForbiddenAbi(String),
+    /// Runtime error: Maximum call depth reached: {0}
+    DepthLimitReached(u32),
+    /// Runtime error: Execution timeout: the maximum execution time was exceeded
+    ExecutionTimeout,
+    /// Runtime error: Execution interrupted through its interrupt handle
+    Interrupted,
+    /// Compilation timeout: the compilation exceeded the maximum of {0:?}
+    CompilationTimeout(std::time::Duration),
+    /// Runtime error: Could not run function: {0}
+    NestedCall(VMError),
+}
+
+impl WasmV1Error {
+    /// Error of an execution failing with `self`, reported with the message
+    /// `error`. Typed like `ABIError::to_vm_error`.
+    pub(crate) fn to_vm_error(
+        &self,
+        error: String,
+        init_gas_cost: u64,
+        remaining_gas: u64,
+        call_stack: Vec<CallFrame>,
+    ) -> VMError {
+        match self {
+            WasmV1Error::Aborted(message) => VMError::Aborted {
+                message: message.clone(),
+                abort_info: None,
+                error,
+                init_gas_cost,
+                remaining_gas,
+                call_stack,
+            },
+            WasmV1Error::NestedCall(VMError::Aborted {
+                message,
+                abort_info,
+                ..
+            }) => VMError::Aborted {
+                message: message.clone(),
+                abort_info: abort_info.clone(),
+                error,
+                init_gas_cost,
+                remaining_gas,
+                call_stack,
+            },
+            WasmV1Error::WriteInReadOnlyMode(abi)
+            | WasmV1Error::ForbiddenAbi(abi)
+            | WasmV1Error::NestedCall(VMError::ForbiddenAbi { abi, .. }) => VMError::ForbiddenAbi {
+                abi: abi.clone(),
+                error,
+                init_gas_cost,
+                remaining_gas,
+                call_stack,
+            },
+            WasmV1Error::ExecutionTimeout | WasmV1Error::NestedCall(VMError::Timeout { .. }) => {
+                VMError::Timeout {
+                    error,
+                    init_gas_cost,
+                    remaining_gas,
+                    call_stack,
+                }
+            }
+            WasmV1Error::Interrupted | WasmV1Error::NestedCall(VMError::Interrupted { .. }) => {
+                VMError::Interrupted {
+                    error,
+                    init_gas_cost,
+                    remaining_gas,
+                    call_stack,
+                }
+            }
+            WasmV1Error::ReturnTooLarge { len, max }
+            | WasmV1Error::NestedCall(VMError::ReturnTooLarge { len, max, .. }) => {
+                VMError::ReturnTooLarge {
+                    len: *len,
+                    max: *max,
+                    error,
+                    init_gas_cost,
+                    remaining_gas,
+                    call_stack,
+                }
+            }
+            WasmV1Error::NestedCall(VMError::FunctionNotFound {
+                function,
+                available,
+                ..
+            }) => VMError::FunctionNotFound {
+                function: function.clone(),
+                available: available.clone(),
+                error,
+                init_gas_cost,
+                remaining_gas,
+                call_stack,
+            },
+            _ => VMError::ExecutionError {
+                error,
+                init_gas_cost,
+                remaining_gas,
+                call_stack,
+            },
+        }
+    }
+}
+
+/// Failures of the checks of the call context shared with the AssemblyScript
+/// ABIs
+impl From<ABIError> for WasmV1Error {
+    fn from(err: ABIError) -> Self {
+        match err {
+            ABIError::DepthLimitReached(max_depth) => WasmV1Error::DepthLimitReached(max_depth),
+            ABIError::ExecutionTimeout => WasmV1Error::ExecutionTimeout,
+            ABIError::Interrupted => WasmV1Error::Interrupted,
+            ABIError::CompilationTimeout { max_time } => WasmV1Error::CompilationTimeout(max_time),
+            ABIError::WriteInReadOnlyMode(abi) => WasmV1Error::WriteInReadOnlyMode(abi),
+            ABIError::ForbiddenAbi(abi) => WasmV1Error::ForbiddenAbi(abi),
+            err => WasmV1Error::RuntimeError(err.to_string()),
+        }
+    }
 }
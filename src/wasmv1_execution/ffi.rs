@@ -58,42 +58,70 @@ impl Ffi {
         self.guest_memory.view(store).data_size()
     }
 
+    /// Length of the buffer at `offset`, read from its header without
+    /// copying the buffer. The header has to be within the guest memory.
+    pub fn buffer_len(
+        &self,
+        store: &impl AsStoreRef,
+        abi: &str,
+        offset: i32,
+    ) -> Result<u64, WasmV1Error> {
+        let view = self.guest_memory.view(store);
+        let mem_size = view.data_size();
+        let offset = offset as u32 as u64;
+        let invalid = || WasmV1Error::InvalidPointer {
+            abi: abi.to_string(),
+            offset,
+            len: 4,
+            mem_size,
+        };
+        if offset + 4 > mem_size {
+            return Err(invalid());
+        }
+        let mut len_buffer = [0u8; 4];
+        view.read(offset, &mut len_buffer).map_err(|_| invalid())?;
+        Ok(u32::from_le_bytes(len_buffer) as u64)
+    }
+
     /// Reads a buffer and tries to deallocate it guest-side.
-    /// Assumes memory layout is: [len: i32 little-endian][data: u8*]
+    /// Assumes memory layout is: [len: u32 little-endian][data: u8*]
+    ///
+    /// The buffer has to be within the guest memory and at most `max_len`
+    /// bytes long.
     pub fn take_buffer(
         &self,
         store: &mut impl AsStoreMut,
+        abi: &str,
         offset: i32,
+        max_len: usize,
     ) -> Result<Vec<u8>, WasmV1Error> {
-        let Ok(offset_u64): Result<u64, _> = offset.try_into() else {
-            return Err(WasmV1Error::RuntimeError(format!(
-                "Invalid memory read offset: {}",
-                offset
-            )));
-        };
         let view = self.guest_memory.view(store);
-
-        let mut len_buffer = [0u8; 4];
-        view.read(offset_u64, &mut len_buffer).map_err(|err| {
-            WasmV1Error::RuntimeError(format!(
-                "Could not read length prefix from guest memory: {}",
-                err
-            ))
-        })?;
-        let len = i32::from_le_bytes(len_buffer);
-        let Ok(len): Result<u64, _> = len.try_into() else {
-            return Err(WasmV1Error::RuntimeError(format!(
-                "Memory read length invalid: {}",
-                len
-            )));
+        let mem_size = view.data_size();
+        let offset_u64 = offset as u32 as u64;
+        let invalid = |len| WasmV1Error::InvalidPointer {
+            abi: abi.to_string(),
+            offset: offset_u64,
+            len,
+            mem_size,
         };
 
-        let memory_size = view.data_size();
-        if len > memory_size {
-            return Err(WasmV1Error::RuntimeError(format!(
-                "Memory read length exceeds memory size: {}",
-                len
-            )));
+        let mut len_buffer = [0u8; 4];
+        if offset_u64 + len_buffer.len() as u64 > mem_size {
+            return Err(invalid(len_buffer.len() as u64));
+        }
+        view.read(offset_u64, &mut len_buffer)
+            .map_err(|_| invalid(len_buffer.len() as u64))?;
+        let len = u32::from_le_bytes(len_buffer) as u64;
+        if len > max_len as u64 {
+            return Err(WasmV1Error::ReadTooLarge {
+                abi: abi.to_string(),
+                len,
+                max: max_len,
+            });
+        }
+        let data_offset = offset_u64 + len_buffer.len() as u64;
+        if data_offset + len > mem_size {
+            return Err(invalid(len));
         }
         let mut buffer = vec![
             0u8;
@@ -101,12 +129,8 @@ impl Ffi {
                 "Buffer too large to be addressed on this system using usize"
             )
         ];
-        let Some(data_offset) = offset_u64.checked_add(len_buffer.len() as u64) else {
-            return Err(WasmV1Error::RuntimeError("Offset overflow".into()));
-        };
-        view.read(data_offset, &mut buffer).map_err(|err| {
-            WasmV1Error::RuntimeError(format!("Could not read guest memory: {}", err))
-        })?;
+        view.read(data_offset, &mut buffer)
+            .map_err(|_| invalid(len))?;
 
         // Deallocate the buffer if there is a dealloc guest function
         if let Some(guest_dealloc_func) = &self.guest_dealloc_func {
@@ -117,10 +141,12 @@ impl Ffi {
         Ok(buffer)
     }
 
-    /// Allocates and creates a buffer.
+    /// Allocates and creates a buffer, the allocation returned by the guest
+    /// has to be within its memory.
     pub fn create_buffer(
         &self,
         store: &mut impl AsStoreMut,
+        abi: &str,
         buffer: &[u8],
     ) -> Result<i32, WasmV1Error> {
         let len: i32 = buffer.len().try_into().map_err(|err| {
@@ -135,16 +161,23 @@ impl Ffi {
                 offset
             )));
         };
+        let view = self.guest_memory.view(store);
+        let mem_size = view.data_size();
+        if offset_u64 + buffer.len() as u64 > mem_size {
+            return Err(WasmV1Error::InvalidPointer {
+                abi: abi.to_string(),
+                offset: offset_u64,
+                len: buffer.len() as u64,
+                mem_size,
+            });
+        }
 
-        self.guest_memory
-            .view(store)
-            .write(offset_u64, buffer)
-            .map_err(|err| {
-                WasmV1Error::RuntimeError(format!(
-                    "Could not write in allocated guest memory: {}",
-                    err
-                ))
-            })?;
+        view.write(offset_u64, buffer).map_err(|err| {
+            WasmV1Error::RuntimeError(format!(
+                "Could not write in allocated guest memory: {}",
+                err
+            ))
+        })?;
         Ok(offset)
     }
 }
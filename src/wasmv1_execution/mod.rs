@@ -4,23 +4,36 @@ mod error;
 mod ffi;
 
 use self::env::{ABIEnv, ExecutionEnv};
+use crate::as_execution::ABIError;
+use crate::compilation_watchdog::{compilation_timeout, compile_module};
+use crate::engine_provider::EngineRuntime;
 use crate::error::VMResult;
-use crate::execution::Compiler;
-use crate::middlewares::gas_calibration::{
-    get_gas_calibration_result, GasCalibration, GasCalibrationResult,
-};
-use crate::settings::max_number_of_pages;
+use crate::execution::{check_exported_function, CallContext, Compiler, GasCalibrationResult};
+use crate::middlewares::bulk_memory::BulkMemoryMetering;
+use crate::middlewares::deadline::{DeadlineCheck, DEADLINE_CHECK_NAMESPACE};
+#[cfg(feature = "calibration")]
+use crate::middlewares::gas_calibration::{get_gas_calibration_result, GasCalibration};
+use crate::middlewares::memory_grow::{memory_limit_exceeded, MemoryGrowMetering};
+use crate::middlewares::stack_limit::stack_limit_exceeded;
+use crate::panic_guard::catch_abi_panic;
 use crate::tunable_memory::LimitingTunables;
-use crate::{GasCosts, Interface, Response, VMError};
+use crate::{
+    AbiFilter, ForbiddenAbiMode, GasCosts, HostExtensions, Interface, Response, RuntimeConfig,
+    VMError,
+};
 use abi::*;
-use anyhow::Result;
+pub(crate) use abi::{register_abis, ABI_NAMES};
+use anyhow::{anyhow, Result};
 pub(crate) use error::*;
 use parking_lot::Mutex;
 use std::sync::Arc;
+#[cfg(feature = "cranelift")]
+use wasmer::Cranelift;
 use wasmer::NativeEngineExt;
 use wasmer::{wasmparser::Operator, BaseTunables, EngineBuilder, Pages, Target};
-use wasmer::{CompilerConfig, Cranelift, Engine, Features, Module, Store};
+use wasmer::{CompilerConfig, Engine, Features, Module, Store, Type};
 use wasmer_compiler_singlepass::Singlepass;
+use wasmer_middlewares::metering::{self, MeteringPoints};
 use wasmer_middlewares::Metering;
 
 /// An executable runtime module compiled from an AssemblyScript SC
@@ -29,6 +42,9 @@ pub struct WasmV1Module {
     pub(crate) binary_module: Module,
     pub(crate) gas_limit_at_compilation: u64,
     pub compiler: Compiler,
+    /// Whether the module was compiled to calibrate the gas costs, it can
+    /// only be executed in that mode
+    pub(crate) gas_calibration: bool,
     // Compilation engine can not be dropped
     pub(crate) _engine: Engine,
 }
@@ -38,59 +54,67 @@ impl WasmV1Module {
         bytecode: &[u8],
         limit: u64,
         gas_costs: GasCosts,
+        config: &RuntimeConfig,
         compiler: Compiler,
     ) -> Result<Self, WasmV1Error> {
         let engine = match compiler {
-            Compiler::CL => init_cl_engine(limit, gas_costs),
-            Compiler::SP => init_sp_engine(limit, gas_costs),
+            #[cfg(feature = "cranelift")]
+            Compiler::CL => init_cl_engine(limit, gas_costs, config),
+            Compiler::SP => init_sp_engine(limit, gas_costs, config),
         };
-        let binary_module = match Module::new(&engine, bytecode) {
+        let binary_module = match compile_module(&engine, bytecode, config.max_compilation_time) {
             Ok(module) => module,
             Err(e) => {
+                if let Some(max_time) = compilation_timeout(&e) {
+                    return Err(WasmV1Error::CompilationTimeout(max_time));
+                }
                 return Err(WasmV1Error::InstanciationError(format!(
                     "Could not compile bytecode: {}",
                     e
-                )))
+                )));
             }
         };
         Ok(Self {
             binary_module,
             gas_limit_at_compilation: limit,
             compiler,
+            gas_calibration: config.gas_calibration,
             _engine: engine,
         })
     }
 
     /// Serialize a module
-    pub fn serialize(&self) -> Vec<u8> {
+    pub fn serialize(&self) -> Result<Vec<u8>> {
         match self.compiler {
-            Compiler::CL => self
-                .binary_module
-                .serialize()
-                .expect("Could not serialize module")
-                .to_vec(),
-            Compiler::SP => {
-                panic!("cannot serialize a module compiled with Singlepass")
-            }
+            #[cfg(feature = "cranelift")]
+            Compiler::CL => Ok(self.binary_module.serialize()?.to_vec()),
+            Compiler::SP => Err(anyhow!(
+                "cannot serialize a module compiled with Singlepass"
+            )),
         }
     }
 
     /// Deserialize a module
-    pub fn deserialize(ser_module: &[u8], limit: u64, gas_costs: GasCosts) -> Result<Self> {
+    #[cfg(feature = "cranelift")]
+    pub fn deserialize(
+        ser_module: &[u8],
+        limit: u64,
+        gas_costs: GasCosts,
+        config: &RuntimeConfig,
+    ) -> Result<Self> {
         // Deserialization is only meant for Cranelift modules
-        let engine = init_cl_engine(limit, gas_costs);
+        let engine = init_cl_engine(limit, gas_costs, config);
         let store = Store::new(engine.clone());
         // Unsafe because code injection is possible
         // That's not an issue because we only deserialize modules we have
-        // serialized by ourselves before. This also means that we
-        // expect the module to be valid and the deserialization to
-        // succeed.
-        let binary_module = unsafe { Module::deserialize(&store, ser_module) }
-            .expect("Could not deserialize module");
+        // serialized by ourselves before. Artifacts produced by another
+        // wasmer version are still rejected by wasmer.
+        let binary_module = unsafe { Module::deserialize(&store, ser_module)? };
         Ok(WasmV1Module {
             binary_module,
             gas_limit_at_compilation: limit,
             compiler: Compiler::CL,
+            gas_calibration: config.gas_calibration,
             _engine: engine,
         })
     }
@@ -113,35 +137,39 @@ impl WasmV1Module {
 // * https://github.com/WebAssembly/design/blob/390bab47efdb76b600371bcef1ec0ea374aa8c43/Nondeterminism.md
 // * https://github.com/WebAssembly/proposals
 //
-// TLDR: Turn off every feature except for `bulk_memory`.
+// TLDR: Turn off every feature except for `bulk_memory`, whose operators are
+// charged per byte by `BulkMemoryMetering`. The sign-extension operators need
+// no feature, wasmer always accepts them, and are allowed by the validation.
 const FEATURES: Features = Features {
-    threads: false,         // non-deterministic
-    reference_types: false, // could be enabled but we have no need for it atm
-    simd: false,            // non-deterministic
-    bulk_memory: true,      // enables the use of buffers in AS
-    multi_value: false,     // could be enabled but we have no need for it atm
-    tail_call: false,       // experimental
-    module_linking: false,  // experimental
-    multi_memory: false,    // experimental
-    memory64: false,        // experimental
-    exceptions: false,      // experimental
-    relaxed_simd: false,    // experimental
-    extended_const: false,  // experimental
+    threads: false,               // non-deterministic
+    reference_types: false,       // could be enabled but we have no need for it atm
+    simd: cfg!(feature = "simd"), // non-deterministic
+    bulk_memory: true,            // enables the use of buffers in AS
+    multi_value: false,           // could be enabled but we have no need for it atm
+    tail_call: false,             // experimental
+    module_linking: false,        // experimental
+    multi_memory: false,          // experimental
+    memory64: false,              // experimental
+    exceptions: false,            // experimental
+    relaxed_simd: false,          // experimental
+    extended_const: false,        // experimental
 };
 
-pub(crate) fn init_sp_engine(limit: u64, gas_costs: GasCosts) -> Engine {
+pub(crate) fn init_sp_engine(limit: u64, gas_costs: GasCosts, config: &RuntimeConfig) -> Engine {
     // Singlepass is used to compile arbitrary bytecode.
     //
     // Reference:
     // * https://docs.rs/wasmer-compiler-singlepass/latest/wasmer_compiler_singlepass/
     let mut compiler_config = Singlepass::new();
 
+    let max_memory_pages = config.condom_limits.max_memory_pages;
+
     // Canonicalize NaN
     compiler_config.canonicalize_nans(true);
-    add_middleware(&mut compiler_config, limit, gas_costs);
+    add_middleware(&mut compiler_config, limit, gas_costs, config);
 
     let base = BaseTunables::for_target(&Target::default());
-    let tunables = LimitingTunables::new(base, Pages(max_number_of_pages()));
+    let tunables = LimitingTunables::new(base, Pages(max_memory_pages));
 
     let mut engine = Engine::from(
         EngineBuilder::new(compiler_config)
@@ -152,19 +180,22 @@ pub(crate) fn init_sp_engine(limit: u64, gas_costs: GasCosts) -> Engine {
     engine
 }
 
-pub(crate) fn init_cl_engine(limit: u64, gas_costs: GasCosts) -> Engine {
+#[cfg(feature = "cranelift")]
+pub(crate) fn init_cl_engine(limit: u64, gas_costs: GasCosts, config: &RuntimeConfig) -> Engine {
     // Cranelift is used to compile bytecode that will be cached.
     //
     // Reference:
     // * https://docs.rs/wasmer-compiler-cranelift/latest/wasmer_compiler_cranelift/
     let mut compiler_config = Cranelift::new();
 
+    let max_memory_pages = config.condom_limits.max_memory_pages;
+
     // Canonicalize NaN
     compiler_config.canonicalize_nans(true);
-    add_middleware(&mut compiler_config, limit, gas_costs);
+    add_middleware(&mut compiler_config, limit, gas_costs, config);
 
     let base = BaseTunables::for_target(&Target::default());
-    let tunables = LimitingTunables::new(base, Pages(max_number_of_pages()));
+    let tunables = LimitingTunables::new(base, Pages(max_memory_pages));
 
     let mut engine = Engine::from(
         EngineBuilder::new(compiler_config)
@@ -175,20 +206,58 @@ pub(crate) fn init_cl_engine(limit: u64, gas_costs: GasCosts) -> Engine {
     engine
 }
 
-fn add_middleware<T>(compiler_config: &mut T, limit: u64, gas_costs: GasCosts)
-where
+fn add_middleware<T>(
+    compiler_config: &mut T,
+    limit: u64,
+    gas_costs: GasCosts,
+    config: &RuntimeConfig,
+) where
     T: CompilerConfig,
 {
-    if cfg!(feature = "gas_calibration") {
-        // Add gas calibration middleware
-        let gas_calibration = Arc::new(GasCalibration::new());
-        compiler_config.push_middleware(gas_calibration);
+    if config.gas_calibration {
+        // Add gas calibration middleware, refused before the compilation
+        // without the `calibration` feature
+        #[cfg(feature = "calibration")]
+        compiler_config.push_middleware(Arc::new(GasCalibration::new()));
     } else {
-        // Add metering middleware
-        let metering = Arc::new(Metering::new(limit, move |_: &Operator| -> u64 {
-            gas_costs.operator_cost
+        // Add metering middleware, then the instrumentations of memory.grow
+        // and of the bulk memory operators which rely on it, and the checks
+        // of the deadline which it does not charge
+        let memory_grow = Arc::new(MemoryGrowMetering::new(&gas_costs, &config.condom_limits));
+        let bulk_memory = Arc::new(BulkMemoryMetering::new(&gas_costs));
+        let deadline = Arc::new(DeadlineCheck::new());
+        let metering = Arc::new(Metering::new(limit, move |operator: &Operator| -> u64 {
+            gas_costs.cost_of_operator(operator)
         }));
         compiler_config.push_middleware(metering);
+        compiler_config.push_middleware(memory_grow);
+        compiler_config.push_middleware(bulk_memory);
+        compiler_config.push_middleware(deadline);
+    }
+}
+
+/// Refuse the modules importing an ABI refused by `abi_filter`, with
+/// `ForbiddenAbiMode::LinkError`, the host extensions being always allowed
+fn check_forbidden_imports(
+    module: &Module,
+    abi_filter: &AbiFilter,
+    host_extensions: &HostExtensions,
+) -> VMResult<()> {
+    if abi_filter.mode != ForbiddenAbiMode::LinkError {
+        return Ok(());
+    }
+    match module.imports().find(|import| {
+        !host_extensions.contains(import.module(), import.name())
+            && import.module() != DEADLINE_CHECK_NAMESPACE
+            && !abi_filter.is_allowed(import.name())
+    }) {
+        Some(import) => Err(VMError::InstanceError(format!(
+            "Failed to link \"{}\".\"{}\": {}",
+            import.module(),
+            import.name(),
+            ABIError::ForbiddenAbi(import.name().to_string())
+        ))),
+        None => Ok(()),
     }
 }
 
@@ -199,33 +268,65 @@ pub(crate) fn exec_wasmv1_module(
     param: &[u8],
     gas_limit: u64,
     gas_costs: GasCosts,
+    call_context: CallContext,
 ) -> VMResult<(Response, Option<GasCalibrationResult>)> {
-    // Init store
-    let engine = match module.compiler {
-        Compiler::CL => init_cl_engine(gas_limit, gas_costs.clone()),
-        Compiler::SP => init_sp_engine(gas_limit, gas_costs.clone()),
+    let config = call_context.config.clone();
+    config.host_extensions.check()?;
+    let gas_calibration = config.gas_calibration;
+
+    // Init store, with the engine of the previous executions
+    let build = match module.compiler {
+        #[cfg(feature = "cranelift")]
+        Compiler::CL => init_cl_engine,
+        Compiler::SP => init_sp_engine,
     };
-    let mut store = Store::new(engine);
+    let engine = call_context.engines.engine(
+        EngineRuntime::WasmV1,
+        module.compiler.clone(),
+        &gas_costs,
+        &config,
+        build,
+    );
+    let mut store = Store::new(Engine::clone(&engine));
 
     // Create the ABI imports and pass them an empty environment for now
     let shared_abi_env: ABIEnv = Arc::new(Mutex::new(None));
-    let import_object = register_abis(&mut store, shared_abi_env.clone());
+    let mut import_object = register_abis(&mut store, shared_abi_env.clone(), &config.abi_filter);
+    config
+        .host_extensions
+        .define(&mut store, &mut import_object);
+    check_forbidden_imports(
+        &module.binary_module,
+        &config.abi_filter,
+        &config.host_extensions,
+    )?;
 
     // save the gas remaining before subexecution: used by readonly execution
     interface.save_gas_remaining_before_subexecution(gas_limit);
 
     // Create an instance of the execution environment.
-    let execution_env =
-        ExecutionEnv::create_instance(&mut store, &module, interface, gas_costs, &import_object)
-            .map_err(|err| {
-                VMError::InstanceError(format!(
-                    "Failed to create instance of execution environment: {}",
-                    err
-                ))
-            })?;
+    let call_stack = call_context.stack.clone();
+    let max_pages = config.condom_limits.max_memory_pages;
+    let max_depth = config.condom_limits.max_stack_depth;
+    let mut execution_env = ExecutionEnv::create_instance(
+        &mut store,
+        &module,
+        interface,
+        gas_costs,
+        call_context,
+        &import_object,
+    )
+    .map_err(|err| {
+        VMError::InstanceError(format!(
+            "Failed to create instance of execution environment: {}",
+            err
+        ))
+    })?;
 
     // Get gas cost of instance creation
     let init_gas_cost = execution_env.get_init_gas_cost();
+    #[cfg(feature = "tracing")]
+    tracing::debug!(init_gas_cost, "instantiation");
 
     // Set gas limit of function execution by subtracting the gas cost of
     // instance creation
@@ -235,10 +336,27 @@ pub(crate) fn exec_wasmv1_module(
             return Err(VMError::ExecutionError {
                 error: "Available gas does not cover instance creation".to_string(),
                 init_gas_cost,
+                remaining_gas: 0,
+                call_stack: call_stack.clone(),
             })
         }
     };
+    // the guest functions take the address of the argument and return the
+    // one of the result
+    check_exported_function(&module.binary_module, function, |ty| {
+        ty.params() == [Type::I32] && ty.results() == [Type::I32]
+    })
+    .map_err(|available| {
+        VMError::function_not_found(
+            function,
+            available,
+            init_gas_cost,
+            available_gas,
+            call_stack.clone(),
+        )
+    })?;
     execution_env.set_remaining_gas(&mut store, available_gas);
+    execution_env.set_gas_limit(available_gas);
 
     // Get function to execute. Must follow the following prototype: param_addr:
     // i32 -> return_addr: i32
@@ -251,17 +369,21 @@ pub(crate) fn exec_wasmv1_module(
                     function, err
                 ),
                 init_gas_cost,
+                remaining_gas: available_gas,
+                call_stack: call_stack.clone(),
             })?;
 
     // Allocate and write function argument to guest memory
     let param_offset = execution_env
-        .create_buffer(&mut store, param)
+        .create_buffer(&mut store, function, param)
         .map_err(|err| VMError::ExecutionError {
             error: format!(
                 "Could not write argument for guest call {}: {}",
                 function, err
             ),
             init_gas_cost,
+            remaining_gas: available_gas,
+            call_stack: call_stack.clone(),
         })?;
 
     // Now that we have an instance, we can make the execution environment
@@ -271,13 +393,72 @@ pub(crate) fn exec_wasmv1_module(
     shared_abi_env.lock().replace(execution_env);
 
     // Call func
-    let returned_offset =
-        wasm_func
-            .call(&mut store, param_offset)
-            .map_err(|err| VMError::ExecutionError {
-                error: format!("Error while calling guest function {}: {}", function, err),
-                init_gas_cost,
-            })?;
+    let result = catch_abi_panic(|| wasm_func.call(&mut store, param_offset))
+        .unwrap_or_else(|err| Err(wasmer::RuntimeError::user(Box::new(err))));
+    let returned_offset = match result {
+        Ok(offset) => offset,
+        Err(err) => {
+            let (remaining_points, limit_exceeded, stack_overflow) = shared_abi_env
+                .lock()
+                .as_ref()
+                .map(|env| {
+                    (
+                        Some(metering::get_remaining_points(&mut store, &env.instance)),
+                        memory_limit_exceeded(&env.instance, &mut store),
+                        stack_limit_exceeded(&env.instance, &mut store),
+                    )
+                })
+                .unwrap_or((None, false, false));
+            let remaining_gas = match remaining_points {
+                Some(MeteringPoints::Remaining(remaining)) => remaining,
+                _ => 0,
+            };
+            let exhausted =
+                !gas_calibration && matches!(remaining_points, Some(MeteringPoints::Exhausted));
+            if exhausted {
+                return Err(VMError::OutOfGas {
+                    at_function: function.to_string(),
+                    init_gas_cost,
+                });
+            }
+            if limit_exceeded {
+                return Err(VMError::ExecutionError {
+                    error: format!(
+                        "Error while calling guest function {}: {}",
+                        function,
+                        ABIError::MemoryLimitExceeded { max_pages }
+                    ),
+                    init_gas_cost,
+                    remaining_gas,
+                    call_stack,
+                });
+            }
+            if stack_overflow {
+                return Err(VMError::ExecutionError {
+                    error: format!(
+                        "Error while calling guest function {}: {}",
+                        function,
+                        ABIError::StackOverflow { max_depth }
+                    ),
+                    init_gas_cost,
+                    remaining_gas,
+                    call_stack,
+                });
+            }
+            let error = format!("Error while calling guest function {}: {}", function, err);
+            return Err(match err.downcast_ref::<WasmV1Error>() {
+                Some(abi_error) => {
+                    abi_error.to_vm_error(error, init_gas_cost, remaining_gas, call_stack)
+                }
+                None => VMError::ExecutionError {
+                    error,
+                    init_gas_cost,
+                    remaining_gas,
+                    call_stack,
+                },
+            });
+        }
+    };
 
     // Take back the execution environment
     let execution_env = shared_abi_env
@@ -285,28 +466,42 @@ pub(crate) fn exec_wasmv1_module(
         .take()
         .expect("Execution environment unavailable after execution");
 
-    // Read returned value from guest memory and deallocate it
-    let ret = execution_env
-        .take_buffer(&mut store, returned_offset)
-        .map_err(|err| VMError::ExecutionError {
-            error: format!(
-                "Could not read return value from guest call {}: {}",
-                function, err
-            ),
+    // Read returned value from guest memory and deallocate it, its bytes
+    // being charged before they are copied
+    let read_error = |err: WasmV1Error, remaining_gas| {
+        let error = format!(
+            "Could not read return value from guest call {}: {}",
+            function, err
+        );
+        err.to_vm_error(error, init_gas_cost, remaining_gas, call_stack.clone())
+    };
+    let ret_len = execution_env
+        .return_value_len(&store, function, returned_offset)
+        .map_err(|err| read_error(err, execution_env.get_remaining_gas(&mut store)))?;
+    let return_cost = execution_env
+        .get_gas_costs()
+        .return_byte_cost
+        .checked_mul(ret_len);
+    if return_cost.map_or(true, |cost| {
+        execution_env.try_subtract_gas(&mut store, cost).is_err()
+    }) {
+        return Err(VMError::OutOfGas {
+            at_function: function.to_string(),
             init_gas_cost,
-        })?;
+        });
+    }
+    let ret = execution_env
+        .take_return_value(&mut store, function, returned_offset)
+        .map_err(|err| read_error(err, execution_env.get_remaining_gas(&mut store)))?;
 
     // Get remaining gas
     let remaining_gas = execution_env.get_remaining_gas(&mut store);
 
-    let gc_result = if cfg!(feature = "gas_calibration") {
-        Some(get_gas_calibration_result(
-            &execution_env.instance,
-            &mut store,
-        ))
-    } else {
-        None
-    };
+    #[cfg(feature = "calibration")]
+    let gc_result =
+        gas_calibration.then(|| get_gas_calibration_result(&execution_env.instance, &mut store));
+    #[cfg(not(feature = "calibration"))]
+    let gc_result = None;
 
     // Return response
     Ok((
@@ -316,6 +511,9 @@ pub(crate) fn exec_wasmv1_module(
             init_gas_cost,
             #[cfg(feature = "execution-trace")]
             trace: execution_env.trace.clone(),
+            gas_breakdown: None,
+            #[cfg(feature = "profiling")]
+            profile: Default::default(),
         },
         gc_result,
     ))
@@ -0,0 +1,212 @@
+//! Deploy and call a contract using only the `TestInterface` of the
+//! `testing` feature.
+
+use massa_sc_runtime::testing::{
+    InMemoryReadOnlyCache, TestInterface, TEST_CALLER_ADDRESS, TEST_CHAIN_ID,
+};
+use massa_sc_runtime::{
+    run_main, run_read_only_cached, Compiler, EngineProvider, ExecutionOptions, GasCosts,
+    RuntimeConfig, RuntimeModule,
+};
+use std::collections::BTreeMap;
+
+/// Data segment escaping of `bytes`
+fn wat_bytes(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("\\{:02x}", byte)).collect()
+}
+
+#[test]
+fn test_deploy_and_call() {
+    // stores "n" => 1 and emits "hi"
+    let counter = wasmer::wat2wasm(
+        br#"(module
+            (import "massa" "assembly_script_set_data" (func $set_data (param i32 i32)))
+            (import "massa" "assembly_script_generate_event" (func $event (param i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 12) "\01\00\00\00n")
+            (data (i32.const 28) "\01\00\00\00\01")
+            (data (i32.const 44) "\04\00\00\00h\00i\00")
+            (func (export "increment")
+                (call $set_data (i32.const 16) (i32.const 32))
+                (call $event (i32.const 48))))"#,
+    )
+    .unwrap();
+    // deploys the counter and calls `increment` with 100 coins
+    let deployer = format!(
+        r#"(module
+            (import "massa" "assembly_script_create_sc" (func $create_sc (param i32) (result i32)))
+            (import "massa" "assembly_script_call"
+                (func $call (param i32 i32 i32 i64) (result i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 28) "\12\00\00\00i\00n\00c\00r\00e\00m\00e\00n\00t\00")
+            (data (i32.const 60) "\00\00\00\00")
+            (data (i32.const 76) "{}{}")
+            (func (export "__new") (param i32 i32) (result i32)
+                (i32.store (i32.const 1020) (local.get 0))
+                (i32.const 1024))
+            (func (export "main")
+                (drop (call $call
+                    (call $create_sc (i32.const 80)) (i32.const 32) (i32.const 64) (i64.const 100)))))"#,
+        wat_bytes(&(counter.len() as u32).to_le_bytes()),
+        wat_bytes(&counter),
+    );
+    let deployer = wasmer::wat2wasm(deployer.as_bytes()).unwrap();
+
+    let interface = TestInterface::new();
+    interface.set_balance(TEST_CALLER_ADDRESS, 1_000);
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    let module = RuntimeModule::new(&deployer, gas_costs.clone(), &config, Compiler::SP).unwrap();
+    run_main(
+        &interface,
+        module,
+        10_000_000,
+        gas_costs,
+        &config,
+        ExecutionOptions::default(),
+    )
+    .unwrap();
+
+    // the first created address is deterministic
+    let address = "AS1";
+    assert_eq!(interface.get_account(address).unwrap().bytecode, counter);
+    assert_eq!(
+        interface.get_datastore(address),
+        BTreeMap::from([(b"n".to_vec(), vec![1])])
+    );
+    assert_eq!(interface.balance(address), 100);
+    assert_eq!(interface.balance(TEST_CALLER_ADDRESS), 900);
+
+    let events = interface.get_events();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].emitter, address);
+    assert_eq!(events[0].data, b"hi");
+    // back to the caller once the call is finished
+    assert_eq!(
+        interface.call_stack(),
+        vec![TEST_CALLER_ADDRESS.to_string()]
+    );
+}
+
+#[test]
+fn test_chain_id() {
+    // stores the chain id under "c"
+    let module = wasmer::wat2wasm(
+        br#"(module
+            (import "massa" "assembly_script_set_data" (func $set_data (param i32 i32)))
+            (import "massa" "assembly_script_chain_id" (func $chain_id (result i64)))
+            (memory (export "memory") 1)
+            (data (i32.const 12) "\01\00\00\00c")
+            (data (i32.const 28) "\08\00\00\00")
+            (func (export "main")
+                (i64.store (i32.const 32) (call $chain_id))
+                (call $set_data (i32.const 16) (i32.const 32))))"#,
+    )
+    .unwrap();
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    let run = |interface: &TestInterface| {
+        let module = RuntimeModule::new(&module, gas_costs.clone(), &config, Compiler::SP).unwrap();
+        run_main(
+            interface,
+            module,
+            1_000_000,
+            gas_costs.clone(),
+            &config,
+            ExecutionOptions::default(),
+        )
+        .unwrap();
+        interface.get_datastore(TEST_CALLER_ADDRESS)[&b"c".to_vec()].clone()
+    };
+
+    let interface = TestInterface::new();
+    assert_eq!(run(&interface), TEST_CHAIN_ID.to_le_bytes());
+    interface.set_chain_id(9_000);
+    assert_eq!(run(&interface), 9_000u64.to_le_bytes());
+}
+
+#[test]
+fn test_read_only_cache() {
+    // both emit "hi", `random` after drawing a random number
+    let contract = wasmer::wat2wasm(
+        br#"(module
+            (import "massa" "assembly_script_generate_event" (func $event (param i32)))
+            (import "massa" "assembly_script_unsafe_random" (func $random (result i64)))
+            (memory (export "memory") 1)
+            (data (i32.const 44) "\04\00\00\00h\00i\00")
+            (func (export "read")
+                (call $event (i32.const 48)))
+            (func (export "random")
+                (drop (call $random))
+                (call $event (i32.const 48))))"#,
+    )
+    .unwrap();
+    let interface = TestInterface::new();
+    let address = interface.deploy(&contract);
+    let cache = InMemoryReadOnlyCache::new();
+    let gas_costs = GasCosts::default();
+    let config = RuntimeConfig::default();
+    let run = |function: &str, param: &[u8], ledger_version: &[u8]| {
+        run_read_only_cached(
+            &interface,
+            &cache,
+            &address,
+            &contract,
+            Compiler::SP,
+            function,
+            param,
+            ledger_version,
+            1_000_000,
+            gas_costs.clone(),
+            &config,
+            None,
+        )
+        .unwrap()
+    };
+
+    // a miss runs the call, a hit gives the same response and events
+    let executed = run("read", b"", b"v1");
+    assert_eq!((cache.hits(), cache.misses(), cache.len()), (0, 1, 1));
+    let cached = run("read", b"", b"v1");
+    assert_eq!((cache.hits(), cache.misses(), cache.len()), (1, 1, 1));
+    assert_eq!(cached.ret, executed.ret);
+    assert_eq!(cached.remaining_gas, executed.remaining_gas);
+    let events = interface.get_events();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0], events[1]);
+    assert_eq!(events[1].data, b"hi");
+
+    // another parameter or ledger version is a miss
+    run("read", b"param", b"v1");
+    run("read", b"", b"v2");
+    assert_eq!((cache.hits(), cache.misses(), cache.len()), (1, 3, 3));
+
+    // so is another configuration, the call running on the engines given
+    let mut other_config = config.clone();
+    other_config.max_return_size += 1;
+    let engines = EngineProvider::new();
+    let other = run_read_only_cached(
+        &interface,
+        &cache,
+        &address,
+        &contract,
+        Compiler::SP,
+        "read",
+        b"",
+        b"v1",
+        1_000_000,
+        gas_costs.clone(),
+        &other_config,
+        Some(engines.clone()),
+    )
+    .unwrap();
+    assert_eq!((cache.hits(), cache.misses(), cache.len()), (1, 4, 4));
+    assert_eq!(other.remaining_gas, executed.remaining_gas);
+    assert!(!engines.is_empty());
+
+    // a call drawing a random number is run every time and never cached
+    run("random", b"", b"v1");
+    run("random", b"", b"v1");
+    assert_eq!((cache.hits(), cache.misses(), cache.len()), (1, 6, 4));
+    assert_eq!(interface.get_events().len(), 7);
+}
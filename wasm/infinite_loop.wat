@@ -0,0 +1,6 @@
+(module
+  (memory $0 1)
+  (export "memory" (memory $0))
+  (func (export "main")
+    (loop $loop
+      br $loop)))